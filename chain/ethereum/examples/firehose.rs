@@ -1,16 +1,15 @@
 use anyhow::Error;
 use graph::{
     firehose::{
-        bstream::BlockResponseV2, bstream::BlocksRequestV2, bstream::ForkStep,
+        archive::BlockResponseStream, bstream::BlocksRequestV2, bstream::ForkStep,
         endpoints::FirehoseEndpoint,
     },
     log::logger,
-    prelude::{prost, tokio, tonic},
+    prelude::{futures03::StreamExt, prost, tokio},
 };
 use graph_chain_ethereum::codec;
 use prost::Message;
 use std::sync::Arc;
-use tonic::Streaming;
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
@@ -23,7 +22,7 @@ async fn main() -> Result<(), Error> {
 
     loop {
         println!("connecting to the stream!");
-        let mut stream: Streaming<BlockResponseV2> = match firehose
+        let mut stream: BlockResponseStream = match firehose
             .clone()
             .stream_blocks(BlocksRequestV2 {
                 start_block_num: 7000000,
@@ -44,13 +43,13 @@ async fn main() -> Result<(), Error> {
         };
 
         loop {
-            let resp = match stream.message().await {
-                Ok(Some(t)) => t,
-                Ok(None) => {
+            let resp = match stream.next().await {
+                Some(Ok(t)) => t,
+                None => {
                     println!("stream completed");
                     break;
                 }
-                Err(e) => {
+                Some(Err(e)) => {
                     println!("error getting message {}", e);
                     break;
                 }
@@ -11,7 +11,7 @@ use std::fmt;
 use std::marker::Unpin;
 use thiserror::Error;
 use tiny_keccak::keccak256;
-use web3::types::{Address, Block, Log, H256};
+use web3::types::{Address, Block, Log, Transaction, H256};
 
 use graph::prelude::*;
 use graph::{
@@ -21,7 +21,7 @@ use graph::{
 };
 
 use crate::capabilities::NodeCapabilities;
-use crate::data_source::BlockHandlerFilter;
+use crate::data_source::{BlockHandlerFilter, MappingTransactionHandler};
 use crate::{data_source::DataSource, Chain};
 
 pub type EventSignature = H256;
@@ -55,6 +55,9 @@ pub enum EthereumContractCallError {
     Revert(String),
     #[error("ethereum node took too long to perform call")]
     Timeout,
+    /// The providers that were asked to verify the call disagree
+    #[error("{0:#}")]
+    Disagreement(Error),
 }
 
 impl From<ABIError> for EthereumContractCallError {
@@ -119,6 +122,7 @@ pub struct TriggerFilter {
     pub(crate) log: EthereumLogFilter,
     pub(crate) call: EthereumCallFilter,
     pub(crate) block: EthereumBlockFilter,
+    pub(crate) transaction: EthereumTransactionFilter,
 }
 
 impl TriggerFilter {
@@ -134,7 +138,9 @@ impl bc::TriggerFilter<Chain> for TriggerFilter {
         self.call
             .extend(EthereumCallFilter::from_data_sources(data_sources.clone()));
         self.block
-            .extend(EthereumBlockFilter::from_data_sources(data_sources));
+            .extend(EthereumBlockFilter::from_data_sources(data_sources.clone()));
+        self.transaction
+            .extend(EthereumTransactionFilter::from_data_sources(data_sources));
     }
 
     fn node_capabilities(&self) -> NodeCapabilities {
@@ -154,6 +160,10 @@ pub(crate) struct EthereumLogFilter {
 
     // Event sigs with no associated address, matching on all addresses.
     wildcard_events: HashSet<EventSignature>,
+
+    /// Events whose handlers want the transaction receipt, with the address
+    /// of their contract or `None` for wildcard events.
+    events_with_receipts: HashSet<(Option<Address>, EventSignature)>,
 }
 
 impl EthereumLogFilter {
@@ -182,7 +192,12 @@ impl EthereumLogFilter {
     pub fn from_data_sources<'a>(iter: impl IntoIterator<Item = &'a DataSource>) -> Self {
         let mut this = EthereumLogFilter::default();
         for ds in iter {
-            for event_sig in ds.mapping.event_handlers.iter().map(|e| e.topic0()) {
+            for event_handler in ds.mapping.event_handlers.iter() {
+                let event_sig = event_handler.topic0();
+                if event_handler.receipt {
+                    this.events_with_receipts
+                        .insert((ds.source.address, event_sig));
+                }
                 match ds.source.address {
                     Some(contract) => {
                         this.contracts_and_events_graph.add_edge(
@@ -206,11 +221,13 @@ impl EthereumLogFilter {
         let EthereumLogFilter {
             contracts_and_events_graph,
             wildcard_events,
+            events_with_receipts,
         } = other;
         for (s, t, ()) in contracts_and_events_graph.all_edges() {
             self.contracts_and_events_graph.add_edge(s, t, ());
         }
         self.wildcard_events.extend(wildcard_events);
+        self.events_with_receipts.extend(events_with_receipts);
     }
 
     /// An empty filter is one that never matches.
@@ -219,10 +236,29 @@ impl EthereumLogFilter {
         let EthereumLogFilter {
             contracts_and_events_graph,
             wildcard_events,
+            events_with_receipts: _,
         } = self;
         contracts_and_events_graph.edge_count() == 0 && wildcard_events.is_empty()
     }
 
+    /// Check if any event handler wants the transaction receipt.
+    pub fn requires_transaction_receipts(&self) -> bool {
+        !self.events_with_receipts.is_empty()
+    }
+
+    /// Check if the handler for the specified `Log` wants the receipt of
+    /// the transaction that emitted it.
+    pub fn requires_transaction_receipt(&self, log: &Log) -> bool {
+        match log.topics.first() {
+            None => false,
+            Some(sig) => {
+                self.events_with_receipts
+                    .contains(&(Some(log.address), *sig))
+                    || self.events_with_receipts.contains(&(None, *sig))
+            }
+        }
+    }
+
     /// Filters for `eth_getLogs` calls. The filters will not return false positives. This attempts
     /// to balance between having granular filters but too many calls and having few calls but too
     /// broad filters causing the Ethereum endpoint to timeout.
@@ -485,10 +521,59 @@ impl EthereumBlockFilter {
     }
 }
 
+#[derive(Clone, Debug, Default)]
+pub(crate) struct EthereumTransactionFilter {
+    // The transaction handlers of all data sources, together with the start
+    // block and the address of their data source
+    handlers: HashSet<(BlockNumber, Option<Address>, MappingTransactionHandler)>,
+}
+
+impl EthereumTransactionFilter {
+    pub fn matches(&self, transaction: &Transaction) -> bool {
+        let block_number = transaction
+            .block_number
+            .map(|number| number.as_u64() as BlockNumber)
+            .unwrap_or(BlockNumber::MAX);
+        self.handlers.iter().any(|(start_block, address, handler)| {
+            *start_block <= block_number && handler.matches(*address, transaction)
+        })
+    }
+
+    pub fn from_data_sources<'a>(iter: impl IntoIterator<Item = &'a DataSource>) -> Self {
+        let handlers = iter
+            .into_iter()
+            .flat_map(|data_source| {
+                let start_block = data_source.source.start_block;
+                let address = data_source.source.address;
+                data_source
+                    .mapping
+                    .transaction_handlers
+                    .iter()
+                    .map(move |handler| (start_block, address, handler.clone()))
+            })
+            .collect();
+        EthereumTransactionFilter { handlers }
+    }
+
+    /// Extends this transaction filter with another one.
+    pub fn extend(&mut self, other: EthereumTransactionFilter) {
+        self.handlers.extend(other.handlers);
+    }
+
+    /// An empty filter is one that never matches.
+    pub fn is_empty(&self) -> bool {
+        self.handlers.is_empty()
+    }
+}
+
 #[derive(Clone)]
 pub struct ProviderEthRpcMetrics {
     request_duration: Box<HistogramVec>,
     errors: Box<CounterVec>,
+    disagreements: Box<CounterVec>,
+    health_score: Box<GaugeVec>,
+    head_lag: Box<GaugeVec>,
+    ejected: Box<GaugeVec>,
 }
 
 impl ProviderEthRpcMetrics {
@@ -508,9 +593,41 @@ impl ProviderEthRpcMetrics {
                 vec![String::from("method"), String::from("provider")],
             )
             .unwrap();
+        let disagreements = registry
+            .new_counter_vec(
+                "eth_rpc_disagreements",
+                "Counts eth rpc responses that disagree with those of other providers",
+                vec![String::from("method"), String::from("provider")],
+            )
+            .unwrap();
+        let health_score = registry
+            .new_gauge_vec(
+                "eth_rpc_provider_health_score",
+                "The health score of a provider between 0 and 1, higher is better",
+                vec![String::from("provider")],
+            )
+            .unwrap();
+        let head_lag = registry
+            .new_gauge_vec(
+                "eth_rpc_provider_head_lag",
+                "The number of blocks a provider is behind the most advanced provider",
+                vec![String::from("provider")],
+            )
+            .unwrap();
+        let ejected = registry
+            .new_gauge_vec(
+                "eth_rpc_provider_ejected",
+                "Whether a provider is currently not used because it failed repeatedly",
+                vec![String::from("provider")],
+            )
+            .unwrap();
         Self {
             request_duration,
             errors,
+            disagreements,
+            health_score,
+            head_lag,
+            ejected,
         }
     }
 
@@ -523,6 +640,22 @@ impl ProviderEthRpcMetrics {
     pub fn add_error(&self, method: &str, provider: &str) {
         self.errors.with_label_values(&[method, provider]).inc();
     }
+
+    pub fn add_disagreement(&self, method: &str, provider: &str) {
+        self.disagreements
+            .with_label_values(&[method, provider])
+            .inc();
+    }
+
+    pub fn set_health(&self, provider: &str, score: f64, head_lag: BlockNumber, ejected: bool) {
+        self.health_score.with_label_values(&[provider]).set(score);
+        self.head_lag
+            .with_label_values(&[provider])
+            .set(head_lag as f64);
+        self.ejected
+            .with_label_values(&[provider])
+            .set(if ejected { 1.0 } else { 0.0 });
+    }
 }
 
 #[derive(Clone)]
@@ -669,14 +802,23 @@ pub trait EthereumAdapter: Send + Sync + 'static {
 
 #[cfg(test)]
 mod tests {
-    use super::EthereumCallFilter;
+    use super::{EthereumCallFilter, EthereumLogFilter, EthereumTransactionFilter, LogFilterNode};
+    use crate::data_source::MappingTransactionHandler;
+    use crate::ethereum_adapter::{attach_receipts_to_log_triggers, parse_log_triggers};
+    use crate::trigger::EthereumTrigger;
 
+    use graph::petgraph::graphmap::GraphMap;
     use graph::prelude::web3::types::Address;
     use graph::prelude::web3::types::Bytes;
-    use graph::prelude::EthereumCall;
+    use graph::prelude::web3::types::Log;
+    use graph::prelude::web3::types::Transaction;
+    use graph::prelude::web3::types::TransactionReceipt;
+    use graph::prelude::web3::types::H256;
+    use graph::prelude::{EthereumBlock, EthereumCall};
 
     use std::collections::{HashMap, HashSet};
     use std::iter::FromIterator;
+    use std::sync::Arc;
 
     #[test]
     fn matching_ethereum_call_filter() {
@@ -721,6 +863,164 @@ mod tests {
         );
     }
 
+    #[test]
+    fn matching_ethereum_transaction_filter() {
+        let address = |id: u64| Address::from_low_u64_be(id);
+        let transaction = |from: u64, to: u64, input: Vec<u8>| Transaction {
+            from: Some(address(from)),
+            to: Some(address(to)),
+            input: Bytes::from(input),
+            block_number: Some(10u64.into()),
+            ..Default::default()
+        };
+        let handler = |from: Option<u64>, to: Option<u64>, function: Option<&str>| {
+            MappingTransactionHandler {
+                handler: "handleTransaction".to_string(),
+                from: from.map(address),
+                to: to.map(address),
+                function: function.map(str::to_string),
+            }
+        };
+
+        let filter = EthereumTransactionFilter {
+            handlers: HashSet::from_iter(vec![
+                // Transactions from or to the data source address 1
+                (0, Some(address(1)), handler(None, None, None)),
+                // Calls of `transfer(address,uint256)` on contract 2
+                (
+                    0,
+                    None,
+                    handler(None, Some(2), Some("transfer(address,uint256)")),
+                ),
+                // Transactions from 3, but only from block 20 on
+                (20, None, handler(Some(3), None, None)),
+            ]),
+        };
+
+        let transfer = vec![0xa9, 0x05, 0x9c, 0xbb, 0, 0];
+
+        assert!(filter.matches(&transaction(1, 4, vec![])));
+        assert!(filter.matches(&transaction(4, 1, vec![])));
+        assert!(filter.matches(&transaction(4, 2, transfer.clone())));
+        assert!(
+            !filter.matches(&transaction(4, 2, vec![1, 2, 3, 4])),
+            "a call of a different function should be ignored"
+        );
+        assert!(
+            !filter.matches(&transaction(4, 2, vec![])),
+            "a plain transfer should not match a function filter"
+        );
+        assert!(
+            !filter.matches(&transaction(3, 4, vec![])),
+            "transactions before the start block should be ignored"
+        );
+        assert!(!filter.matches(&transaction(4, 5, transfer)));
+    }
+
+    #[test]
+    fn ethereum_log_filter_requires_transaction_receipt() {
+        let address = |id: u64| Address::from_low_u64_be(id);
+        let event = |id: u64| H256::from_low_u64_be(id);
+        let log = |address: Address, topic0: H256| Log {
+            address,
+            topics: vec![topic0],
+            ..Default::default()
+        };
+
+        let mut filter = EthereumLogFilter {
+            events_with_receipts: HashSet::from_iter(vec![(Some(address(1)), event(1))]),
+            ..Default::default()
+        };
+        filter.extend(EthereumLogFilter {
+            events_with_receipts: HashSet::from_iter(vec![(None, event(2))]),
+            ..Default::default()
+        });
+
+        assert!(filter.requires_transaction_receipts());
+        assert!(filter.requires_transaction_receipt(&log(address(1), event(1))));
+        assert!(
+            !filter.requires_transaction_receipt(&log(address(2), event(1))),
+            "the same event on another contract should not need a receipt"
+        );
+        assert!(filter.requires_transaction_receipt(&log(address(2), event(2))));
+        assert!(!filter.requires_transaction_receipt(&log(address(1), event(3))));
+        assert!(!EthereumLogFilter::default().requires_transaction_receipts());
+    }
+
+    #[test]
+    fn log_triggers_carry_requested_receipts() {
+        let address = |id: u64| Address::from_low_u64_be(id);
+        let event = |id: u64| H256::from_low_u64_be(id);
+        let tx_hash = H256::from_low_u64_be(100);
+        let log = |address: Address, topic0: H256, log_index: u64| Log {
+            address,
+            topics: vec![topic0],
+            transaction_hash: Some(tx_hash),
+            transaction_index: Some(0u64.into()),
+            log_index: Some(log_index.into()),
+            ..Default::default()
+        };
+        let receipt = TransactionReceipt {
+            transaction_hash: tx_hash,
+            transaction_index: 0u64.into(),
+            block_hash: None,
+            block_number: None,
+            cumulative_gas_used: 0u64.into(),
+            gas_used: None,
+            contract_address: None,
+            logs: vec![log(address(1), event(1), 0), log(address(1), event(2), 1)],
+            status: Some(1u64.into()),
+            root: None,
+            logs_bloom: Default::default(),
+        };
+
+        // Both events are indexed, but only the first one wants the receipt
+        let mut contracts_and_events_graph = GraphMap::new();
+        for sig in vec![event(1), event(2)] {
+            contracts_and_events_graph.add_edge(
+                LogFilterNode::Contract(address(1)),
+                LogFilterNode::Event(sig),
+                (),
+            );
+        }
+        let filter = EthereumLogFilter {
+            contracts_and_events_graph,
+            wildcard_events: HashSet::new(),
+            events_with_receipts: HashSet::from_iter(vec![(Some(address(1)), event(1))]),
+        };
+
+        let receipts_of = |triggers: &[EthereumTrigger]| {
+            triggers
+                .iter()
+                .map(|trigger| match trigger {
+                    EthereumTrigger::Log(_, receipt) => {
+                        receipt.as_ref().map(|receipt| receipt.transaction_hash)
+                    }
+                    _ => panic!("expected a log trigger"),
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // Non-final blocks have their receipts already
+        let block = EthereumBlock {
+            transaction_receipts: vec![receipt.clone()],
+            ..Default::default()
+        };
+        let triggers = parse_log_triggers(&filter, &block);
+        assert_eq!(vec![Some(tx_hash), None], receipts_of(&triggers));
+
+        // For final blocks, the receipts are fetched separately and then
+        // attached to the triggers
+        let triggers = receipt
+            .logs
+            .iter()
+            .map(|log| EthereumTrigger::Log(Arc::new(log.clone()), None))
+            .collect();
+        let receipts = HashMap::from_iter(vec![(tx_hash, Arc::new(receipt.clone()))]);
+        let triggers = attach_receipts_to_log_triggers(triggers, &receipts, &filter);
+        assert_eq!(vec![Some(tx_hash), None], receipts_of(&triggers));
+    }
+
     #[test]
     fn extending_ethereum_call_filter() {
         let mut base = EthereumCallFilter {
@@ -796,6 +1096,7 @@ fn complete_log_filter() {
             let logs_filters: Vec<_> = EthereumLogFilter {
                 contracts_and_events_graph,
                 wildcard_events: HashSet::new(),
+                events_with_receipts: HashSet::new(),
             }
             .eth_get_logs_filters()
             .collect();
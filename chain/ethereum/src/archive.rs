@@ -0,0 +1,559 @@
+//! Answer the JSON-RPC requests that `EthereumAdapter` makes from a local
+//! block archive rather than from an Ethereum node. The archive is a
+//! directory of firehose block bundles as described in
+//! `graph::firehose::archive`; the `dfuse.ethereum.codec.v1.Block`s in it
+//! contain everything that is needed to answer requests for blocks,
+//! receipts and logs. With that, the usual `PollingBlockStream` can index
+//! historical block ranges without an archive node.
+//!
+//! Only the requests needed for event, block and transaction handlers are
+//! supported. Requests that need the state of the chain, like `eth_call`,
+//! or traces, like `trace_filter`, fail with an RPC error.
+//!
+//! Blocks are found by number through the names of the bundle files.
+//! Looking up blocks by hash and receipts by transaction hash first checks
+//! the bundles that were read recently, which is where the blocks that
+//! the adapter asks about usually are, and only then reads the archive
+//! from its newest bundle backwards.
+
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use graph::firehose::archive::BlockArchive;
+use graph::prelude::web3::types::{Log, H160, H256, U64};
+use graph::prelude::*;
+use jsonrpc_core::types::{Call, ErrorCode, Params};
+use jsonrpc_core::Value;
+use prost::Message;
+use web3::RequestId;
+
+use crate::codec;
+
+/// How many bundles we keep in memory once we have decoded them
+const CACHED_BUNDLES: usize = 16;
+/// What we report for `web3_clientVersion`
+const CLIENT_VERSION: &str = "graph-node/block-archive";
+
+/// The decoded blocks of a bundle, together with the number of the first
+/// block in the bundle
+type CachedBundle = (u64, Arc<Vec<codec::Block>>);
+
+#[derive(Debug)]
+pub(crate) struct ArchiveNode {
+    archive: BlockArchive,
+    chain_id: u64,
+    next_id: AtomicUsize,
+    /// Bundles that will not change anymore, the most recently used last
+    cache: Mutex<VecDeque<CachedBundle>>,
+}
+
+impl ArchiveNode {
+    /// Serve requests from the archive in `dir`, reporting `chain_id` as
+    /// the chain id and network version
+    pub fn new(dir: impl Into<PathBuf>, chain_id: u64) -> Result<Self, Error> {
+        Ok(ArchiveNode {
+            archive: BlockArchive::new(dir)?,
+            chain_id,
+            next_id: AtomicUsize::new(1),
+            cache: Mutex::new(VecDeque::new()),
+        })
+    }
+
+    pub fn prepare(&self, method: &str, params: Vec<Value>) -> (RequestId, Call) {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        (id, web3::helpers::build_request(id, method, params))
+    }
+
+    pub async fn send(self: Arc<Self>, request: Call) -> Result<Value, web3::error::Error> {
+        let (method, params) = match request {
+            Call::MethodCall(call) => (call.method, call.params),
+            Call::Notification(_) | Call::Invalid { .. } => {
+                return Err(rpc_error(
+                    ErrorCode::InvalidRequest,
+                    "block archives only support method calls",
+                ))
+            }
+        };
+        let params = match params {
+            Params::Array(params) => params,
+            Params::None => vec![],
+            Params::Map(_) => {
+                return Err(rpc_error(
+                    ErrorCode::InvalidParams,
+                    "block archives only support positional parameters",
+                ))
+            }
+        };
+        self.call(&method, params)
+            .await
+            .map_err(|e| match e.downcast::<web3::error::Error>() {
+                Ok(e) => e,
+                Err(e) => web3::error::Error::Transport(format!("{:#}", e)),
+            })
+    }
+
+    async fn call(&self, method: &str, params: Vec<Value>) -> Result<Value, Error> {
+        match method {
+            "web3_clientVersion" => Ok(Value::from(CLIENT_VERSION)),
+            "net_version" => Ok(Value::from(self.chain_id.to_string())),
+            "eth_chainId" => to_json(U64::from(self.chain_id)),
+            "eth_blockNumber" => to_json(U64::from(self.head().await?.number)),
+            "eth_getBlockByNumber" => {
+                let block = match block_number(param(&params, 0)?)? {
+                    Some(number) => self.block_by_number(number).await?,
+                    None => Some(self.head().await?),
+                };
+                block_json(block, param(&params, 1)?)
+            }
+            "eth_getBlockByHash" => {
+                let hash: H256 = param(&params, 0)?;
+                let block = self.find(|block| block.hash == hash.as_bytes()).await?;
+                block_json(block, param(&params, 1)?)
+            }
+            "eth_getTransactionReceipt" => {
+                let hash: H256 = param(&params, 0)?;
+                let block = self
+                    .find(|block| {
+                        block
+                            .transaction_traces
+                            .iter()
+                            .any(|trace| trace.hash == hash.as_bytes())
+                    })
+                    .await?;
+                let receipt = match block {
+                    Some(block) => ethereum_block(&block)?
+                        .ethereum_block
+                        .transaction_receipts
+                        .into_iter()
+                        .find(|receipt| receipt.transaction_hash == hash),
+                    None => None,
+                };
+                to_json(receipt)
+            }
+            "eth_getLogs" => {
+                let filter: LogFilter = param(&params, 0)?;
+                to_json(self.logs(filter).await?)
+            }
+            _ => Err(rpc_error(
+                ErrorCode::MethodNotFound,
+                &format!("`{}` is not supported by block archives", method),
+            )
+            .into()),
+        }
+    }
+
+    /// The block with the highest number in the archive
+    async fn head(&self) -> Result<codec::Block, Error> {
+        let bundles = self.archive.bundles().await?;
+        for (idx, (base, path)) in bundles.iter().enumerate().rev() {
+            let last = idx + 1 == bundles.len();
+            let (_, blocks) = self.bundle(*base, path.clone(), last).await?;
+            if let Some(block) = blocks.last() {
+                return Ok(block.clone());
+            }
+        }
+        Err(anyhow!(
+            "the block archive {} does not contain any blocks",
+            self.archive.dir().display()
+        ))
+    }
+
+    async fn block_by_number(&self, number: u64) -> Result<Option<codec::Block>, Error> {
+        let bundles = self.archive.bundles().await?;
+        let idx = match bundles.iter().rposition(|(base, _)| *base <= number) {
+            Some(idx) => idx,
+            None => return Ok(None),
+        };
+        let (base, path) = &bundles[idx];
+        let (_, blocks) = self
+            .bundle(*base, path.clone(), idx + 1 == bundles.len())
+            .await?;
+        Ok(blocks.iter().find(|block| block.number == number).cloned())
+    }
+
+    /// Find the first block for which `pred` is true, looking in the
+    /// cached bundles first, and then in all bundles from newest to oldest
+    async fn find(
+        &self,
+        pred: impl Fn(&codec::Block) -> bool,
+    ) -> Result<Option<codec::Block>, Error> {
+        let cached: Vec<_> = self.cache.lock().unwrap().iter().cloned().collect();
+        for (_, blocks) in cached.iter().rev() {
+            if let Some(block) = blocks.iter().find(|block| pred(block)) {
+                return Ok(Some(block.clone()));
+            }
+        }
+
+        let bundles = self.archive.bundles().await?;
+        for (idx, (base, path)) in bundles.iter().enumerate().rev() {
+            if cached.iter().any(|(cached, _)| cached == base) {
+                continue;
+            }
+            let last = idx + 1 == bundles.len();
+            let (_, blocks) = self.bundle(*base, path.clone(), last).await?;
+            if let Some(block) = blocks.iter().find(|block| pred(block)) {
+                return Ok(Some(block.clone()));
+            }
+        }
+        Ok(None)
+    }
+
+    async fn logs(&self, filter: LogFilter) -> Result<Vec<Log>, Error> {
+        let blocks = match filter.block_hash {
+            Some(hash) => self
+                .find(|block| block.hash == hash.as_bytes())
+                .await?
+                .into_iter()
+                .collect(),
+            None => {
+                let head = self.head().await?.number;
+                let from = block_number(filter.from_block)?.unwrap_or(head);
+                let to = block_number(filter.to_block)?.unwrap_or(head);
+                self.blocks_in_range(from, to).await?
+            }
+        };
+
+        let addresses = filter.address.map(OneOrMany::into_vec);
+        let topics: Vec<_> = filter
+            .topics
+            .unwrap_or_default()
+            .into_iter()
+            .map(|topics| topics.map(OneOrMany::into_vec))
+            .collect();
+
+        let mut logs = Vec::new();
+        for block in blocks {
+            for receipt in ethereum_block(&block)?.ethereum_block.transaction_receipts {
+                for log in receipt.logs {
+                    let address_matches = addresses
+                        .as_ref()
+                        .map_or(true, |addresses| addresses.contains(&log.address));
+                    let topics_match = topics.iter().enumerate().all(|(idx, topics)| {
+                        topics.as_ref().map_or(true, |topics| {
+                            log.topics
+                                .get(idx)
+                                .map_or(false, |topic| topics.contains(topic))
+                        })
+                    });
+                    if address_matches && topics_match {
+                        logs.push(log);
+                    }
+                }
+            }
+        }
+        Ok(logs)
+    }
+
+    async fn blocks_in_range(&self, from: u64, to: u64) -> Result<Vec<codec::Block>, Error> {
+        let bundles = self.archive.bundles().await?;
+        let first = bundles
+            .iter()
+            .rposition(|(base, _)| *base <= from)
+            .unwrap_or(0);
+
+        let mut blocks = Vec::new();
+        for (idx, (base, path)) in bundles.iter().enumerate().skip(first) {
+            if *base > to {
+                break;
+            }
+            let last = idx + 1 == bundles.len();
+            let (_, bundle) = self.bundle(*base, path.clone(), last).await?;
+            blocks.extend(
+                bundle
+                    .iter()
+                    .filter(|block| block.number >= from && block.number <= to)
+                    .cloned(),
+            );
+        }
+        Ok(blocks)
+    }
+
+    /// The decoded blocks in the bundle starting at `base`. Bundles are
+    /// only cached once they can't change anymore, i.e., when they are
+    /// complete and a later bundle exists
+    async fn bundle(&self, base: u64, path: PathBuf, last: bool) -> Result<CachedBundle, Error> {
+        {
+            let mut cache = self.cache.lock().unwrap();
+            if let Some(pos) = cache.iter().position(|(cached, _)| *cached == base) {
+                let bundle = cache.remove(pos).unwrap();
+                cache.push_back(bundle.clone());
+                return Ok(bundle);
+            }
+        }
+
+        let bundle = self.archive.read_bundle(base, path).await?;
+        let complete = bundle.complete;
+        let blocks = bundle
+            .blocks
+            .iter()
+            .map(|block| {
+                codec::Block::decode(block.payload_buffer.as_slice())
+                    .with_context(|| format!("invalid Ethereum block #{}", block.number))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let bundle = (base, Arc::new(blocks));
+
+        if complete && !last {
+            let mut cache = self.cache.lock().unwrap();
+            cache.push_back(bundle.clone());
+            while cache.len() > CACHED_BUNDLES {
+                cache.pop_front();
+            }
+        }
+        Ok(bundle)
+    }
+}
+
+/// The filter of an `eth_getLogs` request
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LogFilter {
+    from_block: Option<Value>,
+    to_block: Option<Value>,
+    block_hash: Option<H256>,
+    address: Option<OneOrMany<H160>>,
+    topics: Option<Vec<Option<OneOrMany<H256>>>>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OneOrMany<T> {
+    One(T),
+    Many(Vec<T>),
+}
+
+impl<T> OneOrMany<T> {
+    fn into_vec(self) -> Vec<T> {
+        match self {
+            OneOrMany::One(value) => vec![value],
+            OneOrMany::Many(values) => values,
+        }
+    }
+}
+
+fn rpc_error(code: ErrorCode, message: &str) -> web3::error::Error {
+    web3::error::Error::Rpc(jsonrpc_core::Error {
+        code,
+        message: message.to_string(),
+        data: None,
+    })
+}
+
+fn param<T: serde::de::DeserializeOwned>(params: &[Value], idx: usize) -> Result<T, Error> {
+    let value = params.get(idx).cloned().unwrap_or(Value::Null);
+    serde_json::from_value(value).map_err(|e| {
+        rpc_error(
+            ErrorCode::InvalidParams,
+            &format!("invalid parameter {}: {}", idx, e),
+        )
+        .into()
+    })
+}
+
+fn to_json<T: serde::Serialize>(value: T) -> Result<Value, Error> {
+    Ok(serde_json::to_value(value)?)
+}
+
+/// Parse a block parameter. Returns `None` for the latest block, which is
+/// also what a missing parameter means
+fn block_number(value: Option<Value>) -> Result<Option<u64>, Error> {
+    match value {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::String(s)) if s == "latest" || s == "pending" => Ok(None),
+        Some(Value::String(s)) if s == "earliest" => Ok(Some(0)),
+        Some(value) => {
+            let number: U64 = serde_json::from_value(value).map_err(|e| {
+                rpc_error(
+                    ErrorCode::InvalidParams,
+                    &format!("invalid block number: {}", e),
+                )
+            })?;
+            Ok(Some(number.as_u64()))
+        }
+    }
+}
+
+fn ethereum_block(block: &codec::Block) -> Result<EthereumBlockWithCalls, Error> {
+    if block.header.is_none() {
+        return Err(anyhow!(
+            "Ethereum block #{} in the block archive has no header",
+            block.number
+        ));
+    }
+    Ok(block.into())
+}
+
+/// The JSON for `block` as `eth_getBlockBy*` returns it; unless `full` is
+/// set, transactions are only listed by their hash
+fn block_json(block: Option<codec::Block>, full: bool) -> Result<Value, Error> {
+    let block = match block {
+        Some(block) => ethereum_block(&block)?.ethereum_block.block,
+        None => return Ok(Value::Null),
+    };
+    let mut json = serde_json::to_value(block.as_ref())?;
+    if !full {
+        let hashes: Vec<_> = block.transactions.iter().map(|tx| tx.hash).collect();
+        json["transactions"] = serde_json::to_value(hashes)?;
+    }
+    Ok(json)
+}
+
+#[cfg(test)]
+mod tests {
+    use graph::firehose::bstream;
+    use graph::prelude::web3::types::{BlockId, BlockNumber as Web3BlockNumber, FilterBuilder};
+    use graph::prelude::web3::Web3;
+
+    use super::*;
+    use crate::Transport;
+
+    fn hash(number: u64) -> H256 {
+        H256::from_low_u64_be(number + 1)
+    }
+
+    fn tx_hash(number: u64) -> H256 {
+        H256::from_low_u64_be(1000 + number)
+    }
+
+    fn address(number: u64) -> H160 {
+        H160::from_low_u64_be(number + 1)
+    }
+
+    /// A block with one transaction that emits one log from `address(number)`
+    fn block(number: u64) -> codec::Block {
+        let log = codec::Log {
+            address: address(number).as_bytes().to_vec(),
+            topics: vec![H256::from_low_u64_be(42).as_bytes().to_vec()],
+            ..Default::default()
+        };
+        let trace = codec::TransactionTrace {
+            hash: tx_hash(number).as_bytes().to_vec(),
+            from: vec![0; 20],
+            to: vec![0; 20],
+            status: codec::TransactionTraceStatus::Succeeded as i32,
+            receipt: Some(codec::TransactionReceipt {
+                logs_bloom: vec![0; 256],
+                logs: vec![log],
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let parent_hash = if number == 0 {
+            H256::zero()
+        } else {
+            hash(number - 1)
+        };
+        codec::Block {
+            hash: hash(number).as_bytes().to_vec(),
+            number,
+            header: Some(codec::BlockHeader {
+                parent_hash: parent_hash.as_bytes().to_vec(),
+                uncle_hash: vec![0; 32],
+                coinbase: vec![0; 20],
+                state_root: vec![0; 32],
+                transactions_root: vec![0; 32],
+                receipt_root: vec![0; 32],
+                mix_hash: vec![0; 32],
+                number,
+                hash: hash(number).as_bytes().to_vec(),
+                ..Default::default()
+            }),
+            transaction_traces: vec![trace],
+            ..Default::default()
+        }
+    }
+
+    fn encode_bundle(blocks: &[codec::Block]) -> Vec<u8> {
+        let mut bytes = b"dbin\x01ETH00".to_vec();
+        for block in blocks {
+            let msg = bstream::Block {
+                number: block.number,
+                id: hex::encode(&block.hash),
+                previous_id: hex::encode(&block.header.as_ref().unwrap().parent_hash),
+                payload_kind: bstream::Protocol::Eth as i32,
+                payload_buffer: block.encode_to_vec(),
+                ..Default::default()
+            }
+            .encode_to_vec();
+            bytes.extend_from_slice(&(msg.len() as u32).to_be_bytes());
+            bytes.extend_from_slice(&msg);
+        }
+        bytes
+    }
+
+    #[tokio::test]
+    async fn serves_blocks_receipts_and_logs() {
+        let dir = std::env::temp_dir().join(format!("graph-eth-archive-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("0000000000.dbin"),
+            encode_bundle(&[block(0), block(1)]),
+        )
+        .unwrap();
+        std::fs::write(dir.join("0000000002.dbin"), encode_bundle(&[block(2)])).unwrap();
+
+        let web3 = Web3::new(Transport::new_archive(dir.to_str().unwrap(), 1).unwrap());
+
+        assert_eq!("1", web3.net().version().await.unwrap());
+        assert_eq!(U64::from(2), web3.eth().block_number().await.unwrap());
+
+        let block = web3
+            .eth()
+            .block(BlockId::Number(Web3BlockNumber::Number(1.into())))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(Some(hash(1)), block.hash);
+        assert_eq!(hash(0), block.parent_hash);
+        assert_eq!(vec![tx_hash(1)], block.transactions);
+
+        let block = web3
+            .eth()
+            .block_with_txs(BlockId::Hash(hash(2)))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(Some(U64::from(2)), block.number);
+        assert_eq!(tx_hash(2), block.transactions[0].hash);
+
+        let missing = web3
+            .eth()
+            .block(BlockId::Number(Web3BlockNumber::Number(3.into())))
+            .await
+            .unwrap();
+        assert!(missing.is_none());
+
+        let receipt = web3
+            .eth()
+            .transaction_receipt(tx_hash(0))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(Some(hash(0)), receipt.block_hash);
+        assert_eq!(1, receipt.logs.len());
+
+        let filter = FilterBuilder::default()
+            .from_block(Web3BlockNumber::Number(0.into()))
+            .to_block(Web3BlockNumber::Number(2.into()))
+            .address(vec![address(0), address(2)])
+            .topics(Some(vec![H256::from_low_u64_be(42)]), None, None, None)
+            .build();
+        let logs = web3.eth().logs(filter).await.unwrap();
+        let numbers: Vec<_> = logs.iter().map(|log| log.block_number).collect();
+        assert_eq!(vec![Some(U64::from(0)), Some(U64::from(2))], numbers);
+
+        let filter = FilterBuilder::default()
+            .from_block(Web3BlockNumber::Number(0.into()))
+            .to_block(Web3BlockNumber::Number(2.into()))
+            .topics(Some(vec![H256::from_low_u64_be(7)]), None, None, None)
+            .build();
+        assert!(web3.eth().logs(filter).await.unwrap().is_empty());
+
+        // Requests that need an Ethereum node fail
+        assert!(web3.eth().gas_price().await.is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
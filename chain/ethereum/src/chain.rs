@@ -18,13 +18,13 @@ use graph::{
         IngestorAdapter as IngestorAdapterTrait, IngestorError, TriggerFilter as _,
     },
     cheap_clone::CheapClone,
-    components::store::DeploymentLocator,
+    components::store::{DeploymentLocator, WritableStore},
     firehose::bstream,
     log::factory::{ComponentLoggerConfig, ElasticComponentLoggerConfig},
     prelude::{
         async_trait, error, lazy_static, o, serde_json as json, web3::types::H256, BlockNumber,
         ChainStore, EthereumBlockWithCalls, Future01CompatExt, Logger, LoggerFactory,
-        MetricsRegistry, NodeId, SubgraphStore,
+        MetricsRegistry, NodeId,
     },
 };
 use prost::Message;
@@ -41,7 +41,7 @@ use crate::{
     data_source::{DataSource, UnresolvedDataSource},
     ethereum_adapter::{
         blocks_with_triggers, get_calls, parse_block_triggers, parse_call_triggers,
-        parse_log_triggers,
+        parse_log_triggers, parse_transaction_triggers,
     },
     SubgraphEthRpcMetrics, TriggerFilter,
 };
@@ -75,7 +75,6 @@ pub struct Chain {
     ancestor_count: BlockNumber,
     chain_store: Arc<dyn ChainStore>,
     call_cache: Arc<dyn EthereumCallCache>,
-    subgraph_store: Arc<dyn SubgraphStore>,
     chain_head_update_listener: Arc<dyn ChainHeadUpdateListener>,
     reorg_threshold: BlockNumber,
     pub is_ingestible: bool,
@@ -95,7 +94,6 @@ impl Chain {
         registry: Arc<dyn MetricsRegistry>,
         chain_store: Arc<dyn ChainStore>,
         call_cache: Arc<dyn EthereumCallCache>,
-        subgraph_store: Arc<dyn SubgraphStore>,
         firehose_endpoints: FirehoseNetworkEndpoints,
         eth_adapters: EthereumNetworkAdapters,
         chain_head_update_listener: Arc<dyn ChainHeadUpdateListener>,
@@ -113,7 +111,6 @@ impl Chain {
             ancestor_count,
             chain_store,
             call_cache,
-            subgraph_store,
             chain_head_update_listener,
             reorg_threshold,
             is_ingestible,
@@ -123,6 +120,7 @@ impl Chain {
     async fn new_polling_block_stream(
         &self,
         deployment: DeploymentLocator,
+        store: Arc<dyn WritableStore>,
         start_blocks: Vec<BlockNumber>,
         adapter: Arc<TriggersAdapter>,
         filter: Arc<TriggerFilter>,
@@ -134,12 +132,6 @@ impl Chain {
             .subgraph_logger(&deployment)
             .new(o!("component" => "BlockStream"));
         let chain_store = self.chain_store().clone();
-        let writable = self
-            .subgraph_store
-            .cheap_clone()
-            .writable(logger.clone(), deployment.id)
-            .await
-            .with_context(|| format!("no store for deployment `{}`", deployment.hash))?;
         let chain_head_update_stream = self
             .chain_head_update_listener
             .subscribe(self.name.clone(), logger.clone());
@@ -155,7 +147,7 @@ impl Chain {
         };
 
         Ok(Box::new(PollingBlockStream::new(
-            writable,
+            store,
             chain_store,
             chain_head_update_stream,
             adapter,
@@ -175,6 +167,7 @@ impl Chain {
     async fn new_firehose_block_stream(
         &self,
         deployment: DeploymentLocator,
+        store: Arc<dyn WritableStore>,
         start_blocks: Vec<BlockNumber>,
         adapter: Arc<TriggersAdapter>,
         filter: Arc<TriggerFilter>,
@@ -192,12 +185,7 @@ impl Chain {
             .new(o!("component" => "FirehoseBlockStream"));
 
         let firehose_mapper = Arc::new(FirehoseMapper {});
-        let firehose_cursor = self
-            .subgraph_store
-            .cheap_clone()
-            .writable(logger.clone(), deployment.id)
-            .await?
-            .block_cursor()?;
+        let firehose_cursor = store.block_cursor()?;
 
         Ok(Box::new(FirehoseBlockStream::new(
             firehose_endpoint,
@@ -281,6 +269,7 @@ impl Blockchain for Chain {
     async fn new_block_stream(
         &self,
         deployment: DeploymentLocator,
+        store: Arc<dyn WritableStore>,
         start_blocks: Vec<BlockNumber>,
         filter: Arc<TriggerFilter>,
         metrics: Arc<BlockStreamMetrics>,
@@ -302,6 +291,7 @@ impl Blockchain for Chain {
         if self.firehose_endpoints.len() > 0 {
             self.new_firehose_block_stream(
                 deployment,
+                store,
                 start_blocks,
                 adapter,
                 filter,
@@ -312,6 +302,7 @@ impl Blockchain for Chain {
         } else {
             self.new_polling_block_stream(
                 deployment,
+                store,
                 start_blocks,
                 adapter,
                 filter,
@@ -371,6 +362,10 @@ impl Blockchain for Chain {
             call_cache: self.call_cache.cheap_clone(),
         })
     }
+
+    fn reorg_threshold(&self) -> BlockNumber {
+        self.reorg_threshold
+    }
 }
 
 /// This is used in `EthereumAdapter::triggers_in_block`, called when re-processing a block for
@@ -525,6 +520,10 @@ impl TriggersAdapterTrait<Chain> for TriggersAdapter {
                 ));
                 triggers.append(&mut parse_call_triggers(&filter.call, &full_block)?);
                 triggers.append(&mut parse_block_triggers(filter.block.clone(), &full_block));
+                triggers.append(&mut parse_transaction_triggers(
+                    &filter.transaction,
+                    &full_block.ethereum_block,
+                )?);
                 Ok(BlockWithTriggers::new(block, triggers))
             }
         }
@@ -654,6 +653,10 @@ impl FirehoseMapper {
         triggers.append(&mut parse_log_triggers(&filter.log, &block.ethereum_block));
         triggers.append(&mut parse_call_triggers(&filter.call, &block)?);
         triggers.append(&mut parse_block_triggers(filter.block.clone(), &block));
+        triggers.append(&mut parse_transaction_triggers(
+            &filter.transaction,
+            &block.ethereum_block,
+        )?);
 
         Ok(BlockWithTriggers::new(
             BlockFinality::NonFinal(block),
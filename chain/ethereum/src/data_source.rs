@@ -26,6 +26,7 @@ use graph::{
 
 use graph::data::subgraph::{calls_host_fn, DataSourceContext, Source};
 
+use crate::adapter::FunctionSelector;
 use crate::chain::Chain;
 use crate::trigger::{EthereumBlockTriggerType, EthereumTrigger, MappingTrigger};
 
@@ -74,6 +75,10 @@ impl blockchain::DataSource<Chain> for DataSource {
         &self.kind
     }
 
+    fn entities(&self) -> &[String] {
+        &self.mapping.entities
+    }
+
     fn network(&self) -> Option<&str> {
         self.network.as_ref().map(|s| s.as_str())
     }
@@ -112,20 +117,29 @@ impl blockchain::DataSource<Chain> for DataSource {
             && mapping.event_handlers == other.mapping.event_handlers
             && mapping.call_handlers == other.mapping.call_handlers
             && mapping.block_handlers == other.mapping.block_handlers
+            && mapping.transaction_handlers == other.mapping.transaction_handlers
             && context == &other.context
     }
 
-    fn as_stored_dynamic_data_source(&self) -> StoredDynamicDataSource {
-        StoredDynamicDataSource {
+    fn as_stored_dynamic_data_source(&self) -> Result<StoredDynamicDataSource, Error> {
+        let context = self
+            .context
+            .as_ref()
+            .as_ref()
+            .map(serde_json::to_string)
+            .transpose()
+            .with_context(|| {
+                format!("failed to serialize context of data source `{}`", self.name)
+            })?;
+        Ok(StoredDynamicDataSource {
             name: self.name.to_owned(),
-            source: self.source.clone(),
-            context: self
-                .context
-                .as_ref()
-                .as_ref()
-                .map(|ctx| serde_json::to_string(&ctx).unwrap()),
+            address: self.source.address,
+            account: None,
+            abi: self.source.abi.clone(),
+            start_block: self.source.start_block,
+            context,
             creation_block: self.creation_block,
-        }
+        })
     }
 
     fn from_stored_dynamic_data_source(
@@ -134,13 +148,21 @@ impl blockchain::DataSource<Chain> for DataSource {
     ) -> Result<Self, Error> {
         let StoredDynamicDataSource {
             name,
-            source,
+            address,
+            account: _,
+            abi,
+            start_block,
             context,
             creation_block,
         } = stored;
         let template = templates
             .get(name.as_str())
             .ok_or_else(|| anyhow!("no template named `{}` was found", name))?;
+        let source = Source {
+            address,
+            abi,
+            start_block,
+        };
         let context = context
             .map(|ctx| serde_json::from_str::<Entity>(&ctx))
             .transpose()?;
@@ -197,6 +219,45 @@ impl blockchain::DataSource<Chain> for DataSource {
             errors.push(anyhow!("data source has duplicated block handlers"));
         }
 
+        if !self.mapping.transaction_handlers.is_empty() {
+            if self.mapping.api_version < semver::Version::new(0, 0, 6) {
+                errors.push(anyhow!(
+                    "transaction handlers require apiVersion 0.0.6 or later, but the mapping has \
+                     apiVersion {}",
+                    self.mapping.api_version
+                ));
+            }
+
+            // Without any address to filter on, a handler would be called
+            // for every transaction on the chain
+            if no_source_address {
+                for handler in &self.mapping.transaction_handlers {
+                    if handler.from.is_none() && handler.to.is_none() {
+                        errors.push(anyhow!(
+                            "transaction handler `{}` needs a `from` or `to` address since the \
+                             data source has no source address",
+                            handler.handler
+                        ));
+                    }
+                }
+            }
+        }
+
+        // The receipt is passed as an extra field of the event, which older
+        // versions of graph-ts don't know about
+        if self.mapping.api_version < semver::Version::new(0, 0, 7) {
+            for handler in &self.mapping.event_handlers {
+                if handler.receipt {
+                    errors.push(anyhow!(
+                        "event handler `{}` has `receipt: true`, which requires apiVersion \
+                         0.0.7 or later, but the mapping has apiVersion {}",
+                        handler.handler,
+                        self.mapping.api_version
+                    ));
+                }
+            }
+        }
+
         errors
     }
 
@@ -293,6 +354,17 @@ impl DataSource {
         }
     }
 
+    fn handler_for_transaction(
+        &self,
+        transaction: &Transaction,
+    ) -> Option<MappingTransactionHandler> {
+        self.mapping
+            .transaction_handlers
+            .iter()
+            .find(|handler| handler.matches(self.source.address, transaction))
+            .cloned()
+    }
+
     /// Returns the contract event with the given signature, if it exists. A an event from the ABI
     /// will be matched if:
     /// 1. An event signature is equal to `signature`.
@@ -432,10 +504,14 @@ impl DataSource {
         let trigger_address = match trigger {
             EthereumTrigger::Block(_, EthereumBlockTriggerType::WithCallTo(address)) => address,
             EthereumTrigger::Call(call) => &call.to,
-            EthereumTrigger::Log(log) => &log.address,
+            EthereumTrigger::Log(log, _) => &log.address,
 
             // Unfiltered block triggers match any data source address.
             EthereumTrigger::Block(_, EthereumBlockTriggerType::Every) => return true,
+
+            // Transaction handlers have their own address filters, which
+            // are checked when looking for the handler.
+            EthereumTrigger::Transaction(_) => return true,
         };
 
         ds_address == *trigger_address
@@ -468,7 +544,7 @@ impl DataSource {
                     handler.handler,
                 )))
             }
-            EthereumTrigger::Log(log) => {
+            EthereumTrigger::Log(log, receipt) => {
                 let potential_handlers = self.handlers_for_log(log)?;
 
                 // Map event handlers to (event handler, event ABI) pairs; fail if there are
@@ -557,6 +633,16 @@ impl DataSource {
                     }
                 };
 
+                let receipt = if event_handler.receipt {
+                    Some(
+                        receipt
+                            .cheap_clone()
+                            .context("Found no transaction receipt for event")?,
+                    )
+                } else {
+                    None
+                };
+
                 let logging_extras = Arc::new(o! {
                     "signature" => event_handler.event.to_string(),
                     "address" => format!("{}", &log.address),
@@ -567,6 +653,7 @@ impl DataSource {
                         transaction: Arc::new(transaction),
                         log: log.cheap_clone(),
                         params,
+                        receipt,
                     },
                     event_handler.handler,
                     logging_extras,
@@ -673,6 +760,32 @@ impl DataSource {
                     logging_extras,
                 )))
             }
+            EthereumTrigger::Transaction(receipt) => {
+                let transaction = block
+                    .transactions
+                    .iter()
+                    .find(|tx| tx.hash == receipt.transaction_hash)
+                    .cloned()
+                    .context("Found no transaction for receipt")?;
+
+                let handler = match self.handler_for_transaction(&transaction) {
+                    Some(handler) => handler,
+                    None => return Ok(None),
+                };
+
+                let logging_extras = Arc::new(o! {
+                    "transaction" => format!("{:x}", transaction.hash),
+                });
+                Ok(Some(TriggerWithHandler::new_with_logging_extras(
+                    MappingTrigger::Transaction {
+                        block,
+                        transaction: Arc::new(transaction),
+                        receipt: receipt.cheap_clone(),
+                    },
+                    handler.handler,
+                    logging_extras,
+                )))
+            }
         }
     }
 }
@@ -829,6 +942,8 @@ pub struct UnresolvedMapping {
     pub call_handlers: Vec<MappingCallHandler>,
     #[serde(default)]
     pub event_handlers: Vec<MappingEventHandler>,
+    #[serde(default)]
+    pub transaction_handlers: Vec<MappingTransactionHandler>,
     pub file: Link,
 }
 
@@ -842,6 +957,7 @@ pub struct Mapping {
     pub block_handlers: Vec<MappingBlockHandler>,
     pub call_handlers: Vec<MappingCallHandler>,
     pub event_handlers: Vec<MappingEventHandler>,
+    pub transaction_handlers: Vec<MappingTransactionHandler>,
     pub runtime: Arc<Vec<u8>>,
     pub link: Link,
 }
@@ -886,6 +1002,7 @@ impl UnresolvedMapping {
             block_handlers,
             call_handlers,
             event_handlers,
+            transaction_handlers,
             file: link,
         } = self;
 
@@ -919,6 +1036,7 @@ impl UnresolvedMapping {
             block_handlers: block_handlers.clone(),
             call_handlers: call_handlers.clone(),
             event_handlers: event_handlers.clone(),
+            transaction_handlers,
             runtime,
             link,
         })
@@ -984,6 +1102,10 @@ pub struct MappingEventHandler {
     pub event: String,
     pub topic0: Option<H256>,
     pub handler: String,
+    /// Whether the handler receives the receipt of the transaction that
+    /// emitted the event
+    #[serde(default)]
+    pub receipt: bool,
 }
 
 impl MappingEventHandler {
@@ -993,6 +1115,47 @@ impl MappingEventHandler {
     }
 }
 
+/// A handler for transactions. A transaction matches the handler if it
+/// passes all the filters that are set. A handler that has neither a `from`
+/// nor a `to` address matches transactions sent from or to the address of
+/// its data source
+#[derive(Clone, Debug, Hash, Eq, PartialEq, Deserialize)]
+pub struct MappingTransactionHandler {
+    pub handler: String,
+    pub from: Option<Address>,
+    pub to: Option<Address>,
+    /// The signature of the function the transaction calls, like
+    /// `transfer(address,uint256)`
+    pub function: Option<String>,
+}
+
+impl MappingTransactionHandler {
+    pub fn selector(&self) -> Option<FunctionSelector> {
+        self.function.as_ref().map(|function| {
+            let sig = keccak256(function.as_bytes());
+            [sig[0], sig[1], sig[2], sig[3]]
+        })
+    }
+
+    /// Check whether `transaction` matches this handler of a data source
+    /// with address `address`
+    pub fn matches(&self, address: Option<Address>, transaction: &Transaction) -> bool {
+        let matches_address = match (self.from, self.to) {
+            (None, None) => address.map_or(false, |address| {
+                transaction.from == Some(address) || transaction.to == Some(address)
+            }),
+            (from, to) => {
+                from.map_or(true, |from| transaction.from == Some(from))
+                    && to.map_or(true, |to| transaction.to == Some(to))
+            }
+        };
+        matches_address
+            && self.selector().map_or(true, |selector| {
+                transaction.input.0.get(..4) == Some(&selector[..])
+            })
+    }
+}
+
 /// Hashes a string to a H256 hash.
 fn string_to_h256(s: &str) -> H256 {
     let mut result = [0u8; 32];
@@ -14,7 +14,7 @@ use graph::{
         anyhow::{self, anyhow, bail},
         async_trait, debug, error, ethabi,
         futures03::{self, compat::Future01CompatExt, FutureExt, StreamExt, TryStreamExt},
-        hex, info, retry, serde_json as json, stream, tiny_keccak, trace, warn,
+        hex, info, retry, serde_json as json, stream, tiny_keccak, tokio, trace, warn,
         web3::{
             self,
             types::{
@@ -34,6 +34,7 @@ use graph::{
 };
 use itertools::Itertools;
 use lazy_static::lazy_static;
+use std::borrow::Borrow;
 use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::convert::TryFrom;
 use std::iter::FromIterator;
@@ -46,10 +47,12 @@ use crate::{
     adapter::{
         EthGetLogsFilter, EthereumAdapter as EthereumAdapterTrait, EthereumBlockFilter,
         EthereumCallFilter, EthereumContractCall, EthereumContractCallError, EthereumLogFilter,
-        ProviderEthRpcMetrics, SubgraphEthRpcMetrics,
+        EthereumTransactionFilter, ProviderEthRpcMetrics, SubgraphEthRpcMetrics,
     },
+    health::{ProviderHealth, HEALTH_CHECK_INTERVAL},
     transport::Transport,
     trigger::{EthereumBlockTriggerType, EthereumTrigger},
+    verification::{Verification, Verifier},
     TriggerFilter,
 };
 
@@ -62,6 +65,10 @@ pub struct EthereumAdapter {
     web3: Arc<Web3<Transport>>,
     metrics: Arc<ProviderEthRpcMetrics>,
     supports_eip_1898: bool,
+    /// Checks responses against other providers if verification is
+    /// turned on for the network
+    verifier: Option<Arc<Verifier>>,
+    health: Arc<ProviderHealth>,
 }
 
 lazy_static! {
@@ -140,6 +147,8 @@ impl CheapClone for EthereumAdapter {
             web3: self.web3.cheap_clone(),
             metrics: self.metrics.cheap_clone(),
             supports_eip_1898: self.supports_eip_1898,
+            verifier: self.verifier.cheap_clone(),
+            health: self.health.cheap_clone(),
         }
     }
 }
@@ -153,14 +162,20 @@ impl EthereumAdapter {
         provider_metrics: Arc<ProviderEthRpcMetrics>,
         supports_eip_1898: bool,
     ) -> Self {
-        // Unwrap: The transport was constructed with this url, so it is valid and has a host.
+        // Unwrap: The transport was constructed with this url, so it is valid. Only the
+        // `file` urls of block archives do not have a host
         let hostname = graph::url::Url::parse(url)
             .unwrap()
             .host_str()
-            .unwrap()
-            .to_string();
+            .map(str::to_string)
+            .unwrap_or_else(|| provider.clone());
 
-        let web3 = Arc::new(Web3::new(transport));
+        let health = Arc::new(ProviderHealth::new(
+            logger.clone(),
+            provider.clone(),
+            provider_metrics.cheap_clone(),
+        ));
+        let web3 = Arc::new(Web3::new(transport.with_health(health.cheap_clone())));
 
         // Use the client version to check if it is ganache. For compatibility with unit tests, be
         // are lenient with errors, defaulting to false.
@@ -178,6 +193,38 @@ impl EthereumAdapter {
             web3,
             metrics: provider_metrics,
             supports_eip_1898: supports_eip_1898 && !is_ganache,
+            verifier: None,
+            health,
+        }
+    }
+
+    pub(crate) fn health(&self) -> &ProviderHealth {
+        &self.health
+    }
+
+    /// The number of the latest block the provider knows about. A request
+    /// that takes too long is dropped, which the transport counts as a
+    /// failure of the provider
+    pub(crate) async fn head_block_number(&self) -> Result<BlockNumber, Error> {
+        let number = tokio::time::timeout(*HEALTH_CHECK_INTERVAL, self.web3.eth().block_number())
+            .await
+            .map_err(|_| {
+                anyhow!("Ethereum node took too long to return its latest block number")
+            })??;
+        Ok(number.as_u64() as BlockNumber)
+    }
+
+    /// Return an adapter that checks the responses it gets against those
+    /// of `peers` as described by `verification`
+    pub fn with_verification(
+        &self,
+        verification: Verification,
+        peers: Vec<EthereumAdapter>,
+    ) -> Self {
+        let verifier = Verifier::new(verification, peers, self.metrics.cheap_clone());
+        EthereumAdapter {
+            verifier: Some(Arc::new(verifier)),
+            ..self.cheap_clone()
         }
     }
 
@@ -619,6 +666,75 @@ impl EthereumAdapter {
             .compat()
     }
 
+    /// Like `call`, but checks the result with other providers if
+    /// verification is turned on. Reverts are compared like any other
+    /// result, regardless of the reason the providers give for them
+    fn verified_call(
+        &self,
+        logger: Logger,
+        contract_address: Address,
+        call_data: Bytes,
+        block_ptr: BlockPtr,
+    ) -> Box<dyn Future<Item = Bytes, Error = EthereumContractCallError> + Send> {
+        let call = self.call(
+            logger.clone(),
+            contract_address,
+            call_data.clone(),
+            block_ptr.clone(),
+        );
+        let verifier = match &self.verifier {
+            Some(verifier) => verifier.cheap_clone(),
+            None => return Box::new(call),
+        };
+
+        fn outcome(
+            result: Result<Bytes, EthereumContractCallError>,
+        ) -> Result<Result<Bytes, String>, EthereumContractCallError> {
+            match result {
+                Ok(bytes) => Ok(Ok(bytes)),
+                Err(EthereumContractCallError::Revert(reason)) => Ok(Err(reason)),
+                Err(e) => Err(e),
+            }
+        }
+
+        let checks: Vec<_> = verifier
+            .peers()
+            .into_iter()
+            .map(|peer| {
+                let provider = peer.provider.clone();
+                peer.call(
+                    logger.clone(),
+                    contract_address,
+                    call_data.clone(),
+                    block_ptr.clone(),
+                )
+                .compat()
+                .map(move |result| (provider, outcome(result)))
+            })
+            .collect();
+        let provider = self.provider.clone();
+        Box::new(
+            async move {
+                let (result, checks) =
+                    futures03::future::join(call.compat(), futures03::future::join_all(checks))
+                        .await;
+                verifier
+                    .decide(
+                        &logger,
+                        "eth_call",
+                        &provider,
+                        outcome(result)?,
+                        checks,
+                        |result| result.as_ref().ok().cloned(),
+                    )
+                    .map_err(EthereumContractCallError::Disagreement)?
+                    .map_err(EthereumContractCallError::Revert)
+            }
+            .boxed()
+            .compat(),
+        )
+    }
+
     /// Request blocks by hash through JSON-RPC.
     fn load_blocks_rpc(
         &self,
@@ -626,9 +742,12 @@ impl EthereumAdapter {
         ids: Vec<H256>,
     ) -> impl Stream<Item = Arc<LightEthereumBlock>, Error = Error> + Send {
         let web3 = self.web3.clone();
+        let eth = self.cheap_clone();
 
         stream::iter_ok::<_, Error>(ids.into_iter().map(move |hash| {
             let web3 = web3.clone();
+            let eth = eth.cheap_clone();
+            let logger = logger.clone();
             retry(format!("load block {}", hash), &logger)
                 .limit(*REQUEST_RETRIES)
                 .timeout_secs(*JSON_RPC_TIMEOUT)
@@ -646,10 +765,51 @@ impl EthereumAdapter {
                 .boxed()
                 .compat()
                 .from_err()
+                .and_then(move |block| eth.verified_block(logger, block).boxed().compat())
         }))
         .buffered(*BLOCK_BATCH_SIZE)
     }
 
+    /// Check a block that this provider returned against the blocks with
+    /// the same hash that other providers return, if verification is turned
+    /// on. Providers that do not have the block do not take part
+    async fn verified_block<B>(self, logger: Logger, block: B) -> Result<B, Error>
+    where
+        B: Borrow<LightEthereumBlock> + From<LightEthereumBlock>,
+    {
+        let verifier = match &self.verifier {
+            Some(verifier) => verifier,
+            None => return Ok(block),
+        };
+        let hash = match block.borrow().hash {
+            Some(hash) => hash,
+            None => return Ok(block),
+        };
+
+        let checks = verifier.peers().into_iter().map(|peer| {
+            let provider = peer.provider.clone();
+            peer.block_by_hash(&logger, hash)
+                .compat()
+                .map(move |result| {
+                    let block = result.and_then(|block| {
+                        block
+                            .map(B::from)
+                            .ok_or_else(|| anyhow!("provider does not have block {:?}", hash))
+                    });
+                    (provider, block)
+                })
+        });
+        let checks = futures03::future::join_all(checks).await;
+        verifier.decide(
+            &logger,
+            "eth_getBlockByHash",
+            &self.provider,
+            block,
+            checks,
+            |block| block_key(block.borrow()),
+        )
+    }
+
     /// Request blocks ptrs for numbers through JSON-RPC.
     ///
     /// Reorg safety: If ids are numbers, they must be a final blocks.
@@ -719,6 +879,62 @@ impl EthereumAdapter {
         to: BlockNumber,
         log_filter: EthereumLogFilter,
     ) -> DynTryFuture<'static, Vec<Log>, Error> {
+        if let Some(verifier) = &self.verifier {
+            let eth = EthereumAdapter {
+                verifier: None,
+                ..self.cheap_clone()
+            };
+            let logs = eth.logs_in_block_range(
+                logger,
+                subgraph_metrics.cheap_clone(),
+                from,
+                to,
+                log_filter.clone(),
+            );
+            // A peer that has not seen block `to` yet returns fewer logs
+            // than the provider without being wrong; such peers do not take
+            // part in the comparison
+            let checks: Vec<_> = verifier
+                .peers()
+                .into_iter()
+                .map(|peer| {
+                    let peer = peer.cheap_clone();
+                    let provider = peer.provider.clone();
+                    let logger = logger.clone();
+                    let subgraph_metrics = subgraph_metrics.cheap_clone();
+                    let log_filter = log_filter.clone();
+                    async move {
+                        let head = peer.head_block_number().await?;
+                        if head < to {
+                            return Err(anyhow!(
+                                "provider is at block {} and can not check logs up to block {}",
+                                head,
+                                to
+                            ));
+                        }
+                        peer.logs_in_block_range(&logger, subgraph_metrics, from, to, log_filter)
+                            .await
+                    }
+                    .map(move |logs| (provider, logs))
+                })
+                .collect();
+            let verifier = verifier.cheap_clone();
+            let logger = logger.clone();
+            return async move {
+                let (logs, checks) =
+                    futures03::future::join(logs, futures03::future::join_all(checks)).await;
+                verifier.decide(
+                    &logger,
+                    "eth_getLogs",
+                    &eth.provider,
+                    logs?,
+                    checks,
+                    |logs| logs_key(logs),
+                )
+            }
+            .boxed();
+        }
+
         let eth: Self = self.cheap_clone();
         let logger = logger.clone();
 
@@ -1006,6 +1222,7 @@ impl EthereumAdapterTrait for EthereumAdapter {
     ) -> Box<dyn Future<Item = Option<LightEthereumBlock>, Error = Error> + Send> {
         let web3 = self.web3.clone();
         let logger = logger.clone();
+        let eth = self.cheap_clone();
 
         Box::new(
             retry("eth_getBlockByHash RPC call", &logger)
@@ -1022,6 +1239,12 @@ impl EthereumAdapterTrait for EthereumAdapter {
                         anyhow!("Ethereum node took too long to return block {}", block_hash)
                     })
                 })
+                .and_then(move |block| async move {
+                    match block {
+                        Some(block) => eth.verified_block(logger, block).await.map(Some),
+                        None => Ok(None),
+                    }
+                })
                 .boxed()
                 .compat(),
         )
@@ -1270,7 +1493,7 @@ impl EthereumAdapterTrait for EthereumAdapter {
                     let call = call.clone();
                     let logger = logger.clone();
                     Box::new(
-                        self.call(
+                        self.verified_call(
                             logger.clone(),
                             call.address,
                             Bytes(call_data.clone()),
@@ -1406,8 +1629,7 @@ pub(crate) async fn blocks_with_triggers(
             )
             .map_ok(|logs: Vec<Log>| {
                 logs.into_iter()
-                    .map(Arc::new)
-                    .map(EthereumTrigger::Log)
+                    .map(|log| EthereumTrigger::Log(Arc::new(log), None))
                     .collect()
             })
             .compat(),
@@ -1449,6 +1671,17 @@ pub(crate) async fn blocks_with_triggers(
         ));
     }
 
+    // Transactions can not be filtered by the Ethereum node, so we have to
+    // look at the transactions of every block in the range
+    let transaction_block_ptrs = if !filter.transaction.is_empty() {
+        adapter
+            .block_range_to_ptrs(logger.clone(), from, to)
+            .compat()
+            .await?
+    } else {
+        vec![]
+    };
+
     let logger1 = logger.cheap_clone();
     let logger2 = logger.cheap_clone();
     let eth_clone = eth.cheap_clone();
@@ -1480,13 +1713,18 @@ pub(crate) async fn blocks_with_triggers(
             map
         });
 
+    for ptr in transaction_block_ptrs {
+        block_hashes.insert(ptr.hash_as_h256());
+        triggers_by_block.entry(ptr.number).or_default();
+    }
+
     debug!(logger, "Found {} relevant block(s)", block_hashes.len());
 
     // Make sure `to` is included, even if empty.
     block_hashes.insert(to_hash);
     triggers_by_block.entry(to).or_insert(Vec::new());
 
-    let blocks = adapter
+    let blocks: Vec<BlockWithTriggers<crate::Chain>> = adapter
         .load_blocks(logger1, chain_store.clone(), block_hashes)
         .and_then(
             move |block| match triggers_by_block.remove(&(block.number() as BlockNumber)) {
@@ -1504,6 +1742,35 @@ pub(crate) async fn blocks_with_triggers(
         .compat()
         .await?;
 
+    let blocks = if filter.log.requires_transaction_receipts() {
+        let section = stopwatch_metrics.start_section("add_receipts_to_log_triggers");
+        let futures = blocks
+            .into_iter()
+            .map(|block| add_receipts_to_log_triggers(block, &eth, &filter.log, &logger));
+        let blocks = futures03::future::try_join_all(futures).await?;
+        section.end();
+        blocks
+    } else {
+        blocks
+    };
+
+    let blocks = if !filter.transaction.is_empty() {
+        let section = stopwatch_metrics.start_section("add_transaction_triggers");
+        let futures = blocks
+            .into_iter()
+            .map(|block| add_transaction_triggers(block, &eth, &filter.transaction, &logger));
+        let blocks = futures03::future::try_join_all(futures).await?;
+        section.end();
+
+        // Only keep the blocks that have triggers, and `to`
+        blocks
+            .into_iter()
+            .filter(|block| block.trigger_count() > 0 || block.ptr().number == to)
+            .collect()
+    } else {
+        blocks
+    };
+
     // Filter out call triggers that come from unsuccessful transactions
 
     let mut blocks = if unified_api_version
@@ -1594,11 +1861,25 @@ pub(crate) fn parse_log_triggers(
         .transaction_receipts
         .iter()
         .flat_map(move |receipt| {
+            // Only clone the receipt if one of its logs needs it, and then
+            // share it between all of them
+            let mut shared_receipt = None;
             receipt
                 .logs
                 .iter()
                 .filter(move |log| log_filter.matches(log))
-                .map(move |log| EthereumTrigger::Log(Arc::new(log.clone())))
+                .map(move |log| {
+                    let receipt = if log_filter.requires_transaction_receipt(log) {
+                        Some(
+                            shared_receipt
+                                .get_or_insert_with(|| Arc::new(receipt.clone()))
+                                .cheap_clone(),
+                        )
+                    } else {
+                        None
+                    };
+                    EthereumTrigger::Log(Arc::new(log.clone()), receipt)
+                })
         })
         .collect()
 }
@@ -1654,6 +1935,31 @@ pub(crate) fn parse_block_triggers(
     triggers
 }
 
+pub(crate) fn parse_transaction_triggers(
+    transaction_filter: &EthereumTransactionFilter,
+    block: &EthereumBlock,
+) -> anyhow::Result<Vec<EthereumTrigger>> {
+    block
+        .block
+        .transactions
+        .iter()
+        .filter(|transaction| transaction_filter.matches(transaction))
+        .map(|transaction| {
+            block
+                .transaction_receipts
+                .iter()
+                .find(|receipt| receipt.transaction_hash == transaction.hash)
+                .map(|receipt| EthereumTrigger::Transaction(Arc::new(receipt.clone())))
+                .ok_or_else(|| {
+                    anyhow!(
+                        "failed to find the receipt for transaction {:x}",
+                        transaction.hash
+                    )
+                })
+        })
+        .collect()
+}
+
 async fn fetch_receipt_from_ethereum_client(
     eth: &EthereumAdapter,
     transaction_hash: &H256,
@@ -1665,6 +1971,124 @@ async fn fetch_receipt_from_ethereum_client(
     }
 }
 
+/// Add a trigger for each transaction in `block` that matches
+/// `transaction_filter`. The receipts for these transactions come from the
+/// Ethereum client since the receipts in the chain store do not have logs
+async fn add_transaction_triggers(
+    block: BlockWithTriggers<crate::Chain>,
+    eth: &EthereumAdapter,
+    transaction_filter: &EthereumTransactionFilter,
+    logger: &Logger,
+) -> anyhow::Result<BlockWithTriggers<crate::Chain>> {
+    let block_hash = block.ptr().hash_as_h256();
+    let transaction_hashes: Vec<H256> = match &block.block {
+        BlockFinality::Final(ref block) => block
+            .transactions
+            .iter()
+            .filter(|transaction| transaction_filter.matches(transaction))
+            .map(|transaction| transaction.hash)
+            .collect(),
+        BlockFinality::NonFinal(_) => bail!(
+            "transaction triggers for non-final block {:x} must come from its receipts, \
+             not from the Ethereum client",
+            block_hash
+        ),
+    };
+
+    if transaction_hashes.is_empty() {
+        return Ok(block);
+    }
+
+    // One batch request per block; receipts for a different block mean
+    // that the block was reorged while we were looking at it, which makes
+    // this fail
+    let receipts = fetch_transaction_receipts_in_batch_with_retry(
+        eth.web3.cheap_clone(),
+        transaction_hashes,
+        block_hash,
+        logger.cheap_clone(),
+    )
+    .await?;
+
+    let BlockWithTriggers {
+        block,
+        mut trigger_data,
+    } = block;
+    trigger_data.extend(
+        receipts
+            .into_iter()
+            .map(|receipt| EthereumTrigger::Transaction(Arc::new(receipt))),
+    );
+    Ok(BlockWithTriggers::new(block, trigger_data))
+}
+
+/// Attach the transaction receipt to every log trigger in `block` whose
+/// event handler asked for it. As for transaction triggers, the receipts
+/// come from the Ethereum client
+async fn add_receipts_to_log_triggers(
+    block: BlockWithTriggers<crate::Chain>,
+    eth: &EthereumAdapter,
+    log_filter: &EthereumLogFilter,
+    logger: &Logger,
+) -> anyhow::Result<BlockWithTriggers<crate::Chain>> {
+    let block_hash = block.ptr().hash_as_h256();
+    let transaction_hashes: BTreeSet<H256> = block
+        .trigger_data
+        .iter()
+        .filter_map(|trigger| match trigger {
+            EthereumTrigger::Log(log, None) if log_filter.requires_transaction_receipt(log) => {
+                log.transaction_hash
+            }
+            _ => None,
+        })
+        .collect();
+
+    if transaction_hashes.is_empty() {
+        return Ok(block);
+    }
+
+    let receipts: HashMap<H256, Arc<TransactionReceipt>> =
+        fetch_transaction_receipts_in_batch_with_retry(
+            eth.web3.cheap_clone(),
+            transaction_hashes.into_iter().collect(),
+            block_hash,
+            logger.cheap_clone(),
+        )
+        .await?
+        .into_iter()
+        .map(|receipt| (receipt.transaction_hash, Arc::new(receipt)))
+        .collect();
+
+    let BlockWithTriggers {
+        block,
+        trigger_data,
+    } = block;
+    let trigger_data = attach_receipts_to_log_triggers(trigger_data, &receipts, log_filter);
+    Ok(BlockWithTriggers::new(block, trigger_data))
+}
+
+/// Attach the receipts from `receipts`, keyed by transaction hash, to the
+/// log triggers whose event handlers asked for them
+pub(crate) fn attach_receipts_to_log_triggers(
+    triggers: Vec<EthereumTrigger>,
+    receipts: &HashMap<H256, Arc<TransactionReceipt>>,
+    log_filter: &EthereumLogFilter,
+) -> Vec<EthereumTrigger> {
+    triggers
+        .into_iter()
+        .map(|trigger| match trigger {
+            EthereumTrigger::Log(log, None) if log_filter.requires_transaction_receipt(&log) => {
+                let receipt = log
+                    .transaction_hash
+                    .and_then(|hash| receipts.get(&hash))
+                    .cloned();
+                EthereumTrigger::Log(log, receipt)
+            }
+            trigger => trigger,
+        })
+        .collect()
+}
+
 async fn filter_call_triggers_from_unsuccessful_transactions(
     mut block: BlockWithTriggers<crate::Chain>,
     eth: &EthereumAdapter,
@@ -1801,7 +2225,9 @@ async fn filter_call_triggers_from_unsuccessful_transactions(
     Ok(block)
 }
 
-/// Deprecated. Wraps the [`fetch_transaction_receipts_in_batch`] in a retry loop.
+/// Wraps the [`fetch_transaction_receipts_in_batch`] in a retry loop. Loading
+/// full blocks this way is deprecated, but it is still used to fetch the
+/// receipts that transaction and event handlers need.
 async fn fetch_transaction_receipts_in_batch_with_retry(
     web3: Arc<Web3<Transport>>,
     hashes: Vec<H256>,
@@ -1822,7 +2248,7 @@ async fn fetch_transaction_receipts_in_batch_with_retry(
         .map_err(|_timeout| anyhow!(block_hash).into())
 }
 
-/// Deprecated. Attempts to fetch multiple transaction receipts in a batching contex.
+/// Attempts to fetch multiple transaction receipts in a batching context.
 async fn fetch_transaction_receipts_in_batch(
     web3: Arc<Web3<Transport>>,
     hashes: Vec<H256>,
@@ -1921,3 +2347,53 @@ fn resolve_transaction_receipt(
         }
     }
 }
+
+/// The parts of the logs for a block range that all providers must agree
+/// on, independent of the order in which they returned the logs
+fn logs_key(
+    logs: &[Log],
+) -> Vec<(
+    Option<H256>,
+    Option<web3::types::U256>,
+    Address,
+    Vec<H256>,
+    Vec<u8>,
+)> {
+    let mut key: Vec<_> = logs
+        .iter()
+        .map(|log| {
+            (
+                log.block_hash,
+                log.log_index,
+                log.address,
+                log.topics.clone(),
+                log.data.0.clone(),
+            )
+        })
+        .collect();
+    key.sort();
+    key
+}
+
+/// The parts of a block that all providers must agree on
+fn block_key(
+    block: &LightEthereumBlock,
+) -> (
+    Option<H256>,
+    Option<web3::types::U64>,
+    H256,
+    H256,
+    H256,
+    H256,
+    Vec<H256>,
+) {
+    (
+        block.hash,
+        block.number,
+        block.parent_hash,
+        block.state_root,
+        block.transactions_root,
+        block.receipts_root,
+        block.transactions.iter().map(|tx| tx.hash).collect(),
+    )
+}
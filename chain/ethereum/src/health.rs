@@ -0,0 +1,270 @@
+//! Keep track of how well the providers for a network are doing so that
+//! requests go to healthy providers, and failing providers are left alone
+//! for a while.
+//!
+//! Every JSON-RPC request a provider answers updates moving averages of its
+//! latency and its error rate. Errors that the Ethereum node reports for a
+//! request, like reverts, count as answers since they do not say anything
+//! about the health of the provider, while requests that are abandoned
+//! because they took too long count as failures. A background task
+//! regularly asks all providers of a network for their latest block to
+//! find out how far each of them is behind the most advanced one.
+//!
+//! After a number of consecutive failures, a provider is ejected and not
+//! used for a backoff period. The backoff doubles every time the provider
+//! gets ejected again soon after it was last ejected, so that a flapping
+//! provider is left alone for longer and longer.
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use graph::env::env_var;
+use graph::prelude::{warn, BlockNumber, Logger};
+use lazy_static::lazy_static;
+
+use crate::adapter::ProviderEthRpcMetrics;
+
+lazy_static! {
+    /// Eject a provider after this many consecutive failed requests
+    pub(crate) static ref EJECT_AFTER_FAILURES: u32 = env_var("ETHEREUM_PROVIDER_EJECT_AFTER_FAILURES", 5);
+
+    /// The time a provider is ejected for the first time, in seconds
+    static ref EJECT_BACKOFF: Duration =
+        Duration::from_secs(env_var("ETHEREUM_PROVIDER_EJECT_BACKOFF", 30));
+
+    /// How often all providers are asked for their latest block, in seconds
+    pub(crate) static ref HEALTH_CHECK_INTERVAL: Duration =
+        Duration::from_secs(env_var("ETHEREUM_PROVIDER_HEALTH_CHECK_INTERVAL", 15));
+}
+
+/// The longest time a provider is ejected for
+const MAX_EJECT_BACKOFF: Duration = Duration::from_secs(600);
+
+/// The weight of the latest request in the moving averages
+const ALPHA: f64 = 0.1;
+
+/// The number of blocks a provider can be behind the most advanced provider
+/// without that affecting its score
+const HEAD_LAG_TOLERANCE: BlockNumber = 2;
+
+/// The lowest possible score; every provider that is not ejected has some
+/// chance of getting picked
+const MIN_SCORE: f64 = 0.001;
+
+/// A point-in-time view of the health of a provider
+#[derive(Clone, Debug)]
+pub struct HealthSnapshot {
+    pub provider: String,
+    pub score: f64,
+    /// The moving average of the request latency in seconds
+    pub latency: f64,
+    /// The moving average of the fraction of requests that failed
+    pub error_rate: f64,
+    /// How many blocks the provider is behind the most advanced provider
+    pub head_lag: BlockNumber,
+    pub ejected: bool,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    latency: f64,
+    error_rate: f64,
+    head_lag: BlockNumber,
+    consecutive_failures: u32,
+    /// The number of times the provider was ejected in a row
+    ejections: u32,
+    ejected_at: Option<Instant>,
+    ejected_until: Option<Instant>,
+}
+
+impl State {
+    /// Record the outcome of a request. Return the backoff if the provider
+    /// got ejected because of it
+    fn record(&mut self, now: Instant, latency: Duration, ok: bool) -> Option<Duration> {
+        let failed = if ok { 0.0 } else { 1.0 };
+        self.latency = ALPHA * latency.as_secs_f64() + (1.0 - ALPHA) * self.latency;
+        self.error_rate = ALPHA * failed + (1.0 - ALPHA) * self.error_rate;
+
+        if ok {
+            self.consecutive_failures = 0;
+            return None;
+        }
+
+        self.consecutive_failures += 1;
+        if self.consecutive_failures < *EJECT_AFTER_FAILURES || self.is_ejected(now) {
+            return None;
+        }
+
+        // Start over with the shortest backoff if the last ejection was
+        // long enough ago
+        let recently_ejected = self
+            .ejected_at
+            .map(|at| now.duration_since(at) < MAX_EJECT_BACKOFF * 2)
+            .unwrap_or(false);
+        self.ejections = if recently_ejected {
+            self.ejections + 1
+        } else {
+            1
+        };
+        let backoff =
+            (*EJECT_BACKOFF * 2u32.saturating_pow(self.ejections - 1)).min(MAX_EJECT_BACKOFF);
+        self.consecutive_failures = 0;
+        self.ejected_at = Some(now);
+        self.ejected_until = Some(now + backoff);
+        Some(backoff)
+    }
+
+    fn is_ejected(&self, now: Instant) -> bool {
+        self.ejected_until.map(|until| now < until).unwrap_or(false)
+    }
+
+    /// A score between 0 and 1 where higher is better; fast providers that
+    /// do not fail and keep up with the chain head score close to 1
+    fn score(&self, now: Instant) -> f64 {
+        if self.is_ejected(now) {
+            return 0.0;
+        }
+        let lag = (self.head_lag - HEAD_LAG_TOLERANCE).max(0) as f64;
+        let score = (1.0 - self.error_rate)
+            / (1.0 + self.latency)
+            / (1.0 + lag / HEAD_LAG_TOLERANCE as f64);
+        score.max(MIN_SCORE)
+    }
+}
+
+pub struct ProviderHealth {
+    logger: Logger,
+    provider: String,
+    state: Mutex<State>,
+    metrics: Arc<ProviderEthRpcMetrics>,
+}
+
+impl std::fmt::Debug for ProviderHealth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ProviderHealth")
+            .field("provider", &self.provider)
+            .field("state", &self.state)
+            .finish()
+    }
+}
+
+impl ProviderHealth {
+    pub fn new(logger: Logger, provider: String, metrics: Arc<ProviderEthRpcMetrics>) -> Self {
+        ProviderHealth {
+            logger,
+            provider,
+            state: Mutex::new(State::default()),
+            metrics,
+        }
+    }
+
+    /// Record the outcome of a request to the provider that took `latency`
+    pub fn record(&self, latency: Duration, ok: bool) {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        if let Some(backoff) = state.record(now, latency, ok) {
+            warn!(self.logger, "Ejecting provider after repeated failures";
+                  "provider" => &self.provider,
+                  "ejections" => state.ejections,
+                  "backoff_secs" => backoff.as_secs());
+        }
+        self.update_metrics(&state, now);
+    }
+
+    /// Record how many blocks the provider is behind the most advanced
+    /// provider of its network
+    pub fn set_head_lag(&self, head_lag: BlockNumber) {
+        let mut state = self.state.lock().unwrap();
+        state.head_lag = head_lag;
+        self.update_metrics(&state, Instant::now());
+    }
+
+    pub fn is_ejected(&self) -> bool {
+        self.state.lock().unwrap().is_ejected(Instant::now())
+    }
+
+    pub fn score(&self) -> f64 {
+        self.state.lock().unwrap().score(Instant::now())
+    }
+
+    pub fn snapshot(&self) -> HealthSnapshot {
+        let now = Instant::now();
+        let state = self.state.lock().unwrap();
+        HealthSnapshot {
+            provider: self.provider.clone(),
+            score: state.score(now),
+            latency: state.latency,
+            error_rate: state.error_rate,
+            head_lag: state.head_lag,
+            ejected: state.is_ejected(now),
+        }
+    }
+
+    fn update_metrics(&self, state: &State, now: Instant) {
+        self.metrics.set_health(
+            &self.provider,
+            state.score(now),
+            state.head_lag,
+            state.is_ejected(now),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ejects_with_backoff() {
+        let mut state = State::default();
+        let start = Instant::now();
+        let fail = |state: &mut State, now| {
+            (0..*EJECT_AFTER_FAILURES)
+                .filter_map(|_| state.record(now, Duration::from_millis(10), false))
+                .last()
+        };
+
+        assert_eq!(Some(*EJECT_BACKOFF), fail(&mut state, start));
+        assert!(state.is_ejected(start));
+        assert_eq!(0.0, state.score(start));
+
+        // Failing again right after the ejection ended doubles the backoff
+        let later = start + *EJECT_BACKOFF;
+        assert!(!state.is_ejected(later));
+        assert_eq!(Some(*EJECT_BACKOFF * 2), fail(&mut state, later));
+
+        // Successes reset the count of consecutive failures
+        let later = later + MAX_EJECT_BACKOFF;
+        for _ in 1..*EJECT_AFTER_FAILURES {
+            assert_eq!(None, state.record(later, Duration::from_millis(10), false));
+        }
+        assert_eq!(None, state.record(later, Duration::from_millis(10), true));
+        assert_eq!(None, state.record(later, Duration::from_millis(10), false));
+
+        // Long after the last ejection, the backoff starts over
+        let later = later + MAX_EJECT_BACKOFF * 3;
+        assert_eq!(Some(*EJECT_BACKOFF), fail(&mut state, later));
+    }
+
+    #[test]
+    fn score_prefers_healthy_providers() {
+        let now = Instant::now();
+        let mut fast = State::default();
+        let mut slow = State::default();
+        let mut lagging = State::default();
+        for _ in 0..20 {
+            fast.record(now, Duration::from_millis(50), true);
+            slow.record(now, Duration::from_secs(2), true);
+            lagging.record(now, Duration::from_millis(50), true);
+        }
+        lagging.head_lag = 20;
+
+        assert!(fast.score(now) > slow.score(now));
+        assert!(fast.score(now) > lagging.score(now));
+
+        let mut failing = State::default();
+        failing.record(now, Duration::from_millis(50), false);
+        assert!(fast.score(now) > failing.score(now));
+        assert!(failing.score(now) > 0.0);
+    }
+}
@@ -1,20 +1,25 @@
 mod adapter;
+mod archive;
 mod capabilities;
 pub mod codec;
 mod data_source;
 mod ethereum_adapter;
+mod health;
 pub mod network_indexer;
 pub mod runtime;
 mod transport;
+mod verification;
 
 pub use self::capabilities::NodeCapabilities;
 pub use self::ethereum_adapter::EthereumAdapter;
+pub use self::health::HealthSnapshot;
 pub use self::runtime::RuntimeAdapter;
 pub use self::transport::Transport;
+pub use self::verification::{OnDisagreement, Verification};
 
 // ETHDEP: These concrete types should probably not be exposed.
 pub use data_source::{DataSource, DataSourceTemplate, Mapping, MappingABI, TemplateSource};
-pub use trigger::MappingTrigger;
+pub use trigger::{EthereumBlockTriggerType, EthereumTrigger, MappingTrigger};
 
 pub mod chain;
 
@@ -1,6 +1,8 @@
 use anyhow::{anyhow, Context};
 use graph::cheap_clone::CheapClone;
-use graph::prelude::rand::{self, seq::IteratorRandom};
+use graph::prelude::futures03::future::join_all;
+use graph::prelude::rand::{self, seq::SliceRandom};
+use graph::prelude::{debug, o, tokio, Logger};
 use std::collections::HashMap;
 use std::sync::Arc;
 
@@ -9,6 +11,8 @@ use graph::prelude::Error;
 
 use crate::adapter::EthereumAdapter as _;
 use crate::capabilities::NodeCapabilities;
+use crate::health::{HealthSnapshot, HEALTH_CHECK_INTERVAL};
+use crate::verification::Verification;
 use crate::EthereumAdapter;
 
 #[derive(Clone)]
@@ -23,21 +27,43 @@ pub struct EthereumNetworkAdapters {
 }
 
 impl EthereumNetworkAdapters {
+    /// Pick an adapter with at least `required_capabilities`. Adapters that
+    /// are ejected because they failed repeatedly are only used if there is
+    /// no other choice. Among the cheapest of the remaining adapters, one is
+    /// picked at random, with healthier adapters being more likely to get
+    /// picked
     pub fn cheapest_with(
         &self,
         required_capabilities: &NodeCapabilities,
     ) -> Result<Arc<EthereumAdapter>, Error> {
-        let cheapest_sufficient_capability = self
+        let sufficient: Vec<_> = self
             .adapters
             .iter()
-            .find(|adapter| &adapter.capabilities >= required_capabilities)
-            .map(|adapter| &adapter.capabilities);
-
-        // Select randomly from the cheapest adapters that have sufficent capabilities.
-        self.adapters
+            .filter(|adapter| &adapter.capabilities >= required_capabilities)
+            .collect();
+        let available: Vec<_> = sufficient
             .iter()
-            .filter(|adapter| Some(&adapter.capabilities) == cheapest_sufficient_capability)
-            .choose(&mut rand::thread_rng())
+            .filter(|adapter| !adapter.adapter.health().is_ejected())
+            .cloned()
+            .collect();
+        let candidates = if available.is_empty() {
+            sufficient
+        } else {
+            available
+        };
+
+        // Select from the cheapest adapters that have sufficent capabilities.
+        let cheapest_sufficient_capability = candidates.first().map(|adapter| adapter.capabilities);
+        let cheapest: Vec<_> = candidates
+            .into_iter()
+            .filter(|adapter| Some(adapter.capabilities) == cheapest_sufficient_capability)
+            .collect();
+        cheapest
+            .choose_weighted(&mut rand::thread_rng(), |adapter| {
+                adapter.adapter.health().score()
+            })
+            .ok()
+            .or_else(|| cheapest.choose(&mut rand::thread_rng()))
             .map(|adapter| adapter.adapter.cheap_clone())
             .with_context(|| {
                 anyhow!(
@@ -52,10 +78,34 @@ impl EthereumNetworkAdapters {
         // struct is instantiated so they do not need to be sorted here
         self.adapters
             .iter()
-            .next()
+            .find(|adapter| !adapter.adapter.health().is_ejected())
+            .or_else(|| self.adapters.first())
             .map(|ethereum_network_adapter| ethereum_network_adapter.adapter.clone())
     }
 
+    /// Ask all providers for their latest block and record how far each of
+    /// them is behind the most advanced one
+    pub async fn check_heads(&self, logger: &Logger) {
+        let heads = join_all(
+            self.adapters
+                .iter()
+                .map(|adapter| adapter.adapter.head_block_number()),
+        )
+        .await;
+        let best = match heads.iter().filter_map(|head| head.as_ref().ok()).max() {
+            Some(best) => *best,
+            None => return,
+        };
+        for (adapter, head) in self.adapters.iter().zip(heads) {
+            match head {
+                Ok(head) => adapter.adapter.health().set_head_lag(best - head),
+                Err(e) => debug!(logger, "Failed to get the latest block of provider";
+                                 "provider" => adapter.adapter.provider(),
+                                 "error" => e.to_string()),
+            }
+        }
+    }
+
     pub fn remove(&mut self, provider: &str) {
         self.adapters
             .retain(|adapter| adapter.adapter.provider() != provider);
@@ -96,6 +146,32 @@ impl EthereumNetworks {
         }
     }
 
+    /// Turn on verification for all providers of the network `name`. The
+    /// responses of a provider are checked with the other providers of the
+    /// network that have at least the same capabilities
+    pub fn verify(&mut self, name: &str, verification: Verification) {
+        let adapters = match self.networks.get_mut(name) {
+            Some(adapters) => adapters,
+            None => return,
+        };
+        let originals = adapters.adapters.clone();
+        for network_adapter in adapters.adapters.iter_mut() {
+            let peers = originals
+                .iter()
+                .filter(|peer| {
+                    peer.adapter.provider() != network_adapter.adapter.provider()
+                        && peer.capabilities >= network_adapter.capabilities
+                })
+                .map(|peer| peer.adapter.as_ref().cheap_clone())
+                .collect();
+            network_adapter.adapter = Arc::new(
+                network_adapter
+                    .adapter
+                    .with_verification(verification, peers),
+            );
+        }
+    }
+
     pub fn extend(&mut self, other_networks: EthereumNetworks) {
         self.networks.extend(other_networks.networks);
     }
@@ -126,6 +202,35 @@ impl EthereumNetworks {
         }
     }
 
+    /// Regularly check how far behind the chain head the providers of each
+    /// network are
+    pub fn spawn_health_checks(&self, logger: &Logger) {
+        for (name, adapters) in &self.networks {
+            let logger = logger.new(o!("network" => name.clone()));
+            let adapters = adapters.clone();
+            graph::spawn(async move {
+                let mut interval = tokio::time::interval(*HEALTH_CHECK_INTERVAL);
+                loop {
+                    interval.tick().await;
+                    adapters.check_heads(&logger).await;
+                }
+            });
+        }
+    }
+
+    /// The health of all providers, by network
+    pub fn health(&self) -> Vec<(String, HealthSnapshot)> {
+        let mut health: Vec<_> = self
+            .flatten()
+            .into_iter()
+            .map(|(name, _, adapter)| (name, adapter.health().snapshot()))
+            .collect();
+        health.sort_by(|(a, a_health), (b, b_health)| {
+            (a, &a_health.provider).cmp(&(b, &b_health.provider))
+        });
+        health
+    }
+
     pub fn adapter_with_capabilities(
         &self,
         network_name: String,
@@ -31,6 +31,7 @@ fn create_subgraph(
         data_sources: vec![],
         graft: None,
         templates: vec![],
+        indexer_hints: Default::default(),
         chain: PhantomData,
     };
 
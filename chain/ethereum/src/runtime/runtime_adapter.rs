@@ -15,7 +15,7 @@ use graph::{
         ethabi::{self, Address, Token},
         EthereumCallCache, Future01CompatExt,
     },
-    runtime::{asc_get, asc_new, AscPtr, HostExportError},
+    runtime::{asc_get, asc_new, gas, AscPtr, HostExportError},
     semver::Version,
     slog::{info, trace, Logger},
 };
@@ -60,6 +60,8 @@ fn ethereum_call(
     wasm_ptr: u32,
     abis: &[Arc<MappingABI>],
 ) -> Result<AscEnumArray<EthereumValueKind>, HostExportError> {
+    ctx.gas.consume_host_fn(gas::ETHEREUM_CALL_GAS)?;
+
     // For apiVersion >= 0.0.4 the call passed from the mapping includes the
     // function signature; subgraphs using an apiVersion < 0.0.4 don't pass
     // the signature along with the call.
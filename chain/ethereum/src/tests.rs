@@ -1,16 +1,25 @@
 use std::sync::Arc;
 
 use graph::{
-    blockchain::{block_stream::BlockWithTriggers, BlockPtr},
+    blockchain::{self, block_stream::BlockWithTriggers, BlockPtr},
+    data::subgraph::Source,
     prelude::{
-        web3::types::{Address, Bytes, Log, H160, H256, U64},
-        EthereumCall,
+        ethabi::Contract,
+        web3::types::{
+            Address, Bytes, Log, Transaction, TransactionReceipt, H160, H2048, H256, U256, U64,
+        },
+        EthereumBlock, EthereumBlockWithCalls, EthereumCall, LightEthereumBlock, Link, Logger,
     },
+    semver::Version,
+    slog,
 };
 
 use crate::{
+    adapter::EthereumTransactionFilter,
     chain::BlockFinality,
-    trigger::{EthereumBlockTriggerType, EthereumTrigger},
+    data_source::{DataSource, Mapping, MappingABI, MappingTransactionHandler},
+    ethereum_adapter::parse_transaction_triggers,
+    trigger::{EthereumBlockTriggerType, EthereumTrigger, MappingTrigger},
 };
 
 #[test]
@@ -60,15 +69,15 @@ fn test_trigger_ordering() {
 
     // Event with transaction_index 1 and log_index 0;
     // should be the first element after sorting
-    let log1 = EthereumTrigger::Log(create_log(1, 0));
+    let log1 = EthereumTrigger::Log(create_log(1, 0), None);
 
     // Event with transaction_index 1 and log_index 1;
     // should be the second element after sorting
-    let log2 = EthereumTrigger::Log(create_log(1, 1));
+    let log2 = EthereumTrigger::Log(create_log(1, 1), None);
 
     // Event with transaction_index 2 and log_index 5;
     // should come after call1 and before call2 after sorting
-    let log3 = EthereumTrigger::Log(create_log(2, 5));
+    let log3 = EthereumTrigger::Log(create_log(2, 5), None);
 
     let triggers = vec![
         // Call triggers; these should be in the order 1, 2, 4, 3 after sorting
@@ -95,3 +104,169 @@ fn test_trigger_ordering() {
         vec![log1, log2, call1, log3, call2, call4, call3, block2, block1]
     );
 }
+
+#[test]
+fn test_transaction_trigger_ordering() {
+    fn create_receipt(tx_index: u64) -> EthereumTrigger {
+        EthereumTrigger::Transaction(Arc::new(TransactionReceipt {
+            transaction_hash: H256::from_low_u64_be(tx_index),
+            transaction_index: tx_index.into(),
+            block_hash: Some(H256::zero()),
+            block_number: Some(U64::zero()),
+            cumulative_gas_used: U256::zero(),
+            gas_used: None,
+            contract_address: None,
+            logs: vec![],
+            status: Some(1u64.into()),
+            root: None,
+            logs_bloom: H2048::zero(),
+        }))
+    }
+
+    let block = EthereumTrigger::Block(
+        BlockPtr::from((H256::random(), 0u64)),
+        EthereumBlockTriggerType::Every,
+    );
+
+    let mut call = EthereumCall::default();
+    call.transaction_index = 1;
+    let call = EthereumTrigger::Call(Arc::new(call));
+
+    let log = EthereumTrigger::Log(
+        Arc::new(Log {
+            transaction_index: Some(1u64.into()),
+            log_index: Some(0u64.into()),
+            ..Log::default()
+        }),
+        None,
+    );
+
+    let tx0 = create_receipt(0);
+    let tx1 = create_receipt(1);
+    let tx2 = create_receipt(2);
+
+    // Transactions come after the events and calls that happened in them,
+    // and before the ones of later transactions
+    let block_with_triggers = BlockWithTriggers::<crate::Chain>::new(
+        BlockFinality::Final(Default::default()),
+        vec![
+            block.clone(),
+            tx2.clone(),
+            tx1.clone(),
+            call.clone(),
+            log.clone(),
+            tx0.clone(),
+        ],
+    );
+
+    assert_eq!(
+        block_with_triggers.trigger_data,
+        vec![tx0, log, call, tx1, tx2, block]
+    );
+}
+
+#[test]
+fn test_transaction_handler_receives_receipt() {
+    let contract = Address::from_low_u64_be(1);
+    let block_hash = H256::from_low_u64_be(1000);
+    let make_transaction = |index: u64, to: Address| Transaction {
+        hash: H256::from_low_u64_be(index),
+        block_hash: Some(block_hash),
+        block_number: Some(10u64.into()),
+        transaction_index: Some(index.into()),
+        from: Some(Address::from_low_u64_be(2)),
+        to: Some(to),
+        ..Transaction::default()
+    };
+    let make_receipt = |index: u64| TransactionReceipt {
+        transaction_hash: H256::from_low_u64_be(index),
+        transaction_index: index.into(),
+        block_hash: Some(block_hash),
+        block_number: Some(10u64.into()),
+        cumulative_gas_used: U256::from(21000 * (index + 1)),
+        gas_used: Some(U256::from(21000)),
+        contract_address: None,
+        logs: vec![],
+        status: Some(1u64.into()),
+        root: None,
+        logs_bloom: H2048::zero(),
+    };
+
+    // Only the first transaction is sent to the contract
+    let light_block = Arc::new(LightEthereumBlock {
+        hash: Some(block_hash),
+        number: Some(10u64.into()),
+        transactions: vec![
+            make_transaction(0, contract),
+            make_transaction(1, Address::from_low_u64_be(3)),
+        ],
+        ..Default::default()
+    });
+    let block = EthereumBlock {
+        block: light_block,
+        transaction_receipts: vec![make_receipt(0), make_receipt(1)],
+    };
+
+    let data_source = DataSource {
+        kind: "ethereum/contract".to_string(),
+        network: Some("mainnet".to_string()),
+        name: "Contract".to_string(),
+        source: Source {
+            address: Some(contract),
+            abi: "Contract".to_string(),
+            start_block: 0,
+        },
+        mapping: Mapping {
+            kind: "ethereum/events".to_string(),
+            api_version: Version::new(0, 0, 6),
+            language: "wasm/assemblyscript".to_string(),
+            entities: vec![],
+            abis: vec![],
+            block_handlers: vec![],
+            call_handlers: vec![],
+            event_handlers: vec![],
+            transaction_handlers: vec![MappingTransactionHandler {
+                handler: "handleTransaction".to_string(),
+                from: None,
+                to: None,
+                function: None,
+            }],
+            runtime: Arc::new(vec![]),
+            link: Link {
+                link: "link".to_string(),
+            },
+        },
+        context: Arc::new(None),
+        creation_block: None,
+        contract_abi: Arc::new(MappingABI {
+            name: "Contract".to_string(),
+            contract: Contract::load("[]".as_bytes()).unwrap(),
+        }),
+    };
+
+    let filter = EthereumTransactionFilter::from_data_sources(vec![&data_source]);
+    let triggers = parse_transaction_triggers(&filter, &block).unwrap();
+    assert_eq!(1, triggers.len());
+
+    let block = Arc::new(BlockFinality::NonFinal(EthereumBlockWithCalls {
+        ethereum_block: block,
+        calls: Some(vec![]),
+    }));
+    let logger = Logger::root(slog::Discard, slog::o!());
+    let trigger =
+        blockchain::DataSource::match_and_decode(&data_source, &triggers[0], block, &logger)
+            .unwrap()
+            .expect("the transaction matches the handler");
+    assert_eq!("handleTransaction", trigger.handler_name());
+    match trigger.trigger() {
+        MappingTrigger::Transaction {
+            transaction,
+            receipt,
+            ..
+        } => {
+            assert_eq!(H256::from_low_u64_be(0), transaction.hash);
+            assert_eq!(&make_receipt(0), receipt.as_ref());
+        }
+        _ => panic!("expected a transaction trigger"),
+    }
+}
@@ -6,21 +6,37 @@ use web3::RequestId;
 
 use graph::prelude::*;
 use std::future::Future;
+use std::time::Instant;
+
+use crate::archive::ArchiveNode;
+use crate::health::ProviderHealth;
 
 /// Abstraction over the different web3 transports.
 #[derive(Clone, Debug)]
-pub enum Transport {
+pub struct Transport {
+    kind: Kind,
+    /// Records the outcome of every request if set
+    health: Option<Arc<ProviderHealth>>,
+}
+
+#[derive(Clone, Debug)]
+enum Kind {
     RPC(http::Http),
     IPC(ipc::Ipc),
     WS(ws::WebSocket),
+    Archive(Arc<ArchiveNode>),
 }
 
 impl Transport {
+    fn new(kind: Kind) -> Self {
+        Transport { kind, health: None }
+    }
+
     /// Creates an IPC transport.
     pub async fn new_ipc(ipc: &str) -> Self {
         ipc::Ipc::new(ipc)
             .await
-            .map(|transport| Transport::IPC(transport))
+            .map(|transport| Transport::new(Kind::IPC(transport)))
             .expect("Failed to connect to Ethereum IPC")
     }
 
@@ -28,7 +44,7 @@ impl Transport {
     pub async fn new_ws(ws: &str) -> Self {
         ws::WebSocket::new(ws)
             .await
-            .map(|transport| Transport::WS(transport))
+            .map(|transport| Transport::new(Kind::WS(transport)))
             .expect("Failed to connect to Ethereum WS")
     }
 
@@ -38,28 +54,101 @@ impl Transport {
     /// blocks (one such example is Infura's HTTP endpoint).
     pub fn new_rpc(rpc: &str, headers: ::http::HeaderMap) -> Self {
         http::Http::with_headers(rpc, headers)
-            .map(|transport| Transport::RPC(transport))
+            .map(|transport| Transport::new(Kind::RPC(transport)))
             .expect("Failed to connect to Ethereum RPC")
     }
+
+    /// Creates a transport that answers requests from the block archive in
+    /// the directory `dir` rather than from an Ethereum node.
+    pub fn new_archive(dir: &str, chain_id: u64) -> Result<Self, Error> {
+        ArchiveNode::new(dir, chain_id).map(|node| Transport::new(Kind::Archive(Arc::new(node))))
+    }
+
+    /// Record the latency and outcome of all requests in `health`
+    pub(crate) fn with_health(self, health: Arc<ProviderHealth>) -> Self {
+        Transport {
+            health: Some(health),
+            ..self
+        }
+    }
+}
+
+/// Whether `result` shows that the provider is working. Errors that the
+/// Ethereum node returns for a request, like reverts, do not count against
+/// the provider
+fn is_ok<T>(result: &Result<T, web3::error::Error>) -> bool {
+    match result {
+        Ok(_) | Err(web3::error::Error::Rpc(_)) => true,
+        Err(_) => false,
+    }
+}
+
+/// Records the outcome of a request in `health` when it is dropped. A
+/// request that is dropped before it finished, usually because it timed
+/// out, counts as a failure
+struct HealthGuard {
+    health: Arc<ProviderHealth>,
+    start: Instant,
+    ok: bool,
+}
+
+impl HealthGuard {
+    fn new(health: Arc<ProviderHealth>) -> Self {
+        HealthGuard {
+            health,
+            start: Instant::now(),
+            ok: false,
+        }
+    }
+}
+
+impl Drop for HealthGuard {
+    fn drop(&mut self) {
+        self.health.record(self.start.elapsed(), self.ok);
+    }
+}
+
+/// Record the latency and outcome of the request `out` in `health`
+fn track<T: Send + 'static>(
+    health: Option<&Arc<ProviderHealth>>,
+    out: Box<dyn Future<Output = Result<T, web3::error::Error>> + Send + Unpin>,
+) -> Box<dyn Future<Output = Result<T, web3::error::Error>> + Send + Unpin> {
+    match health {
+        None => out,
+        Some(health) => {
+            let mut guard = HealthGuard::new(health.cheap_clone());
+            Box::new(
+                async move {
+                    let result = out.await;
+                    guard.ok = is_ok(&result);
+                    result
+                }
+                .boxed(),
+            )
+        }
+    }
 }
 
 impl web3::Transport for Transport {
     type Out = Box<dyn Future<Output = Result<Value, web3::error::Error>> + Send + Unpin>;
 
     fn prepare(&self, method: &str, params: Vec<Value>) -> (RequestId, Call) {
-        match self {
-            Transport::RPC(http) => http.prepare(method, params),
-            Transport::IPC(ipc) => ipc.prepare(method, params),
-            Transport::WS(ws) => ws.prepare(method, params),
+        match &self.kind {
+            Kind::RPC(http) => http.prepare(method, params),
+            Kind::IPC(ipc) => ipc.prepare(method, params),
+            Kind::WS(ws) => ws.prepare(method, params),
+            Kind::Archive(node) => node.prepare(method, params),
         }
     }
 
     fn send(&self, id: RequestId, request: Call) -> Self::Out {
-        match self {
-            Transport::RPC(http) => Box::new(http.send(id, request)),
-            Transport::IPC(ipc) => Box::new(ipc.send(id, request)),
-            Transport::WS(ws) => Box::new(ws.send(id, request)),
-        }
+        let out: Self::Out = match &self.kind {
+            Kind::RPC(http) => Box::new(http.send(id, request)),
+            Kind::IPC(ipc) => Box::new(ipc.send(id, request)),
+            Kind::WS(ws) => Box::new(ws.send(id, request)),
+            Kind::Archive(node) => Box::new(node.cheap_clone().send(request).boxed()),
+        };
+        track(self.health.as_ref(), out)
     }
 }
 
@@ -74,10 +163,71 @@ impl web3::BatchTransport for Transport {
     where
         T: IntoIterator<Item = (RequestId, Call)>,
     {
-        match self {
-            Transport::RPC(http) => Box::new(http.send_batch(requests)),
-            Transport::IPC(ipc) => Box::new(ipc.send_batch(requests)),
-            Transport::WS(ws) => Box::new(ws.send_batch(requests)),
+        let out: Self::Batch = match &self.kind {
+            Kind::RPC(http) => Box::new(http.send_batch(requests)),
+            Kind::IPC(ipc) => Box::new(ipc.send_batch(requests)),
+            Kind::WS(ws) => Box::new(ws.send_batch(requests)),
+            Kind::Archive(node) => {
+                let responses = requests
+                    .into_iter()
+                    .map(|(_, request)| node.cheap_clone().send(request));
+                Box::new(
+                    futures03::future::join_all(responses)
+                        .map(Ok::<_, web3::error::Error>)
+                        .boxed(),
+                )
+            }
+        };
+        track(self.health.as_ref(), out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+    use std::time::Duration;
+
+    use graph::prelude::{o, slog, Registry};
+    use graph_core::MetricsRegistry;
+    use web3::Transport as _;
+
+    use super::*;
+    use crate::adapter::ProviderEthRpcMetrics;
+    use crate::health::EJECT_AFTER_FAILURES;
+
+    #[tokio::test]
+    async fn stalled_requests_eject_provider() {
+        // A provider that accepts connections but never answers
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            let mut connections = Vec::new();
+            for connection in listener.incoming() {
+                connections.push(connection);
+            }
+        });
+
+        let logger = Logger::root(slog::Discard, o!());
+        let registry = Arc::new(MetricsRegistry::new(
+            logger.clone(),
+            Arc::new(Registry::new()),
+        ));
+        let metrics = Arc::new(ProviderEthRpcMetrics::new(registry));
+        let health = Arc::new(ProviderHealth::new(logger, "stalled".to_string(), metrics));
+        let transport =
+            Transport::new_rpc(&url, ::http::HeaderMap::new()).with_health(health.cheap_clone());
+
+        let timeout = Duration::from_millis(50);
+        for _ in 0..*EJECT_AFTER_FAILURES {
+            assert!(!health.is_ejected());
+            let (id, call) = transport.prepare("eth_blockNumber", vec![]);
+            let request = transport.send(id, call);
+            assert!(tokio::time::timeout(timeout, request).await.is_err());
+
+            let snapshot = health.snapshot();
+            assert!(snapshot.error_rate > 0.0);
+            assert!(snapshot.latency > 0.0);
         }
+        assert!(health.is_ejected());
     }
 }
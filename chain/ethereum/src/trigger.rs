@@ -1,6 +1,7 @@
 use graph::blockchain;
 use graph::blockchain::TriggerData;
 use graph::prelude::ethabi::ethereum_types::H160;
+use graph::prelude::ethabi::ethereum_types::H2048;
 use graph::prelude::ethabi::ethereum_types::H256;
 use graph::prelude::ethabi::ethereum_types::U128;
 use graph::prelude::ethabi::ethereum_types::U256;
@@ -11,6 +12,7 @@ use graph::prelude::ethabi::LogParam;
 use graph::prelude::web3::types::Block;
 use graph::prelude::web3::types::Log;
 use graph::prelude::web3::types::Transaction;
+use graph::prelude::web3::types::TransactionReceipt;
 use graph::prelude::BlockNumber;
 use graph::prelude::BlockPtr;
 use graph::prelude::{CheapClone, EthereumCall};
@@ -28,6 +30,8 @@ use crate::runtime::abi::AscEthereumBlock_0_0_6;
 use crate::runtime::abi::AscEthereumCall;
 use crate::runtime::abi::AscEthereumCall_0_0_3;
 use crate::runtime::abi::AscEthereumEvent;
+use crate::runtime::abi::AscEthereumEvent_0_0_7;
+use crate::runtime::abi::AscEthereumTransactionWithReceipt;
 use crate::runtime::abi::AscEthereumTransaction_0_0_1;
 use crate::runtime::abi::AscEthereumTransaction_0_0_2;
 use crate::runtime::abi::AscEthereumTransaction_0_0_6;
@@ -41,6 +45,8 @@ pub enum MappingTrigger {
         transaction: Arc<Transaction>,
         log: Arc<Log>,
         params: Vec<LogParam>,
+        /// Only set if the event handler asked for the receipt
+        receipt: Option<Arc<TransactionReceipt>>,
     },
     Call {
         block: Arc<LightEthereumBlock>,
@@ -52,6 +58,11 @@ pub enum MappingTrigger {
     Block {
         block: Arc<LightEthereumBlock>,
     },
+    Transaction {
+        block: Arc<LightEthereumBlock>,
+        transaction: Arc<Transaction>,
+        receipt: Arc<TransactionReceipt>,
+    },
 }
 
 // Logging the block is too verbose, so this strips the block from the trigger for Debug.
@@ -63,6 +74,7 @@ impl std::fmt::Debug for MappingTrigger {
                 _transaction: Arc<Transaction>,
                 _log: Arc<Log>,
                 _params: Vec<LogParam>,
+                _receipt: Option<Arc<TransactionReceipt>>,
             },
             Call {
                 _transaction: Arc<Transaction>,
@@ -71,6 +83,10 @@ impl std::fmt::Debug for MappingTrigger {
                 _outputs: Vec<LogParam>,
             },
             Block,
+            Transaction {
+                _transaction: Arc<Transaction>,
+                _receipt: Arc<TransactionReceipt>,
+            },
         }
 
         let trigger_without_block = match self {
@@ -79,10 +95,12 @@ impl std::fmt::Debug for MappingTrigger {
                 transaction,
                 log,
                 params,
+                receipt,
             } => MappingTriggerWithoutBlock::Log {
                 _transaction: transaction.cheap_clone(),
                 _log: log.cheap_clone(),
                 _params: params.clone(),
+                _receipt: receipt.clone(),
             },
             MappingTrigger::Call {
                 block: _,
@@ -97,6 +115,14 @@ impl std::fmt::Debug for MappingTrigger {
                 _outputs: outputs.clone(),
             },
             MappingTrigger::Block { block: _ } => MappingTriggerWithoutBlock::Block,
+            MappingTrigger::Transaction {
+                block: _,
+                transaction,
+                receipt,
+            } => MappingTriggerWithoutBlock::Transaction {
+                _transaction: transaction.cheap_clone(),
+                _receipt: receipt.cheap_clone(),
+            },
         };
 
         write!(f, "{:?}", trigger_without_block)
@@ -111,6 +137,7 @@ impl blockchain::MappingTrigger for MappingTrigger {
                 transaction,
                 log,
                 params,
+                receipt,
             } => {
                 let ethereum_event_data = EthereumEventData {
                     block: EthereumBlockData::from(block.as_ref()),
@@ -120,9 +147,20 @@ impl blockchain::MappingTrigger for MappingTrigger {
                     transaction_log_index: log.log_index.unwrap_or(U256::zero()),
                     log_type: log.log_type.clone(),
                     params,
+                    receipt: receipt.as_deref().map(EthereumTransactionReceiptData::from),
                 };
                 let api_version = heap.api_version();
-                if api_version >= Version::new(0, 0, 6) {
+                if api_version >= Version::new(0, 0, 7) {
+                    asc_new::<
+                        AscEthereumEvent_0_0_7<
+                            AscEthereumTransaction_0_0_6,
+                            AscEthereumBlock_0_0_6,
+                        >,
+                        _,
+                        _,
+                    >(heap, &ethereum_event_data)?
+                    .erase()
+                } else if api_version >= Version::new(0, 0, 6) {
                     asc_new::<
                         AscEthereumEvent<AscEthereumTransaction_0_0_6, AscEthereumBlock_0_0_6>,
                         _,
@@ -184,6 +222,20 @@ impl blockchain::MappingTrigger for MappingTrigger {
                     asc_new::<AscEthereumBlock, _, _>(heap, &block)?.erase()
                 }
             }
+            MappingTrigger::Transaction {
+                block,
+                transaction,
+                receipt,
+            } => {
+                // Transaction handlers require apiVersion 0.0.6, so there
+                // are no older versions of these types
+                let transaction = EthereumTransactionWithReceiptData {
+                    block: EthereumBlockData::from(block.as_ref()),
+                    transaction: EthereumTransactionData::from(transaction.deref()),
+                    receipt: EthereumTransactionReceiptData::from(receipt.deref()),
+                };
+                asc_new::<AscEthereumTransactionWithReceipt, _, _>(heap, &transaction)?.erase()
+            }
         })
     }
 }
@@ -192,7 +244,12 @@ impl blockchain::MappingTrigger for MappingTrigger {
 pub enum EthereumTrigger {
     Block(BlockPtr, EthereumBlockTriggerType),
     Call(Arc<EthereumCall>),
-    Log(Arc<Log>),
+    /// A log, along with the receipt of its transaction if an event
+    /// handler asked for it
+    Log(Arc<Log>, Option<Arc<TransactionReceipt>>),
+    /// A transaction that matched a transaction handler, identified by its
+    /// receipt
+    Transaction(Arc<TransactionReceipt>),
 }
 
 impl PartialEq for EthereumTrigger {
@@ -204,10 +261,14 @@ impl PartialEq for EthereumTrigger {
 
             (Self::Call(a), Self::Call(b)) => a == b,
 
-            (Self::Log(a), Self::Log(b)) => {
+            (Self::Log(a, _), Self::Log(b, _)) => {
                 a.transaction_hash == b.transaction_hash && a.log_index == b.log_index
             }
 
+            (Self::Transaction(a), Self::Transaction(b)) => {
+                a.transaction_hash == b.transaction_hash
+            }
+
             _ => false,
         }
     }
@@ -226,7 +287,12 @@ impl EthereumTrigger {
         match self {
             EthereumTrigger::Block(block_ptr, _) => block_ptr.number,
             EthereumTrigger::Call(call) => call.block_number,
-            EthereumTrigger::Log(log) => i32::try_from(log.block_number.unwrap().as_u64()).unwrap(),
+            EthereumTrigger::Log(log, _) => {
+                i32::try_from(log.block_number.unwrap().as_u64()).unwrap()
+            }
+            EthereumTrigger::Transaction(receipt) => {
+                i32::try_from(receipt.block_number.unwrap().as_u64()).unwrap()
+            }
         }
     }
 
@@ -234,7 +300,8 @@ impl EthereumTrigger {
         match self {
             EthereumTrigger::Block(block_ptr, _) => block_ptr.hash_as_h256(),
             EthereumTrigger::Call(call) => call.block_hash,
-            EthereumTrigger::Log(log) => log.block_hash.unwrap(),
+            EthereumTrigger::Log(log, _) => log.block_hash.unwrap(),
+            EthereumTrigger::Transaction(receipt) => receipt.block_hash.unwrap(),
         }
     }
 }
@@ -253,28 +320,52 @@ impl Ord for EthereumTrigger {
             (Self::Call(a), Self::Call(b)) => a.transaction_index.cmp(&b.transaction_index),
 
             // Events are ordered by their log index
-            (Self::Log(a), Self::Log(b)) => a.log_index.cmp(&b.log_index),
+            (Self::Log(a, _), Self::Log(b, _)) => a.log_index.cmp(&b.log_index),
 
             // Calls vs. events are logged by their tx index;
             // if they are from the same transaction, events come first
-            (Self::Call(a), Self::Log(b))
+            (Self::Call(a), Self::Log(b, _))
                 if a.transaction_index == b.transaction_index.unwrap().as_u64() =>
             {
                 Ordering::Greater
             }
-            (Self::Log(a), Self::Call(b))
+            (Self::Log(a, _), Self::Call(b))
                 if a.transaction_index.unwrap().as_u64() == b.transaction_index =>
             {
                 Ordering::Less
             }
-            (Self::Call(a), Self::Log(b)) => a
+            (Self::Call(a), Self::Log(b, _)) => a
                 .transaction_index
                 .cmp(&b.transaction_index.unwrap().as_u64()),
-            (Self::Log(a), Self::Call(b)) => a
+            (Self::Log(a, _), Self::Call(b)) => a
                 .transaction_index
                 .unwrap()
                 .as_u64()
                 .cmp(&b.transaction_index),
+
+            // Transactions are ordered by their tx index; a transaction
+            // comes after the events and calls that happened in it
+            (Self::Transaction(a), Self::Transaction(b)) => {
+                a.transaction_index.cmp(&b.transaction_index)
+            }
+            (Self::Transaction(a), Self::Log(b, _)) => a
+                .transaction_index
+                .cmp(&b.transaction_index.unwrap())
+                .then(Ordering::Greater),
+            (Self::Log(a, _), Self::Transaction(b)) => a
+                .transaction_index
+                .unwrap()
+                .cmp(&b.transaction_index)
+                .then(Ordering::Less),
+            (Self::Transaction(a), Self::Call(b)) => a
+                .transaction_index
+                .as_u64()
+                .cmp(&b.transaction_index)
+                .then(Ordering::Greater),
+            (Self::Call(a), Self::Transaction(b)) => a
+                .transaction_index
+                .cmp(&b.transaction_index.as_u64())
+                .then(Ordering::Less),
         }
     }
 }
@@ -288,8 +379,9 @@ impl PartialOrd for EthereumTrigger {
 impl TriggerData for EthereumTrigger {
     fn error_context(&self) -> std::string::String {
         let transaction_id = match self {
-            EthereumTrigger::Log(log) => log.transaction_hash,
+            EthereumTrigger::Log(log, _) => log.transaction_hash,
             EthereumTrigger::Call(call) => call.transaction_hash,
+            EthereumTrigger::Transaction(receipt) => Some(receipt.transaction_hash),
             EthereumTrigger::Block(..) => None,
         };
 
@@ -390,6 +482,9 @@ pub struct EthereumEventData {
     pub block: EthereumBlockData,
     pub transaction: EthereumTransactionData,
     pub params: Vec<LogParam>,
+    /// The receipt of the transaction that emitted the event; only set
+    /// if the event handler has `receipt: true`
+    pub receipt: Option<EthereumTransactionReceiptData>,
 }
 
 impl Clone for EthereumEventData {
@@ -409,6 +504,7 @@ impl Clone for EthereumEventData {
                     value: log_param.value.clone(),
                 })
                 .collect(),
+            receipt: self.receipt.clone(),
         }
     }
 }
@@ -450,3 +546,47 @@ impl Clone for EthereumCallData {
         }
     }
 }
+
+/// The receipt of an Ethereum transaction.
+#[derive(Clone, Debug)]
+pub struct EthereumTransactionReceiptData {
+    pub transaction_hash: H256,
+    pub transaction_index: U64,
+    pub block_hash: Option<H256>,
+    pub block_number: Option<U64>,
+    pub cumulative_gas_used: U256,
+    pub gas_used: Option<U256>,
+    pub contract_address: Option<H160>,
+    pub logs: Vec<Log>,
+    /// `None` for transactions from before EIP-658
+    pub status: Option<U64>,
+    pub root: Option<H256>,
+    pub logs_bloom: H2048,
+}
+
+impl From<&'_ TransactionReceipt> for EthereumTransactionReceiptData {
+    fn from(receipt: &TransactionReceipt) -> EthereumTransactionReceiptData {
+        EthereumTransactionReceiptData {
+            transaction_hash: receipt.transaction_hash,
+            transaction_index: receipt.transaction_index,
+            block_hash: receipt.block_hash,
+            block_number: receipt.block_number,
+            cumulative_gas_used: receipt.cumulative_gas_used,
+            gas_used: receipt.gas_used,
+            contract_address: receipt.contract_address,
+            logs: receipt.logs.clone(),
+            status: receipt.status,
+            root: receipt.root,
+            logs_bloom: receipt.logs_bloom,
+        }
+    }
+}
+
+/// An Ethereum transaction together with its receipt, as passed to
+/// transaction handlers.
+#[derive(Clone, Debug)]
+pub struct EthereumTransactionWithReceiptData {
+    pub block: EthereumBlockData,
+    pub transaction: EthereumTransactionData,
+    pub receipt: EthereumTransactionReceiptData,
+}
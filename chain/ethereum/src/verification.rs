@@ -0,0 +1,232 @@
+//! Check the responses of one provider against those of other providers
+//! for the same network.
+//!
+//! A provider that misbehaves can return wrong logs, blocks or `eth_call`
+//! results without any error, which then leads to wrong data and proofs of
+//! indexing. With verification turned on for a network, the requests whose
+//! results go into subgraphs are also sent to other providers, and the
+//! responses are compared. Providers that fail to answer, or that do not
+//! know about a block yet, do not take part in the comparison.
+
+use std::fmt;
+use std::sync::Arc;
+
+use graph::prelude::rand::{self, seq::IteratorRandom};
+use graph::prelude::{anyhow::anyhow, warn, Error, Logger};
+
+use crate::adapter::ProviderEthRpcMetrics;
+use crate::EthereumAdapter;
+
+/// What to do when the providers that answered a request disagree
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum OnDisagreement {
+    /// Use the response that more than half of the providers agree on, and
+    /// fail the request if there is no such response
+    Majority,
+    /// Fail the request
+    Fail,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Verification {
+    /// The number of providers each request is sent to, including the
+    /// provider that serves the request
+    pub providers: usize,
+    pub on_disagreement: OnDisagreement,
+}
+
+pub(crate) struct Verifier {
+    verification: Verification,
+    /// The providers that can check responses; they do not verify their
+    /// own responses
+    peers: Vec<EthereumAdapter>,
+    metrics: Arc<ProviderEthRpcMetrics>,
+}
+
+impl Verifier {
+    pub fn new(
+        verification: Verification,
+        peers: Vec<EthereumAdapter>,
+        metrics: Arc<ProviderEthRpcMetrics>,
+    ) -> Self {
+        Verifier {
+            verification,
+            peers,
+            metrics,
+        }
+    }
+
+    /// A random selection of peers to check a response with
+    pub fn peers(&self) -> Vec<&EthereumAdapter> {
+        self.peers.iter().choose_multiple(
+            &mut rand::thread_rng(),
+            self.verification.providers.saturating_sub(1),
+        )
+    }
+
+    /// Decide on the result of the request `method` that `provider`
+    /// answered with `response`, given the answers of the peers in
+    /// `checks`. Responses are the same if their `key` is the same
+    pub fn decide<T, E, K>(
+        &self,
+        logger: &Logger,
+        method: &str,
+        provider: &str,
+        response: T,
+        checks: Vec<(String, Result<T, E>)>,
+        key: impl Fn(&T) -> K,
+    ) -> Result<T, Error>
+    where
+        E: fmt::Display,
+        K: PartialEq,
+    {
+        let mut responses = vec![(provider.to_string(), response)];
+        for (peer, check) in checks {
+            match check {
+                Ok(check) => responses.push((peer, check)),
+                Err(e) => {
+                    warn!(logger, "Provider could not verify response";
+                          "method" => method,
+                          "provider" => &peer,
+                          "error" => e.to_string())
+                }
+            }
+        }
+
+        // Group the responses by their key; `groups` holds the indexes
+        // into `responses` for each distinct key
+        let keys: Vec<K> = responses
+            .iter()
+            .map(|(_, response)| key(response))
+            .collect();
+        let mut groups: Vec<Vec<usize>> = Vec::new();
+        for (i, k) in keys.iter().enumerate() {
+            match groups.iter_mut().find(|group| &keys[group[0]] == k) {
+                Some(group) => group.push(i),
+                None => groups.push(vec![i]),
+            }
+        }
+        if groups.len() == 1 {
+            return Ok(responses.swap_remove(0).1);
+        }
+
+        groups.sort_by_key(|group| std::cmp::Reverse(group.len()));
+        let majority = match self.verification.on_disagreement {
+            OnDisagreement::Majority if groups[0].len() * 2 > responses.len() => Some(0),
+            OnDisagreement::Majority | OnDisagreement::Fail => None,
+        };
+
+        let providers = |group: &Vec<usize>| {
+            group
+                .iter()
+                .map(|i| responses[*i].0.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        let description = groups
+            .iter()
+            .map(|group| format!("[{}]", providers(group)))
+            .collect::<Vec<_>>()
+            .join(" vs ");
+        for (g, group) in groups.iter().enumerate() {
+            if Some(g) != majority {
+                for i in group {
+                    self.metrics.add_disagreement(method, &responses[*i].0);
+                }
+            }
+        }
+        warn!(logger, "Providers disagree on the response to a request";
+              "method" => method,
+              "providers" => &description,
+              "majority" => majority.map(|g| providers(&groups[g])));
+
+        match majority {
+            Some(g) => {
+                let i = groups[g][0];
+                Ok(responses.swap_remove(i).1)
+            }
+            None => Err(anyhow!(
+                "providers disagree on the response to {}: {}",
+                method,
+                description
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use graph::prelude::{o, slog, Registry};
+    use graph_core::MetricsRegistry;
+
+    fn verifier(on_disagreement: OnDisagreement) -> Verifier {
+        let logger = Logger::root(slog::Discard, o!());
+        let registry = Arc::new(MetricsRegistry::new(logger, Arc::new(Registry::new())));
+        let verification = Verification {
+            providers: 3,
+            on_disagreement,
+        };
+        Verifier::new(
+            verification,
+            vec![],
+            Arc::new(ProviderEthRpcMetrics::new(registry)),
+        )
+    }
+
+    fn decide(
+        on_disagreement: OnDisagreement,
+        response: u64,
+        checks: Vec<Result<u64, &str>>,
+    ) -> Result<u64, Error> {
+        let logger = Logger::root(slog::Discard, o!());
+        let checks = checks
+            .into_iter()
+            .enumerate()
+            .map(|(i, check)| (format!("peer{}", i), check))
+            .collect();
+        verifier(on_disagreement).decide(&logger, "test", "provider", response, checks, |r| *r)
+    }
+
+    #[test]
+    fn agreeing_providers() {
+        use OnDisagreement::*;
+
+        for mode in vec![Majority, Fail] {
+            assert_eq!(1, decide(mode, 1, vec![Ok(1), Ok(1)]).unwrap());
+            assert_eq!(1, decide(mode, 1, vec![]).unwrap());
+        }
+    }
+
+    #[test]
+    fn peers_that_fail_do_not_take_part() {
+        use OnDisagreement::*;
+
+        for mode in vec![Majority, Fail] {
+            assert_eq!(1, decide(mode, 1, vec![Err("behind"), Ok(1)]).unwrap());
+            assert_eq!(
+                1,
+                decide(mode, 1, vec![Err("behind"), Err("down")]).unwrap()
+            );
+        }
+        // With one peer failing, there is no majority between the
+        // remaining two providers
+        assert!(decide(Majority, 1, vec![Err("behind"), Ok(2)]).is_err());
+    }
+
+    #[test]
+    fn disagreeing_providers() {
+        use OnDisagreement::*;
+
+        // The peers outvote the provider
+        assert_eq!(2, decide(Majority, 1, vec![Ok(2), Ok(2)]).unwrap());
+        assert_eq!(1, decide(Majority, 1, vec![Ok(2), Ok(1)]).unwrap());
+        // There is no response that more than half agree on
+        assert!(decide(Majority, 1, vec![Ok(2), Ok(3)]).is_err());
+        assert!(decide(Majority, 1, vec![Ok(2), Ok(2), Ok(1)]).is_err());
+
+        assert!(decide(Fail, 1, vec![Ok(2), Ok(2)]).is_err());
+        assert!(decide(Fail, 1, vec![Ok(1), Ok(2)]).is_err());
+    }
+}
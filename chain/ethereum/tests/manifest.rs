@@ -4,11 +4,11 @@ use std::time::Duration;
 
 use graph::data::subgraph::SPEC_VERSION_0_0_4;
 use graph::prelude::{
-    anyhow, async_trait, serde_yaml, tokio, DeploymentHash, Entity, Link, Logger, SubgraphManifest,
-    SubgraphManifestValidationError, UnvalidatedSubgraphManifest,
+    anyhow, async_trait, serde_yaml, tokio, web3::types::Address, DeploymentHash, Entity, Link,
+    Logger, SubgraphManifest, SubgraphManifestValidationError, UnvalidatedSubgraphManifest,
 };
 use graph::{
-    blockchain::NodeCapabilities as _,
+    blockchain::{DataSource as _, NodeCapabilities as _},
     components::{
         link_resolver::{JsonValueStream, LinkResolver as LinkResolverTrait},
         store::EntityType,
@@ -200,6 +200,53 @@ specVersion: 0.0.2
     })
 }
 
+#[tokio::test]
+async fn indexer_hints_manifest() {
+    const YAML: &str = "
+dataSources: []
+schema:
+  file:
+    /: /ipfs/Qmschema
+indexerHints:
+  historyBlocks: 1000
+specVersion: 0.0.2
+";
+
+    let manifest = resolve_manifest(YAML).await;
+
+    assert_eq!(Some(1000), manifest.indexer_hints.history_blocks);
+}
+
+#[test]
+fn indexer_hints_invalid_manifest() {
+    const YAML: &str = "
+dataSources: []
+schema:
+  file:
+    /: /ipfs/Qmschema
+indexerHints:
+  historyBlocks: 0
+specVersion: 0.0.2
+";
+
+    test_store::run_test_sequentially(|store| async move {
+        let store = store.subgraph_store();
+
+        let unvalidated = resolve_unvalidated(YAML).await;
+        let msg = unvalidated
+            .validate(store, true)
+            .expect_err("Validation must fail")
+            .into_iter()
+            .find(|e| matches!(e, SubgraphManifestValidationError::IndexerHintsInvalid(_)))
+            .expect("There must be an IndexerHintsInvalid error")
+            .to_string();
+        assert_eq!(
+            "the indexer hints are invalid: historyBlocks must be positive but is 0",
+            msg
+        );
+    })
+}
+
 #[tokio::test]
 async fn parse_call_handlers() {
     const YAML: &str = "
@@ -238,6 +285,99 @@ specVersion: 0.0.2
     assert_eq!(true, required_capabilities.traces);
 }
 
+#[tokio::test]
+async fn parse_transaction_handlers() {
+    const YAML: &str = "
+dataSources:
+  - kind: ethereum/contract
+    name: Wallet
+    network: mainnet
+    source:
+      abi: Factory
+      startBlock: 9562480
+    mapping:
+      kind: ethereum/events
+      apiVersion: 0.0.6
+      language: wasm/assemblyscript
+      entities:
+        - TestEntity
+      file:
+        /: /ipfs/Qmmapping
+      abis:
+        - name: Factory
+          file:
+            /: /ipfs/Qmabi
+      transactionHandlers:
+        - handler: handlePayment
+          to: '0x0000000000000000000000000000000000000001'
+          function: get(uint256)
+schema:
+  file:
+    /: /ipfs/Qmschema
+specVersion: 0.0.2
+";
+
+    let manifest = resolve_manifest(YAML).await;
+    let required_capabilities = NodeCapabilities::from_data_sources(&manifest.data_sources);
+
+    let handlers = &manifest.data_sources[0].mapping.transaction_handlers;
+    assert_eq!(1, handlers.len());
+    assert_eq!("handlePayment", handlers[0].handler);
+    assert_eq!(None, handlers[0].from);
+    assert_eq!(Some(Address::from_low_u64_be(1)), handlers[0].to);
+    assert_eq!(Some("get(uint256)".to_string()), handlers[0].function);
+    assert_eq!(false, required_capabilities.traces);
+}
+
+#[tokio::test]
+async fn parse_event_handlers_with_receipts() {
+    const YAML: &str = "
+dataSources:
+  - kind: ethereum/contract
+    name: Factory
+    network: mainnet
+    source:
+      abi: Factory
+      startBlock: 9562480
+    mapping:
+      kind: ethereum/events
+      apiVersion: 0.0.7
+      language: wasm/assemblyscript
+      entities:
+        - TestEntity
+      file:
+        /: /ipfs/Qmmapping
+      abis:
+        - name: Factory
+          file:
+            /: /ipfs/Qmabi
+      eventHandlers:
+        - event: Created(address)
+          handler: handleCreated
+          receipt: true
+        - event: Destroyed(address)
+          handler: handleDestroyed
+schema:
+  file:
+    /: /ipfs/Qmschema
+specVersion: 0.0.2
+";
+
+    let manifest = resolve_manifest(YAML).await;
+
+    let handlers = &manifest.data_sources[0].mapping.event_handlers;
+    assert_eq!(2, handlers.len());
+    assert_eq!(true, handlers[0].receipt);
+    assert_eq!(false, handlers[1].receipt);
+    assert!(manifest.data_sources[0].validate().is_empty());
+
+    // Receipts are only available from apiVersion 0.0.7 on
+    let manifest = resolve_manifest(&YAML.replace("apiVersion: 0.0.7", "apiVersion: 0.0.6")).await;
+    let errors = manifest.data_sources[0].validate();
+    assert_eq!(1, errors.len());
+    assert!(errors[0].to_string().contains("requires apiVersion 0.0.7"));
+}
+
 #[test]
 fn undeclared_grafting_feature_causes_feature_validation_error() {
     const YAML: &str = "
@@ -1,4 +1,8 @@
+use std::collections::{HashMap, HashSet};
+
 use crate::capabilities::NodeCapabilities;
+use crate::codec;
+use crate::data_source::{Accounts, ReceiptHandler};
 use crate::{data_source::DataSource, Chain};
 use graph::blockchain as bc;
 use graph::prelude::*;
@@ -6,12 +10,15 @@ use graph::prelude::*;
 #[derive(Clone, Debug, Default)]
 pub struct TriggerFilter {
     pub(crate) block: NearBlockFilter,
+    pub(crate) receipt: NearReceiptFilter,
 }
 
 impl bc::TriggerFilter<Chain> for TriggerFilter {
     fn extend<'a>(&mut self, data_sources: impl Iterator<Item = &'a DataSource> + Clone) {
         self.block
-            .extend(NearBlockFilter::from_data_sources(data_sources));
+            .extend(NearBlockFilter::from_data_sources(data_sources.clone()));
+        self.receipt
+            .extend(NearReceiptFilter::from_data_sources(data_sources));
     }
 
     fn node_capabilities(&self) -> NodeCapabilities {
@@ -40,3 +47,162 @@ impl NearBlockFilter {
         self.trigger_every_block = self.trigger_every_block || other.trigger_every_block;
     }
 }
+
+/// Selects the receipts that some data source has a receipt handler for, so that all other
+/// receipts can be dropped before they reach the mappings.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct NearReceiptFilter {
+    /// The receipt handlers of data sources that list accounts exactly, by account
+    accounts: HashMap<String, HashSet<ReceiptHandler>>,
+    /// The receipt handlers of data sources that match accounts by prefix or suffix
+    partial_accounts: HashSet<(Accounts, ReceiptHandler)>,
+}
+
+impl NearReceiptFilter {
+    pub fn from_data_sources<'a>(iter: impl IntoIterator<Item = &'a DataSource>) -> Self {
+        let mut filter = Self::default();
+        for data_source in iter {
+            let handler = match data_source.handler_for_receipt() {
+                Some(handler) => handler,
+                None => continue,
+            };
+
+            let source = &data_source.source;
+            let exact = source
+                .accounts
+                .iter()
+                .flat_map(|accounts| accounts.exact.iter());
+            for account in source.account.iter().chain(exact) {
+                filter
+                    .accounts
+                    .entry(account.clone())
+                    .or_default()
+                    .insert(handler.clone());
+            }
+
+            if let Some(accounts) = source.accounts.as_ref().filter(|a| a.has_partial()) {
+                filter
+                    .partial_accounts
+                    .insert((accounts.clone(), handler.clone()));
+            }
+        }
+        filter
+    }
+
+    pub fn extend(&mut self, other: NearReceiptFilter) {
+        for (account, handlers) in other.accounts {
+            self.accounts.entry(account).or_default().extend(handlers);
+        }
+        self.partial_accounts.extend(other.partial_accounts);
+    }
+
+    /// Whether any data source has a receipt handler that accepts `receipt`
+    pub fn matches(&self, receipt: &codec::Receipt) -> bool {
+        let account = receipt.receiver_id.as_str();
+
+        let exact = self.accounts.get(account).map_or(false, |handlers| {
+            handlers.iter().any(|h| h.matches(receipt))
+        });
+
+        exact
+            || self.partial_accounts.iter().any(|(accounts, handler)| {
+                accounts.matches_partial(account) && handler.matches(receipt)
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bc::TriggerFilter as _;
+    use graph::semver;
+
+    use crate::data_source::{Mapping, Source};
+
+    fn data_source(
+        account: Option<&str>,
+        accounts: Option<Accounts>,
+        methods: &[&str],
+    ) -> DataSource {
+        let handler: ReceiptHandler = serde_json::from_value(serde_json::json!({
+            "handler": "handleReceipt",
+            "methods": methods,
+        }))
+        .unwrap();
+
+        DataSource {
+            kind: "near".to_string(),
+            network: None,
+            name: "Test".to_string(),
+            source: Source {
+                account: account.map(str::to_string),
+                accounts,
+                start_block: 0,
+            },
+            mapping: Mapping {
+                api_version: semver::Version::new(0, 0, 5),
+                language: "wasm/assemblyscript".to_string(),
+                entities: vec![],
+                block_handlers: vec![],
+                receipt_handlers: vec![handler],
+                runtime: Arc::new(vec![]),
+                link: Link {
+                    link: "mapping.wasm".to_string(),
+                },
+            },
+            context: Arc::new(None),
+            creation_block: None,
+        }
+    }
+
+    fn function_call(receiver: &str, method: &str) -> codec::Receipt {
+        codec::Receipt {
+            receiver_id: receiver.to_string(),
+            receipt: Some(codec::receipt::Receipt::Action(codec::ReceiptAction {
+                actions: vec![codec::Action {
+                    action: Some(codec::action::Action::FunctionCall(
+                        codec::FunctionCallAction {
+                            method_name: method.to_string(),
+                            ..Default::default()
+                        },
+                    )),
+                }],
+                ..Default::default()
+            })),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn receipt_filter_matches_accounts_of_data_sources() {
+        let data_sources = vec![
+            data_source(Some("token.near"), None, &[]),
+            data_source(
+                None,
+                Some(Accounts {
+                    suffixes: vec![".factory.near".to_string()],
+                    ..Default::default()
+                }),
+                &["swap"],
+            ),
+        ];
+        let filter = TriggerFilter::from_data_sources(data_sources.iter());
+
+        assert!(filter
+            .receipt
+            .matches(&function_call("token.near", "transfer")));
+        assert!(filter
+            .receipt
+            .matches(&function_call("pool.factory.near", "swap")));
+        assert!(!filter
+            .receipt
+            .matches(&function_call("pool.factory.near", "deposit")));
+        assert!(!filter.receipt.matches(&function_call("other.near", "swap")));
+
+        // Data sources that are created later extend the filter
+        let mut filter = filter;
+        filter.extend(vec![data_source(Some("other.near"), None, &[])].iter());
+        assert!(filter.receipt.matches(&function_call("other.near", "swap")));
+    }
+}
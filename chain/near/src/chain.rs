@@ -11,17 +11,19 @@ use graph::{
             FirehoseMapper as FirehoseMapperTrait, TriggersAdapter as TriggersAdapterTrait,
         },
         firehose_block_stream::FirehoseBlockStream,
-        BlockHash, BlockPtr, Blockchain, IngestorAdapter as IngestorAdapterTrait, IngestorError,
+        Block, BlockHash, BlockPtr, Blockchain, IngestorAdapter as IngestorAdapterTrait,
+        IngestorError,
     },
-    components::store::DeploymentLocator,
+    components::store::{DeploymentLocator, WritableStore},
     firehose::bstream,
     log::factory::{ComponentLoggerConfig, ElasticComponentLoggerConfig},
     prelude::{
-        async_trait, o, BlockNumber, ChainStore, Error, Logger, LoggerFactory, SubgraphStore,
+        async_trait, o, web3::types::H256, BlockNumber, ChainStore, Error, Logger, LoggerFactory,
     },
 };
 use prost::Message;
-use std::sync::Arc;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
 
 use crate::adapter::TriggerFilter;
 use crate::capabilities::NodeCapabilities;
@@ -39,7 +41,8 @@ pub struct Chain {
     name: String,
     firehose_endpoints: Arc<FirehoseNetworkEndpoints>,
     chain_store: Arc<dyn ChainStore>,
-    subgraph_store: Arc<dyn SubgraphStore>,
+    stored_blocks: Arc<StoredBlocks>,
+    reorg_threshold: BlockNumber,
 }
 
 impl std::fmt::Debug for Chain {
@@ -53,15 +56,16 @@ impl Chain {
         logger_factory: LoggerFactory,
         name: String,
         chain_store: Arc<dyn ChainStore>,
-        subgraph_store: Arc<dyn SubgraphStore>,
         firehose_endpoints: FirehoseNetworkEndpoints,
+        reorg_threshold: BlockNumber,
     ) -> Self {
         Chain {
             logger_factory,
             name,
             firehose_endpoints: Arc::new(firehose_endpoints),
             chain_store,
-            subgraph_store,
+            stored_blocks: Arc::new(StoredBlocks::default()),
+            reorg_threshold,
         }
     }
 }
@@ -101,13 +105,16 @@ impl Blockchain for Chain {
         _unified_api_version: UnifiedMappingApiVersion,
         _stopwatch_metrics: StopwatchMetrics,
     ) -> Result<Arc<Self::TriggersAdapter>, Error> {
-        let adapter = TriggersAdapter {};
+        let adapter = TriggersAdapter {
+            chain_store: self.chain_store.cheap_clone(),
+        };
         Ok(Arc::new(adapter))
     }
 
     async fn new_block_stream(
         &self,
         deployment: DeploymentLocator,
+        store: Arc<dyn WritableStore>,
         start_blocks: Vec<BlockNumber>,
         filter: Arc<TriggerFilter>,
         metrics: Arc<BlockStreamMetrics>,
@@ -132,13 +139,11 @@ impl Blockchain for Chain {
             .subgraph_logger(&deployment)
             .new(o!("component" => "FirehoseBlockStream"));
 
-        let firehose_mapper = Arc::new(FirehoseMapper {});
-        let firehose_cursor = self
-            .subgraph_store
-            .cheap_clone()
-            .writable(logger.clone(), deployment.id)
-            .await?
-            .block_cursor()?;
+        let firehose_mapper = Arc::new(FirehoseMapper {
+            chain_store: self.chain_store.cheap_clone(),
+            stored_blocks: self.stored_blocks.cheap_clone(),
+        });
+        let firehose_cursor = store.block_cursor()?;
 
         Ok(Box::new(FirehoseBlockStream::new(
             firehose_endpoint,
@@ -187,9 +192,15 @@ impl Blockchain for Chain {
     fn runtime_adapter(&self) -> Arc<Self::RuntimeAdapter> {
         Arc::new(RuntimeAdapter {})
     }
+
+    fn reorg_threshold(&self) -> BlockNumber {
+        self.reorg_threshold
+    }
 }
 
-pub struct TriggersAdapter {}
+pub struct TriggersAdapter {
+    chain_store: Arc<dyn ChainStore>,
+}
 
 #[async_trait]
 impl TriggersAdapterTrait<Chain> for TriggersAdapter {
@@ -206,40 +217,112 @@ impl TriggersAdapterTrait<Chain> for TriggersAdapter {
     async fn triggers_in_block(
         &self,
         _logger: &Logger,
-        _block: codec::Block,
-        _filter: &TriggerFilter,
+        block: codec::Block,
+        filter: &TriggerFilter,
     ) -> Result<BlockWithTriggers<Chain>, Error> {
-        // FIXME (NEAR): Share implementation with FirehoseMapper::firehose_triggers_in_block version.
-        // This is currently unreachable since Near does not yet support dynamic data sources.
-        todo!()
+        // This is called when dynamic data sources were created in `block` and the block needs to
+        // be processed again for them.
+        Ok(triggers_in_block(Arc::new(block), filter))
     }
 
-    async fn is_on_main_chain(&self, _ptr: BlockPtr) -> Result<bool, Error> {
-        // FIXME (NEAR): Might not be necessary for NEAR support for now
-        Ok(true)
+    async fn is_on_main_chain(&self, ptr: BlockPtr) -> Result<bool, Error> {
+        // The `FirehoseMapper` stores every new block and purges the other blocks with the same
+        // number, so the store only has the main chain block for each number. A block that was
+        // undone is only purged once a new block with its number arrives, since NEAR block
+        // heights can skip numbers.
+        let hashes = self.chain_store.block_hashes_by_block_number(ptr.number)?;
+        Ok(hashes.contains(&ptr.hash_as_h256()))
     }
 
+    /// NEAR block heights can skip numbers, so the ancestor is the block with the highest number
+    /// that is at least `offset` below `ptr`. It is found by following parents through the chain
+    /// store; if the store is missing a block on the way, there is no ancestor.
     fn ancestor_block(
         &self,
-        _ptr: BlockPtr,
-        _offset: BlockNumber,
+        ptr: BlockPtr,
+        offset: BlockNumber,
     ) -> Result<Option<codec::Block>, Error> {
-        // FIXME (NEAR):  Might not be necessary for NEAR support for now
-        Ok(None)
+        anyhow::ensure!(
+            ptr.number >= offset,
+            "block offset {} for block `{}` points to before genesis block",
+            offset,
+            ptr.hash_hex()
+        );
+
+        let number = ptr.number - offset;
+        let mut block = match self.block(&ptr)? {
+            Some(block) => block,
+            None => return Ok(None),
+        };
+        while block.number() > number {
+            block = match block.parent_ptr() {
+                Some(parent) => match self.block(&parent)? {
+                    Some(parent) => parent,
+                    None => return Ok(None),
+                },
+                None => return Ok(None),
+            };
+        }
+        Ok(Some(block))
     }
 
-    /// Panics if `block` is genesis.
-    /// But that's ok since this is only called when reverting `block`.
     async fn parent_ptr(&self, block: &BlockPtr) -> Result<Option<BlockPtr>, Error> {
-        // FIXME (NEAR):  Might not be necessary for NEAR support for now
-        Ok(Some(BlockPtr {
-            hash: BlockHash::from(vec![0xff; 32]),
-            number: block.number.saturating_sub(1),
-        }))
+        let block = self
+            .block(block)?
+            .ok_or_else(|| anyhow::anyhow!("block {} is not in the chain store", block))?;
+        Ok(block.parent_ptr())
     }
 }
 
-pub struct FirehoseMapper {}
+impl TriggersAdapter {
+    fn block(&self, ptr: &BlockPtr) -> Result<Option<codec::Block>, Error> {
+        self.chain_store
+            .blocks(&[ptr.hash_as_h256()])?
+            .into_iter()
+            .next()
+            .map(codec::Block::from_stored_data)
+            .transpose()
+    }
+}
+
+/// The blocks that the `FirehoseMapper`s of a chain stored recently. Each deployment has its own
+/// block stream and mapper; remembering the blocks that one of them stored keeps the others from
+/// storing and confirming the same block again.
+#[derive(Default)]
+struct StoredBlocks {
+    blocks: Mutex<BTreeMap<BlockNumber, H256>>,
+}
+
+impl StoredBlocks {
+    /// How many blocks to remember. When there are more, the ones with the lowest numbers, which
+    /// only deployments that are far behind the others need, are forgotten first
+    const CAPACITY: usize = 1_000;
+
+    /// Store the block in the chain store unless it was stored already. The block replaces any
+    /// block with the same number that was undone.
+    fn store(&self, chain_store: &dyn ChainStore, block: &codec::Block) -> Result<(), Error> {
+        let number = block.number();
+        let hash = block.ptr().hash_as_h256();
+
+        let mut blocks = self.blocks.lock().unwrap();
+        if blocks.get(&number) == Some(&hash) {
+            return Ok(());
+        }
+        chain_store.upsert_light_blocks(&[block])?;
+        chain_store.confirm_block_hash(number, &hash)?;
+        blocks.insert(number, hash);
+        if blocks.len() > Self::CAPACITY {
+            let lowest = *blocks.keys().next().unwrap();
+            blocks.remove(&lowest);
+        }
+        Ok(())
+    }
+}
+
+pub struct FirehoseMapper {
+    chain_store: Arc<dyn ChainStore>,
+    stored_blocks: Arc<StoredBlocks>,
+}
 
 impl FirehoseMapperTrait<Chain> for FirehoseMapper {
     fn to_block_stream_event(
@@ -270,10 +353,16 @@ impl FirehoseMapperTrait<Chain> for FirehoseMapper {
         let block = codec::Block::decode(any_block.value.as_ref())?;
 
         match step {
-            bstream::ForkStep::StepNew => Ok(BlockStreamEvent::ProcessBlock(
-                self.firehose_triggers_in_block(&block, filter)?,
-                Some(response.cursor.clone()),
-            )),
+            bstream::ForkStep::StepNew => {
+                // Keep the block so that reorgs can be handled later
+                self.stored_blocks
+                    .store(self.chain_store.as_ref(), &block)?;
+
+                Ok(BlockStreamEvent::ProcessBlock(
+                    self.firehose_triggers_in_block(&block, filter)?,
+                    Some(response.cursor.clone()),
+                ))
+            }
 
             bstream::ForkStep::StepUndo => {
                 let header = block.header();
@@ -284,7 +373,7 @@ impl FirehoseMapperTrait<Chain> for FirehoseMapper {
                         number: header.height as i32,
                     },
                     Some(response.cursor.clone()),
-                    None, // FIXME: we should get the parent block pointer when we have access to parent block height
+                    block.parent_ptr(),
                 ))
             }
 
@@ -307,52 +396,60 @@ impl FirehoseMapper {
     fn firehose_triggers_in_block(
         &self,
         block: &codec::Block,
-        _filter: &TriggerFilter,
+        filter: &TriggerFilter,
     ) -> Result<BlockWithTriggers<Chain>, FirehoseError> {
         // TODO: Find the best place to introduce an `Arc` and avoid this clone.
-        let block = Arc::new(block.clone());
-
-        // Filter non-successful or non-action receipts.
-        let receipts = block.shards.iter().flat_map(|shard| {
-            shard
-                .receipt_execution_outcomes
-                .iter()
-                .filter_map(|outcome| {
-                    if !outcome
-                        .execution_outcome
-                        .as_ref()?
-                        .outcome
-                        .as_ref()?
-                        .status
-                        .as_ref()?
-                        .is_success()
-                    {
-                        return None;
-                    }
-                    if !matches!(
-                        outcome.receipt.as_ref()?.receipt,
-                        Some(codec::receipt::Receipt::Action(_))
-                    ) {
-                        return None;
-                    }
-
-                    Some(trigger::ReceiptWithOutcome {
-                        outcome: outcome.execution_outcome.as_ref()?.clone(),
-                        receipt: outcome.receipt.as_ref()?.clone(),
-                        block: block.cheap_clone(),
-                    })
+        Ok(triggers_in_block(Arc::new(block.clone()), filter))
+    }
+}
+
+/// The triggers of `block`, shared by the firehose block stream and by `TriggersAdapter`, which
+/// processes blocks again for data sources that were created while processing them.
+fn triggers_in_block(block: Arc<codec::Block>, filter: &TriggerFilter) -> BlockWithTriggers<Chain> {
+    // Filter non-successful or non-action receipts, and receipts that no data source has a
+    // handler for.
+    let receipts = block.shards.iter().flat_map(|shard| {
+        shard
+            .receipt_execution_outcomes
+            .iter()
+            .filter_map(|outcome| {
+                if !outcome
+                    .execution_outcome
+                    .as_ref()?
+                    .outcome
+                    .as_ref()?
+                    .status
+                    .as_ref()?
+                    .is_success()
+                {
+                    return None;
+                }
+                if !matches!(
+                    outcome.receipt.as_ref()?.receipt,
+                    Some(codec::receipt::Receipt::Action(_))
+                ) {
+                    return None;
+                }
+                if !filter.receipt.matches(outcome.receipt.as_ref()?) {
+                    return None;
+                }
+
+                Some(trigger::ReceiptWithOutcome {
+                    outcome: outcome.execution_outcome.as_ref()?.clone(),
+                    receipt: outcome.receipt.as_ref()?.clone(),
+                    block: block.cheap_clone(),
                 })
-        });
+            })
+    });
 
-        let mut trigger_data: Vec<_> = receipts
-            .map(|r| NearTrigger::Receipt(Arc::new(r)))
-            .collect();
+    let mut trigger_data: Vec<_> = receipts
+        .map(|r| NearTrigger::Receipt(Arc::new(r)))
+        .collect();
 
-        trigger_data.push(NearTrigger::Block(block.cheap_clone()));
+    trigger_data.push(NearTrigger::Block(block.cheap_clone()));
 
-        // TODO: `block` should probably be an `Arc` in `BlockWithTriggers` to avoid this clone.
-        Ok(BlockWithTriggers::new(block.as_ref().clone(), trigger_data))
-    }
+    // TODO: `block` should probably be an `Arc` in `BlockWithTriggers` to avoid this clone.
+    BlockWithTriggers::new(block.as_ref().clone(), trigger_data)
 }
 
 pub struct IngestorAdapter {
@@ -394,3 +491,112 @@ impl IngestorAdapterTrait<Chain> for IngestorAdapter {
         Ok(None)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use graph::components::store::BlockStore as _;
+    use test_store::{block_store, run_test_sequentially, LOGGER, NETWORK_NAME};
+
+    fn block(number: u64, parent: Option<&codec::Block>, fork: u8) -> codec::Block {
+        codec::Block {
+            author: format!("author{}.near", fork),
+            header: Some(codec::BlockHeader {
+                height: number,
+                prev_height: parent.map(|parent| parent.header().height).unwrap_or(0),
+                hash: Some(codec::CryptoHash {
+                    bytes: vec![number as u8 + fork; 32],
+                }),
+                prev_hash: parent.map(|parent| parent.header().hash.clone().unwrap()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn response(block: &codec::Block) -> bstream::BlockResponseV2 {
+        bstream::BlockResponseV2 {
+            block: Some(prost_types::Any {
+                type_url: "type.googleapis.com/sf.near.codec.v1.Block".to_string(),
+                value: block.encode_to_vec(),
+            }),
+            step: bstream::ForkStep::StepNew as i32,
+            cursor: block.number().to_string(),
+        }
+    }
+
+    #[test]
+    fn ancestors_and_reorgs() {
+        run_test_sequentially(|store| async move {
+            block_store::set_chain(vec![], NETWORK_NAME);
+            let chain_store: Arc<dyn ChainStore> =
+                store.block_store().chain_store(NETWORK_NAME).unwrap();
+            let logger = LOGGER.clone();
+            let filter = TriggerFilter::default();
+            let stored_blocks = Arc::new(StoredBlocks::default());
+            let adapter = TriggersAdapter {
+                chain_store: chain_store.cheap_clone(),
+            };
+            // Two deployments that share the blocks they stored
+            let mappers: Vec<_> = (0..2)
+                .map(|_| FirehoseMapper {
+                    chain_store: chain_store.cheap_clone(),
+                    stored_blocks: stored_blocks.cheap_clone(),
+                })
+                .collect();
+
+            // Block heights skip numbers
+            let b10 = block(10, None, 0);
+            let b12 = block(12, Some(&b10), 0);
+            let b13 = block(13, Some(&b12), 0);
+            let b16 = block(16, Some(&b13), 0);
+            for block in &[&b10, &b12, &b13, &b16] {
+                for mapper in &mappers {
+                    mapper
+                        .to_block_stream_event(&logger, &response(block), &adapter, &filter)
+                        .unwrap();
+                }
+            }
+
+            let ancestor = |offset| {
+                adapter
+                    .ancestor_block(b16.ptr(), offset)
+                    .unwrap()
+                    .map(|block| block.number())
+            };
+            assert_eq!(Some(16), ancestor(0));
+            assert_eq!(Some(13), ancestor(3));
+            assert_eq!(Some(12), ancestor(4));
+            assert_eq!(Some(10), ancestor(5));
+            assert_eq!(Some(10), ancestor(6));
+            assert!(adapter.ancestor_block(b16.ptr(), 17).is_err());
+
+            // Ancestors are whole blocks, not just their headers
+            assert_eq!(
+                Some(b13.clone()),
+                adapter.ancestor_block(b16.ptr(), 3).unwrap()
+            );
+            assert_eq!(
+                Some(b13.ptr()),
+                adapter.parent_ptr(&b16.ptr()).await.unwrap()
+            );
+
+            // A reorg replaces block 16 with a block 16 on another fork
+            let b16_fork = block(16, Some(&b13), 1);
+            mappers[1]
+                .to_block_stream_event(&logger, &response(&b16_fork), &adapter, &filter)
+                .unwrap();
+            assert!(!adapter.is_on_main_chain(b16.ptr()).await.unwrap());
+            assert!(adapter.is_on_main_chain(b16_fork.ptr()).await.unwrap());
+            assert!(adapter.is_on_main_chain(b13.ptr()).await.unwrap());
+            assert_eq!(
+                Some(12),
+                adapter
+                    .ancestor_block(b16_fork.ptr(), 4)
+                    .unwrap()
+                    .map(|block| block.number())
+            );
+        })
+    }
+}
@@ -2,10 +2,12 @@
 mod pbcodec;
 
 use graph::{
+    anyhow::{Context, Error},
     blockchain::Block as Blockchainblock,
     blockchain::BlockPtr,
-    prelude::{hex, web3::types::H256, BlockNumber},
+    prelude::{hex, serde_json, web3::types::H256, BlockNumber, Deserialize, Serialize},
 };
+use prost::Message;
 use std::convert::TryFrom;
 use std::fmt::LowerHex;
 
@@ -38,6 +40,58 @@ impl Block {
     }
 }
 
+/// What is kept for a block in the `ChainStore`. The header fields make the stored data easy to
+/// inspect, and `block` holds the whole block, encoded as protobuf and then as hex, so that blocks
+/// read back from the store, e.g. when handling reorgs, have all their shards and chunks.
+#[derive(Serialize, Deserialize)]
+struct StoredBlock {
+    height: u64,
+    prev_height: u64,
+    hash: String,
+    prev_hash: Option<String>,
+    timestamp_nanosec: u64,
+    block: String,
+}
+
+impl TryFrom<&Block> for StoredBlock {
+    type Error = serde_json::Error;
+
+    fn try_from(block: &Block) -> Result<Self, Self::Error> {
+        use serde::ser::Error as _;
+
+        let header = block
+            .header
+            .as_ref()
+            .ok_or_else(|| serde_json::Error::custom("block has no header"))?;
+        let hash = header.hash.as_ref().ok_or_else(|| {
+            serde_json::Error::custom(format!("block #{} has no hash", header.height))
+        })?;
+
+        Ok(StoredBlock {
+            height: header.height,
+            prev_height: header.prev_height,
+            hash: hex::encode(&hash.bytes),
+            prev_hash: header
+                .prev_hash
+                .as_ref()
+                .map(|hash| hex::encode(&hash.bytes)),
+            timestamp_nanosec: header.timestamp_nanosec,
+            block: hex::encode(block.encode_to_vec()),
+        })
+    }
+}
+
+impl Block {
+    /// Turn the data that was stored for a block in the `ChainStore` back into a block
+    pub fn from_stored_data(data: serde_json::Value) -> Result<Block, Error> {
+        let stored: StoredBlock = serde_json::from_value(data)?;
+        let bytes = hex::decode(&stored.block)
+            .with_context(|| format!("invalid data for block #{}", stored.height))?;
+        Block::decode(bytes.as_slice())
+            .with_context(|| format!("invalid data for block #{}", stored.height))
+    }
+}
+
 impl From<Block> for BlockPtr {
     fn from(b: Block) -> BlockPtr {
         (&b).into()
@@ -65,6 +119,10 @@ impl Blockchainblock for Block {
     fn parent_ptr(&self) -> Option<BlockPtr> {
         self.parent_ptr()
     }
+
+    fn data(&self) -> Result<serde_json::Value, serde_json::Error> {
+        serde_json::to_value(StoredBlock::try_from(self)?)
+    }
 }
 
 impl execution_outcome::Status {
@@ -76,3 +134,54 @@ impl execution_outcome::Status {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stored_data_round_trips() {
+        let block = Block {
+            author: "test.near".to_string(),
+            header: Some(BlockHeader {
+                height: 12,
+                prev_height: 10,
+                hash: Some(CryptoHash {
+                    bytes: vec![0x12; 32],
+                }),
+                prev_hash: Some(CryptoHash {
+                    bytes: vec![0x10; 32],
+                }),
+                timestamp_nanosec: 1_600_000_000_000_000_000,
+                ..Default::default()
+            }),
+            shards: vec![IndexerShard {
+                shard_id: 3,
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        let data = Blockchainblock::data(&block).unwrap();
+        let stored = Block::from_stored_data(data).unwrap();
+
+        assert_eq!(block, stored);
+        assert_eq!(
+            Some(BlockPtr::from((H256::from([0x10; 32]), 10u64))),
+            stored.parent_ptr()
+        );
+    }
+
+    #[test]
+    fn blocks_without_hash_can_not_be_stored() {
+        let block = Block {
+            header: Some(BlockHeader {
+                height: 12,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        assert!(Blockchainblock::data(&block).is_err());
+    }
+}
@@ -3,11 +3,11 @@ use graph::components::store::StoredDynamicDataSource;
 use graph::data::subgraph::DataSourceContext;
 use graph::prelude::SubgraphManifestValidationError;
 use graph::{
-    anyhow::{anyhow, Error},
+    anyhow::{anyhow, Context, Error},
     blockchain::{self, Blockchain},
     prelude::{
-        async_trait, info, BlockNumber, CheapClone, DataSourceTemplateInfo, Deserialize, Link,
-        LinkResolver, Logger,
+        async_trait, info, serde_json, BlockNumber, CheapClone, DataSourceTemplateInfo,
+        Deserialize, Entity, Link, LinkResolver, Logger,
     },
     semver,
 };
@@ -15,6 +15,7 @@ use std::collections::BTreeMap;
 use std::{convert::TryFrom, sync::Arc};
 
 use crate::chain::Chain;
+use crate::codec;
 use crate::trigger::NearTrigger;
 
 pub const NEAR_KIND: &str = "near";
@@ -57,16 +58,16 @@ impl blockchain::DataSource<Chain> for DataSource {
                 None => return Ok(None),
             },
 
-            // A receipt trigger matches if the receiver matches `source.account` and a receipt
-            // handler is present.
+            // A receipt trigger matches if the receiver matches `source.account` or
+            // `source.accounts` and a receipt handler whose filters accept the receipt is present.
             NearTrigger::Receipt(receipt) => {
-                if Some(&receipt.receipt.receiver_id) != self.source.account.as_ref() {
+                if !self.source.matches_account(&receipt.receipt.receiver_id) {
                     return Ok(None);
                 }
 
                 match self.handler_for_receipt() {
-                    Some(handler) => &handler.handler,
-                    None => return Ok(None),
+                    Some(handler) if handler.matches(&receipt.receipt) => &handler.handler,
+                    _ => return Ok(None),
                 }
             }
         };
@@ -85,6 +86,10 @@ impl blockchain::DataSource<Chain> for DataSource {
         &self.kind
     }
 
+    fn entities(&self) -> &[String] {
+        &self.mapping.entities
+    }
+
     fn network(&self) -> Option<&str> {
         self.network.as_ref().map(|s| s.as_str())
     }
@@ -122,17 +127,61 @@ impl blockchain::DataSource<Chain> for DataSource {
             && context == &other.context
     }
 
-    fn as_stored_dynamic_data_source(&self) -> StoredDynamicDataSource {
-        // FIXME (NEAR): Implement me!
-        todo!()
+    fn as_stored_dynamic_data_source(&self) -> Result<StoredDynamicDataSource, Error> {
+        let context = self
+            .context
+            .as_ref()
+            .as_ref()
+            .map(serde_json::to_string)
+            .transpose()
+            .with_context(|| {
+                format!("failed to serialize context of data source `{}`", self.name)
+            })?;
+        Ok(StoredDynamicDataSource {
+            name: self.name.to_owned(),
+            address: None,
+            account: self.source.account.clone(),
+            // NEAR data sources have no ABI
+            abi: String::new(),
+            start_block: self.source.start_block,
+            context,
+            creation_block: self.creation_block,
+        })
     }
 
     fn from_stored_dynamic_data_source(
-        _templates: &BTreeMap<&str, &DataSourceTemplate>,
-        _stored: StoredDynamicDataSource,
+        templates: &BTreeMap<&str, &DataSourceTemplate>,
+        stored: StoredDynamicDataSource,
     ) -> Result<Self, Error> {
-        // FIXME (NEAR): Implement me correctly
-        todo!()
+        let StoredDynamicDataSource {
+            name,
+            address: _,
+            account,
+            abi: _,
+            start_block,
+            context,
+            creation_block,
+        } = stored;
+        let template = templates
+            .get(name.as_str())
+            .ok_or_else(|| anyhow!("no template named `{}` was found", name))?;
+        let context = context
+            .map(|ctx| serde_json::from_str::<Entity>(&ctx))
+            .transpose()?;
+
+        Ok(DataSource {
+            kind: template.kind.to_string(),
+            network: template.network.as_ref().map(|s| s.to_string()),
+            name,
+            source: Source {
+                account,
+                accounts: None,
+                start_block,
+            },
+            mapping: template.mapping.clone(),
+            context: Arc::new(context),
+            creation_block,
+        })
     }
 
     fn validate(&self) -> Vec<Error> {
@@ -146,13 +195,33 @@ impl blockchain::DataSource<Chain> for DataSource {
             ))
         }
 
-        // Validate that there is a `source` address if there are receipt handlers
-        let no_source_address = self.address().is_none();
+        // Validate that there is a `source` account or accounts if there are receipt handlers
+        let no_source_account = self.source.account.is_none() && self.source.accounts.is_none();
         let has_receipt_handlers = !self.mapping.receipt_handlers.is_empty();
-        if no_source_address && has_receipt_handlers {
+        if no_source_account && has_receipt_handlers {
             errors.push(SubgraphManifestValidationError::SourceAddressRequired.into());
         };
 
+        if let Some(accounts) = &self.source.accounts {
+            if accounts.is_empty() {
+                errors.push(anyhow!(
+                    "data source has empty `source.accounts`, at least one account, prefix or suffix is required"
+                ));
+            }
+        }
+
+        for handler in &self.mapping.receipt_handlers {
+            if !handler.methods.is_empty()
+                && !handler.actions.is_empty()
+                && !handler.actions.contains(&ActionKind::FunctionCall)
+            {
+                errors.push(anyhow!(
+                    "receipt handler `{}` filters by `methods` but its `actions` do not include `FunctionCall`",
+                    handler.handler
+                ));
+            }
+        }
+
         // Validate that there are no more than one of both block handlers and receipt handlers
         if self.mapping.block_handlers.len() > 1 {
             errors.push(anyhow!("data source has duplicated block handlers"));
@@ -200,7 +269,7 @@ impl DataSource {
         self.mapping.block_handlers.first()
     }
 
-    fn handler_for_receipt(&self) -> Option<&ReceiptHandler> {
+    pub(crate) fn handler_for_receipt(&self) -> Option<&ReceiptHandler> {
         self.mapping.receipt_handlers.first()
     }
 }
@@ -242,39 +311,37 @@ impl blockchain::UnresolvedDataSource<Chain> for UnresolvedDataSource {
 impl TryFrom<DataSourceTemplateInfo<Chain>> for DataSource {
     type Error = Error;
 
-    fn try_from(_info: DataSourceTemplateInfo<Chain>) -> Result<Self, Error> {
-        Err(anyhow!("Near subgraphs do not support templates"))
-
-        // How this might be implemented if/when Near gets support for templates:
-        // let DataSourceTemplateInfo {
-        //     template,
-        //     params,
-        //     context,
-        //     creation_block,
-        // } = info;
-
-        // let account = params
-        //     .get(0)
-        //     .with_context(|| {
-        //         format!(
-        //             "Failed to create data source from template `{}`: account parameter is missing",
-        //             template.name
-        //         )
-        //     })?
-        //     .clone();
-
-        // Ok(DataSource {
-        //     kind: template.kind,
-        //     network: template.network,
-        //     name: template.name,
-        //     source: Source {
-        //         account,
-        //         start_block: 0,
-        //     },
-        //     mapping: template.mapping,
-        //     context: Arc::new(context),
-        //     creation_block: Some(creation_block),
-        // })
+    fn try_from(info: DataSourceTemplateInfo<Chain>) -> Result<Self, Error> {
+        let DataSourceTemplateInfo {
+            template,
+            params,
+            context,
+            creation_block,
+        } = info;
+
+        let account = params
+            .get(0)
+            .with_context(|| {
+                format!(
+                    "Failed to create data source from template `{}`: account parameter is missing",
+                    template.name
+                )
+            })?
+            .clone();
+
+        Ok(DataSource {
+            kind: template.kind,
+            network: template.network,
+            name: template.name,
+            source: Source {
+                account: Some(account),
+                accounts: None,
+                start_block: 0,
+            },
+            mapping: template.mapping,
+            context: Arc::new(context),
+            creation_block: Some(creation_block),
+        })
     }
 }
 
@@ -392,12 +459,280 @@ pub struct MappingBlockHandler {
 #[derive(Clone, Debug, Hash, Eq, PartialEq, Deserialize)]
 pub struct ReceiptHandler {
     handler: String,
+    /// Only receipts with an action of one of these kinds are passed to the handler. All
+    /// receipts are passed if this is empty.
+    #[serde(default)]
+    pub(crate) actions: Vec<ActionKind>,
+    /// Only receipts with a `FunctionCall` action for one of these methods are passed to the
+    /// handler. All receipts are passed if this is empty.
+    #[serde(default)]
+    pub(crate) methods: Vec<String>,
+}
+
+impl ReceiptHandler {
+    /// Whether the filters of this handler accept `receipt`. A receipt is accepted if one of
+    /// its actions passes both the `actions` and the `methods` filter.
+    pub(crate) fn matches(&self, receipt: &codec::Receipt) -> bool {
+        let receipt = match &receipt.receipt {
+            Some(codec::receipt::Receipt::Action(receipt)) => receipt,
+            _ => return false,
+        };
+
+        receipt
+            .actions
+            .iter()
+            .filter_map(|action| action.action.as_ref())
+            .any(|action| {
+                let kind_matches =
+                    self.actions.is_empty() || self.actions.contains(&ActionKind::of(action));
+                let method_matches = self.methods.is_empty()
+                    || matches!(
+                        action,
+                        codec::action::Action::FunctionCall(call)
+                            if self.methods.contains(&call.method_name)
+                    );
+                kind_matches && method_matches
+            })
+    }
+}
+
+/// The kinds of actions that a receipt can contain
+#[derive(Clone, Copy, Debug, Hash, Eq, PartialEq, Deserialize)]
+pub enum ActionKind {
+    CreateAccount,
+    DeployContract,
+    FunctionCall,
+    Transfer,
+    Stake,
+    AddKey,
+    DeleteKey,
+    DeleteAccount,
+}
+
+impl ActionKind {
+    fn of(action: &codec::action::Action) -> Self {
+        use codec::action::Action::*;
+
+        match action {
+            CreateAccount(_) => ActionKind::CreateAccount,
+            DeployContract(_) => ActionKind::DeployContract,
+            FunctionCall(_) => ActionKind::FunctionCall,
+            Transfer(_) => ActionKind::Transfer,
+            Stake(_) => ActionKind::Stake,
+            AddKey(_) => ActionKind::AddKey,
+            DeleteKey(_) => ActionKind::DeleteKey,
+            DeleteAccount(_) => ActionKind::DeleteAccount,
+        }
+    }
 }
 
 #[derive(Clone, Debug, Hash, Eq, PartialEq, Deserialize)]
 pub(crate) struct Source {
-    // A data source that does not have an account can only have block handlers.
+    // A data source that does not have an account or accounts can only have block handlers.
     pub(crate) account: Option<String>,
+    #[serde(default)]
+    pub(crate) accounts: Option<Accounts>,
     #[serde(rename = "startBlock", default)]
     pub(crate) start_block: BlockNumber,
 }
+
+impl Source {
+    /// Whether receipts for `account` are meant for this data source
+    pub(crate) fn matches_account(&self, account: &str) -> bool {
+        self.account.as_deref() == Some(account)
+            || self
+                .accounts
+                .as_ref()
+                .map_or(false, |accounts| accounts.matches(account))
+    }
+}
+
+/// The accounts a data source is interested in besides `source.account`
+#[derive(Clone, Debug, Default, Hash, Eq, PartialEq, Deserialize)]
+pub(crate) struct Accounts {
+    #[serde(default)]
+    pub(crate) exact: Vec<String>,
+    #[serde(default)]
+    pub(crate) prefixes: Vec<String>,
+    #[serde(default)]
+    pub(crate) suffixes: Vec<String>,
+}
+
+impl Accounts {
+    fn is_empty(&self) -> bool {
+        self.exact.is_empty() && !self.has_partial()
+    }
+
+    pub(crate) fn has_partial(&self) -> bool {
+        !self.prefixes.is_empty() || !self.suffixes.is_empty()
+    }
+
+    pub(crate) fn matches(&self, account: &str) -> bool {
+        self.exact.iter().any(|exact| exact == account) || self.matches_partial(account)
+    }
+
+    /// Whether `account` starts with one of the `prefixes` and ends with one of the `suffixes`.
+    /// An empty list of prefixes or suffixes does not restrict the account, but no account
+    /// matches if both are empty.
+    pub(crate) fn matches_partial(&self, account: &str) -> bool {
+        self.has_partial()
+            && (self.prefixes.is_empty()
+                || self
+                    .prefixes
+                    .iter()
+                    .any(|prefix| account.starts_with(prefix.as_str())))
+            && (self.suffixes.is_empty()
+                || self
+                    .suffixes
+                    .iter()
+                    .any(|suffix| account.ends_with(suffix.as_str())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use graph::blockchain::DataSource as _;
+    use graph::data::store::Value;
+
+    fn template() -> DataSourceTemplate {
+        DataSourceTemplate {
+            kind: NEAR_KIND.to_string(),
+            network: Some("near-mainnet".to_string()),
+            name: "Pool".to_string(),
+            mapping: Mapping {
+                api_version: semver::Version::new(0, 0, 5),
+                language: "wasm/assemblyscript".to_string(),
+                entities: vec!["Swap".to_string()],
+                block_handlers: vec![],
+                receipt_handlers: vec![ReceiptHandler {
+                    handler: "handleReceipt".to_string(),
+                    actions: vec![],
+                    methods: vec![],
+                }],
+                runtime: Arc::new(vec![]),
+                link: Link {
+                    link: "mapping.wasm".to_string(),
+                },
+            },
+        }
+    }
+
+    #[test]
+    fn data_source_from_template_round_trips() {
+        let mut context = DataSourceContext::new();
+        context.set("factory", Value::from("factory.near"));
+
+        let data_source = DataSource::try_from(DataSourceTemplateInfo {
+            template: template(),
+            params: vec!["pool-1.factory.near".to_string()],
+            context: Some(context),
+            creation_block: 17,
+        })
+        .unwrap();
+        assert_eq!(
+            Some("pool-1.factory.near".as_bytes()),
+            data_source.address()
+        );
+        assert!(data_source.validate().is_empty());
+
+        let template = template();
+        let templates: BTreeMap<_, _> = vec![(template.name.as_str(), &template)]
+            .into_iter()
+            .collect();
+        let stored = data_source.as_stored_dynamic_data_source().unwrap();
+        let loaded = DataSource::from_stored_dynamic_data_source(&templates, stored).unwrap();
+
+        assert!(loaded.is_duplicate_of(&data_source));
+        assert_eq!(Some(17), loaded.creation_block);
+        assert_eq!(data_source.context, loaded.context);
+    }
+
+    fn receipt(receiver: &str, actions: Vec<codec::action::Action>) -> codec::Receipt {
+        codec::Receipt {
+            receiver_id: receiver.to_string(),
+            receipt: Some(codec::receipt::Receipt::Action(codec::ReceiptAction {
+                actions: actions
+                    .into_iter()
+                    .map(|action| codec::Action {
+                        action: Some(action),
+                    })
+                    .collect(),
+                ..Default::default()
+            })),
+            ..Default::default()
+        }
+    }
+
+    fn function_call(method: &str) -> codec::action::Action {
+        codec::action::Action::FunctionCall(codec::FunctionCallAction {
+            method_name: method.to_string(),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn accounts_match_exact_prefixes_and_suffixes() {
+        let accounts = Accounts {
+            exact: vec!["token.near".to_string()],
+            prefixes: vec!["pool".to_string()],
+            suffixes: vec![".factory.near".to_string()],
+        };
+        assert!(accounts.matches("token.near"));
+        assert!(accounts.matches("pool-1.factory.near"));
+        assert!(!accounts.matches("pool-1.near"));
+        assert!(!accounts.matches("vault.factory.near"));
+
+        let accounts = Accounts {
+            suffixes: vec![".near".to_string()],
+            ..Default::default()
+        };
+        assert!(accounts.matches("app.near"));
+        assert!(!accounts.matches("app.testnet"));
+
+        assert!(!Accounts::default().matches("app.near"));
+    }
+
+    #[test]
+    fn receipt_handler_filters_actions_and_methods() {
+        let transfer = codec::action::Action::Transfer(codec::TransferAction { deposit: None });
+
+        let handler = ReceiptHandler {
+            handler: "handleReceipt".to_string(),
+            actions: vec![],
+            methods: vec![],
+        };
+        assert!(handler.matches(&receipt("app.near", vec![transfer.clone()])));
+
+        let handler = ReceiptHandler {
+            actions: vec![ActionKind::Transfer],
+            ..handler
+        };
+        assert!(handler.matches(&receipt("app.near", vec![transfer.clone()])));
+        assert!(!handler.matches(&receipt("app.near", vec![function_call("swap")])));
+
+        let handler = ReceiptHandler {
+            actions: vec![],
+            methods: vec!["swap".to_string()],
+            ..handler
+        };
+        assert!(handler.matches(&receipt(
+            "app.near",
+            vec![transfer.clone(), function_call("swap")]
+        )));
+        assert!(!handler.matches(&receipt("app.near", vec![function_call("deposit")])));
+        assert!(!handler.matches(&receipt("app.near", vec![transfer])));
+    }
+
+    #[test]
+    fn data_source_from_template_requires_account() {
+        let result = DataSource::try_from(DataSourceTemplateInfo {
+            template: template(),
+            params: vec![],
+            context: None,
+            creation_block: 17,
+        });
+        assert!(result.is_err());
+    }
+}
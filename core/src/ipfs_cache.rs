@@ -0,0 +1,494 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use graph::prelude::*;
+
+use crate::link_resolver::read_u64_from_env;
+
+/// Environment variable for the directory in which IPFS files are cached.
+/// The disk cache is only used if this is set
+const IPFS_DISK_CACHE_DIR_VAR: &'static str = "GRAPH_IPFS_DISK_CACHE_DIR";
+
+/// Environment variable for the maximum number of bytes of unpinned files
+/// in the disk cache
+const IPFS_DISK_CACHE_SIZE_VAR: &'static str = "GRAPH_IPFS_DISK_CACHE_SIZE";
+
+/// The default size limit for the disk cache is 1GiB.
+const DEFAULT_IPFS_DISK_CACHE_SIZE: u64 = 1024 * 1024 * 1024;
+
+/// Subdirectories of the cache directory for files that can be evicted,
+/// for files that are pinned and never evicted, and for files that are in
+/// the process of being written
+const FILES_DIR: &'static str = "files";
+const PINNED_DIR: &'static str = "pinned";
+const TMP_DIR: &'static str = "tmp";
+
+/// Each cached file is stored in its own directory, with the file contents
+/// in `DATA_FILE` and their hex-encoded keccak256 hash in `HASH_FILE`
+const DATA_FILE: &'static str = "data";
+const HASH_FILE: &'static str = "keccak256";
+
+/// Information about a file in the cache
+#[derive(Clone, Debug)]
+pub struct IpfsCacheEntry {
+    pub path: String,
+    pub size: u64,
+    pub pinned: bool,
+}
+
+struct Entry {
+    size: u64,
+    pinned: bool,
+    /// The value of `State.clock` when the file was last used
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct State {
+    /// All cached files, keyed by the name of the directory they are
+    /// stored in
+    entries: HashMap<String, Entry>,
+    /// The total size of all unpinned files
+    size: u64,
+    clock: u64,
+}
+
+/// A cache of IPFS files in a local directory. Files are addressed by their
+/// IPFS path, and since the content for a path never changes, entries
+/// never need to be invalidated. Unpinned files are evicted in least
+/// recently used order once their total size exceeds `max_size`; pinned
+/// files are never evicted and do not count towards that limit.
+///
+/// New files are first written into a temporary directory and then moved
+/// into place so that a crash in the middle of a write can not leave a
+/// partial file behind
+pub struct IpfsDiskCache {
+    logger: Logger,
+    dir: PathBuf,
+    max_size: u64,
+    state: Mutex<State>,
+}
+
+impl IpfsDiskCache {
+    /// Create a disk cache if `GRAPH_IPFS_DISK_CACHE_DIR` is set
+    pub fn from_env(logger: &Logger) -> Result<Option<Self>, Error> {
+        match env::var_os(IPFS_DISK_CACHE_DIR_VAR) {
+            None => Ok(None),
+            Some(dir) => {
+                let max_size = read_u64_from_env(IPFS_DISK_CACHE_SIZE_VAR)
+                    .unwrap_or(DEFAULT_IPFS_DISK_CACHE_SIZE);
+                Self::new(logger, dir, max_size).map(Some)
+            }
+        }
+    }
+
+    /// Open the cache in `dir`, creating the directory if needed, and load
+    /// the list of files that were cached by previous runs
+    pub fn new(logger: &Logger, dir: impl Into<PathBuf>, max_size: u64) -> Result<Self, Error> {
+        let dir = dir.into();
+
+        // Anything in the temporary directory is left over from writes
+        // that never finished
+        let tmp = dir.join(TMP_DIR);
+        if tmp.exists() {
+            fs::remove_dir_all(&tmp)
+                .with_context(|| format!("failed to clean up {}", tmp.display()))?;
+        }
+        for subdir in &[FILES_DIR, PINNED_DIR, TMP_DIR] {
+            let subdir = dir.join(subdir);
+            fs::create_dir_all(&subdir)
+                .with_context(|| format!("failed to create {}", subdir.display()))?;
+        }
+
+        let mut found = Vec::new();
+        for (subdir, pinned) in &[(FILES_DIR, false), (PINNED_DIR, true)] {
+            for dirent in fs::read_dir(dir.join(subdir))? {
+                let dirent = dirent?;
+                let name = match dirent.file_name().into_string() {
+                    Ok(name) => name,
+                    Err(_) => continue,
+                };
+                let meta = match fs::metadata(dirent.path().join(DATA_FILE)) {
+                    Ok(meta) => meta,
+                    Err(_) => continue,
+                };
+                let modified = meta.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+                found.push((modified, name, meta.len(), *pinned));
+            }
+        }
+
+        // Files that were written more recently are considered more
+        // recently used
+        found.sort();
+        let mut state = State::default();
+        for (_, name, size, pinned) in found {
+            state.clock += 1;
+            if !pinned {
+                state.size += size;
+            }
+            let entry = Entry {
+                size,
+                pinned,
+                last_used: state.clock,
+            };
+            state.entries.insert(name, entry);
+        }
+
+        info!(logger, "Using IPFS disk cache";
+              "dir" => dir.display().to_string(),
+              "files" => state.entries.len(),
+              "size" => state.size,
+              "max_size" => max_size);
+
+        let cache = IpfsDiskCache {
+            logger: logger.new(o!("component" => "IpfsDiskCache")),
+            dir,
+            max_size,
+            state: Mutex::new(state),
+        };
+        // The size limit might have been lowered since the last run
+        cache.evict(&mut cache.state.lock().unwrap(), 0);
+        Ok(cache)
+    }
+
+    pub fn max_size(&self) -> u64 {
+        self.max_size
+    }
+
+    /// Return the contents of the file for `path` if it is in the cache
+    pub fn get(&self, path: &str) -> Option<Vec<u8>> {
+        let name = file_name(path)?;
+        let pinned = {
+            let mut state = self.state.lock().unwrap();
+            state.clock += 1;
+            let clock = state.clock;
+            let entry = state.entries.get_mut(&name)?;
+            entry.last_used = clock;
+            entry.pinned
+        };
+
+        match fs::read(self.entry_dir(&name, pinned).join(DATA_FILE)) {
+            Ok(data) => Some(data),
+            Err(e) => {
+                warn!(self.logger, "Failed to read file from IPFS disk cache";
+                      "path" => path,
+                      "error" => e.to_string());
+                self.forget(&name);
+                None
+            }
+        }
+    }
+
+    /// Add the `data` for `path` to the cache. Unpinned files that are
+    /// larger than the cache are silently skipped. If the file is already
+    /// cached and `pin` is `true`, it gets pinned
+    pub fn insert(&self, path: &str, data: &[u8], pin: bool) -> Result<(), Error> {
+        let name = file_name(path).ok_or_else(|| anyhow!("can not cache IPFS path `{}`", path))?;
+        let size = data.len() as u64;
+
+        if self.state.lock().unwrap().entries.contains_key(&name) {
+            if pin {
+                self.pin(path)?;
+            }
+            return Ok(());
+        }
+        if !pin && size > self.max_size {
+            return Ok(());
+        }
+
+        let tmp = self
+            .dir
+            .join(TMP_DIR)
+            .join(format!("{}-{}", name, rand::random::<u64>()));
+        if let Err(e) = write_entry(&tmp, data) {
+            fs::remove_dir_all(&tmp).ok();
+            return Err(e.into());
+        }
+
+        let mut state = self.state.lock().unwrap();
+        if state.entries.contains_key(&name) {
+            // Somebody else cached the file while we were writing it
+            fs::remove_dir_all(&tmp)?;
+            drop(state);
+            if pin {
+                self.pin(path)?;
+            }
+            return Ok(());
+        }
+        if !pin {
+            self.evict(&mut state, size);
+        }
+        if let Err(e) = fs::rename(&tmp, self.entry_dir(&name, pin)) {
+            fs::remove_dir_all(&tmp).ok();
+            return Err(e.into());
+        }
+        state.clock += 1;
+        if !pin {
+            state.size += size;
+        }
+        let entry = Entry {
+            size,
+            pinned: pin,
+            last_used: state.clock,
+        };
+        state.entries.insert(name, entry);
+        Ok(())
+    }
+
+    /// Make sure the file for `path` is never evicted. Return `false` if the
+    /// file is not in the cache
+    pub fn pin(&self, path: &str) -> Result<bool, Error> {
+        self.set_pinned(path, true)
+    }
+
+    /// Make the file for `path` subject to eviction again. Return `false` if
+    /// the file is not in the cache
+    pub fn unpin(&self, path: &str) -> Result<bool, Error> {
+        self.set_pinned(path, false)
+    }
+
+    /// Remove the file for `path` from the cache. Return `false` if the file
+    /// was not in the cache
+    pub fn remove(&self, path: &str) -> Result<bool, Error> {
+        let name = match file_name(path) {
+            Some(name) => name,
+            None => return Ok(false),
+        };
+        let mut state = self.state.lock().unwrap();
+        match state.entries.remove(&name) {
+            None => Ok(false),
+            Some(entry) => {
+                if !entry.pinned {
+                    state.size -= entry.size;
+                }
+                fs::remove_dir_all(self.entry_dir(&name, entry.pinned))?;
+                Ok(true)
+            }
+        }
+    }
+
+    /// Check that the contents of the file for `path` still have the hash
+    /// that was recorded when the file was added to the cache
+    pub fn verify(&self, path: &str) -> Result<bool, Error> {
+        let name = file_name(path).ok_or_else(|| anyhow!("invalid IPFS path `{}`", path))?;
+        let pinned = match self.state.lock().unwrap().entries.get(&name) {
+            Some(entry) => entry.pinned,
+            None => return Err(anyhow!("file `{}` is not in the cache", path)),
+        };
+        let dir = self.entry_dir(&name, pinned);
+        let data = fs::read(dir.join(DATA_FILE))?;
+        let hash = fs::read_to_string(dir.join(HASH_FILE))?;
+        Ok(hash.trim() == hex::encode(tiny_keccak::keccak256(&data)))
+    }
+
+    /// List all files in the cache, ordered by their path
+    pub fn entries(&self) -> Vec<IpfsCacheEntry> {
+        let state = self.state.lock().unwrap();
+        let mut entries: Vec<_> = state
+            .entries
+            .iter()
+            .map(|(name, entry)| IpfsCacheEntry {
+                path: name.replace('+', "/"),
+                size: entry.size,
+                pinned: entry.pinned,
+            })
+            .collect();
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+        entries
+    }
+
+    fn set_pinned(&self, path: &str, pinned: bool) -> Result<bool, Error> {
+        let name = match file_name(path) {
+            Some(name) => name,
+            None => return Ok(false),
+        };
+        let mut state = self.state.lock().unwrap();
+        let size = match state.entries.get(&name) {
+            None => return Ok(false),
+            Some(entry) if entry.pinned == pinned => return Ok(true),
+            Some(entry) => entry.size,
+        };
+        fs::rename(
+            self.entry_dir(&name, !pinned),
+            self.entry_dir(&name, pinned),
+        )?;
+        if let Some(entry) = state.entries.get_mut(&name) {
+            entry.pinned = pinned;
+        }
+        if pinned {
+            state.size -= size;
+        } else {
+            state.size += size;
+            self.evict(&mut state, 0);
+        }
+        Ok(true)
+    }
+
+    /// Drop the entry `name` after we found that it can not be read
+    fn forget(&self, name: &str) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(entry) = state.entries.remove(name) {
+            if !entry.pinned {
+                state.size -= entry.size;
+            }
+            fs::remove_dir_all(self.entry_dir(name, entry.pinned)).ok();
+        }
+    }
+
+    /// Evict the least recently used unpinned files until another `extra`
+    /// bytes fit into the cache
+    fn evict(&self, state: &mut State, extra: u64) {
+        while state.size + extra > self.max_size {
+            let victim = state
+                .entries
+                .iter()
+                .filter(|(_, entry)| !entry.pinned)
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(name, _)| name.clone());
+            let name = match victim {
+                Some(name) => name,
+                None => return,
+            };
+            if let Some(entry) = state.entries.remove(&name) {
+                state.size -= entry.size;
+                debug!(self.logger, "Evicting file from IPFS disk cache";
+                       "path" => name.replace('+', "/"),
+                       "size" => entry.size);
+                if let Err(e) = fs::remove_dir_all(self.entry_dir(&name, false)) {
+                    warn!(self.logger, "Failed to remove file from IPFS disk cache";
+                          "path" => name.replace('+', "/"),
+                          "error" => e.to_string());
+                }
+            }
+        }
+    }
+
+    fn entry_dir(&self, name: &str, pinned: bool) -> PathBuf {
+        let subdir = if pinned { PINNED_DIR } else { FILES_DIR };
+        self.dir.join(subdir).join(name)
+    }
+}
+
+fn write_entry(dir: &Path, data: &[u8]) -> std::io::Result<()> {
+    fs::create_dir(dir)?;
+    fs::write(dir.join(DATA_FILE), data)?;
+    fs::write(
+        dir.join(HASH_FILE),
+        hex::encode(tiny_keccak::keccak256(data)),
+    )
+}
+
+/// Turn an IPFS path like `Qm.../file.json` into the name of the directory
+/// in which we store it. Paths that could be used to escape from the cache
+/// directory are rejected and never cached
+fn file_name(path: &str) -> Option<String> {
+    let valid_segment = |segment: &str| {
+        !segment.is_empty()
+            && segment != "."
+            && segment != ".."
+            && segment
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == '_')
+    };
+    if path.split('/').all(valid_segment) {
+        Some(path.replace('/', "+"))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!(
+            "graph-ipfs-disk-cache-{}-{}",
+            name,
+            rand::random::<u64>()
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn logger() -> Logger {
+        Logger::root(slog::Discard, o!())
+    }
+
+    #[test]
+    fn evicts_least_recently_used_files() {
+        let dir = test_dir("evict");
+        let cache = IpfsDiskCache::new(&logger(), &dir, 10).unwrap();
+
+        cache.insert("QmA", b"aaaa", false).unwrap();
+        cache.insert("QmB", b"bbbb", false).unwrap();
+        cache.insert("QmPinned", b"pppppppppppp", true).unwrap();
+        // Using QmA makes QmB the least recently used file
+        assert_eq!(Some(b"aaaa".to_vec()), cache.get("QmA"));
+        cache.insert("QmC", b"cccc", false).unwrap();
+
+        assert_eq!(None, cache.get("QmB"));
+        assert_eq!(Some(b"aaaa".to_vec()), cache.get("QmA"));
+        assert_eq!(Some(b"cccc".to_vec()), cache.get("QmC"));
+        assert_eq!(Some(b"pppppppppppp".to_vec()), cache.get("QmPinned"));
+
+        // Files that are too big are not cached unless they are pinned
+        cache.insert("QmBig", b"bbbbbbbbbbbb", false).unwrap();
+        assert_eq!(None, cache.get("QmBig"));
+
+        // The cache survives a restart
+        drop(cache);
+        let cache = IpfsDiskCache::new(&logger(), &dir, 10).unwrap();
+        let paths: Vec<_> = cache
+            .entries()
+            .into_iter()
+            .map(|entry| (entry.path, entry.pinned))
+            .collect();
+        assert_eq!(
+            vec![
+                ("QmA".to_owned(), false),
+                ("QmC".to_owned(), false),
+                ("QmPinned".to_owned(), true)
+            ],
+            paths
+        );
+        assert_eq!(Some(b"cccc".to_vec()), cache.get("QmC"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn pins_and_verifies_files() {
+        let dir = test_dir("pin");
+        let cache = IpfsDiskCache::new(&logger(), &dir, 8).unwrap();
+
+        cache.insert("QmA/file.json", b"aaaa", false).unwrap();
+        assert!(cache.pin("QmA/file.json").unwrap());
+        assert!(!cache.pin("QmMissing").unwrap());
+        cache.insert("QmB", b"bbbb", false).unwrap();
+        cache.insert("QmC", b"cccc", false).unwrap();
+        assert_eq!(Some(b"bbbb".to_vec()), cache.get("QmB"));
+
+        // Unpinning makes the cache too big and evicts the least recently
+        // used file
+        assert!(cache.unpin("QmA/file.json").unwrap());
+        assert_eq!(None, cache.get("QmA/file.json"));
+
+        assert!(cache.verify("QmB").unwrap());
+        fs::write(dir.join(FILES_DIR).join("QmB").join(DATA_FILE), b"oops").unwrap();
+        assert!(!cache.verify("QmB").unwrap());
+        assert!(cache.remove("QmB").unwrap());
+        assert_eq!(None, cache.get("QmB"));
+
+        // Paths that could escape the cache directory are never cached
+        assert!(cache.insert("../QmA", b"aaaa", false).is_err());
+        assert!(cache.insert("QmA/../..", b"aaaa", false).is_err());
+        assert_eq!(None, cache.get("../QmA"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
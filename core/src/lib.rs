@@ -1,7 +1,9 @@
+mod ipfs_cache;
 mod link_resolver;
 mod metrics;
 mod subgraph;
 
+pub use crate::ipfs_cache::{IpfsCacheEntry, IpfsDiskCache};
 pub use crate::link_resolver::LinkResolver;
 pub use crate::metrics::MetricsRegistry;
 pub use crate::subgraph::{SubgraphAssignmentProvider, SubgraphInstanceManager, SubgraphRegistrar};
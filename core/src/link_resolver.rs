@@ -18,6 +18,8 @@ use graph::{
     prelude::{LinkResolver as LinkResolverTrait, *},
 };
 
+use crate::ipfs_cache::IpfsDiskCache;
+
 /// Environment variable for limiting the `ipfs.map` file size limit.
 const MAX_IPFS_MAP_FILE_SIZE_VAR: &'static str = "GRAPH_MAX_IPFS_MAP_FILE_SIZE";
 
@@ -42,7 +44,7 @@ lazy_static! {
     );
 }
 
-fn read_u64_from_env(name: &str) -> Option<u64> {
+pub(crate) fn read_u64_from_env(name: &str) -> Option<u64> {
     env::var(name).ok().map(|s| {
         u64::from_str(&s).unwrap_or_else(|_| {
             panic!(
@@ -125,19 +127,15 @@ async fn select_fastest_client_with_stat(
     }))
 }
 
-// Returns an error if the stat is bigger than `max_file_bytes`
-fn restrict_file_size(
-    path: &str,
-    stat: &ObjectStatResponse,
-    max_file_bytes: &Option<u64>,
-) -> Result<(), Error> {
+// Returns an error if `size` is bigger than `max_file_bytes`
+fn restrict_file_size(path: &str, size: u64, max_file_bytes: &Option<u64>) -> Result<(), Error> {
     if let Some(max_file_bytes) = max_file_bytes {
-        if stat.cumulative_size > *max_file_bytes {
+        if size > *max_file_bytes {
             return Err(anyhow!(
                 "IPFS file {} is too large. It can be at most {} bytes but is {} bytes",
                 path,
                 max_file_bytes,
-                stat.cumulative_size
+                size
             ));
         }
     }
@@ -148,6 +146,7 @@ fn restrict_file_size(
 pub struct LinkResolver {
     clients: Arc<Vec<Arc<IpfsClient>>>,
     cache: Arc<Mutex<LruCache<String, Vec<u8>>>>,
+    disk_cache: Option<Arc<IpfsDiskCache>>,
     timeout: Duration,
     retry: bool,
 }
@@ -157,6 +156,7 @@ impl CheapClone for LinkResolver {
         LinkResolver {
             clients: self.clients.cheap_clone(),
             cache: self.cache.cheap_clone(),
+            disk_cache: self.disk_cache.cheap_clone(),
             timeout: self.timeout,
             retry: self.retry,
         }
@@ -176,12 +176,59 @@ impl From<Vec<IpfsClient>> for LinkResolver {
             cache: Arc::new(Mutex::new(LruCache::with_capacity(
                 *MAX_IPFS_CACHE_SIZE as usize,
             ))),
+            disk_cache: None,
             timeout: *IPFS_TIMEOUT,
             retry: false,
         }
     }
 }
 
+impl LinkResolver {
+    /// Consult `disk_cache` before going to IPFS, and store files fetched
+    /// from IPFS in it. Since the disk cache survives restarts, subgraphs
+    /// whose files are in it can be started even when IPFS is unavailable
+    pub fn with_disk_cache(mut self, disk_cache: Arc<IpfsDiskCache>) -> Self {
+        self.disk_cache = Some(disk_cache);
+        self
+    }
+
+    async fn disk_cache_get(&self, logger: &Logger, path: &str) -> Option<Vec<u8>> {
+        let disk_cache = self.disk_cache.as_ref()?.cheap_clone();
+        let key = path.to_owned();
+        let data = graph::spawn_blocking_allow_panic(move || disk_cache.get(&key))
+            .await
+            .ok()
+            .flatten();
+        match &data {
+            Some(_) => trace!(logger, "IPFS disk cache hit"; "hash" => path),
+            None => trace!(logger, "IPFS disk cache miss"; "hash" => path),
+        }
+        data
+    }
+
+    /// Store `data` in the disk cache if there is one. Failing to do that
+    /// is not an error since we have the data in hand
+    async fn disk_cache_insert(&self, logger: &Logger, path: &str, data: Vec<u8>) -> Vec<u8> {
+        let disk_cache = match &self.disk_cache {
+            Some(disk_cache) => disk_cache.cheap_clone(),
+            None => return data,
+        };
+        let key = path.to_owned();
+        let (data, res) = graph::spawn_blocking_allow_panic(move || {
+            let res = disk_cache.insert(&key, &data, false);
+            (data, res)
+        })
+        .await
+        .expect("writing to the IPFS disk cache does not panic");
+        if let Err(e) = res {
+            warn!(logger, "Failed to add file to IPFS disk cache";
+                  "path" => path,
+                  "error" => e.to_string());
+        }
+        data
+    }
+}
+
 #[async_trait]
 impl LinkResolverTrait for LinkResolver {
     fn with_timeout(mut self, timeout: Duration) -> Self {
@@ -205,6 +252,25 @@ impl LinkResolverTrait for LinkResolver {
         }
         trace!(logger, "IPFS cache miss"; "hash" => &path);
 
+        // FIXME: Having an env variable here is a problem for consensus.
+        // Index Nodes should not disagree on whether the file should be read.
+        let max_file_size: Option<u64> = read_u64_from_env(MAX_IPFS_FILE_SIZE_VAR);
+
+        // The disk cache can hold files that are bigger than what we allow
+        // here, for example files for `ipfs.map` or files that were added
+        // with `graphman`, and the limit might have been lowered since a
+        // file was cached
+        if let Some(data) = self.disk_cache_get(logger, &path).await {
+            restrict_file_size(&path, data.len() as u64, &max_file_size)?;
+            if data.len() <= *MAX_IPFS_CACHE_FILE_SIZE as usize {
+                let mut cache = self.cache.lock().unwrap();
+                if !cache.contains_key(&path) {
+                    cache.insert(path.to_owned(), data.clone());
+                }
+            }
+            return Ok(data);
+        }
+
         let (stat, client) = select_fastest_client_with_stat(
             self.clients.cheap_clone(),
             logger.cheap_clone(),
@@ -214,21 +280,18 @@ impl LinkResolverTrait for LinkResolver {
         )
         .await?;
 
-        // FIXME: Having an env variable here is a problem for consensus.
-        // Index Nodes should not disagree on whether the file should be read.
-        let max_file_size: Option<u64> = read_u64_from_env(MAX_IPFS_FILE_SIZE_VAR);
-        restrict_file_size(&path, &stat, &max_file_size)?;
+        restrict_file_size(&path, stat.cumulative_size, &max_file_size)?;
 
-        let path = path.clone();
+        let retry_path = path.clone();
         let this = self.clone();
         let timeout = self.timeout;
-        let logger = logger.clone();
-        let data = retry_policy(self.retry, "ipfs.cat", &logger)
+        let retry_logger = logger.clone();
+        let data = retry_policy(self.retry, "ipfs.cat", logger)
             .run(move || {
-                let path = path.clone();
+                let path = retry_path.clone();
                 let client = client.clone();
                 let this = this.clone();
-                let logger = logger.clone();
+                let logger = retry_logger.clone();
                 async move {
                     let data = client.cat_all(path.clone(), timeout).await?.to_vec();
 
@@ -249,13 +312,26 @@ impl LinkResolverTrait for LinkResolver {
             })
             .await?;
 
-        Ok(data)
+        Ok(self.disk_cache_insert(logger, &path, data).await)
     }
 
     async fn json_stream(&self, logger: &Logger, link: &Link) -> Result<JsonValueStream, Error> {
         // Discard the `/ipfs/` prefix (if present) to get the hash.
         let path = link.link.trim_start_matches("/ipfs/");
 
+        let max_file_size =
+            read_u64_from_env(MAX_IPFS_MAP_FILE_SIZE_VAR).or(Some(DEFAULT_MAX_IPFS_MAP_FILE_SIZE));
+
+        if let Some(data) = self.disk_cache_get(logger, path).await {
+            restrict_file_size(path, data.len() as u64, &max_file_size)?;
+            return Ok(json_value_stream(futures03::stream::iter(vec![Ok::<
+                _,
+                Error,
+            >(
+                data
+            )])));
+        }
+
         let (stat, client) = select_fastest_client_with_stat(
             self.clients.cheap_clone(),
             logger.cheap_clone(),
@@ -265,71 +341,99 @@ impl LinkResolverTrait for LinkResolver {
         )
         .await?;
 
-        let max_file_size =
-            read_u64_from_env(MAX_IPFS_MAP_FILE_SIZE_VAR).or(Some(DEFAULT_MAX_IPFS_MAP_FILE_SIZE));
-        restrict_file_size(path, &stat, &max_file_size)?;
-
-        let mut stream = client.cat(path.to_string()).await?.fuse().boxed().compat();
-
-        let mut buf = BytesMut::with_capacity(1024);
-
-        // Count the number of lines we've already successfully deserialized.
-        // We need that to adjust the line number in error messages from serde_json
-        // to translate from line numbers in the snippet we are deserializing
-        // to the line number in the overall file
-        let mut count = 0;
-
-        let stream: JsonValueStream = Box::pin(
-            poll_fn(move || -> Poll<Option<JsonStreamValue>, Error> {
-                loop {
-                    if let Some(offset) = buf.iter().position(|b| *b == b'\n') {
-                        let line_bytes = buf.split_to(offset + 1);
-                        count += 1;
-                        if line_bytes.len() > 1 {
-                            let line = std::str::from_utf8(&line_bytes)?;
-                            let res = match serde_json::from_str::<Value>(line) {
-                                Ok(v) => Ok(Async::Ready(Some(JsonStreamValue {
-                                    value: v,
-                                    line: count,
-                                }))),
-                                Err(e) => {
-                                    // Adjust the line number in the serde error. This
-                                    // is fun because we can only get at the full error
-                                    // message, and not the error message without line number
-                                    let msg = e.to_string();
-                                    let msg = msg.split(" at line ").next().unwrap();
-                                    Err(anyhow!(
-                                        "{} at line {} column {}: '{}'",
-                                        msg,
-                                        e.line() + count - 1,
-                                        e.column(),
-                                        line
-                                    ))
-                                }
-                            };
-                            return res;
-                        }
-                    } else {
-                        // We only get here if there is no complete line in buf, and
-                        // it is therefore ok to immediately pass an Async::NotReady
-                        // from stream through.
-                        // If we get a None from poll, but still have something in buf,
-                        // that means the input was not terminated with a newline. We
-                        // add that so that the last line gets picked up in the next
-                        // run through the loop.
-                        match try_ready!(stream.poll().map_err(|e| anyhow::anyhow!("{}", e))) {
-                            Some(b) => buf.extend_from_slice(&b),
-                            None if buf.len() > 0 => buf.extend_from_slice(&[b'\n']),
-                            None => return Ok(Async::Ready(None)),
-                        }
+        restrict_file_size(path, stat.cumulative_size, &max_file_size)?;
+
+        // When there is a disk cache, fetch the whole file so that we can
+        // store it, unless it is too big for the cache anyway
+        match &self.disk_cache {
+            Some(disk_cache) if stat.cumulative_size <= disk_cache.max_size() => {
+                let data = client
+                    .cat(path.to_string())
+                    .await?
+                    .try_fold(Vec::new(), |mut data, chunk| async move {
+                        data.extend_from_slice(&chunk);
+                        Ok(data)
+                    })
+                    .await?;
+                let data = self.disk_cache_insert(logger, path, data).await;
+                Ok(json_value_stream(futures03::stream::iter(vec![Ok::<
+                    _,
+                    Error,
+                >(
+                    data
+                )])))
+            }
+            _ => Ok(json_value_stream(client.cat(path.to_string()).await?)),
+        }
+    }
+}
+
+/// Split the bytes coming from `stream` into lines and parse each line as
+/// a JSON value
+fn json_value_stream<S, B, E>(stream: S) -> JsonValueStream
+where
+    S: futures03::Stream<Item = Result<B, E>> + Send + 'static,
+    B: AsRef<[u8]> + Send + 'static,
+    E: std::fmt::Display + Send + 'static,
+{
+    let mut stream = stream.fuse().boxed().compat();
+
+    let mut buf = BytesMut::with_capacity(1024);
+
+    // Count the number of lines we've already successfully deserialized.
+    // We need that to adjust the line number in error messages from serde_json
+    // to translate from line numbers in the snippet we are deserializing
+    // to the line number in the overall file
+    let mut count = 0;
+
+    Box::pin(
+        poll_fn(move || -> Poll<Option<JsonStreamValue>, Error> {
+            loop {
+                if let Some(offset) = buf.iter().position(|b| *b == b'\n') {
+                    let line_bytes = buf.split_to(offset + 1);
+                    count += 1;
+                    if line_bytes.len() > 1 {
+                        let line = std::str::from_utf8(&line_bytes)?;
+                        let res = match serde_json::from_str::<Value>(line) {
+                            Ok(v) => Ok(Async::Ready(Some(JsonStreamValue {
+                                value: v,
+                                line: count,
+                            }))),
+                            Err(e) => {
+                                // Adjust the line number in the serde error. This
+                                // is fun because we can only get at the full error
+                                // message, and not the error message without line number
+                                let msg = e.to_string();
+                                let msg = msg.split(" at line ").next().unwrap();
+                                Err(anyhow!(
+                                    "{} at line {} column {}: '{}'",
+                                    msg,
+                                    e.line() + count - 1,
+                                    e.column(),
+                                    line
+                                ))
+                            }
+                        };
+                        return res;
+                    }
+                } else {
+                    // We only get here if there is no complete line in buf, and
+                    // it is therefore ok to immediately pass an Async::NotReady
+                    // from stream through.
+                    // If we get a None from poll, but still have something in buf,
+                    // that means the input was not terminated with a newline. We
+                    // add that so that the last line gets picked up in the next
+                    // run through the loop.
+                    match try_ready!(stream.poll().map_err(|e| anyhow::anyhow!("{}", e))) {
+                        Some(b) => buf.extend_from_slice(b.as_ref()),
+                        None if buf.len() > 0 => buf.extend_from_slice(&[b'\n']),
+                        None => return Ok(Async::Ready(None)),
                     }
                 }
-            })
-            .compat(),
-        );
-
-        Ok(stream)
-    }
+            }
+        })
+        .compat(),
+    )
 }
 
 #[cfg(test)]
@@ -337,6 +441,15 @@ mod tests {
     use super::*;
     use serde_json::json;
 
+    /// A resolver whose disk cache holds `data` for `link`
+    fn cached_resolver(link: &str, data: &[u8]) -> LinkResolver {
+        let logger = Logger::root(slog::Discard, o!());
+        let dir = env::temp_dir().join(format!("graph-link-resolver-{}", rand::random::<u64>()));
+        let disk_cache = IpfsDiskCache::new(&logger, dir, 1024 * 1024).unwrap();
+        disk_cache.insert(link, data, true).unwrap();
+        LinkResolver::from(IpfsClient::localhost()).with_disk_cache(Arc::new(disk_cache))
+    }
+
     #[tokio::test]
     async fn max_file_size() {
         env::set_var(MAX_IPFS_FILE_SIZE_VAR, "200");
@@ -350,6 +463,12 @@ mod tests {
         let err = LinkResolver::cat(&resolver, &logger, &Link { link: link.clone() })
             .await
             .unwrap_err();
+
+        // Files in the disk cache are subject to the same limit
+        let cached = cached_resolver(&link, file);
+        let cached_err = LinkResolver::cat(&cached, &logger, &Link { link: link.clone() })
+            .await
+            .unwrap_err();
         env::remove_var(MAX_IPFS_FILE_SIZE_VAR);
         assert_eq!(
             err.to_string(),
@@ -358,6 +477,13 @@ mod tests {
                 link
             )
         );
+        assert_eq!(
+            cached_err.to_string(),
+            format!(
+                "IPFS file {} is too large. It can be at most 200 bytes but is 201 bytes",
+                link
+            )
+        );
     }
 
     async fn json_round_trip(text: &'static str) -> Result<Vec<Value>, Error> {
@@ -395,9 +521,19 @@ mod tests {
         env::set_var(MAX_IPFS_MAP_FILE_SIZE_VAR, (file.len() - 1).to_string());
 
         let err = json_round_trip(file).await.unwrap_err();
+
+        // Files in the disk cache are subject to the same limit
+        let logger = Logger::root(slog::Discard, o!());
+        let link = "QmCachedMapFile".to_string();
+        let cached = cached_resolver(&link, file.as_bytes());
+        let cached_err = LinkResolver::json_stream(&cached, &logger, &Link { link })
+            .await
+            .err()
+            .unwrap();
         env::remove_var(MAX_IPFS_MAP_FILE_SIZE_VAR);
 
         assert!(err.to_string().contains(" is too large"));
+        assert!(cached_err.to_string().contains(" is too large"));
 
         let values = json_round_trip(file).await;
         assert_eq!(
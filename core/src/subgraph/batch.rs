@@ -0,0 +1,532 @@
+//! Write the changes of many blocks in one transaction while a subgraph is
+//! far behind the chain head.
+//!
+//! Committing the changes of every block in its own transaction makes the
+//! commit latency dominate syncing. A `BatchingStore` wraps the writable
+//! store of a subgraph and holds on to the changes of blocks that are more
+//! than the reorg threshold behind the chain head, and that will therefore
+//! not be reverted, until enough of them have accumulated; they are then
+//! written in one transaction. As soon as a block is closer to the chain
+//! head than that, the batch is written and blocks are transacted one by
+//! one again.
+//!
+//! Reads through the store see the changes that have not been written yet,
+//! and the block pointer and firehose cursor it reports are those of the
+//! last block in the batch, so that block streams continue where the
+//! subgraph left off. All other operations write the batch first so that
+//! they operate on the state that has been written.
+//!
+//! Losing a batch that has not been written yet, for example because the
+//! subgraph was stopped, is harmless since the block pointer in the
+//! database was not advanced, and the blocks in it will simply be processed
+//! again.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use graph::components::store::{
+    BlockOperations, EntityType, StoredDynamicDataSource, WritableStore,
+};
+use graph::data::subgraph::schema::{SubgraphError, SubgraphHealth};
+use graph::env::env_var;
+use graph::prelude::*;
+use lazy_static::lazy_static;
+
+lazy_static! {
+    /// The number of entity changes at which a batch gets written. Batching
+    /// is off unless this is set to a number bigger than 0.
+    static ref WRITE_BATCH_SIZE: usize = env_var("GRAPH_STORE_WRITE_BATCH_SIZE", 0);
+
+    /// The longest time a batch is held on to before it gets written, in
+    /// seconds.
+    static ref WRITE_BATCH_DURATION: Duration =
+        Duration::from_secs(env_var("GRAPH_STORE_WRITE_BATCH_DURATION", 300));
+}
+
+#[derive(Default)]
+struct Batch {
+    blocks: Vec<BlockOperations>,
+    /// The latest version of every entity changed in `blocks`, `None` if
+    /// the entity was removed
+    entities: HashMap<EntityKey, Option<Entity>>,
+    /// The number of entity changes in `blocks`
+    mods: usize,
+    /// When the first block was added to the batch
+    started: Option<Instant>,
+    stopwatch: Option<StopwatchMetrics>,
+}
+
+impl Batch {
+    fn push(&mut self, block: BlockOperations, stopwatch: StopwatchMetrics) {
+        for modification in &block.mods {
+            let entity = match modification {
+                EntityModification::Insert { data, .. }
+                | EntityModification::Overwrite { data, .. } => Some(data.clone()),
+                EntityModification::Remove { .. } => None,
+            };
+            self.entities
+                .insert(modification.entity_key().clone(), entity);
+        }
+        self.mods += block.mods.len();
+        self.started.get_or_insert_with(Instant::now);
+        self.stopwatch = Some(stopwatch);
+        self.blocks.push(block);
+    }
+
+    fn is_full(&self, size: usize, duration: Duration) -> bool {
+        self.mods >= size
+            || self
+                .started
+                .map(|started| started.elapsed() >= duration)
+                .unwrap_or(false)
+    }
+}
+
+pub(crate) struct BatchingStore {
+    logger: Logger,
+    deployment: DeploymentHash,
+    store: Arc<dyn WritableStore>,
+    chain_store: Arc<dyn ChainStore>,
+    /// Blocks that are this close to the chain head might still be
+    /// reverted and are never batched
+    reorg_threshold: BlockNumber,
+    /// The number of entity changes at which a batch gets written
+    batch_size: usize,
+    /// The longest time a batch is held on to before it gets written
+    batch_duration: Duration,
+    batch: Mutex<Batch>,
+    /// The number of the highest chain head we have seen
+    chain_head: AtomicI32,
+}
+
+impl BatchingStore {
+    /// Wrap `store` so that it batches writes of blocks that are more than
+    /// `reorg_threshold` blocks behind the chain head. Unless
+    /// `GRAPH_STORE_WRITE_BATCH_SIZE` is set, batching is off and `store` is
+    /// returned unchanged
+    pub fn wrap(
+        logger: Logger,
+        deployment: DeploymentHash,
+        store: Arc<dyn WritableStore>,
+        chain_store: Arc<dyn ChainStore>,
+        reorg_threshold: BlockNumber,
+    ) -> Arc<dyn WritableStore> {
+        if *WRITE_BATCH_SIZE == 0 {
+            return store;
+        }
+        Arc::new(BatchingStore::new(
+            logger,
+            deployment,
+            store,
+            chain_store,
+            reorg_threshold,
+            *WRITE_BATCH_SIZE,
+            *WRITE_BATCH_DURATION,
+        ))
+    }
+
+    fn new(
+        logger: Logger,
+        deployment: DeploymentHash,
+        store: Arc<dyn WritableStore>,
+        chain_store: Arc<dyn ChainStore>,
+        reorg_threshold: BlockNumber,
+        batch_size: usize,
+        batch_duration: Duration,
+    ) -> Self {
+        BatchingStore {
+            logger,
+            deployment,
+            store,
+            chain_store,
+            reorg_threshold,
+            batch_size,
+            batch_duration,
+            batch: Mutex::new(Batch::default()),
+            chain_head: AtomicI32::new(0),
+        }
+    }
+
+    /// Return `true` if `ptr` is far enough behind the chain head that it
+    /// will not be reverted. The chain head is only looked up when the
+    /// one we have seen last is not far enough ahead of `ptr`
+    fn is_final(&self, ptr: &BlockPtr) -> bool {
+        let is_final = |head: BlockNumber| head - ptr.number > self.reorg_threshold;
+
+        if is_final(self.chain_head.load(Ordering::SeqCst)) {
+            return true;
+        }
+        match self.chain_store.chain_head_ptr() {
+            Ok(Some(head)) => {
+                self.chain_head.fetch_max(head.number, Ordering::SeqCst);
+                is_final(head.number)
+            }
+            Ok(None) => false,
+            Err(e) => {
+                debug!(self.logger, "Could not get the chain head, not batching writes";
+                       "error" => e.to_string());
+                false
+            }
+        }
+    }
+
+    /// Write all blocks in the batch in one transaction
+    fn flush(&self) -> Result<(), StoreError> {
+        // Hold on to the lock until the blocks are written so that nobody
+        // can read from the store while the changes are neither in the
+        // batch nor written
+        let mut batch = self.batch.lock().unwrap();
+        if batch.blocks.is_empty() {
+            return Ok(());
+        }
+
+        let Batch {
+            blocks,
+            mods,
+            stopwatch,
+            ..
+        } = std::mem::take(&mut *batch);
+        debug!(self.logger, "Writing batched blocks";
+               "blocks" => blocks.len(),
+               "entity_changes" => mods,
+               "block_number" => blocks.last().map(|block| block.block_ptr.number));
+        self.store.transact_block_batch(
+            blocks,
+            stopwatch.expect("a batch with blocks has a stopwatch"),
+        )
+    }
+}
+
+#[async_trait]
+impl WritableStore for BatchingStore {
+    fn block_ptr(&self) -> Result<Option<BlockPtr>, StoreError> {
+        match self.batch.lock().unwrap().blocks.last() {
+            Some(block) => Ok(Some(block.block_ptr.clone())),
+            None => self.store.block_ptr(),
+        }
+    }
+
+    fn block_cursor(&self) -> Result<Option<String>, StoreError> {
+        let cursor = self
+            .batch
+            .lock()
+            .unwrap()
+            .blocks
+            .last()
+            .and_then(|block| block.firehose_cursor.clone());
+        match cursor {
+            Some(cursor) => Ok(Some(cursor)),
+            None => self.store.block_cursor(),
+        }
+    }
+
+    fn start_subgraph_deployment(&self, logger: &Logger) -> Result<(), StoreError> {
+        self.flush()?;
+        self.store.start_subgraph_deployment(logger)
+    }
+
+    fn revert_block_operations(&self, block_ptr_to: BlockPtr) -> Result<(), StoreError> {
+        self.flush()?;
+        self.store.revert_block_operations(block_ptr_to)
+    }
+
+    fn unfail_deterministic_error(
+        &self,
+        current_ptr: &BlockPtr,
+        parent_ptr: &BlockPtr,
+    ) -> Result<(), StoreError> {
+        self.flush()?;
+        self.store
+            .unfail_deterministic_error(current_ptr, parent_ptr)
+    }
+
+    fn unfail_non_deterministic_error(&self, current_ptr: &BlockPtr) -> Result<(), StoreError> {
+        self.flush()?;
+        self.store.unfail_non_deterministic_error(current_ptr)
+    }
+
+    async fn fail_subgraph(&self, error: SubgraphError) -> Result<(), StoreError> {
+        self.flush()?;
+        self.store.fail_subgraph(error).await
+    }
+
+    async fn supports_proof_of_indexing(&self) -> Result<bool, StoreError> {
+        self.store.supports_proof_of_indexing().await
+    }
+
+    fn get(&self, key: &EntityKey) -> Result<Option<Entity>, StoreError> {
+        if let Some(entity) = self.batch.lock().unwrap().entities.get(key) {
+            return Ok(entity.clone());
+        }
+        self.store.get(key)
+    }
+
+    fn transact_block_operations(
+        &self,
+        block_ptr_to: BlockPtr,
+        firehose_cursor: Option<String>,
+        mods: Vec<EntityModification>,
+        stopwatch: StopwatchMetrics,
+        data_sources: Vec<StoredDynamicDataSource>,
+        deterministic_errors: Vec<SubgraphError>,
+    ) -> Result<(), StoreError> {
+        if deterministic_errors.is_empty() && self.is_final(&block_ptr_to) {
+            let full = {
+                let mut batch = self.batch.lock().unwrap();
+                batch.push(
+                    BlockOperations {
+                        block_ptr: block_ptr_to,
+                        firehose_cursor,
+                        mods,
+                        data_sources,
+                    },
+                    stopwatch,
+                );
+                batch.is_full(self.batch_size, self.batch_duration)
+            };
+            if full {
+                self.flush()?;
+            }
+            return Ok(());
+        }
+
+        self.flush()?;
+        self.store.transact_block_operations(
+            block_ptr_to,
+            firehose_cursor,
+            mods,
+            stopwatch,
+            data_sources,
+            deterministic_errors,
+        )
+    }
+
+    fn transact_block_batch(
+        &self,
+        blocks: Vec<BlockOperations>,
+        stopwatch: StopwatchMetrics,
+    ) -> Result<(), StoreError> {
+        self.flush()?;
+        self.store.transact_block_batch(blocks, stopwatch)
+    }
+
+    fn get_many(
+        &self,
+        ids_for_type: BTreeMap<&EntityType, Vec<&str>>,
+    ) -> Result<BTreeMap<EntityType, Vec<Entity>>, StoreError> {
+        let mut found: BTreeMap<EntityType, Vec<Entity>> = BTreeMap::new();
+        let mut missing: BTreeMap<&EntityType, Vec<&str>> = BTreeMap::new();
+        {
+            let batch = self.batch.lock().unwrap();
+            if batch.entities.is_empty() {
+                return self.store.get_many(ids_for_type);
+            }
+            for (entity_type, ids) in ids_for_type {
+                for id in ids {
+                    let key = EntityKey {
+                        subgraph_id: self.deployment.clone(),
+                        entity_type: entity_type.clone(),
+                        entity_id: id.to_string(),
+                    };
+                    match batch.entities.get(&key) {
+                        Some(Some(entity)) => found
+                            .entry(entity_type.clone())
+                            .or_default()
+                            .push(entity.clone()),
+                        Some(None) => {}
+                        None => missing.entry(entity_type).or_default().push(id),
+                    }
+                }
+            }
+        }
+
+        if !missing.is_empty() {
+            for (entity_type, entities) in self.store.get_many(missing)? {
+                found.entry(entity_type).or_default().extend(entities);
+            }
+        }
+        Ok(found)
+    }
+
+    fn deployment_synced(&self) -> Result<(), StoreError> {
+        self.flush()?;
+        self.store.deployment_synced()
+    }
+
+    async fn is_deployment_synced(&self) -> Result<bool, StoreError> {
+        self.store.is_deployment_synced().await
+    }
+
+    fn unassign_subgraph(&self) -> Result<(), StoreError> {
+        self.flush()?;
+        self.store.unassign_subgraph()
+    }
+
+    async fn load_dynamic_data_sources(&self) -> Result<Vec<StoredDynamicDataSource>, StoreError> {
+        self.flush()?;
+        self.store.load_dynamic_data_sources().await
+    }
+
+    fn shard(&self) -> &str {
+        self.store.shard()
+    }
+
+    async fn health(&self, id: &DeploymentHash) -> Result<SubgraphHealth, StoreError> {
+        self.store.health(id).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph::components::store::BlockStore as _;
+    use test_store::*;
+
+    const SCHEMA: &str = "type Thing @entity { id: ID!, value: String! }";
+
+    fn key(deployment: &DeploymentHash, id: &str) -> EntityKey {
+        EntityKey::data(deployment.clone(), "Thing".to_owned(), id.to_owned())
+    }
+
+    async fn batching_store(
+        deployment: &DeploymentLocator,
+        batch_size: usize,
+    ) -> (Arc<dyn WritableStore>, BatchingStore) {
+        let writable = STORE
+            .subgraph_store()
+            .writable(LOGGER.clone(), deployment.id)
+            .await
+            .unwrap();
+        let chain_store = STORE.block_store().chain_store(NETWORK_NAME).unwrap();
+        let store = BatchingStore::new(
+            LOGGER.clone(),
+            deployment.hash.clone(),
+            writable.cheap_clone(),
+            chain_store,
+            0,
+            batch_size,
+            Duration::from_secs(3600),
+        );
+        // Pretend the chain is far enough ahead that all test blocks are final
+        store.chain_head.store(1_000, Ordering::SeqCst);
+        (writable, store)
+    }
+
+    /// Insert the entity `Thing(id)` at block `ptr`
+    fn insert(store: &BatchingStore, ptr: &BlockPtr, id: &str) {
+        let mut data = Entity::new();
+        data.set("id", id);
+        data.set("value", format!("inserted at {}", ptr.number));
+        let stopwatch = StopwatchMetrics::new(
+            LOGGER.clone(),
+            store.deployment.clone(),
+            METRICS_REGISTRY.clone(),
+        );
+        store
+            .transact_block_operations(
+                ptr.clone(),
+                None,
+                vec![EntityModification::Insert {
+                    key: key(&store.deployment, id),
+                    data,
+                }],
+                stopwatch,
+                vec![],
+                vec![],
+            )
+            .unwrap();
+    }
+
+    fn exists(store: &dyn WritableStore, deployment: &DeploymentHash, id: &str) -> bool {
+        store.get(&key(deployment, id)).unwrap().is_some()
+    }
+
+    #[test]
+    fn writes_full_batches() {
+        run_test_sequentially(|_| async {
+            let hash = DeploymentHash::new("batchingStoreWritesFullBatches").unwrap();
+            let deployment = create_test_subgraph(&hash, SCHEMA);
+            let (writable, store) = batching_store(&deployment, 2).await;
+
+            // The first block is held in memory, but visible through the
+            // batching store
+            insert(&store, &BLOCKS[0], "0");
+            assert_eq!(None, writable.block_ptr().unwrap());
+            assert!(!exists(writable.as_ref(), &hash, "0"));
+            assert_eq!(Some(BLOCKS[0].clone()), store.block_ptr().unwrap());
+            assert!(exists(&store, &hash, "0"));
+
+            // The second block fills the batch and both blocks get written
+            insert(&store, &BLOCKS[1], "1");
+            assert_eq!(Some(BLOCKS[1].clone()), writable.block_ptr().unwrap());
+            assert!(exists(writable.as_ref(), &hash, "0"));
+            assert!(exists(writable.as_ref(), &hash, "1"));
+
+            // Flushing writes a batch that is not full yet
+            insert(&store, &BLOCKS[2], "2");
+            assert_eq!(Some(BLOCKS[1].clone()), writable.block_ptr().unwrap());
+            store.flush().unwrap();
+            assert_eq!(Some(BLOCKS[2].clone()), writable.block_ptr().unwrap());
+            assert!(exists(writable.as_ref(), &hash, "2"));
+
+            remove_subgraph(&hash);
+        })
+    }
+
+    #[test]
+    fn revert_writes_batch_first() {
+        run_test_sequentially(|_| async {
+            let hash = DeploymentHash::new("batchingStoreRevert").unwrap();
+            let deployment = create_test_subgraph(&hash, SCHEMA);
+            let (writable, store) = batching_store(&deployment, 100).await;
+
+            insert(&store, &BLOCKS[0], "0");
+            insert(&store, &BLOCKS[1], "1");
+            insert(&store, &BLOCKS[2], "2");
+            assert_eq!(None, writable.block_ptr().unwrap());
+
+            // Reverting the last block in the batch writes the batch and
+            // then reverts the block in the store
+            store.revert_block_operations(BLOCKS[1].clone()).unwrap();
+            assert_eq!(Some(BLOCKS[1].clone()), writable.block_ptr().unwrap());
+            assert_eq!(Some(BLOCKS[1].clone()), store.block_ptr().unwrap());
+            assert!(exists(&store, &hash, "0"));
+            assert!(exists(&store, &hash, "1"));
+            assert!(!exists(&store, &hash, "2"));
+            assert!(!exists(writable.as_ref(), &hash, "2"));
+
+            remove_subgraph(&hash);
+        })
+    }
+
+    #[test]
+    fn unwritten_batch_is_processed_again_after_restart() {
+        run_test_sequentially(|_| async {
+            let hash = DeploymentHash::new("batchingStoreRestart").unwrap();
+            let deployment = create_test_subgraph(&hash, SCHEMA);
+
+            let (writable, store) = batching_store(&deployment, 100).await;
+            insert(&store, &BLOCKS[0], "0");
+            insert(&store, &BLOCKS[1], "1");
+            // Stopping the subgraph loses the batch
+            drop(store);
+            assert_eq!(None, writable.block_ptr().unwrap());
+            assert!(!exists(writable.as_ref(), &hash, "0"));
+
+            // After a restart, the subgraph starts from the block pointer in
+            // the store and processes the same blocks again
+            let (writable, store) = batching_store(&deployment, 100).await;
+            assert_eq!(None, store.block_ptr().unwrap());
+            insert(&store, &BLOCKS[0], "0");
+            insert(&store, &BLOCKS[1], "1");
+            store.flush().unwrap();
+            assert_eq!(Some(BLOCKS[1].clone()), writable.block_ptr().unwrap());
+            assert!(exists(writable.as_ref(), &hash, "0"));
+            assert!(exists(writable.as_ref(), &hash, "1"));
+
+            remove_subgraph(&hash);
+        })
+    }
+}
@@ -5,7 +5,7 @@ use std::collections::HashMap;
 use std::env;
 use std::str::FromStr;
 
-use graph::{blockchain::DataSource, prelude::*};
+use graph::{blockchain::DataSource, data::subgraph::SubgraphFeature, prelude::*};
 use graph::{
     blockchain::{Block, Blockchain},
     components::subgraph::{MappingError, SharedProofOfIndexing},
@@ -30,10 +30,23 @@ pub struct SubgraphInstance<C: Blockchain, T: RuntimeHostBuilder<C>> {
     /// stream events are processed by the mappings in this same order.
     hosts: Vec<Arc<T::Host>>,
 
+    /// Whether the handlers of independent data sources may run
+    /// concurrently
+    parallel: bool,
+
     /// Maps the hash of a module to a channel to the thread in which the module is instantiated.
-    module_cache: HashMap<[u8; 32], Sender<T::Req>>,
+    module_cache: HashMap<ModuleKey, Sender<T::Req>>,
 }
 
+/// Hosts whose keys are equal share the thread that runs their module, and
+/// that thread runs one handler at a time. When handlers run in parallel,
+/// hosts for different data sources may be in different groups, which must
+/// not wait for each other; the key therefore also contains the name of the
+/// data source. Hosts for the same data source or template always end up
+/// in the same group, so there is still only one thread per data source or
+/// template, no matter how many dynamic data sources get created
+type ModuleKey = ([u8; 32], Option<String>);
+
 impl<T, C: Blockchain> SubgraphInstance<C, T>
 where
     T: RuntimeHostBuilder<C>,
@@ -47,12 +60,16 @@ where
         let subgraph_id = manifest.id.clone();
         let network = manifest.network_name();
         let templates = Arc::new(manifest.templates);
+        let parallel = manifest
+            .features
+            .contains(&SubgraphFeature::ParallelDataSources);
 
         let mut this = SubgraphInstance {
             host_builder,
             subgraph_id,
             network,
             hosts: Vec::new(),
+            parallel,
             module_cache: HashMap::new(),
         };
 
@@ -81,8 +98,11 @@ where
     ) -> Result<T::Host, Error> {
         let mapping_request_sender = {
             let module_bytes = data_source.runtime();
-            let module_hash = tiny_keccak::keccak256(module_bytes);
-            if let Some(sender) = self.module_cache.get(&module_hash) {
+            let module_key: ModuleKey = (
+                tiny_keccak::keccak256(module_bytes),
+                self.parallel.then(|| data_source.name().to_owned()),
+            );
+            if let Some(sender) = self.module_cache.get(&module_key) {
                 sender.clone()
             } else {
                 let sender = T::spawn_mapping(
@@ -91,7 +111,7 @@ where
                     self.subgraph_id.clone(),
                     host_metrics.clone(),
                 )?;
-                self.module_cache.insert(module_hash, sender.clone());
+                self.module_cache.insert(module_key, sender.clone());
                 sender
             }
         };
@@ -230,4 +250,12 @@ where
     pub(crate) fn network(&self) -> &str {
         &self.network
     }
+
+    pub(crate) fn hosts(&self) -> &[Arc<T::Host>] {
+        &self.hosts
+    }
+
+    pub(crate) fn parallel(&self) -> bool {
+        self.parallel
+    }
 }
@@ -1,4 +1,6 @@
+use super::batch::BatchingStore;
 use super::loader::load_dynamic_data_sources;
+use super::parallel;
 use super::SubgraphInstance;
 use atomic_refcell::AtomicRefCell;
 use fail::fail_point;
@@ -17,7 +19,9 @@ use graph::{
 };
 use graph::{
     blockchain::{block_stream::BlockStreamEvent, Blockchain, TriggerFilter as _},
-    components::subgraph::{CausalityRegion, MappingError, ProofOfIndexing, SharedProofOfIndexing},
+    components::subgraph::{
+        CausalityRegion, MappingError, ProofOfIndexing, SharedProofOfIndexing, SubgraphProfiles,
+    },
 };
 use graph::{
     blockchain::{Block, BlockchainMap},
@@ -99,6 +103,7 @@ pub struct SubgraphInstanceManager<S, M, L> {
     manager_metrics: SubgraphInstanceManagerMetrics,
     instances: SharedInstanceKeepAliveMap,
     link_resolver: Arc<L>,
+    profiles: Arc<SubgraphProfiles>,
 }
 
 struct SubgraphInstanceManagerMetrics {
@@ -118,7 +123,7 @@ impl SubgraphInstanceManagerMetrics {
     }
 }
 
-struct SubgraphInstanceMetrics {
+pub(super) struct SubgraphInstanceMetrics {
     pub block_trigger_count: Box<Histogram>,
     pub block_processing_duration: Box<Histogram>,
     pub block_ops_transaction_duration: Box<Histogram>,
@@ -237,6 +242,7 @@ where
         // Drop the cancel guard to shut down the subgraph now
         let mut instances = self.instances.write().unwrap();
         instances.remove(&loc.id);
+        self.profiles.remove(&loc.hash);
 
         self.manager_metrics.subgraph_count.dec();
     }
@@ -254,6 +260,7 @@ where
         chains: Arc<BlockchainMap>,
         metrics_registry: Arc<M>,
         link_resolver: Arc<L>,
+        profiles: Arc<SubgraphProfiles>,
     ) -> Self {
         let logger = logger_factory.component_logger("SubgraphInstanceManager", None);
         let logger_factory = logger_factory.with_parent(logger.clone());
@@ -266,6 +273,7 @@ where
             metrics_registry,
             instances: SharedInstanceKeepAliveMap::default(),
             link_resolver,
+            profiles,
         }
     }
 
@@ -378,6 +386,8 @@ where
             deployment.hash.as_str(),
             stopwatch_metrics.clone(),
         ));
+        self.profiles
+            .insert(deployment.hash.clone(), host_metrics.profile.cheap_clone());
         let block_stream_metrics = Arc::new(BlockStreamMetrics::new(
             registry.cheap_clone(),
             &deployment.hash,
@@ -406,6 +416,16 @@ where
         let instance =
             SubgraphInstance::from_manifest(&logger, manifest, host_builder, host_metrics.clone())?;
 
+        // While the subgraph is far behind the chain head, write the changes
+        // of many blocks at once
+        let store = BatchingStore::wrap(
+            logger.cheap_clone(),
+            deployment.hash.clone(),
+            store,
+            chain.chain_store(),
+            chain.reorg_threshold(),
+        );
+
         // The subgraph state tracks the state of the subgraph instance over time
         let ctx = IndexingContext {
             inputs: IndexingInputs {
@@ -485,6 +505,7 @@ where
             .chain
             .new_block_stream(
                 ctx.inputs.deployment.clone(),
+                ctx.inputs.store.cheap_clone(),
                 ctx.inputs.start_blocks.clone(),
                 Arc::new(ctx.state.filter.clone()),
                 ctx.block_stream_metrics.clone(),
@@ -746,6 +767,10 @@ where
 
                             // Retry logic below:
 
+                            // The entity cache might contain changes that
+                            // never made it into the store
+                            ctx.state.entity_lfu_cache = LfuCache::new();
+
                             // Cancel the stream for real.
                             ctx.state
                                 .instances
@@ -942,7 +967,7 @@ async fn process_block<T: RuntimeHostBuilder<C>, C: Blockchain>(
             ctx,
             &mut block_state.entity_cache,
             data_sources,
-        );
+        )?;
 
         // Process the triggers in each host in the same order the
         // corresponding data sources have been created.
@@ -1160,6 +1185,24 @@ async fn process_triggers<C: Blockchain>(
 ) -> Result<BlockState<C>, MappingError> {
     use graph::blockchain::TriggerData;
 
+    if instance.parallel() && triggers.len() > 1 {
+        match parallel::process_triggers(
+            logger,
+            block_state,
+            &proof_of_indexing,
+            &subgraph_metrics,
+            instance.hosts(),
+            block,
+            &triggers,
+            causality_region,
+        )
+        .await
+        {
+            parallel::Outcome::Processed(block_state) => return Ok(block_state),
+            parallel::Outcome::Sequential(state) => block_state = state,
+        }
+    }
+
     for trigger in triggers.into_iter() {
         let start = Instant::now();
         block_state = instance
@@ -1234,7 +1277,7 @@ fn persist_dynamic_data_sources<T: RuntimeHostBuilder<C>, C: Blockchain>(
     ctx: &mut IndexingContext<T, C>,
     entity_cache: &mut EntityCache,
     data_sources: Vec<C::DataSource>,
-) {
+) -> Result<(), Error> {
     if !data_sources.is_empty() {
         debug!(
             logger,
@@ -1252,9 +1295,10 @@ fn persist_dynamic_data_sources<T: RuntimeHostBuilder<C>, C: Blockchain>(
             "name" => &data_source.name(),
             "address" => &data_source.address().map(|address| hex::encode(address)).unwrap_or("none".to_string()),
         );
-        entity_cache.add_data_source(data_source);
+        entity_cache.add_data_source(data_source)?;
     }
 
     // Merge filters from data sources into the block stream builder
     ctx.state.filter.extend(data_sources.iter());
+    Ok(())
 }
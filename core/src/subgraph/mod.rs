@@ -1,6 +1,8 @@
+mod batch;
 mod instance;
 mod instance_manager;
 mod loader;
+mod parallel;
 mod provider;
 mod registrar;
 
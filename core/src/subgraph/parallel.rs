@@ -0,0 +1,556 @@
+//! Process the triggers of a block with the handlers of independent data
+//! sources running concurrently.
+//!
+//! Data sources are grouped by the entity types their mappings declare:
+//! data sources whose declared entity types overlap end up in the same
+//! group. Each group works on its own fork of the block state, and runs its
+//! handlers in trigger order. Once all groups are done, their changes are
+//! merged back, and the events they wrote to the proof of indexing are
+//! replayed in the order in which the handlers would have run one after the
+//! other, so that the outcome is exactly the same as for sequential
+//! processing.
+//!
+//! Whenever a group does something that makes that impossible to guarantee,
+//! like touching an entity type that was not declared, creating a data
+//! source, or failing, all changes made by the groups are discarded and the
+//! block has to be processed sequentially.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::time::Instant;
+
+use atomic_refcell::AtomicRefCell;
+use graph::blockchain::{Block, Blockchain, DataSource, TriggerWithHandler};
+use graph::components::store::EntityType;
+use graph::components::subgraph::{MappingError, ProofOfIndexing, SharedProofOfIndexing};
+use graph::prelude::*;
+
+use super::instance_manager::SubgraphInstanceMetrics;
+
+/// The result of trying to process the triggers of a block in parallel
+pub(crate) enum Outcome<C: Blockchain> {
+    /// All triggers have been processed
+    Processed(BlockState<C>),
+    /// The triggers need to be processed sequentially, starting from this
+    /// block state
+    Sequential(BlockState<C>),
+}
+
+/// One handler invocation, identified by the index of the trigger and of
+/// the host that handles it
+struct Work<C: Blockchain> {
+    trigger: usize,
+    host: usize,
+    mapping_trigger: TriggerWithHandler<C>,
+}
+
+/// The events a handler wrote to the proof of indexing
+struct Recorded {
+    trigger: usize,
+    host: usize,
+    proof_of_indexing: Arc<AtomicRefCell<ProofOfIndexing>>,
+}
+
+struct GroupResult<C: Blockchain> {
+    state: BlockState<C>,
+    recorded: Vec<Recorded>,
+    /// How long each handler took, by the index of its trigger
+    durations: Vec<(usize, Duration)>,
+}
+
+/// Assign each host to a group such that hosts whose data sources declare
+/// overlapping entity types are in the same group. Returns the group of
+/// each host and the entity types of each group. Groups are numbered in
+/// the order in which their first host appears in `hosts`
+fn group_hosts<C: Blockchain, H: RuntimeHost<C>>(
+    hosts: &[Arc<H>],
+) -> (Vec<usize>, Vec<HashSet<EntityType>>) {
+    // All hosts for the same data source or template have the same
+    // entities, so it is enough to group the distinct names
+    let mut names: Vec<(&str, &[String])> = Vec::new();
+    let mut name_index: HashMap<&str, usize> = HashMap::new();
+    for host in hosts {
+        let ds = host.data_source();
+        if !name_index.contains_key(ds.name()) {
+            name_index.insert(ds.name(), names.len());
+            names.push((ds.name(), ds.entities()));
+        }
+    }
+
+    // A simple union-find over the names
+    fn find(parent: &mut Vec<usize>, i: usize) -> usize {
+        let mut root = i;
+        while parent[root] != root {
+            root = parent[root];
+        }
+        parent[i] = root;
+        root
+    }
+
+    let mut parent: Vec<usize> = (0..names.len()).collect();
+    for i in 0..names.len() {
+        for j in 0..i {
+            let overlaps = names[i].1.iter().any(|entity| names[j].1.contains(entity));
+            if overlaps {
+                let (a, b) = (find(&mut parent, i), find(&mut parent, j));
+                parent[a.max(b)] = a.min(b);
+            }
+        }
+    }
+
+    let mut group_of_root: HashMap<usize, usize> = HashMap::new();
+    let mut entity_types: Vec<HashSet<EntityType>> = Vec::new();
+    let mut name_group = Vec::with_capacity(names.len());
+    for (i, (_, entities)) in names.iter().enumerate() {
+        let root = find(&mut parent, i);
+        let group = *group_of_root.entry(root).or_insert_with(|| {
+            entity_types.push(HashSet::new());
+            entity_types.len() - 1
+        });
+        entity_types[group].extend(
+            entities
+                .iter()
+                .map(|entity| EntityType::from(entity.as_str())),
+        );
+        name_group.push(group);
+    }
+
+    let host_group = hosts
+        .iter()
+        .map(|host| name_group[name_index[host.data_source().name()]])
+        .collect();
+    (host_group, entity_types)
+}
+
+/// Try to process `triggers` with the handlers of different groups of
+/// data sources running concurrently. If that is not possible, return the
+/// block state that sequential processing should start from
+pub(crate) async fn process_triggers<C: Blockchain, H: RuntimeHost<C>>(
+    logger: &Logger,
+    mut block_state: BlockState<C>,
+    proof_of_indexing: &SharedProofOfIndexing,
+    subgraph_metrics: &SubgraphInstanceMetrics,
+    hosts: &[Arc<H>],
+    block: &Arc<C::Block>,
+    triggers: &[C::TriggerData],
+    causality_region: &str,
+) -> Outcome<C> {
+    let (host_group, entity_types) = group_hosts(hosts);
+    if entity_types.len() < 2 {
+        return Outcome::Sequential(block_state);
+    }
+
+    let mut work: Vec<Vec<Work<C>>> = entity_types.iter().map(|_| Vec::new()).collect();
+    for (trigger_idx, trigger) in triggers.iter().enumerate() {
+        for (host_idx, host) in hosts.iter().enumerate() {
+            match host.match_and_decode(trigger, block.cheap_clone(), logger) {
+                Ok(Some(mapping_trigger)) => work[host_group[host_idx]].push(Work {
+                    trigger: trigger_idx,
+                    host: host_idx,
+                    mapping_trigger,
+                }),
+                Ok(None) => {}
+                // Sequential processing reports the error
+                Err(_) => return Outcome::Sequential(block_state),
+            }
+        }
+    }
+    if work.iter().filter(|work| !work.is_empty()).count() < 2 {
+        return Outcome::Sequential(block_state);
+    }
+
+    let record = proof_of_indexing.is_some();
+    let mut groups = Vec::new();
+    for (work, entity_types) in work.into_iter().zip(entity_types) {
+        if !work.is_empty() {
+            let state = block_state.fork(entity_types);
+            groups.push(process_group(logger, hosts, block, work, state, record));
+        }
+    }
+    let results = futures03::future::join_all(groups).await;
+
+    let mut sequential = false;
+    let mut finished = Vec::new();
+    for result in results {
+        match result {
+            Ok(group) => {
+                let state = &group.state;
+                if state.has_errors()
+                    || state.has_created_data_sources()
+                    || state.entity_cache.has_undeclared_access()
+                {
+                    sequential = true;
+                }
+                finished.push(group);
+            }
+            Err(e) => {
+                debug!(logger, "Failed to process triggers in parallel";
+                       "error" => format!("{:?}", e));
+                sequential = true;
+            }
+        }
+    }
+
+    if sequential {
+        debug!(
+            logger,
+            "Processing the triggers of this block sequentially instead"
+        );
+        for group in finished {
+            block_state
+                .entity_cache
+                .extend_current(group.state.entity_cache);
+        }
+        return Outcome::Sequential(block_state);
+    }
+
+    let mut recorded = Vec::new();
+    let mut durations = BTreeMap::new();
+    for group in finished {
+        block_state.extend(group.state);
+        recorded.extend(group.recorded);
+        for (trigger, duration) in group.durations {
+            *durations.entry(trigger).or_default() += duration;
+        }
+    }
+
+    // Report the time spent on each trigger as the sum of the time its
+    // handlers took, the same as if they had run one after the other
+    for duration in durations.values() {
+        subgraph_metrics.observe_trigger_processing_duration(duration.as_secs_f64());
+    }
+
+    if let Some(proof_of_indexing) = proof_of_indexing {
+        recorded.sort_by_key(|recorded| (recorded.trigger, recorded.host));
+        let mut recorded = recorded.into_iter().peekable();
+        let mut proof_of_indexing = proof_of_indexing.borrow_mut();
+        for trigger_idx in 0..triggers.len() {
+            proof_of_indexing.start_handler(causality_region);
+            while let Some(handler) = recorded.next_if(|handler| handler.trigger == trigger_idx) {
+                let handler_poi = Arc::try_unwrap(handler.proof_of_indexing)
+                    .unwrap_or_else(|_| panic!("handler still holds on to its proof of indexing"))
+                    .into_inner();
+                handler_poi.replay(logger, &mut proof_of_indexing);
+            }
+        }
+    }
+
+    Outcome::Processed(block_state)
+}
+
+/// Run the handlers in `work` one after the other
+async fn process_group<C: Blockchain, H: RuntimeHost<C>>(
+    logger: &Logger,
+    hosts: &[Arc<H>],
+    block: &Arc<C::Block>,
+    work: Vec<Work<C>>,
+    mut state: BlockState<C>,
+    record: bool,
+) -> Result<GroupResult<C>, MappingError> {
+    let mut recorded = Vec::new();
+    let mut durations = Vec::new();
+    for work in work {
+        let start = Instant::now();
+        let proof_of_indexing = if record {
+            Some(Arc::new(AtomicRefCell::new(ProofOfIndexing::recording(
+                block.number(),
+            ))))
+        } else {
+            None
+        };
+
+        state = hosts[work.host]
+            .process_mapping_trigger(
+                logger,
+                block.ptr(),
+                work.mapping_trigger,
+                state,
+                proof_of_indexing.cheap_clone(),
+            )
+            .await?;
+
+        if let Some(proof_of_indexing) = proof_of_indexing {
+            recorded.push(Recorded {
+                trigger: work.trigger,
+                host: work.host,
+                proof_of_indexing,
+            });
+        }
+        durations.push((work.trigger, start.elapsed()));
+    }
+    Ok(GroupResult {
+        state,
+        recorded,
+        durations,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicI32, Ordering};
+
+    use graph::components::store::{EntityKey, EntityModification};
+    use graph::components::subgraph::{ProofOfIndexingEvent, ProofOfIndexingFinisher};
+    use graph::data::subgraph::Source;
+    use graph::prelude::ethabi::Contract;
+    use graph::prelude::web3::types::{H256, U64};
+    use graph::util::lfu_cache::LfuCache;
+    use graph_chain_ethereum::chain::BlockFinality;
+    use graph_chain_ethereum::{
+        Chain, DataSource as EthereumDataSource, EthereumBlockTriggerType, EthereumTrigger,
+        Mapping, MappingABI, MappingTrigger,
+    };
+    use semver::Version;
+    use test_store::*;
+
+    use super::*;
+    use crate::subgraph::SubgraphInstance;
+
+    const SCHEMA: &str = "
+        type Apple @entity { id: ID!, count: Int! }
+        type Banana @entity { id: ID!, count: Int! }";
+    const CAUSALITY_REGION: &str = "ethereum/mainnet";
+
+    /// A host that stores an entity of the one type its data source
+    /// declares for every trigger, and counts how often it was called
+    struct MockHost {
+        deployment: DeploymentHash,
+        data_source: EthereumDataSource,
+        calls: AtomicI32,
+    }
+
+    impl MockHost {
+        fn new(deployment: &DeploymentHash, name: &str, entity_type: &str) -> Arc<Self> {
+            let data_source = EthereumDataSource {
+                kind: "ethereum/contract".to_string(),
+                network: Some("mainnet".to_string()),
+                name: name.to_string(),
+                source: Source {
+                    address: None,
+                    abi: "abi".to_string(),
+                    start_block: 0,
+                },
+                mapping: Mapping {
+                    kind: "ethereum/events".to_string(),
+                    api_version: Version::new(0, 0, 5),
+                    language: "wasm/assemblyscript".to_string(),
+                    entities: vec![entity_type.to_string()],
+                    abis: vec![],
+                    event_handlers: vec![],
+                    call_handlers: vec![],
+                    block_handlers: vec![],
+                    transaction_handlers: vec![],
+                    link: Link {
+                        link: "link".to_string(),
+                    },
+                    runtime: Arc::new(vec![]),
+                },
+                context: Default::default(),
+                creation_block: None,
+                contract_abi: Arc::new(MappingABI {
+                    name: "abi".to_string(),
+                    contract: Contract::load("[]".as_bytes()).unwrap(),
+                }),
+            };
+            Arc::new(MockHost {
+                deployment: deployment.clone(),
+                data_source,
+                calls: AtomicI32::new(0),
+            })
+        }
+    }
+
+    impl PartialEq for MockHost {
+        fn eq(&self, other: &Self) -> bool {
+            self.data_source.name == other.data_source.name
+        }
+    }
+
+    #[async_trait]
+    impl RuntimeHost<Chain> for MockHost {
+        fn match_and_decode(
+            &self,
+            _trigger: &EthereumTrigger,
+            block: Arc<BlockFinality>,
+            _logger: &Logger,
+        ) -> Result<Option<TriggerWithHandler<Chain>>, Error> {
+            let block = match block.as_ref() {
+                BlockFinality::Final(block) => block.cheap_clone(),
+                BlockFinality::NonFinal(block) => block.ethereum_block.block.cheap_clone(),
+            };
+            Ok(Some(TriggerWithHandler::new(
+                MappingTrigger::Block { block },
+                "handleBlock".to_string(),
+            )))
+        }
+
+        async fn process_mapping_trigger(
+            &self,
+            logger: &Logger,
+            _block_ptr: BlockPtr,
+            _trigger: TriggerWithHandler<Chain>,
+            mut state: BlockState<Chain>,
+            proof_of_indexing: SharedProofOfIndexing,
+        ) -> Result<BlockState<Chain>, MappingError> {
+            let entity_type = &self.data_source.mapping.entities[0];
+            let count = self.calls.fetch_add(1, Ordering::SeqCst) + 1;
+            let id = format!("{}-{}", self.data_source.name, count);
+            let mut data = HashMap::new();
+            data.insert("id".to_string(), Value::from(id.clone()));
+            data.insert("count".to_string(), Value::from(count));
+
+            state.enter_handler();
+            if let Some(proof_of_indexing) = &proof_of_indexing {
+                proof_of_indexing.borrow_mut().write(
+                    logger,
+                    CAUSALITY_REGION,
+                    &ProofOfIndexingEvent::SetEntity {
+                        entity_type,
+                        id: &id,
+                        data: &data,
+                    },
+                );
+            }
+            let key = EntityKey::data(self.deployment.clone(), entity_type.clone(), id);
+            state.entity_cache.set(key, Entity::from(data));
+            state.exit_handler();
+            Ok(state)
+        }
+
+        fn creation_block_number(&self) -> Option<BlockNumber> {
+            None
+        }
+
+        fn data_source(&self) -> &EthereumDataSource {
+            &self.data_source
+        }
+    }
+
+    #[derive(Clone)]
+    struct MockHostBuilder;
+
+    impl RuntimeHostBuilder<Chain> for MockHostBuilder {
+        type Host = MockHost;
+        type Req = ();
+
+        fn build(
+            &self,
+            _network_name: String,
+            _subgraph_id: DeploymentHash,
+            _data_source: EthereumDataSource,
+            _top_level_templates: Arc<Vec<graph_chain_ethereum::DataSourceTemplate>>,
+            _mapping_request_sender: futures01::sync::mpsc::Sender<()>,
+            _metrics: Arc<HostMetrics>,
+        ) -> Result<MockHost, Error> {
+            unimplemented!()
+        }
+
+        fn spawn_mapping(
+            _raw_module: Vec<u8>,
+            _logger: Logger,
+            _subgraph_id: DeploymentHash,
+            _metrics: Arc<HostMetrics>,
+        ) -> Result<futures01::sync::mpsc::Sender<()>, Error> {
+            unimplemented!()
+        }
+    }
+
+    /// Process `triggers` with freshly made hosts, either sequentially or
+    /// in parallel, and return the digest of the proof of indexing and the
+    /// changes to the store
+    async fn process(
+        deployment: &DeploymentLocator,
+        writable: Arc<dyn WritableStore>,
+        parallel: bool,
+    ) -> (Vec<u8>, Vec<EntityModification>) {
+        let hosts = vec![
+            MockHost::new(&deployment.hash, "Apples", "Apple"),
+            MockHost::new(&deployment.hash, "Bananas", "Banana"),
+            MockHost::new(&deployment.hash, "MoreApples", "Apple"),
+        ];
+        let block = LightEthereumBlock {
+            hash: Some(H256::from_low_u64_be(1)),
+            number: Some(U64::from(1)),
+            ..Default::default()
+        };
+        let block = Arc::new(BlockFinality::Final(Arc::new(block)));
+        let triggers: Vec<_> = (0..3)
+            .map(|_| EthereumTrigger::Block(block.ptr(), EthereumBlockTriggerType::Every))
+            .collect();
+        let proof_of_indexing = Some(Arc::new(AtomicRefCell::new(ProofOfIndexing::new(
+            block.number(),
+        ))));
+        let metrics =
+            SubgraphInstanceMetrics::new(METRICS_REGISTRY.clone(), deployment.hash.as_str());
+
+        let mut state = BlockState::<Chain>::new(writable, LfuCache::new());
+        if parallel {
+            let outcome = process_triggers(
+                &LOGGER,
+                state,
+                &proof_of_indexing,
+                &metrics,
+                &hosts,
+                &block,
+                &triggers,
+                CAUSALITY_REGION,
+            )
+            .await;
+            state = match outcome {
+                Outcome::Processed(state) => state,
+                Outcome::Sequential(_) => panic!("the triggers can be processed in parallel"),
+            };
+        } else {
+            for trigger in &triggers {
+                state =
+                    SubgraphInstance::<Chain, MockHostBuilder>::process_trigger_in_runtime_hosts(
+                        &LOGGER,
+                        &hosts,
+                        &block,
+                        trigger,
+                        state,
+                        proof_of_indexing.cheap_clone(),
+                        CAUSALITY_REGION,
+                    )
+                    .await
+                    .unwrap();
+            }
+        }
+
+        let proof_of_indexing = Arc::try_unwrap(proof_of_indexing.unwrap())
+            .unwrap()
+            .into_inner();
+        let mut finisher = ProofOfIndexingFinisher::new(&block.ptr(), &deployment.hash, &None);
+        for (name, region) in proof_of_indexing.take() {
+            finisher.add_causality_region(&name, &region.pause(None));
+        }
+        let digest = finisher.finish().to_vec();
+
+        let mut modifications = state.entity_cache.as_modifications().unwrap().modifications;
+        modifications.sort_by(|a, b| a.entity_key().cmp(b.entity_key()));
+        (digest, modifications)
+    }
+
+    #[test]
+    fn parallel_processing_matches_sequential_processing() {
+        run_test_sequentially(|store| async move {
+            let hash = DeploymentHash::new("parallelDataSources").unwrap();
+            let deployment = create_test_subgraph(&hash, SCHEMA);
+            let writable = store
+                .subgraph_store()
+                .writable(LOGGER.clone(), deployment.id)
+                .await
+                .unwrap();
+
+            let (sequential_digest, sequential_changes) =
+                process(&deployment, writable.cheap_clone(), false).await;
+            let (parallel_digest, parallel_changes) = process(&deployment, writable, true).await;
+
+            // Every host handles every trigger
+            assert_eq!(9, sequential_changes.len());
+            assert_eq!(sequential_changes, parallel_changes);
+            assert_eq!(sequential_digest, parallel_digest);
+
+            remove_subgraph(&hash);
+        })
+    }
+}
@@ -3,6 +3,7 @@ use std::sync::Arc;
 use std::task::{Context, Poll};
 use std::time::Duration;
 
+use crate::firehose::archive::BlockResponseStream;
 use crate::firehose::endpoints::FirehoseEndpoint;
 use crate::prelude::*;
 
@@ -27,15 +28,9 @@ where
 enum BlockStreamState {
     Disconnected,
     Connecting(
-        Pin<
-            Box<
-                dyn futures03::Future<
-                    Output = Result<tonic::Streaming<bstream::BlockResponseV2>, anyhow::Error>,
-                >,
-            >,
-        >,
+        Pin<Box<dyn futures03::Future<Output = Result<BlockResponseStream, anyhow::Error>>>>,
     ),
-    Connected(tonic::Streaming<bstream::BlockResponseV2>),
+    Connected(BlockResponseStream),
 }
 
 pub struct FirehoseBlockStream<C: Blockchain, F: FirehoseMapper<C>> {
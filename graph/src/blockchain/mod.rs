@@ -13,18 +13,19 @@ use crate::{
     cheap_clone::CheapClone,
     components::{
         metrics::stopwatch::StopwatchMetrics,
-        store::{DeploymentLocator, StoredDynamicDataSource},
+        store::{DeploymentLocator, StoredDynamicDataSource, WritableStore},
     },
     data::subgraph::UnifiedMappingApiVersion,
+    env::env_var,
     prelude::DataSourceContext,
-    runtime::{AscHeap, AscPtr, DeterministicHostError, HostExportError},
+    runtime::{gas::GasCounter, AscHeap, AscPtr, DeterministicHostError, HostExportError},
 };
 use crate::{
     components::{
         store::{BlockNumber, ChainStore},
         subgraph::DataSourceTemplateInfo,
     },
-    prelude::{thiserror::Error, LinkResolver},
+    prelude::{lazy_static, thiserror::Error, LinkResolver},
 };
 use anyhow::{anyhow, Context, Error};
 use async_trait::async_trait;
@@ -47,6 +48,15 @@ pub use types::{BlockHash, BlockPtr, ChainIdentifier};
 
 use self::block_stream::{BlockStream, BlockStreamMetrics};
 
+lazy_static! {
+    /// The number of blocks behind the chain head after which blocks are
+    /// considered final and will not be reverted anymore. This is the
+    /// default for the `reorg_threshold` of chains; code that needs to know
+    /// how far back reverts can happen should ask the chain through
+    /// `Blockchain::reorg_threshold` when it has one at hand.
+    pub static ref REORG_THRESHOLD: BlockNumber = env_var("ETHEREUM_REORG_THRESHOLD", 50);
+}
+
 pub trait Block: Send + Sync {
     fn ptr(&self) -> BlockPtr;
     fn parent_ptr(&self) -> Option<BlockPtr>;
@@ -109,9 +119,12 @@ pub trait Blockchain: Debug + Sized + Send + Sync + Unpin + 'static {
         stopwatch_metrics: StopwatchMetrics,
     ) -> Result<Arc<Self::TriggersAdapter>, Error>;
 
+    /// Create a block stream for `deployment`. The stream follows the
+    /// progress of the deployment through `store`
     async fn new_block_stream(
         &self,
         deployment: DeploymentLocator,
+        store: Arc<dyn WritableStore>,
         start_blocks: Vec<BlockNumber>,
         filter: Arc<Self::TriggerFilter>,
         metrics: Arc<BlockStreamMetrics>,
@@ -129,6 +142,10 @@ pub trait Blockchain: Debug + Sized + Send + Sync + Unpin + 'static {
     ) -> Result<BlockPtr, IngestorError>;
 
     fn runtime_adapter(&self) -> Arc<Self::RuntimeAdapter>;
+
+    /// Blocks that are more than this many blocks behind the chain head
+    /// will not be reverted anymore
+    fn reorg_threshold(&self) -> BlockNumber;
 }
 
 #[derive(Error, Debug)]
@@ -215,6 +232,9 @@ pub trait DataSource<C: Blockchain>:
     fn name(&self) -> &str;
     fn kind(&self) -> &str;
     fn network(&self) -> Option<&str>;
+    /// The entity types that the mapping of this data source declares it
+    /// works with
+    fn entities(&self) -> &[String];
     fn context(&self) -> Arc<Option<DataSourceContext>>;
     fn creation_block(&self) -> Option<BlockNumber>;
     fn api_version(&self) -> semver::Version;
@@ -231,7 +251,7 @@ pub trait DataSource<C: Blockchain>:
 
     fn is_duplicate_of(&self, other: &Self) -> bool;
 
-    fn as_stored_dynamic_data_source(&self) -> StoredDynamicDataSource;
+    fn as_stored_dynamic_data_source(&self) -> Result<StoredDynamicDataSource, Error>;
 
     fn from_stored_dynamic_data_source(
         templates: &BTreeMap<&str, &C::DataSourceTemplate>,
@@ -286,6 +306,7 @@ pub struct HostFnCtx<'a> {
     pub logger: Logger,
     pub block_ptr: BlockPtr,
     pub heap: &'a mut dyn AscHeap,
+    pub gas: GasCounter,
 }
 
 /// Host fn that receives one u32 argument and returns an u32.
@@ -434,6 +455,10 @@ impl<C: Blockchain> TriggerWithHandler<C> {
         &self.handler
     }
 
+    pub fn trigger(&self) -> &C::MappingTrigger {
+        &self.trigger
+    }
+
     pub fn to_asc_ptr<H: AscHeap>(
         self,
         heap: &mut H,
@@ -12,3 +12,6 @@ pub mod index_node;
 
 /// Components for the Prometheus metrics server.
 pub mod metrics;
+
+/// Component for authenticating tenants and enforcing their query limits.
+pub mod tenant;
@@ -0,0 +1,331 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::bail;
+use http::StatusCode;
+
+use crate::util::stats::MovingStats;
+
+const ONE_SECOND: Duration = Duration::from_secs(1);
+
+/// The limits that apply to the queries of one tenant. A limit that is
+/// `None` is not enforced
+#[derive(Clone, Debug, Default)]
+pub struct TenantLimits {
+    /// The maximum number of requests the tenant can make in any one second
+    pub requests_per_second: Option<u32>,
+    /// The maximum total time that the tenant's queries can take within
+    /// the moving window set with `GRAPH_LOAD_WINDOW_SIZE`
+    pub effort_per_window: Option<Duration>,
+    /// The maximum complexity of a single query. This takes precedence
+    /// over `GRAPH_GRAPHQL_MAX_COMPLEXITY`
+    pub max_complexity: Option<u64>,
+}
+
+/// How much a tenant has used the node recently
+struct Usage {
+    /// The start of the second for which we are counting requests
+    second: Instant,
+    requests: u32,
+    effort: MovingStats,
+}
+
+/// A customer of this node who authenticates with one of `tokens` and
+/// whose queries are subject to `limits`
+pub struct Tenant {
+    name: String,
+    tokens: Vec<String>,
+    limits: TenantLimits,
+    usage: Mutex<Usage>,
+}
+
+impl fmt::Debug for Tenant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Never print the tokens
+        f.debug_struct("Tenant")
+            .field("name", &self.name)
+            .field("limits", &self.limits)
+            .finish()
+    }
+}
+
+impl Tenant {
+    pub fn new(name: String, tokens: Vec<String>, limits: TenantLimits) -> Self {
+        let usage = Usage {
+            second: Instant::now(),
+            requests: 0,
+            effort: MovingStats::default(),
+        };
+        Tenant {
+            name,
+            tokens,
+            limits,
+            usage: Mutex::new(usage),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn max_complexity(&self) -> Option<u64> {
+        self.limits.max_complexity
+    }
+
+    /// Count a new request against the tenant's limits and return an
+    /// error if the request would exceed them
+    pub fn admit(&self) -> Result<(), TenantRejection> {
+        let now = Instant::now();
+        let mut usage = self.usage.lock().unwrap();
+
+        if let Some(budget) = self.limits.effort_per_window {
+            let effort = usage.effort.current_duration(now);
+            if effort >= budget {
+                return Err(TenantRejection::EffortBudget(self.name.clone(), budget));
+            }
+        }
+
+        if now.saturating_duration_since(usage.second) >= ONE_SECOND {
+            usage.second = now;
+            usage.requests = 0;
+        }
+        if let Some(limit) = self.limits.requests_per_second {
+            if usage.requests >= limit {
+                return Err(TenantRejection::RequestRate(self.name.clone(), limit));
+            }
+        }
+        usage.requests += 1;
+        Ok(())
+    }
+
+    /// Record that one of the tenant's queries took `duration`
+    pub fn record_effort(&self, duration: Duration) {
+        self.usage.lock().unwrap().effort.add(duration);
+    }
+}
+
+/// The reason why a request was refused
+#[derive(Clone, Debug, PartialEq)]
+pub enum TenantRejection {
+    /// The request did not carry a known bearer token
+    Unauthorized,
+    /// The tenant made more than the given number of requests in one second
+    RequestRate(String, u32),
+    /// The tenant's queries used up their effort budget
+    EffortBudget(String, Duration),
+}
+
+impl TenantRejection {
+    /// The name of the tenant the request belongs to, for use in metrics
+    pub fn tenant(&self) -> &str {
+        match self {
+            TenantRejection::Unauthorized => "unknown",
+            TenantRejection::RequestRate(name, _) | TenantRejection::EffortBudget(name, _) => name,
+        }
+    }
+
+    /// A short name for the reason, for use in metrics
+    pub fn reason(&self) -> &'static str {
+        match self {
+            TenantRejection::Unauthorized => "unauthorized",
+            TenantRejection::RequestRate(_, _) => "request_rate",
+            TenantRejection::EffortBudget(_, _) => "effort",
+        }
+    }
+
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            TenantRejection::Unauthorized => StatusCode::UNAUTHORIZED,
+            TenantRejection::RequestRate(_, _) | TenantRejection::EffortBudget(_, _) => {
+                StatusCode::TOO_MANY_REQUESTS
+            }
+        }
+    }
+}
+
+impl fmt::Display for TenantRejection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TenantRejection::Unauthorized => {
+                write!(f, "a valid API key must be passed as a bearer token")
+            }
+            TenantRejection::RequestRate(_, limit) => write!(
+                f,
+                "too many requests: at most {} requests per second are allowed",
+                limit
+            ),
+            TenantRejection::EffortBudget(_, budget) => write!(
+                f,
+                "query budget exhausted: queries can take at most {}ms within {}s",
+                budget.as_millis(),
+                crate::util::stats::WINDOW_SIZE.as_secs()
+            ),
+        }
+    }
+}
+
+/// All the tenants of this node, indexed by their tokens. Without any
+/// tenants, all requests are allowed and are not subject to any
+/// per-tenant limits
+#[derive(Debug)]
+pub struct Tenants {
+    by_token: HashMap<String, Arc<Tenant>>,
+    allow_anonymous: bool,
+}
+
+impl Default for Tenants {
+    fn default() -> Self {
+        Tenants {
+            by_token: HashMap::new(),
+            allow_anonymous: true,
+        }
+    }
+}
+
+impl Tenants {
+    /// Create the tenants; requests that do not carry a bearer token are
+    /// only allowed if `allow_anonymous` is `true`. Fails if a token is
+    /// listed more than once since we could not tell which tenant a
+    /// request with it belongs to
+    pub fn new(tenants: Vec<Tenant>, allow_anonymous: bool) -> Result<Self, anyhow::Error> {
+        let mut by_token: HashMap<String, Arc<Tenant>> = HashMap::new();
+        for tenant in tenants {
+            let tenant = Arc::new(tenant);
+            for token in &tenant.tokens {
+                if let Some(other) = by_token.insert(token.clone(), tenant.clone()) {
+                    if other.name == tenant.name {
+                        bail!("tenant {} lists the same token twice", tenant.name);
+                    }
+                    bail!(
+                        "tenants {} and {} use the same token",
+                        other.name,
+                        tenant.name
+                    );
+                }
+            }
+        }
+        Ok(Tenants {
+            by_token,
+            allow_anonymous,
+        })
+    }
+
+    /// Find the tenant for the value of an `Authorization` header and
+    /// count the request against their limits. Returns `None` for
+    /// anonymous requests if those are allowed
+    pub fn authenticate(
+        &self,
+        authorization: Option<&str>,
+    ) -> Result<Option<Arc<Tenant>>, TenantRejection> {
+        if self.by_token.is_empty() && self.allow_anonymous {
+            // No tenants are configured; leave the `Authorization` header
+            // to whatever proxy might sit in front of us
+            return Ok(None);
+        }
+
+        let token = authorization.and_then(|value| {
+            let value = value.trim();
+            match value.get(..7) {
+                Some(scheme) if scheme.eq_ignore_ascii_case("bearer ") => Some(value[7..].trim()),
+                _ => None,
+            }
+        });
+
+        match token {
+            Some(token) => {
+                let tenant = self
+                    .by_token
+                    .get(token)
+                    .ok_or(TenantRejection::Unauthorized)?;
+                tenant.admit()?;
+                Ok(Some(tenant.clone()))
+            }
+            None if self.allow_anonymous => Ok(None),
+            None => Err(TenantRejection::Unauthorized),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tenants(limits: TenantLimits) -> Tenants {
+        let tenant = Tenant::new("acme".to_string(), vec!["secret".to_string()], limits);
+        Tenants::new(vec![tenant], false).unwrap()
+    }
+
+    #[test]
+    fn duplicate_tokens() {
+        let tenant = |name: &str, tokens: &[&str]| {
+            let tokens = tokens.iter().map(|token| token.to_string()).collect();
+            Tenant::new(name.to_string(), tokens, TenantLimits::default())
+        };
+
+        assert!(Tenants::new(
+            vec![tenant("acme", &["a"]), tenant("globex", &["b"])],
+            false
+        )
+        .is_ok());
+        assert!(Tenants::new(
+            vec![tenant("acme", &["a"]), tenant("globex", &["a"])],
+            false
+        )
+        .is_err());
+        assert!(Tenants::new(vec![tenant("acme", &["a", "a"])], false).is_err());
+    }
+
+    #[test]
+    fn authenticate() {
+        let tenants = tenants(TenantLimits::default());
+
+        let tenant = tenants.authenticate(Some("Bearer secret")).unwrap();
+        assert_eq!(Some("acme"), tenant.as_ref().map(|tenant| tenant.name()));
+        let tenant = tenants.authenticate(Some("bearer  secret ")).unwrap();
+        assert_eq!(Some("acme"), tenant.as_ref().map(|tenant| tenant.name()));
+
+        for auth in vec![None, Some("Bearer wrong"), Some("Basic secret"), Some("")] {
+            assert_eq!(
+                TenantRejection::Unauthorized,
+                tenants.authenticate(auth).unwrap_err()
+            );
+        }
+
+        let open = Tenants::default();
+        assert!(open.authenticate(None).unwrap().is_none());
+        assert!(open.authenticate(Some("Bearer secret")).unwrap().is_none());
+    }
+
+    #[test]
+    fn limits() {
+        let limits = TenantLimits {
+            requests_per_second: Some(2),
+            ..Default::default()
+        };
+        let tenants = tenants(limits);
+        assert!(tenants.authenticate(Some("Bearer secret")).is_ok());
+        assert!(tenants.authenticate(Some("Bearer secret")).is_ok());
+        assert_eq!(
+            TenantRejection::RequestRate("acme".to_string(), 2),
+            tenants.authenticate(Some("Bearer secret")).unwrap_err()
+        );
+
+        let budget = Duration::from_millis(100);
+        let limits = TenantLimits {
+            effort_per_window: Some(budget),
+            ..Default::default()
+        };
+        let tenants = tenants(limits);
+        let tenant = tenants
+            .authenticate(Some("Bearer secret"))
+            .unwrap()
+            .unwrap();
+        tenant.record_effort(Duration::from_millis(150));
+        assert_eq!(
+            TenantRejection::EffortBudget("acme".to_string(), budget),
+            tenant.admit().unwrap_err()
+        );
+    }
+}
@@ -21,8 +21,8 @@ use web3::types::{Address, H256};
 use crate::blockchain::{Block, Blockchain};
 use crate::components::server::index_node::VersionInfo;
 use crate::components::transaction_receipt;
+use crate::data::store::*;
 use crate::data::subgraph::status;
-use crate::data::{store::*, subgraph::Source};
 use crate::prelude::*;
 use crate::util::lfu_cache::LfuCache;
 use crate::{
@@ -233,6 +233,14 @@ pub struct EntityRange {
 
     /// How many entities to skip.
     pub skip: u32,
+
+    /// Only return entities that come after this cursor in the order of
+    /// the query
+    pub after: Option<EntityCursor>,
+
+    /// Only return entities that come before this cursor in the order of
+    /// the query
+    pub before: Option<EntityCursor>,
 }
 
 impl EntityRange {
@@ -241,8 +249,44 @@ impl EntityRange {
         Self {
             first: Some(n),
             skip: 0,
+            after: None,
+            before: None,
+        }
+    }
+}
+
+/// A position in a list of entities that is ordered by `attribute`. The
+/// cursor holds the value of that attribute for the entity at that
+/// position, and its `id` to break ties between entities with the same
+/// value. Cursors are handed to clients as opaque strings
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct EntityCursor {
+    pub attribute: Attribute,
+    pub value: Value,
+    pub id: String,
+}
+
+impl EntityCursor {
+    pub fn new(attribute: Attribute, value: Value, id: String) -> Self {
+        EntityCursor {
+            attribute,
+            value,
+            id,
         }
     }
+
+    /// Encode the cursor as an opaque string
+    pub fn encode(&self) -> String {
+        hex::encode(serde_json::to_vec(self).expect("cursors can always be serialized"))
+    }
+
+    /// Decode a cursor from the string produced by `encode`
+    pub fn decode(cursor: &str) -> Result<Self, QueryExecutionError> {
+        let invalid =
+            |reason: String| QueryExecutionError::InvalidCursor(cursor.to_owned(), reason);
+        let bytes = hex::decode(cursor).map_err(|e| invalid(e.to_string()))?;
+        serde_json::from_slice(&bytes).map_err(|e| invalid(e.to_string()))
+    }
 }
 
 /// The attribute we want to window by in an `EntityWindow`. We have to
@@ -366,6 +410,19 @@ pub type BlockNumber = i32;
 
 pub const BLOCK_NUMBER_MAX: BlockNumber = std::i32::MAX;
 
+/// A span of blocks; both `from` and `to` are inclusive
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BlockSpan {
+    pub from: BlockNumber,
+    pub to: BlockNumber,
+}
+
+impl BlockSpan {
+    pub fn new(from: BlockNumber, to: BlockNumber) -> Self {
+        Self { from, to }
+    }
+}
+
 /// A query for entities in a store.
 ///
 /// Details of how query generation for `EntityQuery` works can be found
@@ -395,6 +452,11 @@ pub struct EntityQuery {
     /// A range to limit the size of the result.
     pub range: EntityRange,
 
+    /// If set, return all versions of the matching entities that were
+    /// current at some block in the span instead of the versions that
+    /// were current at `block`
+    pub history: Option<BlockSpan>,
+
     /// Optional logger for anything related to this query
     pub logger: Option<Logger>,
 
@@ -416,12 +478,18 @@ impl EntityQuery {
             filter: None,
             order: EntityOrder::Default,
             range: EntityRange::first(100),
+            history: None,
             logger: None,
             query_id: None,
             _force_use_of_new: (),
         }
     }
 
+    pub fn history(mut self, span: BlockSpan) -> Self {
+        self.history = Some(span);
+        self
+    }
+
     pub fn filter(mut self, filter: EntityFilter) -> Self {
         self.filter = Some(filter);
         self
@@ -478,6 +546,168 @@ impl EntityQuery {
     }
 }
 
+/// The aggregate functions that an `AggregateQuery` can compute over an
+/// attribute
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum AggregateFunction {
+    Sum,
+    Min,
+    Max,
+    Avg,
+}
+
+impl AggregateFunction {
+    pub const ALL: [AggregateFunction; 4] = [
+        AggregateFunction::Sum,
+        AggregateFunction::Min,
+        AggregateFunction::Max,
+        AggregateFunction::Avg,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AggregateFunction::Sum => "sum",
+            AggregateFunction::Min => "min",
+            AggregateFunction::Max => "max",
+            AggregateFunction::Avg => "avg",
+        }
+    }
+}
+
+impl fmt::Display for AggregateFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// An aggregate function applied to one attribute of an entity type
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AttributeAggregate {
+    pub attribute: Attribute,
+    pub function: AggregateFunction,
+}
+
+impl AttributeAggregate {
+    pub fn new(attribute: Attribute, function: AggregateFunction) -> Self {
+        Self {
+            attribute,
+            function,
+        }
+    }
+
+    /// The key under which the value of this aggregate is returned. It has
+    /// the form `{attribute}_{function}`, e.g., `amount_sum`
+    pub fn output_name(&self) -> String {
+        format!("{}_{}", self.attribute, self.function)
+    }
+}
+
+/// A query for aggregates of the entities of one type over time. The
+/// aggregates are computed for the blocks `blocks.from`,
+/// `blocks.from + interval`, and so on up to `blocks.to`, and each of them
+/// only takes the entity versions into account that were current at that
+/// block.
+///
+/// If `group_by` is not empty, the entities at each block are grouped by
+/// the values of those attributes, and aggregates are computed for each
+/// group separately.
+///
+/// Each row of the result has the block number under the key `block`, the
+/// number of matching entities under `count`, the value of each group by
+/// attribute under its name, and the value of each aggregate under its
+/// `AttributeAggregate::output_name`. Rows are ordered by block and then
+/// by the group by attributes
+#[derive(Clone, Debug)]
+pub struct AggregateQuery {
+    /// ID of the subgraph.
+    pub subgraph_id: DeploymentHash,
+
+    /// The entity type over which to aggregate
+    pub entity_type: EntityType,
+
+    /// The first and last block for which to compute aggregates
+    pub blocks: BlockSpan,
+
+    /// The number of blocks between two consecutive data points
+    pub interval: BlockNumber,
+
+    /// Only aggregate entities that match this filter
+    pub filter: Option<EntityFilter>,
+
+    /// The aggregates to compute
+    pub aggregates: Vec<AttributeAggregate>,
+
+    /// The attributes by which to group entities
+    pub group_by: Vec<Attribute>,
+
+    /// A range to limit the number of rows in the result
+    pub range: Option<EntityRange>,
+
+    /// Optional logger for anything related to this query
+    pub logger: Option<Logger>,
+
+    pub query_id: Option<String>,
+}
+
+impl AggregateQuery {
+    pub fn new(
+        subgraph_id: DeploymentHash,
+        entity_type: EntityType,
+        blocks: BlockSpan,
+        interval: BlockNumber,
+    ) -> Self {
+        AggregateQuery {
+            subgraph_id,
+            entity_type,
+            blocks,
+            interval,
+            filter: None,
+            aggregates: vec![],
+            group_by: vec![],
+            range: None,
+            logger: None,
+            query_id: None,
+        }
+    }
+
+    /// A query for aggregates over the entities that are current at `block`
+    pub fn at_block(
+        subgraph_id: DeploymentHash,
+        entity_type: EntityType,
+        block: BlockNumber,
+    ) -> Self {
+        Self::new(subgraph_id, entity_type, BlockSpan::new(block, block), 1)
+    }
+
+    pub fn filter(mut self, filter: EntityFilter) -> Self {
+        self.filter = Some(filter);
+        self
+    }
+
+    pub fn aggregates(mut self, aggregates: Vec<AttributeAggregate>) -> Self {
+        self.aggregates = aggregates;
+        self
+    }
+
+    pub fn group_by(mut self, group_by: Vec<Attribute>) -> Self {
+        self.group_by = group_by;
+        self
+    }
+
+    pub fn range(mut self, range: EntityRange) -> Self {
+        self.range = Some(range);
+        self
+    }
+
+    /// The number of data points this query produces
+    pub fn data_points(&self) -> i64 {
+        if self.blocks.to < self.blocks.from || self.interval <= 0 {
+            return 0;
+        }
+        (self.blocks.to as i64 - self.blocks.from as i64) / self.interval as i64 + 1
+    }
+}
+
 /// Operation types that lead to entity changes.
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
 #[serde(rename_all = "lowercase")]
@@ -613,6 +843,39 @@ impl PartialEq for StoreEvent {
     }
 }
 
+/// How a block changed an entity
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EntityChangeKind {
+    Insert,
+    Update,
+    Delete,
+}
+
+/// A change that a block made to an entity, reconstructed from the
+/// versions of the entity in the store. For inserts and updates, `data` is
+/// the entity as it was written by `block`; for deletes, it is the last
+/// version of the entity before it was deleted
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct EntityVersionChange {
+    pub block: BlockNumber,
+    pub entity_type: EntityType,
+    pub entity_id: String,
+    pub kind: EntityChangeKind,
+    pub data: Entity,
+}
+
+/// The changes that a range of blocks made to the entities of a deployment
+#[derive(Clone, Debug)]
+pub struct EntityChanges {
+    /// The block pointer of the deployment when the changes were read. The
+    /// changes are consistent with the chain that ends in this block
+    pub head: Option<BlockPtr>,
+    /// The changes, ordered by block, entity type and entity id
+    pub changes: Vec<EntityVersionChange>,
+}
+
 /// A `StoreEventStream` produces the `StoreEvents`. Various filters can be applied
 /// to it to reduce which and how many events are delivered by the stream.
 pub struct StoreEventStream<S> {
@@ -841,9 +1104,18 @@ impl From<std::fmt::Error> for StoreError {
     }
 }
 
+/// A dynamic data source in the form in which it is stored. Each chain
+/// only fills in the fields that make sense for its data sources
+#[derive(Clone, Debug)]
 pub struct StoredDynamicDataSource {
     pub name: String,
-    pub source: Source,
+    /// The address of the contract; only used for Ethereum
+    pub address: Option<Address>,
+    /// The account the data source is for; only used for NEAR
+    pub account: Option<String>,
+    /// The name of the ABI of the contract. Empty for chains without ABIs
+    pub abi: String,
+    pub start_block: BlockNumber,
     pub context: Option<String>,
     pub creation_block: Option<BlockNumber>,
 }
@@ -1001,6 +1273,15 @@ pub trait SubgraphStore: Send + Sync + 'static {
     fn locators(&self, hash: &str) -> Result<Vec<DeploymentLocator>, StoreError>;
 }
 
+/// The changes that processing one block made, for writing the changes of
+/// several blocks at once with `WritableStore::transact_block_batch`
+pub struct BlockOperations {
+    pub block_ptr: BlockPtr,
+    pub firehose_cursor: Option<String>,
+    pub mods: Vec<EntityModification>,
+    pub data_sources: Vec<StoredDynamicDataSource>,
+}
+
 /// A view of the store for indexing. All indexing-related operations need
 /// to go through this trait. Methods in this trait will never return a
 /// `StoreError::DatabaseUnavailable`. Instead, they will retry the
@@ -1057,6 +1338,32 @@ pub trait WritableStore: Send + Sync + 'static {
         deterministic_errors: Vec<SubgraphError>,
     ) -> Result<(), StoreError>;
 
+    /// Transact the entity changes from several consecutive blocks into the
+    /// store, and update the subgraph block pointer and the firehose cursor
+    /// to those of the last block in `blocks`. The changes of each block are
+    /// written with that block's number, so that the resulting entity
+    /// versions are the same as if the blocks had been transacted one by one.
+    ///
+    /// The default implementation transacts the blocks one by one; stores
+    /// should write all of them in one transaction.
+    fn transact_block_batch(
+        &self,
+        blocks: Vec<BlockOperations>,
+        stopwatch: StopwatchMetrics,
+    ) -> Result<(), StoreError> {
+        for block in blocks {
+            self.transact_block_operations(
+                block.block_ptr,
+                block.firehose_cursor,
+                block.mods,
+                stopwatch.cheap_clone(),
+                block.data_sources,
+                Vec::new(),
+            )?;
+        }
+        Ok(())
+    }
+
     /// Look up multiple entities as of the latest block. Returns a map of
     /// entities by type.
     fn get_many(
@@ -1392,6 +1699,26 @@ pub trait QueryStore: Send + Sync {
         query: EntityQuery,
     ) -> Result<Vec<BTreeMap<String, r::Value>>, QueryExecutionError>;
 
+    /// Compute the aggregates described by `query`. See `AggregateQuery`
+    /// for the layout of the returned rows
+    fn aggregate(
+        &self,
+        query: AggregateQuery,
+    ) -> Result<Vec<BTreeMap<String, r::Value>>, QueryExecutionError>;
+
+    /// Return the changes that the blocks in `blocks` made to the entities
+    /// of the deployment, together with the block pointer of the deployment
+    /// at the time the changes were read
+    fn entity_changes(&self, blocks: BlockSpan) -> Result<EntityChanges, StoreError>;
+
+    /// Return `block` followed by up to `count` of its ancestors, going
+    /// backwards. The list ends early when an ancestor is not in the block
+    /// cache of the deployment's chain
+    fn ancestors(&self, block: &BlockPtr, count: BlockNumber) -> Result<Vec<BlockPtr>, StoreError>;
+
+    /// Return the head of the chain the deployment indexes, if it is known
+    fn chain_head_ptr(&self) -> Result<Option<BlockPtr>, StoreError>;
+
     async fn is_deployment_synced(&self) -> Result<bool, Error>;
 
     fn block_ptr(&self) -> Result<Option<BlockPtr>, StoreError>;
@@ -1443,6 +1770,13 @@ pub trait StatusStore: Send + Sync + 'static {
         deployment_hash: &str,
     ) -> Result<Vec<(String, String)>, StoreError>;
 
+    /// Statistics about the tables of the active deployment `subgraph_id`,
+    /// or `None` if there is no such deployment
+    fn deployment_stats(
+        &self,
+        subgraph_id: &DeploymentHash,
+    ) -> Result<Option<status::DeploymentStats>, StoreError>;
+
     /// A value of None indicates that the table is not available. Re-deploying
     /// the subgraph fixes this. It is undesirable to force everything to
     /// re-sync from scratch, so existing deployments will continue without a
@@ -1456,6 +1790,25 @@ pub trait StatusStore: Send + Sync + 'static {
     ) -> Result<Option<[u8; 32]>, StoreError>;
 }
 
+/// The registry of persisted queries. Query documents are identified by
+/// the hex-encoded SHA-256 hash of their text, and the registry is shared
+/// by all query nodes
+#[async_trait]
+pub trait PersistedQueryStore: Send + Sync + 'static {
+    /// Return the text of the query registered under `hash`
+    async fn persisted_query(&self, hash: &str) -> Result<Option<String>, StoreError>;
+
+    /// Register `query` under `hash`. Registering a hash that is already
+    /// known does nothing
+    async fn register_persisted_query(&self, hash: &str, query: &str) -> Result<(), StoreError>;
+
+    /// Return all registered queries as `(hash, query)` pairs
+    async fn persisted_queries(&self) -> Result<Vec<(String, String)>, StoreError>;
+
+    /// Return how many queries are registered
+    async fn persisted_query_count(&self) -> Result<usize, StoreError>;
+}
+
 /// An entity operation that can be transacted into the store; as opposed to
 /// `EntityOperation`, we already know whether a `Set` should be an `Insert`
 /// or `Update`
@@ -1549,6 +1902,19 @@ pub struct EntityCache {
 
     data_sources: Vec<StoredDynamicDataSource>,
 
+    /// If this is set, handlers are only supposed to access entities of
+    /// these types. See `fork`
+    entity_types: Option<HashSet<EntityType>>,
+
+    // Marks whether an entity of a type outside of `entity_types` was
+    // accessed.
+    undeclared_access: bool,
+
+    /// The number of lookups that were answered from `current`, and the
+    /// number that had to go to the store
+    hits: u64,
+    misses: u64,
+
     /// The store is only used to read entities.
     pub store: Arc<dyn WritableStore>,
 }
@@ -1576,6 +1942,10 @@ impl EntityCache {
             handler_updates: HashMap::new(),
             in_handler: false,
             data_sources: vec![],
+            entity_types: None,
+            undeclared_access: false,
+            hits: 0,
+            misses: 0,
             store,
         }
     }
@@ -1590,6 +1960,10 @@ impl EntityCache {
             handler_updates: HashMap::new(),
             in_handler: false,
             data_sources: vec![],
+            entity_types: None,
+            undeclared_access: false,
+            hits: 0,
+            misses: 0,
             store,
         }
     }
@@ -1617,6 +1991,14 @@ impl EntityCache {
     }
 
     pub fn get(&mut self, key: &EntityKey) -> Result<Option<Entity>, QueryExecutionError> {
+        self.check_access(key);
+
+        if self.current.contains_key(key) {
+            self.hits += 1;
+        } else {
+            self.misses += 1;
+        }
+
         // Get the current entity, apply any updates from `updates`, then from `handler_updates`.
         let mut entity = self.current.get_entity(&*self.store, key)?;
         if let Some(op) = self.updates.get(key).cloned() {
@@ -1628,14 +2010,62 @@ impl EntityCache {
         Ok(entity)
     }
 
+    /// The number of lookups through `get` that were answered from the
+    /// cache, and the number that had to go to the store
+    pub fn hits_and_misses(&self) -> (u64, u64) {
+        (self.hits, self.misses)
+    }
+
     pub fn remove(&mut self, key: EntityKey) {
+        self.check_access(&key);
         self.entity_op(key, EntityOp::Remove);
     }
 
     pub fn set(&mut self, key: EntityKey, entity: Entity) {
+        self.check_access(&key);
         self.entity_op(key, EntityOp::Update(entity))
     }
 
+    fn check_access(&mut self, key: &EntityKey) {
+        if let Some(entity_types) = &self.entity_types {
+            if !entity_types.contains(&key.entity_type) {
+                self.undeclared_access = true;
+            }
+        }
+    }
+
+    /// Create a cache for handlers that only access entities of
+    /// `entity_types`. The new cache takes over the cached entities of
+    /// these types; changes made through it have to be added back with
+    /// `extend`. Changes to these types that were made through `self`
+    /// would not be visible in the new cache, and there must not be any
+    pub fn fork(&mut self, entity_types: HashSet<EntityType>) -> EntityCache {
+        assert!(!self.in_handler);
+        assert!(self
+            .updates
+            .keys()
+            .all(|key| !entity_types.contains(&key.entity_type)));
+
+        let current = self
+            .current
+            .split_off(|key| entity_types.contains(&key.entity_type));
+        let mut fork = EntityCache::with_current(self.store.cheap_clone(), current);
+        fork.entity_types = Some(entity_types);
+        fork
+    }
+
+    /// Return `true` if a handler accessed an entity whose type is not
+    /// one of the types the cache was forked for
+    pub fn has_undeclared_access(&self) -> bool {
+        self.undeclared_access
+    }
+
+    /// Take over the entities that `other` read from the store, but
+    /// discard all the changes that were made through it
+    pub fn extend_current(&mut self, other: EntityCache) {
+        self.current.merge(other.current);
+    }
+
     pub fn append(&mut self, operations: Vec<EntityOperation>) {
         assert!(!self.in_handler);
 
@@ -1652,9 +2082,13 @@ impl EntityCache {
     }
 
     /// Add a dynamic data source
-    pub fn add_data_source<C: Blockchain>(&mut self, data_source: &impl DataSource<C>) {
+    pub fn add_data_source<C: Blockchain>(
+        &mut self,
+        data_source: &impl DataSource<C>,
+    ) -> Result<(), anyhow::Error> {
         self.data_sources
-            .push(data_source.as_stored_dynamic_data_source());
+            .push(data_source.as_stored_dynamic_data_source()?);
+        Ok(())
     }
 
     fn entity_op(&mut self, key: EntityKey, op: EntityOp) {
@@ -1672,10 +2106,10 @@ impl EntityCache {
         }
     }
 
-    pub(crate) fn extend(&mut self, other: EntityCache) {
+    pub fn extend(&mut self, other: EntityCache) {
         assert!(!other.in_handler);
 
-        self.current.extend(other.current);
+        self.current.merge(other.current);
         for (key, op) in other.updates {
             self.entity_op(key, op);
         }
@@ -11,6 +11,8 @@ use crate::prelude::*;
 use crate::{blockchain::Blockchain, components::subgraph::SharedProofOfIndexing};
 use crate::{components::metrics::HistogramVec, runtime::DeterministicHostError};
 
+use super::profile::{DeploymentProfile, HandlerRun};
+
 #[derive(Debug)]
 pub enum MappingError {
     /// A possible reorg was detected while running the mapping.
@@ -62,12 +64,19 @@ pub trait RuntimeHost<C: Blockchain>: Send + Sync + 'static {
     /// Block number in which this host was created.
     /// Returns `None` for static data sources.
     fn creation_block_number(&self) -> Option<BlockNumber>;
+
+    /// The data source this host runs the mappings of.
+    fn data_source(&self) -> &C::DataSource;
 }
 
 pub struct HostMetrics {
     handler_execution_time: Box<HistogramVec>,
     host_fn_execution_time: Box<HistogramVec>,
+    handler_gas: Box<CounterVec>,
+    handler_host_fn_time: Box<CounterVec>,
+    handler_entity_cache: Box<CounterVec>,
     pub stopwatch: StopwatchMetrics,
+    pub profile: Arc<DeploymentProfile>,
 }
 
 impl HostMetrics {
@@ -94,10 +103,38 @@ impl HostMetrics {
                 vec![0.025, 0.05, 0.2, 2.0, 8.0, 20.0],
             )
             .expect("failed to create `deployment_host_fn_execution_time` histogram");
+        let handler_gas = registry
+            .new_deployment_counter_vec(
+                "deployment_handler_gas",
+                "Counts the gas used by each handler",
+                subgraph,
+                vec![String::from("handler")],
+            )
+            .expect("failed to create `deployment_handler_gas` counter");
+        let handler_host_fn_time = registry
+            .new_deployment_counter_vec(
+                "deployment_handler_host_fn_time",
+                "Counts the time in seconds each handler spent in host functions",
+                subgraph,
+                vec![String::from("handler"), String::from("host_fn_name")],
+            )
+            .expect("failed to create `deployment_handler_host_fn_time` counter");
+        let handler_entity_cache = registry
+            .new_deployment_counter_vec(
+                "deployment_handler_entity_cache",
+                "Counts the entity lookups of each handler that hit or missed the entity cache",
+                subgraph,
+                vec![String::from("handler"), String::from("result")],
+            )
+            .expect("failed to create `deployment_handler_entity_cache` counter");
         Self {
             handler_execution_time,
             host_fn_execution_time,
+            handler_gas,
+            handler_host_fn_time,
+            handler_entity_cache,
             stopwatch,
+            profile: Arc::new(DeploymentProfile::default()),
         }
     }
 
@@ -113,6 +150,26 @@ impl HostMetrics {
             .observe(duration);
     }
 
+    /// Record what happened while the handler `handler` ran, both in the
+    /// profile of the deployment and in Prometheus
+    pub fn observe_handler_run(&self, handler: &str, run: &HandlerRun) {
+        self.handler_gas
+            .with_label_values(&[handler][..])
+            .inc_by(run.gas as f64);
+        for (host_fn, stats) in &run.host_fns {
+            self.handler_host_fn_time
+                .with_label_values(&[handler, host_fn][..])
+                .inc_by(stats.time.as_secs_f64());
+        }
+        self.handler_entity_cache
+            .with_label_values(&[handler, "hit"][..])
+            .inc_by(run.cache_hits as f64);
+        self.handler_entity_cache
+            .with_label_values(&[handler, "miss"][..])
+            .inc_by(run.cache_misses as f64);
+        self.profile.record(handler, run);
+    }
+
     pub fn time_host_fn_execution_region(
         self: Arc<HostMetrics>,
         fn_name: &'static str,
@@ -1,7 +1,12 @@
+use std::collections::HashSet;
+
 use crate::blockchain::Blockchain;
 use crate::prelude::*;
 use crate::util::lfu_cache::LfuCache;
-use crate::{components::store::WritableStore, data::subgraph::schema::SubgraphError};
+use crate::{
+    components::store::{EntityType, WritableStore},
+    data::subgraph::schema::SubgraphError,
+};
 
 #[derive(Clone, Debug)]
 pub struct DataSourceTemplateInfo<C: Blockchain> {
@@ -38,6 +43,21 @@ impl<C: Blockchain> BlockState<C> {
         }
     }
 
+    /// Create a block state for handlers that only access entities of
+    /// `entity_types`; see `EntityCache::fork`. Use `extend` to add the
+    /// results of these handlers back to `self`
+    pub fn fork(&mut self, entity_types: HashSet<EntityType>) -> Self {
+        assert!(!self.in_handler);
+
+        BlockState {
+            entity_cache: self.entity_cache.fork(entity_types),
+            deterministic_errors: Vec::new(),
+            created_data_sources: Vec::new(),
+            handler_created_data_sources: Vec::new(),
+            in_handler: false,
+        }
+    }
+
     pub fn extend(&mut self, other: BlockState<C>) {
         assert!(!other.in_handler);
 
@@ -1,6 +1,7 @@
 mod host;
 mod instance;
 mod instance_manager;
+mod profile;
 mod proof_of_indexing;
 mod provider;
 mod registrar;
@@ -10,6 +11,9 @@ pub use crate::prelude::Entity;
 pub use self::host::{HostMetrics, MappingError, RuntimeHost, RuntimeHostBuilder};
 pub use self::instance::{BlockState, DataSourceTemplateInfo};
 pub use self::instance_manager::SubgraphInstanceManager;
+pub use self::profile::{
+    DeploymentProfile, HandlerRun, HandlerStats, HostFnStats, SubgraphProfiles,
+};
 pub use self::proof_of_indexing::{
     BlockEventStream, CausalityRegion, ProofOfIndexing, ProofOfIndexingEvent,
     ProofOfIndexingFinisher, SharedProofOfIndexing,
@@ -0,0 +1,185 @@
+//! Per-handler profiles of the mappings of a deployment, so that subgraph
+//! authors can find out which of their handlers are slow and why.
+//!
+//! While a handler runs, the wasm runtime collects a `HandlerRun` with the
+//! time spent in each host export and the entity cache lookups. When the
+//! handler is done, the run is added to the `DeploymentProfile` of the
+//! deployment. The profiles of all deployments this node indexes are kept in
+//! `SubgraphProfiles` so that the index node can serve them.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use crate::prelude::DeploymentHash;
+
+/// How often a host export was called and how long the calls took
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct HostFnStats {
+    pub calls: u64,
+    pub time: Duration,
+}
+
+impl HostFnStats {
+    fn add(&mut self, other: &HostFnStats) {
+        self.calls += other.calls;
+        self.time += other.time;
+    }
+}
+
+/// What happened during one invocation of a handler
+#[derive(Clone, Debug, Default)]
+pub struct HandlerRun {
+    /// The time the handler ran, including the time spent in host exports
+    pub time: Duration,
+    /// The gas the handler used
+    pub gas: u64,
+    pub host_fns: HashMap<&'static str, HostFnStats>,
+    /// Entity lookups that were answered from the entity cache
+    pub cache_hits: u64,
+    /// Entity lookups that had to go to the store
+    pub cache_misses: u64,
+}
+
+impl HandlerRun {
+    /// Record a call of the host export `name` that took `time`
+    pub fn host_fn(&mut self, name: &'static str, time: Duration) {
+        let stats = self.host_fns.entry(name).or_default();
+        stats.calls += 1;
+        stats.time += time;
+    }
+}
+
+/// The accumulated runs of one handler
+#[derive(Clone, Debug, Default)]
+pub struct HandlerStats {
+    pub calls: u64,
+    pub time: Duration,
+    pub gas: u64,
+    pub host_fns: BTreeMap<String, HostFnStats>,
+    pub cache_hits: u64,
+    pub cache_misses: u64,
+}
+
+impl HandlerStats {
+    fn add(&mut self, run: &HandlerRun) {
+        self.calls += 1;
+        self.time += run.time;
+        self.gas += run.gas;
+        for (name, stats) in &run.host_fns {
+            self.host_fns
+                .entry(name.to_string())
+                .or_default()
+                .add(stats);
+        }
+        self.cache_hits += run.cache_hits;
+        self.cache_misses += run.cache_misses;
+    }
+}
+
+/// The profile of all handlers of one deployment since the deployment was
+/// started on this node
+#[derive(Debug, Default)]
+pub struct DeploymentProfile {
+    handlers: Mutex<BTreeMap<String, HandlerStats>>,
+}
+
+impl DeploymentProfile {
+    pub fn record(&self, handler: &str, run: &HandlerRun) {
+        let mut handlers = self.handlers.lock().unwrap();
+        match handlers.get_mut(handler) {
+            Some(stats) => stats.add(run),
+            None => {
+                let mut stats = HandlerStats::default();
+                stats.add(run);
+                handlers.insert(handler.to_string(), stats);
+            }
+        }
+    }
+
+    /// The stats for all handlers, ordered by handler name
+    pub fn handlers(&self) -> Vec<(String, HandlerStats)> {
+        self.handlers
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(handler, stats)| (handler.clone(), stats.clone()))
+            .collect()
+    }
+}
+
+/// The profiles of all deployments that are running on this node
+#[derive(Debug, Default)]
+pub struct SubgraphProfiles {
+    deployments: RwLock<HashMap<DeploymentHash, Arc<DeploymentProfile>>>,
+}
+
+impl SubgraphProfiles {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&self, deployment: DeploymentHash, profile: Arc<DeploymentProfile>) {
+        self.deployments
+            .write()
+            .unwrap()
+            .insert(deployment, profile);
+    }
+
+    pub fn remove(&self, deployment: &DeploymentHash) {
+        self.deployments.write().unwrap().remove(deployment);
+    }
+
+    pub fn get(&self, deployment: &DeploymentHash) -> Option<Arc<DeploymentProfile>> {
+        self.deployments.read().unwrap().get(deployment).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accumulates_runs() {
+        let profile = DeploymentProfile::default();
+
+        let mut run = HandlerRun {
+            time: Duration::from_millis(10),
+            gas: 700,
+            cache_misses: 2,
+            ..Default::default()
+        };
+        run.host_fn("store.get", Duration::from_millis(2));
+        run.host_fn("store.get", Duration::from_millis(3));
+        profile.record("handleTransfer", &run);
+
+        let mut run = HandlerRun {
+            time: Duration::from_millis(5),
+            cache_hits: 1,
+            ..Default::default()
+        };
+        run.host_fn("store.set", Duration::from_millis(1));
+        profile.record("handleTransfer", &run);
+        profile.record("handleApproval", &HandlerRun::default());
+
+        let handlers = profile.handlers();
+        assert_eq!(
+            vec!["handleApproval", "handleTransfer"],
+            handlers.iter().map(|(h, _)| h.as_str()).collect::<Vec<_>>()
+        );
+        let stats = &handlers[1].1;
+        assert_eq!(2, stats.calls);
+        assert_eq!(Duration::from_millis(15), stats.time);
+        assert_eq!(700, stats.gas);
+        assert_eq!(1, stats.cache_hits);
+        assert_eq!(2, stats.cache_misses);
+        assert_eq!(
+            Some(&HostFnStats {
+                calls: 2,
+                time: Duration::from_millis(5)
+            }),
+            stats.host_fns.get("store.get")
+        );
+        assert_eq!(1, stats.host_fns.get("store.set").unwrap().calls);
+    }
+}
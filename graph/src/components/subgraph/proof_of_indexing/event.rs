@@ -84,6 +84,70 @@ impl StableHash for ProofOfIndexingEvent<'_> {
     }
 }
 
+/// An owned copy of a `ProofOfIndexingEvent` that is kept around until it
+/// can be added to the digest
+pub(super) enum RecordedEvent {
+    RemoveEntity {
+        entity_type: String,
+        id: String,
+    },
+    SetEntity {
+        entity_type: String,
+        id: String,
+        data: HashMap<String, Value>,
+    },
+    DeterministicError {
+        redacted_events: u64,
+    },
+}
+
+impl RecordedEvent {
+    pub(super) fn from_event(event: &ProofOfIndexingEvent<'_>) -> Self {
+        match event {
+            ProofOfIndexingEvent::RemoveEntity { entity_type, id } => RecordedEvent::RemoveEntity {
+                entity_type: entity_type.to_string(),
+                id: id.to_string(),
+            },
+            ProofOfIndexingEvent::SetEntity {
+                entity_type,
+                id,
+                data,
+            } => RecordedEvent::SetEntity {
+                entity_type: entity_type.to_string(),
+                id: id.to_string(),
+                data: (*data).clone(),
+            },
+            ProofOfIndexingEvent::DeterministicError { redacted_events } => {
+                RecordedEvent::DeterministicError {
+                    redacted_events: *redacted_events,
+                }
+            }
+        }
+    }
+
+    pub(super) fn as_event(&self) -> ProofOfIndexingEvent<'_> {
+        match self {
+            RecordedEvent::RemoveEntity { entity_type, id } => {
+                ProofOfIndexingEvent::RemoveEntity { entity_type, id }
+            }
+            RecordedEvent::SetEntity {
+                entity_type,
+                id,
+                data,
+            } => ProofOfIndexingEvent::SetEntity {
+                entity_type,
+                id,
+                data,
+            },
+            RecordedEvent::DeterministicError { redacted_events } => {
+                ProofOfIndexingEvent::DeterministicError {
+                    redacted_events: *redacted_events,
+                }
+            }
+        }
+    }
+}
+
 /// Different than #[derive(Debug)] in order to be deterministic so logs can be
 /// diffed easily. In particular, we swap out the HashMap for a BTreeMap when
 /// printing the data field of the SetEntity variant so that the keys are
@@ -262,4 +262,48 @@ mod tests {
             }
         }
     }
+
+    /// Recording events and replaying them later must give the same digest
+    /// as writing the events directly
+    #[test]
+    fn record_and_replay() {
+        let logger = Logger::root(Discard, o!());
+        let data = hashmap! {
+            "val".to_owned() => Value::Int(1)
+        };
+        let events = vec![
+            ProofOfIndexingEvent::SetEntity {
+                entity_type: "t",
+                id: "1",
+                data: &data,
+            },
+            ProofOfIndexingEvent::RemoveEntity {
+                entity_type: "t",
+                id: "2",
+            },
+        ];
+
+        let mut direct = ProofOfIndexing::new(7);
+        direct.start_handler("eth");
+        for event in &events {
+            direct.write(&logger, "eth", event);
+        }
+
+        let mut recording = ProofOfIndexing::recording(7);
+        recording.start_handler("eth");
+        for event in &events {
+            recording.write(&logger, "eth", event);
+        }
+        let mut replayed = ProofOfIndexing::new(7);
+        replayed.start_handler("eth");
+        recording.replay(&logger, &mut replayed);
+
+        let digest = |poi: ProofOfIndexing| {
+            poi.take()
+                .into_iter()
+                .map(|(name, stream)| (name, stream.pause(None)))
+                .collect::<HashMap<_, _>>()
+        };
+        assert_eq!(digest(direct), digest(replayed));
+    }
 }
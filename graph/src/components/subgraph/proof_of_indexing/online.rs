@@ -2,6 +2,7 @@
 //! Any hash constructed from here should be the same as if the same data was given
 //! to the reference implementation, but this is updated incrementally
 
+use super::event::RecordedEvent;
 use super::ProofOfIndexingEvent;
 use crate::{
     blockchain::BlockPtr,
@@ -116,6 +117,10 @@ pub struct ProofOfIndexing {
     /// state with other data sources. This may also give us some freedom to change
     /// the order of triggers in the future.
     per_causality_region: HashMap<String, BlockEventStream>,
+    /// When this is set, events are not added to the digest but kept here
+    /// in the order in which they were written so that they can be
+    /// replayed into another `ProofOfIndexing` later
+    recorded: Option<Vec<(String, RecordedEvent)>>,
 }
 
 impl fmt::Debug for ProofOfIndexing {
@@ -129,10 +134,42 @@ impl ProofOfIndexing {
         Self {
             block_number,
             per_causality_region: HashMap::new(),
+            recorded: None,
+        }
+    }
+
+    /// Create a `ProofOfIndexing` that only records events; use `replay`
+    /// to add them to the digest of another `ProofOfIndexing`. This makes
+    /// it possible to run handlers concurrently and still add their events
+    /// in the order in which the handlers would have run one after the
+    /// other
+    pub fn recording(block_number: BlockNumber) -> Self {
+        Self {
+            block_number,
+            per_causality_region: HashMap::new(),
+            recorded: Some(Vec::new()),
+        }
+    }
+
+    /// Write all events recorded by `self` into `other`
+    ///
+    /// # Panics
+    ///
+    /// Panics if `self` was not created with `recording`
+    pub fn replay(self, logger: &Logger, other: &mut ProofOfIndexing) {
+        let recorded = self
+            .recorded
+            .expect("only a recording ProofOfIndexing can be replayed");
+        for (causality_region, event) in recorded {
+            other.write(logger, &causality_region, &event.as_event());
         }
     }
 
     pub fn write_deterministic_error(&mut self, logger: &Logger, causality_region: &str) {
+        assert!(
+            self.recorded.is_none(),
+            "deterministic errors can not be recorded"
+        );
         let redacted_events = self.with_causality_region(causality_region, |entry| {
             entry.vec_length - entry.handler_start
         });
@@ -150,6 +187,14 @@ impl ProofOfIndexing {
         causality_region: &str,
         event: &ProofOfIndexingEvent<'_>,
     ) {
+        if let Some(recorded) = &mut self.recorded {
+            recorded.push((
+                causality_region.to_owned(),
+                RecordedEvent::from_event(event),
+            ));
+            return;
+        }
+
         if *LOG_EVENTS {
             debug!(
                 logger,
@@ -163,6 +208,10 @@ impl ProofOfIndexing {
     }
 
     pub fn start_handler(&mut self, causality_region: &str) {
+        if self.recorded.is_some() {
+            // Whoever replays the events starts the handler
+            return;
+        }
         self.with_causality_region(causality_region, |entry| entry.start_handler())
     }
 
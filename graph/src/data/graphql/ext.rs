@@ -1,5 +1,5 @@
 use super::ObjectOrInterface;
-use crate::data::schema::{META_FIELD_TYPE, SCHEMA_TYPE_NAME};
+use crate::data::schema::{AGGREGATION_DIRECTIVE, META_FIELD_TYPE, SCHEMA_TYPE_NAME};
 use crate::prelude::s::{
     Definition, Directive, Document, EnumType, Field, InterfaceType, ObjectType, Type,
     TypeDefinition, Value,
@@ -18,6 +18,7 @@ lazy_static! {
 pub trait ObjectTypeExt {
     fn field(&self, name: &str) -> Option<&Field>;
     fn is_meta(&self) -> bool;
+    fn is_aggregation(&self) -> bool;
 }
 
 impl ObjectTypeExt for ObjectType {
@@ -28,6 +29,12 @@ impl ObjectTypeExt for ObjectType {
     fn is_meta(&self) -> bool {
         self.name == META_FIELD_TYPE
     }
+
+    fn is_aggregation(&self) -> bool {
+        self.directives
+            .iter()
+            .any(|directive| directive.name == AGGREGATION_DIRECTIVE)
+    }
 }
 
 impl ObjectTypeExt for InterfaceType {
@@ -38,6 +45,10 @@ impl ObjectTypeExt for InterfaceType {
     fn is_meta(&self) -> bool {
         false
     }
+
+    fn is_aggregation(&self) -> bool {
+        false
+    }
 }
 
 pub trait DocumentExt {
@@ -143,4 +143,11 @@ impl<'a> ObjectOrInterface<'a> {
             ObjectOrInterface::Interface(i) => i.is_meta(),
         }
     }
+
+    pub fn is_aggregation(&self) -> bool {
+        match self {
+            ObjectOrInterface::Object(o) => o.is_aggregation(),
+            ObjectOrInterface::Interface(i) => i.is_aggregation(),
+        }
+    }
 }
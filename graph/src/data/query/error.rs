@@ -10,7 +10,7 @@ use std::sync::Arc;
 
 use crate::data::graphql::SerializableValue;
 use crate::data::subgraph::*;
-use crate::prelude::q;
+use crate::prelude::{q, BlockNumber};
 use crate::{components::store::StoreError, prelude::CacheWeight};
 
 #[derive(Debug)]
@@ -81,6 +81,13 @@ pub enum QueryExecutionError {
     SubgraphManifestResolveError(Arc<SubgraphManifestResolveError>),
     InvalidSubgraphManifest,
     ResultTooBig(usize, usize),
+    InvalidBlockSpan(i64, i64, BlockNumber), // (fromBlock, toBlock, latest block)
+    TooManyIntervals(i64, i64),              // (data points, max data points)
+    InvalidCursor(String, String),           // (cursor, reason)
+    HistoryPruned(BlockNumber, BlockNumber), // (requested block, earliest block)
+    PersistedQueryNotFound,
+    PersistedQueryHashMismatch(String),
+    QueryNotAllowed,
 }
 
 impl QueryExecutionError {
@@ -113,7 +120,8 @@ impl QueryExecutionError {
             | Unimplemented(_)
             | CyclicalFragment(_)
             | UndefinedFragment(_)
-            | FulltextQueryRequiresFilter => true,
+            | FulltextQueryRequiresFilter
+            | InvalidCursor(_, _) => true,
             NonNullError(_, _)
             | ListValueError(_, _)
             | ResolveEntitiesError(_)
@@ -135,7 +143,13 @@ impl QueryExecutionError {
             | DeploymentReverted
             | SubgraphManifestResolveError(_)
             | InvalidSubgraphManifest
-            | ResultTooBig(_, _) => false,
+            | ResultTooBig(_, _)
+            | InvalidBlockSpan(_, _, _)
+            | TooManyIntervals(_, _)
+            | HistoryPruned(_, _)
+            | PersistedQueryNotFound
+            | PersistedQueryHashMismatch(_)
+            | QueryNotAllowed => false,
         }
     }
 }
@@ -279,6 +293,15 @@ impl fmt::Display for QueryExecutionError {
             SubgraphManifestResolveError(e) => write!(f, "failed to resolve subgraph manifest: {}", e),
             InvalidSubgraphManifest => write!(f, "invalid subgraph manifest file"),
             ResultTooBig(actual, limit) => write!(f, "the result size of {} is larger than the allowed limit of {}", actual, limit),
+            InvalidBlockSpan(from, to, latest) => write!(f, "the block span from `fromBlock` {} to `toBlock` {} is invalid; the blocks must satisfy 0 <= fromBlock <= toBlock <= {}", from, to, latest),
+            TooManyIntervals(points, limit) => write!(f, "the query would produce {} data points which is more than the allowed limit of {}; use a larger `interval` or a smaller block span", points, limit),
+            InvalidCursor(cursor, reason) => write!(f, "invalid cursor `{}`: {}", cursor, reason),
+            HistoryPruned(block, earliest) => write!(f, "the history of this subgraph has been pruned and data for block number {} is no longer available; the earliest block that can be queried is {}", block, earliest),
+            // Clients that implement the persisted query protocol look for
+            // exactly this message
+            PersistedQueryNotFound => write!(f, "PersistedQueryNotFound"),
+            PersistedQueryHashMismatch(hash) => write!(f, "the query does not match the persisted query hash `{}`", hash),
+            QueryNotAllowed => write!(f, "this endpoint only runs queries that have been registered as persisted queries"),
         }
     }
 }
@@ -235,6 +235,10 @@ impl QueryResult {
         self.data = data
     }
 
+    pub fn errors(&self) -> &[QueryError] {
+        &self.errors
+    }
+
     pub fn errors_mut(&mut self) -> &mut Vec<QueryError> {
         &mut self.errors
     }
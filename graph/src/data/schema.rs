@@ -28,6 +28,14 @@ pub const META_FIELD_NAME: &str = "_meta";
 
 pub const BLOCK_FIELD_TYPE: &str = "_Block_";
 
+/// The directive that marks the generated types that hold the results of
+/// block interval aggregations in the API schema
+pub const AGGREGATION_DIRECTIVE: &str = "aggregation";
+
+/// The field that holds the cursor of an entity in the list of entities
+/// returned by a collection field in the API schema
+pub const CURSOR_FIELD_NAME: &str = "_cursor";
+
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Strings(Vec<String>);
 
@@ -33,6 +33,7 @@ pub enum SubgraphFeature {
     Grafting,
     FullTextSearch,
     IpfsOnEthereumContracts,
+    ParallelDataSources,
 }
 
 impl fmt::Display for SubgraphFeature {
@@ -88,6 +89,7 @@ pub fn detect_features<C: Blockchain>(
         detect_grafting(&manifest),
         detect_full_text_search(&manifest.schema),
         detect_ipfs_on_ethereum_contracts(&manifest)?,
+        detect_parallel_data_sources(&manifest),
     ]
     .into_iter()
     .filter_map(|x| x)
@@ -105,6 +107,19 @@ fn detect_non_fatal_errors<C: Blockchain>(
     }
 }
 
+fn detect_parallel_data_sources<C: Blockchain>(
+    manifest: &SubgraphManifest<C>,
+) -> Option<SubgraphFeature> {
+    if manifest
+        .features
+        .contains(&SubgraphFeature::ParallelDataSources)
+    {
+        Some(SubgraphFeature::ParallelDataSources)
+    } else {
+        None
+    }
+}
+
 fn detect_grafting<C: Blockchain>(manifest: &SubgraphManifest<C>) -> Option<SubgraphFeature> {
     manifest.graft.as_ref().map(|_| SubgraphFeature::Grafting)
 }
@@ -146,17 +161,19 @@ fn detect_ipfs_on_ethereum_contracts<C: Blockchain>(
 mod tests {
     use super::*;
     use SubgraphFeature::*;
-    const VARIANTS: [SubgraphFeature; 4] = [
+    const VARIANTS: [SubgraphFeature; 5] = [
         NonFatalErrors,
         Grafting,
         FullTextSearch,
         IpfsOnEthereumContracts,
+        ParallelDataSources,
     ];
-    const STRING: [&'static str; 4] = [
+    const STRING: [&'static str; 5] = [
         "nonFatalErrors",
         "grafting",
         "fullTextSearch",
         "ipfsOnEthereumContracts",
+        "parallelDataSources",
     ];
 
     #[test]
@@ -64,7 +64,7 @@ lazy_static! {
     static ref MAX_API_VERSION: semver::Version = std::env::var("GRAPH_MAX_API_VERSION")
         .ok()
         .and_then(|api_version_str| semver::Version::parse(&api_version_str).ok())
-        .unwrap_or(semver::Version::new(0, 0, 6));
+        .unwrap_or(semver::Version::new(0, 0, 7));
 }
 
 /// Rust representation of the GraphQL schema for a `SubgraphManifest`.
@@ -377,6 +377,8 @@ pub enum SubgraphManifestValidationError {
     FeatureValidationError(#[from] SubgraphFeatureValidationError),
     #[error("data source {0} is invalid: {1}")]
     DataSourceValidation(String, Error),
+    #[error("the indexer hints are invalid: {0}")]
+    IndexerHintsInvalid(String),
 }
 
 #[derive(Error, Debug)]
@@ -553,6 +555,30 @@ impl Graft {
     }
 }
 
+/// Hints that tell the indexer how the subgraph will be used so that it can
+/// store it more efficiently
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IndexerHints {
+    /// How many blocks of history, counted back from the subgraph head, need
+    /// to be available for time-travel queries. If this is not set, all
+    /// history is kept
+    pub history_blocks: Option<BlockNumber>,
+}
+
+impl IndexerHints {
+    fn validate(&self) -> Vec<SubgraphManifestValidationError> {
+        match self.history_blocks {
+            Some(blocks) if blocks <= 0 => {
+                vec![SubgraphManifestValidationError::IndexerHintsInvalid(
+                    format!("historyBlocks must be positive but is {}", blocks),
+                )]
+            }
+            _ => vec![],
+        }
+    }
+}
+
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct BaseSubgraphManifest<C, S, D, T> {
@@ -567,6 +593,8 @@ pub struct BaseSubgraphManifest<C, S, D, T> {
     pub graft: Option<Graft>,
     #[serde(default)]
     pub templates: Vec<T>,
+    #[serde(default)]
+    pub indexer_hints: IndexerHints,
     #[serde(skip_serializing, default)]
     pub chain: PhantomData<C>,
 }
@@ -671,6 +699,8 @@ impl<C: Blockchain> UnvalidatedSubgraphManifest<C> {
             }
         }
 
+        errors.extend(self.0.indexer_hints.validate());
+
         // Validate subgraph feature usage and declaration.
         if self.0.spec_version >= SPEC_VERSION_0_0_4 {
             if let Err(feature_validation_error) = validate_subgraph_features(&self.0) {
@@ -773,6 +803,7 @@ impl<C: Blockchain> UnresolvedSubgraphManifest<C> {
             data_sources,
             graft,
             templates,
+            indexer_hints,
             chain,
         } = self;
 
@@ -822,6 +853,7 @@ impl<C: Blockchain> UnresolvedSubgraphManifest<C> {
             data_sources,
             graft,
             templates,
+            indexer_hints,
             chain,
         })
     }
@@ -845,6 +877,10 @@ pub struct DeploymentState {
     pub max_reorg_depth: u32,
     /// The number of the last block that the subgraph has processed
     pub latest_ethereum_block_number: BlockNumber,
+    /// The earliest block for which the subgraph still has all its
+    /// history; queries for blocks before that can not be answered since
+    /// the entity versions they need have been pruned
+    pub earliest_block_number: BlockNumber,
 }
 
 impl DeploymentState {
@@ -114,6 +114,10 @@ pub struct SubgraphDeploymentEntity {
     pub reorg_count: i32,
     pub current_reorg_depth: i32,
     pub max_reorg_depth: i32,
+    /// How many blocks of history to keep; `None` means keep everything
+    pub history_blocks: Option<BlockNumber>,
+    /// The earliest block for which the deployment has all its history
+    pub earliest_block_number: BlockNumber,
 }
 
 impl SubgraphDeploymentEntity {
@@ -136,6 +140,8 @@ impl SubgraphDeploymentEntity {
             reorg_count: 0,
             current_reorg_depth: 0,
             max_reorg_depth: 0,
+            history_blocks: source_manifest.indexer_hints.history_blocks,
+            earliest_block_number: 0,
         }
     }
 
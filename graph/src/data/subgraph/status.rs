@@ -164,3 +164,75 @@ impl IntoValue for Info {
         }
     }
 }
+
+/// Size and version statistics for one table of a deployment. Entity and
+/// version counts are estimates that Postgres maintains and that are only
+/// as current as the last time the table was analyzed
+#[derive(Clone, Debug)]
+pub struct TableStats {
+    pub table: String,
+    /// The estimated number of distinct entities in the table
+    pub entities: i64,
+    /// The estimated number of rows, i.e., entity versions, in the table
+    pub versions: i64,
+    pub account_like: bool,
+    /// The size of the table in bytes, excluding indexes
+    pub table_size: i64,
+    /// The size of all indexes on the table in bytes
+    pub index_size: i64,
+}
+
+impl IntoValue for TableStats {
+    fn into_value(self) -> r::Value {
+        let TableStats {
+            table,
+            entities,
+            versions,
+            account_like,
+            table_size,
+            index_size,
+        } = self;
+
+        object! {
+            __typename: "TableStats",
+            table: table,
+            entities: format!("{}", entities),
+            versions: format!("{}", versions),
+            accountLike: account_like,
+            tableSize: format!("{}", table_size),
+            indexSize: format!("{}", index_size),
+        }
+    }
+}
+
+/// Statistics about the tables of a deployment
+#[derive(Debug)]
+pub struct DeploymentStats {
+    pub subgraph: String,
+    pub shard: String,
+    pub namespace: String,
+    /// The latest block the deployment has written changes for
+    pub latest_block: Option<EthereumBlock>,
+    pub tables: Vec<TableStats>,
+}
+
+impl IntoValue for DeploymentStats {
+    fn into_value(self) -> r::Value {
+        let DeploymentStats {
+            subgraph,
+            shard,
+            namespace,
+            latest_block,
+            tables,
+        } = self;
+
+        object! {
+            __typename: "DeploymentStats",
+            subgraph: subgraph,
+            shard: shard,
+            namespace: namespace,
+            latestBlock: latest_block,
+            tables: tables.into_iter().map(|table| table.into_value()).collect::<Vec<_>>(),
+        }
+    }
+}
@@ -0,0 +1,415 @@
+//! Serve firehose block streams from a local directory of block files
+//! rather than from a firehose gRPC endpoint. This makes it possible to
+//! reprocess historical block ranges, or to replay a fixed set of blocks
+//! deterministically, without access to an archive node or a firehose
+//! deployment.
+//!
+//! The directory must contain the merged block bundles that firehose
+//! produces, uncompressed. Each bundle is a file named after the number of
+//! its first block, zero-padded to ten digits and optionally with a `.dbin`
+//! extension, e.g. `0000012300` or `0000012300.dbin`. A bundle starts with
+//! the `dbin` header, followed by `bstream.v1.Block` messages, each
+//! prefixed with its length as a four byte big-endian integer. The payload
+//! of these blocks is the chain-specific block, e.g., a
+//! `dfuse.ethereum.codec.v1.Block`, exactly as it is sent by a firehose
+//! endpoint.
+//!
+//! The archive must form one linear chain, i.e., it must not contain
+//! forked blocks. Since there are no reorgs, the stream only ever produces
+//! `StepNew` responses. Once all bundles in the directory have been sent,
+//! the stream waits for new blocks to appear. A bundle that ends in the
+//! middle of a block is assumed to be still in the process of being
+//! written; the stream sends the blocks before that point and then waits
+//! for the rest of the bundle.
+use anyhow::{anyhow, bail, Context};
+use futures03::Stream;
+use prost::Message;
+use slog::{debug, warn, Logger};
+use std::{
+    convert::TryFrom,
+    fs,
+    path::{Path, PathBuf},
+    pin::Pin,
+    time::Duration,
+};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+
+use super::bstream;
+
+/// The magic bytes at the start of every bundle
+const DBIN_MAGIC: &[u8] = b"dbin";
+/// The length of the `dbin` header: magic, format version, content type
+/// and content version
+const DBIN_HEADER_LEN: usize = 10;
+/// The prefix of the cursors that the archive hands out
+const CURSOR_PREFIX: &str = "archive:";
+/// How long to wait before looking for new blocks once we have sent all
+/// blocks in the archive, or when a bundle is still being written
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+/// How many blocks we read ahead of the consumer of the stream
+const BUFFER_SIZE: usize = 200;
+
+/// The stream of responses for a `BlocksRequestV2`, regardless of whether
+/// it is served by a gRPC endpoint or a local archive
+pub type BlockResponseStream =
+    Pin<Box<dyn Stream<Item = Result<bstream::BlockResponseV2, tonic::Status>> + Send>>;
+
+/// The blocks in one bundle file
+#[derive(Clone, Debug)]
+pub struct Bundle {
+    /// The number of the first block in the bundle, taken from the name
+    /// of the file
+    pub base: u64,
+    pub blocks: Vec<bstream::Block>,
+    /// Whether the file ends after a complete message. A bundle that is
+    /// still being written usually ends in the middle of one; it will have
+    /// more blocks once the writer is done
+    pub complete: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct BlockArchive {
+    dir: PathBuf,
+    poll_interval: Duration,
+}
+
+impl BlockArchive {
+    pub fn new(dir: impl Into<PathBuf>) -> Result<Self, anyhow::Error> {
+        let dir = dir.into();
+        if !dir.is_dir() {
+            bail!("the block archive {} is not a directory", dir.display());
+        }
+        Ok(BlockArchive {
+            dir,
+            poll_interval: POLL_INTERVAL,
+        })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// List the bundles in the archive, ordered by their first block
+    pub async fn bundles(&self) -> Result<Vec<(u64, PathBuf)>, anyhow::Error> {
+        let dir = self.dir.clone();
+        tokio::task::spawn_blocking(move || list_bundles(&dir)).await?
+    }
+
+    /// Read and decode the bundle that starts with block `base`
+    pub async fn read_bundle(&self, base: u64, path: PathBuf) -> Result<Bundle, anyhow::Error> {
+        let (blocks, complete) = tokio::task::spawn_blocking(move || read_bundle(&path)).await??;
+        Ok(Bundle {
+            base,
+            blocks,
+            complete,
+        })
+    }
+
+    /// Stream the blocks requested by `request`. Only the start and stop
+    /// block of the request are taken into account; filter expressions
+    /// are ignored
+    pub fn stream_blocks(
+        &self,
+        logger: Logger,
+        request: bstream::BlocksRequestV2,
+    ) -> Result<BlockResponseStream, anyhow::Error> {
+        let start = if request.start_cursor.is_empty() {
+            u64::try_from(request.start_block_num).map_err(|_| {
+                anyhow!(
+                    "block archives do not support negative start blocks like {}",
+                    request.start_block_num
+                )
+            })?
+        } else {
+            parse_cursor(&request.start_cursor)? + 1
+        };
+        let stop = match request.stop_block_num {
+            0 => None,
+            stop => Some(stop),
+        };
+
+        let (sender, receiver) = mpsc::channel(BUFFER_SIZE);
+        let archive = self.clone();
+        crate::spawn(async move {
+            if let Err(e) = archive.send_blocks(&logger, start, stop, &sender).await {
+                let _ = sender
+                    .send(Err(tonic::Status::internal(e.to_string())))
+                    .await;
+            }
+        });
+        Ok(Box::pin(ReceiverStream::new(receiver)))
+    }
+
+    /// Send all blocks from `start` to `stop` to `sender`. Returns once
+    /// `stop` has been reached, or the receiver went away
+    async fn send_blocks(
+        &self,
+        logger: &Logger,
+        start: u64,
+        stop: Option<u64>,
+        sender: &mpsc::Sender<Result<bstream::BlockResponseV2, tonic::Status>>,
+    ) -> Result<(), anyhow::Error> {
+        let mut next = start;
+        let mut last_id: Option<String> = None;
+
+        loop {
+            let bundles = self.bundles().await?;
+            // Skip all bundles that end before the block we need next. The
+            // bundle with the most recent blocks is read again on every
+            // pass since its writer might still be adding to it
+            let first = bundles
+                .iter()
+                .rposition(|(base, _)| *base <= next)
+                .unwrap_or(0);
+            let before = next;
+
+            for (idx, (base, path)) in bundles.iter().enumerate().skip(first) {
+                let bundle = self.read_bundle(*base, path.clone()).await?;
+                for block in bundle.blocks {
+                    if block.number < next {
+                        continue;
+                    }
+                    if stop.map_or(false, |stop| block.number > stop) {
+                        return Ok(());
+                    }
+                    if let Some(last_id) = &last_id {
+                        if &block.previous_id != last_id {
+                            bail!(
+                                "block #{} ({}) in {} does not follow block {}; \
+                                 the block archive must not contain forks",
+                                block.number,
+                                block.id,
+                                path.display(),
+                                last_id
+                            );
+                        }
+                    }
+                    next = block.number + 1;
+                    last_id = Some(block.id.clone());
+
+                    if sender.send(Ok(response(block))).await.is_err() {
+                        // The stream was dropped
+                        return Ok(());
+                    }
+                    if stop.map_or(false, |stop| next > stop) {
+                        return Ok(());
+                    }
+                }
+
+                if !bundle.complete {
+                    // Wait for the writer to finish the bundle rather than
+                    // skipping over the blocks that are still missing
+                    if idx + 1 < bundles.len() {
+                        warn!(logger, "Bundle in block archive is incomplete even though later bundles exist, waiting for it to be completed";
+                              "bundle" => path.display().to_string(),
+                              "next_block" => next);
+                    }
+                    break;
+                }
+            }
+
+            if next == before {
+                if sender.is_closed() {
+                    return Ok(());
+                }
+                debug!(logger, "Waiting for new blocks in block archive";
+                       "dir" => self.dir.display().to_string(),
+                       "next_block" => next);
+                tokio::time::sleep(self.poll_interval).await;
+            }
+        }
+    }
+}
+
+fn response(block: bstream::Block) -> bstream::BlockResponseV2 {
+    let type_url = match bstream::Protocol::from_i32(block.payload_kind) {
+        Some(bstream::Protocol::Eth) => "type.googleapis.com/dfuse.ethereum.codec.v1.Block",
+        Some(bstream::Protocol::Near) => "type.googleapis.com/sf.near.codec.v1.Block",
+        _ => "",
+    };
+    bstream::BlockResponseV2 {
+        cursor: format!("{}{}", CURSOR_PREFIX, block.number),
+        block: Some(prost_types::Any {
+            type_url: type_url.to_string(),
+            value: block.payload_buffer,
+        }),
+        step: bstream::ForkStep::StepNew as i32,
+    }
+}
+
+fn parse_cursor(cursor: &str) -> Result<u64, anyhow::Error> {
+    cursor
+        .strip_prefix(CURSOR_PREFIX)
+        .and_then(|number| number.parse().ok())
+        .ok_or_else(|| {
+            anyhow!(
+                "the cursor `{}` was not produced by a block archive and can not be used with one",
+                cursor
+            )
+        })
+}
+
+fn list_bundles(dir: &Path) -> Result<Vec<(u64, PathBuf)>, anyhow::Error> {
+    let mut bundles = Vec::new();
+    for entry in fs::read_dir(dir)
+        .with_context(|| format!("can not read block archive {}", dir.display()))?
+    {
+        let path = entry?.path();
+        let base = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .map(|name| name.trim_end_matches(".dbin"))
+            .filter(|name| name.len() == 10)
+            .and_then(|name| name.parse::<u64>().ok());
+        if let Some(base) = base {
+            bundles.push((base, path));
+        }
+    }
+    bundles.sort();
+    Ok(bundles)
+}
+
+fn read_bundle(path: &Path) -> Result<(Vec<bstream::Block>, bool), anyhow::Error> {
+    let bytes =
+        fs::read(path).with_context(|| format!("can not read bundle {}", path.display()))?;
+    decode_bundle(&bytes).with_context(|| format!("invalid bundle {}", path.display()))
+}
+
+/// Decode the blocks in the contents of a bundle file. Returns the blocks
+/// and whether the contents end after a complete message; for a bundle
+/// that is still being written, the blocks that have been written so far
+/// are returned
+fn decode_bundle(bytes: &[u8]) -> Result<(Vec<bstream::Block>, bool), anyhow::Error> {
+    if bytes.len() < DBIN_HEADER_LEN {
+        if DBIN_MAGIC.starts_with(bytes) || bytes.starts_with(DBIN_MAGIC) {
+            return Ok((Vec::new(), false));
+        }
+        bail!("the file does not start with a dbin header");
+    }
+    if !bytes.starts_with(DBIN_MAGIC) {
+        bail!("the file does not start with a dbin header");
+    }
+
+    let mut blocks = Vec::new();
+    let mut rest = &bytes[DBIN_HEADER_LEN..];
+    while !rest.is_empty() {
+        if rest.len() < 4 {
+            return Ok((blocks, false));
+        }
+        let len = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+        rest = &rest[4..];
+        if rest.len() < len {
+            return Ok((blocks, false));
+        }
+        blocks.push(bstream::Block::decode(&rest[..len])?);
+        rest = &rest[len..];
+    }
+    Ok((blocks, true))
+}
+
+#[cfg(test)]
+mod tests {
+    use futures03::StreamExt;
+
+    use super::*;
+
+    fn block(number: u64) -> bstream::Block {
+        bstream::Block {
+            number,
+            id: format!("{:x}", number),
+            previous_id: format!("{:x}", number.saturating_sub(1)),
+            payload_kind: bstream::Protocol::Eth as i32,
+            payload_buffer: vec![number as u8],
+            ..Default::default()
+        }
+    }
+
+    fn encode_bundle(blocks: &[bstream::Block]) -> Vec<u8> {
+        let mut bytes = b"dbin\x01ETH00".to_vec();
+        for block in blocks {
+            let msg = block.encode_to_vec();
+            bytes.extend_from_slice(&(msg.len() as u32).to_be_bytes());
+            bytes.extend_from_slice(&msg);
+        }
+        bytes
+    }
+
+    #[test]
+    fn decode_bundles() {
+        let blocks = vec![block(100), block(101), block(102)];
+        let (decoded, complete) = decode_bundle(&encode_bundle(&blocks)).unwrap();
+        assert_eq!(blocks, decoded);
+        assert!(complete);
+
+        let (empty, complete) = decode_bundle(&encode_bundle(&[])).unwrap();
+        assert!(empty.is_empty());
+        assert!(complete);
+
+        // A bundle that is still being written has the blocks before the
+        // partial one
+        let bytes = encode_bundle(&blocks);
+        let (decoded, complete) = decode_bundle(&bytes[..bytes.len() - 1]).unwrap();
+        assert_eq!(&blocks[..2], decoded.as_slice());
+        assert!(!complete);
+        let (decoded, complete) = decode_bundle(&bytes[..6]).unwrap();
+        assert!(decoded.is_empty());
+        assert!(!complete);
+
+        assert!(decode_bundle(b"nope").is_err());
+        assert!(decode_bundle(b"nope, not a bundle").is_err());
+    }
+
+    #[tokio::test]
+    async fn stream_waits_for_incomplete_bundles() {
+        let dir = std::env::temp_dir().join(format!(
+            "graph-block-archive-{}-{}",
+            std::process::id(),
+            line!()
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        let archive = BlockArchive {
+            dir: dir.clone(),
+            poll_interval: Duration::from_millis(10),
+        };
+
+        let first: Vec<_> = (100..103).map(block).collect();
+        let second: Vec<_> = (103..106).map(block).collect();
+        fs::write(dir.join("0000000100.dbin"), encode_bundle(&first)).unwrap();
+        let bytes = encode_bundle(&second);
+        fs::write(dir.join("0000000103.dbin"), &bytes[..bytes.len() - 3]).unwrap();
+
+        let request = bstream::BlocksRequestV2 {
+            start_block_num: 101,
+            stop_block_num: 105,
+            ..Default::default()
+        };
+        let mut stream = archive
+            .stream_blocks(Logger::root(slog::Discard, slog::o!()), request)
+            .unwrap();
+
+        let mut numbers = Vec::new();
+        for _ in 101..105 {
+            let resp = stream.next().await.unwrap().unwrap();
+            numbers.push(parse_cursor(&resp.cursor).unwrap());
+        }
+        assert_eq!(vec![101, 102, 103, 104], numbers);
+
+        // Block 105 only shows up once the bundle has been written
+        // completely, and the stream ends after it
+        fs::write(dir.join("0000000103.dbin"), &bytes).unwrap();
+        let resp = stream.next().await.unwrap().unwrap();
+        assert_eq!(105, parse_cursor(&resp.cursor).unwrap());
+        assert!(stream.next().await.is_none());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn cursors() {
+        let resp = response(block(42));
+        assert_eq!(bstream::ForkStep::StepNew as i32, resp.step);
+        assert_eq!(42, parse_cursor(&resp.cursor).unwrap());
+        assert!(parse_cursor("c1:1:42:abc").is_err());
+    }
+}
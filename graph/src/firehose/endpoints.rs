@@ -3,22 +3,30 @@ use anyhow::Context;
 use http::uri::{Scheme, Uri};
 use rand::prelude::IteratorRandom;
 use slog::Logger;
-use std::{collections::BTreeMap, fmt::Display, sync::Arc};
+use std::{collections::BTreeMap, fmt::Display, path::PathBuf, sync::Arc};
 use tonic::{
     metadata::MetadataValue,
     transport::{Channel, ClientTlsConfig},
     Request,
 };
 
+use super::archive::{BlockArchive, BlockResponseStream};
 use super::bstream;
 
+/// Where a `FirehoseEndpoint` gets its blocks from
+#[derive(Clone, Debug)]
+enum BlockSource {
+    Grpc(Channel),
+    Archive(BlockArchive),
+}
+
 #[derive(Clone, Debug)]
 pub struct FirehoseEndpoint {
     pub provider: String,
     pub uri: String,
     pub token: Option<String>,
-    channel: Channel,
-    _logger: Logger,
+    source: BlockSource,
+    logger: Logger,
 }
 
 impl Display for FirehoseEndpoint {
@@ -59,23 +67,48 @@ impl FirehoseEndpoint {
         Ok(FirehoseEndpoint {
             provider: provider.as_ref().to_string(),
             uri,
-            channel,
+            source: BlockSource::Grpc(channel),
             token,
-            _logger: logger,
+            logger,
+        })
+    }
+
+    /// Create an endpoint that serves blocks from the block archive in
+    /// the directory `dir` rather than from a firehose gRPC service
+    pub fn new_archive<S: AsRef<str>>(
+        logger: Logger,
+        provider: S,
+        dir: S,
+    ) -> Result<Self, anyhow::Error> {
+        let archive = BlockArchive::new(PathBuf::from(dir.as_ref()))?;
+
+        Ok(FirehoseEndpoint {
+            provider: provider.as_ref().to_string(),
+            uri: format!("file://{}", archive.dir().display()),
+            source: BlockSource::Archive(archive),
+            token: None,
+            logger,
         })
     }
 
     pub async fn stream_blocks(
         self: Arc<Self>,
         request: bstream::BlocksRequestV2,
-    ) -> Result<tonic::Streaming<bstream::BlockResponseV2>, anyhow::Error> {
+    ) -> Result<BlockResponseStream, anyhow::Error> {
+        let channel = match &self.source {
+            BlockSource::Grpc(channel) => channel,
+            BlockSource::Archive(archive) => {
+                return archive.stream_blocks(self.logger.clone(), request);
+            }
+        };
+
         let token_metadata = match self.token.clone() {
             Some(token) => Some(MetadataValue::from_str(token.as_str())?),
             None => None,
         };
 
         let mut client = bstream::block_stream_v2_client::BlockStreamV2Client::with_interceptor(
-            self.channel.cheap_clone(),
+            channel.cheap_clone(),
             move |mut r: Request<()>| match token_metadata.as_ref() {
                 Some(t) => {
                     r.metadata_mut().insert("authorization", t.clone());
@@ -88,7 +121,7 @@ impl FirehoseEndpoint {
         let response_stream = client.blocks(request).await?;
         let block_stream = response_stream.into_inner();
 
-        Ok(block_stream)
+        Ok(Box::pin(block_stream))
     }
 }
 
@@ -1,6 +1,7 @@
 #[path = "dfuse.bstream.v1.rs"]
 mod pbbstream;
 
+pub mod archive;
 pub mod endpoints;
 
 pub mod bstream {
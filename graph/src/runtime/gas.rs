@@ -0,0 +1,162 @@
+//! Gas measures the work a mapping handler does in a way that does not
+//! depend on the machine that runs it, so that all indexers agree on
+//! whether a handler ran out of gas.
+//!
+//! The wasm runtime charges one unit of gas for each instruction of the
+//! mapping. Host exports charge `HOST_EXPORT_GAS` for each call, plus costs
+//! that depend on the amount of data they handle, like the size of the
+//! entities passed to `store.set` or the files returned by `ipfs.cat`.
+//! Both come out of the same budget: a handler fails as soon as the sum of
+//! the two exceeds its limit, no matter which of them pushed it over.
+
+use std::cell::Cell;
+use std::rc::Rc;
+
+use lazy_static::lazy_static;
+
+use crate::data::store::Value;
+use crate::env::env_var;
+use crate::prelude::anyhow;
+use crate::runtime::DeterministicHostError;
+
+lazy_static! {
+    /// The most gas a handler can use before it fails
+    pub static ref MAX_GAS_PER_HANDLER: u64 = env_var("GRAPH_MAX_GAS_PER_HANDLER", 10_000_000_000);
+}
+
+/// The cost of calling any host export
+pub const HOST_EXPORT_GAS: u64 = 1_000;
+
+/// The cost per byte of data that a host export stores, loads or processes
+pub const GAS_PER_BYTE: u64 = 100;
+
+/// The cost of `store.get`, which might have to query the database
+pub const STORE_GET_GAS: u64 = 100_000;
+
+/// The cost of `store.set` and `store.remove`, which eventually write to
+/// the database
+pub const STORE_SET_GAS: u64 = 100_000;
+
+/// The cost of an `eth_call`
+pub const ETHEREUM_CALL_GAS: u64 = 25_000_000;
+
+/// The cost of fetching a file from IPFS
+pub const IPFS_GAS: u64 = 10_000_000;
+
+/// The number of bytes of `value` for the purpose of charging gas. This
+/// only depends on the value itself, not on how it is laid out in memory
+pub fn value_size(value: &Value) -> u64 {
+    match value {
+        Value::String(s) => s.len() as u64,
+        Value::Int(_) => 4,
+        Value::BigDecimal(d) => d.to_string().len() as u64,
+        Value::Bool(_) | Value::Null => 1,
+        Value::List(values) => values.iter().map(value_size).sum(),
+        Value::Bytes(bytes) => bytes.as_slice().len() as u64,
+        Value::BigInt(n) => n.to_signed_bytes_le().len() as u64,
+    }
+}
+
+/// The number of bytes of an entity with the attributes `attrs`
+pub fn entity_size<'a>(attrs: impl IntoIterator<Item = (&'a String, &'a Value)>) -> u64 {
+    attrs
+        .into_iter()
+        .map(|(name, value)| name.len() as u64 + value_size(value))
+        .sum()
+}
+
+/// Keeps track of the gas one handler has used. The gas for wasm
+/// instructions is counted by the wasm runtime, which has to report it
+/// with `set_wasm_gas` before the limit is checked. The runtime can never
+/// give the handler more than `limit` units of gas for instructions, and
+/// host exports check the combined count whenever they charge gas. Clones
+/// share the same counts
+#[derive(Clone, Debug)]
+pub struct GasCounter {
+    limit: u64,
+    wasm: Rc<Cell<u64>>,
+    host: Rc<Cell<u64>>,
+}
+
+impl GasCounter {
+    pub fn new(limit: u64) -> Self {
+        GasCounter {
+            limit,
+            wasm: Rc::new(Cell::new(0)),
+            host: Rc::new(Cell::new(0)),
+        }
+    }
+
+    /// The total gas the handler may use
+    pub fn limit(&self) -> u64 {
+        self.limit
+    }
+
+    /// Record that the wasm instructions of the handler have used `gas` so
+    /// far
+    pub fn set_wasm_gas(&self, gas: u64) {
+        self.wasm.set(gas);
+    }
+
+    /// Charge `gas` for work a host export did, and fail if that exhausts
+    /// the gas of the handler
+    pub fn consume_host_fn(&self, gas: u64) -> Result<(), DeterministicHostError> {
+        self.host.set(self.host.get().saturating_add(gas));
+        self.check()
+    }
+
+    pub fn used(&self) -> u64 {
+        self.wasm.get().saturating_add(self.host.get())
+    }
+
+    /// Fail if the handler used more than its gas limit
+    pub fn check(&self) -> Result<(), DeterministicHostError> {
+        if self.used() > self.limit {
+            return Err(self.limit_exceeded());
+        }
+        Ok(())
+    }
+
+    /// Whether the wasm instructions alone used up all the gas. When that
+    /// happens, the wasm runtime stops the handler
+    pub fn wasm_exhausted(&self) -> bool {
+        self.wasm.get() >= self.limit
+    }
+
+    /// The error for a handler that exhausted its gas
+    pub fn limit_exceeded(&self) -> DeterministicHostError {
+        DeterministicHostError(anyhow!(
+            "Gas limit exceeded. Used: {}, limit: {}",
+            self.used(),
+            self.limit
+        ))
+    }
+}
+
+impl Default for GasCounter {
+    fn default() -> Self {
+        GasCounter::new(*MAX_GAS_PER_HANDLER)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_wasm_and_host_gas() {
+        let gas = GasCounter::new(1_000);
+        gas.set_wasm_gas(400);
+        assert!(gas.consume_host_fn(500).is_ok());
+        assert_eq!(900, gas.used());
+
+        gas.set_wasm_gas(450);
+        assert!(gas.check().is_ok());
+        assert!(gas.consume_host_fn(100).is_err());
+        assert_eq!(1_050, gas.used());
+        assert!(!gas.wasm_exhausted());
+
+        gas.set_wasm_gas(1_000);
+        assert!(gas.wasm_exhausted());
+    }
+}
@@ -5,6 +5,7 @@
 
 mod asc_heap;
 mod asc_ptr;
+pub mod gas;
 
 pub use asc_heap::{asc_get, asc_new, try_asc_get, AscHeap, FromAscObj, ToAscObj, TryFromAscObj};
 pub use asc_ptr::AscPtr;
@@ -238,6 +239,14 @@ pub enum IndexForAscTypeId {
     NearChunkHeader = 84,
     NearBlock = 85,
     NearReceiptWithOutcome = 86,
+
+    // Ethereum transaction receipts
+    ArrayH256 = 87,
+    EthereumLog = 88,
+    ArrayEthereumLog = 89,
+    EthereumTransactionReceipt = 90,
+    EthereumTransactionWithReceipt = 91,
+    EthereumEventWithReceipt = 92,
 }
 
 impl ToAscObj<u32> for IndexForAscTypeId {
@@ -159,6 +159,35 @@ impl<K: Clone + Ord + Eq + Hash + Debug + CacheWeight, V: CacheWeight + Default>
         self.queue.is_empty()
     }
 
+    /// Move all entries whose key matches `pred` into a new cache, keeping
+    /// their priorities
+    pub fn split_off(&mut self, pred: impl Fn(&K) -> bool) -> Self {
+        let mut other = Self::new();
+        let mut keep = PriorityQueue::new();
+        for (entry, priority) in std::mem::replace(&mut self.queue, PriorityQueue::new()) {
+            if pred(&entry.key) {
+                self.total_weight -= entry.weight;
+                other.total_weight += entry.weight;
+                other.queue.push(entry, priority);
+            } else {
+                keep.push(entry, priority);
+            }
+        }
+        self.queue = keep;
+        other
+    }
+
+    /// Move all entries from `other` into this cache. Entries that are
+    /// already in this cache are left alone
+    pub fn merge(&mut self, other: Self) {
+        for (entry, priority) in other.queue {
+            if self.queue.get(&entry).is_none() {
+                self.total_weight += entry.weight;
+                self.queue.push(entry, priority);
+            }
+        }
+    }
+
     pub fn len(&self) -> usize {
         self.queue.len()
     }
@@ -294,3 +323,37 @@ fn entity_lru_cache() {
     assert!(cache.get(&"alligator").is_none());
     assert_eq!(cache.get(&"lion"), Some(&Weight(lion_inner_weight)));
 }
+
+#[test]
+fn split_off_and_merge() {
+    #[derive(Default, Debug, PartialEq, Eq)]
+    struct Weight(usize);
+
+    impl CacheWeight for Weight {
+        fn weight(&self) -> usize {
+            self.indirect_weight()
+        }
+
+        fn indirect_weight(&self) -> usize {
+            self.0
+        }
+    }
+
+    let mut cache: LfuCache<&'static str, Weight> = LfuCache::new();
+    cache.insert("panda", Weight(2));
+    cache.insert("cow", Weight(1));
+    cache.insert("pig", Weight(3));
+    let total = cache.total_weight;
+
+    let mut other = cache.split_off(|key| key.starts_with('p'));
+    assert_eq!(1, cache.len());
+    assert_eq!(2, other.len());
+    assert_eq!(Some(&Weight(1)), cache.get(&"cow"));
+    assert_eq!(Some(&Weight(3)), other.get(&"pig"));
+    assert_eq!(total, cache.total_weight + other.total_weight);
+
+    other.insert("cow", Weight(1));
+    cache.merge(other);
+    assert_eq!(3, cache.len());
+    assert_eq!(total, cache.total_weight);
+}
@@ -181,6 +181,14 @@ impl MovingStats {
         self.total.duration
     }
 
+    /// Return the sum of the durations added within the window that ends
+    /// at `now`. Unlike `duration`, this expires old measurements first
+    /// and is therefore accurate even if nothing was added recently
+    pub fn current_duration(&mut self, now: Instant) -> Duration {
+        self.expire_bins(now);
+        self.total.duration
+    }
+
     /// Adds `duration` to the stats, and register the average ms to `avg_gauge`.
     pub fn add_and_register(&mut self, duration: Duration, avg_gauge: &Gauge) {
         let wait_avg = {
@@ -1,7 +1,8 @@
 use graph::prelude::SubgraphStore;
 use lazy_static::lazy_static;
 use slog::{o, Logger};
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashSet};
+use std::iter::FromIterator;
 use std::sync::Arc;
 
 use graph::{components::store::EntityType, mock::MockStore};
@@ -211,3 +212,42 @@ fn consecutive_modifications() {
         },])
     );
 }
+
+#[test]
+fn forked_modifications() {
+    let mut store = MockStore::new();
+    store
+        .expect_get_many_mock()
+        .returning(|_| Ok(BTreeMap::new()));
+
+    let store = Arc::new(store);
+    let mut cache = EntityCache::new(store.clone());
+
+    let band_types = HashSet::from_iter(vec![EntityType::from("Band")]);
+    let mut fork = cache.fork(band_types);
+
+    let (mogwai_key, mogwai_data) = make_band(
+        "mogwai",
+        vec![("id", "mogwai".into()), ("name", "Mogwai".into())],
+    );
+    fork.set(mogwai_key.clone(), mogwai_data.clone());
+    assert!(!fork.has_undeclared_access());
+
+    // Changes made through the fork end up in the original cache
+    cache.extend(fork);
+    let result = cache.as_modifications();
+    assert_eq!(
+        result.unwrap().modifications,
+        vec![EntityModification::Insert {
+            key: mogwai_key,
+            data: mogwai_data,
+        }]
+    );
+
+    // Touching an entity type the fork was not made for is noticed
+    let mut cache = EntityCache::new(store.clone());
+    let mut fork = cache.fork(HashSet::from_iter(vec![EntityType::from("Album")]));
+    let (key, data) = make_band("mogwai", vec![("id", "mogwai".into())]);
+    fork.set(key, data);
+    assert!(fork.has_undeclared_access());
+}
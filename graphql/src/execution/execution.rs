@@ -286,6 +286,24 @@ where
     }
 }
 
+/// Check whether the root field `name` returns block interval aggregations.
+/// Those can not be prefetched, and the resolver computes them directly
+fn is_aggregation_field(
+    ctx: &ExecutionContext<impl Resolver>,
+    root_type: &s::ObjectType,
+    name: &str,
+) -> bool {
+    sast::get_field(root_type, name)
+        .and_then(|field| {
+            ctx.query
+                .schema
+                .document()
+                .object_or_interface(field.field_type.get_base_type())
+        })
+        .map(|object_type| object_type.is_aggregation())
+        .unwrap_or(false)
+}
+
 pub fn execute_root_selection_set_uncached(
     ctx: &ExecutionContext<impl Resolver>,
     selection_set: &q::SelectionSet,
@@ -311,7 +329,7 @@ pub fn execute_root_selection_set_uncached(
         // the data_set SelectionSet
         if is_introspection_field(&name) {
             intro_set.items.extend(selections)
-        } else if &name == META_FIELD_NAME {
+        } else if &name == META_FIELD_NAME || is_aggregation_field(ctx, root_type, &name) {
             meta_items.extend(selections)
         } else {
             data_set.items.extend(selections)
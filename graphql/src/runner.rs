@@ -233,6 +233,7 @@ where
                 store.cheap_clone(),
                 self.subscription_manager.cheap_clone(),
                 bc,
+                state.earliest_block_number,
                 error_policy,
                 query.schema.id().clone(),
                 result_size.cheap_clone(),
@@ -1,3 +1,4 @@
+use std::collections::HashSet;
 use std::str::FromStr;
 
 use graphql_parser::Pos;
@@ -8,7 +9,10 @@ use crate::schema::ast;
 
 use graph::data::{
     graphql::ext::{DirectiveExt, DocumentExt, ValueExt},
-    schema::{META_FIELD_NAME, META_FIELD_TYPE, SCHEMA_TYPE_NAME},
+    schema::{
+        AGGREGATION_DIRECTIVE, CURSOR_FIELD_NAME, META_FIELD_NAME, META_FIELD_TYPE,
+        SCHEMA_TYPE_NAME,
+    },
 };
 use graph::prelude::s::{Value, *};
 use graph::prelude::*;
@@ -22,6 +26,8 @@ pub enum APISchemaError {
     TypeNotFound(String),
     #[error("Fulltext search is not yet deterministic")]
     FulltextSearchNonDeterministic,
+    #[error("field {1} of type {0} uses a reserved name")]
+    FieldReserved(String, String),
 }
 
 const BLOCK_HEIGHT: &str = "Block_height";
@@ -88,10 +94,11 @@ pub fn api_schema(input_schema: &Document) -> Result<Document, APISchemaError> {
     add_order_direction_enum(&mut schema);
     add_block_height_type(&mut schema);
     add_meta_field_type(&mut schema);
-    add_types_for_object_types(&mut schema, &object_types)?;
+    add_types_for_object_types(&mut schema, input_schema, &object_types)?;
     add_types_for_interface_types(&mut schema, &interface_types)?;
     add_field_arguments(&mut schema, input_schema)?;
-    add_query_type(&mut schema, &object_types, &interface_types)?;
+    add_cursor_fields(&mut schema, input_schema)?;
+    add_query_type(&mut schema, input_schema, &object_types, &interface_types)?;
     add_subscription_type(&mut schema, &object_types, &interface_types)?;
 
     // Remove the `_Schema_` type from the generated schema.
@@ -179,9 +186,26 @@ fn add_directives(schema: &mut Document) {
         repeatable: false,
     });
 
+    let aggregation = Definition::DirectiveDefinition(DirectiveDefinition {
+        position: Pos::default(),
+        description: None,
+        name: AGGREGATION_DIRECTIVE.to_owned(),
+        arguments: vec![InputValue {
+            position: Pos::default(),
+            description: None,
+            name: "entity".to_owned(),
+            value_type: Type::NamedType("String".to_owned()),
+            default_value: None,
+            directives: vec![],
+        }],
+        locations: vec![DirectiveLocation::Object],
+        repeatable: false,
+    });
+
     schema.definitions.push(entity);
     schema.definitions.push(derived_from);
     schema.definitions.push(subgraph_id);
+    schema.definitions.push(aggregation);
 }
 
 /// Adds a global `OrderDirection` type to the schema.
@@ -261,17 +285,65 @@ fn add_meta_field_type(schema: &mut Document) {
 
 fn add_types_for_object_types(
     schema: &mut Document,
+    input_schema: &Document,
     object_types: &Vec<&ObjectType>,
 ) -> Result<(), APISchemaError> {
     for object_type in object_types {
         if !object_type.name.eq(SCHEMA_TYPE_NAME) {
             add_order_by_type(schema, &object_type.name, &object_type.fields)?;
             add_filter_type(schema, &object_type.name, &object_type.fields)?;
+            if has_aggregations(input_schema, &object_type.name) {
+                add_interval_type(schema, &object_type.name, &object_type.fields)?;
+            }
+            if has_group_aggregates(input_schema, object_type) {
+                add_group_by_type(schema, &object_type.name, &object_type.fields)?;
+                add_aggregate_type(schema, &object_type.name, &object_type.fields)?;
+            }
         }
     }
     Ok(())
 }
 
+/// Whether the API schema offers aggregations over entities of type
+/// `type_name`. Subgraphs that predate aggregations may already define
+/// types with the names we would generate for them, e.g. `User_interval`;
+/// for those types, we leave the user's types alone and do not generate
+/// any aggregation types or `Query` fields
+fn has_aggregations(input_schema: &Document, type_name: &str) -> bool {
+    ["interval", "groupBy", "aggregate"].iter().all(|suffix| {
+        input_schema
+            .get_named_type(&format!("{}_{}", type_name, suffix))
+            .is_none()
+    })
+}
+
+/// Whether the API schema offers aggregates over groups of entities of
+/// type `object_type`. Each group becomes one `<type_name>_aggregate`
+/// object with the fields `count`, the fields the group is formed by, and
+/// the aggregates like `f_sum`. If any of these names clash, e.g., because
+/// the type has a field `count`, or fields `f` and `f_sum`, we could not
+/// tell their values apart and therefore do not generate the
+/// `<type_name>_groupBy` and `<type_name>_aggregate` types and the
+/// `<typeName>Aggregate` field
+fn has_group_aggregates(input_schema: &Document, object_type: &ObjectType) -> bool {
+    if !has_aggregations(input_schema, &object_type.name) {
+        return false;
+    }
+    let mut names = HashSet::new();
+    names.insert("count".to_owned());
+    let group_names = groupable_fields(input_schema, &object_type.fields)
+        .into_iter()
+        .map(|(name, _)| name.to_owned());
+    let aggregate_names = aggregatable_fields(&object_type.fields).flat_map(|(name, _)| {
+        AggregateFunction::ALL
+            .iter()
+            .map(move |function| format!("{}_{}", name, function))
+    });
+    group_names
+        .chain(aggregate_names)
+        .all(|name| names.insert(name))
+}
+
 /// Adds `*_orderBy` and `*_filter` enum types for the given interfaces to the schema.
 fn add_types_for_interface_types(
     schema: &mut Document,
@@ -343,6 +415,203 @@ fn add_filter_type(
     Ok(())
 }
 
+/// Adds a `<type_name>_interval` object type to the schema. It holds the
+/// result of aggregating the entities of type `type_name` at one block and
+/// has the fields `block` and `count`, and for each numeric field `f` of the
+/// type the fields `f_sum`, `f_min`, `f_max`, and `f_avg`
+fn add_interval_type(
+    schema: &mut Document,
+    type_name: &str,
+    fields: &[Field],
+) -> Result<(), APISchemaError> {
+    let interval_type_name = format!("{}_interval", type_name);
+    if schema.get_named_type(&interval_type_name).is_some() {
+        return Err(APISchemaError::TypeExists(interval_type_name));
+    }
+
+    let non_null = |name: &str| Type::NonNullType(Box::new(Type::NamedType(name.to_owned())));
+    let field = |name: String, field_type: Type| Field {
+        position: Pos::default(),
+        description: None,
+        name,
+        arguments: vec![],
+        field_type,
+        directives: vec![],
+    };
+
+    let mut interval_fields = vec![
+        field("block".to_owned(), non_null("Int")),
+        field("count".to_owned(), non_null("Int")),
+    ];
+    for (name, value_type) in aggregatable_fields(fields) {
+        for function in AggregateFunction::ALL.iter() {
+            let result_type = match (function, value_type) {
+                (AggregateFunction::Avg, _) => "BigDecimal",
+                (AggregateFunction::Sum, "Int") => "BigInt",
+                (_, value_type) => value_type,
+            };
+            interval_fields.push(field(
+                format!("{}_{}", name, function),
+                Type::NamedType(result_type.to_owned()),
+            ));
+        }
+    }
+
+    let typedef = TypeDefinition::Object(ObjectType {
+        position: Pos::default(),
+        description: None,
+        name: interval_type_name,
+        implements_interfaces: vec![],
+        directives: vec![Directive {
+            position: Pos::default(),
+            name: AGGREGATION_DIRECTIVE.to_owned(),
+            arguments: vec![("entity".to_owned(), Value::String(type_name.to_owned()))],
+        }],
+        fields: interval_fields,
+    });
+    schema.definitions.push(Definition::TypeDefinition(typedef));
+    Ok(())
+}
+
+/// Adds a `<type_name>_groupBy` enum type to the schema that lists the
+/// fields by which aggregates over entities of type `type_name` can be
+/// grouped
+fn add_group_by_type(
+    schema: &mut Document,
+    type_name: &str,
+    fields: &[Field],
+) -> Result<(), APISchemaError> {
+    let group_by_type_name = format!("{}_groupBy", type_name);
+    if schema.get_named_type(&group_by_type_name).is_some() {
+        return Err(APISchemaError::TypeExists(group_by_type_name));
+    }
+
+    let values = groupable_fields(schema, fields)
+        .into_iter()
+        .map(|(name, _)| EnumValue {
+            position: Pos::default(),
+            description: None,
+            name: name.to_owned(),
+            directives: vec![],
+        })
+        .collect();
+    let typedef = TypeDefinition::Enum(EnumType {
+        position: Pos::default(),
+        description: None,
+        name: group_by_type_name,
+        directives: vec![],
+        values,
+    });
+    schema.definitions.push(Definition::TypeDefinition(typedef));
+    Ok(())
+}
+
+/// Adds a `<type_name>_aggregate` object type to the schema. It holds one
+/// group of the result of aggregating the entities of type `type_name` and
+/// has the field `count`, a field for each field the group can be formed
+/// by, and the same `f_sum`, `f_min`, `f_max`, and `f_avg` fields as the
+/// `<type_name>_interval` type
+fn add_aggregate_type(
+    schema: &mut Document,
+    type_name: &str,
+    fields: &[Field],
+) -> Result<(), APISchemaError> {
+    let aggregate_type_name = format!("{}_aggregate", type_name);
+    if schema.get_named_type(&aggregate_type_name).is_some() {
+        return Err(APISchemaError::TypeExists(aggregate_type_name));
+    }
+
+    let field = |name: String, field_type: Type| Field {
+        position: Pos::default(),
+        description: None,
+        name,
+        arguments: vec![],
+        field_type,
+        directives: vec![],
+    };
+
+    let mut aggregate_fields = vec![field(
+        "count".to_owned(),
+        Type::NonNullType(Box::new(Type::NamedType("Int".to_owned()))),
+    )];
+    for (name, value_type) in groupable_fields(schema, fields) {
+        aggregate_fields.push(field(name.to_owned(), Type::NamedType(value_type)));
+    }
+    for (name, value_type) in aggregatable_fields(fields) {
+        for function in AggregateFunction::ALL.iter() {
+            let result_type = match (function, value_type) {
+                (AggregateFunction::Avg, _) => "BigDecimal",
+                (AggregateFunction::Sum, "Int") => "BigInt",
+                (_, value_type) => value_type,
+            };
+            aggregate_fields.push(field(
+                format!("{}_{}", name, function),
+                Type::NamedType(result_type.to_owned()),
+            ));
+        }
+    }
+
+    let typedef = TypeDefinition::Object(ObjectType {
+        position: Pos::default(),
+        description: None,
+        name: aggregate_type_name,
+        implements_interfaces: vec![],
+        directives: vec![Directive {
+            position: Pos::default(),
+            name: AGGREGATION_DIRECTIVE.to_owned(),
+            arguments: vec![("entity".to_owned(), Value::String(type_name.to_owned()))],
+        }],
+        fields: aggregate_fields,
+    });
+    schema.definitions.push(Definition::TypeDefinition(typedef));
+    Ok(())
+}
+
+/// The names and types of the fields that aggregates can be grouped by,
+/// i.e., of all fields that hold a single scalar, enum, or reference to
+/// another entity. References are grouped by the id of the other entity
+/// and therefore have type `ID`
+fn groupable_fields<'a>(schema: &Document, fields: &'a [Field]) -> Vec<(&'a str, String)> {
+    fields
+        .iter()
+        .filter(|field| ast::get_derived_from_directive(field).is_none())
+        .filter_map(|field| {
+            let name = match &field.field_type {
+                Type::NamedType(name) => name,
+                Type::NonNullType(inner) => match inner.as_ref() {
+                    Type::NamedType(name) => name,
+                    _ => return None,
+                },
+                Type::ListType(_) => return None,
+            };
+            let value_type = match schema.get_named_type(name) {
+                Some(TypeDefinition::Object(_)) | Some(TypeDefinition::Interface(_)) => {
+                    "ID".to_owned()
+                }
+                _ => name.to_owned(),
+            };
+            Some((field.name.as_str(), value_type))
+        })
+        .collect()
+}
+
+/// The names and types of the fields that can be aggregated, i.e., of all
+/// fields that hold a single `Int`, `BigInt`, or `BigDecimal`
+pub(crate) fn aggregatable_fields(fields: &[Field]) -> impl Iterator<Item = (&str, &str)> {
+    fields.iter().filter_map(|field| {
+        let named_type = match &field.field_type {
+            Type::NonNullType(inner) => inner.as_ref(),
+            field_type => field_type,
+        };
+        match named_type {
+            Type::NamedType(name) if ["Int", "BigInt", "BigDecimal"].contains(&name.as_str()) => {
+                Some((field.name.as_str(), name.as_str()))
+            }
+            _ => None,
+        }
+    })
+}
+
 /// Generates `*_filter` input values for the given set of fields.
 fn field_input_values(
     schema: &Document,
@@ -520,6 +789,7 @@ fn input_value(name: &str, suffix: &'static str, value_type: Type) -> InputValue
 /// Adds a root `Query` object type to the schema.
 fn add_query_type(
     schema: &mut Document,
+    input_schema: &Document,
     object_types: &[&ObjectType],
     interface_types: &[&InterfaceType],
 ) -> Result<(), APISchemaError> {
@@ -536,6 +806,29 @@ fn add_query_type(
         .chain(interface_types.iter().map(|t| &t.name))
         .flat_map(|name| query_fields_for_type(name))
         .collect::<Vec<Field>>();
+    let mut history_fields = object_types
+        .iter()
+        .map(|t| &t.name)
+        .filter(|name| !name.eq(&SCHEMA_TYPE_NAME))
+        .chain(interface_types.iter().map(|t| &t.name))
+        .map(|name| history_field_for_type(name))
+        .collect();
+    fields.append(&mut history_fields);
+    let mut interval_fields = object_types
+        .iter()
+        .map(|t| &t.name)
+        .filter(|name| !name.eq(&SCHEMA_TYPE_NAME))
+        .filter(|name| has_aggregations(input_schema, name))
+        .map(|name| intervals_field_for_type(name))
+        .collect();
+    fields.append(&mut interval_fields);
+    let mut aggregate_fields = object_types
+        .iter()
+        .filter(|t| !t.name.eq(SCHEMA_TYPE_NAME))
+        .filter(|t| has_group_aggregates(input_schema, t))
+        .map(|t| aggregate_field_for_type(&t.name))
+        .collect();
+    fields.append(&mut aggregate_fields);
     let mut fulltext_fields = schema
         .get_fulltext_directives()
         .map_err(|_| APISchemaError::FulltextSearchNonDeterministic)?
@@ -723,6 +1016,151 @@ fn query_fields_for_type(type_name: &str) -> Vec<Field> {
     ]
 }
 
+/// Generates the `Query` field that lists all versions of the entity with a
+/// given id between two blocks (e.g. `user_history`)
+fn history_field_for_type(type_name: &str) -> Field {
+    let mut skip = input_value("skip", "", Type::NamedType("Int".to_string()));
+    skip.default_value = Some(Value::Int(0.into()));
+
+    let mut first = input_value("first", "", Type::NamedType("Int".to_string()));
+    first.default_value = Some(Value::Int(100.into()));
+
+    let mut from_block = input_value("fromBlock", "", Type::NamedType("Int".to_string()));
+    from_block.description = Some(
+        "The first block to consider. Defaults to the earliest block for which \
+         the subgraph has all of its history."
+            .to_owned(),
+    );
+    // The default of `null` marks this as a history query even when
+    // `fromBlock` is omitted; it is resolved to the earliest block when the
+    // query is built
+    from_block.default_value = Some(Value::Null);
+
+    let mut to_block = input_value("toBlock", "", Type::NamedType("Int".to_string()));
+    to_block.description =
+        Some("The last block to consider. Defaults to the latest block when omitted.".to_owned());
+
+    let arguments = vec![
+        input_value(
+            "id",
+            "",
+            Type::NonNullType(Box::new(Type::NamedType("ID".to_string()))),
+        ),
+        from_block,
+        to_block,
+        skip,
+        first,
+        subgraph_error_argument(),
+    ];
+
+    Field {
+        position: Pos::default(),
+        description: Some(format!(
+            "All versions of the `{}` with the given id that existed at some block \
+             between `fromBlock` and `toBlock`, oldest first. Entities that these \
+             versions reference are resolved at `toBlock`",
+            type_name
+        )),
+        name: format!("{}_history", type_name.to_camel_case()),
+        arguments,
+        field_type: Type::NonNullType(Box::new(Type::ListType(Box::new(Type::NonNullType(
+            Box::new(Type::NamedType(type_name.to_owned())),
+        ))))),
+        directives: vec![],
+    }
+}
+
+/// Generates the `Query` field that aggregates the entities of a type over
+/// fixed block intervals (e.g. `user_intervals`)
+fn intervals_field_for_type(type_name: &str) -> Field {
+    let mut interval = input_value(
+        "interval",
+        "",
+        Type::NonNullType(Box::new(Type::NamedType("Int".to_string()))),
+    );
+    interval.description = Some("The number of blocks between two data points".to_owned());
+
+    let from_block = input_value(
+        "fromBlock",
+        "",
+        Type::NonNullType(Box::new(Type::NamedType("Int".to_string()))),
+    );
+
+    let mut to_block = input_value("toBlock", "", Type::NamedType("Int".to_string()));
+    to_block.description =
+        Some("The last block to consider. Defaults to the latest block when omitted.".to_owned());
+
+    let arguments = vec![
+        interval,
+        from_block,
+        to_block,
+        input_value(
+            "where",
+            "",
+            Type::NamedType(format!("{}_filter", type_name)),
+        ),
+        subgraph_error_argument(),
+    ];
+
+    Field {
+        position: Pos::default(),
+        description: Some(format!(
+            "Aggregates over all `{}` entities at every `interval` blocks \
+             between `fromBlock` and `toBlock`",
+            type_name
+        )),
+        name: format!("{}_intervals", type_name.to_camel_case()),
+        arguments,
+        field_type: Type::NonNullType(Box::new(Type::ListType(Box::new(Type::NonNullType(
+            Box::new(Type::NamedType(format!("{}_interval", type_name))),
+        ))))),
+        directives: vec![],
+    }
+}
+
+fn aggregate_field_for_type(type_name: &str) -> Field {
+    let group_by = input_value(
+        "groupBy",
+        "",
+        Type::ListType(Box::new(Type::NonNullType(Box::new(Type::NamedType(
+            format!("{}_groupBy", type_name),
+        ))))),
+    );
+
+    let mut skip = input_value("skip", "", Type::NamedType("Int".to_string()));
+    skip.default_value = Some(Value::Int(0.into()));
+    let mut first = input_value("first", "", Type::NamedType("Int".to_string()));
+    first.default_value = Some(Value::Int(100.into()));
+
+    let arguments = vec![
+        input_value(
+            "where",
+            "",
+            Type::NamedType(format!("{}_filter", type_name)),
+        ),
+        group_by,
+        skip,
+        first,
+        block_argument(),
+        subgraph_error_argument(),
+    ];
+
+    Field {
+        position: Pos::default(),
+        description: Some(format!(
+            "Aggregates over all `{}` entities that match `where`, with one \
+             result for each combination of values of the `groupBy` fields",
+            type_name
+        )),
+        name: format!("{}Aggregate", type_name.to_camel_case()),
+        arguments,
+        field_type: Type::NonNullType(Box::new(Type::ListType(Box::new(Type::NonNullType(
+            Box::new(Type::NamedType(format!("{}_aggregate", type_name))),
+        ))))),
+        directives: vec![],
+    }
+}
+
 fn meta_field() -> Field {
     lazy_static! {
         static ref META_FIELD: Field = Field {
@@ -758,6 +1196,30 @@ fn collection_arguments_for_named_type(type_name: &str) -> Vec<InputValue> {
     let mut first = input_value(&"first".to_string(), "", Type::NamedType("Int".to_string()));
     first.default_value = Some(Value::Int(100.into()));
 
+    let mut after = input_value(
+        &"after".to_string(),
+        "",
+        Type::NamedType("String".to_string()),
+    );
+    after.description = Some(
+        "Only return entities that come after the entity with this `_cursor` \
+         in the order of the query"
+            .to_owned(),
+    );
+
+    let mut before = input_value(
+        &"before".to_string(),
+        "",
+        Type::NamedType("String".to_string()),
+    );
+    before.description = Some(
+        "Only return entities that come before the entity with this `_cursor` \
+         in the order of the query. Combined with `first: n`, this returns the \
+         first n of those entities, not the n closest to the cursor; to page \
+         backwards, reverse `orderDirection` and use `after` instead"
+            .to_owned(),
+    );
+
     let args = vec![
         skip,
         first,
@@ -776,6 +1238,8 @@ fn collection_arguments_for_named_type(type_name: &str) -> Vec<InputValue> {
             "",
             Type::NamedType(format!("{}_filter", type_name)),
         ),
+        after,
+        before,
     ];
 
     args
@@ -854,6 +1318,54 @@ fn add_field_arguments(
     Ok(())
 }
 
+/// Adds a `_cursor` field to all object and interface types from the input
+/// schema. The field holds the position of an entity in the list that a
+/// collection field returns, and can be passed as the `after` or `before`
+/// argument to the same field to page through the list
+fn add_cursor_fields(schema: &mut Document, input_schema: &Document) -> Result<(), APISchemaError> {
+    let cursor_field = || Field {
+        position: Pos::default(),
+        description: Some(
+            "The position of this entity in the list it was returned in. \
+             It is null for entities that are not part of a list"
+                .to_owned(),
+        ),
+        name: CURSOR_FIELD_NAME.to_owned(),
+        arguments: vec![],
+        field_type: Type::NamedType("String".to_owned()),
+        directives: vec![],
+    };
+    let check_reserved = |type_name: &str, fields: &[Field]| {
+        if fields.iter().any(|field| field.name == CURSOR_FIELD_NAME) {
+            Err(APISchemaError::FieldReserved(
+                type_name.to_owned(),
+                CURSOR_FIELD_NAME.to_owned(),
+            ))
+        } else {
+            Ok(())
+        }
+    };
+
+    for input_object_type in input_schema.get_object_type_definitions() {
+        if input_object_type.name == SCHEMA_TYPE_NAME {
+            continue;
+        }
+        check_reserved(&input_object_type.name, &input_object_type.fields)?;
+        let object_type = ast::get_object_type_mut(schema, &input_object_type.name)
+            .expect("object type from input schema is missing in API schema");
+        object_type.fields.push(cursor_field());
+    }
+
+    for input_interface_type in input_schema.get_interface_type_definitions() {
+        check_reserved(&input_interface_type.name, &input_interface_type.fields)?;
+        let interface_type = ast::get_interface_type_mut(schema, &input_interface_type.name)
+            .expect("interface type from input schema is missing in API schema");
+        interface_type.fields.push(cursor_field());
+    }
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use graph::data::graphql::DocumentExt;
@@ -1107,6 +1619,8 @@ mod tests {
                 "orderBy",
                 "orderDirection",
                 "where",
+                "after",
+                "before",
                 "block",
                 "subgraphError",
             ]
@@ -1204,6 +1718,8 @@ mod tests {
                 "orderBy",
                 "orderDirection",
                 "where",
+                "after",
+                "before",
                 "block",
                 "subgraphError"
             ]
@@ -1250,4 +1766,270 @@ type Gravatar @entity {
         }
         .expect("\"metadata\" field is missing on Query type");
     }
+
+    #[test]
+    fn api_schema_contains_history_and_intervals_fields_on_query_type() {
+        let input_schema = parse_schema(
+            "type User { id: ID!, name: String!, age: Int!, balance: BigDecimal, tags: [Int!]! }",
+        )
+        .expect("Failed to parse input schema");
+        let schema = api_schema(&input_schema).expect("Failed to derive API schema");
+
+        let query_type = match schema
+            .get_named_type("Query")
+            .expect("Query type is missing in derived API schema")
+        {
+            TypeDefinition::Object(t) => t,
+            _ => panic!("Query type is not an object type"),
+        };
+
+        let history_field = ast::get_field(query_type, &"user_history".to_string())
+            .expect("\"user_history\" field is missing on Query type");
+        assert_eq!(
+            history_field.field_type,
+            Type::NonNullType(Box::new(Type::ListType(Box::new(Type::NonNullType(
+                Box::new(Type::NamedType("User".to_string()))
+            )))))
+        );
+        assert_eq!(
+            history_field
+                .arguments
+                .iter()
+                .map(|input_value| input_value.name.to_owned())
+                .collect::<Vec<String>>(),
+            [
+                "id",
+                "fromBlock",
+                "toBlock",
+                "skip",
+                "first",
+                "subgraphError"
+            ]
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<String>>()
+        );
+
+        let intervals_field = ast::get_field(query_type, &"user_intervals".to_string())
+            .expect("\"user_intervals\" field is missing on Query type");
+        assert_eq!(
+            intervals_field.field_type,
+            Type::NonNullType(Box::new(Type::ListType(Box::new(Type::NonNullType(
+                Box::new(Type::NamedType("User_interval".to_string()))
+            )))))
+        );
+        assert_eq!(
+            intervals_field
+                .arguments
+                .iter()
+                .map(|input_value| input_value.name.to_owned())
+                .collect::<Vec<String>>(),
+            ["interval", "fromBlock", "toBlock", "where", "subgraphError"]
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<String>>()
+        );
+
+        let interval_type = match schema
+            .get_named_type("User_interval")
+            .expect("User_interval type is missing in derived API schema")
+        {
+            TypeDefinition::Object(t) => t,
+            _ => panic!("User_interval type is not an object type"),
+        };
+        assert!(interval_type
+            .directives
+            .iter()
+            .any(|directive| directive.name == "aggregation"));
+
+        let field_type = |name: &str| {
+            ast::get_field(interval_type, &name.to_string())
+                .unwrap_or_else(|| panic!("\"{}\" field is missing on User_interval", name))
+                .field_type
+                .clone()
+        };
+        assert_eq!(
+            field_type("count"),
+            Type::NonNullType(Box::new(Type::NamedType("Int".to_string())))
+        );
+        assert_eq!(field_type("age_sum"), Type::NamedType("BigInt".to_string()));
+        assert_eq!(field_type("age_min"), Type::NamedType("Int".to_string()));
+        assert_eq!(
+            field_type("age_avg"),
+            Type::NamedType("BigDecimal".to_string())
+        );
+        assert_eq!(
+            field_type("balance_sum"),
+            Type::NamedType("BigDecimal".to_string())
+        );
+        assert!(ast::get_field(interval_type, &"name_sum".to_string()).is_none());
+        assert!(ast::get_field(interval_type, &"tags_sum".to_string()).is_none());
+    }
+
+    #[test]
+    fn api_schema_keeps_user_defined_interval_types() {
+        let input_schema = parse_schema(
+            "type User { id: ID!, age: Int! } type User_interval { id: ID!, start: Int! }",
+        )
+        .expect("Failed to parse input schema");
+        let schema = api_schema(&input_schema).expect("Failed to derive API schema");
+
+        let query_type = match schema
+            .get_named_type("Query")
+            .expect("Query type is missing in derived API schema")
+        {
+            TypeDefinition::Object(t) => t,
+            _ => panic!("Query type is not an object type"),
+        };
+        assert!(ast::get_field(query_type, &"user_intervals".to_string()).is_none());
+        assert!(ast::get_field(query_type, &"userAggregate".to_string()).is_none());
+        assert!(ast::get_field(query_type, &"userInterval".to_string()).is_some());
+        assert!(schema.get_named_type("User_aggregate").is_none());
+
+        let interval_type = match schema.get_named_type("User_interval") {
+            Some(TypeDefinition::Object(t)) => t,
+            _ => panic!("User_interval type is missing in derived API schema"),
+        };
+        assert!(interval_type.directives.is_empty());
+        assert!(ast::get_field(interval_type, &"start".to_string()).is_some());
+    }
+
+    #[test]
+    fn api_schema_contains_cursor_fields() {
+        let input_schema = parse_schema(
+            "interface Node { id: ID! } type User implements Node { id: ID!, name: String! }",
+        )
+        .expect("Failed to parse input schema");
+        let schema = api_schema(&input_schema).expect("Failed to derive API schema");
+
+        let user_type = match schema.get_named_type("User") {
+            Some(TypeDefinition::Object(t)) => t,
+            _ => panic!("User type is missing in derived API schema"),
+        };
+        assert_eq!(
+            ast::get_field(user_type, &"_cursor".to_string())
+                .expect("\"_cursor\" field is missing on User")
+                .field_type,
+            Type::NamedType("String".to_string())
+        );
+        let node_type = match schema.get_named_type("Node") {
+            Some(TypeDefinition::Interface(t)) => t,
+            _ => panic!("Node type is missing in derived API schema"),
+        };
+        assert!(node_type.fields.iter().any(|field| field.name == "_cursor"));
+
+        let input_schema = parse_schema("type User { id: ID!, _cursor: String }")
+            .expect("Failed to parse input schema");
+        assert!(api_schema(&input_schema).is_err());
+    }
+
+    #[test]
+    fn api_schema_contains_aggregate_field_on_query_type() {
+        let input_schema = parse_schema(
+            "type User { id: ID!, name: String!, age: Int!, tags: [Int!]!, pet: Pet } \
+             type Pet { id: ID!, owner: User! @derivedFrom(field: \"pet\") }",
+        )
+        .expect("Failed to parse input schema");
+        let schema = api_schema(&input_schema).expect("Failed to derive API schema");
+
+        let query_type = match schema
+            .get_named_type("Query")
+            .expect("Query type is missing in derived API schema")
+        {
+            TypeDefinition::Object(t) => t,
+            _ => panic!("Query type is not an object type"),
+        };
+        let aggregate_field = ast::get_field(query_type, &"userAggregate".to_string())
+            .expect("\"userAggregate\" field is missing on Query type");
+        assert_eq!(
+            aggregate_field.field_type,
+            Type::NonNullType(Box::new(Type::ListType(Box::new(Type::NonNullType(
+                Box::new(Type::NamedType("User_aggregate".to_string()))
+            )))))
+        );
+        assert_eq!(
+            aggregate_field
+                .arguments
+                .iter()
+                .map(|input_value| input_value.name.to_owned())
+                .collect::<Vec<String>>(),
+            [
+                "where",
+                "groupBy",
+                "skip",
+                "first",
+                "block",
+                "subgraphError"
+            ]
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<String>>()
+        );
+
+        let group_by_values = |name: &str| match schema.get_named_type(name) {
+            Some(TypeDefinition::Enum(t)) => t
+                .values
+                .iter()
+                .map(|value| value.name.to_owned())
+                .collect::<Vec<String>>(),
+            _ => panic!("{} type is missing in derived API schema", name),
+        };
+        assert_eq!(
+            group_by_values("User_groupBy"),
+            ["id", "name", "age", "pet"]
+        );
+        assert_eq!(group_by_values("Pet_groupBy"), ["id"]);
+
+        let aggregate_type = match schema
+            .get_named_type("User_aggregate")
+            .expect("User_aggregate type is missing in derived API schema")
+        {
+            TypeDefinition::Object(t) => t,
+            _ => panic!("User_aggregate type is not an object type"),
+        };
+        assert!(aggregate_type
+            .directives
+            .iter()
+            .any(|directive| directive.name == "aggregation"));
+        let field_type = |name: &str| {
+            ast::get_field(aggregate_type, &name.to_string())
+                .unwrap_or_else(|| panic!("\"{}\" field is missing on User_aggregate", name))
+                .field_type
+                .clone()
+        };
+        assert_eq!(
+            field_type("count"),
+            Type::NonNullType(Box::new(Type::NamedType("Int".to_string())))
+        );
+        assert_eq!(field_type("name"), Type::NamedType("String".to_string()));
+        assert_eq!(field_type("pet"), Type::NamedType("ID".to_string()));
+        assert_eq!(field_type("age_sum"), Type::NamedType("BigInt".to_string()));
+        assert!(ast::get_field(aggregate_type, &"tags".to_string()).is_none());
+    }
+
+    #[test]
+    fn api_schema_skips_group_aggregates_with_clashing_names() {
+        for input in &[
+            "type User { id: ID!, count: Int!, age: Int! }",
+            "type User { id: ID!, age: Int!, age_sum: BigInt }",
+        ] {
+            let input_schema = parse_schema(input).expect("Failed to parse input schema");
+            let schema = api_schema(&input_schema).expect("Failed to derive API schema");
+
+            let query_type = match schema
+                .get_named_type("Query")
+                .expect("Query type is missing in derived API schema")
+            {
+                TypeDefinition::Object(t) => t,
+                _ => panic!("Query type is not an object type"),
+            };
+            assert!(ast::get_field(query_type, &"userAggregate".to_string()).is_none());
+            assert!(schema.get_named_type("User_aggregate").is_none());
+            assert!(schema.get_named_type("User_groupBy").is_none());
+
+            // Intervals only have aggregates and can not clash
+            assert!(ast::get_field(query_type, &"user_intervals".to_string()).is_some());
+            assert!(schema.get_named_type("User_interval").is_some());
+        }
+    }
 }
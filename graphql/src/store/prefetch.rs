@@ -15,11 +15,12 @@ use std::time::Instant;
 
 use graph::{components::store::EntityType, data::graphql::*};
 use graph::{
-    data::graphql::ext::DirectiveFinder,
+    data::{graphql::ext::DirectiveFinder, schema::CURSOR_FIELD_NAME},
     prelude::{
         q, s, ApiSchema, AttributeNames, BlockNumber, ChildMultiplicity, EntityCollection,
-        EntityFilter, EntityLink, EntityOrder, EntityWindow, Logger, ParentLink,
-        QueryExecutionError, QueryStore, StoreError, Value as StoreValue, WindowAttribute,
+        EntityCursor, EntityFilter, EntityLink, EntityOrder, EntityWindow, Logger, ParentLink,
+        QueryExecutionError, QueryStore, StoreError, Value as StoreValue, ValueType,
+        WindowAttribute,
     },
 };
 
@@ -570,6 +571,7 @@ fn execute_root_selection_set(
         make_root_node(),
         grouped_field_set,
         ComplementaryFields::new(),
+        resolver.block_number(),
     )
 }
 
@@ -589,6 +591,7 @@ fn execute_selection_set<'a>(
     mut parents: Vec<Node>,
     grouped_field_set: GroupedFieldSet<'a>,
     mut complementary_fields: ComplementaryFields<'a>,
+    block: BlockNumber,
 ) -> Result<Vec<Node>, Vec<QueryExecutionError>> {
     let schema = &ctx.query.schema;
     let mut errors: Vec<QueryExecutionError> = Vec::new();
@@ -639,14 +642,23 @@ fn execute_selection_set<'a>(
             // If this environment variable is set, the program will use an empty collection that,
             // effectively, causes the `AttributeNames::All` variant to be used as a fallback value for all
             // queries.
-            let collected_columns =
+            let (collected_columns, with_cursors) =
                 if *DISABLE_EXPERIMENTAL_FEATURE_SELECT_BY_SPECIFIC_ATTRIBUTE_NAMES {
-                    BTreeMap::new()
+                    let with_cursors = grouped_field_set.values().any(|response_key| {
+                        response_key
+                            .collected_column_names
+                            .selects(CURSOR_FIELD_NAME)
+                    });
+                    (BTreeMap::new(), with_cursors)
                 } else {
                     let mut collected =
                         CollectedAttributeNames::consolidate_column_names(&mut grouped_field_set);
+                    let with_cursors = collected.take_cursor_field();
                     collected.populate_complementary_fields(&mut complementary_fields);
-                    collected.resolve_interfaces(&ctx.query.schema.types_for_interface())
+                    (
+                        collected.resolve_interfaces(&ctx.query.schema.types_for_interface()),
+                        with_cursors,
+                    )
                 };
 
             match execute_field(
@@ -658,14 +670,17 @@ fn execute_selection_set<'a>(
                 &fields[0],
                 field,
                 collected_columns,
+                with_cursors,
+                block,
             ) {
-                Ok(children) => {
+                Ok((children, child_block)) => {
                     match execute_selection_set(
                         resolver,
                         ctx,
                         children,
                         grouped_field_set,
                         new_complementary_fields,
+                        child_block,
                     ) {
                         Ok(children) => {
                             Join::perform(&mut parents, children, response_key);
@@ -972,7 +987,8 @@ fn collect_fields_inner<'a>(
     }
 }
 
-/// Executes a field.
+/// Executes a field at `block`. Returns the entities for the field
+/// together with the block at which fields nested in it should be resolved
 fn execute_field(
     resolver: &StoreResolver,
     ctx: &ExecutionContext<impl Resolver>,
@@ -982,7 +998,9 @@ fn execute_field(
     field: &q::Field,
     field_definition: &s::Field,
     collected_column_names: AttributeNamesByObjectType<'_>,
-) -> Result<Vec<Node>, Vec<QueryExecutionError>> {
+    with_cursors: bool,
+    block: BlockNumber,
+) -> Result<(Vec<Node>, BlockNumber), Vec<QueryExecutionError>> {
     let argument_values = crate::execution::coerce_argument_values(&ctx.query, object_type, field)?;
     let multiplicity = if sast::is_list_or_non_null_list_field(field_definition) {
         ChildMultiplicity::Many
@@ -998,18 +1016,27 @@ fn execute_field(
         argument_values,
         multiplicity,
         ctx.query.schema.types_for_interface(),
-        resolver.block_number(),
+        block,
+        resolver.earliest_block,
         ctx.max_first,
         ctx.max_skip,
         ctx.query.query_id.clone(),
         collected_column_names,
+        with_cursors,
     )
     .map_err(|e| vec![e])
 }
 
 /// Query child entities for `parents` from the store. The `join` indicates
 /// in which child field to look for the parent's id/join field. When
-/// `is_single` is `true`, there is at most one child per parent.
+/// `is_single` is `true`, there is at most one child per parent. When
+/// `with_cursors` is `true`, each child gets a `_cursor` field with its
+/// position in the list of children.
+///
+/// Besides the children, return the block at which the fields of the
+/// children need to be resolved. That is `block`, except for the versions
+/// listed by a `_history` field: their fields are resolved at the end of
+/// the span of that field, i.e., at its `toBlock`
 fn fetch(
     logger: Logger,
     store: &(impl QueryStore + ?Sized),
@@ -1019,14 +1046,17 @@ fn fetch(
     multiplicity: ChildMultiplicity,
     types_for_interface: &BTreeMap<EntityType, Vec<s::ObjectType>>,
     block: BlockNumber,
+    earliest_block: BlockNumber,
     max_first: u32,
     max_skip: u32,
     query_id: String,
     collected_column_names: AttributeNamesByObjectType<'_>,
-) -> Result<Vec<Node>, QueryExecutionError> {
+    with_cursors: bool,
+) -> Result<(Vec<Node>, BlockNumber), QueryExecutionError> {
     let mut query = build_query(
         join.child_type,
         block,
+        earliest_block,
         &arguments,
         types_for_interface,
         max_first,
@@ -1058,9 +1088,56 @@ fn fetch(
         }
         query.collection = EntityCollection::Window(windows);
     }
-    store
-        .find_query_values(query)
-        .map(|entities| entities.into_iter().map(|entity| entity.into()).collect())
+    let order = query.order.clone();
+    let child_block = query.history.map_or(block, |span| span.to);
+    store.find_query_values(query).map(|entities| {
+        let children = entities
+            .into_iter()
+            .map(|mut entity| {
+                if with_cursors {
+                    add_cursor(&mut entity, &order);
+                }
+                entity.into()
+            })
+            .collect();
+        (children, child_block)
+    })
+}
+
+/// Add the `_cursor` field to an `entity` that was returned by a query
+/// with the given `order`. Entities that were not fetched in any specific
+/// order do not have a cursor
+fn add_cursor(entity: &mut BTreeMap<String, r::Value>, order: &EntityOrder) {
+    let id = match entity.get("id") {
+        Some(r::Value::String(id)) => id.clone(),
+        _ => return,
+    };
+    let cursor = match order {
+        EntityOrder::Ascending(attribute, value_type)
+        | EntityOrder::Descending(attribute, value_type) => {
+            let type_name = match value_type {
+                ValueType::Boolean => "Boolean",
+                ValueType::BigInt => "BigInt",
+                ValueType::Bytes => "Bytes",
+                ValueType::BigDecimal => "BigDecimal",
+                ValueType::Int => "Int",
+                ValueType::String => "String",
+            };
+            let value = entity.get(attribute).unwrap_or(&r::Value::Null);
+            match StoreValue::from_query_value(value, &s::Type::NamedType(type_name.to_owned())) {
+                Ok(value) => EntityCursor::new(attribute.clone(), value, id),
+                Err(_) => return,
+            }
+        }
+        EntityOrder::Default => {
+            EntityCursor::new("id".to_owned(), StoreValue::String(id.clone()), id)
+        }
+        EntityOrder::Unordered => return,
+    };
+    entity.insert(
+        CURSOR_FIELD_NAME.to_owned(),
+        r::Value::String(cursor.encode()),
+    );
 }
 
 /// Represents a finished column collection operation, mapping each object type to the final set of
@@ -1078,6 +1155,27 @@ impl<'a> CollectedAttributeNames<'a> {
             .add(field);
     }
 
+    /// Returns `true` if any object or interface selected the attribute
+    /// `name`
+    fn selects(&self, name: &str) -> bool {
+        self.0.values().any(|names| match names {
+            AttributeNames::All => false,
+            AttributeNames::Select(names) => names.contains(name),
+        })
+    }
+
+    /// Removes the `_cursor` field from the selected attributes since it
+    /// is not stored in the database. Returns `true` if any object or
+    /// interface selected it
+    fn take_cursor_field(&mut self) -> bool {
+        self.0
+            .values_mut()
+            .fold(false, |selected, names| match names {
+                AttributeNames::All => selected,
+                AttributeNames::Select(names) => names.remove(CURSOR_FIELD_NAME) || selected,
+            })
+    }
+
     /// Injects complementary fields that were collected priviously in upper hierarchical levels of
     /// the query into `self`.
     fn populate_complementary_fields(
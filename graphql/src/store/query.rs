@@ -4,6 +4,7 @@ use std::mem::discriminant;
 use graph::prelude::*;
 use graph::{components::store::EntityType, data::graphql::ObjectOrInterface};
 
+use crate::schema::api::aggregatable_fields;
 use crate::schema::ast as sast;
 use crate::store::prefetch::ObjectCondition;
 
@@ -19,6 +20,7 @@ enum OrderDirection {
 pub fn build_query<'a>(
     entity: impl Into<ObjectOrInterface<'a>>,
     block: BlockNumber,
+    earliest_block: BlockNumber,
     arguments: &HashMap<&str, r::Value>,
     types_for_interface: &'a BTreeMap<EntityType, Vec<s::ObjectType>>,
     max_first: u32,
@@ -62,9 +64,147 @@ pub fn build_query<'a>(
         (None, _) => EntityOrder::Default,
     };
     query = query.order(order);
+    if let Some(history) = build_history(arguments, block, earliest_block)? {
+        query = query.history(history);
+    }
     Ok(query)
 }
 
+/// Builds an AggregateQuery from the GraphQL arguments of an `_intervals`
+/// field. The query computes all aggregates for all numeric attributes of
+/// `entity`
+pub fn build_aggregate_query(
+    entity: &s::ObjectType,
+    block: BlockNumber,
+    earliest_block: BlockNumber,
+    arguments: &HashMap<&str, r::Value>,
+    max_intervals: i64,
+) -> Result<AggregateQuery, QueryExecutionError> {
+    let interval = match arguments.get("interval") {
+        Some(r::Value::Int(n)) if *n > 0 && *n <= BLOCK_NUMBER_MAX as i64 => *n as BlockNumber,
+        Some(r::Value::Int(n)) => {
+            return Err(QueryExecutionError::RangeArgumentsError(
+                "interval",
+                BLOCK_NUMBER_MAX as u32,
+                *n,
+            ))
+        }
+        _ => unreachable!("interval is a non-null Int"),
+    };
+    let from = match arguments.get("fromBlock") {
+        Some(r::Value::Int(n)) => *n,
+        _ => unreachable!("fromBlock is a non-null Int"),
+    };
+    let blocks = build_block_span(from, arguments.get("toBlock"), block, earliest_block)?;
+
+    let aggregates = aggregatable_fields(&entity.fields)
+        .flat_map(|(name, _)| {
+            AggregateFunction::ALL
+                .iter()
+                .map(move |function| AttributeAggregate::new(name.to_owned(), *function))
+        })
+        .collect();
+    let mut query = AggregateQuery::new(
+        parse_subgraph_id(entity)?,
+        EntityType::from(entity),
+        blocks,
+        interval,
+    )
+    .aggregates(aggregates);
+    if query.data_points() > max_intervals {
+        return Err(QueryExecutionError::TooManyIntervals(
+            query.data_points(),
+            max_intervals,
+        ));
+    }
+    if let Some(filter) = build_filter(entity.into(), arguments)? {
+        query = query.filter(filter);
+    }
+    Ok(query)
+}
+
+/// Builds an AggregateQuery for a `<type>Aggregate` field from its GraphQL
+/// arguments. The query aggregates all entities that match `where` at
+/// `block`, grouped by the fields listed in `groupBy`
+pub fn build_group_query(
+    entity: &s::ObjectType,
+    block: BlockNumber,
+    arguments: &HashMap<&str, r::Value>,
+    max_groups: u32,
+) -> Result<AggregateQuery, QueryExecutionError> {
+    let aggregates = aggregatable_fields(&entity.fields)
+        .flat_map(|(name, _)| {
+            AggregateFunction::ALL
+                .iter()
+                .map(move |function| AttributeAggregate::new(name.to_owned(), *function))
+        })
+        .collect();
+    let group_by = match arguments.get("groupBy") {
+        Some(r::Value::List(fields)) => fields
+            .iter()
+            .map(|field| match field {
+                r::Value::Enum(name) | r::Value::String(name) => name.clone(),
+                _ => unreachable!("groupBy is a list of non-null enum values"),
+            })
+            .collect(),
+        Some(r::Value::Null) | None => vec![],
+        _ => unreachable!("groupBy is a list of enum values"),
+    };
+    let mut query =
+        AggregateQuery::at_block(parse_subgraph_id(entity)?, EntityType::from(entity), block)
+            .aggregates(aggregates)
+            .group_by(group_by)
+            .range(build_range(arguments, max_groups, u32::MAX)?);
+    if let Some(filter) = build_filter(entity.into(), arguments)? {
+        query = query.filter(filter);
+    }
+    Ok(query)
+}
+
+/// Parses the `fromBlock` and `toBlock` arguments of `_history` fields into
+/// a BlockSpan, if present. `fromBlock` defaults to `earliest_block`, the
+/// earliest block for which the subgraph still has all its history
+fn build_history(
+    arguments: &HashMap<&str, r::Value>,
+    block: BlockNumber,
+    earliest_block: BlockNumber,
+) -> Result<Option<BlockSpan>, QueryExecutionError> {
+    let from = match arguments.get("fromBlock") {
+        Some(r::Value::Int(n)) => *n,
+        Some(r::Value::Null) => earliest_block as i64,
+        None => return Ok(None),
+        _ => unreachable!("fromBlock is an Int"),
+    };
+    build_block_span(from, arguments.get("toBlock"), block, earliest_block).map(Some)
+}
+
+/// Builds the span from `from` to `to`, where `to` defaults to `block`.
+/// Since we only know the state of the subgraph up to `block`, the span
+/// must end at or before it, and since history before `earliest_block` has
+/// been pruned, it must start at or after that
+fn build_block_span(
+    from: i64,
+    to: Option<&r::Value>,
+    block: BlockNumber,
+    earliest_block: BlockNumber,
+) -> Result<BlockSpan, QueryExecutionError> {
+    let to = match to {
+        Some(r::Value::Int(n)) => *n,
+        Some(r::Value::Null) | None => block as i64,
+        _ => unreachable!("toBlock is an Int"),
+    };
+    if from < 0 || from > to || to > block as i64 {
+        return Err(QueryExecutionError::InvalidBlockSpan(from, to, block));
+    }
+    if from < earliest_block as i64 {
+        return Err(QueryExecutionError::HistoryPruned(
+            from as BlockNumber,
+            earliest_block,
+        ));
+    }
+    Ok(BlockSpan::new(from as BlockNumber, to as BlockNumber))
+}
+
 /// Parses GraphQL arguments into a EntityRange, if present.
 fn build_range(
     arguments: &HashMap<&str, r::Value>,
@@ -101,9 +241,17 @@ fn build_range(
         _ => unreachable!("skip is an Int with a default value"),
     };
 
+    let cursor = |name: &str| match arguments.get(name) {
+        Some(r::Value::String(cursor)) => EntityCursor::decode(cursor).map(Some),
+        Some(r::Value::Null) | None => Ok(None),
+        _ => unreachable!("{} is a String", name),
+    };
+
     Ok(EntityRange {
         first: Some(first),
         skip,
+        after: cursor("after")?,
+        before: cursor("before")?,
     })
 }
 
@@ -432,6 +580,7 @@ mod tests {
             build_query(
                 &object("Entity1"),
                 BLOCK_NUMBER_MAX,
+                0,
                 &default_arguments(),
                 &BTreeMap::new(),
                 std::u32::MAX,
@@ -446,6 +595,7 @@ mod tests {
             build_query(
                 &object("Entity2"),
                 BLOCK_NUMBER_MAX,
+                0,
                 &default_arguments(),
                 &BTreeMap::new(),
                 std::u32::MAX,
@@ -464,6 +614,7 @@ mod tests {
             build_query(
                 &default_object(),
                 BLOCK_NUMBER_MAX,
+                0,
                 &default_arguments(),
                 &BTreeMap::new(),
                 std::u32::MAX,
@@ -485,6 +636,7 @@ mod tests {
             build_query(
                 &default_object(),
                 BLOCK_NUMBER_MAX,
+                0,
                 &args,
                 &BTreeMap::new(),
                 std::u32::MAX,
@@ -502,6 +654,7 @@ mod tests {
             build_query(
                 &default_object(),
                 BLOCK_NUMBER_MAX,
+                0,
                 &args,
                 &BTreeMap::new(),
                 std::u32::MAX,
@@ -523,6 +676,7 @@ mod tests {
             build_query(
                 &default_object(),
                 BLOCK_NUMBER_MAX,
+                0,
                 &args,
                 &BTreeMap::new(),
                 std::u32::MAX,
@@ -540,6 +694,7 @@ mod tests {
             build_query(
                 &default_object(),
                 BLOCK_NUMBER_MAX,
+                0,
                 &args,
                 &BTreeMap::new(),
                 std::u32::MAX,
@@ -563,6 +718,7 @@ mod tests {
             build_query(
                 &default_object(),
                 BLOCK_NUMBER_MAX,
+                0,
                 &args,
                 &BTreeMap::new(),
                 std::u32::MAX,
@@ -581,6 +737,7 @@ mod tests {
             build_query(
                 &default_object(),
                 BLOCK_NUMBER_MAX,
+                0,
                 &args,
                 &BTreeMap::new(),
                 std::u32::MAX,
@@ -602,6 +759,7 @@ mod tests {
             build_query(
                 &default_object(),
                 BLOCK_NUMBER_MAX,
+                0,
                 &args,
                 &BTreeMap::new(),
                 std::u32::MAX,
@@ -623,6 +781,7 @@ mod tests {
             build_query(
                 &default_object(),
                 BLOCK_NUMBER_MAX,
+                0,
                 &args,
                 &BTreeMap::new(),
                 std::u32::MAX,
@@ -646,6 +805,7 @@ mod tests {
             build_query(
                 &default_object(),
                 BLOCK_NUMBER_MAX,
+                0,
                 &args,
                 &BTreeMap::new(),
                 std::u32::MAX,
@@ -664,6 +824,7 @@ mod tests {
             build_query(
                 &default_object(),
                 BLOCK_NUMBER_MAX,
+                0,
                 &args,
                 &BTreeMap::new(),
                 std::u32::MAX,
@@ -682,6 +843,7 @@ mod tests {
             build_query(
                 &default_object(),
                 BLOCK_NUMBER_MAX,
+                0,
                 &default_arguments(),
                 &BTreeMap::new(),
                 std::u32::MAX,
@@ -703,6 +865,7 @@ mod tests {
             build_query(
                 &default_object(),
                 BLOCK_NUMBER_MAX,
+                0,
                 &args,
                 &BTreeMap::new(),
                 std::u32::MAX,
@@ -714,6 +877,8 @@ mod tests {
             EntityRange {
                 first: Some(100),
                 skip: 50,
+                after: None,
+                before: None,
             },
         );
     }
@@ -736,6 +901,7 @@ mod tests {
                     ..default_object()
                 },
                 BLOCK_NUMBER_MAX,
+                0,
                 &args,
                 &BTreeMap::new(),
                 std::u32::MAX,
@@ -750,4 +916,41 @@ mod tests {
             )]))
         )
     }
+
+    #[test]
+    fn build_query_respects_pruned_history() {
+        let history = |from_block: r::Value, earliest_block: BlockNumber| {
+            let mut args = default_arguments();
+            args.insert("fromBlock", from_block);
+            build_query(
+                &object("Entity1"),
+                10,
+                earliest_block,
+                &args,
+                &BTreeMap::new(),
+                std::u32::MAX,
+                std::u32::MAX,
+                Default::default(),
+            )
+            .map(|query| query.history)
+        };
+
+        // `fromBlock` defaults to the earliest block
+        assert_eq!(
+            Some(BlockSpan::new(4, 10)),
+            history(r::Value::Null, 4).unwrap()
+        );
+        assert_eq!(
+            Some(BlockSpan::new(0, 10)),
+            history(r::Value::Null, 0).unwrap()
+        );
+        assert_eq!(
+            Some(BlockSpan::new(5, 10)),
+            history(r::Value::Int(5.into()), 4).unwrap()
+        );
+        assert!(matches!(
+            history(r::Value::Int(3.into()), 4),
+            Err(QueryExecutionError::HistoryPruned(3, 4))
+        ));
+    }
 }
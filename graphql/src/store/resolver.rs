@@ -1,29 +1,51 @@
 use std::collections::{BTreeMap, HashMap};
+use std::env;
 use std::result;
+use std::str::FromStr;
 use std::sync::Arc;
 
 use graph::data::{
-    graphql::{object, ObjectOrInterface},
-    schema::META_FIELD_TYPE,
+    graphql::{object, DocumentExt, ObjectOrInterface},
+    schema::{AGGREGATION_DIRECTIVE, META_FIELD_TYPE},
 };
+use graph::env::env_var;
 use graph::prelude::*;
 use graph::{components::store::*, data::schema::BLOCK_FIELD_TYPE};
+use lazy_static::lazy_static;
 
 use crate::query::ext::BlockConstraint;
 use crate::runner::ResultSizeMetrics;
 use crate::schema::ast as sast;
 use crate::{prelude::*, schema::api::ErrorPolicy};
 
-use crate::store::query::collect_entities_from_query_field;
+use crate::store::query::{
+    build_aggregate_query, build_group_query, collect_entities_from_query_field,
+};
+
+lazy_static! {
+    /// The maximum number of data points that a query for block interval
+    /// aggregations may produce
+    static ref MAX_INTERVALS: i64 = env::var("GRAPH_GRAPHQL_MAX_INTERVALS")
+        .ok()
+        .map(|s| i64::from_str(&s)
+            .unwrap_or_else(|_| panic!("failed to parse env var GRAPH_GRAPHQL_MAX_INTERVALS")))
+        .unwrap_or(1000);
+
+    /// The maximum number of groups that a `<type>Aggregate` query may
+    /// return
+    static ref MAX_GROUPS: u32 = env_var("GRAPH_GRAPHQL_MAX_GROUPS", 1000);
+}
 
 /// A resolver that fetches entities from a `Store`.
 #[derive(Clone)]
 pub struct StoreResolver {
-    #[allow(dead_code)]
     logger: Logger,
     pub(crate) store: Arc<dyn QueryStore>,
     subscription_manager: Arc<dyn SubscriptionManager>,
     pub(crate) block_ptr: Option<BlockPtr>,
+    /// The earliest block for which the deployment still has all its
+    /// history; queries can not look at blocks before that
+    pub(crate) earliest_block: BlockNumber,
     deployment: DeploymentHash,
     has_non_fatal_errors: bool,
     error_policy: ErrorPolicy,
@@ -49,6 +71,7 @@ impl StoreResolver {
             store,
             subscription_manager,
             block_ptr: None,
+            earliest_block: 0,
             deployment,
 
             // Checking for non-fatal errors does not work with subscriptions.
@@ -62,12 +85,14 @@ impl StoreResolver {
     /// by `bc`. Any calls to find objects will always return entities as
     /// of that block. Note that if `bc` is `BlockConstraint::Latest` we use
     /// whatever the latest block for the subgraph was when the resolver was
-    /// created
+    /// created. Queries for blocks before `earliest_block`, whose history
+    /// has been pruned, fail
     pub async fn at_block(
         logger: &Logger,
         store: Arc<dyn QueryStore>,
         subscription_manager: Arc<dyn SubscriptionManager>,
         bc: BlockConstraint,
+        earliest_block: BlockNumber,
         error_policy: ErrorPolicy,
         deployment: DeploymentHash,
         result_size: Arc<ResultSizeMetrics>,
@@ -80,6 +105,12 @@ impl StoreResolver {
         .await
         .map_err(|e| QueryExecutionError::Panic(e.to_string()))
         .and_then(|x| x)?; // Propagate panics.
+        if block_ptr.number < earliest_block {
+            return Err(QueryExecutionError::HistoryPruned(
+                block_ptr.number,
+                earliest_block,
+            ));
+        }
 
         let has_non_fatal_errors = store
             .has_non_fatal_errors(Some(block_ptr.block_number()))
@@ -90,6 +121,7 @@ impl StoreResolver {
             store,
             subscription_manager,
             block_ptr: Some(block_ptr),
+            earliest_block,
             deployment,
             has_non_fatal_errors,
             error_policy,
@@ -224,6 +256,72 @@ impl StoreResolver {
         }
         Ok((prefetched_object, None))
     }
+
+    /// Compute the block interval aggregations for an `_intervals` field.
+    /// Like `_meta`, these fields are not handled by prefetch, and we
+    /// compute them eagerly here
+    fn handle_aggregation(
+        &self,
+        field: &q::Field,
+        object_type: &ObjectOrInterface<'_>,
+        arguments: &HashMap<&str, r::Value>,
+    ) -> Result<r::Value, QueryExecutionError> {
+        let entity_name = object_type
+            .directives()
+            .iter()
+            .find(|directive| directive.name == AGGREGATION_DIRECTIVE)
+            .and_then(|directive| {
+                directive
+                    .arguments
+                    .iter()
+                    .find(|(name, _)| name == "entity")
+            })
+            .and_then(|(_, value)| match value {
+                s::Value::String(name) => Some(name.clone()),
+                _ => None,
+            })
+            .ok_or_else(|| {
+                QueryExecutionError::ResolveEntitiesError(format!(
+                    "internal error resolving {}.{}: \
+                     aggregation type is missing the entity it aggregates",
+                    object_type.name(),
+                    &field.name,
+                ))
+            })?;
+        let schema = self.store.api_schema()?;
+        let entity = schema
+            .document()
+            .get_object_type_definition(&entity_name)
+            .ok_or_else(|| QueryExecutionError::NamedTypeError(entity_name.clone()))?;
+
+        // `_intervals` fields aggregate over a range of blocks, and
+        // `<type>Aggregate` fields group the entities at a single block
+        let mut query = if arguments.contains_key("interval") {
+            build_aggregate_query(
+                entity,
+                self.block_number(),
+                self.earliest_block,
+                arguments,
+                *MAX_INTERVALS,
+            )?
+        } else {
+            build_group_query(entity, self.block_number(), arguments, *MAX_GROUPS)?
+        };
+        query.logger = Some(self.logger.clone());
+        let rows = self
+            .store
+            .aggregate(query)?
+            .into_iter()
+            .map(|mut row| {
+                row.insert(
+                    "__typename".to_string(),
+                    r::Value::String(object_type.name().to_string()),
+                );
+                r::Value::Object(row)
+            })
+            .collect();
+        Ok(r::Value::List(rows))
+    }
 }
 
 #[async_trait]
@@ -248,10 +346,12 @@ impl Resolver for StoreResolver {
         field: &q::Field,
         _field_definition: &s::Field,
         object_type: ObjectOrInterface<'_>,
-        _arguments: &HashMap<&str, r::Value>,
+        arguments: &HashMap<&str, r::Value>,
     ) -> Result<r::Value, QueryExecutionError> {
         if let Some(child) = prefetched_objects {
             Ok(child)
+        } else if object_type.is_aggregation() {
+            self.handle_aggregation(field, &object_type, arguments)
         } else {
             Err(QueryExecutionError::ResolveEntitiesError(format!(
                 "internal error resolving {}.{}: \
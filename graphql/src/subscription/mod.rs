@@ -190,11 +190,16 @@ async fn execute_subscription_event(
     max_skip: u32,
     result_size: Arc<ResultSizeMetrics>,
 ) -> Arc<QueryResult> {
+    let state = match store.deployment_state().await {
+        Ok(state) => state,
+        Err(e) => return Arc::new(e.into()),
+    };
     let resolver = match StoreResolver::at_block(
         &logger,
         store,
         subscription_manager,
         BlockConstraint::Latest,
+        state.earliest_block_number,
         ErrorPolicy::Deny,
         query.schema.id().clone(),
         result_size,
@@ -66,6 +66,7 @@ fn setup_with_features(
         data_sources: vec![],
         graft: None,
         templates: vec![],
+        indexer_hints: Default::default(),
         chain: PhantomData,
     };
 
@@ -1097,6 +1098,87 @@ fn first_is_nullable() {
     })
 }
 
+#[test]
+fn can_page_with_cursors() {
+    run_test_sequentially(|store| async move {
+        let deployment = setup(store.as_ref());
+
+        // Return the names and cursors of the musicians that
+        // `musicians(arguments)` returns
+        async fn page(id: &DeploymentHash, arguments: &str) -> Vec<(String, String)> {
+            let query = format!("query {{ musicians({}) {{ name _cursor }} }}", arguments);
+            let query = graphql_parser::parse_query(&query)
+                .expect("invalid test query")
+                .into_static();
+            let result = execute_query_document(id, query).await;
+            assert!(!result.has_errors(), "query failed: {:?}", result);
+            let data = serde_json::to_value(&result).unwrap();
+            data["data"]["musicians"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|musician| {
+                    (
+                        musician["name"].as_str().unwrap().to_owned(),
+                        musician["_cursor"].as_str().unwrap().to_owned(),
+                    )
+                })
+                .collect()
+        }
+        fn names(page: &[(String, String)]) -> Vec<&str> {
+            page.iter().map(|(name, _)| name.as_str()).collect()
+        }
+
+        let first = page(&deployment.hash, "orderBy: name, first: 2").await;
+        assert_eq!(vec!["John", "Lisa"], names(&first));
+
+        let after = format!("orderBy: name, first: 2, after: \"{}\"", first[1].1);
+        let second = page(&deployment.hash, &after).await;
+        assert_eq!(vec!["Tom", "Valerie"], names(&second));
+
+        let before = format!("orderBy: name, before: \"{}\"", second[1].1);
+        let all_but_last = page(&deployment.hash, &before).await;
+        assert_eq!(vec!["John", "Lisa", "Tom"], names(&all_but_last));
+
+        // `first` counts from the start of the list, not from the cursor
+        let before = format!("orderBy: name, first: 2, before: \"{}\"", second[1].1);
+        let first_before = page(&deployment.hash, &before).await;
+        assert_eq!(vec!["John", "Lisa"], names(&first_before));
+
+        // Paging backwards from a cursor reverses the order instead
+        let backwards = format!(
+            "orderBy: name, orderDirection: desc, first: 2, after: \"{}\"",
+            second[1].1
+        );
+        let previous = page(&deployment.hash, &backwards).await;
+        assert_eq!(vec!["Tom", "Lisa"], names(&previous));
+
+        let desc = format!(
+            "orderBy: name, orderDirection: desc, after: \"{}\"",
+            second[0].1
+        );
+        let reversed = page(&deployment.hash, &desc).await;
+        assert_eq!(vec!["Lisa", "John"], names(&reversed));
+
+        // Cursors from the default order work with the default order
+        let by_id = page(&deployment.hash, "first: 1").await;
+        let after = format!("after: \"{}\"", by_id[0].1);
+        let rest = page(&deployment.hash, &after).await;
+        assert_eq!(vec!["Lisa", "Tom", "Valerie"], names(&rest));
+
+        // A cursor for one order can not be used with another one
+        let query = format!(
+            "query {{ musicians(orderBy: id, after: \"{}\") {{ name }} }}",
+            first[0].1
+        );
+        let query = graphql_parser::parse_query(&query)
+            .expect("invalid test query")
+            .into_static();
+        let result = execute_query_document(&deployment.hash, query).await;
+        assert!(result.has_errors());
+    })
+}
+
 #[test]
 fn nested_variable() {
     run_test_sequentially(|store| async move {
@@ -14,8 +14,8 @@ use graph::{
 };
 use graph_node::{manager::PanicSubscriptionManager, store_builder::StoreBuilder};
 use graph_store_postgres::{
-    connection_pool::ConnectionPool, BlockStore, Shard, Store, SubgraphStore, SubscriptionManager,
-    PRIMARY_SHARD,
+    command_support::dump::Format, connection_pool::ConnectionPool, BlockStore, Shard, Store,
+    SubgraphStore, SubscriptionManager, PRIMARY_SHARD,
 };
 
 use graph_node::config::{self, Config as Cfg};
@@ -144,6 +144,56 @@ pub enum Command {
         /// The deployments to rewind
         names: Vec<String>,
     },
+    /// Export the data and metadata of a deployment into a directory
+    ///
+    /// Writes one CSV or Parquet file per entity table, including all
+    /// entity versions and their block ranges, and a `manifest.json`
+    /// describing the deployment, its schema, and the block up to which it
+    /// was indexed. Only CSV dumps can be restored with `restore`
+    Dump {
+        /// The format of the data files
+        #[structopt(long, default_value = "csv", possible_values = &["csv", "parquet"])]
+        format: Format,
+        /// The IPFS hash of the deployment to dump
+        deployment: String,
+        /// The directory into which to write the dump
+        dir: String,
+        /// The shard of the deployment if `deployment` itself is ambiguous
+        shard: Option<String>,
+    },
+    /// Show or change how much history a deployment keeps
+    ///
+    /// A deployment that keeps `blocks` blocks of history can only be
+    /// queried at blocks that are at most that many blocks (plus the reorg
+    /// threshold) behind its head. Older entity versions are removed
+    /// periodically by `graph-node`. Without `blocks` or `--unlimited`,
+    /// only print the current setting
+    History {
+        /// Keep all history from now on; history that was already removed
+        /// can not be brought back
+        #[structopt(long, conflicts_with = "blocks")]
+        unlimited: bool,
+        /// The shard of the deployment if `deployment` itself is ambiguous
+        #[structopt(long)]
+        shard: Option<String>,
+        /// The IPFS hash of the deployment
+        deployment: String,
+        /// How many blocks of history to keep
+        blocks: Option<i32>,
+    },
+    /// Create a new deployment from a dump made with `dump` in CSV format
+    ///
+    /// The deployment is created in `shard` with the data from the dump
+    /// and assigned to `node`, which will continue indexing it from the
+    /// block at which the dump was made
+    Restore {
+        /// The directory that contains the dump
+        dir: String,
+        /// The name of the database shard into which to restore
+        shard: String,
+        /// The name of the node that should index the deployment
+        node: String,
+    },
     /// Check and interrogate the configuration
     ///
     /// Print information about a configuration file without
@@ -168,6 +218,17 @@ pub enum Command {
     Chain(ChainCommand),
     /// Manipulate internal subgraph statistics
     Stats(StatsCommand),
+    /// Manage the local disk cache for IPFS files
+    ///
+    /// The cache is in the directory given by `GRAPH_IPFS_DISK_CACHE_DIR`
+    /// and is shared with the graph-node processes that use that directory
+    IpfsCache(IpfsCacheCommand),
+    /// Manage the persisted queries that GraphQL over HTTP accepts
+    ///
+    /// With `GRAPH_GRAPHQL_PERSISTED_QUERIES=strict`, query nodes only run
+    /// queries that have the same shape as one of these queries. Query
+    /// nodes pick up changes within a minute
+    PersistedQuery(PersistedQueryCommand),
 }
 
 impl Command {
@@ -175,7 +236,7 @@ impl Command {
     /// sizes, in general only when we will not actually connect to any
     /// databases
     fn use_configured_pool_size(&self) -> bool {
-        matches!(self, Command::Config(_))
+        matches!(self, Command::Config(_) | Command::IpfsCache(_))
     }
 }
 
@@ -340,6 +401,61 @@ pub enum StatsCommand {
     },
 }
 
+#[derive(Clone, Debug, StructOpt)]
+pub enum IpfsCacheCommand {
+    /// Fetch files from IPFS and add them to the cache
+    ///
+    /// If a file is a subgraph manifest, also add all the files that it
+    /// references, like the schema, ABIs and mappings
+    Populate {
+        #[structopt(
+            long,
+            value_name = "HOST:PORT",
+            env = "IPFS",
+            help = "HTTP addresses of IPFS nodes\n"
+        )]
+        ipfs: Vec<String>,
+        /// Do not pin the files so that they can be evicted from the cache
+        #[structopt(long)]
+        no_pin: bool,
+        /// The IPFS hashes of the files to add
+        hashes: Vec<String>,
+    },
+    /// Check that cached files have not been corrupted
+    Verify {
+        /// Remove corrupted files from the cache
+        #[structopt(long)]
+        remove: bool,
+    },
+    /// List the files in the cache
+    List,
+    /// Allow pinned files to be evicted from the cache
+    Unpin {
+        /// The IPFS hashes of the files to unpin
+        hashes: Vec<String>,
+    },
+}
+
+#[derive(Clone, Debug, StructOpt)]
+pub enum PersistedQueryCommand {
+    /// Register the query in a file and print its hash
+    Add {
+        /// The file that contains the query
+        file: String,
+    },
+    /// List the hashes of all persisted queries
+    List {
+        /// Also print the text of each query
+        #[structopt(short, long)]
+        verbose: bool,
+    },
+    /// Remove a persisted query
+    Remove {
+        /// The hash of the query
+        hash: String,
+    },
+}
+
 impl From<Opt> for config::Opt {
     fn from(opt: Opt) -> Self {
         let mut config_opt = config::Opt::default();
@@ -582,6 +698,21 @@ async fn main() {
                 sleep,
             )
         }
+        Dump {
+            format,
+            deployment,
+            dir,
+            shard,
+        } => commands::dump::run(ctx.subgraph_store(), deployment, shard, dir, format),
+        Restore { dir, shard, node } => {
+            commands::restore::run(ctx.subgraph_store(), dir, shard, node)
+        }
+        History {
+            unlimited,
+            shard,
+            deployment,
+            blocks,
+        } => commands::history::run(ctx.subgraph_store(), deployment, shard, blocks, unlimited),
         Listen(cmd) => {
             use ListenCommand::*;
             match cmd {
@@ -647,6 +778,27 @@ async fn main() {
                 Show { nsp, table } => commands::stats::show(ctx.pools(), nsp, table),
             }
         }
+        IpfsCache(cmd) => {
+            use IpfsCacheCommand::*;
+            match cmd {
+                Populate {
+                    ipfs,
+                    no_pin,
+                    hashes,
+                } => commands::ipfs_cache::populate(logger, ipfs, hashes, !no_pin).await,
+                Verify { remove } => commands::ipfs_cache::verify(logger, remove),
+                List => commands::ipfs_cache::list(logger),
+                Unpin { hashes } => commands::ipfs_cache::unpin(logger, hashes),
+            }
+        }
+        PersistedQuery(cmd) => {
+            use PersistedQueryCommand::*;
+            match cmd {
+                Add { file } => commands::persisted_query::add(ctx.subgraph_store(), file),
+                List { verbose } => commands::persisted_query::list(ctx.subgraph_store(), verbose),
+                Remove { hash } => commands::persisted_query::remove(ctx.subgraph_store(), hash),
+            }
+        }
     };
     if let Err(e) = result {
         die!("error: {}", e)
@@ -0,0 +1,69 @@
+use std::{env, path::PathBuf, sync::Arc};
+
+use git_testament::{git_testament, render_testament};
+use lazy_static::lazy_static;
+use structopt::StructOpt;
+
+use graph::{
+    log::logger,
+    prelude::{anyhow::Error, o, slog, tokio, Logger},
+};
+use graph_node::test_runner::{self, FileLinkResolver, Fixture, TestRunner};
+
+git_testament!(TESTAMENT);
+
+lazy_static! {
+    static ref RENDERED_TESTAMENT: String = render_testament!(TESTAMENT);
+}
+
+#[derive(Clone, Debug, StructOpt)]
+#[structopt(
+    name = "graph-test",
+    about = "Run the mappings of a subgraph against the blocks in a fixture \
+             file and check the resulting entities",
+    author = "Graph Protocol, Inc.",
+    version = RENDERED_TESTAMENT.as_str()
+)]
+pub struct Opt {
+    #[structopt(help = "the build directory of the subgraph, i.e., the output of `graph build`")]
+    pub build_dir: PathBuf,
+    #[structopt(help = "the fixture with blocks and expected entities, in YAML or JSON")]
+    pub fixture: PathBuf,
+}
+
+async fn run(logger: Logger, opt: Opt) -> Result<usize, Error> {
+    let fixture = Fixture::load(&opt.fixture)?;
+    let resolver = Arc::new(FileLinkResolver::new(&opt.build_dir));
+    let manifest = test_runner::load_manifest(&logger, &resolver, &opt.build_dir).await?;
+    let mut runner = TestRunner::new(logger, manifest, resolver).await?;
+
+    // Host exports block on futures, and therefore can not run on one of
+    // the runtime's worker threads
+    let failures = graph::spawn_blocking_allow_panic(move || runner.run(&fixture)).await??;
+    for failure in &failures {
+        println!("block {}: {}", failure.block, failure.message);
+    }
+    Ok(failures.len())
+}
+
+#[tokio::main]
+async fn main() {
+    let opt = Opt::from_args();
+
+    let logger = match env::var_os("GRAPH_LOG") {
+        Some(_) => logger(false),
+        None => Logger::root(slog::Discard, o!()),
+    };
+
+    match run(logger, opt).await {
+        Ok(0) => println!("all checks passed"),
+        Ok(failures) => {
+            println!("{} checks failed", failures);
+            std::process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("error: {:#}", e);
+            std::process::exit(2);
+        }
+    }
+}
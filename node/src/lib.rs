@@ -4,5 +4,6 @@ extern crate diesel;
 pub mod config;
 pub mod opt;
 pub mod store_builder;
+pub mod test_runner;
 
 pub mod manager;
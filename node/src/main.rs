@@ -16,9 +16,10 @@ use tokio::sync::mpsc;
 
 use graph::blockchain::block_ingestor::BlockIngestor;
 use graph::blockchain::{
-    BlockHash, Blockchain as _, BlockchainKind, BlockchainMap, ChainIdentifier,
+    BlockHash, Blockchain as _, BlockchainKind, BlockchainMap, ChainHeadUpdateListener,
+    ChainIdentifier, REORG_THRESHOLD,
 };
-use graph::components::store::BlockStore;
+use graph::components::subgraph::SubgraphProfiles;
 use graph::data::graphql::effort::LoadManager;
 use graph::log::logger;
 use graph::prelude::{IndexNodeServer as _, JsonRpcServer as _, *};
@@ -26,34 +27,27 @@ use graph::util::security::SafeDisplay;
 use graph_chain_ethereum::{self as ethereum, network_indexer, EthereumAdapterTrait, Transport};
 use graph_chain_near::{self as near};
 use graph_core::{
-    LinkResolver, MetricsRegistry, SubgraphAssignmentProvider as IpfsSubgraphAssignmentProvider,
-    SubgraphInstanceManager, SubgraphRegistrar as IpfsSubgraphRegistrar,
+    IpfsDiskCache, LinkResolver, MetricsRegistry,
+    SubgraphAssignmentProvider as IpfsSubgraphAssignmentProvider, SubgraphInstanceManager,
+    SubgraphRegistrar as IpfsSubgraphRegistrar,
 };
 use graph_graphql::prelude::GraphQlRunner;
-use graph_server_http::GraphQLServer as GraphQLQueryServer;
+use graph_server_http::{GraphQLServer as GraphQLQueryServer, PersistedQueries};
 use graph_server_index_node::IndexNodeServer;
 use graph_server_json_rpc::JsonRpcServer;
 use graph_server_metrics::PrometheusMetricsServer;
 use graph_server_websocket::SubscriptionServer as GraphQLSubscriptionServer;
-use graph_store_postgres::{register_jobs as register_store_jobs, ChainHeadUpdateListener, Store};
 
 mod config;
 mod opt;
 mod store_builder;
 
 use config::Config;
-use store_builder::StoreBuilder;
+use store_builder::{MemoryStoreBuilder, NetworkStore, NetworkStoreBuilder, StoreBuilder};
 
 use crate::config::ProviderDetails;
 
 lazy_static! {
-    // Default to an Ethereum reorg threshold to 50 blocks
-    static ref REORG_THRESHOLD: BlockNumber = env::var("ETHEREUM_REORG_THRESHOLD")
-        .ok()
-        .map(|s| BlockNumber::from_str(&s)
-            .unwrap_or_else(|_| panic!("failed to parse env var ETHEREUM_REORG_THRESHOLD")))
-        .unwrap_or(50);
-
     // Default to an ancestor count of 50 blocks
     static ref ANCESTOR_COUNT: BlockNumber = env::var("ETHEREUM_ANCESTOR_COUNT")
         .ok()
@@ -139,6 +133,16 @@ async fn main() {
         std::process::exit(0);
     }
 
+    if config.in_memory() {
+        run::<MemoryStoreBuilder>(opt, logger, config).await
+    } else {
+        run::<StoreBuilder>(opt, logger, config).await
+    }
+}
+
+/// Set up and run all components of `graph-node` on top of the store that
+/// `B` builds
+async fn run<B: NetworkStoreBuilder>(opt: opt::Opt, logger: Logger, config: Config) {
     let node_id =
         NodeId::new(opt.node_id.clone()).expect("Node ID must contain only a-z, A-Z, 0-9, and '_'");
     let query_only = config.query_only(&node_id);
@@ -179,7 +183,13 @@ async fn main() {
 
     // Convert the clients into a link resolver. Since we want to get past
     // possible temporary DNS failures, make the resolver retry
-    let link_resolver = Arc::new(LinkResolver::from(ipfs_clients));
+    let mut link_resolver = LinkResolver::from(ipfs_clients);
+    match IpfsDiskCache::from_env(&logger) {
+        Ok(Some(disk_cache)) => link_resolver = link_resolver.with_disk_cache(Arc::new(disk_cache)),
+        Ok(None) => {}
+        Err(e) => panic!("Failed to set up the IPFS disk cache: {:#}", e),
+    }
+    let link_resolver = Arc::new(link_resolver);
 
     // Set up Prometheus registry
     let prometheus_registry = Arc::new(Registry::new());
@@ -214,13 +224,11 @@ async fn main() {
 
     let expensive_queries = read_expensive_queries().unwrap();
 
-    let store_builder =
-        StoreBuilder::new(&logger, &node_id, &config, metrics_registry.cheap_clone()).await;
+    let store_builder = B::new(&logger, &node_id, &config, metrics_registry.cheap_clone()).await;
 
     let launch_services = |logger: Logger| async move {
         let subscription_manager = store_builder.subscription_manager();
         let chain_head_update_listener = store_builder.chain_head_update_listener();
-        let primary_pool = store_builder.primary_pool();
 
         // To support the ethereum block ingestor, ethereum networks are referenced both by the
         // `blockchain_map` and `ethereum_chains`. Future chains should be referred to only in
@@ -228,6 +236,7 @@ async fn main() {
         let mut blockchain_map = BlockchainMap::new();
 
         let (eth_networks, ethereum_idents) = connect_networks(&logger, eth_networks).await;
+        eth_networks.spawn_health_checks(&logger);
         let near_idents =
             compute_near_network_identifiers(firehose_networks_by_kind.get(&BlockchainKind::Near));
 
@@ -272,21 +281,33 @@ async fn main() {
             load_manager,
             metrics_registry.clone(),
         ));
+        let tenants = Arc::new(config.tenants().expect("invalid tenant configuration"));
+        let persisted_queries = Arc::new(PersistedQueries::from_env(network_store.clone()));
         let mut graphql_server = GraphQLQueryServer::new(
             &logger_factory,
             graphql_metrics_registry,
             graphql_runner.clone(),
             node_id.clone(),
+            tenants.clone(),
+            persisted_queries,
+        );
+        let subscription_server = GraphQLSubscriptionServer::new(
+            &logger,
+            graphql_runner.clone(),
+            network_store.clone(),
+            tenants,
+            graphql_server.metrics(),
         );
-        let subscription_server =
-            GraphQLSubscriptionServer::new(&logger, graphql_runner.clone(), network_store.clone());
 
+        let subgraph_profiles = Arc::new(SubgraphProfiles::new());
         let mut index_node_server = IndexNodeServer::new(
             &logger_factory,
             graphql_runner.clone(),
             network_store.clone(),
             link_resolver.clone(),
             network_store.subgraph_store().clone(),
+            Arc::new(eth_networks.clone()),
+            subgraph_profiles.cheap_clone(),
         );
 
         // Spawn Ethereum network indexers for all networks that are to be indexed
@@ -335,10 +356,9 @@ async fn main() {
 
             // Start a task runner
             let mut job_runner = graph::util::jobs::Runner::new(&logger);
-            register_store_jobs(
+            store_builder.register_jobs(
                 &mut job_runner,
                 network_store.clone(),
-                primary_pool,
                 metrics_registry.clone(),
             );
             graph::spawn_blocking(job_runner.start());
@@ -350,6 +370,7 @@ async fn main() {
             blockchain_map.cheap_clone(),
             metrics_registry.clone(),
             link_resolver.cheap_clone(),
+            subgraph_profiles,
         );
 
         // Create IPFS-based subgraph provider
@@ -498,52 +519,95 @@ async fn create_ethereum_networks(
 ) -> Result<EthereumNetworks, anyhow::Error> {
     let eth_rpc_metrics = Arc::new(ProviderEthRpcMetrics::new(registry));
     let mut parsed_networks = EthereumNetworks::new();
+    let mut verifications = Vec::new();
     for (name, chain) in config.chains.chains {
         if chain.protocol != BlockchainKind::Ethereum {
             continue;
         }
 
+        if let Some(verification) = chain.verification {
+            use crate::config::OnDisagreement::*;
+
+            let on_disagreement = match verification.on_disagreement {
+                Majority => ethereum::OnDisagreement::Majority,
+                Fail => ethereum::OnDisagreement::Fail,
+            };
+            verifications.push((
+                name.clone(),
+                ethereum::Verification {
+                    providers: verification.providers,
+                    on_disagreement,
+                },
+            ));
+        }
+
         for provider in chain.providers {
-            if let ProviderDetails::Web3(web3) = provider.details {
-                let capabilities = web3.node_capabilities();
+            let logger = logger.new(o!("provider" => provider.label.clone()));
+            let (url, transport, capabilities, supports_eip_1898) = match provider.details {
+                ProviderDetails::Web3(web3) => {
+                    let capabilities = web3.node_capabilities();
+                    info!(
+                        logger,
+                        "Creating transport";
+                        "url" => &web3.url,
+                        "capabilities" => capabilities
+                    );
 
-                let logger = logger.new(o!("provider" => provider.label.clone()));
-                info!(
-                    logger,
-                    "Creating transport";
-                    "url" => &web3.url,
-                    "capabilities" => capabilities
-                );
+                    use crate::config::Transport::*;
 
-                use crate::config::Transport::*;
+                    let transport = match web3.transport {
+                        Rpc => Transport::new_rpc(&web3.url, web3.headers),
+                        Ipc => Transport::new_ipc(&web3.url).await,
+                        Ws => Transport::new_ws(&web3.url).await,
+                    };
 
-                let transport = match web3.transport {
-                    Rpc => Transport::new_rpc(&web3.url, web3.headers),
-                    Ipc => Transport::new_ipc(&web3.url).await,
-                    Ws => Transport::new_ws(&web3.url).await,
-                };
+                    let supports_eip_1898 = !web3.features.contains("no_eip1898");
+                    (web3.url, transport, capabilities, supports_eip_1898)
+                }
+                ProviderDetails::Archive(archive) => {
+                    let capabilities = archive.node_capabilities();
+                    info!(
+                        logger,
+                        "Creating transport from block archive";
+                        "path" => &archive.path,
+                        "capabilities" => capabilities
+                    );
 
-                let supports_eip_1898 = !web3.features.contains("no_eip1898");
+                    // Unwrap: `Config` validates that archives for ethereum
+                    // chains have a chain id
+                    let chain_id = archive.chain_id.unwrap();
+                    let transport = Transport::new_archive(&archive.path, chain_id)?;
+                    let url = format!("file://{}", archive.path);
+                    (url, transport, capabilities, true)
+                }
+                ProviderDetails::Firehose(_) => continue,
+            };
 
-                parsed_networks.insert(
-                    name.to_string(),
-                    capabilities,
-                    Arc::new(
-                        graph_chain_ethereum::EthereumAdapter::new(
-                            logger,
-                            provider.label,
-                            &web3.url,
-                            transport,
-                            eth_rpc_metrics.clone(),
-                            supports_eip_1898,
-                        )
-                        .await,
-                    ),
-                );
-            }
+            parsed_networks.insert(
+                name.to_string(),
+                capabilities,
+                Arc::new(
+                    graph_chain_ethereum::EthereumAdapter::new(
+                        logger,
+                        provider.label,
+                        &url,
+                        transport,
+                        eth_rpc_metrics.clone(),
+                        supports_eip_1898,
+                    )
+                    .await,
+                ),
+            );
         }
     }
     parsed_networks.sort();
+    for (name, verification) in verifications {
+        info!(logger, "Verifying responses with other providers";
+              "network" => &name,
+              "providers" => verification.providers,
+              "on_disagreement" => format!("{:?}", verification.on_disagreement));
+        parsed_networks.verify(&name, verification);
+    }
     Ok(parsed_networks)
 }
 
@@ -563,27 +627,44 @@ async fn create_firehose_networks(
 
     for (name, chain) in &config.chains.chains {
         for provider in &chain.providers {
-            if let ProviderDetails::Firehose(ref firehose) = provider.details {
-                let logger = logger.new(o!("provider" => provider.label.clone()));
-                info!(
-                    logger,
-                    "Creating firehose endpoint";
-                    "url" => &firehose.url,
-                );
+            let logger = logger.new(o!("provider" => provider.label.clone()));
+            let endpoint = match provider.details {
+                ProviderDetails::Firehose(ref firehose) => {
+                    info!(
+                        logger,
+                        "Creating firehose endpoint";
+                        "url" => &firehose.url,
+                    );
 
-                let endpoint = FirehoseEndpoint::new(
-                    logger,
-                    &provider.label,
-                    &firehose.url,
-                    firehose.token.clone(),
-                )
-                .await?;
+                    FirehoseEndpoint::new(
+                        logger,
+                        &provider.label,
+                        &firehose.url,
+                        firehose.token.clone(),
+                    )
+                    .await?
+                }
+                // Ethereum chains read archives through their JSON-RPC
+                // adapters
+                ProviderDetails::Archive(_) if chain.protocol == BlockchainKind::Ethereum => {
+                    continue
+                }
+                ProviderDetails::Archive(ref archive) => {
+                    info!(
+                        logger,
+                        "Creating firehose endpoint from block archive";
+                        "path" => &archive.path,
+                    );
 
-                let parsed_networks = networks_by_kind
-                    .entry(chain.protocol)
-                    .or_insert_with(|| FirehoseNetworks::new());
-                parsed_networks.insert(name.to_string(), Arc::new(endpoint));
-            }
+                    FirehoseEndpoint::new_archive(logger, &provider.label, &archive.path)?
+                }
+                ProviderDetails::Web3(_) => continue,
+            };
+
+            let parsed_networks = networks_by_kind
+                .entry(chain.protocol)
+                .or_insert_with(|| FirehoseNetworks::new());
+            parsed_networks.insert(name.to_string(), Arc::new(endpoint));
         }
     }
 
@@ -783,8 +864,8 @@ fn ethereum_networks_as_chains(
     registry: Arc<MetricsRegistry>,
     firehose_networks: Option<&FirehoseNetworks>,
     eth_networks: &EthereumNetworks,
-    store: &Store,
-    chain_head_update_listener: Arc<ChainHeadUpdateListener>,
+    store: &impl NetworkStore,
+    chain_head_update_listener: Arc<dyn ChainHeadUpdateListener>,
     logger_factory: &LoggerFactory,
 ) -> HashMap<String, Arc<ethereum::Chain>> {
     let chains: Vec<_> = eth_networks
@@ -792,11 +873,15 @@ fn ethereum_networks_as_chains(
         .iter()
         .filter_map(|(network_name, eth_adapters)| {
             store
-                .block_store()
                 .chain_store(network_name)
-                .map(|chain_store| {
-                    let is_ingestible = chain_store.is_ingestible();
-                    (network_name, eth_adapters, chain_store, is_ingestible)
+                .map(|(chain_store, call_cache, is_ingestible)| {
+                    (
+                        network_name,
+                        eth_adapters,
+                        chain_store,
+                        call_cache,
+                        is_ingestible,
+                    )
                 })
                 .or_else(|| {
                     error!(
@@ -807,26 +892,29 @@ fn ethereum_networks_as_chains(
                     None
                 })
         })
-        .map(|(network_name, eth_adapters, chain_store, is_ingestible)| {
-            let firehose_endpoints = firehose_networks.and_then(|v| v.networks.get(network_name));
-
-            let chain = ethereum::Chain::new(
-                logger_factory.clone(),
-                network_name.clone(),
-                node_id.clone(),
-                registry.clone(),
-                chain_store.cheap_clone(),
-                chain_store,
-                store.subgraph_store(),
-                firehose_endpoints.map_or_else(|| FirehoseNetworkEndpoints::new(), |v| v.clone()),
-                eth_adapters.clone(),
-                chain_head_update_listener.clone(),
-                *ANCESTOR_COUNT,
-                *REORG_THRESHOLD,
-                is_ingestible,
-            );
-            (network_name.clone(), Arc::new(chain))
-        })
+        .map(
+            |(network_name, eth_adapters, chain_store, call_cache, is_ingestible)| {
+                let firehose_endpoints =
+                    firehose_networks.and_then(|v| v.networks.get(network_name));
+
+                let chain = ethereum::Chain::new(
+                    logger_factory.clone(),
+                    network_name.clone(),
+                    node_id.clone(),
+                    registry.clone(),
+                    chain_store,
+                    call_cache,
+                    firehose_endpoints
+                        .map_or_else(|| FirehoseNetworkEndpoints::new(), |v| v.clone()),
+                    eth_adapters.clone(),
+                    chain_head_update_listener.clone(),
+                    *ANCESTOR_COUNT,
+                    *REORG_THRESHOLD,
+                    is_ingestible,
+                );
+                (network_name.clone(), Arc::new(chain))
+            },
+        )
         .collect();
 
     for (network_name, chain) in chains.iter().cloned() {
@@ -841,7 +929,7 @@ fn near_networks_as_chains(
     blockchain_map: &mut BlockchainMap,
     logger: &Logger,
     firehose_networks: Option<&FirehoseNetworks>,
-    store: &Store,
+    store: &impl NetworkStore,
     logger_factory: &LoggerFactory,
 ) -> HashMap<String, Arc<near::Chain>> {
     match firehose_networks {
@@ -852,9 +940,8 @@ fn near_networks_as_chains(
                 .iter()
                 .filter_map(|(network_name, firehose_endpoints)| {
                     store
-                        .block_store()
                         .chain_store(network_name)
-                        .map(|chain_store| (network_name, chain_store, firehose_endpoints))
+                        .map(|(chain_store, _, _)| (network_name, chain_store, firehose_endpoints))
                         .or_else(|| {
                             error!(
                                 logger,
@@ -871,8 +958,8 @@ fn near_networks_as_chains(
                             logger_factory.clone(),
                             network_name.clone(),
                             chain_store,
-                            store.subgraph_store(),
                             firehose_endpoints.clone(),
+                            *REORG_THRESHOLD,
                         )),
                     )
                 })
@@ -0,0 +1,48 @@
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Instant;
+
+use graph::prelude::anyhow::{bail, Error};
+use graph_store_postgres::command_support::dump::{Format, MANIFEST_FILE};
+use graph_store_postgres::SubgraphStore;
+
+use crate::manager::deployment;
+
+pub fn run(
+    store: Arc<SubgraphStore>,
+    hash: String,
+    shard: Option<String>,
+    dir: String,
+    format: Format,
+) -> Result<(), Error> {
+    let loc = deployment::locate(&store, hash, shard)?;
+
+    let dir = PathBuf::from(dir);
+    if dir.join(MANIFEST_FILE).exists() {
+        bail!(
+            "the directory {} already contains a dump; remove it or use a different directory",
+            dir.display()
+        );
+    }
+    fs::create_dir_all(&dir)?;
+
+    println!("dumping {} into {} as {}", loc, dir.display(), format);
+    let start = Instant::now();
+    let manifest = store.dump(&loc, &dir, format)?;
+
+    for table in &manifest.tables {
+        println!("{:>40}: {:>12} rows", table.entity_type, table.rows);
+    }
+    match &manifest.head {
+        Some(head) => println!("deployment head is block {} ({})", head.number, head.hash),
+        None => println!("deployment has not processed any blocks yet"),
+    }
+    println!(
+        "finished dump of {} tables and {} dynamic data sources in {}s",
+        manifest.tables.len(),
+        manifest.data_sources.len(),
+        start.elapsed().as_secs()
+    );
+    Ok(())
+}
@@ -0,0 +1,36 @@
+use std::sync::Arc;
+
+use graph::prelude::anyhow::{bail, Error};
+use graph::prelude::BlockNumber;
+use graph_store_postgres::SubgraphStore;
+
+use crate::manager::deployment;
+
+pub fn run(
+    store: Arc<SubgraphStore>,
+    hash: String,
+    shard: Option<String>,
+    blocks: Option<BlockNumber>,
+    unlimited: bool,
+) -> Result<(), Error> {
+    let loc = deployment::locate(&store, hash, shard)?;
+
+    if unlimited {
+        store.set_history_blocks(&loc, None)?;
+    } else if let Some(blocks) = blocks {
+        if blocks <= 0 {
+            bail!("the number of blocks of history must be positive");
+        }
+        store.set_history_blocks(&loc, Some(blocks))?;
+    }
+
+    let (history_blocks, earliest_block) = store.history(&loc)?;
+    match history_blocks {
+        Some(blocks) => println!("{} keeps {} blocks of history", loc, blocks),
+        None => println!("{} keeps all of its history", loc),
+    }
+    if earliest_block > 0 {
+        println!("history before block {} has been removed", earliest_block);
+    }
+    Ok(())
+}
@@ -0,0 +1,174 @@
+use std::collections::BTreeSet;
+
+use graph::ipfs_client::IpfsClient;
+use graph::prelude::{
+    anyhow::{bail, Error},
+    serde_yaml, Link, LinkResolver as _, Logger,
+};
+use graph_core::{IpfsDiskCache, LinkResolver};
+
+fn disk_cache(logger: &Logger) -> Result<IpfsDiskCache, Error> {
+    match IpfsDiskCache::from_env(logger)? {
+        Some(disk_cache) => Ok(disk_cache),
+        None => bail!(
+            "the IPFS disk cache is not configured; set GRAPH_IPFS_DISK_CACHE_DIR to its directory"
+        ),
+    }
+}
+
+/// Collect all IPFS links in a subgraph manifest. Return nothing if `data`
+/// is not a manifest
+fn manifest_links(data: &[u8]) -> BTreeSet<String> {
+    fn collect(value: &serde_yaml::Value, links: &mut BTreeSet<String>) {
+        match value {
+            serde_yaml::Value::String(s) if s.starts_with("/ipfs/") => {
+                links.insert(s.trim_start_matches("/ipfs/").to_owned());
+            }
+            serde_yaml::Value::Sequence(values) => {
+                values.iter().for_each(|value| collect(value, links))
+            }
+            serde_yaml::Value::Mapping(map) => {
+                map.iter().for_each(|(_, value)| collect(value, links))
+            }
+            _ => {}
+        }
+    }
+
+    let mut links = BTreeSet::new();
+    if let Ok(manifest) = serde_yaml::from_slice::<serde_yaml::Value>(data) {
+        if manifest.get("specVersion").is_some() {
+            collect(&manifest, &mut links);
+        }
+    }
+    links
+}
+
+async fn fetch(
+    logger: &Logger,
+    resolver: &LinkResolver,
+    disk_cache: &IpfsDiskCache,
+    path: &str,
+    pin: bool,
+) -> Result<Vec<u8>, Error> {
+    let data = resolver
+        .cat(
+            logger,
+            &Link {
+                link: path.to_owned(),
+            },
+        )
+        .await?;
+    disk_cache.insert(path, &data, pin)?;
+    println!("{:>12} {}", data.len(), path);
+    Ok(data)
+}
+
+/// Fetch the files for `hashes` from IPFS and add them to the disk cache.
+/// For subgraph manifests, also add all files that the manifest references
+pub async fn populate(
+    logger: Logger,
+    ipfs: Vec<String>,
+    hashes: Vec<String>,
+    pin: bool,
+) -> Result<(), Error> {
+    if ipfs.is_empty() {
+        bail!("no IPFS nodes were given with --ipfs");
+    }
+    let disk_cache = disk_cache(&logger)?;
+
+    let clients = ipfs
+        .into_iter()
+        .map(|address| {
+            if address.starts_with("http://") || address.starts_with("https://") {
+                IpfsClient::new(&address)
+            } else {
+                IpfsClient::new(&format!("http://{}", address))
+            }
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let resolver = LinkResolver::from(clients);
+
+    let mut count = 0;
+    for hash in hashes {
+        let hash = hash.trim_start_matches("/ipfs/");
+        let data = fetch(&logger, &resolver, &disk_cache, hash, pin).await?;
+        count += 1;
+        for link in manifest_links(&data) {
+            fetch(&logger, &resolver, &disk_cache, &link, pin).await?;
+            count += 1;
+        }
+    }
+    println!(
+        "added {} files to the cache{}",
+        count,
+        if pin { " and pinned them" } else { "" }
+    );
+    Ok(())
+}
+
+/// Check that none of the files in the cache have been corrupted, and
+/// remove corrupted files if `remove` is `true`
+pub fn verify(logger: Logger, remove: bool) -> Result<(), Error> {
+    let disk_cache = disk_cache(&logger)?;
+
+    let entries = disk_cache.entries();
+    let mut corrupted = 0;
+    for entry in &entries {
+        let valid = match disk_cache.verify(&entry.path) {
+            Ok(valid) => valid,
+            Err(e) => {
+                println!("failed to check {}: {}", entry.path, e);
+                false
+            }
+        };
+        if !valid {
+            corrupted += 1;
+            if remove {
+                disk_cache.remove(&entry.path)?;
+                println!("removed corrupted file {}", entry.path);
+            } else {
+                println!("corrupted file {}", entry.path);
+            }
+        }
+    }
+    println!(
+        "checked {} files, {} were corrupted",
+        entries.len(),
+        corrupted
+    );
+    if corrupted > 0 && !remove {
+        bail!("the cache contains corrupted files; rerun with --remove to remove them");
+    }
+    Ok(())
+}
+
+pub fn list(logger: Logger) -> Result<(), Error> {
+    let disk_cache = disk_cache(&logger)?;
+
+    let entries = disk_cache.entries();
+    println!("{:<6} {:>12} {}", "pinned", "size", "path");
+    for entry in &entries {
+        let pinned = if entry.pinned { "yes" } else { "no" };
+        println!("{:<6} {:>12} {}", pinned, entry.size, entry.path);
+    }
+    println!(
+        "{} files with a total size of {} bytes",
+        entries.len(),
+        entries.iter().map(|entry| entry.size).sum::<u64>()
+    );
+    Ok(())
+}
+
+pub fn unpin(logger: Logger, paths: Vec<String>) -> Result<(), Error> {
+    let disk_cache = disk_cache(&logger)?;
+
+    for path in paths {
+        let path = path.trim_start_matches("/ipfs/");
+        if disk_cache.unpin(path)? {
+            println!("unpinned {}", path);
+        } else {
+            println!("{} is not in the cache", path);
+        }
+    }
+    Ok(())
+}
@@ -3,10 +3,15 @@ pub mod chain;
 pub mod config;
 pub mod copy;
 pub mod create;
+pub mod dump;
+pub mod history;
 pub mod info;
+pub mod ipfs_cache;
 pub mod listen;
+pub mod persisted_query;
 pub mod query;
 pub mod remove;
+pub mod restore;
 pub mod rewind;
 pub mod stats;
 pub mod txn_speed;
@@ -0,0 +1,37 @@
+use std::fs;
+use std::sync::Arc;
+
+use graph::prelude::anyhow::{anyhow, bail, Error};
+use graph_server_http::query_hash;
+use graph_store_postgres::SubgraphStore;
+
+/// Register the query in `file` and print its hash
+pub fn add(store: Arc<SubgraphStore>, file: String) -> Result<(), Error> {
+    let query = fs::read_to_string(&file)
+        .map_err(|e| anyhow!("could not read query from `{}`: {}", file, e))?;
+    graphql_parser::parse_query::<&str>(&query)
+        .map_err(|e| anyhow!("`{}` does not contain a valid query: {}", file, e))?;
+
+    let hash = query_hash(&query);
+    store.register_persisted_query(&hash, &query)?;
+    println!("{}", hash);
+    Ok(())
+}
+
+pub fn list(store: Arc<SubgraphStore>, verbose: bool) -> Result<(), Error> {
+    for (hash, query, created_at) in store.persisted_queries()? {
+        println!("{}  {}", hash, created_at);
+        if verbose {
+            println!("{}\n", query.trim_end());
+        }
+    }
+    Ok(())
+}
+
+pub fn remove(store: Arc<SubgraphStore>, hash: String) -> Result<(), Error> {
+    if !store.remove_persisted_query(&hash.to_lowercase())? {
+        bail!("there is no persisted query with hash {}", hash);
+    }
+    println!("removed persisted query {}", hash);
+    Ok(())
+}
@@ -0,0 +1,50 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Instant;
+
+use graph::prelude::{
+    anyhow::{anyhow, bail, Error},
+    NodeId,
+};
+use graph_store_postgres::command_support::dump::{Manifest, MANIFEST_FILE};
+use graph_store_postgres::{Shard, SubgraphStore};
+
+pub fn run(
+    store: Arc<SubgraphStore>,
+    dir: String,
+    shard: String,
+    node: String,
+) -> Result<(), Error> {
+    let dir = PathBuf::from(dir);
+    if !dir.join(MANIFEST_FILE).exists() {
+        bail!("the directory {} does not contain a dump", dir.display());
+    }
+    let shard = Shard::new(shard)?;
+    let node = NodeId::new(node.clone()).map_err(|()| anyhow!("invalid node id `{}`", node))?;
+
+    let manifest = Manifest::read(&dir)?;
+    println!(
+        "restoring {} from {} into shard {}",
+        manifest.deployment,
+        dir.display(),
+        shard
+    );
+    match &manifest.head {
+        Some(head) => println!(
+            "the dump was taken at block {} ({})",
+            head.number, head.hash
+        ),
+        None => println!("the dump was taken before any blocks were processed"),
+    }
+
+    let start = Instant::now();
+    let loc = store.restore(&dir, shard, node.clone())?;
+    println!(
+        "restored {} tables into {} in {}s; assigned it to node {}",
+        manifest.tables.len(),
+        loc,
+        start.elapsed().as_secs(),
+        node
+    );
+    Ok(())
+}
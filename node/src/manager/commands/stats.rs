@@ -3,7 +3,7 @@ use std::collections::HashMap;
 use diesel::r2d2::ConnectionManager;
 use diesel::r2d2::PooledConnection;
 use diesel::sql_query;
-use diesel::sql_types::{Integer, Text};
+use diesel::sql_types::{BigInt, Text};
 use diesel::PgConnection;
 use diesel::RunQueryDsl;
 use graph::prelude::anyhow;
@@ -70,10 +70,10 @@ pub fn show(
 
     #[derive(Queryable, QueryableByName)]
     struct VersionStats {
-        #[sql_type = "Integer"]
-        entities: i32,
-        #[sql_type = "Integer"]
-        versions: i32,
+        #[sql_type = "BigInt"]
+        entities: i64,
+        #[sql_type = "BigInt"]
+        versions: i64,
         #[sql_type = "Text"]
         tablename: String,
     }
@@ -88,13 +88,21 @@ pub fn show(
         }
 
         fn print(&self, account_like: bool) {
+            let ratio = if self.versions > 0 {
+                format!(
+                    "{:>5.1}%",
+                    self.entities as f32 * 100.0 / self.versions as f32
+                )
+            } else {
+                format!("{:>6}", "-")
+            };
             println!(
-                "{:<26} {:3} | {:>10} | {:>10} | {:>5.1}%",
+                "{:<26} {:3} | {:>10} | {:>10} | {}",
                 self.tablename,
                 if account_like { "(a)" } else { "   " },
                 self.entities,
                 self.versions,
-                self.entities as f32 * 100.0 / self.versions as f32
+                ratio
             );
         }
 
@@ -103,50 +111,40 @@ pub fn show(
         }
     }
 
-    let query = format!(
-        "select s.n_distinct::int4 as entities,
-                c.reltuples::int4  as versions,
-                c.relname as tablename
-           from pg_namespace n, pg_class c, pg_stats s
-          where n.nspname = $1
-            and c.relnamespace = n.oid
-            and s.schemaname = n.nspname
-            and s.attname = 'id'
-            and c.relname = s.tablename
-          order by c.relname"
-    );
-    let stats = sql_query(query)
-        .bind::<Text, _>(&site.namespace.as_str())
-        .load::<VersionStats>(&conn)?;
-
-    let account_like = store_catalog::account_like(&conn, &site)?;
+    let stats = store_catalog::table_stats(&conn, &site)?;
 
     VersionStats::header();
     for stat in &stats {
-        stat.print(account_like.contains(&stat.tablename));
+        let version_stats = VersionStats {
+            entities: stat.entities,
+            versions: stat.versions,
+            tablename: stat.table.clone(),
+        };
+        version_stats.print(stat.account_like);
     }
     VersionStats::footer();
 
     if let Some(table) = table {
-        if !stats.iter().any(|stat| stat.tablename == table) {
-            bail!(
+        let account_like = match stats.iter().find(|stat| stat.table == table) {
+            Some(stat) => stat.account_like,
+            None => bail!(
                 "deployment {} does not have a table `{}`",
                 site.namespace,
                 table
-            );
-        }
+            ),
+        };
 
         println!("doing a full count on {}.{} ...", site.namespace, table);
         let query = format!(
-            "select count(distinct id)::int4 as entities,
-                    count(*)::int4 as versions,
+            "select count(distinct id)::int8 as entities,
+                    count(*)::int8 as versions,
                     '{table}' as tablename
                from {nsp}.{table}",
             nsp = &site.namespace,
             table = table
         );
         let stat = sql_query(query).get_result::<VersionStats>(&conn)?;
-        stat.print(account_like.contains(&stat.tablename));
+        stat.print(account_like);
     }
 
     Ok(())
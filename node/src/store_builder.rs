@@ -2,21 +2,125 @@ use std::iter::FromIterator;
 use std::{collections::HashMap, sync::Arc};
 
 use futures::future::join_all;
-use graph::blockchain::ChainIdentifier;
-use graph::prelude::{o, MetricsRegistry, NodeId};
+use graph::blockchain::{ChainHeadUpdateListener, ChainIdentifier};
+use graph::components::store::{
+    BlockStore as _, ChainStore, PersistedQueryStore, QueryStoreManager, StatusStore,
+    SubgraphStore as SubgraphStoreTrait, SubscriptionManager as SubscriptionManagerTrait,
+};
+use graph::prelude::{async_trait, o, EthereumCallCache, MetricsRegistry, NodeId};
+use graph::util::jobs::Runner;
 use graph::{
     prelude::{info, CheapClone, Logger},
     util::security::SafeDisplay,
 };
+use graph_core::MetricsRegistry as CoreMetricsRegistry;
+use graph_store_memory::{
+    BlockStore as MemoryBlockStore, ChainHeadUpdateListener as MemoryChainHeadUpdateListener,
+    Store as MemoryStore, SubgraphStore as MemorySubgraphStore,
+    SubscriptionManager as MemorySubscriptionManager,
+};
 use graph_store_postgres::connection_pool::{ConnectionPool, ForeignServer, PoolName};
 use graph_store_postgres::{
-    BlockStore as DieselBlockStore, ChainHeadUpdateListener as PostgresChainHeadUpdateListener,
-    NotificationSender, Shard as ShardName, Store as DieselStore, SubgraphStore,
-    SubscriptionManager, PRIMARY_SHARD,
+    register_jobs as register_store_jobs, BlockStore as DieselBlockStore,
+    ChainHeadUpdateListener as PostgresChainHeadUpdateListener, NotificationSender,
+    Shard as ShardName, Store as DieselStore, SubgraphStore, SubscriptionManager, PRIMARY_SHARD,
 };
 
 use crate::config::{Config, Shard};
 
+/// The parts of a store that `graph-node` needs to run, independent of
+/// whether the store is backed by Postgres or kept in memory
+pub trait NetworkStore:
+    QueryStoreManager + StatusStore + PersistedQueryStore + Send + Sync + 'static
+{
+    type SubgraphStore: SubgraphStoreTrait;
+
+    fn subgraph_store(&self) -> Arc<Self::SubgraphStore>;
+
+    /// Return the chain store for `network`, the call cache for it, and
+    /// whether we can ingest blocks for it
+    fn chain_store(
+        &self,
+        network: &str,
+    ) -> Option<(Arc<dyn ChainStore>, Arc<dyn EthereumCallCache>, bool)>;
+}
+
+impl NetworkStore for DieselStore {
+    type SubgraphStore = SubgraphStore;
+
+    fn subgraph_store(&self) -> Arc<SubgraphStore> {
+        DieselStore::subgraph_store(self)
+    }
+
+    fn chain_store(
+        &self,
+        network: &str,
+    ) -> Option<(Arc<dyn ChainStore>, Arc<dyn EthereumCallCache>, bool)> {
+        self.block_store().chain_store(network).map(|chain_store| {
+            let is_ingestible = chain_store.is_ingestible();
+            (
+                chain_store.cheap_clone() as Arc<dyn ChainStore>,
+                chain_store as Arc<dyn EthereumCallCache>,
+                is_ingestible,
+            )
+        })
+    }
+}
+
+impl NetworkStore for MemoryStore {
+    type SubgraphStore = MemorySubgraphStore;
+
+    fn subgraph_store(&self) -> Arc<MemorySubgraphStore> {
+        MemoryStore::subgraph_store(self)
+    }
+
+    fn chain_store(
+        &self,
+        network: &str,
+    ) -> Option<(Arc<dyn ChainStore>, Arc<dyn EthereumCallCache>, bool)> {
+        self.block_store().chain_store(network).map(|chain_store| {
+            let is_ingestible = chain_store.is_ingestible();
+            (
+                chain_store.cheap_clone() as Arc<dyn ChainStore>,
+                chain_store as Arc<dyn EthereumCallCache>,
+                is_ingestible,
+            )
+        })
+    }
+}
+
+/// Sets up the store that `graph-node` runs against. `graph-node` is
+/// generic over this so that it can run against Postgres or keep all its
+/// data in memory
+#[async_trait]
+pub trait NetworkStoreBuilder: Sized + Send + 'static {
+    type Store: NetworkStore;
+    type SubscriptionManager: SubscriptionManagerTrait;
+
+    async fn new(
+        logger: &Logger,
+        node: &NodeId,
+        config: &Config,
+        registry: Arc<CoreMetricsRegistry>,
+    ) -> Self;
+
+    fn subscription_manager(&self) -> Arc<Self::SubscriptionManager>;
+
+    fn chain_head_update_listener(&self) -> Arc<dyn ChainHeadUpdateListener>;
+
+    /// Return a store that combines both a `Store` for subgraph data
+    /// and a `BlockStore` for all chain related data
+    fn network_store(&self, networks: Vec<(String, Vec<ChainIdentifier>)>) -> Arc<Self::Store>;
+
+    /// Register the jobs that maintain `store` with `runner`
+    fn register_jobs(
+        &self,
+        runner: &mut Runner,
+        store: Arc<Self::Store>,
+        registry: Arc<CoreMetricsRegistry>,
+    );
+}
+
 pub struct StoreBuilder {
     logger: Logger,
     subgraph_store: Arc<SubgraphStore>,
@@ -256,27 +360,136 @@ impl StoreBuilder {
         )
     }
 
-    /// Return a store that combines both a `Store` for subgraph data
-    /// and a `BlockStore` for all chain related data
-    pub fn network_store(self, networks: Vec<(String, Vec<ChainIdentifier>)>) -> Arc<DieselStore> {
+    pub fn subscription_manager(&self) -> Arc<SubscriptionManager> {
+        self.subscription_manager.cheap_clone()
+    }
+
+    pub fn chain_head_update_listener(&self) -> Arc<PostgresChainHeadUpdateListener> {
+        self.chain_head_update_listener.clone()
+    }
+
+    pub fn primary_pool(&self) -> ConnectionPool {
+        self.pools.get(&*PRIMARY_SHARD).unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl NetworkStoreBuilder for StoreBuilder {
+    type Store = DieselStore;
+    type SubscriptionManager = SubscriptionManager;
+
+    async fn new(
+        logger: &Logger,
+        node: &NodeId,
+        config: &Config,
+        registry: Arc<CoreMetricsRegistry>,
+    ) -> Self {
+        StoreBuilder::new(logger, node, config, registry).await
+    }
+
+    fn subscription_manager(&self) -> Arc<SubscriptionManager> {
+        StoreBuilder::subscription_manager(self)
+    }
+
+    fn chain_head_update_listener(&self) -> Arc<dyn ChainHeadUpdateListener> {
+        StoreBuilder::chain_head_update_listener(self)
+    }
+
+    fn network_store(&self, networks: Vec<(String, Vec<ChainIdentifier>)>) -> Arc<DieselStore> {
         Self::make_store(
             &self.logger,
-            self.pools,
-            self.subgraph_store,
-            self.chains,
+            self.pools.clone(),
+            self.subgraph_store.cheap_clone(),
+            self.chains.clone(),
             networks,
         )
     }
 
-    pub fn subscription_manager(&self) -> Arc<SubscriptionManager> {
+    fn register_jobs(
+        &self,
+        runner: &mut Runner,
+        store: Arc<DieselStore>,
+        registry: Arc<CoreMetricsRegistry>,
+    ) {
+        register_store_jobs(runner, store, self.primary_pool(), registry)
+    }
+}
+
+/// Set up a store that keeps everything in memory. It is selected by
+/// using a connection string that starts with `memory://` for the primary
+/// store, and does not need a database
+pub struct MemoryStoreBuilder {
+    subscription_manager: Arc<MemorySubscriptionManager>,
+    chain_head_update_listener: Arc<MemoryChainHeadUpdateListener>,
+    query_permits: usize,
+}
+
+impl MemoryStoreBuilder {
+    pub fn new(logger: &Logger, node: &NodeId, config: &Config) -> Self {
+        let primary_shard = config.primary_store();
+        // Use the pool size to limit how many queries run at the same
+        // time, just like it does for Postgres
+        let query_permits = primary_shard
+            .pool_size
+            .size_for(node, PRIMARY_SHARD.as_str())
+            .expect("we can determine the pool size for the primary store");
+        info!(
+            logger,
+            "Using the in-memory store; nothing will be persisted";
+            "query_permits" => query_permits
+        );
+
+        Self {
+            subscription_manager: Arc::new(MemorySubscriptionManager::new()),
+            chain_head_update_listener: Arc::new(MemoryChainHeadUpdateListener::new()),
+            query_permits: query_permits as usize,
+        }
+    }
+}
+
+#[async_trait]
+impl NetworkStoreBuilder for MemoryStoreBuilder {
+    type Store = MemoryStore;
+    type SubscriptionManager = MemorySubscriptionManager;
+
+    async fn new(
+        logger: &Logger,
+        node: &NodeId,
+        config: &Config,
+        _registry: Arc<CoreMetricsRegistry>,
+    ) -> Self {
+        MemoryStoreBuilder::new(logger, node, config)
+    }
+
+    fn subscription_manager(&self) -> Arc<MemorySubscriptionManager> {
         self.subscription_manager.cheap_clone()
     }
 
-    pub fn chain_head_update_listener(&self) -> Arc<PostgresChainHeadUpdateListener> {
+    fn chain_head_update_listener(&self) -> Arc<dyn ChainHeadUpdateListener> {
         self.chain_head_update_listener.clone()
     }
 
-    pub fn primary_pool(&self) -> ConnectionPool {
-        self.pools.get(&*PRIMARY_SHARD).unwrap().clone()
+    fn network_store(&self, networks: Vec<(String, Vec<ChainIdentifier>)>) -> Arc<MemoryStore> {
+        let block_store = Arc::new(
+            MemoryBlockStore::new(networks, self.chain_head_update_listener.clone())
+                .expect("Creating the BlockStore works"),
+        );
+        let subgraph_store = Arc::new(MemorySubgraphStore::new(
+            self.subscription_manager.cheap_clone(),
+        ));
+        Arc::new(MemoryStore::new(
+            subgraph_store,
+            block_store,
+            self.query_permits,
+        ))
+    }
+
+    fn register_jobs(
+        &self,
+        _runner: &mut Runner,
+        _store: Arc<MemoryStore>,
+        _registry: Arc<CoreMetricsRegistry>,
+    ) {
+        // The in-memory store does not need any maintenance
     }
 }
@@ -0,0 +1,331 @@
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::path::Path;
+use std::sync::Arc;
+
+use graph::blockchain::BlockWithTriggers;
+use graph::prelude::{
+    anyhow::{anyhow, Context as _, Error},
+    serde_json, serde_yaml, tiny_keccak,
+    web3::types::{Address, Bytes, Log, Transaction, TransactionReceipt, H2048, H256, U256, U64},
+    BlockNumber, BlockPtr, CheapClone, EthereumCall, LightEthereumBlock,
+};
+use graph_chain_ethereum::{
+    chain::BlockFinality, Chain, EthereumBlockTriggerType, EthereumTrigger,
+};
+use serde::Deserialize;
+
+/// A fixture describes the blocks that should be fed to a subgraph's
+/// mappings, and what entities should be in the store after each of them
+/// has been processed
+#[derive(Clone, Debug, Deserialize)]
+pub struct Fixture {
+    pub blocks: Vec<FixtureBlock>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FixtureBlock {
+    pub number: u64,
+    pub hash: Option<H256>,
+    pub parent_hash: Option<H256>,
+    #[serde(default)]
+    pub timestamp: u64,
+    #[serde(default)]
+    pub transactions: Vec<FixtureTransaction>,
+    #[serde(default)]
+    pub logs: Vec<FixtureLog>,
+    #[serde(default)]
+    pub calls: Vec<FixtureCall>,
+    #[serde(default)]
+    pub expect: Vec<Expectation>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FixtureTransaction {
+    pub hash: Option<H256>,
+    pub from: Option<Address>,
+    pub to: Option<Address>,
+    pub value: Option<U256>,
+    pub gas: Option<U256>,
+    pub gas_price: Option<U256>,
+    pub input: Option<Bytes>,
+    /// The status from the receipt of the transaction; defaults to `1`,
+    /// i.e., a successful transaction
+    pub status: Option<U64>,
+    pub gas_used: Option<U256>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FixtureLog {
+    pub address: Address,
+    pub topics: Vec<H256>,
+    #[serde(default)]
+    pub data: Bytes,
+    /// The index of the transaction in the block that emitted this log
+    #[serde(default)]
+    pub transaction_index: u64,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FixtureCall {
+    pub from: Address,
+    pub to: Address,
+    pub value: Option<U256>,
+    pub input: Bytes,
+    #[serde(default)]
+    pub output: Bytes,
+    /// The index of the transaction in the block that made this call
+    #[serde(default)]
+    pub transaction_index: u64,
+}
+
+/// An assertion about the entity `entity` with id `id`. If `data` is
+/// `None`, the entity must not exist. Otherwise, it must exist and have
+/// the values in `data` for the attributes listed there; attributes that
+/// are not mentioned in `data` are not checked
+#[derive(Clone, Debug, Deserialize)]
+pub struct Expectation {
+    pub entity: String,
+    pub id: String,
+    pub data: Option<BTreeMap<String, serde_json::Value>>,
+}
+
+impl Fixture {
+    /// Read a fixture from a YAML or JSON file
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let data = std::fs::read(path)
+            .with_context(|| format!("failed to read fixture {}", path.display()))?;
+        serde_yaml::from_slice(&data)
+            .with_context(|| format!("failed to parse fixture {}", path.display()))
+    }
+}
+
+/// A deterministic, made up hash for the things in a fixture that do not
+/// specify their own hash
+fn synthetic_hash(kind: &str, block: u64, index: u64) -> H256 {
+    H256::from(tiny_keccak::keccak256(
+        format!("{}:{}:{}", kind, block, index).as_bytes(),
+    ))
+}
+
+impl FixtureBlock {
+    pub fn hash(&self) -> H256 {
+        self.hash
+            .unwrap_or_else(|| synthetic_hash("block", self.number, 0))
+    }
+
+    pub fn ptr(&self) -> BlockPtr {
+        BlockPtr::from((self.hash(), self.number))
+    }
+
+    fn block_number(&self) -> Result<BlockNumber, Error> {
+        BlockNumber::try_from(self.number)
+            .map_err(|_| anyhow!("block number {} is too large", self.number))
+    }
+
+    /// Turn this block into an Ethereum block together with the triggers it
+    /// contains. `parent` is the hash of the preceding block in the fixture
+    /// and is used when the block does not set its parent hash explicitly.
+    /// Transactions that logs or calls refer to but that are not listed in
+    /// the fixture are filled in with default values
+    pub fn to_block_with_triggers(
+        &self,
+        parent: Option<H256>,
+    ) -> Result<BlockWithTriggers<Chain>, Error> {
+        let hash = self.hash();
+        let number = U64::from(self.number);
+        let block_number = self.block_number()?;
+
+        let tx_count = self
+            .logs
+            .iter()
+            .map(|log| log.transaction_index + 1)
+            .chain(self.calls.iter().map(|call| call.transaction_index + 1))
+            .max()
+            .unwrap_or(0)
+            .max(self.transactions.len() as u64);
+        let transactions: Vec<Transaction> = (0..tx_count)
+            .map(|index| {
+                let tx = self
+                    .transactions
+                    .get(index as usize)
+                    .cloned()
+                    .unwrap_or_default();
+                Transaction {
+                    hash: tx
+                        .hash
+                        .unwrap_or_else(|| synthetic_hash("tx", self.number, index)),
+                    block_hash: Some(hash),
+                    block_number: Some(number),
+                    transaction_index: Some(U64::from(index)),
+                    from: Some(tx.from.unwrap_or_default()),
+                    to: tx.to,
+                    value: tx.value.unwrap_or_default(),
+                    gas: tx.gas.unwrap_or_default(),
+                    gas_price: tx.gas_price.unwrap_or_default(),
+                    input: tx.input.unwrap_or_default(),
+                    ..Transaction::default()
+                }
+            })
+            .collect();
+
+        let mut triggers = vec![EthereumTrigger::Block(
+            self.ptr(),
+            EthereumBlockTriggerType::Every,
+        )];
+
+        let logs: Vec<Log> = self
+            .logs
+            .iter()
+            .enumerate()
+            .map(|(log_index, log)| {
+                let tx = &transactions[log.transaction_index as usize];
+                Log {
+                    address: log.address,
+                    topics: log.topics.clone(),
+                    data: log.data.clone(),
+                    block_hash: Some(hash),
+                    block_number: Some(number),
+                    transaction_hash: Some(tx.hash),
+                    transaction_index: tx.transaction_index,
+                    log_index: Some(U256::from(log_index)),
+                    ..Log::default()
+                }
+            })
+            .collect();
+
+        let receipts: Vec<Arc<TransactionReceipt>> = transactions
+            .iter()
+            .enumerate()
+            .map(|(index, tx)| {
+                let fixture_tx = self.transactions.get(index).cloned().unwrap_or_default();
+                let gas_used = fixture_tx.gas_used.unwrap_or_default();
+                Arc::new(TransactionReceipt {
+                    transaction_hash: tx.hash,
+                    transaction_index: tx.transaction_index.unwrap_or_default(),
+                    block_hash: Some(hash),
+                    block_number: Some(number),
+                    cumulative_gas_used: gas_used,
+                    gas_used: Some(gas_used),
+                    contract_address: None,
+                    logs: logs
+                        .iter()
+                        .filter(|log| log.transaction_hash == Some(tx.hash))
+                        .cloned()
+                        .collect(),
+                    status: Some(fixture_tx.status.unwrap_or_else(|| U64::from(1))),
+                    root: None,
+                    logs_bloom: H2048::zero(),
+                })
+            })
+            .collect();
+
+        // Logs always carry the receipt of their transaction; it only gets
+        // passed to event handlers that ask for it
+        for log in &logs {
+            let receipt = &receipts[log.transaction_index.unwrap().as_usize()];
+            triggers.push(EthereumTrigger::Log(
+                Arc::new(log.clone()),
+                Some(receipt.cheap_clone()),
+            ));
+        }
+
+        // Only the transactions that are listed in the fixture trigger
+        // transaction handlers
+        for receipt in receipts.iter().take(self.transactions.len()) {
+            triggers.push(EthereumTrigger::Transaction(receipt.cheap_clone()));
+        }
+
+        let mut called = Vec::new();
+        for call in &self.calls {
+            let tx = &transactions[call.transaction_index as usize];
+            triggers.push(EthereumTrigger::Call(Arc::new(EthereumCall {
+                from: call.from,
+                to: call.to,
+                value: call.value.unwrap_or_default(),
+                gas_used: U256::zero(),
+                input: call.input.clone(),
+                output: call.output.clone(),
+                block_number,
+                block_hash: hash,
+                transaction_hash: Some(tx.hash),
+                transaction_index: call.transaction_index,
+            })));
+            if !called.contains(&call.to) {
+                called.push(call.to);
+                triggers.push(EthereumTrigger::Block(
+                    self.ptr(),
+                    EthereumBlockTriggerType::WithCallTo(call.to),
+                ));
+            }
+        }
+
+        let block = LightEthereumBlock {
+            hash: Some(hash),
+            number: Some(number),
+            parent_hash: self.parent_hash.or(parent).unwrap_or_default(),
+            timestamp: U256::from(self.timestamp),
+            transactions,
+            ..LightEthereumBlock::default()
+        };
+
+        Ok(BlockWithTriggers::new(
+            BlockFinality::Final(Arc::new(block)),
+            triggers,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fills_in_transactions_and_triggers() {
+        const FIXTURE: &str = "
+blocks:
+  - number: 3
+    logs:
+      - address: '0x0000000000000000000000000000000000000001'
+        topics: []
+        transactionIndex: 1
+    calls:
+      - from: '0x0000000000000000000000000000000000000002'
+        to: '0x0000000000000000000000000000000000000001'
+        input: '0x12345678'
+    expect:
+      - entity: Thing
+        id: one
+";
+        let fixture: Fixture = serde_yaml::from_str(FIXTURE).unwrap();
+        let block = &fixture.blocks[0];
+        assert!(block.expect[0].data.is_none());
+
+        let parent = H256::from_low_u64_be(2);
+        let block_with_triggers = block.to_block_with_triggers(Some(parent)).unwrap();
+        let light_block = match &block_with_triggers.block {
+            BlockFinality::Final(block) => block.clone(),
+            BlockFinality::NonFinal(_) => unreachable!("fixture blocks are final"),
+        };
+        assert_eq!(parent, light_block.parent_hash);
+        assert_eq!(2, light_block.transactions.len());
+        assert_eq!(block.ptr(), block_with_triggers.ptr());
+
+        // One trigger each for the log and the call, one block trigger for
+        // every block, and one for blocks with calls to the contract
+        assert_eq!(4, block_with_triggers.trigger_count());
+        let log = block_with_triggers
+            .trigger_data
+            .iter()
+            .find_map(|trigger| match trigger {
+                EthereumTrigger::Log(log, _) => Some(log.clone()),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(Some(light_block.transactions[1].hash), log.transaction_hash);
+    }
+}
@@ -0,0 +1,59 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use graph::prelude::*;
+
+/// A link resolver that reads files from a subgraph build directory
+/// instead of IPFS. Links are interpreted relative to that directory; a
+/// leading `/ipfs/` is ignored
+pub struct FileLinkResolver {
+    dir: PathBuf,
+}
+
+impl FileLinkResolver {
+    pub fn new(dir: &Path) -> Self {
+        Self {
+            dir: dir.to_path_buf(),
+        }
+    }
+
+    fn path(&self, link: &Link) -> PathBuf {
+        let link = link.link.trim_start_matches("/ipfs/");
+        self.dir.join(link)
+    }
+
+    fn read(&self, link: &Link) -> Result<Vec<u8>, Error> {
+        let path = self.path(link);
+        std::fs::read(&path).with_context(|| format!("failed to read {}", path.display()))
+    }
+}
+
+#[async_trait]
+impl LinkResolver for FileLinkResolver {
+    fn with_timeout(self, _: Duration) -> Self {
+        self
+    }
+
+    fn with_retries(self) -> Self {
+        self
+    }
+
+    async fn cat(&self, _: &Logger, link: &Link) -> Result<Vec<u8>, Error> {
+        self.read(link)
+    }
+
+    async fn json_stream(&self, _: &Logger, link: &Link) -> Result<JsonValueStream, Error> {
+        let data = String::from_utf8(self.read(link)?)?;
+        let values = data
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(line, text)| {
+                serde_json::from_str(text)
+                    .map(|value| JsonStreamValue { value, line })
+                    .map_err(|e| anyhow!("{}: {}", e, text))
+            })
+            .collect::<Vec<_>>();
+        Ok(Box::pin(futures03::stream::iter(values)))
+    }
+}
@@ -0,0 +1,439 @@
+//! Run the mappings of a subgraph against blocks that are described in a
+//! fixture file, without a connection to a chain, IPFS or Postgres. The
+//! fixture also lists what entities should be in the store after each
+//! block, and the runner reports every place where the mappings did not
+//! produce the expected entities
+mod fixture;
+mod link_resolver;
+
+use std::convert::TryFrom;
+use std::path::Path;
+use std::sync::Arc;
+
+use graph::blockchain::{self, Block as _, BlockWithTriggers, Blockchain, DataSource as _, HostFn};
+use graph::components::store::{DeploymentLocator, EntityType, SubgraphStore as _, WritableStore};
+use graph::components::subgraph::MappingError;
+use graph::data::subgraph::{schema::SubgraphError, MAX_SPEC_VERSION};
+use graph::prelude::{
+    anyhow::{anyhow, bail, Context as _, Error},
+    r, serde_json, serde_yaml, BlockNumber, BlockState, CheapClone, DeploymentHash, EntityKey,
+    HostMetrics, Logger, NodeId, StopwatchMetrics, SubgraphDeploymentEntity, SubgraphManifest,
+    SubgraphName, SubgraphVersionSwitchingMode,
+};
+use graph::runtime::HostExportError;
+use graph_chain_ethereum::{chain::BlockFinality, Chain, DataSource, DataSourceTemplate};
+use graph_core::MetricsRegistry;
+use graph_runtime_wasm::{
+    ExperimentalFeatures, HostExports, MappingContext, ValidModule, WasmInstance,
+};
+use graph_store_memory::{SubgraphStore, SubscriptionManager};
+
+pub use self::fixture::{Expectation, Fixture, FixtureBlock};
+pub use self::link_resolver::FileLinkResolver;
+
+/// The name of the manifest in a subgraph build directory
+const MANIFEST: &str = "subgraph.yaml";
+
+/// The name and node under which the subgraph is deployed in the store
+const NAME: &str = "test";
+
+/// A failed expectation, or an error that a handler raised
+#[derive(Clone, Debug)]
+pub struct Failure {
+    pub block: BlockNumber,
+    pub message: String,
+}
+
+/// Turn `file: <path>` entries, which is how `graph build` refers to the
+/// files that make up a subgraph, into the IPLD links that the manifest
+/// parser expects
+fn link_files(value: &mut serde_yaml::Value) {
+    match value {
+        serde_yaml::Value::Mapping(map) => {
+            for (key, value) in map.iter_mut() {
+                match (key.as_str(), &value) {
+                    (Some("file"), serde_yaml::Value::String(path)) => {
+                        let mut link = serde_yaml::Mapping::new();
+                        link.insert("/".into(), path.as_str().into());
+                        *value = serde_yaml::Value::Mapping(link);
+                    }
+                    _ => link_files(value),
+                }
+            }
+        }
+        serde_yaml::Value::Sequence(values) => values.iter_mut().for_each(link_files),
+        _ => {}
+    }
+}
+
+/// Load and resolve the manifest of the subgraph whose build output is in
+/// `dir`. Files are read from `dir` instead of IPFS
+pub async fn load_manifest(
+    logger: &Logger,
+    resolver: &FileLinkResolver,
+    dir: &Path,
+) -> Result<SubgraphManifest<Chain>, Error> {
+    let path = dir.join(MANIFEST);
+    let data = std::fs::read(&path)
+        .with_context(|| format!("failed to read manifest {}", path.display()))?;
+    let mut raw: serde_yaml::Value = serde_yaml::from_slice(&data)
+        .with_context(|| format!("failed to parse manifest {}", path.display()))?;
+    link_files(&mut raw);
+    let raw = match raw {
+        serde_yaml::Value::Mapping(raw) => raw,
+        _ => bail!("manifest {} is not a YAML mapping", path.display()),
+    };
+
+    let id = DeploymentHash::new("test").unwrap();
+    SubgraphManifest::resolve_from_raw(id, raw, resolver, logger, MAX_SPEC_VERSION.clone())
+        .await
+        .with_context(|| format!("failed to resolve manifest {}", path.display()))
+}
+
+/// A data source together with everything needed to run its mappings
+struct Host {
+    data_source: DataSource,
+    module: Arc<ValidModule>,
+    host_exports: Arc<HostExports<Chain>>,
+}
+
+/// Runs the mappings of a subgraph for one block after the other and
+/// checks the resulting entities
+pub struct TestRunner {
+    logger: Logger,
+    network: String,
+    deployment: DeploymentLocator,
+    subgraph_store: Arc<SubgraphStore>,
+    store: Arc<dyn WritableStore>,
+    resolver: Arc<FileLinkResolver>,
+    templates: Arc<Vec<DataSourceTemplate>>,
+    host_fns: Arc<Vec<HostFn>>,
+    host_metrics: Arc<HostMetrics>,
+    hosts: Vec<Host>,
+}
+
+impl TestRunner {
+    pub async fn new(
+        logger: Logger,
+        manifest: SubgraphManifest<Chain>,
+        resolver: Arc<FileLinkResolver>,
+    ) -> Result<Self, Error> {
+        let network = manifest.network_name();
+
+        // Deploy the subgraph into an in-memory store of its own
+        let subgraph_store = Arc::new(SubgraphStore::new(Arc::new(SubscriptionManager::new())));
+        let deployment = subgraph_store.create_subgraph_deployment(
+            SubgraphName::new(NAME).unwrap(),
+            &manifest.schema,
+            SubgraphDeploymentEntity::new(&manifest, false, None),
+            NodeId::new(NAME).unwrap(),
+            network.clone(),
+            SubgraphVersionSwitchingMode::Instant,
+        )?;
+        let store = subgraph_store
+            .cheap_clone()
+            .writable(logger.clone(), deployment.id)
+            .await?;
+        store.start_subgraph_deployment(&logger)?;
+
+        let registry = Arc::new(MetricsRegistry::new(
+            logger.clone(),
+            Arc::new(graph::prometheus::Registry::new()),
+        ));
+        let stopwatch =
+            StopwatchMetrics::new(logger.clone(), deployment.hash.clone(), registry.clone());
+        let host_metrics = Arc::new(HostMetrics::new(
+            registry,
+            deployment.hash.as_str(),
+            stopwatch,
+        ));
+
+        // Calling contracts needs a connection to an Ethereum node; make
+        // that a deterministic error so that tests fail in a repeatable way
+        let ethereum_call = HostFn {
+            name: "ethereum.call",
+            func: Arc::new(|_, _| {
+                Err(HostExportError::Deterministic(anyhow!(
+                    "ethereum.call is not supported when running against a fixture"
+                )))
+            }),
+        };
+
+        let mut runner = Self {
+            logger,
+            network,
+            deployment,
+            subgraph_store,
+            store,
+            resolver,
+            templates: Arc::new(manifest.templates),
+            host_fns: Arc::new(vec![ethereum_call]),
+            host_metrics,
+            hosts: vec![],
+        };
+        for data_source in manifest.data_sources {
+            let host = runner.new_host(data_source)?;
+            runner.hosts.push(host);
+        }
+        Ok(runner)
+    }
+
+    pub fn store(&self) -> &Arc<dyn WritableStore> {
+        &self.store
+    }
+
+    fn new_host(&self, data_source: DataSource) -> Result<Host, Error> {
+        let module = Arc::new(ValidModule::new(data_source.runtime())?);
+        let host_exports = Arc::new(HostExports::new(
+            self.deployment.hash.clone(),
+            &data_source,
+            self.network.clone(),
+            self.templates.cheap_clone(),
+            self.resolver.cheap_clone(),
+            self.subgraph_store.cheap_clone(),
+        ));
+        Ok(Host {
+            data_source,
+            module,
+            host_exports,
+        })
+    }
+
+    /// Run all blocks in `fixture` and check the expectations for each of
+    /// them. This must be called from a thread that can block on futures
+    /// in the tokio runtime since some host exports rely on that
+    pub fn run(&mut self, fixture: &Fixture) -> Result<Vec<Failure>, Error> {
+        let mut failures = vec![];
+        let mut parent = None;
+        for block in &fixture.blocks {
+            let block_with_triggers = block.to_block_with_triggers(parent)?;
+            let number = block_with_triggers.ptr().number;
+            parent = Some(block.hash());
+
+            for error in self.process_block(block_with_triggers)? {
+                failures.push(Failure {
+                    block: number,
+                    message: format!("handler failed: {}", error),
+                });
+            }
+            for expectation in &block.expect {
+                if let Some(message) = self.check(expectation)? {
+                    failures.push(Failure {
+                        block: number,
+                        message,
+                    });
+                }
+            }
+        }
+        Ok(failures)
+    }
+
+    /// Process the triggers in `block` the same way the subgraph instance
+    /// manager does, including data sources created by the mappings, and
+    /// write the resulting changes to the store. Return the errors that
+    /// handlers raised
+    fn process_block(
+        &mut self,
+        block: BlockWithTriggers<Chain>,
+    ) -> Result<Vec<SubgraphError>, Error> {
+        let block_ptr = block.ptr();
+        let triggers = block.trigger_data;
+        let block = Arc::new(block.block);
+
+        let mut state = BlockState::new(self.store.cheap_clone(), Default::default());
+        for trigger in &triggers {
+            state = self.process_trigger(0, &block, trigger, state)?;
+        }
+
+        while state.has_created_data_sources() {
+            let first_new = self.hosts.len();
+            for info in state.drain_created_data_sources() {
+                let data_source = DataSource::try_from(info)?;
+                if self
+                    .hosts
+                    .iter()
+                    .any(|host| host.data_source.is_duplicate_of(&data_source))
+                {
+                    continue;
+                }
+                state.entity_cache.add_data_source(&data_source)?;
+                let host = self.new_host(data_source)?;
+                self.hosts.push(host);
+            }
+            for trigger in &triggers {
+                state = self.process_trigger(first_new, &block, trigger, state)?;
+            }
+        }
+
+        let modifications = state.entity_cache.as_modifications()?;
+        let errors = state.deterministic_errors;
+        self.store.transact_block_operations(
+            block_ptr,
+            None,
+            modifications.modifications,
+            self.host_metrics.stopwatch.clone(),
+            modifications.data_sources,
+            errors.clone(),
+        )?;
+        Ok(errors)
+    }
+
+    /// Run the handlers of all hosts starting at `first_host` that match
+    /// `trigger`
+    fn process_trigger(
+        &self,
+        first_host: usize,
+        block: &Arc<BlockFinality>,
+        trigger: &<Chain as Blockchain>::TriggerData,
+        mut state: BlockState<Chain>,
+    ) -> Result<BlockState<Chain>, Error> {
+        for host in &self.hosts[first_host..] {
+            // `DataSource` has an inherent method of the same name
+            let trigger = match blockchain::DataSource::match_and_decode(
+                &host.data_source,
+                trigger,
+                block.cheap_clone(),
+                &self.logger,
+            )? {
+                Some(trigger) => trigger,
+                None => continue,
+            };
+
+            let ctx = MappingContext {
+                logger: self.logger.cheap_clone(),
+                host_exports: host.host_exports.cheap_clone(),
+                block_ptr: block.ptr(),
+                state,
+                proof_of_indexing: None,
+                host_fns: self.host_fns.cheap_clone(),
+            };
+            let instance = WasmInstance::from_valid_module_with_ctx(
+                host.module.cheap_clone(),
+                ctx,
+                self.host_metrics.cheap_clone(),
+                None,
+                ExperimentalFeatures {
+                    allow_non_deterministic_ipfs: true,
+                },
+            )?;
+            state = instance.handle_trigger(trigger).map_err(|e| match e {
+                MappingError::PossibleReorg(e) | MappingError::Unknown(e) => e,
+            })?;
+        }
+        Ok(state)
+    }
+
+    /// Check `expectation` against the store and describe how it was not
+    /// met, or return `None` if it was met
+    fn check(&self, expectation: &Expectation) -> Result<Option<String>, Error> {
+        let Expectation { entity, id, data } = expectation;
+        let key = EntityKey {
+            subgraph_id: self.deployment.hash.clone(),
+            entity_type: EntityType::new(entity.to_owned()),
+            entity_id: id.to_owned(),
+        };
+        let actual = self.store.get(&key)?;
+        let message = match (data, actual) {
+            (None, None) => None,
+            (None, Some(_)) => Some(format!("{}[{}] should not exist but does", entity, id)),
+            (Some(_), None) => Some(format!("{}[{}] should exist but does not", entity, id)),
+            (Some(data), Some(actual)) => {
+                let mismatches: Vec<_> = data
+                    .iter()
+                    .filter_map(|(attr, expected)| {
+                        let value = actual
+                            .get(attr)
+                            .cloned()
+                            .map(r::Value::from)
+                            .unwrap_or(r::Value::Null);
+                        let value = serde_json::to_value(&value).unwrap();
+                        if values_match(expected, &value) {
+                            None
+                        } else {
+                            Some(format!("{}: expected {} but got {}", attr, expected, value))
+                        }
+                    })
+                    .collect();
+                if mismatches.is_empty() {
+                    None
+                } else {
+                    Some(format!("{}[{}] {}", entity, id, mismatches.join(", ")))
+                }
+            }
+        };
+        Ok(message)
+    }
+}
+
+/// Compare a value from a fixture with the JSON representation of an
+/// entity attribute. Since `BigInt` and `BigDecimal` are represented as
+/// strings, numbers in the fixture also match strings with the same text,
+/// and hex strings are compared without regard to case so that checksummed
+/// addresses match
+fn values_match(expected: &serde_json::Value, actual: &serde_json::Value) -> bool {
+    use serde_json::Value::*;
+
+    match (expected, actual) {
+        (Array(expected), Array(actual)) => {
+            expected.len() == actual.len()
+                && expected
+                    .iter()
+                    .zip(actual)
+                    .all(|(expected, actual)| values_match(expected, actual))
+        }
+        (Number(number), String(s)) | (String(s), Number(number)) => number.to_string() == *s,
+        (String(expected), String(actual)) if expected.starts_with("0x") => {
+            expected.eq_ignore_ascii_case(actual)
+        }
+        _ => expected == actual,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn graph_build_files_become_links() {
+        let mut raw: serde_yaml::Value = serde_yaml::from_str(
+            "
+schema:
+  file: schema.graphql
+dataSources:
+  - mapping:
+      file: Token/Token.wasm
+      abis:
+        - name: Token
+          file: abis/Token.json
+",
+        )
+        .unwrap();
+        link_files(&mut raw);
+
+        let expected: serde_yaml::Value = serde_yaml::from_str(
+            "
+schema:
+  file: { '/': schema.graphql }
+dataSources:
+  - mapping:
+      file: { '/': Token/Token.wasm }
+      abis:
+        - name: Token
+          file: { '/': abis/Token.json }
+",
+        )
+        .unwrap();
+        assert_eq!(expected, raw);
+    }
+
+    #[test]
+    fn fixture_values_match_attributes() {
+        use graph::prelude::serde_json::json;
+
+        assert!(values_match(&json!(100), &json!("100")));
+        assert!(values_match(&json!("0xABcd"), &json!("0xabcd")));
+        assert!(values_match(&json!([1, "a"]), &json!(["1", "a"])));
+        assert!(!values_match(&json!("ABcd"), &json!("abcd")));
+        assert!(!values_match(&json!(null), &json!("1")));
+    }
+}
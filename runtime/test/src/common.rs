@@ -42,6 +42,7 @@ fn mock_host_exports(
             event_handlers: vec![],
             call_handlers: vec![],
             block_handlers: vec![],
+            transaction_handlers: vec![],
             link: Link {
                 link: "link".to_owned(),
             },
@@ -129,6 +130,7 @@ pub fn mock_data_source(path: &str, api_version: Version) -> DataSource {
             event_handlers: vec![],
             call_handlers: vec![],
             block_handlers: vec![],
+            transaction_handlers: vec![],
             link: Link {
                 link: "link".to_owned(),
             },
@@ -1,11 +1,13 @@
+use graph::blockchain::TriggerWithHandler;
 use graph::data::store::scalar;
 use graph::data::subgraph::*;
-use graph::prelude::web3::types::U256;
+use graph::prelude::web3::types::{H256, U256};
 use graph::prelude::*;
+use graph::runtime::gas::GasCounter;
 use graph::runtime::AscPtr;
 use graph::runtime::{asc_get, asc_new, try_asc_get};
 use graph::{components::store::*, ipfs_client::IpfsClient};
-use graph_chain_ethereum::{Chain, DataSource};
+use graph_chain_ethereum::{Chain, DataSource, MappingTrigger};
 use graph_mock::MockMetricsRegistry;
 use graph_runtime_wasm::asc_abi::class::{Array, AscBigInt, AscEntity, AscString, Uint8Array};
 use graph_runtime_wasm::{ExperimentalFeatures, ValidModule, WasmInstance};
@@ -58,6 +60,26 @@ fn test_valid_module_and_store_with_timeout(
     WasmInstance<Chain>,
     Arc<impl SubgraphStore>,
     DeploymentLocator,
+) {
+    test_valid_module_and_store_with_gas(
+        subgraph_id,
+        data_source,
+        api_version,
+        timeout,
+        GasCounter::default(),
+    )
+}
+
+fn test_valid_module_and_store_with_gas(
+    subgraph_id: &str,
+    data_source: DataSource,
+    api_version: Version,
+    timeout: Option<Duration>,
+    gas: GasCounter,
+) -> (
+    WasmInstance<Chain>,
+    Arc<impl SubgraphStore>,
+    DeploymentLocator,
 ) {
     let subgraph_id_with_api_version =
         subgraph_id_with_api_version(subgraph_id, api_version.clone());
@@ -93,7 +115,7 @@ fn test_valid_module_and_store_with_timeout(
         allow_non_deterministic_ipfs: true,
     };
 
-    let module = WasmInstance::from_valid_module_with_ctx(
+    let module = WasmInstance::from_valid_module_with_gas(
         Arc::new(ValidModule::new(data_source.mapping.runtime.as_ref()).unwrap()),
         mock_context(
             deployment.clone(),
@@ -104,6 +126,7 @@ fn test_valid_module_and_store_with_timeout(
         host_metrics,
         timeout,
         experimental_features,
+        gas,
     )
     .unwrap();
 
@@ -1002,3 +1025,46 @@ fn test_safe_null_ptr_read(api_version: Version) {
 async fn safe_null_ptr_read_0_0_5() {
     test_safe_null_ptr_read(API_VERSION_0_0_5);
 }
+
+fn test_out_of_gas(api_version: Version) {
+    let run = |subgraph_id: &str| {
+        let module = test_valid_module_and_store_with_gas(
+            subgraph_id,
+            mock_data_source(
+                &wasm_file_path("out_of_gas.wasm", api_version.clone()),
+                api_version.clone(),
+            ),
+            api_version.clone(),
+            None,
+            GasCounter::new(1_000_000),
+        )
+        .0;
+        let block = LightEthereumBlock {
+            hash: Some(H256::zero()),
+            number: Some(1.into()),
+            ..Default::default()
+        };
+        let trigger = TriggerWithHandler::new(
+            MappingTrigger::Block {
+                block: Arc::new(block),
+            },
+            "loop".to_string(),
+        );
+        let state = module
+            .handle_trigger(trigger)
+            .expect("running out of gas is a deterministic error");
+        assert_eq!(1, state.deterministic_errors.len());
+        state.deterministic_errors[0].message.clone()
+    };
+
+    // The handler loops forever; it fails when it runs out of gas, and
+    // always in exactly the same way
+    let message = run("outOfGas");
+    assert!(message.starts_with("Gas limit exceeded"), "{}", message);
+    assert_eq!(message, run("outOfGasAgain"));
+}
+
+#[tokio::test]
+async fn out_of_gas_v0_0_4() {
+    test_out_of_gas(API_VERSION_0_0_4);
+}
@@ -0,0 +1,8 @@
+import "allocator/arena";
+export { memory };
+
+// Test that handlers that never finish fail deterministically once they
+// run out of gas
+export function loop(block: i32): void {
+    while (true) {}
+}
@@ -235,6 +235,10 @@ impl<C: Blockchain> RuntimeHostTrait<C> for RuntimeHost<C> {
     fn creation_block_number(&self) -> Option<BlockNumber> {
         self.data_source.creation_block()
     }
+
+    fn data_source(&self) -> &C::DataSource {
+        &self.data_source
+    }
 }
 
 impl<C: Blockchain> PartialEq for RuntimeHost<C> {
@@ -5,10 +5,12 @@ use std::rc::Rc;
 use std::time::Instant;
 
 use graph::blockchain::{Blockchain, HostFnCtx, TriggerWithHandler};
+use graph::components::subgraph::HandlerRun;
+use graph::runtime::gas::{self, GasCounter};
 use graph::runtime::HostExportError;
 use never::Never;
 use semver::Version;
-use wasmtime::{Memory, Trap};
+use wasmtime::{Caller, Memory, Trap};
 
 use crate::error::DeterminismLevel;
 pub use crate::host_exports;
@@ -107,7 +109,7 @@ impl<C: Blockchain> WasmInstance<C> {
         Ok(self.take_ctx().ctx.state)
     }
 
-    pub(crate) fn handle_trigger(
+    pub fn handle_trigger(
         mut self,
         trigger: TriggerWithHandler<C>,
     ) -> Result<BlockState<C>, MappingError> {
@@ -133,6 +135,20 @@ impl<C: Blockchain> WasmInstance<C> {
         self.instance.get_func(func_name).unwrap()
     }
 
+    /// Add what happened while `handler` ran to the profile of the
+    /// deployment. `hits` and `misses` are the entity cache counts from
+    /// before the handler started
+    fn observe_handler_run(&self, handler: &str, time: Duration, hits: u64, misses: u64) {
+        let mut ctx = self.instance_ctx_mut();
+        let (now_hits, now_misses) = ctx.ctx.state.entity_cache.hits_and_misses();
+        let mut run = std::mem::take(&mut ctx.handler_run);
+        run.time = time;
+        run.gas = ctx.gas.used();
+        run.cache_hits = now_hits - hits;
+        run.cache_misses = now_misses - misses;
+        ctx.host_metrics.observe_handler_run(handler, &run);
+    }
+
     fn invoke_handler<T>(
         &mut self,
         handler: &str,
@@ -143,12 +159,27 @@ impl<C: Blockchain> WasmInstance<C> {
             .get_func(handler)
             .with_context(|| format!("function {} not found", handler))?;
 
+        let func = func.typed()?;
+        let (hits, misses) = self.instance_ctx().ctx.state.entity_cache.hits_and_misses();
+
         // Caution: Make sure all exit paths from this function call `exit_handler`.
         self.instance_ctx_mut().ctx.state.enter_handler();
 
+        let start = Instant::now();
+        let result = func.call(arg.wasm_ptr());
+        let gas = self.instance_ctx().gas.clone();
+        gas.set_wasm_gas(self.instance.store().fuel_consumed().unwrap_or(0));
+        self.observe_handler_run(handler, start.elapsed(), hits, misses);
+
         // This `match` will return early if there was a non-deterministic trap.
-        let deterministic_error: Option<Error> = match func.typed()?.call(arg.wasm_ptr()) {
-            Ok(()) => None,
+        let deterministic_error: Option<Error> = match result {
+            // Host exports charge gas after the wasm code has used its share,
+            // so the handler can finish with more than the limit
+            Ok(()) => gas.check().err().map(|e| e.0),
+            // The instance only ever gets `gas.limit()` fuel, and the
+            // runtime traps when all of it is used up. Any trap that leaves
+            // no fuel behind means the handler ran out of gas
+            Err(_) if gas.wasm_exhausted() => Some(gas.limit_exceeded().0),
             Err(trap) if self.instance_ctx().possible_reorg => {
                 self.instance_ctx_mut().ctx.state.exit_handler();
                 return Err(MappingError::PossibleReorg(trap.into()));
@@ -250,6 +281,12 @@ pub struct WasmInstanceContext<C: Blockchain> {
     // A host export trap ocurred for a deterministic reason.
     pub deterministic_host_trap: bool,
 
+    // The host exports that the currently running handler called.
+    pub(crate) handler_run: HandlerRun,
+
+    // The gas the handler has used so far.
+    pub(crate) gas: GasCounter,
+
     pub(crate) experimental_features: ExperimentalFeatures,
 }
 
@@ -261,8 +298,33 @@ impl<C: Blockchain> WasmInstance<C> {
         host_metrics: Arc<HostMetrics>,
         timeout: Option<Duration>,
         experimental_features: ExperimentalFeatures,
+    ) -> Result<WasmInstance<C>, anyhow::Error> {
+        Self::from_valid_module_with_gas(
+            valid_module,
+            ctx,
+            host_metrics,
+            timeout,
+            experimental_features,
+            GasCounter::default(),
+        )
+    }
+
+    /// Like `from_valid_module_with_ctx`, but the handler has to make do
+    /// with the gas in `gas`
+    pub fn from_valid_module_with_gas(
+        valid_module: Arc<ValidModule>,
+        ctx: MappingContext<C>,
+        host_metrics: Arc<HostMetrics>,
+        timeout: Option<Duration>,
+        experimental_features: ExperimentalFeatures,
+        gas: GasCounter,
     ) -> Result<WasmInstance<C>, anyhow::Error> {
         let mut linker = wasmtime::Linker::new(&wasmtime::Store::new(valid_module.module.engine()));
+
+        // Every instance runs exactly one handler, so the fuel of the
+        // instance is the gas limit of the handler. Wasm instructions and
+        // host exports both count against that same limit
+        linker.store().add_fuel(gas.limit())?;
         let host_fns = ctx.host_fns.cheap_clone();
         let api_version = ctx.host_exports.api_version.clone();
 
@@ -316,10 +378,12 @@ impl<C: Blockchain> WasmInstance<C> {
                     let host_metrics = host_metrics.cheap_clone();
                     let timeout_stopwatch = timeout_stopwatch.cheap_clone();
                     let ctx = ctx.cheap_clone();
+                    let gas = gas.clone();
                     linker.func(
                         module,
                         $wasm_name,
                         move |caller: wasmtime::Caller, $($param: u32),*| {
+                            let wasm_gas = caller.store().fuel_consumed().unwrap_or(0);
                             let instance = func_shared_ctx.upgrade().unwrap();
                             let mut instance = instance.borrow_mut();
 
@@ -332,16 +396,25 @@ impl<C: Blockchain> WasmInstance<C> {
                                     host_metrics.cheap_clone(),
                                     timeout,
                                     timeout_stopwatch.cheap_clone(),
-                                    experimental_features.clone()
+                                    experimental_features.clone(),
+                                    gas.clone(),
                                 ).unwrap())
                             }
 
                             let instance = instance.as_mut().unwrap();
                             let _section = instance.host_metrics.stopwatch.start_section($section);
 
+                            instance.gas.set_wasm_gas(wasm_gas);
+                            if let Err(e) = instance.gas.consume_host_fn(gas::HOST_EXPORT_GAS) {
+                                instance.deterministic_host_trap = true;
+                                return Err(IntoTrap::into_trap(HostExportError::from(e)));
+                            }
+
+                            let start = Instant::now();
                             let result = instance.$rust_name(
                                 $($param.into()),*
                             );
+                            instance.handler_run.host_fn($wasm_name, start.elapsed());
                             match result {
                                 Ok(result) => Ok(result.into_wasm_ret()),
                                 Err(e) => {
@@ -375,51 +448,65 @@ impl<C: Blockchain> WasmInstance<C> {
             for module in modules {
                 let func_shared_ctx = Rc::downgrade(&shared_ctx);
                 let host_fn = host_fn.cheap_clone();
-                linker.func(module, host_fn.name, move |call_ptr: u32| {
-                    let start = Instant::now();
-                    let instance = func_shared_ctx.upgrade().unwrap();
-                    let mut instance = instance.borrow_mut();
-
-                    let instance = match &mut *instance {
-                        Some(instance) => instance,
-
-                        // Happens when calling a host fn in Wasm start.
-                        None => {
-                            return Err(anyhow!(
-                                "{} is not allowed in global variables",
-                                host_fn.name
-                            )
-                            .into())
-                        }
-                    };
-
-                    let name_for_metrics = host_fn.name.replace('.', "_");
-                    let stopwatch = &instance.host_metrics.stopwatch;
-                    let _section =
-                        stopwatch.start_section(&format!("host_export_{}", name_for_metrics));
-
-                    let ctx = HostFnCtx {
-                        logger: instance.ctx.logger.cheap_clone(),
-                        block_ptr: instance.ctx.block_ptr.cheap_clone(),
-                        heap: instance,
-                    };
-                    let ret = (host_fn.func)(ctx, call_ptr).map_err(|e| match e {
-                        HostExportError::Deterministic(e) => {
+                linker.func(
+                    module,
+                    host_fn.name,
+                    move |caller: Caller, call_ptr: u32| {
+                        let start = Instant::now();
+                        let wasm_gas = caller.store().fuel_consumed().unwrap_or(0);
+                        let instance = func_shared_ctx.upgrade().unwrap();
+                        let mut instance = instance.borrow_mut();
+
+                        let instance = match &mut *instance {
+                            Some(instance) => instance,
+
+                            // Happens when calling a host fn in Wasm start.
+                            None => {
+                                return Err(anyhow!(
+                                    "{} is not allowed in global variables",
+                                    host_fn.name
+                                )
+                                .into())
+                            }
+                        };
+
+                        let name_for_metrics = host_fn.name.replace('.', "_");
+                        let stopwatch = &instance.host_metrics.stopwatch;
+                        let _section =
+                            stopwatch.start_section(&format!("host_export_{}", name_for_metrics));
+
+                        instance.gas.set_wasm_gas(wasm_gas);
+                        if let Err(e) = instance.gas.consume_host_fn(gas::HOST_EXPORT_GAS) {
                             instance.deterministic_host_trap = true;
-                            e
-                        }
-                        HostExportError::PossibleReorg(e) => {
-                            instance.possible_reorg = true;
-                            e
+                            return Err(e.0.into());
                         }
-                        HostExportError::Unknown(e) => e,
-                    })?;
-                    instance.host_metrics.observe_host_fn_execution_time(
-                        start.elapsed().as_secs_f64(),
-                        &name_for_metrics,
-                    );
-                    Ok(ret)
-                })?;
+
+                        let ctx = HostFnCtx {
+                            logger: instance.ctx.logger.cheap_clone(),
+                            block_ptr: instance.ctx.block_ptr.cheap_clone(),
+                            gas: instance.gas.clone(),
+                            heap: instance,
+                        };
+                        let ret = (host_fn.func)(ctx, call_ptr);
+                        instance.handler_run.host_fn(host_fn.name, start.elapsed());
+                        let ret = ret.map_err(|e| match e {
+                            HostExportError::Deterministic(e) => {
+                                instance.deterministic_host_trap = true;
+                                e
+                            }
+                            HostExportError::PossibleReorg(e) => {
+                                instance.possible_reorg = true;
+                                e
+                            }
+                            HostExportError::Unknown(e) => e,
+                        })?;
+                        instance.host_metrics.observe_host_fn_execution_time(
+                            start.elapsed().as_secs_f64(),
+                            &name_for_metrics,
+                        );
+                        Ok(ret)
+                    },
+                )?;
             }
         }
 
@@ -527,6 +614,7 @@ impl<C: Blockchain> WasmInstance<C> {
                 timeout,
                 timeout_stopwatch,
                 experimental_features,
+                gas,
             )?);
         }
 
@@ -637,6 +725,7 @@ impl<C: Blockchain> WasmInstanceContext<C> {
         timeout: Option<Duration>,
         timeout_stopwatch: Arc<std::sync::Mutex<TimeoutStopwatch>>,
         experimental_features: ExperimentalFeatures,
+        gas: GasCounter,
     ) -> Result<Self, anyhow::Error> {
         // Provide access to the WASM runtime linear memory
         let memory = instance
@@ -678,6 +767,8 @@ impl<C: Blockchain> WasmInstanceContext<C> {
             arena_start_ptr: 0,
             possible_reorg: false,
             deterministic_host_trap: false,
+            handler_run: HandlerRun::default(),
+            gas,
             experimental_features,
         })
     }
@@ -690,6 +781,7 @@ impl<C: Blockchain> WasmInstanceContext<C> {
         timeout: Option<Duration>,
         timeout_stopwatch: Arc<std::sync::Mutex<TimeoutStopwatch>>,
         experimental_features: ExperimentalFeatures,
+        gas: GasCounter,
     ) -> Result<Self, anyhow::Error> {
         let memory = caller
             .get_export("memory")
@@ -734,6 +826,8 @@ impl<C: Blockchain> WasmInstanceContext<C> {
             arena_start_ptr: 0,
             possible_reorg: false,
             deterministic_host_trap: false,
+            handler_run: HandlerRun::default(),
+            gas,
             experimental_features,
         })
     }
@@ -784,7 +878,9 @@ impl<C: Blockchain> WasmInstanceContext<C> {
 
         let entity = asc_get(self, entity_ptr)?;
         let id = asc_get(self, id_ptr)?;
-        let data = try_asc_get(self, data_ptr)?;
+        let data: HashMap<String, store::Value> = try_asc_get(self, data_ptr)?;
+        self.gas
+            .consume_host_fn(gas::STORE_SET_GAS + gas::GAS_PER_BYTE * gas::entity_size(&data))?;
 
         self.ctx.host_exports.store_set(
             &self.ctx.logger,
@@ -804,6 +900,7 @@ impl<C: Blockchain> WasmInstanceContext<C> {
         entity_ptr: AscPtr<AscString>,
         id_ptr: AscPtr<AscString>,
     ) -> Result<(), HostExportError> {
+        self.gas.consume_host_fn(gas::STORE_SET_GAS)?;
         let entity = asc_get(self, entity_ptr)?;
         let id = asc_get(self, id_ptr)?;
         self.ctx.host_exports.store_remove(
@@ -825,6 +922,7 @@ impl<C: Blockchain> WasmInstanceContext<C> {
             .host_metrics
             .cheap_clone()
             .time_host_fn_execution_region("store_get");
+        self.gas.consume_host_fn(gas::STORE_GET_GAS)?;
         let entity_ptr = asc_get(self, entity_ptr)?;
         let id_ptr = asc_get(self, id_ptr)?;
         let entity_option =
@@ -834,11 +932,16 @@ impl<C: Blockchain> WasmInstanceContext<C> {
 
         let ret = match entity_option {
             Some(entity) => {
+                let entity = entity.sorted();
+                self.gas.consume_host_fn(
+                    gas::GAS_PER_BYTE
+                        * gas::entity_size(entity.iter().map(|(name, value)| (name, value))),
+                )?;
                 let _section = self
                     .host_metrics
                     .stopwatch
                     .start_section("store_get_asc_new");
-                asc_new(self, &entity.sorted())?
+                asc_new(self, &entity)?
             }
             None => AscPtr::null(),
         };
@@ -918,6 +1021,8 @@ impl<C: Blockchain> WasmInstanceContext<C> {
         bytes_ptr: AscPtr<Uint8Array>,
     ) -> Result<AscPtr<AscEnum<JsonValueKind>>, DeterministicHostError> {
         let bytes: Vec<u8> = asc_get(self, bytes_ptr)?;
+        self.gas
+            .consume_host_fn(gas::GAS_PER_BYTE * bytes.len() as u64)?;
 
         let result = host_exports::json_from_bytes(&bytes)
             .with_context(|| {
@@ -937,6 +1042,8 @@ impl<C: Blockchain> WasmInstanceContext<C> {
     ) -> Result<AscPtr<AscResult<AscPtr<AscEnum<JsonValueKind>>, bool>>, DeterministicHostError>
     {
         let bytes: Vec<u8> = asc_get(self, bytes_ptr)?;
+        self.gas
+            .consume_host_fn(gas::GAS_PER_BYTE * bytes.len() as u64)?;
         let result = host_exports::json_from_bytes(&bytes).map_err(|e| {
             warn!(
                 &self.ctx.logger,
@@ -963,10 +1070,15 @@ impl<C: Blockchain> WasmInstanceContext<C> {
             )));
         }
 
+        self.gas.consume_host_fn(gas::IPFS_GAS)?;
         let link = asc_get(self, link_ptr)?;
         let ipfs_res = self.ctx.host_exports.ipfs_cat(&self.ctx.logger, link);
         match ipfs_res {
-            Ok(bytes) => asc_new(self, &*bytes).map_err(Into::into),
+            Ok(bytes) => {
+                self.gas
+                    .consume_host_fn(gas::GAS_PER_BYTE * bytes.len() as u64)?;
+                asc_new(self, &*bytes).map_err(Into::into)
+            }
 
             // Return null in case of error.
             Err(e) => {
@@ -992,6 +1104,7 @@ impl<C: Blockchain> WasmInstanceContext<C> {
             )));
         }
 
+        self.gas.consume_host_fn(gas::IPFS_GAS)?;
         let link: String = asc_get(self, link_ptr)?;
         let callback: String = asc_get(self, callback)?;
         let user_data: store::Value = try_asc_get(self, user_data)?;
@@ -1073,10 +1186,10 @@ impl<C: Blockchain> WasmInstanceContext<C> {
         &mut self,
         input_ptr: AscPtr<Uint8Array>,
     ) -> Result<AscPtr<Uint8Array>, DeterministicHostError> {
-        let input = self
-            .ctx
-            .host_exports
-            .crypto_keccak_256(asc_get(self, input_ptr)?)?;
+        let input: Vec<u8> = asc_get(self, input_ptr)?;
+        self.gas
+            .consume_host_fn(gas::GAS_PER_BYTE * input.len() as u64)?;
+        let input = self.ctx.host_exports.crypto_keccak_256(input)?;
         asc_new(self, input.as_ref())
     }
 
@@ -8,12 +8,14 @@ extern crate http;
 extern crate hyper;
 extern crate serde;
 
+mod persisted;
 mod request;
 mod server;
 mod service;
 
+pub use self::persisted::{query_hash, PersistedQueries, PersistedQueryMode};
 pub use self::request::GraphQLRequest;
 pub use self::server::GraphQLServer;
-pub use self::service::{GraphQLService, GraphQLServiceResponse};
+pub use self::service::{GraphQLService, GraphQLServiceMetrics, GraphQLServiceResponse};
 
 pub mod test_utils;
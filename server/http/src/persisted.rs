@@ -0,0 +1,260 @@
+//! Support for persisted queries: clients register the text of a query
+//! once and afterwards only send its SHA-256 hash. In strict mode, the
+//! server only runs queries whose shape matches a registered query
+
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+
+use graph::components::server::query::GraphQLServerError;
+use graph::components::store::PersistedQueryStore;
+use graph::env::env_var;
+use graph::prelude::*;
+use sha2::{Digest, Sha256};
+
+use crate::request::{parse_document, RequestParts};
+
+/// How often we reload the complete registry in strict mode so that
+/// queries that were registered or removed elsewhere are picked up
+const RELOAD_INTERVAL: Duration = Duration::from_secs(60);
+
+lazy_static! {
+    static ref MODE: PersistedQueryMode = env::var("GRAPH_GRAPHQL_PERSISTED_QUERIES")
+        .ok()
+        .map(|s| match s.as_str() {
+            "off" => PersistedQueryMode::Off,
+            "on" => PersistedQueryMode::On,
+            "strict" => PersistedQueryMode::Strict,
+            _ => panic!(
+                "GRAPH_GRAPHQL_PERSISTED_QUERIES must be one of `off`, `on`, or `strict` but is `{}`",
+                s
+            ),
+        })
+        .unwrap_or(PersistedQueryMode::Off);
+
+    /// The longest query text, in bytes, that clients can register
+    static ref MAX_QUERY_SIZE: usize =
+        env_var("GRAPH_GRAPHQL_PERSISTED_QUERIES_MAX_SIZE", 20_000);
+
+    /// Once the registry has this many queries, clients can not register
+    /// more of them
+    static ref MAX_QUERY_COUNT: usize =
+        env_var("GRAPH_GRAPHQL_PERSISTED_QUERIES_MAX_COUNT", 10_000);
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PersistedQueryMode {
+    /// Ignore the persisted query extension
+    Off,
+    /// Run queries by their hash, and let clients register new queries
+    /// as long as they and the registry are not too big
+    On,
+    /// Only run queries whose shape matches that of a registered query.
+    /// Clients can not register queries; that must be done with `graphman`
+    Strict,
+}
+
+/// The hex-encoded SHA-256 hash of the text of a query
+pub fn query_hash(query: &str) -> String {
+    hex::encode(Sha256::digest(query.as_bytes()))
+}
+
+#[derive(Default)]
+struct Cache {
+    /// Parsed documents by their hash
+    documents: HashMap<String, Arc<q::Document>>,
+    /// The shape hashes of all registered documents; only maintained in
+    /// strict mode
+    shapes: HashSet<u64>,
+    /// When we last loaded the entire registry
+    loaded_at: Option<Instant>,
+}
+
+/// The persisted queries known to this node, backed by the registry in
+/// the store. Documents are parsed once and then kept in memory
+pub struct PersistedQueries {
+    mode: PersistedQueryMode,
+    store: Arc<dyn PersistedQueryStore>,
+    cache: RwLock<Cache>,
+    /// Held while the registry is reloaded so that only one request does
+    /// that when the cache becomes stale
+    reload: tokio::sync::Mutex<()>,
+    max_query_size: usize,
+    max_query_count: usize,
+}
+
+impl PersistedQueries {
+    pub fn new(mode: PersistedQueryMode, store: Arc<dyn PersistedQueryStore>) -> Self {
+        PersistedQueries {
+            mode,
+            store,
+            cache: RwLock::new(Cache::default()),
+            reload: tokio::sync::Mutex::new(()),
+            max_query_size: *MAX_QUERY_SIZE,
+            max_query_count: *MAX_QUERY_COUNT,
+        }
+    }
+
+    /// Change how big queries that clients register can be, and how many
+    /// queries the registry can hold before clients can not register more
+    pub fn with_limits(self, max_query_size: usize, max_query_count: usize) -> Self {
+        PersistedQueries {
+            max_query_size,
+            max_query_count,
+            ..self
+        }
+    }
+
+    /// Use the mode set with `GRAPH_GRAPHQL_PERSISTED_QUERIES`
+    pub fn from_env(store: Arc<dyn PersistedQueryStore>) -> Self {
+        Self::new(*MODE, store)
+    }
+
+    /// Turn the parts of a request into a query, looking up or registering
+    /// persisted queries as needed, and make sure that the query is
+    /// allowed to run
+    pub async fn query(&self, parts: RequestParts) -> Result<Query, GraphQLServerError> {
+        if self.mode == PersistedQueryMode::Off {
+            return parts.into_query();
+        }
+        if self.mode == PersistedQueryMode::Strict {
+            self.reload_if_stale().await?;
+        }
+
+        let RequestParts {
+            query,
+            variables,
+            persisted_hash,
+        } = parts;
+
+        let document = match (persisted_hash, query) {
+            (Some(hash), None) => self
+                .find(&hash)
+                .await?
+                .ok_or(QueryExecutionError::PersistedQueryNotFound)
+                .map_err(QueryError::from)?,
+            (Some(hash), Some(text)) => {
+                if query_hash(&text) != hash {
+                    return Err(
+                        QueryError::from(QueryExecutionError::PersistedQueryHashMismatch(hash))
+                            .into(),
+                    );
+                }
+                match self.find(&hash).await? {
+                    Some(document) => document,
+                    None => {
+                        let document = Arc::new(parse_document(&text)?);
+                        if self.mode == PersistedQueryMode::On && self.can_register(&text).await? {
+                            self.store.register_persisted_query(&hash, &text).await?;
+                            self.cache
+                                .write()
+                                .unwrap()
+                                .documents
+                                .insert(hash, document.cheap_clone());
+                        }
+                        document
+                    }
+                }
+            }
+            (None, text) => {
+                return RequestParts {
+                    query: text,
+                    variables,
+                    persisted_hash: None,
+                }
+                .into_query()
+                .and_then(|query| self.check_allowed(query));
+            }
+        };
+
+        self.check_allowed(Query::new(document.as_ref().clone(), variables))
+    }
+
+    /// Whether a client may register `text`. Queries that are too big, or
+    /// that arrive once the registry is full, are still run, but not
+    /// registered
+    async fn can_register(&self, text: &str) -> Result<bool, GraphQLServerError> {
+        if text.len() > self.max_query_size {
+            return Ok(false);
+        }
+        Ok(self.store.persisted_query_count().await? < self.max_query_count)
+    }
+
+    /// In strict mode, only allow queries that have the same shape as a
+    /// registered query
+    fn check_allowed(&self, query: Query) -> Result<Query, GraphQLServerError> {
+        if self.mode == PersistedQueryMode::Strict
+            && !self
+                .cache
+                .read()
+                .unwrap()
+                .shapes
+                .contains(&query.shape_hash)
+        {
+            return Err(QueryError::from(QueryExecutionError::QueryNotAllowed).into());
+        }
+        Ok(query)
+    }
+
+    /// Find the document for `hash`, first in memory, then in the store
+    async fn find(&self, hash: &str) -> Result<Option<Arc<q::Document>>, GraphQLServerError> {
+        if let Some(document) = self.cache.read().unwrap().documents.get(hash) {
+            return Ok(Some(document.cheap_clone()));
+        }
+
+        // In strict mode, the cache has everything from the last reload
+        if self.mode == PersistedQueryMode::Strict {
+            return Ok(None);
+        }
+
+        match self.store.persisted_query(hash).await? {
+            None => Ok(None),
+            Some(text) => {
+                let document = Arc::new(parse_document(&text)?);
+                self.cache
+                    .write()
+                    .unwrap()
+                    .documents
+                    .insert(hash.to_string(), document.cheap_clone());
+                Ok(Some(document))
+            }
+        }
+    }
+
+    fn is_stale(&self) -> bool {
+        self.cache
+            .read()
+            .unwrap()
+            .loaded_at
+            .map_or(true, |loaded_at| loaded_at.elapsed() >= RELOAD_INTERVAL)
+    }
+
+    /// Load the entire registry if we haven't done that in a while. Only
+    /// one request reloads; others that find the cache stale at the same
+    /// time wait for it and then use what it loaded
+    async fn reload_if_stale(&self) -> Result<(), GraphQLServerError> {
+        if !self.is_stale() {
+            return Ok(());
+        }
+
+        let _reload = self.reload.lock().await;
+        if !self.is_stale() {
+            return Ok(());
+        }
+
+        let mut cache = Cache {
+            loaded_at: Some(Instant::now()),
+            ..Default::default()
+        };
+        for (hash, text) in self.store.persisted_queries().await? {
+            // Only queries that parse are ever registered
+            if let Ok(document) = parse_document(&text) {
+                cache.shapes.insert(shape_hash(&document));
+                cache.documents.insert(hash, Arc::new(document));
+            }
+        }
+        *self.cache.write().unwrap() = cache;
+        Ok(())
+    }
+}
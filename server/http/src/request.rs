@@ -10,18 +10,46 @@ pub struct GraphQLRequest {
     body: Bytes,
 }
 
+/// The parts of a GraphQL request body before the query text is parsed.
+/// Clients that use persisted queries send the hash of the query in the
+/// `persistedQuery` extension, and can leave out the query text
+#[derive(Debug)]
+pub struct RequestParts {
+    pub query: Option<String>,
+    pub variables: Option<QueryVariables>,
+    pub persisted_hash: Option<String>,
+}
+
+impl RequestParts {
+    /// Parse the query text into a `Query`
+    pub fn into_query(self) -> Result<Query, GraphQLServerError> {
+        // Ensure the JSON data has a "query" field
+        let query_string = self.query.ok_or_else(|| {
+            GraphQLServerError::ClientError(String::from(
+                "The \"query\" field is missing in request data",
+            ))
+        })?;
+
+        let document = parse_document(&query_string)?;
+        Ok(Query::new(document, self.variables))
+    }
+}
+
+/// Parse the text of a query into a document
+pub fn parse_document(query: &str) -> Result<q::Document, GraphQLServerError> {
+    Ok(graphql_parser::parse_query(query)
+        .map_err(|e| GraphQLServerError::from(QueryError::ParseError(Arc::new(e.into()))))?
+        .into_static())
+}
+
 impl GraphQLRequest {
     /// Creates a new GraphQLRequest future based on an HTTP request and a result sender.
     pub fn new(body: Bytes) -> Self {
         GraphQLRequest { body }
     }
-}
 
-impl Future for GraphQLRequest {
-    type Item = Query;
-    type Error = GraphQLServerError;
-
-    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+    /// Split the request body into its parts without parsing the query
+    pub fn parts(&self) -> Result<RequestParts, GraphQLServerError> {
         // Parse request body as JSON
         let json: serde_json::Value = serde_json::from_slice(&self.body)
             .map_err(|e| GraphQLServerError::ClientError(format!("{}", e)))?;
@@ -31,22 +59,16 @@ impl Future for GraphQLRequest {
             GraphQLServerError::ClientError(String::from("Request data is not an object"))
         })?;
 
-        // Ensure the JSON data has a "query" field
-        let query_value = obj.get("query").ok_or_else(|| {
-            GraphQLServerError::ClientError(String::from(
-                "The \"query\" field is missing in request data",
-            ))
-        })?;
-
-        // Ensure the "query" field is a string
-        let query_string = query_value.as_str().ok_or_else(|| {
-            GraphQLServerError::ClientError(String::from("The \"query\" field is not a string"))
-        })?;
-
-        // Parse the "query" field of the JSON body
-        let document = graphql_parser::parse_query(query_string)
-            .map_err(|e| GraphQLServerError::from(QueryError::ParseError(Arc::new(e.into()))))?
-            .into_static();
+        // Ensure the "query" field is a string if it is present
+        let query = match obj.get("query") {
+            None | Some(serde_json::Value::Null) => None,
+            Some(serde_json::Value::String(query)) => Some(query.clone()),
+            Some(_) => {
+                return Err(GraphQLServerError::ClientError(String::from(
+                    "The \"query\" field is not a string",
+                )))
+            }
+        };
 
         // Parse the "variables" field of the JSON body, if present
         let variables = match obj.get("variables") {
@@ -61,7 +83,37 @@ impl Future for GraphQLRequest {
             )),
         }?;
 
-        Ok(Async::Ready(Query::new(document, variables)))
+        // Look for `extensions.persistedQuery.sha256Hash`
+        let persisted_hash = match obj
+            .get("extensions")
+            .and_then(|extensions| extensions.get("persistedQuery"))
+        {
+            None | Some(serde_json::Value::Null) => None,
+            Some(persisted) => match persisted.get("sha256Hash").and_then(|hash| hash.as_str()) {
+                Some(hash) => Some(hash.to_lowercase()),
+                None => {
+                    return Err(GraphQLServerError::ClientError(String::from(
+                        "The \"persistedQuery\" extension must have a \"sha256Hash\"",
+                    )))
+                }
+            },
+        };
+
+        Ok(RequestParts {
+            query,
+            variables,
+            persisted_hash,
+        })
+    }
+}
+
+impl Future for GraphQLRequest {
+    type Item = Query;
+    type Error = GraphQLServerError;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let query = self.parts()?.into_query()?;
+        Ok(Async::Ready(query))
     }
 }
 
@@ -185,4 +237,29 @@ mod tests {
         assert_eq!(query.document, expected_query);
         assert_eq!(query.variables, Some(expected_variables));
     }
+
+    #[test]
+    fn parses_persisted_query_extension() {
+        let request = GraphQLRequest::new(hyper::body::Bytes::from(
+            "\
+                 {\
+                 \"extensions\": { \
+                 \"persistedQuery\": { \"version\": 1, \"sha256Hash\": \"ABC123\" } \
+                 } \
+                 }",
+        ));
+        let parts = request.parts().expect("Should accept persisted queries");
+        assert_eq!(parts.query, None);
+        assert_eq!(parts.persisted_hash, Some("abc123".to_string()));
+        parts
+            .into_query()
+            .expect_err("Should reject turning a request without query text into a query");
+
+        let request = GraphQLRequest::new(hyper::body::Bytes::from(
+            "{\"extensions\": { \"persistedQuery\": { \"version\": 1 } } }",
+        ));
+        request
+            .parts()
+            .expect_err("Should reject persisted queries without a hash");
+    }
 }
@@ -4,7 +4,9 @@ use hyper;
 use hyper::service::make_service_fn;
 use hyper::Server;
 
+use crate::persisted::PersistedQueries;
 use crate::service::{GraphQLService, GraphQLServiceMetrics};
+use graph::components::server::tenant::Tenants;
 use graph::prelude::{GraphQLServer as GraphQLServerTrait, *};
 use thiserror::Error;
 
@@ -27,6 +29,8 @@ pub struct GraphQLServer<Q> {
     metrics: Arc<GraphQLServiceMetrics>,
     graphql_runner: Arc<Q>,
     node_id: NodeId,
+    tenants: Arc<Tenants>,
+    persisted_queries: Arc<PersistedQueries>,
 }
 
 impl<Q> GraphQLServer<Q> {
@@ -36,6 +40,8 @@ impl<Q> GraphQLServer<Q> {
         metrics_registry: Arc<impl MetricsRegistry>,
         graphql_runner: Arc<Q>,
         node_id: NodeId,
+        tenants: Arc<Tenants>,
+        persisted_queries: Arc<PersistedQueries>,
     ) -> Self {
         let logger = logger_factory.component_logger(
             "GraphQLServer",
@@ -51,8 +57,16 @@ impl<Q> GraphQLServer<Q> {
             metrics,
             graphql_runner,
             node_id,
+            tenants,
+            persisted_queries,
         }
     }
+
+    /// The metrics of this server; other servers that enforce tenant
+    /// limits report rejections through them
+    pub fn metrics(&self) -> Arc<GraphQLServiceMetrics> {
+        self.metrics.clone()
+    }
 }
 
 impl<Q> GraphQLServerTrait for GraphQLServer<Q>
@@ -81,6 +95,8 @@ where
         let graphql_runner = self.graphql_runner.clone();
         let metrics = self.metrics.clone();
         let node_id = self.node_id.clone();
+        let tenants = self.tenants.clone();
+        let persisted_queries = self.persisted_queries.clone();
         let new_service = make_service_fn(move |_| {
             futures03::future::ok::<_, Error>(GraphQLService::new(
                 logger_for_service.clone(),
@@ -88,6 +104,8 @@ where
                 graphql_runner.clone(),
                 ws_port,
                 node_id.clone(),
+                tenants.clone(),
+                persisted_queries.clone(),
             ))
         });
 
@@ -5,21 +5,24 @@ use std::task::Context;
 use std::task::Poll;
 use std::time::Instant;
 
+use graph::components::server::tenant::{TenantRejection, Tenants};
 use graph::prelude::*;
 use graph::{components::server::query::GraphQLServerError, data::query::QueryTarget};
 use http::header;
 use http::header::{
     ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN,
-    CONTENT_TYPE, LOCATION,
+    AUTHORIZATION, CONTENT_TYPE, LOCATION,
 };
 use hyper::service::Service;
 use hyper::{Body, Method, Request, Response, StatusCode};
 
+use crate::persisted::PersistedQueries;
 use crate::request::GraphQLRequest;
 
 pub struct GraphQLServiceMetrics {
     query_execution_time: Box<HistogramVec>,
     failed_query_execution_time: Box<HistogramVec>,
+    rejected_queries: Box<CounterVec>,
 }
 
 impl fmt::Debug for GraphQLServiceMetrics {
@@ -48,9 +51,18 @@ impl GraphQLServiceMetrics {
             )
             .expect("failed to create `query_failed_execution_time` histogram");
 
+        let rejected_queries = registry
+            .new_counter_vec(
+                "query_rejected_count",
+                "Number of GraphQL requests rejected because of tenant limits",
+                vec![String::from("tenant"), String::from("reason")],
+            )
+            .expect("failed to create `query_rejected_count` counter");
+
         Self {
             query_execution_time,
             failed_query_execution_time,
+            rejected_queries,
         }
     }
 
@@ -65,6 +77,12 @@ impl GraphQLServiceMetrics {
             .with_label_values(vec![deployment_id.as_ref()].as_slice())
             .observe(duration);
     }
+
+    pub fn observe_rejected_query(&self, tenant: &str, reason: &str) {
+        self.rejected_queries
+            .with_label_values(vec![tenant, reason].as_slice())
+            .inc();
+    }
 }
 
 pub type GraphQLServiceResult = Result<Response<Body>, GraphQLServerError>;
@@ -80,6 +98,8 @@ pub struct GraphQLService<Q> {
     graphql_runner: Arc<Q>,
     ws_port: u16,
     node_id: NodeId,
+    tenants: Arc<Tenants>,
+    persisted_queries: Arc<PersistedQueries>,
 }
 
 impl<Q> Clone for GraphQLService<Q> {
@@ -90,6 +110,8 @@ impl<Q> Clone for GraphQLService<Q> {
             graphql_runner: self.graphql_runner.clone(),
             ws_port: self.ws_port,
             node_id: self.node_id.clone(),
+            tenants: self.tenants.clone(),
+            persisted_queries: self.persisted_queries.clone(),
         }
     }
 }
@@ -105,6 +127,8 @@ where
         graphql_runner: Arc<Q>,
         ws_port: u16,
         node_id: NodeId,
+        tenants: Arc<Tenants>,
+        persisted_queries: Arc<PersistedQueries>,
     ) -> Self {
         GraphQLService {
             logger,
@@ -112,6 +136,8 @@ where
             graphql_runner,
             ws_port,
             node_id,
+            tenants,
+            persisted_queries,
         }
     }
 
@@ -175,7 +201,7 @@ where
             GraphQLServerError::ClientError(format!("Invalid subgraph name {:?}", subgraph_name))
         })?;
 
-        self.handle_graphql_query(subgraph_name.into(), request.into_body())
+        self.handle_graphql_query(subgraph_name.into(), request)
             .await
     }
 
@@ -188,32 +214,72 @@ where
             .map_err(|id| GraphQLServerError::ClientError(format!("Invalid subgraph id `{}`", id)));
         match res {
             Err(_) => self.handle_not_found(),
-            Ok(id) => self
-                .handle_graphql_query(id.into(), request.into_body())
-                .boxed(),
+            Ok(id) => self.handle_graphql_query(id.into(), request).boxed(),
         }
     }
 
     async fn handle_graphql_query(
         self,
         target: QueryTarget,
-        request_body: Body,
+        request: Request<Body>,
     ) -> GraphQLServiceResult {
         let service = self.clone();
         let service_metrics = self.metrics.clone();
 
+        let authorization = request
+            .headers()
+            .get(AUTHORIZATION)
+            .map(|value| value.to_str().unwrap_or_default());
+        let tenant = match self.tenants.authenticate(authorization) {
+            Ok(tenant) => tenant,
+            Err(rejection) => return Ok(self.handle_rejection(rejection)),
+        };
+
         let start = Instant::now();
-        let body = hyper::body::to_bytes(request_body)
+        let body = hyper::body::to_bytes(request.into_body())
             .map_err(|_| GraphQLServerError::InternalError("Failed to read request body".into()))
             .await?;
-        let query = GraphQLRequest::new(body).compat().await;
+        let query = match GraphQLRequest::new(body).parts() {
+            Ok(parts) => self.persisted_queries.query(parts).await,
+            Err(e) => Err(e),
+        };
 
-        let result = match query {
-            Ok(query) => service.graphql_runner.run_query(query, target).await,
-            Err(GraphQLServerError::QueryError(e)) => QueryResult::from(e).into(),
-            Err(e) => return Err(e),
+        let max_complexity = tenant.as_ref().and_then(|tenant| tenant.max_complexity());
+        let result = match (query, max_complexity) {
+            (Ok(query), None) => service.graphql_runner.run_query(query, target).await,
+            (Ok(query), Some(max_complexity)) => {
+                service
+                    .graphql_runner
+                    .run_query_with_complexity(
+                        query,
+                        target,
+                        Some(max_complexity),
+                        None,
+                        None,
+                        None,
+                    )
+                    .await
+            }
+            (Err(GraphQLServerError::QueryError(e)), _) => QueryResult::from(e).into(),
+            (Err(e), _) => return Err(e),
         };
 
+        if let Some(tenant) = &tenant {
+            tenant.record_effort(start.elapsed());
+
+            let too_complex = result.first().map_or(false, |res| {
+                res.errors().iter().any(|e| {
+                    matches!(
+                        e,
+                        QueryError::ExecutionError(QueryExecutionError::TooComplex(_, _))
+                    )
+                })
+            });
+            if too_complex {
+                service_metrics.observe_rejected_query(tenant.name(), "complexity");
+            }
+        }
+
         if let Some(id) = result.first().and_then(|res| res.deployment.clone()) {
             service_metrics
                 .observe_query_execution_time(start.elapsed().as_secs_f64(), id.to_string());
@@ -222,13 +288,29 @@ where
         Ok(result.as_http_response())
     }
 
+    /// Responds to a request that the tenant limits do not allow
+    fn handle_rejection(&self, rejection: TenantRejection) -> Response<Body> {
+        self.metrics
+            .observe_rejected_query(rejection.tenant(), rejection.reason());
+
+        Response::builder()
+            .status(rejection.status_code())
+            .header(CONTENT_TYPE, "text/plain")
+            .header(ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+            .body(Body::from(rejection.to_string()))
+            .unwrap()
+    }
+
     // Handles OPTIONS requests
     fn handle_graphql_options(&self, _request: Request<Body>) -> GraphQLServiceResponse {
         async {
             Ok(Response::builder()
                 .status(200)
                 .header(ACCESS_CONTROL_ALLOW_ORIGIN, "*")
-                .header(ACCESS_CONTROL_ALLOW_HEADERS, "Content-Type, User-Agent")
+                .header(
+                    ACCESS_CONTROL_ALLOW_HEADERS,
+                    "Content-Type, User-Agent, Authorization",
+                )
                 .header(ACCESS_CONTROL_ALLOW_METHODS, "GET, OPTIONS, POST")
                 .header(CONTENT_TYPE, "text/html")
                 .body(Body::from(""))
@@ -390,8 +472,11 @@ mod tests {
     use http::status::StatusCode;
     use hyper::service::Service;
     use hyper::{Body, Method, Request};
-    use std::collections::BTreeMap;
+    use std::collections::{BTreeMap, HashMap};
+    use std::sync::Mutex;
 
+    use graph::components::server::tenant::{Tenant, TenantLimits, Tenants};
+    use graph::components::store::PersistedQueryStore;
     use graph::data::{
         graphql::effort::LoadManager,
         query::{QueryResults, QueryTarget},
@@ -399,6 +484,8 @@ mod tests {
     use graph::prelude::*;
     use graph_mock::MockMetricsRegistry;
 
+    use crate::persisted::{query_hash, PersistedQueries, PersistedQueryMode};
+    use crate::request::RequestParts;
     use crate::test_utils;
 
     use super::GraphQLService;
@@ -448,6 +535,53 @@ mod tests {
         }
     }
 
+    /// A persisted query registry that only lives in memory
+    #[derive(Default)]
+    pub struct TestPersistedQueryStore {
+        queries: Mutex<HashMap<String, String>>,
+    }
+
+    #[async_trait]
+    impl PersistedQueryStore for TestPersistedQueryStore {
+        async fn persisted_query(&self, hash: &str) -> Result<Option<String>, StoreError> {
+            Ok(self.queries.lock().unwrap().get(hash).cloned())
+        }
+
+        async fn register_persisted_query(
+            &self,
+            hash: &str,
+            query: &str,
+        ) -> Result<(), StoreError> {
+            self.queries
+                .lock()
+                .unwrap()
+                .entry(hash.to_string())
+                .or_insert_with(|| query.to_string());
+            Ok(())
+        }
+
+        async fn persisted_queries(&self) -> Result<Vec<(String, String)>, StoreError> {
+            Ok(self
+                .queries
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(hash, query)| (hash.clone(), query.clone()))
+                .collect())
+        }
+
+        async fn persisted_query_count(&self) -> Result<usize, StoreError> {
+            Ok(self.queries.lock().unwrap().len())
+        }
+    }
+
+    fn persisted_queries(
+        mode: PersistedQueryMode,
+        store: Arc<TestPersistedQueryStore>,
+    ) -> Arc<PersistedQueries> {
+        Arc::new(PersistedQueries::new(mode, store))
+    }
+
     #[test]
     fn posting_invalid_query_yields_error_response() {
         let logger = Logger::root(slog::Discard, o!());
@@ -457,7 +591,17 @@ mod tests {
         let graphql_runner = Arc::new(TestGraphQlRunner);
 
         let node_id = NodeId::new("test").unwrap();
-        let mut service = GraphQLService::new(logger, metrics, graphql_runner, 8001, node_id);
+        let tenants = Arc::new(Tenants::default());
+        let persisted = persisted_queries(PersistedQueryMode::Off, Default::default());
+        let mut service = GraphQLService::new(
+            logger,
+            metrics,
+            graphql_runner,
+            8001,
+            node_id,
+            tenants,
+            persisted,
+        );
 
         let request = Request::builder()
             .method(Method::POST)
@@ -489,7 +633,17 @@ mod tests {
         let graphql_runner = Arc::new(TestGraphQlRunner);
 
         let node_id = NodeId::new("test").unwrap();
-        let mut service = GraphQLService::new(logger, metrics, graphql_runner, 8001, node_id);
+        let tenants = Arc::new(Tenants::default());
+        let persisted = persisted_queries(PersistedQueryMode::Off, Default::default());
+        let mut service = GraphQLService::new(
+            logger,
+            metrics,
+            graphql_runner,
+            8001,
+            node_id,
+            tenants,
+            persisted,
+        );
 
         let request = Request::builder()
             .method(Method::POST)
@@ -515,4 +669,185 @@ mod tests {
             .expect("Query result field \"name\" is not a string");
         assert_eq!(name, "Jordi".to_string());
     }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn tenant_limits_reject_requests() {
+        let logger = Logger::root(slog::Discard, o!());
+        let metrics_registry = Arc::new(MockMetricsRegistry::new());
+        let metrics = Arc::new(GraphQLServiceMetrics::new(metrics_registry));
+        let graphql_runner = Arc::new(TestGraphQlRunner);
+        let limits = TenantLimits {
+            requests_per_second: Some(1),
+            ..Default::default()
+        };
+        let tenant = Tenant::new("acme".to_string(), vec!["secret".to_string()], limits);
+        let tenants = Arc::new(Tenants::new(vec![tenant], false).unwrap());
+
+        let node_id = NodeId::new("test").unwrap();
+        let persisted = persisted_queries(PersistedQueryMode::Off, Default::default());
+        let service = GraphQLService::new(
+            logger,
+            metrics,
+            graphql_runner,
+            8001,
+            node_id,
+            tenants,
+            persisted,
+        );
+
+        let request = |token: Option<&str>| {
+            let mut builder = Request::builder()
+                .method(Method::POST)
+                .uri(format!("http://localhost:8000/subgraphs/id/{}", *USERS));
+            if let Some(token) = token {
+                builder = builder.header("Authorization", format!("Bearer {}", token));
+            }
+            builder
+                .body(Body::from("{\"query\": \"{ name }\"}"))
+                .unwrap()
+        };
+
+        let status = |token: Option<&'static str>| {
+            let mut service = service.clone();
+            let request = request(token);
+            async move {
+                tokio::spawn(service.call(request))
+                    .await
+                    .unwrap()
+                    .expect("Should return a response")
+                    .status()
+            }
+        };
+
+        assert_eq!(StatusCode::UNAUTHORIZED, status(None).await);
+        assert_eq!(StatusCode::UNAUTHORIZED, status(Some("wrong")).await);
+        assert_eq!(StatusCode::OK, status(Some("secret")).await);
+        assert_eq!(StatusCode::TOO_MANY_REQUESTS, status(Some("secret")).await);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn persisted_queries_and_allowlist() {
+        const QUERY: &str = "{ name }";
+
+        let store = Arc::new(TestPersistedQueryStore::default());
+        let hash = query_hash(QUERY);
+
+        let service = |mode: PersistedQueryMode| {
+            let logger = Logger::root(slog::Discard, o!());
+            let metrics_registry = Arc::new(MockMetricsRegistry::new());
+            let metrics = Arc::new(GraphQLServiceMetrics::new(metrics_registry));
+            let node_id = NodeId::new("test").unwrap();
+            GraphQLService::new(
+                logger,
+                metrics,
+                Arc::new(TestGraphQlRunner),
+                8001,
+                node_id,
+                Arc::new(Tenants::default()),
+                persisted_queries(mode, store.clone()),
+            )
+        };
+
+        let request = |query: Option<&str>, hash: &str| {
+            let extensions = format!(
+                "\"extensions\": {{ \"persistedQuery\": {{ \"version\": 1, \"sha256Hash\": \"{}\" }} }}",
+                hash
+            );
+            let body = match query {
+                Some(query) => format!("{{ \"query\": \"{}\", {} }}", query, extensions),
+                None => format!("{{ {} }}", extensions),
+            };
+            Request::builder()
+                .method(Method::POST)
+                .uri(format!("http://localhost:8000/subgraphs/id/{}", *USERS))
+                .body(Body::from(body))
+                .unwrap()
+        };
+
+        let call = |mut service: GraphQLService<TestGraphQlRunner>, request| async move {
+            tokio::spawn(service.call(request))
+                .await
+                .unwrap()
+                .expect("Should return a response")
+        };
+
+        let error_message = |response| {
+            let errors = test_utils::assert_error_response(response, StatusCode::OK, true);
+            errors[0]
+                .get("message")
+                .and_then(|message| message.as_str())
+                .expect("Error message is not a string")
+                .to_string()
+        };
+
+        // A strict server refuses queries that have not been registered
+        let strict = service(PersistedQueryMode::Strict);
+        let response = call(strict.clone(), request(Some(QUERY), &hash)).await;
+        assert_eq!(
+            "this endpoint only runs queries that have been registered as persisted queries",
+            error_message(response)
+        );
+
+        // Queries that are only sent by their hash need to be registered
+        let open = service(PersistedQueryMode::On);
+        let response = call(open.clone(), request(None, &hash)).await;
+        assert_eq!("PersistedQueryNotFound", error_message(response));
+
+        // The hash has to match the query
+        let response = call(open.clone(), request(Some(QUERY), "0badc0de")).await;
+        assert!(error_message(response).contains("does not match the persisted query hash"));
+
+        // Sending the query together with its hash registers it
+        let response = call(open.clone(), request(Some(QUERY), &hash)).await;
+        test_utils::assert_successful_response(response);
+        let response = call(open, request(None, &hash)).await;
+        test_utils::assert_successful_response(response);
+
+        // A new strict server loads the registered query
+        let strict = service(PersistedQueryMode::Strict);
+        let response = call(strict.clone(), request(None, &hash)).await;
+        test_utils::assert_successful_response(response);
+
+        // Queries with the same shape as a registered query are allowed, too
+        let response = call(strict, request(Some("{name}"), &query_hash("{name}"))).await;
+        test_utils::assert_successful_response(response);
+    }
+
+    #[tokio::test]
+    async fn persisted_query_limits() {
+        const SHORT: &str = "{ name }";
+        const LONG: &str = "{ name name }";
+
+        let store = Arc::new(TestPersistedQueryStore::default());
+        let persisted = PersistedQueries::new(PersistedQueryMode::On, store.clone())
+            .with_limits(SHORT.len(), 1);
+
+        let persisted = &persisted;
+        let register = move |text: &'static str| {
+            let parts = RequestParts {
+                query: Some(text.to_string()),
+                variables: None,
+                persisted_hash: Some(query_hash(text)),
+            };
+            async move { persisted.query(parts).await.expect("the query runs") }
+        };
+
+        // Queries that are too long run, but are not registered
+        register(LONG).await;
+        assert_eq!(
+            None,
+            store.persisted_query(&query_hash(LONG)).await.unwrap()
+        );
+
+        register(SHORT).await;
+        assert_eq!(1, store.persisted_query_count().await.unwrap());
+
+        // Once the registry is full, nothing else gets registered
+        register("{ a }").await;
+        assert_eq!(1, store.persisted_query_count().await.unwrap());
+        assert_eq!(
+            None,
+            store.persisted_query(&query_hash("{ a }")).await.unwrap()
+        );
+    }
 }
@@ -3,6 +3,8 @@ use hyper::{Body, Client, Request};
 use std::collections::BTreeMap;
 use std::time::Duration;
 
+use graph::components::server::tenant::Tenants;
+use graph::components::store::PersistedQueryStore;
 use graph::data::{
     graphql::effort::LoadManager,
     query::{QueryResults, QueryTarget},
@@ -11,6 +13,7 @@ use graph::prelude::*;
 
 use graph_server_http::test_utils;
 use graph_server_http::GraphQLServer as HyperGraphQLServer;
+use graph_server_http::{PersistedQueries, PersistedQueryMode};
 
 use tokio::time::sleep;
 
@@ -74,6 +77,35 @@ impl GraphQlRunner for TestGraphQlRunner {
     }
 }
 
+/// A persisted query registry for servers that do not use persisted queries
+pub struct NoPersistedQueries;
+
+#[async_trait]
+impl PersistedQueryStore for NoPersistedQueries {
+    async fn persisted_query(&self, _hash: &str) -> Result<Option<String>, StoreError> {
+        unreachable!()
+    }
+
+    async fn register_persisted_query(&self, _hash: &str, _query: &str) -> Result<(), StoreError> {
+        unreachable!()
+    }
+
+    async fn persisted_queries(&self) -> Result<Vec<(String, String)>, StoreError> {
+        unreachable!()
+    }
+
+    async fn persisted_query_count(&self) -> Result<usize, StoreError> {
+        unreachable!()
+    }
+}
+
+fn no_persisted_queries() -> Arc<PersistedQueries> {
+    Arc::new(PersistedQueries::new(
+        PersistedQueryMode::Off,
+        Arc::new(NoPersistedQueries),
+    ))
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -94,7 +126,14 @@ mod test {
                 let id = USERS.clone();
                 let query_runner = Arc::new(TestGraphQlRunner);
                 let node_id = NodeId::new("test").unwrap();
-                let mut server = HyperGraphQLServer::new(&logger_factory, metrics_registry, query_runner, node_id);
+                let mut server = HyperGraphQLServer::new(
+                    &logger_factory,
+                    metrics_registry,
+                    query_runner,
+                    node_id,
+                    Arc::new(Tenants::default()),
+                    no_persisted_queries(),
+                );
                 let http_server = server
                     .serve(8007, 8008)
                     .expect("Failed to start GraphQL server");
@@ -136,8 +175,14 @@ mod test {
             let id = USERS.clone();
             let query_runner = Arc::new(TestGraphQlRunner);
             let node_id = NodeId::new("test").unwrap();
-            let mut server =
-                HyperGraphQLServer::new(&logger_factory, metrics_registry, query_runner, node_id);
+            let mut server = HyperGraphQLServer::new(
+                &logger_factory,
+                metrics_registry,
+                query_runner,
+                node_id,
+                Arc::new(Tenants::default()),
+                no_persisted_queries(),
+            );
             let http_server = server
                 .serve(8002, 8003)
                 .expect("Failed to start GraphQL server");
@@ -218,8 +263,14 @@ mod test {
             let id = USERS.clone();
             let query_runner = Arc::new(TestGraphQlRunner);
             let node_id = NodeId::new("test").unwrap();
-            let mut server =
-                HyperGraphQLServer::new(&logger_factory, metrics_registry, query_runner, node_id);
+            let mut server = HyperGraphQLServer::new(
+                &logger_factory,
+                metrics_registry,
+                query_runner,
+                node_id,
+                Arc::new(Tenants::default()),
+                no_persisted_queries(),
+            );
             let http_server = server
                 .serve(8003, 8004)
                 .expect("Failed to start GraphQL server");
@@ -265,8 +316,14 @@ mod test {
             let id = USERS.clone();
             let query_runner = Arc::new(TestGraphQlRunner);
             let node_id = NodeId::new("test").unwrap();
-            let mut server =
-                HyperGraphQLServer::new(&logger_factory, metrics_registry, query_runner, node_id);
+            let mut server = HyperGraphQLServer::new(
+                &logger_factory,
+                metrics_registry,
+                query_runner,
+                node_id,
+                Arc::new(Tenants::default()),
+                no_persisted_queries(),
+            );
             let http_server = server
                 .serve(8005, 8006)
                 .expect("Failed to start GraphQL server");
@@ -2,13 +2,15 @@ use either::Either;
 use graph::blockchain::{Blockchain, BlockchainKind};
 use std::collections::{BTreeMap, HashMap};
 
+use graph::components::subgraph::SubgraphProfiles;
 use graph::data::subgraph::features::detect_features;
 use graph::data::subgraph::{status, MAX_SPEC_VERSION};
 use graph::prelude::*;
 use graph::{
     components::store::StatusStore,
-    data::graphql::{IntoValue, ObjectOrInterface, ValueMap},
+    data::graphql::{object, IntoValue, ObjectOrInterface, ValueMap},
 };
+use graph_chain_ethereum::EthereumNetworks;
 use graph_graphql::prelude::{ExecutionContext, Resolver};
 use std::convert::TryInto;
 use web3::types::{Address, H256};
@@ -19,6 +21,8 @@ pub struct IndexNodeResolver<S, R, St> {
     store: Arc<S>,
     link_resolver: Arc<R>,
     subgraph_store: Arc<St>,
+    eth_networks: Arc<EthereumNetworks>,
+    profiles: Arc<SubgraphProfiles>,
 }
 
 impl<S, R, St> IndexNodeResolver<S, R, St>
@@ -32,6 +36,8 @@ where
         store: Arc<S>,
         link_resolver: Arc<R>,
         subgraph_store: Arc<St>,
+        eth_networks: Arc<EthereumNetworks>,
+        profiles: Arc<SubgraphProfiles>,
     ) -> Self {
         let logger = logger.new(o!("component" => "IndexNodeResolver"));
         Self {
@@ -39,9 +45,76 @@ where
             store,
             link_resolver,
             subgraph_store,
+            eth_networks,
+            profiles,
         }
     }
 
+    fn resolve_ethereum_providers(&self) -> r::Value {
+        r::Value::List(
+            self.eth_networks
+                .health()
+                .into_iter()
+                .map(|(network, health)| {
+                    object! {
+                        __typename: "EthereumProviderHealth",
+                        network: network,
+                        provider: health.provider,
+                        score: health.score,
+                        latency: health.latency,
+                        errorRate: health.error_rate,
+                        headLag: health.head_lag,
+                        ejected: health.ejected,
+                    }
+                })
+                .collect(),
+        )
+    }
+
+    fn resolve_handler_profiles(
+        &self,
+        arguments: &HashMap<&str, r::Value>,
+    ) -> Result<r::Value, QueryExecutionError> {
+        // We can safely unwrap because the argument is non-nullable and has been validated.
+        let subgraph = arguments.get_required::<String>("subgraph").unwrap();
+        let deployment_hash = DeploymentHash::new(subgraph)
+            .map_err(QueryExecutionError::SubgraphDeploymentIdError)?;
+
+        let handlers = match self.profiles.get(&deployment_hash) {
+            Some(profile) => profile.handlers(),
+            None => return Ok(r::Value::List(vec![])),
+        };
+        Ok(r::Value::List(
+            handlers
+                .into_iter()
+                .map(|(handler, stats)| {
+                    let host_functions: Vec<_> = stats
+                        .host_fns
+                        .into_iter()
+                        .map(|(name, host_fn)| {
+                            object! {
+                                __typename: "HostFunctionProfile",
+                                name: name,
+                                calls: format!("{}", host_fn.calls),
+                                time: host_fn.time.as_secs_f64(),
+                            }
+                        })
+                        .collect();
+                    object! {
+                        __typename: "HandlerProfile",
+                        handler: handler,
+                        calls: format!("{}", stats.calls),
+                        time: stats.time.as_secs_f64(),
+                        gas: format!("{}", stats.gas),
+                        entityCacheHits: format!("{}", stats.cache_hits),
+                        entityCacheMisses: format!("{}", stats.cache_misses),
+                        hostFunctions: host_functions,
+                    }
+                })
+                .collect(),
+        ))
+    }
+
     fn resolve_indexing_statuses(
         &self,
         arguments: &HashMap<&str, r::Value>,
@@ -66,6 +139,21 @@ where
         Ok(infos.into_value())
     }
 
+    fn resolve_deployment_stats(
+        &self,
+        arguments: &HashMap<&str, r::Value>,
+    ) -> Result<r::Value, QueryExecutionError> {
+        // We can safely unwrap because the argument is non-nullable and has been validated.
+        let subgraph = arguments.get_required::<String>("subgraph").unwrap();
+        let deployment_hash = DeploymentHash::new(subgraph)
+            .map_err(QueryExecutionError::SubgraphDeploymentIdError)?;
+
+        let stats = self.store.deployment_stats(&deployment_hash)?;
+        Ok(stats
+            .map(|stats| stats.into_value())
+            .unwrap_or(r::Value::Null))
+    }
+
     fn resolve_indexing_statuses_for_subgraph_name(
         &self,
         arguments: &HashMap<&str, r::Value>,
@@ -424,6 +512,14 @@ where
                 self.resolve_indexing_statuses_for_subgraph_name(arguments)
             }
 
+            // The top-level `ethereumProviders` field
+            (None, "EthereumProviderHealth", "ethereumProviders") => {
+                Ok(self.resolve_ethereum_providers())
+            }
+
+            // The top-level `handlerProfiles` field
+            (None, "HandlerProfile", "handlerProfiles") => self.resolve_handler_profiles(arguments),
+
             // Resolve fields of `Object` values (e.g. the `chains` field of `ChainIndexingStatus`)
             (value, _, _) => Ok(value.unwrap_or(r::Value::Null)),
         }
@@ -453,6 +549,9 @@ where
                 graph::block_on(self.resolve_subgraph_features(arguments))
             }
 
+            // The top-level `deploymentStats` field
+            (None, "deploymentStats") => self.resolve_deployment_stats(arguments),
+
             // Resolve fields of `Object` values (e.g. the `latestBlock` field of `EthereumBlock`)
             (value, _) => Ok(value.unwrap_or(r::Value::Null)),
         }
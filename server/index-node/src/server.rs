@@ -9,6 +9,8 @@ use graph::{
 };
 
 use crate::service::IndexNodeService;
+use graph::components::subgraph::SubgraphProfiles;
+use graph_chain_ethereum::EthereumNetworks;
 use thiserror::Error;
 
 /// Errors that may occur when starting the server.
@@ -31,6 +33,8 @@ pub struct IndexNodeServer<Q, S, R, St> {
     store: Arc<S>,
     link_resolver: Arc<R>,
     subgraph_store: Arc<St>,
+    eth_networks: Arc<EthereumNetworks>,
+    profiles: Arc<SubgraphProfiles>,
 }
 
 impl<Q, S, R, St> IndexNodeServer<Q, S, R, St> {
@@ -41,6 +45,8 @@ impl<Q, S, R, St> IndexNodeServer<Q, S, R, St> {
         store: Arc<S>,
         link_resolver: Arc<R>,
         subgraph_store: Arc<St>,
+        eth_networks: Arc<EthereumNetworks>,
+        profiles: Arc<SubgraphProfiles>,
     ) -> Self {
         let logger = logger_factory.component_logger(
             "IndexNodeServer",
@@ -57,6 +63,8 @@ impl<Q, S, R, St> IndexNodeServer<Q, S, R, St> {
             store,
             link_resolver,
             subgraph_store,
+            eth_networks,
+            profiles,
         }
     }
 }
@@ -94,6 +102,8 @@ where
             store.clone(),
             self.link_resolver.clone(),
             self.subgraph_store.clone(),
+            self.eth_networks.clone(),
+            self.profiles.clone(),
         );
         let new_service =
             make_service_fn(move |_| futures03::future::ok::<_, Error>(service.clone()));
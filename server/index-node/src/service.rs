@@ -7,8 +7,10 @@ use hyper::{Body, Method, Request, Response, StatusCode};
 use std::task::Context;
 use std::task::Poll;
 
+use graph::components::subgraph::SubgraphProfiles;
 use graph::{components::server::query::GraphQLServerError, data::query::QueryResults};
 use graph::{components::store::StatusStore, prelude::*};
+use graph_chain_ethereum::EthereumNetworks;
 use graph_graphql::prelude::{execute_query, Query as PreparedQuery, QueryExecutionOptions};
 
 use crate::explorer::Explorer;
@@ -20,7 +22,6 @@ use crate::schema::SCHEMA;
 pub type IndexNodeServiceResponse = DynTryFuture<'static, Response<Body>, GraphQLServerError>;
 
 /// A Hyper Service that serves GraphQL over a POST / endpoint.
-#[derive(Debug)]
 pub struct IndexNodeService<Q, S, R, St> {
     logger: Logger,
     graphql_runner: Arc<Q>,
@@ -28,6 +29,8 @@ pub struct IndexNodeService<Q, S, R, St> {
     explorer: Arc<Explorer<S>>,
     link_resolver: Arc<R>,
     subgraph_store: Arc<St>,
+    eth_networks: Arc<EthereumNetworks>,
+    profiles: Arc<SubgraphProfiles>,
 }
 
 impl<Q, S, R, St> Clone for IndexNodeService<Q, S, R, St> {
@@ -39,6 +42,8 @@ impl<Q, S, R, St> Clone for IndexNodeService<Q, S, R, St> {
             explorer: self.explorer.clone(),
             link_resolver: self.link_resolver.clone(),
             subgraph_store: self.subgraph_store.clone(),
+            eth_networks: self.eth_networks.clone(),
+            profiles: self.profiles.clone(),
         }
     }
 }
@@ -59,6 +64,8 @@ where
         store: Arc<S>,
         link_resolver: Arc<R>,
         subgraph_store: Arc<St>,
+        eth_networks: Arc<EthereumNetworks>,
+        profiles: Arc<SubgraphProfiles>,
     ) -> Self {
         let explorer = Arc::new(Explorer::new(store.clone()));
 
@@ -69,6 +76,8 @@ where
             explorer,
             link_resolver,
             subgraph_store,
+            eth_networks,
+            profiles,
         }
     }
 
@@ -130,6 +139,9 @@ where
                     store,
                     self.link_resolver.clone(),
                     self.subgraph_store.clone(),
+                    self.eth_networks.clone(),
+                    self.profiles.clone(),
+                    self.profiles.clone(),
                 ),
                 deadline: None,
                 max_first: std::u32::MAX,
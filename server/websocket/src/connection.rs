@@ -12,7 +12,9 @@ use tokio_tungstenite::tungstenite::{Error as WsError, Message as WsMessage};
 use tokio_tungstenite::WebSocketStream;
 use uuid::Uuid;
 
+use graph::components::server::tenant::{Tenant, TenantRejection};
 use graph::{data::query::QueryTarget, prelude::*};
+use graph_server_http::GraphQLServiceMetrics;
 
 lazy_static! {
     static ref MAX_OPERATIONS_PER_CONNECTION: Option<usize> =
@@ -180,6 +182,30 @@ impl Drop for Operations {
     }
 }
 
+/// Checks the operations started on a connection against the limits of
+/// the tenant that opened it
+pub(crate) struct TenantCheck {
+    tenant: Option<Arc<Tenant>>,
+    metrics: Arc<GraphQLServiceMetrics>,
+}
+
+impl TenantCheck {
+    pub(crate) fn new(tenant: Option<Arc<Tenant>>, metrics: Arc<GraphQLServiceMetrics>) -> Self {
+        TenantCheck { tenant, metrics }
+    }
+
+    fn admit(&self) -> Result<(), TenantRejection> {
+        match &self.tenant {
+            None => Ok(()),
+            Some(tenant) => tenant.admit().map_err(|rejection| {
+                self.metrics
+                    .observe_rejected_query(rejection.tenant(), rejection.reason());
+                rejection
+            }),
+        }
+    }
+}
+
 /// A WebSocket connection implementing the GraphQL over WebSocket protocol.
 pub struct GraphQlConnection<Q, S> {
     id: String,
@@ -187,6 +213,7 @@ pub struct GraphQlConnection<Q, S> {
     graphql_runner: Arc<Q>,
     stream: WebSocketStream<S>,
     deployment: DeploymentHash,
+    tenant: TenantCheck,
 }
 
 impl<Q, S> GraphQlConnection<Q, S>
@@ -200,6 +227,7 @@ where
         deployment: DeploymentHash,
         stream: WebSocketStream<S>,
         graphql_runner: Arc<Q>,
+        tenant: TenantCheck,
     ) -> Self {
         GraphQlConnection {
             id: Uuid::new_v4().to_string(),
@@ -207,6 +235,7 @@ where
             graphql_runner,
             stream,
             deployment,
+            tenant,
         }
     }
 
@@ -217,6 +246,7 @@ where
         connection_id: String,
         deployment: DeploymentHash,
         graphql_runner: Arc<Q>,
+        tenant: TenantCheck,
     ) -> Result<(), WsError> {
         let mut operations = Operations::new(msg_sink.clone());
 
@@ -275,6 +305,11 @@ where
                         }
                     }
 
+                    // Every operation counts against the tenant's request rate
+                    if let Err(rejection) = tenant.admit() {
+                        return send_error_string(&msg_sink, id.clone(), rejection.to_string());
+                    }
+
                     // Parse the GraphQL query document; respond with a GQL_ERROR if
                     // the query is invalid
                     let query = match parse_query(&payload.query) {
@@ -425,6 +460,7 @@ where
             self.id.clone(),
             self.deployment.clone(),
             self.graphql_runner.clone(),
+            self.tenant,
         );
 
         // Send outgoing messages asynchronously
@@ -0,0 +1,422 @@
+//! Stream the changes that blocks make to the entities of a deployment to
+//! a WebSocket client. Clients select this protocol by asking for the
+//! `entity-changes` subprotocol when they connect to the same URLs that
+//! serve GraphQL subscriptions.
+//!
+//! For every block that changed entities, the server sends a `block`
+//! message with all inserts, updates and deletes of that block and a
+//! cursor. When the deployment reverts blocks that the client has already
+//! received, the server sends a `revert` message with the block the client
+//! needs to revert to; the client must discard all changes from blocks
+//! after that block. A client resumes a feed by passing the cursor of the
+//! last message it processed as the `cursor` query parameter.
+use graph::blockchain::{BlockHash, REORG_THRESHOLD};
+use graph::env::env_var;
+use graph::prelude::*;
+use http::StatusCode;
+use lazy_static::lazy_static;
+use std::fmt;
+use std::str::FromStr;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_tungstenite::tungstenite::{Error as WsError, Message as WsMessage};
+use tokio_tungstenite::WebSocketStream;
+
+/// The WebSocket subprotocol that clients use to ask for entity changes
+pub(crate) const ENTITY_CHANGES_PROTOCOL: &str = "entity-changes";
+
+/// The maximum number of blocks whose changes we read at once
+const MAX_BLOCKS: BlockNumber = 1_000;
+
+lazy_static! {
+    /// How often we check for new blocks once a client has received all
+    /// changes
+    static ref POLL_INTERVAL: Duration =
+        Duration::from_millis(env_var("GRAPH_ENTITY_CHANGES_POLL_INTERVAL", 1_000));
+}
+
+/// The position of a client in the feed. The hash is only known for
+/// blocks close to the deployment head, and is omitted for older blocks
+/// since they can not be reverted anymore
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Cursor {
+    number: BlockNumber,
+    hash: Option<BlockHash>,
+}
+
+impl Cursor {
+    fn new(number: BlockNumber, hash: Option<BlockHash>) -> Self {
+        Cursor { number, hash }
+    }
+}
+
+impl From<&BlockPtr> for Cursor {
+    fn from(ptr: &BlockPtr) -> Self {
+        Cursor::new(ptr.number, Some(ptr.hash.clone()))
+    }
+}
+
+impl FromStr for Cursor {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(2, ':');
+        let number = parts
+            .next()
+            .and_then(|number| BlockNumber::from_str(number).ok())
+            .filter(|number| *number >= 0)
+            .ok_or_else(|| anyhow!("invalid cursor `{}`", s))?;
+        let hash = parts.next().map(BlockHash::try_from).transpose()?;
+        Ok(Cursor { number, hash })
+    }
+}
+
+impl fmt::Display for Cursor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.hash {
+            Some(hash) => write!(f, "{}:{}", self.number, hash),
+            None => write!(f, "{}", self.number),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct BlockInfo {
+    number: BlockNumber,
+    hash: Option<String>,
+}
+
+impl From<&Cursor> for BlockInfo {
+    fn from(cursor: &Cursor) -> Self {
+        BlockInfo {
+            number: cursor.number,
+            hash: cursor.hash.as_ref().map(|hash| hash.to_string()),
+        }
+    }
+}
+
+/// Message sent to the client
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum OutgoingMessage {
+    Block {
+        block: BlockInfo,
+        cursor: String,
+        changes: Vec<EntityVersionChange>,
+    },
+    Revert {
+        block: BlockInfo,
+        cursor: String,
+    },
+    Error {
+        message: String,
+    },
+}
+
+impl OutgoingMessage {
+    fn block(cursor: &Cursor, changes: Vec<EntityVersionChange>) -> Self {
+        OutgoingMessage::Block {
+            block: cursor.into(),
+            cursor: cursor.to_string(),
+            changes,
+        }
+    }
+
+    fn revert(cursor: &Cursor) -> Self {
+        OutgoingMessage::Revert {
+            block: cursor.into(),
+            cursor: cursor.to_string(),
+        }
+    }
+}
+
+impl From<OutgoingMessage> for WsMessage {
+    fn from(msg: OutgoingMessage) -> Self {
+        WsMessage::text(serde_json::to_string(&msg).expect("invalid entity change message"))
+    }
+}
+
+/// The hash of the block with `number` in `chain`, which must be ordered
+/// from the newest to the oldest block
+fn hash_at(chain: &[BlockPtr], number: BlockNumber) -> Option<&BlockHash> {
+    let head = chain.first()?;
+    let offset = usize::try_from(head.number - number).ok()?;
+    chain
+        .get(offset)
+        .filter(|ptr| ptr.number == number)
+        .map(|ptr| &ptr.hash)
+}
+
+/// Find the newest block in `cursor_chain` that is also part of `chain`
+fn common_ancestor(chain: &[BlockPtr], cursor_chain: &[BlockPtr]) -> Option<BlockPtr> {
+    cursor_chain
+        .iter()
+        .find(|ptr| hash_at(chain, ptr.number) == Some(&ptr.hash))
+        .cloned()
+}
+
+/// Check whether the client at `cursor` has seen blocks that are not on
+/// the chain that ends in `head`
+fn diverged(cursor: &Cursor, head: &BlockPtr, chain: &[BlockPtr]) -> bool {
+    match &cursor.hash {
+        // Without a hash, the cursor points to a final block
+        None => false,
+        Some(_) if cursor.number > head.number => true,
+        Some(hash) => matches!(hash_at(chain, cursor.number), Some(canonical) if canonical != hash),
+    }
+}
+
+/// Fail unless `missing`, a block that is not in the block cache, is final.
+/// Without the hashes of blocks that can still be reverted, we could not
+/// tell clients about reverts. Chains that do not fill the block cache,
+/// like Ethereum chains read through Firehose, can not use the feed
+fn check_final(store: &dyn QueryStore, head: &BlockPtr, missing: BlockNumber) -> Result<(), Error> {
+    let chain_head = store
+        .chain_head_ptr()?
+        .map_or(head.number, |ptr| ptr.number.max(head.number));
+    if missing > chain_head - *REORG_THRESHOLD {
+        return Err(anyhow!(
+            "block {} is missing from the block cache of the chain but is not final yet. \
+             The entity changes feed needs the chain's block cache to detect reverts, and \
+             can not be used with chains that do not fill it",
+            missing
+        ));
+    }
+    Ok(())
+}
+
+/// Produce the messages for the blocks after `cursor`, and the cursor from
+/// which to continue. Fails if the history of the deployment after the
+/// cursor has been pruned
+fn next_batch(
+    store: &dyn QueryStore,
+    cursor: Option<&Cursor>,
+    earliest_block: BlockNumber,
+) -> Result<(Vec<OutgoingMessage>, Option<Cursor>), Error> {
+    let from = cursor.map_or(0, |cursor| cursor.number + 1);
+    // Pruning removes the versions whose block range ends at or before
+    // `earliest_block`, and with them the record of entities that were
+    // deleted in `earliest_block`; the feed can only continue after it
+    if earliest_block > 0 && from <= earliest_block {
+        return Err(QueryExecutionError::HistoryPruned(from, earliest_block + 1).into());
+    }
+    let EntityChanges { head, changes } =
+        store.entity_changes(BlockSpan::new(from, from + MAX_BLOCKS - 1))?;
+    let head = match head {
+        Some(head) => head,
+        None => return Ok((vec![], cursor.cloned())),
+    };
+
+    // The blocks on the deployment's chain that we might need hashes for
+    let lowest = cursor.map_or(from, |cursor| cursor.number).min(head.number);
+    let wanted = (head.number - lowest).min(*REORG_THRESHOLD);
+    let chain = store.ancestors(&head, wanted)?;
+    if chain.len() as BlockNumber <= wanted {
+        check_final(store, &head, head.number - chain.len() as BlockNumber)?;
+    }
+
+    if let Some(cursor) = cursor {
+        if diverged(cursor, &head, &chain) {
+            let chain = store.ancestors(&head, *REORG_THRESHOLD)?;
+            let cursor_block = BlockPtr::new(cursor.hash.clone().unwrap(), cursor.number);
+            let cursor_chain = store.ancestors(&cursor_block, *REORG_THRESHOLD)?;
+            let ancestor = common_ancestor(&chain, &cursor_chain).ok_or_else(|| {
+                anyhow!(
+                    "the block for cursor {} is not on the chain of the deployment \
+                     and has no common ancestor with it within {} blocks",
+                    cursor,
+                    *REORG_THRESHOLD
+                )
+            })?;
+            let ancestor = Cursor::from(&ancestor);
+            return Ok((vec![OutgoingMessage::revert(&ancestor)], Some(ancestor)));
+        }
+    }
+
+    if from > head.number {
+        return Ok((vec![], cursor.cloned()));
+    }
+    let to = (from + MAX_BLOCKS - 1).min(head.number);
+
+    let mut blocks: Vec<(BlockNumber, Vec<EntityVersionChange>)> = Vec::new();
+    for change in changes {
+        match blocks.last_mut() {
+            Some((block, changes)) if *block == change.block => changes.push(change),
+            _ => blocks.push((change.block, vec![change])),
+        }
+    }
+    let messages = blocks
+        .into_iter()
+        .map(|(block, changes)| {
+            let cursor = Cursor::new(block, hash_at(&chain, block).cloned());
+            OutgoingMessage::block(&cursor, changes)
+        })
+        .collect();
+    Ok((
+        messages,
+        Some(Cursor::new(to, hash_at(&chain, to).cloned())),
+    ))
+}
+
+/// A WebSocket connection that streams entity changes to the client
+pub struct EntityChangeConnection<S> {
+    logger: Logger,
+    store: Arc<dyn QueryStore + Send + Sync>,
+    stream: WebSocketStream<S>,
+    cursor: Option<Cursor>,
+}
+
+impl<S> EntityChangeConnection<S>
+where
+    S: AsyncRead + AsyncWrite + Send + 'static + Unpin,
+{
+    pub(crate) fn new(
+        logger: &Logger,
+        store: Arc<dyn QueryStore + Send + Sync>,
+        stream: WebSocketStream<S>,
+        cursor: Option<Cursor>,
+    ) -> Self {
+        EntityChangeConnection {
+            logger: logger.new(o!("component" => "EntityChangeConnection")),
+            store,
+            stream,
+            cursor,
+        }
+    }
+
+    pub(crate) async fn run(self) {
+        let EntityChangeConnection {
+            logger,
+            store,
+            stream,
+            cursor,
+        } = self;
+        debug!(logger, "Entity change connection opened";
+               "cursor" => cursor.as_ref().map(|cursor| cursor.to_string()));
+
+        let (mut ws_sink, mut ws_stream) = stream.split();
+
+        // We do not expect any messages from the client, but need to read
+        // from the socket to notice when the client goes away
+        let reader = async move {
+            while let Some(Ok(msg)) = ws_stream.next().await {
+                if msg.is_close() {
+                    break;
+                }
+            }
+        };
+
+        let writer_logger = logger.clone();
+        let writer = async move {
+            if let Err(e) = Self::send_changes(store, cursor, &mut ws_sink).await {
+                debug!(writer_logger, "Entity change feed failed"; "error" => e.to_string());
+                let msg = OutgoingMessage::Error {
+                    message: e.to_string(),
+                };
+                let _ = ws_sink.send(msg.into()).await;
+                let _ = ws_sink.close().await;
+            }
+        };
+
+        futures03::future::select(Box::pin(reader), Box::pin(writer)).await;
+        debug!(logger, "Entity change connection closed");
+    }
+
+    async fn send_changes<K>(
+        store: Arc<dyn QueryStore + Send + Sync>,
+        mut cursor: Option<Cursor>,
+        sink: &mut K,
+    ) -> Result<(), Error>
+    where
+        K: futures03::Sink<WsMessage, Error = WsError> + Unpin,
+    {
+        loop {
+            let earliest_block = store.deployment_state().await?.earliest_block_number;
+            let batch_store = store.clone();
+            let batch_cursor = cursor.clone();
+            let (messages, next) = graph::spawn_blocking_allow_panic(move || {
+                next_batch(batch_store.as_ref(), batch_cursor.as_ref(), earliest_block)
+            })
+            .await??;
+
+            let idle = next == cursor;
+            for msg in messages {
+                sink.send(msg.into())
+                    .await
+                    .map_err(|e| anyhow!("failed to send message: {}", e))?;
+            }
+            cursor = next;
+
+            if idle {
+                tokio::time::sleep(*POLL_INTERVAL).await;
+            }
+        }
+    }
+}
+
+/// Find the cursor in the query string of a request and parse it
+pub(crate) fn cursor_from_query(query: Option<&str>) -> Result<Option<Cursor>, StatusCode> {
+    let query = match query {
+        Some(query) => query,
+        None => return Ok(None),
+    };
+    graph::url::form_urlencoded::parse(query.as_bytes())
+        .find(|(key, _)| key == "cursor")
+        .map(|(_, cursor)| Cursor::from_str(&cursor).map_err(|_| StatusCode::BAD_REQUEST))
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ptr(number: BlockNumber, fork: u8) -> BlockPtr {
+        BlockPtr::new(BlockHash::from(vec![fork, number as u8]), number)
+    }
+
+    fn chain(head: BlockNumber, fork: u8, fork_point: BlockNumber) -> Vec<BlockPtr> {
+        (0..=head)
+            .rev()
+            .map(|number| ptr(number, if number > fork_point { fork } else { 0 }))
+            .collect()
+    }
+
+    #[test]
+    fn cursors() {
+        let cursor = Cursor::from(&ptr(17, 1));
+        assert_eq!("17:0x0111", cursor.to_string());
+        assert_eq!(cursor, Cursor::from_str("17:0x0111").unwrap());
+        assert_eq!(Cursor::new(17, None), Cursor::from_str("17").unwrap());
+        assert!(Cursor::from_str("-1").is_err());
+        assert!(Cursor::from_str("17:xyz").is_err());
+
+        assert_eq!(Ok(None), cursor_from_query(None));
+        assert_eq!(
+            Ok(Some(cursor)),
+            cursor_from_query(Some("x=1&cursor=17%3A0x0111"))
+        );
+        assert_eq!(
+            Err(StatusCode::BAD_REQUEST),
+            cursor_from_query(Some("cursor=abc"))
+        );
+    }
+
+    #[test]
+    fn reverts() {
+        let main = chain(10, 0, 10);
+        let fork = chain(12, 1, 7);
+        let head = main.first().unwrap();
+
+        // Cursors on the main chain, or too old to check, have not diverged
+        assert!(!diverged(&Cursor::from(&ptr(8, 0)), head, &main));
+        assert!(!diverged(&Cursor::new(8, None), head, &main));
+        assert!(!diverged(&Cursor::from(&ptr(8, 1)), head, &main[..2]));
+
+        // Cursors on a fork, or ahead of the deployment, have
+        assert!(diverged(&Cursor::from(&ptr(8, 1)), head, &main));
+        assert!(diverged(&Cursor::from(&ptr(12, 1)), head, &main));
+
+        assert_eq!(Some(ptr(7, 0)), common_ancestor(&main, &fork));
+        assert_eq!(Some(ptr(7, 0)), common_ancestor(&main, &fork[3..]));
+        assert_eq!(None, common_ancestor(&main[..2], &fork));
+    }
+}
@@ -1,4 +1,5 @@
 mod connection;
+mod entity_changes;
 mod server;
 
 pub use self::server::SubscriptionServer;
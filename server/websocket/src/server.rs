@@ -1,8 +1,10 @@
 use graph::{
+    components::server::tenant::Tenants,
     data::query::QueryTarget,
     prelude::{SubscriptionServer as SubscriptionServerTrait, *},
 };
-use http::header::{ACCESS_CONTROL_ALLOW_ORIGIN, CONTENT_TYPE};
+use graph_server_http::GraphQLServiceMetrics;
+use http::header::{ACCESS_CONTROL_ALLOW_ORIGIN, AUTHORIZATION, CONTENT_TYPE};
 use http::{HeaderValue, Response, StatusCode};
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::sync::Mutex;
@@ -10,13 +12,24 @@ use tokio::net::TcpListener;
 use tokio_tungstenite::accept_hdr_async;
 use tokio_tungstenite::tungstenite::handshake::server::Request;
 
-use crate::connection::GraphQlConnection;
+use crate::connection::{GraphQlConnection, TenantCheck};
+use crate::entity_changes::{
+    cursor_from_query, Cursor, EntityChangeConnection, ENTITY_CHANGES_PROTOCOL,
+};
+
+/// The protocol that a client asked for when it connected
+enum Protocol {
+    GraphQl,
+    EntityChanges(Option<Cursor>),
+}
 
 /// A GraphQL subscription server based on Hyper / Websockets.
 pub struct SubscriptionServer<Q, S> {
     logger: Logger,
     graphql_runner: Arc<Q>,
     store: Arc<S>,
+    tenants: Arc<Tenants>,
+    metrics: Arc<GraphQLServiceMetrics>,
 }
 
 impl<Q, S> SubscriptionServer<Q, S>
@@ -24,11 +37,19 @@ where
     Q: GraphQlRunner,
     S: QueryStoreManager,
 {
-    pub fn new(logger: &Logger, graphql_runner: Arc<Q>, store: Arc<S>) -> Self {
+    pub fn new(
+        logger: &Logger,
+        graphql_runner: Arc<Q>,
+        store: Arc<S>,
+        tenants: Arc<Tenants>,
+        metrics: Arc<GraphQLServiceMetrics>,
+    ) -> Self {
         SubscriptionServer {
             logger: logger.new(o!("component" => "SubscriptionServer")),
             graphql_runner,
             store,
+            tenants,
+            metrics,
         }
     }
 
@@ -114,12 +135,37 @@ where
             let logger2 = self.logger.clone();
             let graphql_runner = self.graphql_runner.clone();
             let store = self.store.clone();
+            let feed_store = self.store.clone();
+            let tenants = self.tenants.clone();
+            let metrics = self.metrics.clone();
+            let connection_metrics = self.metrics.clone();
 
             // Subgraph that the request is resolved to (if any)
             let subgraph_id = Arc::new(Mutex::new(None));
             let accept_subgraph_id = subgraph_id.clone();
+            let protocol = Arc::new(Mutex::new(Protocol::GraphQl));
+            let accept_protocol = protocol.clone();
+            let tenant = Arc::new(Mutex::new(None));
+            let accept_tenant = tenant.clone();
 
             accept_hdr_async(stream, move |request: &Request, mut response: Response<()>| {
+                // Only let known tenants connect, and count the connection
+                // against their request rate
+                let authorization = request
+                    .headers()
+                    .get(AUTHORIZATION)
+                    .map(|value| value.to_str().unwrap_or_default());
+                *accept_tenant.lock().unwrap() =
+                    tenants.authenticate(authorization).map_err(|rejection| {
+                        metrics.observe_rejected_query(rejection.tenant(), rejection.reason());
+                        Response::builder()
+                            .status(rejection.status_code())
+                            .header(ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+                            .header(CONTENT_TYPE, "text/plain")
+                            .body(Some(rejection.to_string()))
+                            .unwrap()
+                    })?;
+
                 // Try to obtain the subgraph ID or name from the URL path.
                 // Return a 404 if the URL path contains no name/ID segment.
                 let path = request.uri().path();
@@ -171,10 +217,34 @@ where
                             .unwrap());
                     }
 
+                // Clients that ask for the entity change protocol get a
+                // change feed instead of GraphQL subscriptions
+                let wants_changes = request
+                    .headers()
+                    .get_all("Sec-WebSocket-Protocol")
+                    .iter()
+                    .filter_map(|value| value.to_str().ok())
+                    .flat_map(|value| value.split(','))
+                    .any(|protocol| protocol.trim() == ENTITY_CHANGES_PROTOCOL);
+                let (protocol, header) = if wants_changes {
+                    let cursor = cursor_from_query(request.uri().query()).map_err(|status| {
+                        Response::builder()
+                            .status(status)
+                            .header(ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+                            .header(CONTENT_TYPE, "text/plain")
+                            .body(Some("invalid cursor".to_string()))
+                            .unwrap()
+                    })?;
+                    (Protocol::EntityChanges(cursor), ENTITY_CHANGES_PROTOCOL)
+                } else {
+                    (Protocol::GraphQl, "graphql-ws")
+                };
+
                 *accept_subgraph_id.lock().unwrap() = Some(state.id);
+                *accept_protocol.lock().unwrap() = protocol;
                 response.headers_mut().insert(
                     "Sec-WebSocket-Protocol",
-                    HeaderValue::from_static("graphql-ws"),
+                    HeaderValue::from_static(header),
                 );
                 Ok(response)
             })
@@ -183,16 +253,43 @@ where
                     Ok(ws_stream) => {
                         // Obtain the subgraph ID or name that we resolved the request to
                         let subgraph_id = subgraph_id.lock().unwrap().clone().unwrap();
+                        let protocol =
+                            std::mem::replace(&mut *protocol.lock().unwrap(), Protocol::GraphQl);
 
-                        // Spawn a GraphQL over WebSocket connection
-                        let service = GraphQlConnection::new(
-                            &logger2,
-                            subgraph_id,
-                            ws_stream,
-                            graphql_runner.clone(),
-                        );
+                        match protocol {
+                            Protocol::GraphQl => {
+                                // Spawn a GraphQL over WebSocket connection
+                                let service = GraphQlConnection::new(
+                                    &logger2,
+                                    subgraph_id,
+                                    ws_stream,
+                                    graphql_runner.clone(),
+                                    TenantCheck::new(
+                                        tenant.lock().unwrap().take(),
+                                        connection_metrics,
+                                    ),
+                                );
 
-                        graph::spawn_allow_panic(service.into_future().compat());
+                                graph::spawn_allow_panic(service.into_future().compat());
+                            }
+                            Protocol::EntityChanges(cursor) => {
+                                let target = QueryTarget::Deployment(subgraph_id);
+                                match feed_store.query_store(target, true).await {
+                                    Ok(query_store) => {
+                                        let service = EntityChangeConnection::new(
+                                            &logger2,
+                                            query_store,
+                                            ws_stream,
+                                            cursor,
+                                        );
+                                        graph::spawn_allow_panic(service.run());
+                                    }
+                                    Err(e) => {
+                                        trace!(logger2, "Failed to open entity change feed: {}", e)
+                                    }
+                                }
+                            }
+                        }
                     }
                     Err(e) => {
                         // We gracefully skip over failed connection attempts rather
@@ -0,0 +1,379 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+use graph::blockchain::{
+    Block, ChainHeadUpdateListener as ChainHeadUpdateListenerTrait, ChainHeadUpdateStream,
+    ChainIdentifier,
+};
+use graph::components::store::BlockStore as BlockStoreTrait;
+use graph::components::transaction_receipt::LightTransactionReceipt;
+use graph::ensure;
+use graph::parking_lot::RwLock;
+use graph::prelude::tokio::sync::watch;
+use graph::prelude::web3::types::{Address, TransactionReceipt, H256};
+use graph::prelude::*;
+
+/// Notify subscribers when the head of a chain changes. Chain stores call
+/// `notify` directly whenever they move their head pointer
+#[derive(Default)]
+pub struct ChainHeadUpdateListener {
+    /// Update watchers keyed by network.
+    watchers: RwLock<BTreeMap<String, watch::Sender<()>>>,
+}
+
+impl ChainHeadUpdateListener {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn notify(&self, network: &str) {
+        if let Some(sender) = self.watchers.read().get(network) {
+            // There is nothing to do if nobody is listening
+            sender.send(()).ok();
+        }
+    }
+}
+
+impl ChainHeadUpdateListenerTrait for ChainHeadUpdateListener {
+    fn subscribe(&self, network_name: String, logger: Logger) -> ChainHeadUpdateStream {
+        debug!(logger, "subscribing to chain head updates");
+
+        let update_receiver = self
+            .watchers
+            .write()
+            .entry(network_name)
+            .or_insert_with(|| watch::channel(()).0)
+            .subscribe();
+
+        Box::new(futures03::stream::unfold(
+            update_receiver,
+            |mut update_receiver| {
+                async move {
+                    // The sender lives as long as the listener, and the stream
+                    // ends with it
+                    update_receiver.changed().await.ok()?;
+                    Some(((), update_receiver))
+                }
+                .boxed()
+            },
+        ))
+    }
+}
+
+struct StoredBlock {
+    number: BlockNumber,
+    parent_hash: Option<H256>,
+    data: serde_json::Value,
+}
+
+#[derive(Default)]
+struct Inner {
+    blocks: HashMap<H256, StoredBlock>,
+    head: Option<BlockPtr>,
+    call_cache: HashMap<(Address, Vec<u8>, H256), Vec<u8>>,
+}
+
+/// The block cache for one chain
+pub struct ChainStore {
+    chain: String,
+    genesis_block_ptr: BlockPtr,
+    inner: RwLock<Inner>,
+    chain_head_update_listener: Arc<ChainHeadUpdateListener>,
+}
+
+impl ChainStore {
+    pub fn new(
+        chain: String,
+        ident: &ChainIdentifier,
+        chain_head_update_listener: Arc<ChainHeadUpdateListener>,
+    ) -> Self {
+        ChainStore {
+            chain,
+            genesis_block_ptr: BlockPtr::new(ident.genesis_block_hash.clone(), 0),
+            inner: RwLock::new(Inner::default()),
+            chain_head_update_listener,
+        }
+    }
+
+    /// Blocks are never ingested by another node, so every chain store is
+    /// ingestible
+    pub fn is_ingestible(&self) -> bool {
+        true
+    }
+
+    /// Return `block_ptr` followed by up to `count` of its ancestors. The
+    /// list ends early if a block on the way is not in the cache
+    pub fn ancestors(&self, block_ptr: &BlockPtr, count: BlockNumber) -> Vec<BlockPtr> {
+        let inner = self.inner.read();
+        let mut ancestors = vec![block_ptr.clone()];
+        let mut hash = block_ptr.hash_as_h256();
+        for offset in 1..=count {
+            match inner.blocks.get(&hash).and_then(|block| block.parent_hash) {
+                Some(parent_hash) => {
+                    ancestors.push(BlockPtr::from((parent_hash, block_ptr.number - offset)));
+                    hash = parent_hash;
+                }
+                None => break,
+            }
+        }
+        ancestors
+    }
+
+    fn insert(&self, block: &dyn Block, overwrite: bool) -> Result<(), Error> {
+        let hash = block.ptr().hash_as_h256();
+        let stored = StoredBlock {
+            number: block.number(),
+            parent_hash: block.parent_ptr().map(|ptr| ptr.hash_as_h256()),
+            data: block.data()?,
+        };
+        let mut inner = self.inner.write();
+        if overwrite || !inner.blocks.contains_key(&hash) {
+            inner.blocks.insert(hash, stored);
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl graph::components::store::ChainStore for ChainStore {
+    fn genesis_block_ptr(&self) -> Result<BlockPtr, Error> {
+        Ok(self.genesis_block_ptr.clone())
+    }
+
+    async fn upsert_block(&self, block: Arc<dyn Block>) -> Result<(), Error> {
+        self.insert(block.as_ref(), true)
+    }
+
+    fn upsert_light_blocks(&self, blocks: &[&dyn Block]) -> Result<(), Error> {
+        for block in blocks {
+            self.insert(*block, false)?;
+        }
+        Ok(())
+    }
+
+    async fn attempt_chain_head_update(
+        self: Arc<Self>,
+        ancestor_count: BlockNumber,
+    ) -> Result<Option<H256>, Error> {
+        {
+            let mut inner = self.inner.write();
+            let head_number = inner.head.as_ref().map(|head| head.number).unwrap_or(-1);
+
+            // The candidate is the block with the highest number above the
+            // current head, using the hash to break ties deterministically
+            let candidate = inner
+                .blocks
+                .iter()
+                .filter(|(_, block)| block.number > head_number)
+                .max_by(|(hash1, block1), (hash2, block2)| {
+                    block1.number.cmp(&block2.number).then(hash1.cmp(hash2))
+                })
+                .map(|(hash, block)| (*hash, block.number));
+            let (hash, number) = match candidate {
+                Some(candidate) => candidate,
+                None => return Ok(None),
+            };
+
+            // Make sure all the ancestors we need are there
+            let earliest = (number - ancestor_count).max(0);
+            let mut current = hash;
+            while let Some(block) = inner.blocks.get(&current) {
+                if block.number <= earliest {
+                    break;
+                }
+                match block.parent_hash {
+                    Some(parent_hash) if inner.blocks.contains_key(&parent_hash) => {
+                        current = parent_hash
+                    }
+                    Some(parent_hash) => return Ok(Some(parent_hash)),
+                    None => break,
+                }
+            }
+
+            inner.head = Some(BlockPtr::from((hash, number)));
+        }
+        self.chain_head_update_listener.notify(&self.chain);
+        Ok(None)
+    }
+
+    fn chain_head_ptr(&self) -> Result<Option<BlockPtr>, Error> {
+        Ok(self.inner.read().head.clone())
+    }
+
+    fn blocks(&self, hashes: &[H256]) -> Result<Vec<serde_json::Value>, Error> {
+        let inner = self.inner.read();
+        Ok(hashes
+            .iter()
+            .filter_map(|hash| inner.blocks.get(hash))
+            .map(|block| {
+                // Return the contents of the 'block' field if it exists,
+                // otherwise assume the whole data is what should be in it
+                block
+                    .data
+                    .get("block")
+                    .cloned()
+                    .unwrap_or_else(|| block.data.clone())
+            })
+            .collect())
+    }
+
+    fn ancestor_block(
+        &self,
+        block_ptr: BlockPtr,
+        offset: BlockNumber,
+    ) -> Result<Option<serde_json::Value>, Error> {
+        ensure!(
+            block_ptr.number >= offset,
+            "block offset {} for block `{}` points to before genesis block",
+            offset,
+            block_ptr.hash_hex()
+        );
+
+        let inner = self.inner.read();
+        let mut hash = block_ptr.hash_as_h256();
+        for _ in 0..offset {
+            match inner.blocks.get(&hash).and_then(|block| block.parent_hash) {
+                Some(parent_hash) => hash = parent_hash,
+                None => return Ok(None),
+            }
+        }
+        Ok(inner.blocks.get(&hash).map(|block| block.data.clone()))
+    }
+
+    fn cleanup_cached_blocks(
+        &self,
+        _ancestor_count: BlockNumber,
+    ) -> Result<Option<(BlockNumber, usize)>, Error> {
+        Ok(None)
+    }
+
+    fn block_hashes_by_block_number(&self, number: BlockNumber) -> Result<Vec<H256>, Error> {
+        Ok(self
+            .inner
+            .read()
+            .blocks
+            .iter()
+            .filter(|(_, block)| block.number == number)
+            .map(|(hash, _)| *hash)
+            .collect())
+    }
+
+    fn confirm_block_hash(&self, number: BlockNumber, hash: &H256) -> Result<usize, Error> {
+        let mut inner = self.inner.write();
+        let count = inner.blocks.len();
+        inner
+            .blocks
+            .retain(|other, block| block.number != number || other == hash);
+        Ok(count - inner.blocks.len())
+    }
+
+    fn block_number(&self, block_hash: H256) -> Result<Option<(String, BlockNumber)>, StoreError> {
+        Ok(self
+            .inner
+            .read()
+            .blocks
+            .get(&block_hash)
+            .map(|block| (self.chain.clone(), block.number)))
+    }
+
+    async fn transaction_receipts_in_block(
+        &self,
+        block_hash: &H256,
+    ) -> Result<Vec<LightTransactionReceipt>, StoreError> {
+        let receipts = match self
+            .inner
+            .read()
+            .blocks
+            .get(block_hash)
+            .and_then(|block| block.data.get("transaction_receipts"))
+        {
+            Some(receipts) => receipts.clone(),
+            None => return Ok(vec![]),
+        };
+        let receipts: Vec<TransactionReceipt> =
+            serde_json::from_value(receipts).map_err(|e| StoreError::Unknown(e.into()))?;
+        Ok(receipts
+            .into_iter()
+            .map(LightTransactionReceipt::from)
+            .collect())
+    }
+}
+
+impl EthereumCallCache for ChainStore {
+    fn get_call(
+        &self,
+        contract_address: Address,
+        encoded_call: &[u8],
+        block: BlockPtr,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        let key = (
+            contract_address,
+            encoded_call.to_vec(),
+            block.hash_as_h256(),
+        );
+        Ok(self.inner.read().call_cache.get(&key).cloned())
+    }
+
+    fn set_call(
+        &self,
+        contract_address: Address,
+        encoded_call: &[u8],
+        block: BlockPtr,
+        return_value: &[u8],
+    ) -> Result<(), Error> {
+        let key = (
+            contract_address,
+            encoded_call.to_vec(),
+            block.hash_as_h256(),
+        );
+        self.inner
+            .write()
+            .call_cache
+            .insert(key, return_value.to_vec());
+        Ok(())
+    }
+}
+
+/// The chain stores for all configured networks
+pub struct BlockStore {
+    chain_stores: HashMap<String, Arc<ChainStore>>,
+}
+
+impl BlockStore {
+    pub fn new(
+        networks: Vec<(String, Vec<ChainIdentifier>)>,
+        chain_head_update_listener: Arc<ChainHeadUpdateListener>,
+    ) -> Result<Self, Error> {
+        let chain_stores = networks
+            .into_iter()
+            .map(|(network, idents)| {
+                let ident = idents.first().ok_or_else(|| {
+                    anyhow!("no chain identifier was provided for network `{}`", network)
+                })?;
+                let store = ChainStore::new(
+                    network.clone(),
+                    ident,
+                    chain_head_update_listener.cheap_clone(),
+                );
+                Ok((network, Arc::new(store)))
+            })
+            .collect::<Result<_, Error>>()?;
+        Ok(BlockStore { chain_stores })
+    }
+
+    /// The names of all networks and the head of their chain
+    pub fn chain_heads(&self) -> Vec<(String, Option<BlockPtr>)> {
+        self.chain_stores
+            .iter()
+            .map(|(network, store)| (network.clone(), store.inner.read().head.clone()))
+            .collect()
+    }
+}
+
+impl BlockStoreTrait for BlockStore {
+    type ChainStore = ChainStore;
+
+    fn chain_store(&self, network: &str) -> Option<Arc<Self::ChainStore>> {
+        self.chain_stores.get(network).cloned()
+    }
+}
@@ -0,0 +1,393 @@
+//! The state of one deployment: its metadata, errors, dynamic data sources
+//! and entities, together with the operations that the `WritableStore`
+//! performs on them
+use std::collections::BTreeSet;
+use std::iter::FromIterator;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use graph::components::store::{DeploymentLocator, EntityType, StoredDynamicDataSource};
+use graph::data::subgraph::schema::{SubgraphError, SubgraphHealth};
+use graph::prelude::{
+    anyhow, ApiSchema, BlockNumber, BlockPtr, DeploymentHash, DeploymentState, EntityChange,
+    EntityModification, Schema, StoreError, StoreEvent, SubgraphDeploymentEntity, BLOCK_NUMBER_MAX,
+};
+use graph_graphql::prelude::api_schema;
+
+use crate::entities::Entities;
+
+/// A dynamic data source together with the block that created it
+#[derive(Clone)]
+pub(crate) struct DynamicDataSource {
+    data: StoredDynamicDataSource,
+    block: BlockNumber,
+}
+
+impl DynamicDataSource {
+    fn new(ds: &StoredDynamicDataSource, block: BlockNumber) -> Self {
+        DynamicDataSource {
+            data: ds.clone(),
+            block,
+        }
+    }
+
+    fn stored(&self) -> StoredDynamicDataSource {
+        self.data.clone()
+    }
+}
+
+pub(crate) struct Deployment {
+    pub locator: DeploymentLocator,
+    pub schema: Arc<Schema>,
+    pub api_schema: Arc<ApiSchema>,
+    pub network: String,
+    pub description: Option<String>,
+    pub repository: Option<String>,
+    pub created_at: u64,
+
+    pub block_ptr: Option<BlockPtr>,
+    pub firehose_cursor: Option<String>,
+    pub earliest_block: Option<BlockPtr>,
+    /// The deployment we graft onto and the block at which we graft
+    pub graft: Option<(DeploymentHash, BlockPtr)>,
+
+    pub synced: bool,
+    pub health: SubgraphHealth,
+    pub fatal_error: Option<SubgraphError>,
+    pub non_fatal_errors: Vec<SubgraphError>,
+
+    pub reorg_count: i32,
+    pub current_reorg_depth: i32,
+    pub max_reorg_depth: i32,
+
+    pub dynamic_data_sources: Vec<DynamicDataSource>,
+    pub entities: Entities,
+}
+
+impl Deployment {
+    pub fn new(
+        locator: DeploymentLocator,
+        schema: &Schema,
+        deployment: SubgraphDeploymentEntity,
+        network: String,
+    ) -> Result<Self, StoreError> {
+        // Generate an API schema for the subgraph and make sure all types in the
+        // API schema have a @subgraphId directive as well
+        let mut api = schema.clone();
+        api.document = api_schema(&schema.document).map_err(|e| StoreError::Unknown(e.into()))?;
+        api.add_subgraph_id_directives(locator.hash.clone());
+
+        let graft = match (deployment.graft_base, deployment.graft_block) {
+            (Some(base), Some(block)) => Some((base, block)),
+            _ => None,
+        };
+        let created_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+
+        Ok(Deployment {
+            locator,
+            schema: Arc::new(schema.clone()),
+            api_schema: Arc::new(ApiSchema::from_api_schema(api)?),
+            network,
+            description: deployment.manifest.description,
+            repository: deployment.manifest.repository,
+            created_at,
+            block_ptr: deployment.latest_block,
+            firehose_cursor: None,
+            earliest_block: deployment.earliest_block,
+            graft,
+            synced: deployment.synced,
+            health: deployment.health,
+            fatal_error: deployment.fatal_error,
+            non_fatal_errors: deployment.non_fatal_errors,
+            reorg_count: deployment.reorg_count,
+            current_reorg_depth: deployment.current_reorg_depth,
+            max_reorg_depth: deployment.max_reorg_depth,
+            dynamic_data_sources: vec![],
+            entities: Entities::default(),
+        })
+    }
+
+    pub fn hash(&self) -> &DeploymentHash {
+        &self.locator.hash
+    }
+
+    /// The graft that still needs to be performed, i.e., the graft for a
+    /// deployment that has not processed any blocks yet
+    pub fn graft_pending(&self) -> Option<&(DeploymentHash, BlockPtr)> {
+        match self.block_ptr {
+            Some(_) => None,
+            None => self.graft.as_ref(),
+        }
+    }
+
+    /// Initialize this deployment from `base`, which it is grafted onto at
+    /// `block`, by copying everything up to and including `block`
+    pub fn copy_from(&mut self, base: &Deployment, block: &BlockPtr) {
+        self.entities = base.entities.copy_until(block.number);
+        self.dynamic_data_sources = base
+            .dynamic_data_sources
+            .iter()
+            .filter(|ds| ds.block <= block.number)
+            .cloned()
+            .collect();
+        self.non_fatal_errors = base
+            .non_fatal_errors
+            .iter()
+            .filter(|error| error_block(error) <= block.number)
+            .map(|error| SubgraphError {
+                subgraph_id: self.locator.hash.clone(),
+                ..error.clone()
+            })
+            .collect();
+        self.block_ptr = Some(block.clone());
+    }
+
+    pub fn state(&self) -> DeploymentState {
+        DeploymentState {
+            id: self.locator.hash.clone(),
+            reorg_count: self.reorg_count as u32,
+            max_reorg_depth: self.max_reorg_depth as u32,
+            latest_ethereum_block_number: self
+                .block_ptr
+                .as_ref()
+                .map(|ptr| ptr.number)
+                .unwrap_or(0),
+            // The in-memory store never prunes history
+            earliest_block_number: 0,
+        }
+    }
+
+    pub fn has_non_fatal_errors(&self, block: Option<BlockNumber>) -> bool {
+        let block = match block.or_else(|| self.block_ptr.as_ref().map(|ptr| ptr.number)) {
+            Some(block) => block,
+            None => return false,
+        };
+        self.non_fatal_errors
+            .iter()
+            .any(|error| error.deterministic && error_block(error) <= block)
+    }
+
+    /// Set the health of the deployment depending on whether there are
+    /// non-fatal errors as of `block`. Has no effect on failed deployments
+    fn check_health(&mut self, block: BlockNumber) {
+        if self.health.is_failed() {
+            return;
+        }
+        self.health = if self.has_non_fatal_errors(Some(block)) {
+            SubgraphHealth::Unhealthy
+        } else {
+            SubgraphHealth::Healthy
+        };
+    }
+
+    pub fn transact_block_operations(
+        &mut self,
+        block_ptr_to: BlockPtr,
+        firehose_cursor: Option<String>,
+        mods: &[EntityModification],
+        data_sources: &[StoredDynamicDataSource],
+        deterministic_errors: Vec<SubgraphError>,
+    ) -> Result<StoreEvent, StoreError> {
+        // All operations should apply only to data or metadata for this subgraph
+        if mods
+            .iter()
+            .map(|modification| modification.entity_key())
+            .any(|key| &key.subgraph_id != self.hash())
+        {
+            panic!(
+                "transact_block_operations must affect only entities \
+                 in the subgraph or in the subgraph of subgraphs"
+            );
+        }
+
+        // Asserts that the processing direction is forward
+        if let Some(block_ptr_from) = &self.block_ptr {
+            if block_ptr_from.number >= block_ptr_to.number {
+                return Err(StoreError::DuplicateBlockProcessing(
+                    self.hash().clone(),
+                    block_ptr_to.number,
+                ));
+            }
+        }
+
+        for modification in mods {
+            if let EntityModification::Insert { key, .. } = modification {
+                self.check_interface_entity_uniqueness(&key.entity_type, &key.entity_id)?;
+            }
+        }
+
+        // If this fails, `apply` has already undone its changes
+        self.entities.apply(mods, block_ptr_to.number)?;
+
+        self.dynamic_data_sources.extend(
+            data_sources
+                .iter()
+                .map(|ds| DynamicDataSource::new(ds, block_ptr_to.number)),
+        );
+
+        if !deterministic_errors.is_empty() {
+            self.non_fatal_errors.extend(deterministic_errors);
+            self.check_health(block_ptr_to.number);
+        }
+
+        self.block_ptr = Some(block_ptr_to);
+        self.current_reorg_depth = 0;
+
+        if let Some(cursor) = firehose_cursor {
+            if cursor != "" {
+                self.firehose_cursor = Some(cursor);
+            }
+        }
+
+        Ok(mods.iter().collect())
+    }
+
+    /// Make sure that no other entity type that shares an interface with
+    /// `entity_type` has an entity with `id`. Otherwise, we could not tell
+    /// which of the entities an interface reference points to
+    fn check_interface_entity_uniqueness(
+        &self,
+        entity_type: &EntityType,
+        id: &str,
+    ) -> Result<(), StoreError> {
+        let types_for_interface = &self.schema.types_for_interface;
+        let types_with_shared_interface = BTreeSet::from_iter(
+            self.schema
+                .interfaces_for_type
+                .get(entity_type)
+                .into_iter()
+                .flatten()
+                .filter_map(|interface| types_for_interface.get(&EntityType::from(interface)))
+                .flatten()
+                .map(EntityType::from)
+                .filter(|type_name| type_name != entity_type),
+        );
+
+        for other in types_with_shared_interface {
+            if self.entities.find(&other, id, BLOCK_NUMBER_MAX).is_some() {
+                return Err(StoreError::ConflictingId(
+                    entity_type.to_string(),
+                    id.to_owned(),
+                    other.to_string(),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn revert_block_operations(
+        &mut self,
+        block_ptr_to: BlockPtr,
+    ) -> Result<StoreEvent, StoreError> {
+        let block_ptr_from = match &self.block_ptr {
+            Some(ptr) => ptr.clone(),
+            None => {
+                return Err(StoreError::Unknown(anyhow!(
+                    "can not revert deployment {} since it has not processed any blocks",
+                    self.hash()
+                )))
+            }
+        };
+        // Sanity check on block numbers
+        if block_ptr_from.number != block_ptr_to.number + 1 {
+            panic!("revert_block_operations must revert a single block only");
+        }
+
+        // Don't revert past a graft point
+        if let Some((_, graft_block)) = &self.graft {
+            if graft_block.number > block_ptr_to.number {
+                return Err(anyhow!(
+                    "Can not revert subgraph `{}` to block {} as it was \
+                    grafted at block {} and reverting past a graft point \
+                    is not possible",
+                    self.hash(),
+                    block_ptr_to.number,
+                    graft_block.number
+                )
+                .into());
+            }
+        }
+
+        let changed = self.entities.revert(block_ptr_from.number);
+        self.dynamic_data_sources
+            .retain(|ds| ds.block < block_ptr_from.number);
+        self.non_fatal_errors
+            .retain(|error| error_block(error) < block_ptr_from.number);
+        self.check_health(block_ptr_to.number);
+
+        self.block_ptr = Some(block_ptr_to);
+        self.reorg_count += 1;
+        self.current_reorg_depth += 1;
+        self.max_reorg_depth = self.max_reorg_depth.max(self.current_reorg_depth);
+
+        let changes = changed
+            .into_iter()
+            .map(|entity_type| EntityChange::Data {
+                subgraph_id: self.hash().clone(),
+                entity_type,
+            })
+            .collect();
+        Ok(StoreEvent::new(changes))
+    }
+
+    pub fn fail(&mut self, error: SubgraphError) {
+        self.health = SubgraphHealth::Failed;
+        self.fatal_error = Some(error);
+    }
+
+    /// Revert the block in which a deterministic error happened if it is
+    /// the current head of the deployment and unfail the deployment
+    pub fn unfail_deterministic_error(
+        &mut self,
+        current_ptr: &BlockPtr,
+        parent_ptr: &BlockPtr,
+    ) -> Result<(), StoreError> {
+        // We'll only unfail subgraphs that had fatal errors
+        let error_ptr = match &self.fatal_error {
+            Some(error) if error.deterministic => error.block_ptr.clone(),
+            _ => return Ok(()),
+        };
+
+        // The error happened for the current deployment head; revert
+        // everything to the parent block
+        if error_ptr.map_or(false, |ptr| ptr.hash == current_ptr.hash) {
+            self.revert_block_operations(parent_ptr.clone())?;
+            self.health = if self.has_non_fatal_errors(Some(parent_ptr.number)) {
+                SubgraphHealth::Unhealthy
+            } else {
+                SubgraphHealth::Healthy
+            };
+            self.fatal_error = None;
+        }
+        Ok(())
+    }
+
+    /// Unfail the deployment if its head has moved past the block at which
+    /// a non-deterministic error happened
+    pub fn unfail_non_deterministic_error(&mut self, current_ptr: &BlockPtr) {
+        let error_block = match &self.fatal_error {
+            Some(error) if !error.deterministic => error_block(error),
+            _ => return,
+        };
+        if current_ptr.number >= error_block {
+            self.health = SubgraphHealth::Healthy;
+            self.fatal_error = None;
+        }
+    }
+
+    pub fn load_dynamic_data_sources(&self) -> Vec<StoredDynamicDataSource> {
+        self.dynamic_data_sources
+            .iter()
+            .map(DynamicDataSource::stored)
+            .collect()
+    }
+}
+
+/// The block at which `error` happened. Errors without a block happened
+/// before any blocks were processed
+fn error_block(error: &SubgraphError) -> BlockNumber {
+    error.block_ptr.as_ref().map(|ptr| ptr.number).unwrap_or(0)
+}
@@ -0,0 +1,275 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use graph::components::store::EntityType;
+use graph::constraint_violation;
+use graph::data::subgraph::schema::POI_OBJECT;
+use graph::prelude::{
+    BlockNumber, BlockSpan, Entity, EntityChangeKind, EntityModification, EntityVersionChange,
+    StoreError, BLOCK_NUMBER_MAX,
+};
+
+/// One version of an entity. The version is current for all blocks in the
+/// half-open range `[start, end)`; for the latest version of an entity that
+/// has not been deleted, `end` is `BLOCK_NUMBER_MAX`. This is the same
+/// layout as the `block_range` column in Postgres
+#[derive(Clone, Debug)]
+pub(crate) struct Version {
+    pub start: BlockNumber,
+    pub end: BlockNumber,
+    pub data: Entity,
+}
+
+impl Version {
+    pub fn is_current_at(&self, block: BlockNumber) -> bool {
+        self.start <= block && (block < self.end || self.is_open())
+    }
+
+    pub fn overlaps(&self, span: &BlockSpan) -> bool {
+        self.start <= span.to && span.from < self.end
+    }
+
+    fn is_open(&self) -> bool {
+        self.end == BLOCK_NUMBER_MAX
+    }
+}
+
+/// All versions of all entities of one deployment. Versions of an entity
+/// are kept in the order in which they were written, and their block ranges
+/// never overlap
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Entities {
+    versions: BTreeMap<EntityType, BTreeMap<String, Vec<Version>>>,
+    /// The entities that each block changed, so that reverting blocks only
+    /// needs to look at those entities
+    changed: BTreeMap<BlockNumber, BTreeSet<(EntityType, String)>>,
+}
+
+impl Entities {
+    /// Find the version of the entity that was current at `block`
+    pub fn find(&self, entity_type: &EntityType, id: &str, block: BlockNumber) -> Option<&Entity> {
+        self.versions
+            .get(entity_type)?
+            .get(id)?
+            .iter()
+            .find(|version| version.is_current_at(block))
+            .map(|version| &version.data)
+    }
+
+    /// All versions of entities of type `entity_type`, ordered by id and
+    /// then by block range
+    pub fn versions<'a>(
+        &'a self,
+        entity_type: &EntityType,
+    ) -> impl Iterator<Item = (&'a str, &'a Version)> + 'a {
+        self.versions
+            .get(entity_type)
+            .into_iter()
+            .flat_map(|entities| entities.iter())
+            .flat_map(|(id, versions)| versions.iter().map(move |version| (id.as_str(), version)))
+    }
+
+    /// The entities of type `entity_type` as of `block`, ordered by id
+    pub fn current<'a>(
+        &'a self,
+        entity_type: &EntityType,
+        block: BlockNumber,
+    ) -> impl Iterator<Item = &'a Entity> + 'a {
+        self.versions(entity_type)
+            .filter(move |(_, version)| version.is_current_at(block))
+            .map(|(_, version)| &version.data)
+    }
+
+    /// The number of entities as of `block`, not counting the entities for
+    /// the proof of indexing
+    pub fn count(&self, block: BlockNumber) -> usize {
+        self.versions
+            .iter()
+            .filter(|(entity_type, _)| *entity_type != &*POI_OBJECT)
+            .flat_map(|(_, entities)| entities.values())
+            .filter(|versions| versions.iter().any(|version| version.is_current_at(block)))
+            .count()
+    }
+
+    /// For each entity type, the number of entities and the number of
+    /// versions of all of them, including ones that were deleted
+    pub fn stats(&self) -> impl Iterator<Item = (&EntityType, usize, usize)> + '_ {
+        self.versions.iter().map(|(entity_type, entities)| {
+            let versions = entities.values().map(|versions| versions.len()).sum();
+            (entity_type, entities.len(), versions)
+        })
+    }
+
+    /// Apply the modifications that the block `block` made. Existing
+    /// versions are ended at `block`, and new versions start there. The
+    /// modifications are applied in place; if one of them fails, the ones
+    /// that were already applied are reverted so that the entities are as
+    /// they were before the call
+    pub fn apply(
+        &mut self,
+        mods: &[EntityModification],
+        block: BlockNumber,
+    ) -> Result<(), StoreError> {
+        let res = self.apply_in_place(mods, block);
+        if res.is_err() {
+            self.revert(block);
+        }
+        res
+    }
+
+    fn apply_in_place(
+        &mut self,
+        mods: &[EntityModification],
+        block: BlockNumber,
+    ) -> Result<(), StoreError> {
+        for modification in mods {
+            let key = modification.entity_key();
+            self.changed
+                .entry(block)
+                .or_default()
+                .insert((key.entity_type.clone(), key.entity_id.clone()));
+            let versions = self
+                .versions
+                .entry(key.entity_type.clone())
+                .or_default()
+                .entry(key.entity_id.clone())
+                .or_default();
+            match modification {
+                EntityModification::Insert { data, .. } => {
+                    if versions.last().map_or(false, Version::is_open) {
+                        return Err(constraint_violation!(
+                            "entity {}[{}] can not be inserted at block {} since it already exists",
+                            key.entity_type,
+                            key.entity_id,
+                            block
+                        ));
+                    }
+                    versions.push(Version {
+                        start: block,
+                        end: BLOCK_NUMBER_MAX,
+                        data: data.clone(),
+                    });
+                }
+                EntityModification::Overwrite { data, .. } => {
+                    Self::clamp(versions, block);
+                    versions.push(Version {
+                        start: block,
+                        end: BLOCK_NUMBER_MAX,
+                        data: data.clone(),
+                    });
+                }
+                EntityModification::Remove { .. } => Self::clamp(versions, block),
+            }
+        }
+        Ok(())
+    }
+
+    /// End the latest version in `versions` at `block`. A version that
+    /// would become empty by that is removed
+    fn clamp(versions: &mut Vec<Version>, block: BlockNumber) {
+        if let Some(version) = versions.last_mut() {
+            if version.is_open() {
+                if version.start >= block {
+                    versions.pop();
+                } else {
+                    version.end = block;
+                }
+            }
+        }
+    }
+
+    /// Undo all changes that were made at `block` or later and return the
+    /// entity types that were affected by that
+    pub fn revert(&mut self, block: BlockNumber) -> BTreeSet<EntityType> {
+        let keys: BTreeSet<_> = self
+            .changed
+            .split_off(&block)
+            .into_iter()
+            .flat_map(|(_, keys)| keys)
+            .collect();
+
+        let mut changed = BTreeSet::new();
+        for (entity_type, id) in keys {
+            let entities = match self.versions.get_mut(&entity_type) {
+                Some(entities) => entities,
+                None => continue,
+            };
+            if let Some(versions) = entities.get_mut(&id) {
+                let count = versions.len();
+                versions.retain(|version| version.start < block);
+                let mut touched = versions.len() != count;
+                for version in versions.iter_mut() {
+                    if version.end >= block && !version.is_open() {
+                        version.end = BLOCK_NUMBER_MAX;
+                        touched = true;
+                    }
+                }
+                if versions.is_empty() {
+                    entities.remove(&id);
+                }
+                if touched {
+                    changed.insert(entity_type);
+                }
+            }
+        }
+        changed
+    }
+
+    /// Return a copy of these entities as they were at `block`, with all
+    /// later changes removed. This is what a graft starts from
+    pub fn copy_until(&self, block: BlockNumber) -> Entities {
+        let mut copy = self.clone();
+        copy.revert(block + 1);
+        copy
+    }
+
+    /// Reconstruct the changes that the blocks in `span` made from the
+    /// versions of each entity. A block inserted an entity if it started a
+    /// version without ending an older one, it deleted the entity if it
+    /// ended a version without starting a new one, and it updated the
+    /// entity if it did both
+    pub fn changes(&self, span: &BlockSpan) -> Vec<EntityVersionChange> {
+        let in_span = |block: BlockNumber| span.from <= block && block <= span.to;
+
+        // Map (block, entity type, id) to the version that the block ended
+        // and the version that it created
+        let mut changes: BTreeMap<_, (Option<&Entity>, Option<&Entity>)> = BTreeMap::new();
+        for (entity_type, entities) in &self.versions {
+            if entity_type == &*POI_OBJECT {
+                continue;
+            }
+            for (id, versions) in entities {
+                for version in versions {
+                    if in_span(version.start) {
+                        changes
+                            .entry((version.start, entity_type, id))
+                            .or_default()
+                            .1 = Some(&version.data);
+                    }
+                    if !version.is_open() && in_span(version.end) {
+                        changes.entry((version.end, entity_type, id)).or_default().0 =
+                            Some(&version.data);
+                    }
+                }
+            }
+        }
+
+        changes
+            .into_iter()
+            .filter_map(|((block, entity_type, entity_id), versions)| {
+                let (kind, data) = match versions {
+                    (None, Some(created)) => (EntityChangeKind::Insert, created),
+                    (Some(_), Some(created)) => (EntityChangeKind::Update, created),
+                    (Some(ended), None) => (EntityChangeKind::Delete, ended),
+                    (None, None) => return None,
+                };
+                Some(EntityVersionChange {
+                    block,
+                    entity_type: entity_type.clone(),
+                    entity_id: entity_id.clone(),
+                    kind,
+                    data: data.clone(),
+                })
+            })
+            .collect()
+    }
+}
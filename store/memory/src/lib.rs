@@ -0,0 +1,22 @@
+//! This crate implements the store for all chain and subgraph data entirely
+//! in memory. It keeps the same block-range versioning of entities as the
+//! Postgres store, so that time-travel queries, reverts and the entity
+//! change feed behave the same, but nothing is persisted across restarts.
+//! It is meant for running `graph-node` without a database during
+//! development, and for tests that need a store
+//!
+//! The [Store] is the entry point; it is made of a [SubgraphStore] and a
+//! [BlockStore], just like the Postgres store.
+
+mod chain_store;
+mod deployment;
+mod entities;
+mod query;
+mod store;
+mod store_events;
+mod subgraph_store;
+
+pub use self::chain_store::{BlockStore, ChainHeadUpdateListener, ChainStore};
+pub use self::store::{QueryStore, Store};
+pub use self::store_events::SubscriptionManager;
+pub use self::subgraph_store::{SubgraphStore, WritableStore};
@@ -0,0 +1,602 @@
+//! Evaluation of `EntityQuery` and `AggregateQuery` against the entities of
+//! a deployment. The results are meant to be indistinguishable from what
+//! the relational queries in `graph-store-postgres` produce
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+
+use graph::components::store::EntityType;
+use graph::data::graphql::{DocumentExt, ObjectTypeExt};
+use graph::prelude::{
+    anyhow, r, s, AggregateFunction, AggregateQuery, BigDecimal, BigInt, BlockNumber, Entity,
+    EntityCollection, EntityCursor, EntityFilter, EntityLink, EntityOrder, EntityQuery,
+    EntityRange, ParentLink, QueryExecutionError, Schema, StoreError, Value, WindowAttribute,
+};
+
+use crate::entities::Entities;
+
+/// The name of the attribute that holds the id of the parent of an entity
+/// in the results of windowed queries
+const PARENT_ID: &str = "g$parent_id";
+
+const PRIMARY_KEY: &str = "id";
+
+/// How the rows of a query are sorted. Sorting by an attribute uses the id
+/// of entities as the tie breaker
+enum SortKey<'a> {
+    None,
+    /// Sort versions of entities by id and then by block range, which is
+    /// what we do for queries for the history of entities
+    Versions,
+    IdAsc,
+    IdDesc,
+    Asc(&'a str),
+    Desc(&'a str),
+}
+
+impl<'a> SortKey<'a> {
+    fn new(order: &'a EntityOrder, history: bool) -> Self {
+        if history {
+            return SortKey::Versions;
+        }
+        match order {
+            EntityOrder::Default => SortKey::IdAsc,
+            EntityOrder::Unordered => SortKey::None,
+            EntityOrder::Ascending(attr, _) if attr == PRIMARY_KEY => SortKey::IdAsc,
+            EntityOrder::Descending(attr, _) if attr == PRIMARY_KEY => SortKey::IdDesc,
+            EntityOrder::Ascending(attr, _) => SortKey::Asc(attr),
+            EntityOrder::Descending(attr, _) => SortKey::Desc(attr),
+        }
+    }
+
+    /// Check that the cursors in `range` can be used with this sort key
+    fn check_cursors(&self, range: &EntityRange) -> Result<(), QueryExecutionError> {
+        for cursor in range.after.iter().chain(range.before.iter()) {
+            let invalid =
+                |reason: String| QueryExecutionError::InvalidCursor(cursor.encode(), reason);
+            let attribute = match self {
+                SortKey::None => continue,
+                SortKey::Versions => {
+                    return Err(invalid(
+                        "cursors can not be used when listing the history of entities".to_owned(),
+                    ))
+                }
+                SortKey::IdAsc | SortKey::IdDesc => PRIMARY_KEY,
+                SortKey::Asc(attr) | SortKey::Desc(attr) => attr,
+            };
+            if cursor.attribute != attribute {
+                return Err(invalid(format!(
+                    "the cursor is for a list ordered by `{}`, not by `{}`",
+                    cursor.attribute, attribute
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Compare two rows, each given by the value of the sort attribute and
+    /// the id of the entity
+    fn compare(&self, a: (&Value, &str), b: (&Value, &str)) -> Ordering {
+        match self {
+            SortKey::None | SortKey::Versions => Ordering::Equal,
+            SortKey::IdAsc => a.1.cmp(b.1),
+            SortKey::IdDesc => b.1.cmp(a.1),
+            SortKey::Asc(_) => sort_cmp(a.0, b.0).then_with(|| a.1.cmp(b.1)),
+            SortKey::Desc(_) => sort_cmp(b.0, a.0).then_with(|| b.1.cmp(a.1)),
+        }
+    }
+
+    fn attribute(&self) -> Option<&str> {
+        match self {
+            SortKey::Asc(attr) | SortKey::Desc(attr) => Some(attr),
+            _ => None,
+        }
+    }
+}
+
+/// One entity version that is a candidate for the result of a query
+struct Row<'a> {
+    entity_type: &'a EntityType,
+    id: String,
+    start: BlockNumber,
+    data: &'a Entity,
+    parent: Option<String>,
+}
+
+impl<'a> Row<'a> {
+    fn sort_value(&self, key: &SortKey) -> &'a Value {
+        key.attribute()
+            .and_then(|attr| self.data.get(attr))
+            .unwrap_or(&Value::Null)
+    }
+
+    fn into_value(self) -> BTreeMap<String, r::Value> {
+        let mut map: BTreeMap<_, _> = self
+            .data
+            .clone()
+            .sorted()
+            .into_iter()
+            .filter(|(_, value)| !is_null(value))
+            .map(|(key, value)| (key, r::Value::from(value)))
+            .collect();
+        map.insert(
+            "__typename".to_owned(),
+            r::Value::String(self.entity_type.as_str().to_owned()),
+        );
+        if let Some(parent) = self.parent {
+            map.insert(PARENT_ID.to_owned(), r::Value::String(parent));
+        }
+        map
+    }
+}
+
+pub(crate) fn find_query_values(
+    entities: &Entities,
+    query: EntityQuery,
+) -> Result<Vec<BTreeMap<String, r::Value>>, QueryExecutionError> {
+    let sort_key = SortKey::new(&query.order, query.history.is_some());
+    sort_key.check_cursors(&query.range)?;
+
+    // All entity versions of one entity type that the query looks at
+    let candidates = |entity_type: &EntityType| -> Vec<(&str, BlockNumber, &Entity)> {
+        entities
+            .versions(entity_type)
+            .filter(|(_, version)| match &query.history {
+                Some(span) => version.overlaps(span),
+                None => version.is_current_at(query.block),
+            })
+            .map(|(id, version)| (id, version.start, &version.data))
+            .collect()
+    };
+
+    let mut rows = Vec::new();
+    match &query.collection {
+        EntityCollection::All(types) => {
+            for (entity_type, _) in types {
+                for (id, start, data) in candidates(entity_type) {
+                    rows.push(Row {
+                        entity_type,
+                        id: id.to_owned(),
+                        start,
+                        data,
+                        parent: None,
+                    });
+                }
+            }
+        }
+        EntityCollection::Window(windows) => {
+            for window in windows {
+                let parents_of = parent_lookup(&window.ids, &window.link);
+                for (id, start, data) in candidates(&window.child_type) {
+                    for parent in parents_of(id, data) {
+                        rows.push(Row {
+                            entity_type: &window.child_type,
+                            id: id.to_owned(),
+                            start,
+                            data,
+                            parent: Some(parent),
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    if let Some(filter) = &query.filter {
+        rows.retain(|row| matches_filter(row.data, filter));
+    }
+
+    // Order and range apply to the children of each parent separately;
+    // for queries that are not windowed, all rows have the parent `None`
+    let mut windows: BTreeMap<Option<String>, Vec<Row>> = BTreeMap::new();
+    for row in rows {
+        windows.entry(row.parent.clone()).or_default().push(row);
+    }
+
+    let range = &query.range;
+    let mut values = Vec::new();
+    for (_, mut rows) in windows {
+        match sort_key {
+            SortKey::None => {}
+            SortKey::Versions => rows.sort_by(|a, b| a.id.cmp(&b.id).then(a.start.cmp(&b.start))),
+            _ => rows.sort_by(|a, b| {
+                sort_key.compare(
+                    (a.sort_value(&sort_key), &a.id),
+                    (b.sort_value(&sort_key), &b.id),
+                )
+            }),
+        }
+        if !matches!(sort_key, SortKey::None) {
+            let position = |row: &Row, cursor: &EntityCursor| {
+                sort_key.compare(
+                    (row.sort_value(&sort_key), &row.id),
+                    (&cursor.value, &cursor.id),
+                )
+            };
+            if let Some(after) = &range.after {
+                rows.retain(|row| position(row, after) == Ordering::Greater);
+            }
+            if let Some(before) = &range.before {
+                rows.retain(|row| position(row, before) == Ordering::Less);
+            }
+        }
+        let first = range
+            .first
+            .map(|first| first as usize)
+            .unwrap_or(usize::MAX);
+        values.extend(
+            rows.into_iter()
+                .skip(range.skip as usize)
+                .take(first)
+                .map(Row::into_value),
+        );
+    }
+    Ok(values)
+}
+
+/// Return a function that lists the ids of the parents in `ids` of the
+/// child with id `child_id` and data `child` according to `link`
+fn parent_lookup<'a>(
+    ids: &'a [String],
+    link: &'a EntityLink,
+) -> Box<dyn Fn(&str, &Entity) -> Vec<String> + 'a> {
+    match link {
+        EntityLink::Direct(attribute, _) => {
+            let attribute = attribute.clone();
+            Box::new(move |_, child| {
+                let is_parent = |value: &Value| value_as_id(value).filter(|id| ids.contains(id));
+                match (&attribute, child.get(attribute.name())) {
+                    (WindowAttribute::Scalar(_), Some(value)) => {
+                        is_parent(value).into_iter().collect()
+                    }
+                    (WindowAttribute::List(_), Some(Value::List(values))) => {
+                        values.iter().filter_map(is_parent).collect()
+                    }
+                    _ => vec![],
+                }
+            })
+        }
+        EntityLink::Parent(parent_link) => {
+            let mut parents: HashMap<&str, Vec<String>> = HashMap::new();
+            match parent_link {
+                ParentLink::Scalar(child_ids) => {
+                    for (child_id, parent_id) in child_ids.iter().zip(ids) {
+                        parents
+                            .entry(child_id.as_str())
+                            .or_default()
+                            .push(parent_id.clone());
+                    }
+                }
+                ParentLink::List(child_ids) => {
+                    for (child_ids, parent_id) in child_ids.iter().zip(ids) {
+                        for child_id in child_ids {
+                            parents
+                                .entry(child_id.as_str())
+                                .or_default()
+                                .push(parent_id.clone());
+                        }
+                    }
+                }
+            }
+            Box::new(move |child_id, _| parents.get(child_id).cloned().unwrap_or_default())
+        }
+    }
+}
+
+fn value_as_id(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Bytes(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+pub(crate) fn matches_filter(entity: &Entity, filter: &EntityFilter) -> bool {
+    use EntityFilter::*;
+
+    let get = |attr: &str| entity.get(attr).filter(|value| !is_null(value));
+    let compare = |attr: &str, value: &Value, pred: fn(Ordering) -> bool| {
+        get(attr)
+            .and_then(|actual| partial_cmp(actual, value))
+            .map_or(false, pred)
+    };
+    let contained = |attr: &str, value: &Value| get(attr).map(|actual| contains(actual, value));
+    let starts_with = |attr: &str, value: &Value| {
+        get(attr).map(|actual| match (actual, value) {
+            (Value::String(s), Value::String(prefix)) => s.starts_with(prefix.as_str()),
+            _ => false,
+        })
+    };
+    let ends_with = |attr: &str, value: &Value| {
+        get(attr).map(|actual| match (actual, value) {
+            (Value::String(s), Value::String(suffix)) => s.ends_with(suffix.as_str()),
+            _ => false,
+        })
+    };
+
+    match filter {
+        And(filters) => filters.iter().all(|filter| matches_filter(entity, filter)),
+        Or(filters) => filters.iter().any(|filter| matches_filter(entity, filter)),
+        Equal(attr, Value::Null) => get(attr).is_none(),
+        Not(attr, Value::Null) => get(attr).is_some(),
+        Equal(attr, value) => get(attr).map_or(false, |actual| values_equal(actual, value)),
+        Not(attr, value) => get(attr).map_or(false, |actual| !values_equal(actual, value)),
+        GreaterThan(attr, value) => compare(attr, value, Ordering::is_gt),
+        LessThan(attr, value) => compare(attr, value, Ordering::is_lt),
+        GreaterOrEqual(attr, value) => compare(attr, value, Ordering::is_ge),
+        LessOrEqual(attr, value) => compare(attr, value, Ordering::is_le),
+        In(attr, values) => get(attr).map_or(false, |actual| {
+            values.iter().any(|value| values_equal(actual, value))
+        }),
+        NotIn(attr, values) => get(attr).map_or(false, |actual| {
+            !values.iter().any(|value| values_equal(actual, value))
+        }),
+        Contains(attr, value) => contained(attr, value).unwrap_or(false),
+        NotContains(attr, value) => contained(attr, value).map_or(false, |c| !c),
+        StartsWith(attr, value) => starts_with(attr, value).unwrap_or(false),
+        NotStartsWith(attr, value) => starts_with(attr, value).map_or(false, |c| !c),
+        EndsWith(attr, value) => ends_with(attr, value).unwrap_or(false),
+        NotEndsWith(attr, value) => ends_with(attr, value).map_or(false, |c| !c),
+    }
+}
+
+fn contains(actual: &Value, value: &Value) -> bool {
+    match (actual, value) {
+        (Value::String(s), Value::String(part)) => s.contains(part.as_str()),
+        (Value::Bytes(bytes), Value::Bytes(part)) => {
+            let (bytes, part) = (bytes.as_slice(), part.as_slice());
+            part.is_empty() || bytes.windows(part.len()).any(|window| window == part)
+        }
+        (Value::List(values), Value::List(parts)) => parts
+            .iter()
+            .all(|part| values.iter().any(|value| values_equal(value, part))),
+        _ => false,
+    }
+}
+
+fn values_equal(a: &Value, b: &Value) -> bool {
+    partial_cmp(a, b) == Some(Ordering::Equal)
+}
+
+/// Convert numeric values to `BigDecimal` so that values of different
+/// numeric types can be compared with each other
+fn as_decimal(value: &Value) -> Option<BigDecimal> {
+    match value {
+        Value::Int(i) => Some(BigDecimal::from(*i)),
+        Value::BigInt(i) => Some(BigDecimal::new(i.clone(), 0)),
+        Value::BigDecimal(d) => Some(d.clone()),
+        _ => None,
+    }
+}
+
+/// Compare two values the way SQL would: comparisons involving `null` or
+/// values of incompatible types have no result
+fn partial_cmp(a: &Value, b: &Value) -> Option<Ordering> {
+    match (a, b) {
+        (Value::Null, _) | (_, Value::Null) => None,
+        (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+        (Value::Bool(a), Value::Bool(b)) => Some(a.cmp(b)),
+        (Value::Bytes(a), Value::Bytes(b)) => Some(a.as_slice().cmp(b.as_slice())),
+        (Value::Int(a), Value::Int(b)) => Some(a.cmp(b)),
+        (Value::BigInt(a), Value::BigInt(b)) => Some(a.cmp(b)),
+        (Value::List(a), Value::List(b)) => {
+            for (a, b) in a.iter().zip(b.iter()) {
+                match partial_cmp(a, b)? {
+                    Ordering::Equal => continue,
+                    ord => return Some(ord),
+                }
+            }
+            Some(a.len().cmp(&b.len()))
+        }
+        (a, b) => Some(as_decimal(a)?.cmp(&as_decimal(b)?)),
+    }
+}
+
+/// Compare two values for sorting; `null` sorts after all other values,
+/// like it does in Postgres for ascending order
+fn sort_cmp(a: &Value, b: &Value) -> Ordering {
+    match (a, b) {
+        (Value::Null, Value::Null) => Ordering::Equal,
+        (Value::Null, _) => Ordering::Greater,
+        (_, Value::Null) => Ordering::Less,
+        (a, b) => partial_cmp(a, b).unwrap_or(Ordering::Equal),
+    }
+}
+
+/// The base type of attribute `attr` of `entity_type` and whether the
+/// attribute is a list
+fn attribute_type<'a>(
+    object: &'a s::ObjectType,
+    attr: &str,
+) -> Result<(&'a str, bool), StoreError> {
+    fn resolve(ty: &s::Type, is_list: bool) -> (&str, bool) {
+        match ty {
+            s::Type::NamedType(name) => (name, is_list),
+            s::Type::NonNullType(inner) => resolve(inner, is_list),
+            s::Type::ListType(inner) => resolve(inner, true),
+        }
+    }
+
+    object
+        .field(attr)
+        .map(|field| resolve(&field.field_type, false))
+        .ok_or_else(|| StoreError::UnknownField(attr.to_owned()))
+}
+
+pub(crate) fn aggregate(
+    entities: &Entities,
+    schema: &Schema,
+    query: AggregateQuery,
+) -> Result<Vec<BTreeMap<String, r::Value>>, QueryExecutionError> {
+    let object = schema
+        .document
+        .get_object_type_definition(query.entity_type.as_str())
+        .ok_or_else(|| StoreError::UnknownTable(query.entity_type.to_string()))?;
+
+    for attr in &query.group_by {
+        let (ty, is_list) = attribute_type(object, attr)?;
+        if is_list {
+            return Err(StoreError::Unknown(anyhow!(
+                "can not group by attribute `{}` of type [{}]",
+                attr,
+                ty
+            ))
+            .into());
+        }
+    }
+    let mut aggregates = Vec::new();
+    for aggregate in &query.aggregates {
+        let (ty, is_list) = attribute_type(object, &aggregate.attribute)?;
+        match ty {
+            "Int" | "BigInt" | "BigDecimal" if !is_list => {}
+            _ => {
+                return Err(StoreError::Unknown(anyhow!(
+                    "can not compute `{}` of attribute `{}` of type {}",
+                    aggregate.function,
+                    aggregate.attribute,
+                    ty
+                ))
+                .into())
+            }
+        }
+        aggregates.push((aggregate, ty));
+    }
+
+    let interval = query.interval.max(1) as usize;
+    let mut rows = Vec::new();
+    for block in (query.blocks.from..=query.blocks.to).step_by(interval) {
+        let matching = entities
+            .current(&query.entity_type, block)
+            .filter(|entity| {
+                query
+                    .filter
+                    .as_ref()
+                    .map_or(true, |filter| matches_filter(entity, filter))
+            });
+
+        // Group the matching entities; without group by attributes, every
+        // block produces exactly one row, even if no entities match
+        let mut groups: BTreeMap<GroupKey, Vec<&Entity>> = BTreeMap::new();
+        if query.group_by.is_empty() {
+            groups.insert(GroupKey(vec![]), matching.collect());
+        } else {
+            for entity in matching {
+                let key = query
+                    .group_by
+                    .iter()
+                    .map(|attr| entity.get(attr).cloned().unwrap_or(Value::Null))
+                    .collect();
+                groups.entry(GroupKey(key)).or_default().push(entity);
+            }
+        }
+
+        for (GroupKey(key), members) in groups {
+            let mut row = BTreeMap::new();
+            row.insert("block".to_owned(), r::Value::Int(block as i64));
+            row.insert("count".to_owned(), r::Value::Int(members.len() as i64));
+            for (attr, value) in query.group_by.iter().zip(key) {
+                row.insert(attr.clone(), r::Value::from(value));
+            }
+            for (aggregate, ty) in &aggregates {
+                let values: Vec<&Value> = members
+                    .iter()
+                    .filter_map(|entity| entity.get(&aggregate.attribute))
+                    .filter(|value| !is_null(value))
+                    .collect();
+                let value = compute(aggregate.function, ty, &values);
+                row.insert(aggregate.output_name(), r::Value::from(value));
+            }
+            rows.push(row);
+        }
+    }
+
+    let rows = match query.range {
+        Some(range) => rows
+            .into_iter()
+            .skip(range.skip as usize)
+            .take(
+                range
+                    .first
+                    .map(|first| first as usize)
+                    .unwrap_or(usize::MAX),
+            )
+            .collect(),
+        None => rows,
+    };
+    Ok(rows)
+}
+
+/// The values of the group by attributes of an entity. Two entities belong
+/// to the same group if all their group by attributes are equal, where
+/// `null` is considered equal to `null`. Groups are ordered the way Postgres
+/// orders them, with `null` last
+struct GroupKey(Vec<Value>);
+
+impl Ord for GroupKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0
+            .iter()
+            .zip(other.0.iter())
+            .map(|(a, b)| sort_cmp(a, b))
+            .find(|ord| *ord != Ordering::Equal)
+            .unwrap_or(Ordering::Equal)
+    }
+}
+
+impl PartialOrd for GroupKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for GroupKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for GroupKey {}
+
+/// Compute `function` over `values`, which are all values of an attribute
+/// of type `ty`. Like in SQL, the result is `null` if there are no values
+fn compute(function: AggregateFunction, ty: &str, values: &[&Value]) -> Value {
+    if values.is_empty() {
+        return Value::Null;
+    }
+    let decimals = || values.iter().filter_map(|value| as_decimal(value));
+    match function {
+        AggregateFunction::Sum => match ty {
+            "BigDecimal" => {
+                Value::BigDecimal(decimals().fold(BigDecimal::zero(), |sum, d| sum + d))
+            }
+            // Sums of integers are always integers
+            _ => Value::BigInt(
+                values
+                    .iter()
+                    .filter_map(|value| match value {
+                        Value::Int(i) => Some(BigInt::from(*i)),
+                        Value::BigInt(i) => Some(i.clone()),
+                        _ => None,
+                    })
+                    .fold(BigInt::from(0), |sum, i| sum + i),
+            ),
+        },
+        AggregateFunction::Avg => {
+            let sum = decimals().fold(BigDecimal::zero(), |sum, value| sum + value);
+            Value::BigDecimal(sum / BigDecimal::from(values.len() as u64))
+        }
+        AggregateFunction::Min | AggregateFunction::Max => {
+            let pick = values.iter().skip(1).fold(values[0], |best, value| {
+                match (function, sort_cmp(value, best)) {
+                    (AggregateFunction::Min, Ordering::Less)
+                    | (AggregateFunction::Max, Ordering::Greater) => *value,
+                    _ => best,
+                }
+            });
+            pick.clone()
+        }
+    }
+}
+
+fn is_null(value: &Value) -> bool {
+    matches!(value, Value::Null)
+}
@@ -0,0 +1,300 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
+
+use graph::components::server::index_node::VersionInfo;
+use graph::components::store::{
+    BlockStore as BlockStoreTrait, ChainStore as ChainStoreTrait, EntityChanges,
+    PersistedQueryStore, PoolWaitStats, QueryStore as QueryStoreTrait, QueryStoreManager,
+    StatusStore,
+};
+use graph::constraint_violation;
+use graph::data::query::QueryTarget;
+use graph::data::subgraph::status;
+use graph::prelude::tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use graph::prelude::web3::types::{Address, H256};
+use graph::prelude::*;
+use graph::util::stats::MovingStats;
+
+use crate::chain_store::{BlockStore, ChainStore};
+use crate::query;
+use crate::subgraph_store::SubgraphStore;
+
+/// The overall in-memory store, consisting of a [SubgraphStore] and a
+/// [BlockStore]. Like its Postgres counterpart, this struct should only be
+/// used during configuration and setup of `graph-node`
+pub struct Store {
+    subgraph_store: Arc<SubgraphStore>,
+    block_store: Arc<BlockStore>,
+    /// Limits the number of queries that run concurrently
+    query_semaphore: Arc<Semaphore>,
+    wait_stats: PoolWaitStats,
+    /// Persisted queries by their hash
+    persisted_queries: RwLock<BTreeMap<String, String>>,
+}
+
+impl Store {
+    pub fn new(
+        subgraph_store: Arc<SubgraphStore>,
+        block_store: Arc<BlockStore>,
+        query_permits: usize,
+    ) -> Self {
+        Self {
+            subgraph_store,
+            block_store,
+            query_semaphore: Arc::new(Semaphore::new(query_permits)),
+            wait_stats: Arc::new(RwLock::new(MovingStats::default())),
+            persisted_queries: RwLock::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn subgraph_store(&self) -> Arc<SubgraphStore> {
+        self.subgraph_store.cheap_clone()
+    }
+
+    pub fn block_store(&self) -> Arc<BlockStore> {
+        self.block_store.cheap_clone()
+    }
+}
+
+#[async_trait]
+impl QueryStoreManager for Store {
+    async fn query_store(
+        &self,
+        target: QueryTarget,
+        _for_subscription: bool,
+    ) -> Result<Arc<dyn QueryStoreTrait + Send + Sync>, QueryExecutionError> {
+        let deployment = match target {
+            QueryTarget::Name(name) => {
+                self.subgraph_store.current_deployment_for_subgraph(&name)?
+            }
+            QueryTarget::Deployment(id) => id,
+        };
+
+        let network = self
+            .subgraph_store
+            .with_deployment(&deployment, |deployment| deployment.network.clone())?;
+        let chain_store = self.block_store.chain_store(&network).ok_or_else(|| {
+            constraint_violation!(
+                "Subgraphs index a known network, but {} indexes `{}` which we do not know about. This is most likely a configuration error.",
+                deployment,
+                network
+            )
+        })?;
+
+        Ok(Arc::new(QueryStore {
+            deployment,
+            network,
+            store: self.subgraph_store.cheap_clone(),
+            chain_store,
+            query_semaphore: self.query_semaphore.cheap_clone(),
+            wait_stats: self.wait_stats.cheap_clone(),
+        }))
+    }
+}
+
+#[async_trait]
+impl StatusStore for Store {
+    fn status(&self, filter: status::Filter) -> Result<Vec<status::Info>, StoreError> {
+        let mut infos = self.subgraph_store.status(filter)?;
+        let ptrs: BTreeMap<_, _> = self.block_store.chain_heads().into_iter().collect();
+
+        for info in &mut infos {
+            for chain in &mut info.chains {
+                chain.chain_head_block =
+                    ptrs.get(&chain.network).cloned().flatten().map(Into::into);
+            }
+        }
+        Ok(infos)
+    }
+
+    fn version_info(&self, version_id: &str) -> Result<VersionInfo, StoreError> {
+        let mut info = self.subgraph_store.version_info(version_id)?;
+
+        info.total_ethereum_blocks_count = self
+            .block_store
+            .chain_store(&info.network)
+            .map(|chain_store| chain_store.chain_head_ptr())
+            .transpose()?
+            .flatten()
+            .map(|ptr| ptr.number);
+
+        Ok(info)
+    }
+
+    fn versions_for_subgraph_id(
+        &self,
+        subgraph_id: &str,
+    ) -> Result<(Option<String>, Option<String>), StoreError> {
+        self.subgraph_store.versions_for_subgraph_id(subgraph_id)
+    }
+
+    fn subgraphs_for_deployment_hash(
+        &self,
+        deployment_hash: &str,
+    ) -> Result<Vec<(String, String)>, StoreError> {
+        self.subgraph_store
+            .subgraphs_for_deployment_hash(deployment_hash)
+    }
+
+    fn deployment_stats(
+        &self,
+        subgraph_id: &DeploymentHash,
+    ) -> Result<Option<status::DeploymentStats>, StoreError> {
+        self.subgraph_store.deployment_stats(subgraph_id)
+    }
+
+    async fn get_proof_of_indexing(
+        &self,
+        subgraph_id: &DeploymentHash,
+        indexer: &Option<Address>,
+        block: BlockPtr,
+    ) -> Result<Option<[u8; 32]>, StoreError> {
+        self.subgraph_store
+            .get_proof_of_indexing(subgraph_id, indexer, block)
+    }
+
+    async fn query_permit(&self) -> OwnedSemaphorePermit {
+        // Unwrap: the semaphore is never closed
+        self.query_semaphore
+            .cheap_clone()
+            .acquire_owned()
+            .await
+            .unwrap()
+    }
+}
+
+#[async_trait]
+impl PersistedQueryStore for Store {
+    async fn persisted_query(&self, hash: &str) -> Result<Option<String>, StoreError> {
+        Ok(self.persisted_queries.read().unwrap().get(hash).cloned())
+    }
+
+    async fn register_persisted_query(&self, hash: &str, query: &str) -> Result<(), StoreError> {
+        self.persisted_queries
+            .write()
+            .unwrap()
+            .entry(hash.to_string())
+            .or_insert_with(|| query.to_string());
+        Ok(())
+    }
+
+    async fn persisted_queries(&self) -> Result<Vec<(String, String)>, StoreError> {
+        Ok(self
+            .persisted_queries
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(hash, query)| (hash.clone(), query.clone()))
+            .collect())
+    }
+
+    async fn persisted_query_count(&self) -> Result<usize, StoreError> {
+        Ok(self.persisted_queries.read().unwrap().len())
+    }
+}
+
+/// A `QueryStore` for one deployment in the in-memory store
+pub struct QueryStore {
+    deployment: DeploymentHash,
+    network: String,
+    store: Arc<SubgraphStore>,
+    chain_store: Arc<ChainStore>,
+    query_semaphore: Arc<Semaphore>,
+    wait_stats: PoolWaitStats,
+}
+
+#[async_trait]
+impl QueryStoreTrait for QueryStore {
+    fn find_query_values(
+        &self,
+        query: EntityQuery,
+    ) -> Result<Vec<BTreeMap<String, r::Value>>, QueryExecutionError> {
+        assert_eq!(&self.deployment, &query.subgraph_id);
+        self.store.with_deployment(&self.deployment, |deployment| {
+            query::find_query_values(&deployment.entities, query)
+        })?
+    }
+
+    fn aggregate(
+        &self,
+        query: AggregateQuery,
+    ) -> Result<Vec<BTreeMap<String, r::Value>>, QueryExecutionError> {
+        assert_eq!(&self.deployment, &query.subgraph_id);
+        self.store.with_deployment(&self.deployment, |deployment| {
+            query::aggregate(&deployment.entities, &deployment.schema, query)
+        })?
+    }
+
+    fn entity_changes(&self, blocks: BlockSpan) -> Result<EntityChanges, StoreError> {
+        // Holding on to the deployment while we read both the block pointer
+        // and the changes makes them consistent with each other
+        self.store
+            .with_deployment(&self.deployment, |deployment| EntityChanges {
+                head: deployment.block_ptr.clone(),
+                changes: deployment.entities.changes(&blocks),
+            })
+    }
+
+    fn ancestors(&self, block: &BlockPtr, count: BlockNumber) -> Result<Vec<BlockPtr>, StoreError> {
+        Ok(self.chain_store.ancestors(block, count))
+    }
+
+    fn chain_head_ptr(&self) -> Result<Option<BlockPtr>, StoreError> {
+        self.chain_store.chain_head_ptr().map_err(StoreError::from)
+    }
+
+    async fn is_deployment_synced(&self) -> Result<bool, Error> {
+        Ok(self
+            .store
+            .with_deployment(&self.deployment, |deployment| deployment.synced)?)
+    }
+
+    fn block_ptr(&self) -> Result<Option<BlockPtr>, StoreError> {
+        self.store
+            .with_deployment(&self.deployment, |deployment| deployment.block_ptr.clone())
+    }
+
+    fn block_number(&self, block_hash: H256) -> Result<Option<BlockNumber>, StoreError> {
+        // Every chain store only holds blocks of its own network, so there
+        // is no need to check the network like Postgres does
+        Ok(self
+            .chain_store
+            .block_number(block_hash)?
+            .map(|(_, number)| number))
+    }
+
+    fn wait_stats(&self) -> PoolWaitStats {
+        self.wait_stats.cheap_clone()
+    }
+
+    async fn has_non_fatal_errors(&self, block: Option<BlockNumber>) -> Result<bool, StoreError> {
+        self.store.with_deployment(&self.deployment, |deployment| {
+            deployment.has_non_fatal_errors(block)
+        })
+    }
+
+    async fn deployment_state(&self) -> Result<DeploymentState, QueryExecutionError> {
+        Ok(self
+            .store
+            .with_deployment(&self.deployment, |deployment| deployment.state())?)
+    }
+
+    fn api_schema(&self) -> Result<Arc<ApiSchema>, QueryExecutionError> {
+        Ok(self.store.with_deployment(&self.deployment, |deployment| {
+            deployment.api_schema.cheap_clone()
+        })?)
+    }
+
+    fn network_name(&self) -> &str {
+        &self.network
+    }
+
+    async fn query_permit(&self) -> OwnedSemaphorePermit {
+        // Unwrap: the semaphore is never closed
+        self.query_semaphore
+            .cheap_clone()
+            .acquire_owned()
+            .await
+            .unwrap()
+    }
+}
@@ -0,0 +1,132 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use graph::components::store::{SubscriptionManager as SubscriptionManagerTrait, UnitStream};
+use graph::parking_lot::{Mutex, RwLock};
+use graph::prelude::tokio::sync::{mpsc, watch};
+use graph::prelude::*;
+use graph::tokio_stream::{self, wrappers::UnboundedReceiverStream};
+
+struct Watcher<T> {
+    sender: Arc<watch::Sender<T>>,
+    receiver: watch::Receiver<T>,
+}
+
+impl<T: Clone + Debug + Send + Sync + 'static> Watcher<T> {
+    fn new(init: T) -> Self {
+        let (sender, receiver) = watch::channel(init);
+        Watcher {
+            sender: Arc::new(sender),
+            receiver,
+        }
+    }
+
+    fn send(&self, v: T) {
+        // Unwrap: `self` holds a receiver.
+        self.sender.send(v).unwrap()
+    }
+
+    fn stream(&self) -> Box<dyn futures03::Stream<Item = T> + Unpin + Send + Sync> {
+        Box::new(tokio_stream::wrappers::WatchStream::new(
+            self.receiver.clone(),
+        ))
+    }
+
+    /// Outstanding receivers returned from `Self::stream`.
+    fn receiver_count(&self) -> usize {
+        // Do not count the internal receiver.
+        self.sender.receiver_count() - 1
+    }
+}
+
+/// Manage subscriptions to the `StoreEvent` stream. Since all writes
+/// happen in this process, there is no notification listener; instead, the
+/// store hands each event to `send` right after it has been written, and
+/// the event is forwarded to all matching subscriptions immediately
+#[derive(Default)]
+pub struct SubscriptionManager {
+    // These are more efficient since only one entry is stored per filter.
+    subscriptions_no_payload: Mutex<HashMap<BTreeSet<SubscriptionFilter>, Watcher<()>>>,
+
+    subscriptions: RwLock<
+        HashMap<
+            usize,
+            (
+                Arc<BTreeSet<SubscriptionFilter>>,
+                mpsc::UnboundedSender<Arc<StoreEvent>>,
+            ),
+        >,
+    >,
+
+    next_id: AtomicUsize,
+}
+
+impl SubscriptionManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Send `event` to all active subscriptions that it matches. Detect
+    /// stale subscriptions in the process and close them
+    pub fn send(&self, event: StoreEvent) {
+        if event.changes.is_empty() {
+            return;
+        }
+        let event = Arc::new(event);
+
+        let mut stale_ids = Vec::new();
+        for (id, (_, sender)) in self
+            .subscriptions
+            .read()
+            .iter()
+            .filter(|(_, (filter, _))| event.matches(filter))
+        {
+            if sender.send(event.cheap_clone()).is_err() {
+                // Receiver was dropped
+                stale_ids.push(*id);
+            }
+        }
+        if !stale_ids.is_empty() {
+            let mut subscriptions = self.subscriptions.write();
+            for id in stale_ids {
+                subscriptions.remove(&id);
+            }
+        }
+
+        let mut watchers = self.subscriptions_no_payload.lock();
+        watchers.retain(|_, watcher| watcher.receiver_count() > 0);
+        for (_, watcher) in watchers.iter().filter(|(filter, _)| event.matches(filter)) {
+            watcher.send(());
+        }
+    }
+}
+
+impl SubscriptionManagerTrait for SubscriptionManager {
+    fn subscribe(&self, entities: BTreeSet<SubscriptionFilter>) -> StoreEventStreamBox {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+
+        // Prepare the new subscription by creating a channel and a subscription object
+        let (sender, receiver) = mpsc::unbounded_channel();
+
+        // Add the new subscription
+        self.subscriptions
+            .write()
+            .insert(id, (Arc::new(entities.clone()), sender));
+
+        // Return the subscription ID and entity change stream
+        StoreEventStream::new(Box::new(
+            UnboundedReceiverStream::new(receiver).map(Ok).compat(),
+        ))
+        .filter_by_entities(entities)
+    }
+
+    fn subscribe_no_payload(&self, entities: BTreeSet<SubscriptionFilter>) -> UnitStream {
+        self.subscriptions_no_payload
+            .lock()
+            .entry(entities)
+            .or_insert_with(|| Watcher::new(()))
+            .stream()
+    }
+}
@@ -0,0 +1,851 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use graph::components::server::index_node::VersionInfo;
+use graph::components::store::{
+    DeploymentId, DeploymentLocator, EntityType, StoredDynamicDataSource,
+    SubgraphStore as SubgraphStoreTrait, WritableStore as WritableStoreTrait,
+};
+use graph::components::subgraph::ProofOfIndexingFinisher;
+use graph::data::subgraph::schema::{SubgraphError, SubgraphHealth, POI_OBJECT};
+use graph::data::subgraph::status;
+use graph::parking_lot::RwLock;
+use graph::prelude::web3::types::Address;
+use graph::prelude::*;
+
+use crate::deployment::Deployment;
+use crate::store_events::SubscriptionManager;
+
+/// The name of the only shard the in-memory store has. It is only used for
+/// reporting and monitoring
+const SHARD: &str = "primary";
+
+struct Subgraph {
+    id: String,
+    current_version: Option<String>,
+    pending_version: Option<String>,
+}
+
+struct SubgraphVersion {
+    subgraph: String,
+    deployment: DeploymentHash,
+    created_at: u64,
+}
+
+#[derive(Default)]
+struct Inner {
+    deployments: HashMap<DeploymentId, Deployment>,
+    /// Subgraphs by their name
+    subgraphs: BTreeMap<String, Subgraph>,
+    /// Subgraph versions by their id
+    versions: BTreeMap<String, SubgraphVersion>,
+    assignments: HashMap<DeploymentId, NodeId>,
+    next_id: i32,
+}
+
+impl Inner {
+    fn next_id(&mut self) -> i32 {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    fn deployment(&self, hash: &DeploymentHash) -> Result<&Deployment, StoreError> {
+        self.deployments
+            .values()
+            .find(|deployment| deployment.hash() == hash)
+            .ok_or_else(|| StoreError::DeploymentNotFound(hash.to_string()))
+    }
+
+    fn version_deployment(&self, version: &Option<String>) -> Option<&DeploymentHash> {
+        version
+            .as_ref()
+            .and_then(|version| self.versions.get(version))
+            .map(|version| &version.deployment)
+    }
+
+    fn create_subgraph(&mut self, name: &SubgraphName) -> String {
+        if let Some(subgraph) = self.subgraphs.get(name.as_str()) {
+            return subgraph.id.clone();
+        }
+        let id = format!("{:032x}", self.next_id());
+        self.subgraphs.insert(
+            name.to_string(),
+            Subgraph {
+                id: id.clone(),
+                current_version: None,
+                pending_version: None,
+            },
+        );
+        id
+    }
+
+    /// Point the current or pending version of `name` at `locator`, and
+    /// assign the deployment to `node_id` if it is not assigned yet. This
+    /// follows the same rules as `create_subgraph_version` in Postgres
+    fn create_subgraph_version(
+        &mut self,
+        name: SubgraphName,
+        locator: &DeploymentLocator,
+        node_id: NodeId,
+        mode: SubgraphVersionSwitchingMode,
+    ) -> Vec<EntityChange> {
+        use SubgraphVersionSwitchingMode::*;
+
+        let subgraph_id = self.create_subgraph(&name);
+        let subgraph = &self.subgraphs[name.as_str()];
+        let current_deployment = self.version_deployment(&subgraph.current_version);
+        let pending_deployment = self.version_deployment(&subgraph.pending_version);
+
+        // If the subgraph has no current version, we treat it the same as
+        // if it were not synced
+        let current_exists_and_synced = current_deployment
+            .and_then(|hash| self.deployment(hash).ok())
+            .map_or(false, |deployment| deployment.synced);
+
+        // Check if we even need to make any changes
+        let change_needed = match (mode, current_exists_and_synced) {
+            (Instant, _) | (Synced, false) => current_deployment != Some(&locator.hash),
+            (Synced, true) => pending_deployment != Some(&locator.hash),
+        };
+        if !change_needed {
+            return vec![];
+        }
+
+        let version_id = format!("{:032x}", self.next_id());
+        self.versions.insert(
+            version_id.clone(),
+            SubgraphVersion {
+                subgraph: subgraph_id,
+                deployment: locator.hash.clone(),
+                created_at: now(),
+            },
+        );
+
+        // Create a subgraph assignment if there isn't one already
+        let new_assignment = !self.assignments.contains_key(&locator.id);
+        if new_assignment {
+            self.assignments.insert(locator.id, node_id);
+        }
+
+        // Unwrap: we made sure above that the subgraph exists
+        let subgraph = self.subgraphs.get_mut(name.as_str()).unwrap();
+        match (mode, current_exists_and_synced) {
+            (Instant, _) | (Synced, false) => {
+                subgraph.current_version = Some(version_id);
+                subgraph.pending_version = None;
+            }
+            (Synced, true) => {
+                subgraph.pending_version = Some(version_id);
+            }
+        }
+
+        let mut changes = self.remove_unused_assignments();
+        if new_assignment {
+            changes.push(EntityChange::for_assignment(
+                locator.clone(),
+                EntityChangeOperation::Set,
+            ));
+        }
+        changes
+    }
+
+    /// Remove the assignments of all deployments that are neither the
+    /// current nor the pending version of any subgraph
+    fn remove_unused_assignments(&mut self) -> Vec<EntityChange> {
+        let used: Vec<_> = self
+            .subgraphs
+            .values()
+            .flat_map(|subgraph| {
+                vec![
+                    self.version_deployment(&subgraph.current_version),
+                    self.version_deployment(&subgraph.pending_version),
+                ]
+            })
+            .flatten()
+            .collect();
+        let unused: Vec<_> = self
+            .assignments
+            .keys()
+            .filter_map(|id| self.deployments.get(id))
+            .filter(|deployment| !used.contains(&deployment.hash()))
+            .map(|deployment| deployment.locator.clone())
+            .collect();
+
+        unused
+            .into_iter()
+            .map(|locator| {
+                self.assignments.remove(&locator.id);
+                EntityChange::for_assignment(locator, EntityChangeOperation::Removed)
+            })
+            .collect()
+    }
+
+    /// Make the pending version of every subgraph that uses `hash` as its
+    /// pending version the current version
+    fn promote_deployment(&mut self, hash: &DeploymentHash) -> Vec<EntityChange> {
+        let versions = &self.versions;
+        for subgraph in self.subgraphs.values_mut() {
+            let pending = subgraph
+                .pending_version
+                .as_ref()
+                .and_then(|version| versions.get(version));
+            if pending.map_or(false, |version| &version.deployment == hash) {
+                subgraph.current_version = subgraph.pending_version.take();
+            }
+        }
+        self.remove_unused_assignments()
+    }
+
+    fn status(&self, deployment: &Deployment) -> status::Info {
+        status::Info {
+            id: deployment.locator.id,
+            subgraph: deployment.hash().to_string(),
+            synced: deployment.synced,
+            health: deployment.health,
+            fatal_error: deployment.fatal_error.clone(),
+            non_fatal_errors: deployment.non_fatal_errors.clone(),
+            chains: vec![status::ChainInfo {
+                network: deployment.network.clone(),
+                chain_head_block: None,
+                earliest_block: deployment.earliest_block.clone().map(Into::into),
+                latest_block: deployment.block_ptr.clone().map(Into::into),
+            }],
+            entity_count: deployment.entities.count(BLOCK_NUMBER_MAX) as u64,
+            node: self
+                .assignments
+                .get(&deployment.locator.id)
+                .map(|node| node.to_string()),
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// A `SubgraphStore` that keeps all subgraph metadata and entities in
+/// memory. Nothing survives a restart of the process
+pub struct SubgraphStore {
+    inner: RwLock<Inner>,
+    subscription_manager: Arc<SubscriptionManager>,
+}
+
+impl SubgraphStore {
+    pub fn new(subscription_manager: Arc<SubscriptionManager>) -> Self {
+        SubgraphStore {
+            inner: RwLock::new(Inner::default()),
+            subscription_manager,
+        }
+    }
+
+    fn send_store_event(&self, changes: Vec<EntityChange>) {
+        self.subscription_manager.send(StoreEvent::new(changes));
+    }
+
+    /// Run `f` against the deployment with hash `hash`
+    pub(crate) fn with_deployment<T>(
+        &self,
+        hash: &DeploymentHash,
+        f: impl FnOnce(&Deployment) -> T,
+    ) -> Result<T, StoreError> {
+        self.inner.read().deployment(hash).map(f)
+    }
+
+    fn read<T>(&self, id: DeploymentId, f: impl FnOnce(&Deployment) -> T) -> Result<T, StoreError> {
+        match self.inner.read().deployments.get(&id) {
+            Some(deployment) => Ok(f(deployment)),
+            None => Err(StoreError::DeploymentNotFound(id.to_string())),
+        }
+    }
+
+    fn write<T>(
+        &self,
+        id: DeploymentId,
+        f: impl FnOnce(&mut Deployment) -> Result<T, StoreError>,
+    ) -> Result<T, StoreError> {
+        match self.inner.write().deployments.get_mut(&id) {
+            Some(deployment) => f(deployment),
+            None => Err(StoreError::DeploymentNotFound(id.to_string())),
+        }
+    }
+
+    /// Find the deployment hash of the current version of the subgraph
+    /// `name`
+    pub fn current_deployment_for_subgraph(
+        &self,
+        name: &SubgraphName,
+    ) -> Result<DeploymentHash, StoreError> {
+        let inner = self.inner.read();
+        inner
+            .subgraphs
+            .get(name.as_str())
+            .and_then(|subgraph| inner.version_deployment(&subgraph.current_version))
+            .cloned()
+            .ok_or_else(|| {
+                StoreError::QueryExecutionError(format!("Subgraph `{}` not found", name.as_str()))
+            })
+    }
+
+    pub(crate) fn status(&self, filter: status::Filter) -> Result<Vec<status::Info>, StoreError> {
+        let inner = self.inner.read();
+        let hashes: Vec<DeploymentHash> = match filter {
+            status::Filter::SubgraphName(name) => {
+                let subgraph_id = match inner.subgraphs.get(&name) {
+                    Some(subgraph) => &subgraph.id,
+                    None => return Ok(vec![]),
+                };
+                let mut versions: Vec<_> = inner
+                    .versions
+                    .values()
+                    .filter(|version| &version.subgraph == subgraph_id)
+                    .collect();
+                versions.sort_by_key(|version| version.created_at);
+                let mut hashes: Vec<_> = vec![];
+                for version in versions {
+                    if !hashes.contains(&version.deployment) {
+                        hashes.push(version.deployment.clone());
+                    }
+                }
+                hashes
+            }
+            status::Filter::SubgraphVersion(name, use_current) => inner
+                .subgraphs
+                .get(&name)
+                .and_then(|subgraph| {
+                    let version = if use_current {
+                        &subgraph.current_version
+                    } else {
+                        &subgraph.pending_version
+                    };
+                    inner.version_deployment(version)
+                })
+                .cloned()
+                .into_iter()
+                .collect(),
+            status::Filter::Deployments(hashes) if hashes.is_empty() => {
+                let mut deployments: Vec<_> = inner.deployments.values().collect();
+                deployments.sort_by_key(|deployment| deployment.locator.id.0);
+                deployments
+                    .into_iter()
+                    .map(|deployment| deployment.hash().clone())
+                    .collect()
+            }
+            status::Filter::Deployments(hashes) => hashes
+                .into_iter()
+                .filter_map(|hash| DeploymentHash::new(hash).ok())
+                .collect(),
+            status::Filter::DeploymentIds(ids) => ids
+                .iter()
+                .filter_map(|id| inner.deployments.get(id))
+                .map(|deployment| deployment.hash().clone())
+                .collect(),
+        };
+
+        Ok(hashes
+            .iter()
+            .filter_map(|hash| inner.deployment(hash).ok())
+            .map(|deployment| inner.status(deployment))
+            .collect())
+    }
+
+    pub(crate) fn version_info(&self, version_id: &str) -> Result<VersionInfo, StoreError> {
+        let inner = self.inner.read();
+        let version = inner
+            .versions
+            .get(version_id)
+            .ok_or_else(|| StoreError::DeploymentNotFound(version_id.to_string()))?;
+        let deployment = inner.deployment(&version.deployment)?;
+
+        Ok(VersionInfo {
+            created_at: version.created_at.to_string(),
+            deployment_id: deployment.hash().to_string(),
+            latest_ethereum_block_number: deployment.block_ptr.as_ref().map(|ptr| ptr.number),
+            total_ethereum_blocks_count: None,
+            synced: deployment.synced,
+            failed: deployment.health.is_failed(),
+            description: deployment.description.clone(),
+            repository: deployment.repository.clone(),
+            schema: deployment.schema.cheap_clone(),
+            network: deployment.network.clone(),
+        })
+    }
+
+    pub(crate) fn versions_for_subgraph_id(
+        &self,
+        subgraph_id: &str,
+    ) -> Result<(Option<String>, Option<String>), StoreError> {
+        Ok(self
+            .inner
+            .read()
+            .subgraphs
+            .values()
+            .find(|subgraph| subgraph.id == subgraph_id)
+            .map(|subgraph| {
+                (
+                    subgraph.current_version.clone(),
+                    subgraph.pending_version.clone(),
+                )
+            })
+            .unwrap_or((None, None)))
+    }
+
+    pub(crate) fn subgraphs_for_deployment_hash(
+        &self,
+        deployment_hash: &str,
+    ) -> Result<Vec<(String, String)>, StoreError> {
+        let inner = self.inner.read();
+        let uses_hash = |version: &Option<String>| {
+            inner
+                .version_deployment(version)
+                .map_or(false, |hash| hash.as_str() == deployment_hash)
+        };
+        let mut subgraphs = vec![];
+        for (name, subgraph) in &inner.subgraphs {
+            if uses_hash(&subgraph.current_version) {
+                subgraphs.push((name.clone(), "current".to_string()));
+            }
+            if uses_hash(&subgraph.pending_version) {
+                subgraphs.push((name.clone(), "pending".to_string()));
+            }
+        }
+        Ok(subgraphs)
+    }
+
+    /// Statistics in the same shape as the ones for Postgres. Since nothing
+    /// is stored on disk, all sizes are 0, and no entity type is treated as
+    /// account-like
+    pub(crate) fn deployment_stats(
+        &self,
+        id: &DeploymentHash,
+    ) -> Result<Option<status::DeploymentStats>, StoreError> {
+        let stats = self.with_deployment(id, |deployment| status::DeploymentStats {
+            subgraph: deployment.hash().to_string(),
+            shard: SHARD.to_string(),
+            namespace: format!("sgd{}", deployment.locator.id),
+            latest_block: deployment.block_ptr.clone().map(Into::into),
+            tables: deployment
+                .entities
+                .stats()
+                .map(|(entity_type, entities, versions)| status::TableStats {
+                    table: entity_type.to_string(),
+                    entities: entities as i64,
+                    versions: versions as i64,
+                    account_like: false,
+                    table_size: 0,
+                    index_size: 0,
+                })
+                .collect(),
+        });
+        match stats {
+            Ok(stats) => Ok(Some(stats)),
+            Err(StoreError::DeploymentNotFound(_)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub(crate) fn get_proof_of_indexing(
+        &self,
+        id: &DeploymentHash,
+        indexer: &Option<Address>,
+        block: BlockPtr,
+    ) -> Result<Option<[u8; 32]>, StoreError> {
+        let by_causality_region = self.with_deployment(id, |deployment| {
+            // Like Postgres, we can only check that the block number is
+            // high enough, not that the block is on the deployment's chain
+            match &deployment.block_ptr {
+                Some(latest) if latest.number >= block.number => (),
+                _ => return Ok(None),
+            }
+
+            deployment
+                .entities
+                .versions(&*POI_OBJECT)
+                .filter(|(_, version)| version.is_current_at(block.number))
+                .map(|(causality_region, version)| {
+                    let digest = match version.data.get("digest") {
+                        Some(Value::Bytes(b)) => Ok(b.to_owned()),
+                        other => Err(anyhow!(
+                            "Entity has non-bytes digest attribute: {:?}",
+                            other
+                        )),
+                    }?;
+                    Ok((causality_region.to_string(), digest))
+                })
+                .collect::<Result<HashMap<_, _>, Error>>()
+                .map(Some)
+        })??;
+
+        let mut by_causality_region = match by_causality_region {
+            Some(by_causality_region) => by_causality_region,
+            None => return Ok(None),
+        };
+
+        let mut finisher = ProofOfIndexingFinisher::new(&block, id, indexer);
+        for (name, region) in by_causality_region.drain() {
+            finisher.add_causality_region(&name, &region);
+        }
+
+        Ok(Some(finisher.finish()))
+    }
+}
+
+#[async_trait]
+impl SubgraphStoreTrait for SubgraphStore {
+    fn find_ens_name(&self, _hash: &str) -> Result<Option<String>, StoreError> {
+        // There is no rainbow table in memory
+        Ok(None)
+    }
+
+    fn is_deployed(&self, id: &DeploymentHash) -> Result<bool, StoreError> {
+        Ok(self.inner.read().deployment(id).is_ok())
+    }
+
+    fn create_subgraph_deployment(
+        &self,
+        name: SubgraphName,
+        schema: &Schema,
+        deployment: SubgraphDeploymentEntity,
+        node_id: NodeId,
+        network: String,
+        mode: SubgraphVersionSwitchingMode,
+    ) -> Result<DeploymentLocator, StoreError> {
+        let mut inner = self.inner.write();
+
+        if let Some(base) = &deployment.graft_base {
+            inner.deployment(base)?;
+        }
+
+        let locator = match inner.deployment(&schema.id) {
+            Ok(existing) => existing.locator.clone(),
+            Err(_) => {
+                let locator =
+                    DeploymentLocator::new(DeploymentId(inner.next_id()), schema.id.clone());
+                let deployment = Deployment::new(locator.clone(), schema, deployment, network)?;
+                inner.deployments.insert(locator.id, deployment);
+                locator
+            }
+        };
+
+        let changes = inner.create_subgraph_version(name, &locator, node_id, mode);
+        drop(inner);
+
+        self.send_store_event(changes);
+        Ok(locator)
+    }
+
+    fn create_subgraph(&self, name: SubgraphName) -> Result<String, StoreError> {
+        Ok(self.inner.write().create_subgraph(&name))
+    }
+
+    fn remove_subgraph(&self, name: SubgraphName) -> Result<(), StoreError> {
+        let mut inner = self.inner.write();
+        let subgraph = match inner.subgraphs.remove(name.as_str()) {
+            Some(subgraph) => subgraph,
+            None => return Ok(()),
+        };
+        inner
+            .versions
+            .retain(|_, version| version.subgraph != subgraph.id);
+        let changes = inner.remove_unused_assignments();
+        drop(inner);
+
+        self.send_store_event(changes);
+        Ok(())
+    }
+
+    fn reassign_subgraph(
+        &self,
+        deployment: &DeploymentLocator,
+        node_id: &NodeId,
+    ) -> Result<(), StoreError> {
+        match self.inner.write().assignments.get_mut(&deployment.id) {
+            Some(node) => *node = node_id.clone(),
+            None => return Err(StoreError::DeploymentNotFound(deployment.hash.to_string())),
+        }
+
+        self.send_store_event(vec![EntityChange::for_assignment(
+            deployment.clone(),
+            EntityChangeOperation::Set,
+        )]);
+        Ok(())
+    }
+
+    fn assigned_node(&self, deployment: &DeploymentLocator) -> Result<Option<NodeId>, StoreError> {
+        Ok(self.inner.read().assignments.get(&deployment.id).cloned())
+    }
+
+    fn assignments(&self, node: &NodeId) -> Result<Vec<DeploymentLocator>, StoreError> {
+        let inner = self.inner.read();
+        Ok(inner
+            .assignments
+            .iter()
+            .filter(|(_, assigned)| *assigned == node)
+            .filter_map(|(id, _)| inner.deployments.get(id))
+            .map(|deployment| deployment.locator.clone())
+            .collect())
+    }
+
+    fn subgraph_exists(&self, name: &SubgraphName) -> Result<bool, StoreError> {
+        Ok(self.inner.read().subgraphs.contains_key(name.as_str()))
+    }
+
+    fn input_schema(&self, id: &DeploymentHash) -> Result<Arc<Schema>, StoreError> {
+        self.with_deployment(id, |deployment| deployment.schema.cheap_clone())
+    }
+
+    fn api_schema(&self, id: &DeploymentHash) -> Result<Arc<ApiSchema>, StoreError> {
+        self.with_deployment(id, |deployment| deployment.api_schema.cheap_clone())
+    }
+
+    async fn writable(
+        self: Arc<Self>,
+        logger: Logger,
+        deployment: DeploymentId,
+    ) -> Result<Arc<dyn WritableStoreTrait>, StoreError> {
+        if !self.inner.read().deployments.contains_key(&deployment) {
+            return Err(StoreError::DeploymentNotFound(deployment.to_string()));
+        }
+        Ok(Arc::new(WritableStore::new(self, logger, deployment)))
+    }
+
+    fn writable_for_network_indexer(
+        &self,
+        _logger: Logger,
+        _id: &DeploymentHash,
+    ) -> Result<Arc<dyn WritableStoreTrait>, StoreError> {
+        // A `WritableStore` needs to hold on to its `SubgraphStore`, which
+        // we can only get through `writable`
+        Err(StoreError::Unknown(anyhow!(
+            "the network indexer is not supported by the in-memory store"
+        )))
+    }
+
+    fn least_block_ptr(&self, id: &DeploymentHash) -> Result<Option<BlockPtr>, StoreError> {
+        self.with_deployment(id, |deployment| deployment.block_ptr.clone())
+    }
+
+    fn locators(&self, hash: &str) -> Result<Vec<DeploymentLocator>, StoreError> {
+        Ok(self
+            .inner
+            .read()
+            .deployments
+            .values()
+            .filter(|deployment| deployment.hash().as_str() == hash)
+            .map(|deployment| deployment.locator.clone())
+            .collect())
+    }
+}
+
+/// A `WritableStore` for one deployment in a `SubgraphStore`
+pub struct WritableStore {
+    store: Arc<SubgraphStore>,
+    logger: Logger,
+    id: DeploymentId,
+}
+
+impl WritableStore {
+    fn new(store: Arc<SubgraphStore>, logger: Logger, id: DeploymentId) -> Self {
+        WritableStore { store, logger, id }
+    }
+}
+
+#[async_trait]
+impl WritableStoreTrait for WritableStore {
+    fn block_ptr(&self) -> Result<Option<BlockPtr>, StoreError> {
+        self.store
+            .read(self.id, |deployment| deployment.block_ptr.clone())
+    }
+
+    fn block_cursor(&self) -> Result<Option<String>, StoreError> {
+        self.store
+            .read(self.id, |deployment| deployment.firehose_cursor.clone())
+    }
+
+    fn start_subgraph_deployment(&self, logger: &Logger) -> Result<(), StoreError> {
+        let mut inner = self.store.inner.write();
+        let deployment = inner
+            .deployments
+            .get(&self.id)
+            .ok_or_else(|| StoreError::DeploymentNotFound(self.id.to_string()))?;
+
+        if let Some((base, block)) = deployment.graft_pending().cloned() {
+            info!(logger, "Copying data from graft base";
+                          "base" => base.as_str(), "block" => block.number);
+            // Unwrap: we checked above that the deployment exists
+            let mut deployment = inner.deployments.remove(&self.id).unwrap();
+            let base = match inner.deployment(&base) {
+                Ok(base) => base,
+                Err(e) => {
+                    inner.deployments.insert(self.id, deployment);
+                    return Err(e);
+                }
+            };
+            match &base.block_ptr {
+                Some(ptr) if ptr.number >= block.number => (),
+                _ => {
+                    let error = anyhow!(
+                        "can not graft onto `{}` at block {} since it has not \
+                         processed that block yet",
+                        base.hash(),
+                        block.number
+                    );
+                    inner.deployments.insert(self.id, deployment);
+                    return Err(error.into());
+                }
+            }
+            deployment.copy_from(base, &block);
+            inner.deployments.insert(self.id, deployment);
+        }
+        Ok(())
+    }
+
+    fn revert_block_operations(&self, block_ptr_to: BlockPtr) -> Result<(), StoreError> {
+        let event = self.store.write(self.id, |deployment| {
+            deployment.revert_block_operations(block_ptr_to)
+        })?;
+        self.store.subscription_manager.send(event);
+        Ok(())
+    }
+
+    fn unfail_deterministic_error(
+        &self,
+        current_ptr: &BlockPtr,
+        parent_ptr: &BlockPtr,
+    ) -> Result<(), StoreError> {
+        self.store.write(self.id, |deployment| {
+            deployment.unfail_deterministic_error(current_ptr, parent_ptr)
+        })
+    }
+
+    fn unfail_non_deterministic_error(&self, current_ptr: &BlockPtr) -> Result<(), StoreError> {
+        self.store.write(self.id, |deployment| {
+            deployment.unfail_non_deterministic_error(current_ptr);
+            Ok(())
+        })
+    }
+
+    async fn fail_subgraph(&self, error: SubgraphError) -> Result<(), StoreError> {
+        self.store.write(self.id, |deployment| {
+            deployment.fail(error);
+            Ok(())
+        })
+    }
+
+    async fn supports_proof_of_indexing(&self) -> Result<bool, StoreError> {
+        Ok(true)
+    }
+
+    fn get(&self, key: &EntityKey) -> Result<Option<Entity>, StoreError> {
+        self.store.read(self.id, |deployment| {
+            deployment
+                .entities
+                .find(&key.entity_type, &key.entity_id, BLOCK_NUMBER_MAX)
+                .cloned()
+        })
+    }
+
+    fn transact_block_operations(
+        &self,
+        block_ptr_to: BlockPtr,
+        firehose_cursor: Option<String>,
+        mods: Vec<EntityModification>,
+        stopwatch: StopwatchMetrics,
+        data_sources: Vec<StoredDynamicDataSource>,
+        deterministic_errors: Vec<SubgraphError>,
+    ) -> Result<(), StoreError> {
+        let _section = stopwatch.start_section("transact_block");
+        let event = self.store.write(self.id, |deployment| {
+            deployment.transact_block_operations(
+                block_ptr_to,
+                firehose_cursor,
+                &mods,
+                &data_sources,
+                deterministic_errors,
+            )
+        })?;
+        self.store.subscription_manager.send(event);
+        Ok(())
+    }
+
+    fn get_many(
+        &self,
+        ids_for_type: BTreeMap<&EntityType, Vec<&str>>,
+    ) -> Result<BTreeMap<EntityType, Vec<Entity>>, StoreError> {
+        self.store.read(self.id, |deployment| {
+            ids_for_type
+                .into_iter()
+                .map(|(entity_type, ids)| {
+                    let entities: Vec<_> = ids
+                        .into_iter()
+                        .filter_map(|id| {
+                            deployment
+                                .entities
+                                .find(entity_type, id, BLOCK_NUMBER_MAX)
+                                .cloned()
+                        })
+                        .collect();
+                    (entity_type.clone(), entities)
+                })
+                .filter(|(_, entities)| !entities.is_empty())
+                .collect()
+        })
+    }
+
+    fn deployment_synced(&self) -> Result<(), StoreError> {
+        let mut inner = self.store.inner.write();
+        let hash = match inner.deployments.get_mut(&self.id) {
+            Some(deployment) => {
+                deployment.synced = true;
+                deployment.hash().clone()
+            }
+            None => return Err(StoreError::DeploymentNotFound(self.id.to_string())),
+        };
+        let changes = inner.promote_deployment(&hash);
+        drop(inner);
+
+        self.store.send_store_event(changes);
+        Ok(())
+    }
+
+    async fn is_deployment_synced(&self) -> Result<bool, StoreError> {
+        self.store.read(self.id, |deployment| deployment.synced)
+    }
+
+    fn unassign_subgraph(&self) -> Result<(), StoreError> {
+        let mut inner = self.store.inner.write();
+        let locator = match inner.deployments.get(&self.id) {
+            Some(deployment) => deployment.locator.clone(),
+            None => return Err(StoreError::DeploymentNotFound(self.id.to_string())),
+        };
+        let removed = inner.assignments.remove(&self.id).is_some();
+        drop(inner);
+
+        if removed {
+            debug!(self.logger, "Unassigned subgraph"; "deployment" => &locator);
+            self.store
+                .send_store_event(vec![EntityChange::for_assignment(
+                    locator,
+                    EntityChangeOperation::Removed,
+                )]);
+        }
+        Ok(())
+    }
+
+    async fn load_dynamic_data_sources(&self) -> Result<Vec<StoredDynamicDataSource>, StoreError> {
+        self.store
+            .read(self.id, |deployment| deployment.load_dynamic_data_sources())
+    }
+
+    fn shard(&self) -> &str {
+        SHARD
+    }
+
+    async fn health(&self, id: &DeploymentHash) -> Result<SubgraphHealth, StoreError> {
+        self.store
+            .with_deployment(id, |deployment| deployment.health)
+    }
+}
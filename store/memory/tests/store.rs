@@ -0,0 +1,490 @@
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+use graph::blockchain::ChainIdentifier;
+use graph::components::store::{
+    DeploymentLocator, EntityType, QueryStoreManager, StatusStore,
+    SubgraphStore as SubgraphStoreTrait, SubscriptionManager as _, WritableStore,
+};
+use graph::data::query::QueryTarget;
+use graph::data::subgraph::schema::{SubgraphHealth, SubgraphManifestEntity};
+use graph::data::subgraph::status;
+use graph::entity;
+use graph::prelude::web3::types::H256;
+use graph::prelude::*;
+use graph_mock::MockMetricsRegistry;
+use graph_store_memory::{
+    BlockStore, ChainHeadUpdateListener, Store, SubgraphStore, SubscriptionManager,
+};
+
+const GQL: &str = "
+    type User @entity {
+        id: ID!,
+        name: String!,
+        age: Int!,
+    }";
+
+const NETWORK_NAME: &str = "fake_network";
+
+lazy_static! {
+    static ref LOGGER: Logger = Logger::root(slog::Discard, o!());
+    static ref NODE_ID: NodeId = NodeId::new("test").unwrap();
+    static ref USER: EntityType = EntityType::new("User".to_string());
+}
+
+fn block(number: BlockNumber) -> BlockPtr {
+    BlockPtr::from((H256::from_low_u64_be(number as u64 + 1), number))
+}
+
+fn deployment_entity() -> SubgraphDeploymentEntity {
+    SubgraphDeploymentEntity {
+        manifest: SubgraphManifestEntity {
+            spec_version: "0.0.2".to_string(),
+            description: None,
+            repository: None,
+            features: vec![],
+            schema: GQL.to_string(),
+        },
+        failed: false,
+        health: SubgraphHealth::Healthy,
+        synced: false,
+        fatal_error: None,
+        non_fatal_errors: vec![],
+        earliest_block: None,
+        latest_block: None,
+        graft_base: None,
+        graft_block: None,
+        reorg_count: 0,
+        current_reorg_depth: 0,
+        max_reorg_depth: 0,
+        history_blocks: None,
+        earliest_block_number: 0,
+    }
+}
+
+struct TestStore {
+    store: Arc<Store>,
+    subscription_manager: Arc<SubscriptionManager>,
+}
+
+impl TestStore {
+    fn new() -> Self {
+        let subscription_manager = Arc::new(SubscriptionManager::new());
+        let ident = ChainIdentifier {
+            net_version: "1".to_string(),
+            genesis_block_hash: block(0).hash,
+        };
+        let block_store = BlockStore::new(
+            vec![(NETWORK_NAME.to_string(), vec![ident])],
+            Arc::new(ChainHeadUpdateListener::new()),
+        )
+        .unwrap();
+        let subgraph_store = SubgraphStore::new(subscription_manager.clone());
+        let store = Store::new(Arc::new(subgraph_store), Arc::new(block_store), 10);
+        TestStore {
+            store: Arc::new(store),
+            subscription_manager,
+        }
+    }
+
+    fn create(
+        &self,
+        name: &str,
+        hash: &str,
+        mode: SubgraphVersionSwitchingMode,
+    ) -> (DeploymentLocator, Arc<dyn WritableStore>) {
+        let hash = DeploymentHash::new(hash).unwrap();
+        let schema = Schema::parse(GQL, hash).unwrap();
+        let locator = self
+            .store
+            .subgraph_store()
+            .create_subgraph_deployment(
+                SubgraphName::new(name).unwrap(),
+                &schema,
+                deployment_entity(),
+                NODE_ID.clone(),
+                NETWORK_NAME.to_string(),
+                mode,
+            )
+            .unwrap();
+        let writable = futures03::executor::block_on(
+            self.store
+                .subgraph_store()
+                .writable(LOGGER.clone(), locator.id),
+        )
+        .unwrap();
+        writable.start_subgraph_deployment(&*LOGGER).unwrap();
+        (locator, writable)
+    }
+}
+
+fn user(id: &str, name: &str, age: i32) -> Entity {
+    entity! { id: id, name: name, age: age }
+}
+
+fn key(hash: &DeploymentHash, id: &str) -> EntityKey {
+    EntityKey::data(hash.clone(), "User".to_string(), id.to_string())
+}
+
+fn insert(hash: &DeploymentHash, entity: Entity) -> EntityModification {
+    EntityModification::Insert {
+        key: key(hash, entity.id().unwrap().as_str()),
+        data: entity,
+    }
+}
+
+fn overwrite(hash: &DeploymentHash, entity: Entity) -> EntityModification {
+    EntityModification::Overwrite {
+        key: key(hash, entity.id().unwrap().as_str()),
+        data: entity,
+    }
+}
+
+fn transact(
+    writable: &Arc<dyn WritableStore>,
+    hash: &DeploymentHash,
+    number: BlockNumber,
+    mods: Vec<EntityModification>,
+) -> Result<(), StoreError> {
+    let stopwatch = StopwatchMetrics::new(
+        LOGGER.clone(),
+        hash.clone(),
+        Arc::new(MockMetricsRegistry::new()),
+    );
+    writable.transact_block_operations(block(number), None, mods, stopwatch, vec![], vec![])
+}
+
+fn names(store: &Arc<Store>, query: EntityQuery) -> Vec<String> {
+    let query_store = futures03::executor::block_on(
+        store.query_store(QueryTarget::Deployment(query.subgraph_id.clone()), false),
+    )
+    .unwrap();
+    query_store
+        .find_query_values(query)
+        .unwrap()
+        .into_iter()
+        .map(|row| match row.get("name") {
+            Some(r::Value::String(name)) => name.clone(),
+            other => panic!("unexpected name {:?}", other),
+        })
+        .collect()
+}
+
+fn user_query(hash: &DeploymentHash, block: BlockNumber) -> EntityQuery {
+    EntityQuery::new(
+        hash.clone(),
+        block,
+        EntityCollection::All(vec![(USER.clone(), AttributeNames::All)]),
+    )
+}
+
+#[test]
+fn versions_and_reverts() {
+    let test = TestStore::new();
+    let (locator, writable) = test.create(
+        "versions",
+        "QmVersions",
+        SubgraphVersionSwitchingMode::Instant,
+    );
+    let hash = &locator.hash;
+
+    transact(
+        &writable,
+        hash,
+        1,
+        vec![insert(hash, user("1", "Alice", 30))],
+    )
+    .unwrap();
+    transact(
+        &writable,
+        hash,
+        2,
+        vec![overwrite(hash, user("1", "Alicia", 31))],
+    )
+    .unwrap();
+    transact(
+        &writable,
+        hash,
+        3,
+        vec![EntityModification::Remove {
+            key: key(hash, "1"),
+        }],
+    )
+    .unwrap();
+
+    assert_eq!(vec!["Alice"], names(&test.store, user_query(hash, 1)));
+    assert_eq!(vec!["Alicia"], names(&test.store, user_query(hash, 2)));
+    assert!(names(&test.store, user_query(hash, 3)).is_empty());
+    assert_eq!(None, writable.get(&key(hash, "1")).unwrap());
+
+    writable.revert_block_operations(block(2)).unwrap();
+    assert_eq!(Some(block(2)), writable.block_ptr().unwrap());
+    assert_eq!(
+        Some(user("1", "Alicia", 31)),
+        writable.get(&key(hash, "1")).unwrap()
+    );
+
+    writable.revert_block_operations(block(1)).unwrap();
+    assert_eq!(
+        Some(user("1", "Alice", 30)),
+        writable.get(&key(hash, "1")).unwrap()
+    );
+    assert_eq!(
+        vec!["Alice"],
+        names(&test.store, user_query(hash, BLOCK_NUMBER_MAX))
+    );
+}
+
+#[test]
+fn duplicate_insert_fails_and_leaves_store_untouched() {
+    let test = TestStore::new();
+    let (locator, writable) = test.create(
+        "duplicate",
+        "QmDuplicate",
+        SubgraphVersionSwitchingMode::Instant,
+    );
+    let hash = &locator.hash;
+
+    transact(
+        &writable,
+        hash,
+        1,
+        vec![insert(hash, user("1", "Alice", 30))],
+    )
+    .unwrap();
+    let res = transact(
+        &writable,
+        hash,
+        2,
+        vec![
+            insert(hash, user("2", "Bob", 40)),
+            overwrite(hash, user("3", "Carol", 25)),
+            insert(hash, user("1", "Alice", 30)),
+        ],
+    );
+    assert!(res.is_err());
+    assert_eq!(Some(block(1)), writable.block_ptr().unwrap());
+    assert_eq!(None, writable.get(&key(hash, "2")).unwrap());
+    assert_eq!(None, writable.get(&key(hash, "3")).unwrap());
+    assert_eq!(
+        vec!["Alice"],
+        names(&test.store, user_query(hash, BLOCK_NUMBER_MAX))
+    );
+
+    // The failed block did not leave anything behind that a later block
+    // could trip over
+    transact(
+        &writable,
+        hash,
+        2,
+        vec![
+            insert(hash, user("2", "Bob", 40)),
+            overwrite(hash, user("1", "Alicia", 31)),
+        ],
+    )
+    .unwrap();
+    writable.revert_block_operations(block(1)).unwrap();
+    assert_eq!(
+        vec!["Alice"],
+        names(&test.store, user_query(hash, BLOCK_NUMBER_MAX))
+    );
+
+    // Processing the same block twice is an error
+    let res = transact(&writable, hash, 1, vec![]);
+    assert!(matches!(
+        res,
+        Err(StoreError::DuplicateBlockProcessing(_, 1))
+    ));
+}
+
+#[test]
+fn filter_order_and_range() {
+    let test = TestStore::new();
+    let (locator, writable) =
+        test.create("filter", "QmFilter", SubgraphVersionSwitchingMode::Instant);
+    let hash = &locator.hash;
+
+    transact(
+        &writable,
+        hash,
+        1,
+        vec![
+            insert(hash, user("1", "Alice", 30)),
+            insert(hash, user("2", "Bob", 40)),
+            insert(hash, user("3", "Carol", 25)),
+            insert(hash, user("4", "Dave", 40)),
+        ],
+    )
+    .unwrap();
+
+    let query = user_query(hash, 1)
+        .filter(EntityFilter::GreaterOrEqual(
+            "age".to_string(),
+            Value::Int(30),
+        ))
+        .order(EntityOrder::Descending("age".to_string(), ValueType::Int));
+    assert_eq!(vec!["Bob", "Dave", "Alice"], names(&test.store, query));
+
+    let query = user_query(hash, 1)
+        .filter(EntityFilter::Or(vec![
+            EntityFilter::Equal("name".to_string(), Value::from("Carol")),
+            EntityFilter::StartsWith("name".to_string(), Value::from("Da")),
+        ]))
+        .order(EntityOrder::Ascending(
+            "name".to_string(),
+            ValueType::String,
+        ));
+    assert_eq!(vec!["Carol", "Dave"], names(&test.store, query));
+
+    let query = user_query(hash, 1)
+        .order(EntityOrder::Ascending("age".to_string(), ValueType::Int))
+        .skip(1)
+        .first(2);
+    assert_eq!(vec!["Alice", "Bob"], names(&test.store, query));
+}
+
+#[test]
+fn entity_changes_and_events() {
+    let test = TestStore::new();
+    let (locator, writable) = test.create(
+        "changes",
+        "QmChanges",
+        SubgraphVersionSwitchingMode::Instant,
+    );
+    let hash = &locator.hash;
+
+    let mut filter = BTreeSet::new();
+    filter.insert(SubscriptionFilter::Entities(hash.clone(), USER.clone()));
+    let mut events = test.subscription_manager.subscribe(filter).compat();
+
+    transact(
+        &writable,
+        hash,
+        1,
+        vec![insert(hash, user("1", "Alice", 30))],
+    )
+    .unwrap();
+    transact(
+        &writable,
+        hash,
+        2,
+        vec![overwrite(hash, user("1", "Alicia", 31))],
+    )
+    .unwrap();
+
+    let event = futures03::executor::block_on(events.next())
+        .unwrap()
+        .unwrap();
+    let expected = EntityChange::Data {
+        subgraph_id: hash.clone(),
+        entity_type: USER.clone(),
+    };
+    assert!(event.changes.contains(&expected));
+
+    let query_store = futures03::executor::block_on(test.store.query_store(
+        QueryTarget::Name(SubgraphName::new("changes").unwrap()),
+        false,
+    ))
+    .unwrap();
+    let changes = query_store.entity_changes(BlockSpan::new(1, 2)).unwrap();
+    assert_eq!(Some(block(2)), changes.head);
+    let kinds: Vec<_> = changes
+        .changes
+        .iter()
+        .map(|change| (change.block, change.kind))
+        .collect();
+    assert_eq!(
+        vec![(1, EntityChangeKind::Insert), (2, EntityChangeKind::Update)],
+        kinds
+    );
+}
+
+#[test]
+fn version_switching_and_status() {
+    let test = TestStore::new();
+    let subgraph_store = test.store.subgraph_store();
+    let name = SubgraphName::new("switching").unwrap();
+
+    let (first, writable) =
+        test.create("switching", "QmFirst", SubgraphVersionSwitchingMode::Synced);
+    writable.deployment_synced().unwrap();
+
+    // The first deployment is synced, so the second one becomes pending
+    let (second, writable) = test.create(
+        "switching",
+        "QmSecond",
+        SubgraphVersionSwitchingMode::Synced,
+    );
+    let infos = test
+        .store
+        .status(status::Filter::SubgraphVersion(name.to_string(), true))
+        .unwrap();
+    assert_eq!(
+        vec![first.hash.to_string()],
+        infos
+            .iter()
+            .map(|info| info.subgraph.clone())
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(
+        vec![(name.to_string(), "pending".to_string())],
+        test.store
+            .subgraphs_for_deployment_hash(second.hash.as_str())
+            .unwrap()
+    );
+
+    // Once the second deployment is synced, it replaces the first one
+    writable.deployment_synced().unwrap();
+    let infos = test
+        .store
+        .status(status::Filter::SubgraphVersion(name.to_string(), true))
+        .unwrap();
+    assert_eq!(second.hash.to_string(), infos[0].subgraph);
+    assert_eq!(None, subgraph_store.assigned_node(&first).unwrap());
+    assert_eq!(
+        Some(NODE_ID.clone()),
+        subgraph_store.assigned_node(&second).unwrap()
+    );
+
+    subgraph_store.remove_subgraph(name.clone()).unwrap();
+    assert!(!subgraph_store.subgraph_exists(&name).unwrap());
+    assert!(subgraph_store.assignments(&NODE_ID).unwrap().is_empty());
+}
+
+#[test]
+fn deployment_stats() {
+    let test = TestStore::new();
+    let (locator, writable) =
+        test.create("stats", "QmStats", SubgraphVersionSwitchingMode::Instant);
+    let hash = &locator.hash;
+
+    transact(
+        &writable,
+        hash,
+        1,
+        vec![
+            insert(hash, user("1", "Alice", 30)),
+            insert(hash, user("2", "Bob", 40)),
+        ],
+    )
+    .unwrap();
+    transact(
+        &writable,
+        hash,
+        2,
+        vec![overwrite(hash, user("1", "Alicia", 31))],
+    )
+    .unwrap();
+
+    let stats = test.store.deployment_stats(hash).unwrap().unwrap();
+    assert_eq!(hash.as_str(), stats.subgraph);
+    assert_eq!(2, stats.latest_block.unwrap().number());
+    assert_eq!(1, stats.tables.len());
+    let table = &stats.tables[0];
+    assert_eq!("User", table.table);
+    assert_eq!(2, table.entities);
+    assert_eq!(3, table.versions);
+
+    let missing = DeploymentHash::new("QmMissing").unwrap();
+    assert!(test.store.deployment_stats(&missing).unwrap().is_none());
+}
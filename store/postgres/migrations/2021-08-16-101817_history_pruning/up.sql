@@ -0,0 +1,6 @@
+-- history_blocks: how many blocks of history to keep; null keeps everything
+-- earliest_block_number: the earliest block for which we have all history;
+-- entity versions that are not visible at that block have been deleted
+alter table subgraphs.subgraph_deployment
+    add column history_blocks int4 default null,
+    add column earliest_block_number int4 not null default 0;
@@ -0,0 +1,6 @@
+-- The registry of persisted queries; it is only used in the primary
+create table public.persisted_queries (
+	hash        text primary key,
+	query       text not null,
+	created_at  timestamptz not null default now()
+);
@@ -0,0 +1,6 @@
+delete from subgraphs.dynamic_ethereum_contract_data_source
+ where address is null;
+
+alter table subgraphs.dynamic_ethereum_contract_data_source
+      drop column account,
+      alter column address set not null;
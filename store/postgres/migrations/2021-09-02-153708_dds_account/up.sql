@@ -0,0 +1,6 @@
+-- NEAR data sources are for an account rather than a contract address
+alter table subgraphs.dynamic_ethereum_contract_data_source
+      alter column address drop not null,
+      add column account text,
+      add constraint dynamic_ethereum_contract_data_source_address_or_account
+          check (address is not null or account is not null);
@@ -7,7 +7,7 @@ use diesel::sql_types::{Integer, Range};
 use std::io::Write;
 use std::ops::{Bound, RangeBounds, RangeFrom};
 
-use graph::prelude::{BlockNumber, BlockPtr, BLOCK_NUMBER_MAX};
+use graph::prelude::{BlockNumber, BlockPtr, BlockSpan, BLOCK_NUMBER_MAX};
 
 use crate::relational::Table;
 
@@ -123,6 +123,64 @@ impl<'a> QueryFragment<Pg> for BlockRangeContainsClause<'a> {
     }
 }
 
+/// Generate the clause that checks whether the block range of an entity
+/// has at least one block in common with `span`, i.e., whether that version
+/// of the entity was current at any block in `span`
+#[derive(Constructor)]
+pub struct BlockRangeOverlapsClause<'a> {
+    table_prefix: &'a str,
+    span: BlockSpan,
+}
+
+impl<'a> QueryFragment<Pg> for BlockRangeOverlapsClause<'a> {
+    fn walk_ast(&self, mut out: AstPass<Pg>) -> QueryResult<()> {
+        out.unsafe_to_cache_prepared();
+
+        // Generate `{prefix}block_range && int4range($from, $to, '[]')`
+        // which can use the exclusion index on `(id, block_range)`
+        out.push_sql(self.table_prefix);
+        out.push_identifier(BLOCK_RANGE_COLUMN)?;
+        out.push_sql(" && int4range(");
+        out.push_bind_param::<Integer, _>(&self.span.from)?;
+        out.push_sql(", ");
+        out.push_bind_param::<Integer, _>(&self.span.to)?;
+        out.push_sql(", '[]')");
+        Ok(())
+    }
+}
+
+/// The versions of entities that a query looks at
+#[derive(Clone, Copy, Debug)]
+pub enum BlockVersions {
+    /// The versions that are current at this block
+    At(BlockNumber),
+    /// All versions that were current at some block in this span; used
+    /// when listing the history of entities
+    Span(BlockSpan),
+}
+
+/// Generate the clause that restricts the versions of an entity to the
+/// ones described by `versions`
+#[derive(Constructor)]
+pub struct BlockVersionsClause<'a> {
+    table: &'a Table,
+    table_prefix: &'a str,
+    versions: BlockVersions,
+}
+
+impl<'a> QueryFragment<Pg> for BlockVersionsClause<'a> {
+    fn walk_ast(&self, out: AstPass<Pg>) -> QueryResult<()> {
+        match self.versions {
+            BlockVersions::At(block) => {
+                BlockRangeContainsClause::new(self.table, self.table_prefix, block).walk_ast(out)
+            }
+            BlockVersions::Span(span) => {
+                BlockRangeOverlapsClause::new(self.table_prefix, span).walk_ast(out)
+            }
+        }
+    }
+}
+
 #[test]
 fn block_number_max_is_i32_max() {
     // The code in this file embeds i32::MAX aka BLOCK_NUMBER_MAX in strings
@@ -1,4 +1,4 @@
-use diesel::sql_types::Integer;
+use diesel::sql_types::{BigInt, Integer};
 use diesel::{connection::SimpleConnection, prelude::RunQueryDsl, select};
 use diesel::{insert_into, OptionalExtension};
 use diesel::{pg::PgConnection, sql_query};
@@ -11,6 +11,7 @@ use std::fmt::Write;
 use std::iter::FromIterator;
 use std::sync::Arc;
 
+use graph::data::subgraph::status::TableStats;
 use graph::prelude::anyhow::anyhow;
 use graph::{data::subgraph::schema::POI_TABLE, prelude::StoreError};
 
@@ -227,6 +228,60 @@ pub fn account_like(conn: &PgConnection, site: &Site) -> Result<HashSet<String>,
     Ok(names)
 }
 
+/// Size and version statistics for all tables of the deployment `site`.
+/// The entity and version counts come from the statistics Postgres keeps
+/// about each table; `n_distinct` for the `id` column is a fraction of the
+/// number of rows if it is negative
+pub fn table_stats(conn: &PgConnection, site: &Site) -> Result<Vec<TableStats>, StoreError> {
+    #[derive(QueryableByName)]
+    struct Stats {
+        #[sql_type = "Text"]
+        table_name: String,
+        #[sql_type = "BigInt"]
+        entities: i64,
+        #[sql_type = "BigInt"]
+        versions: i64,
+        #[sql_type = "BigInt"]
+        table_size: i64,
+        #[sql_type = "BigInt"]
+        index_size: i64,
+    }
+
+    let query = "
+        select c.relname::text as table_name,
+               coalesce(case when s.n_distinct < 0
+                             then - s.n_distinct * greatest(c.reltuples, 0)
+                             else s.n_distinct end, 0)::int8 as entities,
+               greatest(c.reltuples, 0)::int8 as versions,
+               pg_table_size(c.oid)::int8 as table_size,
+               pg_indexes_size(c.oid)::int8 as index_size
+          from pg_namespace n
+               join pg_class c on c.relnamespace = n.oid
+               left join pg_stats s
+                 on s.schemaname = n.nspname
+                and s.tablename = c.relname
+                and s.attname = 'id'
+         where n.nspname = $1
+           and c.relkind = 'r'
+         order by c.relname";
+
+    let account_like = account_like(conn, site)?;
+    let stats = sql_query(query)
+        .bind::<Text, _>(site.namespace.as_str())
+        .load::<Stats>(conn)?
+        .into_iter()
+        .map(|stats| TableStats {
+            account_like: account_like.contains(&stats.table_name),
+            table: stats.table_name,
+            entities: stats.entities,
+            versions: stats.versions,
+            table_size: stats.table_size,
+            index_size: stats.index_size,
+        })
+        .collect();
+    Ok(stats)
+}
+
 pub fn set_account_like(
     conn: &PgConnection,
     site: &Site,
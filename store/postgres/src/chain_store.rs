@@ -859,6 +859,73 @@ mod data {
             Ok(data)
         }
 
+        /// Return `block_ptr` followed by up to `count` of its ancestors.
+        /// The list ends at the first ancestor that is not in the store
+        pub(super) fn ancestors(
+            &self,
+            conn: &PgConnection,
+            block_ptr: &BlockPtr,
+            count: BlockNumber,
+        ) -> Result<Vec<BlockPtr>, Error> {
+            #[derive(QueryableByName)]
+            struct AncestorText {
+                #[sql_type = "Text"]
+                hash: String,
+                #[sql_type = "Integer"]
+                block_offset: i32,
+            }
+
+            #[derive(QueryableByName)]
+            struct AncestorBytea {
+                #[sql_type = "Bytea"]
+                hash: Vec<u8>,
+                #[sql_type = "Integer"]
+                block_offset: i32,
+            }
+
+            // Like the query in `ancestor_block`, but returning all the
+            // ancestors we find rather than just the last one. The first
+            // row is `block_ptr` itself, whether it is in the store or not
+            let query = |blocks: &str| {
+                format!(
+                    "
+        with recursive ancestors(block_hash, block_offset) as (
+            values ($1, 0)
+            union all
+            select b.parent_hash, a.block_offset+1
+              from ancestors a, {} b
+             where a.block_hash = b.hash
+               and a.block_offset < $2
+        )
+        select a.block_hash as hash, a.block_offset
+          from ancestors a
+         order by a.block_offset",
+                    blocks
+                )
+            };
+
+            let ancestors = match self {
+                Storage::Shared => sql_query(query("ethereum_blocks"))
+                    .bind::<Text, _>(block_ptr.hash_hex())
+                    .bind::<BigInt, _>(count as i64)
+                    .load::<AncestorText>(conn)?
+                    .into_iter()
+                    .map(|row| {
+                        let hash = graph::prelude::hex::decode(&row.hash)?;
+                        Ok(BlockPtr::from((hash, block_ptr.number - row.block_offset)))
+                    })
+                    .collect::<Result<_, Error>>()?,
+                Storage::Private(Schema { blocks, .. }) => sql_query(query(&blocks.qname))
+                    .bind::<Bytea, _>(block_ptr.hash_slice())
+                    .bind::<BigInt, _>(count as i64)
+                    .load::<AncestorBytea>(conn)?
+                    .into_iter()
+                    .map(|row| BlockPtr::from((row.hash, block_ptr.number - row.block_offset)))
+                    .collect(),
+            };
+            Ok(ancestors)
+        }
+
         pub(super) fn delete_blocks_before(
             &self,
             conn: &PgConnection,
@@ -1284,6 +1351,18 @@ impl ChainStore {
         )
     }
 
+    /// Return `block_ptr` followed by up to `count` of its ancestors,
+    /// going backwards. The list ends early at the first ancestor that is
+    /// not in the block cache
+    pub fn ancestors(
+        &self,
+        block_ptr: &BlockPtr,
+        count: BlockNumber,
+    ) -> Result<Vec<BlockPtr>, Error> {
+        let conn = self.get_conn()?;
+        self.storage.ancestors(&conn, block_ptr, count)
+    }
+
     /// Store the given chain as the blocks for the `network` set the
     /// network's genesis block to `genesis_hash`, and head block to
     /// `null`
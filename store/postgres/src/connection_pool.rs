@@ -486,6 +486,15 @@ impl ConnectionPool {
         self.get_ready()?.get_fdw(logger, timeout)
     }
 
+    /// Open a new connection to the database that is not managed by the
+    /// pool. Since these connections support `COPY`, they are used for
+    /// bulk loading data, and should not be used for anything else
+    pub(crate) fn copy_client(&self) -> Result<postgres::Client, StoreError> {
+        let pool = self.get_ready()?;
+        postgres::Client::connect(&pool.postgres_url, postgres::NoTls)
+            .map_err(|e| StoreError::Unknown(e.into()))
+    }
+
     pub fn connection_detail(&self) -> Result<ForeignServer, StoreError> {
         let pool = self.get_ready()?;
         ForeignServer::new(pool.shard.clone(), &pool.postgres_url).map_err(|e| e.into())
@@ -24,7 +24,10 @@ use std::str::FromStr;
 use std::{collections::BTreeSet, convert::TryFrom, ops::Bound};
 
 use crate::connection_pool::ForeignServer;
-use crate::{block_range::BLOCK_RANGE_COLUMN, primary::Site};
+use crate::{
+    block_range::BLOCK_RANGE_COLUMN,
+    primary::{DeploymentId, Site},
+};
 use graph::constraint_violation;
 
 #[derive(DbEnum, Debug, Clone, Copy)]
@@ -78,6 +81,8 @@ table! {
         current_reorg_depth -> Integer,
         max_reorg_depth -> Integer,
         firehose_cursor -> Nullable<Text>,
+        history_blocks -> Nullable<Integer>,
+        earliest_block_number -> Integer,
     }
 }
 
@@ -394,15 +399,22 @@ pub fn state(conn: &PgConnection, id: DeploymentHash) -> Result<DeploymentState,
             d::reorg_count,
             d::max_reorg_depth,
             d::latest_ethereum_block_number,
+            d::earliest_block_number,
         ))
-        .first::<(String, i32, i32, Option<BigDecimal>)>(conn)
+        .first::<(String, i32, i32, Option<BigDecimal>, BlockNumber)>(conn)
         .optional()?
     {
         None => Err(StoreError::QueryExecutionError(format!(
             "No data found for subgraph {}",
             id
         ))),
-        Some((_, reorg_count, max_reorg_depth, latest_ethereum_block_number)) => {
+        Some((
+            _,
+            reorg_count,
+            max_reorg_depth,
+            latest_ethereum_block_number,
+            earliest_block_number,
+        )) => {
             let reorg_count = convert_to_u32(Some(reorg_count), "reorg_count", id.as_str())?;
             let max_reorg_depth =
                 convert_to_u32(Some(max_reorg_depth), "max_reorg_depth", id.as_str())?;
@@ -414,11 +426,95 @@ pub fn state(conn: &PgConnection, id: DeploymentHash) -> Result<DeploymentState,
                 reorg_count,
                 max_reorg_depth,
                 latest_ethereum_block_number,
+                earliest_block_number,
             })
         }
     }
 }
 
+/// Return how many blocks of history the deployment keeps (`None` if it
+/// keeps all of it) and the earliest block for which it has all history
+pub fn history(
+    conn: &PgConnection,
+    site: &Site,
+) -> Result<(Option<BlockNumber>, BlockNumber), StoreError> {
+    use subgraph_deployment as d;
+
+    Ok(d::table
+        .filter(d::id.eq(site.id))
+        .select((d::history_blocks, d::earliest_block_number))
+        .first::<(Option<BlockNumber>, BlockNumber)>(conn)?)
+}
+
+/// Set how many blocks of history the deployment should keep. Setting this
+/// to `None` stops pruning the deployment, but can not bring back history
+/// that was already pruned
+pub fn set_history_blocks(
+    conn: &PgConnection,
+    site: &Site,
+    history_blocks: Option<BlockNumber>,
+) -> Result<(), StoreError> {
+    use subgraph_deployment as d;
+
+    update(d::table.filter(d::id.eq(site.id)))
+        .set(d::history_blocks.eq(history_blocks))
+        .execute(conn)?;
+    Ok(())
+}
+
+pub fn set_earliest_block_number(
+    conn: &PgConnection,
+    site: &Site,
+    earliest_block_number: BlockNumber,
+) -> Result<(), StoreError> {
+    use subgraph_deployment as d;
+
+    update(d::table.filter(d::id.eq(site.id)))
+        .set(d::earliest_block_number.eq(earliest_block_number))
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Find all deployments in this shard that keep a limited amount of history
+/// and have more history than that. For each of them, return the block
+/// before which their history can be removed. That block lies
+/// `reorg_threshold` blocks further back than the history they need to
+/// keep so that we never prune blocks that might still be reverted
+pub fn prunable(
+    conn: &PgConnection,
+    reorg_threshold: BlockNumber,
+) -> Result<Vec<(DeploymentId, BlockNumber)>, StoreError> {
+    use subgraph_deployment as d;
+
+    let deployments = d::table
+        .filter(d::history_blocks.is_not_null())
+        .select((
+            d::id,
+            d::history_blocks,
+            d::latest_ethereum_block_number,
+            d::earliest_block_number,
+        ))
+        .load::<(
+            DeploymentId,
+            Option<BlockNumber>,
+            Option<BigDecimal>,
+            BlockNumber,
+        )>(conn)?;
+
+    Ok(deployments
+        .into_iter()
+        .filter_map(|(id, history_blocks, latest, earliest)| {
+            let latest = latest.and_then(|latest| latest.to_i32())?;
+            let horizon = latest - reorg_threshold - history_blocks?;
+            if horizon > earliest {
+                Some((id, horizon))
+            } else {
+                None
+            }
+        })
+        .collect())
+}
+
 /// Mark the deployment `id` as synced
 pub fn set_synced(conn: &PgConnection, id: &DeploymentHash) -> Result<(), StoreError> {
     use subgraph_deployment as d;
@@ -793,6 +889,8 @@ pub fn create_deployment(
         reorg_count: _,
         current_reorg_depth: _,
         max_reorg_depth: _,
+        history_blocks,
+        earliest_block_number,
     } = deployment;
 
     let deployment_values = (
@@ -811,6 +909,8 @@ pub fn create_deployment(
         d::graft_base.eq(graft_base.as_ref().map(|s| s.as_str())),
         d::graft_block_hash.eq(b(&graft_block)),
         d::graft_block_number.eq(n(&graft_block)),
+        d::history_blocks.eq(history_blocks),
+        d::earliest_block_number.eq(earliest_block_number),
     );
 
     let graph_node_version_id = GraphNodeVersion::create_or_get(&conn)?;
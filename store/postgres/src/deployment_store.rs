@@ -3,7 +3,7 @@ use diesel::connection::SimpleConnection;
 use diesel::pg::PgConnection;
 use diesel::prelude::*;
 use diesel::r2d2::{ConnectionManager, PooledConnection};
-use graph::components::store::{EntityType, StoredDynamicDataSource};
+use graph::components::store::{BlockOperations, EntityType, StoredDynamicDataSource};
 use graph::data::subgraph::status;
 use graph::prelude::{
     tokio, CancelHandle, CancelToken, CancelableError, PoolWaitStats, SubgraphDeploymentEntity,
@@ -18,6 +18,7 @@ use std::env;
 use std::iter::FromIterator;
 use std::ops::Bound;
 use std::ops::Deref;
+use std::path::Path;
 use std::str::FromStr;
 use std::sync::{atomic::AtomicUsize, Arc, Mutex};
 use std::time::Duration;
@@ -28,10 +29,10 @@ use graph::components::subgraph::ProofOfIndexingFinisher;
 use graph::constraint_violation;
 use graph::data::subgraph::schema::{SubgraphError, POI_OBJECT};
 use graph::prelude::{
-    anyhow, debug, info, lazy_static, o, warn, web3, ApiSchema, AttributeNames, BlockNumber,
-    BlockPtr, CheapClone, DeploymentHash, DeploymentState, Entity, EntityKey, EntityModification,
-    EntityQuery, Error, Logger, QueryExecutionError, Schema, StopwatchMetrics, StoreError,
-    StoreEvent, Value, BLOCK_NUMBER_MAX,
+    anyhow, debug, info, lazy_static, o, warn, web3, AggregateQuery, ApiSchema, AttributeNames,
+    BlockNumber, BlockPtr, BlockSpan, CheapClone, DeploymentHash, DeploymentState, Entity,
+    EntityChanges, EntityKey, EntityModification, EntityQuery, Error, Logger, QueryExecutionError,
+    Schema, StopwatchMetrics, StoreError, StoreEvent, Value, BLOCK_NUMBER_MAX,
 };
 use graph_graphql::prelude::api_schema;
 use web3::types::Address;
@@ -39,10 +40,14 @@ use web3::types::Address;
 use crate::block_range::block_number;
 use crate::catalog;
 use crate::deployment;
+use crate::dump;
 use crate::relational::{Layout, LayoutCache};
 use crate::relational_queries::FromEntityData;
 use crate::{connection_pool::ConnectionPool, detail};
-use crate::{dynds, primary::Site};
+use crate::{
+    dynds,
+    primary::{DeploymentId, Site},
+};
 
 lazy_static! {
     /// `GRAPH_QUERY_STATS_REFRESH_INTERVAL` is how long statistics that
@@ -253,10 +258,51 @@ impl DeploymentStore {
             query.order,
             query.range,
             query.block,
+            query.history,
             query.query_id,
         )
     }
 
+    pub(crate) fn execute_aggregate<T: FromEntityData>(
+        &self,
+        conn: &PgConnection,
+        site: Arc<Site>,
+        query: AggregateQuery,
+    ) -> Result<Vec<T>, QueryExecutionError> {
+        let layout = self.layout(conn, site)?;
+
+        layout.aggregate(
+            conn,
+            &query.entity_type,
+            query.filter,
+            &query.aggregates,
+            &query.group_by,
+            query.blocks,
+            query.interval,
+            query.range,
+            query.query_id,
+        )
+    }
+
+    pub(crate) fn entity_changes(
+        &self,
+        conn: &PgConnection,
+        site: Arc<Site>,
+        blocks: BlockSpan,
+    ) -> Result<EntityChanges, StoreError> {
+        let layout = self.layout(conn, site.cheap_clone())?;
+        // Read the block pointer and the changes from the same snapshot so
+        // that a concurrent revert can not make them inconsistent
+        conn.build_transaction()
+            .read_only()
+            .repeatable_read()
+            .run(|| {
+                let head = Self::block_ptr_with_conn(&site.deployment, conn)?;
+                let changes = layout.entity_changes(conn, blocks)?;
+                Ok(EntityChanges { head, changes })
+            })
+    }
+
     fn check_interface_entity_uniqueness(
         &self,
         conn: &PgConnection,
@@ -602,6 +648,38 @@ impl DeploymentStore {
         deployment::block_ptr(&conn, subgraph_id)
     }
 
+    /// Write the data and metadata of the deployment `site` into `dir`
+    pub(crate) fn dump(
+        &self,
+        site: Arc<Site>,
+        dir: &Path,
+        format: dump::Format,
+    ) -> Result<dump::Manifest, StoreError> {
+        let conn = self.get_conn()?;
+        let layout = self.layout(&conn, site)?;
+        dump::dump(&conn, layout.as_ref(), dir, format)
+    }
+
+    /// Create the deployment `site` from the dump in `dir` that is
+    /// described by `manifest`
+    pub(crate) fn restore(
+        &self,
+        site: Arc<Site>,
+        schema: &Schema,
+        manifest: &dump::Manifest,
+        dir: &Path,
+    ) -> Result<(), StoreError> {
+        let deployment = manifest.deployment_entity()?;
+        self.create_deployment(schema, deployment, site.clone(), None, false)?;
+
+        let layout = self.find_layout(site)?;
+        let mut client = self.pool.copy_client()?;
+        dump::load(&mut client, layout.as_ref(), manifest, dir)?;
+
+        let conn = self.get_conn()?;
+        conn.transaction(|| dump::finish(&conn, layout.as_ref(), manifest))
+    }
+
     pub(crate) fn deployment_details(
         &self,
         ids: Vec<String>,
@@ -674,11 +752,83 @@ impl DeploymentStore {
         })
         .await
     }
+
+    pub(crate) fn history(
+        &self,
+        site: &Site,
+    ) -> Result<(Option<BlockNumber>, BlockNumber), StoreError> {
+        let conn = self.get_conn()?;
+        deployment::history(&conn, site)
+    }
+
+    pub(crate) fn set_history_blocks(
+        &self,
+        site: &Site,
+        history_blocks: Option<BlockNumber>,
+    ) -> Result<(), StoreError> {
+        let conn = self.get_conn()?;
+        conn.transaction(|| deployment::set_history_blocks(&conn, site, history_blocks))
+    }
+
+    pub(crate) fn prunable_deployments(
+        &self,
+        reorg_threshold: BlockNumber,
+    ) -> Result<Vec<(DeploymentId, BlockNumber)>, StoreError> {
+        let conn = self.get_conn()?;
+        deployment::prunable(&conn, reorg_threshold)
+    }
+
+    /// Remove the history of the deployment `site` from before
+    /// `earliest_block` and return how many entity versions were removed.
+    /// Versions are removed in batches, each in its own transaction. Once
+    /// `deadline` has passed, we stop after the current batch; the versions
+    /// that are left are removed the next time the deployment is pruned
+    pub(crate) fn prune(
+        &self,
+        site: Arc<Site>,
+        earliest_block: BlockNumber,
+        deadline: Instant,
+    ) -> Result<usize, StoreError> {
+        const PRUNE_BATCH_SIZE: usize = 10_000;
+
+        let conn = self.get_conn()?;
+        let layout = self.layout(&conn, site.cheap_clone())?;
+
+        // Move the earliest block forward in the same transaction that
+        // removes the first batch so that queries never see the history
+        // before `earliest_block` with versions missing
+        let mut removed = conn.transaction(|| -> Result<_, StoreError> {
+            deployment::set_earliest_block_number(&conn, site.as_ref(), earliest_block)?;
+            layout.prune(&conn, earliest_block, PRUNE_BATCH_SIZE)
+        })?;
+        let mut count = removed;
+        while removed == PRUNE_BATCH_SIZE && Instant::now() < deadline {
+            removed = conn.transaction(|| layout.prune(&conn, earliest_block, PRUNE_BATCH_SIZE))?;
+            count += removed;
+        }
+        Ok(count)
+    }
 }
 
 /// Methods that back the trait `graph::components::Store`, but have small
 /// variations in their signatures
 impl DeploymentStore {
+    pub(crate) fn deployment_stats(
+        &self,
+        site: Arc<Site>,
+    ) -> Result<status::DeploymentStats, StoreError> {
+        let conn = self.get_conn()?;
+        let tables = catalog::table_stats(&conn, &site)?;
+        let latest_block = Self::block_ptr_with_conn(&site.deployment, &conn)?;
+        Ok(status::DeploymentStats {
+            subgraph: site.deployment.to_string(),
+            shard: site.shard.as_str().to_string(),
+            namespace: site.namespace.to_string(),
+            latest_block: latest_block.map(Into::into),
+            tables,
+        })
+    }
+
     pub(crate) fn block_ptr(&self, site: &Site) -> Result<Option<BlockPtr>, StoreError> {
         let conn = self.get_conn()?;
         Self::block_ptr_with_conn(&site.deployment, &conn)
@@ -915,6 +1065,93 @@ impl DeploymentStore {
         Ok(event)
     }
 
+    /// Write the changes of several consecutive blocks in one transaction.
+    /// Each block's changes are applied with that block's number so that
+    /// the entity versions get the same block ranges as when the blocks
+    /// are transacted one by one; the block pointer only moves once
+    pub(crate) fn transact_block_batch(
+        &self,
+        site: Arc<Site>,
+        blocks: &[BlockOperations],
+        stopwatch: StopwatchMetrics,
+    ) -> Result<StoreEvent, StoreError> {
+        let last = blocks.last().ok_or_else(|| {
+            constraint_violation!("transact_block_batch needs at least one block")
+        })?;
+
+        if blocks
+            .iter()
+            .flat_map(|block| block.mods.iter())
+            .any(|modification| modification.entity_key().subgraph_id != site.deployment)
+        {
+            panic!("transact_block_batch must affect only entities in the subgraph");
+        }
+
+        let conn = {
+            let _section = stopwatch.start_section("transact_blocks_get_conn");
+            self.get_conn()?
+        };
+
+        let event = conn.transaction(|| -> Result<_, StoreError> {
+            // `forward_block_ptr` only checks that the last block is ahead
+            // of the subgraph; every block has to be, and the blocks have
+            // to be in ascending order, or we would write the changes of a
+            // block twice
+            let mut previous = deployment::block_ptr(&conn, &site.deployment)?;
+            for block in blocks {
+                if let Some(previous) = &previous {
+                    if previous.number >= block.block_ptr.number {
+                        return Err(StoreError::DuplicateBlockProcessing(
+                            site.deployment.clone(),
+                            block.block_ptr.number,
+                        ));
+                    }
+                }
+                previous = Some(block.block_ptr.clone());
+            }
+
+            let event: StoreEvent = blocks.iter().flat_map(|block| block.mods.iter()).collect();
+
+            let layout = self.layout(&conn, site.clone())?;
+            let section = stopwatch.start_section("apply_entity_modifications");
+            let mut count = 0;
+            for block in blocks {
+                count += self.apply_entity_modifications(
+                    &conn,
+                    layout.as_ref(),
+                    &block.mods,
+                    &block.block_ptr,
+                    stopwatch.cheap_clone(),
+                )?;
+                dynds::insert(
+                    &conn,
+                    &site.deployment,
+                    &block.data_sources,
+                    &block.block_ptr,
+                )?;
+            }
+            deployment::update_entity_count(
+                &conn,
+                site.as_ref(),
+                layout.count_query.as_str(),
+                count,
+            )?;
+            section.end();
+
+            deployment::forward_block_ptr(&conn, &site.deployment, &last.block_ptr)?;
+
+            if let Some(cursor) = &last.firehose_cursor {
+                if cursor != "" {
+                    deployment::update_firehose_cursor(&conn, &site.deployment, cursor)?;
+                }
+            }
+
+            Ok(event)
+        })?;
+
+        Ok(event)
+    }
+
     fn rewind_with_conn(
         &self,
         conn: &PgConnection,
@@ -938,6 +1175,19 @@ impl DeploymentStore {
                 }
             }
 
+            // Don't revert into history that was pruned
+            let (_, earliest_block) = deployment::history(&conn, site.as_ref())?;
+            if earliest_block > block_ptr_to.number {
+                return Err(anyhow!(
+                    "Can not revert subgraph `{}` to block {} as its history \
+                    before block {} has been pruned",
+                    site.deployment.clone(),
+                    block_ptr_to.number,
+                    earliest_block
+                )
+                .into());
+            }
+
             deployment::revert_block_ptr(&conn, &site.deployment, block_ptr_to.clone())?;
 
             // Revert the data
@@ -63,6 +63,8 @@ pub struct DeploymentDetail {
     current_reorg_depth: i32,
     max_reorg_depth: i32,
     firehose_cursor: Option<String>,
+    history_blocks: Option<i32>,
+    earliest_block_number: i32,
 }
 
 #[derive(Queryable, QueryableByName)]
@@ -374,6 +376,8 @@ impl TryFrom<StoredDeploymentEntity> for SubgraphDeploymentEntity {
             reorg_count: detail.reorg_count,
             current_reorg_depth: detail.current_reorg_depth,
             max_reorg_depth: detail.max_reorg_depth,
+            history_blocks: detail.history_blocks,
+            earliest_block_number: detail.earliest_block_number,
         })
     }
 }
@@ -0,0 +1,1093 @@
+//! Export all data and metadata of a deployment into a directory so that
+//! it can be analyzed outside of `graph-node` or restored into another
+//! installation.
+//!
+//! A dump consists of one data file per table in the deployment's
+//! `Layout` and a `manifest.json` that describes the deployment and the
+//! tables. The data files are either CSV or Parquet files. Since we keep
+//! the `block_range` of every row, a dump contains the full history of
+//! every entity.
+//!
+//! Each CSV file has a header row; its first two columns are always `vid`
+//! and `block_range`, followed by the columns of the table in the order in
+//! which they are listed in the manifest.
+//!
+//! All values in CSV files are written in their Postgres text representation so that
+//! they can be read back with a simple cast to the column's type. That
+//! means that `BigDecimal` and `BigInt` values are written as decimal
+//! strings, `Bytes` and `BytesId` as `\x..` hex strings, lists as Postgres
+//! array literals, and fulltext columns in the text format of a `tsvector`.
+//! `NULL` is written as an unquoted empty field, every other value is
+//! quoted, which is the convention that Postgres' `COPY .. (format csv)`
+//! also uses.
+//!
+//! Parquet files are meant for analyzing the data with other tools. Their
+//! first columns are `vid`, and the block range split into
+//! `block_range_start` and `block_range_end`, where a missing end means
+//! that the entity version is current. The columns of the table follow in
+//! the order in which they are listed in the manifest, and are all
+//! optional; whether they can actually be null is recorded in the
+//! manifest. `Boolean` and `Int` become Parquet booleans and 32 bit
+//! integers, `Bytes` and `BytesId` binary values. All other types,
+//! including `BigInt` and `BigDecimal` whose values do not fit into any
+//! Parquet number type, are written as UTF-8 strings. Lists are Parquet
+//! lists of these types.
+//!
+//! The entire dump is read in one repeatable read transaction so that the
+//! data of all tables and the head block in the manifest are consistent
+//! with each other, even if the deployment is indexing while we dump it.
+//!
+//! Restoring a dump creates a new deployment from the manifest and loads
+//! the CSV files with `COPY`, keeping the `vid` of every row. The restored
+//! deployment starts out at the head block of the dump so that indexing
+//! resumes from there. Only dumps in CSV format can be restored.
+use std::{
+    convert::TryFrom,
+    fmt,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+    str::FromStr,
+    sync::Arc,
+};
+
+use diesel::{
+    prelude::{ExpressionMethods, QueryDsl, RunQueryDsl},
+    sql_query,
+    sql_types::{Array, BigInt, Nullable, Text},
+    PgConnection,
+};
+use graph::{
+    components::store::{EntityType, StoredDynamicDataSource},
+    data::subgraph::schema::{SubgraphHealth, SubgraphManifestEntity},
+    prelude::{
+        anyhow, bigdecimal::ToPrimitive, serde_json, BigDecimal, BlockNumber, BlockPtr,
+        Deserialize, Serialize, StoreError, SubgraphDeploymentEntity,
+    },
+};
+
+use parquet::{
+    basic::Compression,
+    column::writer::ColumnWriter,
+    data_type::ByteArray,
+    errors::ParquetError,
+    file::{
+        properties::WriterProperties,
+        writer::{FileWriter, RowGroupWriter, SerializedFileWriter},
+    },
+    schema::parser::parse_message_type,
+};
+
+use crate::{
+    block_range::BLOCK_RANGE_COLUMN,
+    deployment, detail, dynds,
+    relational::{Column, ColumnType, Layout, Table, VID_COLUMN},
+};
+
+/// The version of the dump format. Needs to be bumped whenever the
+/// format changes in a way that makes older dumps unreadable
+pub const DUMP_VERSION: u32 = 1;
+
+/// The name of the file that holds the `Manifest` of a dump
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+/// The number of rows we read from the database at a time
+const BATCH_SIZE: i64 = 10_000;
+
+/// The format of the data files of a dump
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    Csv,
+    Parquet,
+}
+
+impl Default for Format {
+    fn default() -> Self {
+        Format::Csv
+    }
+}
+
+impl Format {
+    fn extension(&self) -> &'static str {
+        match self {
+            Format::Csv => "csv",
+            Format::Parquet => "parquet",
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = StoreError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(Format::Csv),
+            "parquet" => Ok(Format::Parquet),
+            _ => Err(StoreError::Unknown(anyhow!(
+                "unknown dump format `{}`; use `csv` or `parquet`",
+                s
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.extension())
+    }
+}
+
+/// A block pointer in a form that can be written into the manifest
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Ptr {
+    pub hash: String,
+    pub number: BlockNumber,
+}
+
+impl From<&BlockPtr> for Ptr {
+    fn from(ptr: &BlockPtr) -> Self {
+        Ptr {
+            hash: ptr.hash_hex(),
+            number: ptr.number,
+        }
+    }
+}
+
+impl TryFrom<&Ptr> for BlockPtr {
+    type Error = StoreError;
+
+    fn try_from(ptr: &Ptr) -> Result<Self, Self::Error> {
+        BlockPtr::try_from((ptr.hash.as_str(), ptr.number as i64))
+            .map_err(|e| StoreError::Unknown(anyhow!("invalid block pointer in dump: {}", e)))
+    }
+}
+
+/// Description of one column of a table in the dump
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ColumnInfo {
+    /// The name of the column in the database
+    pub name: String,
+    /// The name of the GraphQL attribute stored in this column
+    pub field: String,
+    /// The kind of `ColumnType`, e.g., `BigDecimal` or `Enum`
+    pub column_type: String,
+    /// The SQL type of the column, without a trailing `[]` for lists
+    pub sql_type: String,
+    pub list: bool,
+    pub nullable: bool,
+    /// The possible values if this column holds an enum
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub enum_values: Option<Vec<String>>,
+}
+
+impl From<&Column> for ColumnInfo {
+    fn from(column: &Column) -> Self {
+        let column_type = match &column.column_type {
+            ColumnType::Boolean => "Boolean",
+            ColumnType::BigDecimal => "BigDecimal",
+            ColumnType::BigInt => "BigInt",
+            ColumnType::Bytes => "Bytes",
+            ColumnType::Int => "Int",
+            ColumnType::String => "String",
+            ColumnType::TSVector(_) => "TSVector",
+            ColumnType::Enum(_) => "Enum",
+            ColumnType::BytesId => "BytesId",
+        };
+        let enum_values = match &column.column_type {
+            ColumnType::Enum(enum_type) => Some(enum_type.values.iter().cloned().collect()),
+            _ => None,
+        };
+        ColumnInfo {
+            name: column.name.as_str().to_string(),
+            field: column.field.clone(),
+            column_type: column_type.to_string(),
+            sql_type: column.column_type.sql_type().to_string(),
+            list: column.is_list(),
+            nullable: column.is_nullable(),
+            enum_values,
+        }
+    }
+}
+
+/// Description of one table in the dump
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TableInfo {
+    /// The GraphQL type of the entities in this table
+    pub entity_type: String,
+    /// The name of the table in the database
+    pub name: String,
+    /// The name of the data file, relative to the dump directory
+    pub file: String,
+    /// The number of rows (entity versions) in the file
+    pub rows: usize,
+    /// The columns of the table, not including `vid` and `block_range`
+    pub columns: Vec<ColumnInfo>,
+}
+
+impl TableInfo {
+    /// The entity type of the entities in this table
+    pub fn entity_type(&self) -> EntityType {
+        EntityType::new(self.entity_type.clone())
+    }
+}
+
+/// A dynamic data source of the deployment
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DataSourceInfo {
+    pub name: String,
+    /// The address of the contract as a hex string; only set for Ethereum
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+    /// The account of a NEAR data source
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub account: Option<String>,
+    pub abi: String,
+    pub start_block: BlockNumber,
+    /// The block in which the data source was created
+    pub creation_block: Ptr,
+    pub context: Option<String>,
+}
+
+/// The contents of `manifest.json`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Manifest {
+    pub version: u32,
+    /// The format of the data files
+    #[serde(default)]
+    pub format: Format,
+    pub deployment: String,
+    pub network: String,
+    pub spec_version: String,
+    pub description: Option<String>,
+    pub repository: Option<String>,
+    pub features: Vec<String>,
+    /// The GraphQL schema of the deployment as provided by the user
+    pub schema: String,
+    pub earliest_block: Option<Ptr>,
+    /// How many blocks of history the deployment keeps, if it is pruned
+    #[serde(default)]
+    pub history_blocks: Option<BlockNumber>,
+    /// The earliest block for which the dump contains all history
+    #[serde(default)]
+    pub earliest_block_number: BlockNumber,
+    /// The block up to which the deployment had been indexed when it was
+    /// dumped
+    pub head: Option<Ptr>,
+    pub tables: Vec<TableInfo>,
+    pub data_sources: Vec<DataSourceInfo>,
+}
+
+impl Manifest {
+    /// Read the manifest of the dump in `dir`
+    pub fn read(dir: &Path) -> Result<Self, StoreError> {
+        let path = dir.join(MANIFEST_FILE);
+        let file =
+            File::open(&path).map_err(|e| anyhow!("can not open {}: {}", path.display(), e))?;
+        let manifest: Manifest = serde_json::from_reader(file)
+            .map_err(|e| anyhow!("can not read {}: {}", path.display(), e))?;
+        if manifest.version != DUMP_VERSION {
+            return Err(StoreError::Unknown(anyhow!(
+                "the dump in {} has version {} but we can only read version {}",
+                dir.display(),
+                manifest.version,
+                DUMP_VERSION
+            )));
+        }
+        Ok(manifest)
+    }
+
+    /// The metadata for a deployment restored from this dump. The
+    /// deployment does not have a head block yet; that only gets set once
+    /// all the data has been loaded
+    pub(crate) fn deployment_entity(&self) -> Result<SubgraphDeploymentEntity, StoreError> {
+        let earliest_block = self
+            .earliest_block
+            .as_ref()
+            .map(BlockPtr::try_from)
+            .transpose()?;
+        Ok(SubgraphDeploymentEntity {
+            manifest: SubgraphManifestEntity {
+                spec_version: self.spec_version.clone(),
+                description: self.description.clone(),
+                repository: self.repository.clone(),
+                features: self.features.clone(),
+                schema: self.schema.clone(),
+            },
+            failed: false,
+            health: SubgraphHealth::Healthy,
+            synced: false,
+            fatal_error: None,
+            non_fatal_errors: vec![],
+            earliest_block,
+            latest_block: None,
+            graft_base: None,
+            graft_block: None,
+            reorg_count: 0,
+            current_reorg_depth: 0,
+            max_reorg_depth: 0,
+            history_blocks: self.history_blocks,
+            earliest_block_number: self.earliest_block_number,
+        })
+    }
+
+    fn write(&self, dir: &Path) -> Result<(), StoreError> {
+        let path = dir.join(MANIFEST_FILE);
+        let file =
+            File::create(&path).map_err(|e| anyhow!("can not create {}: {}", path.display(), e))?;
+        serde_json::to_writer_pretty(file, self)
+            .map_err(|e| anyhow!("can not write {}: {}", path.display(), e))?;
+        Ok(())
+    }
+}
+
+#[derive(QueryableByName)]
+struct DumpRow {
+    #[sql_type = "BigInt"]
+    vid: i64,
+    #[sql_type = "Array<Nullable<Text>>"]
+    values: Vec<Option<String>>,
+}
+
+/// Write `value` as one CSV field. `None` becomes an empty field, all other
+/// values are quoted so that we can tell `NULL` and the empty string apart
+fn write_field(out: &mut impl Write, value: Option<&str>) -> std::io::Result<()> {
+    if let Some(value) = value {
+        out.write_all(b"\"")?;
+        out.write_all(value.replace('"', "\"\"").as_bytes())?;
+        out.write_all(b"\"")?;
+    }
+    Ok(())
+}
+
+fn write_row<'a>(
+    out: &mut impl Write,
+    values: impl Iterator<Item = Option<&'a str>>,
+) -> std::io::Result<()> {
+    for (i, value) in values.enumerate() {
+        if i > 0 {
+            out.write_all(b",")?;
+        }
+        write_field(out, value)?;
+    }
+    out.write_all(b"\n")
+}
+
+/// Read all rows of `table` in batches of `BATCH_SIZE` rows, ordered by
+/// `vid`, and pass each batch to `f`. The `values` of each row are the
+/// `expressions` evaluated for that row. Return the number of rows read
+fn for_each_batch(
+    conn: &PgConnection,
+    table: &Table,
+    expressions: &[String],
+    mut f: impl FnMut(&[DumpRow]) -> Result<(), StoreError>,
+) -> Result<usize, StoreError> {
+    let query = format!(
+        "select {vid} as vid, array[{values}] as values \
+           from {table} \
+          where {vid} >= $1 \
+          order by {vid} \
+          limit $2",
+        vid = VID_COLUMN,
+        values = expressions.join(", "),
+        table = table.qualified_name
+    );
+
+    let mut rows = 0;
+    let mut next_vid = 0;
+    loop {
+        let batch = sql_query(&query)
+            .bind::<BigInt, _>(next_vid)
+            .bind::<BigInt, _>(BATCH_SIZE)
+            .load::<DumpRow>(conn)?;
+        let last_vid = match batch.last() {
+            Some(row) => row.vid,
+            None => break,
+        };
+        f(&batch)?;
+        rows += batch.len();
+        next_vid = last_vid + 1;
+    }
+    Ok(rows)
+}
+
+/// Write all rows of `table` into the CSV file `path`
+fn dump_csv(conn: &PgConnection, table: &Table, path: &Path) -> Result<usize, StoreError> {
+    let io_err = |e: std::io::Error| anyhow!("error writing {}: {}", path.display(), e);
+
+    let mut out = BufWriter::new(File::create(path).map_err(io_err)?);
+
+    let header = [VID_COLUMN, BLOCK_RANGE_COLUMN]
+        .iter()
+        .map(|name| name.to_string())
+        .chain(table.columns.iter().map(|c| c.name.as_str().to_string()))
+        .collect::<Vec<_>>()
+        .join(",");
+    writeln!(out, "{}", header).map_err(io_err)?;
+
+    let expressions = std::iter::once(BLOCK_RANGE_COLUMN.to_string())
+        .chain(table.columns.iter().map(|c| c.name.quoted()))
+        .map(|name| format!("{}::text", name))
+        .collect::<Vec<_>>();
+    let rows = for_each_batch(conn, table, &expressions, |batch| {
+        for row in batch {
+            let vid = row.vid.to_string();
+            let values = std::iter::once(Some(vid.as_str()))
+                .chain(row.values.iter().map(|value| value.as_deref()));
+            write_row(&mut out, values).map_err(io_err)?;
+        }
+        Ok(())
+    })?;
+    out.flush().map_err(io_err)?;
+    Ok(rows)
+}
+
+/// The type in which we store the values of a column in a Parquet file
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ParquetType {
+    Boolean,
+    Int32,
+    Int64,
+    Utf8,
+    Binary,
+}
+
+impl ParquetType {
+    fn for_column(column_type: &ColumnType) -> Self {
+        match column_type {
+            ColumnType::Boolean => ParquetType::Boolean,
+            ColumnType::Int => ParquetType::Int32,
+            ColumnType::Bytes | ColumnType::BytesId => ParquetType::Binary,
+            ColumnType::BigDecimal
+            | ColumnType::BigInt
+            | ColumnType::String
+            | ColumnType::TSVector(_)
+            | ColumnType::Enum(_) => ParquetType::Utf8,
+        }
+    }
+
+    /// The physical type and the annotation for the logical type of a
+    /// field with this type in a Parquet schema
+    fn schema(&self) -> (&'static str, &'static str) {
+        match self {
+            ParquetType::Boolean => ("BOOLEAN", ""),
+            ParquetType::Int32 => ("INT32", ""),
+            ParquetType::Int64 => ("INT64", ""),
+            ParquetType::Utf8 => ("BYTE_ARRAY", " (UTF8)"),
+            ParquetType::Binary => ("BYTE_ARRAY", ""),
+        }
+    }
+}
+
+/// A column of a Parquet file
+struct ParquetColumn {
+    name: String,
+    ptype: ParquetType,
+    optional: bool,
+    list: bool,
+}
+
+impl ParquetColumn {
+    fn new(name: &str, ptype: ParquetType, optional: bool, list: bool) -> Self {
+        ParquetColumn {
+            name: name.to_string(),
+            ptype,
+            optional,
+            list,
+        }
+    }
+
+    /// The columns of the Parquet file for `table`
+    fn for_table(table: &Table) -> Vec<ParquetColumn> {
+        vec![
+            ParquetColumn::new(VID_COLUMN, ParquetType::Int64, false, false),
+            ParquetColumn::new("block_range_start", ParquetType::Int32, false, false),
+            ParquetColumn::new("block_range_end", ParquetType::Int32, true, false),
+        ]
+        .into_iter()
+        .chain(table.columns.iter().map(|column| {
+            ParquetColumn::new(
+                column.name.as_str(),
+                ParquetType::for_column(&column.column_type),
+                true,
+                column.is_list(),
+            )
+        }))
+        .collect()
+    }
+
+    /// The definition of this column in a Parquet schema. Lists use the
+    /// standard three-level structure for Parquet lists
+    fn schema(&self) -> String {
+        let (physical, logical) = self.ptype.schema();
+        if self.list {
+            format!(
+                "OPTIONAL group {} (LIST) {{ REPEATED group list {{ OPTIONAL {} element{}; }} }}",
+                self.name, physical, logical
+            )
+        } else {
+            let repetition = if self.optional {
+                "OPTIONAL"
+            } else {
+                "REQUIRED"
+            };
+            format!("{} {} {}{};", repetition, physical, self.name, logical)
+        }
+    }
+}
+
+enum Values {
+    Boolean(Vec<bool>),
+    Int32(Vec<i32>),
+    Int64(Vec<i64>),
+    ByteArray(Vec<ByteArray>),
+}
+
+/// The values of one column in one row group, together with their
+/// definition and repetition levels
+struct ColumnChunk<'a> {
+    column: &'a ParquetColumn,
+    values: Values,
+    def: Vec<i16>,
+    rep: Vec<i16>,
+}
+
+impl<'a> ColumnChunk<'a> {
+    fn new(column: &'a ParquetColumn) -> Self {
+        let values = match column.ptype {
+            ParquetType::Boolean => Values::Boolean(Vec::new()),
+            ParquetType::Int32 => Values::Int32(Vec::new()),
+            ParquetType::Int64 => Values::Int64(Vec::new()),
+            ParquetType::Utf8 | ParquetType::Binary => Values::ByteArray(Vec::new()),
+        };
+        ColumnChunk {
+            column,
+            values,
+            def: Vec::new(),
+            rep: Vec::new(),
+        }
+    }
+
+    /// Add the value of this column for one row. `value` is the Postgres
+    /// text representation of the value, and for lists a JSON array of the
+    /// text representations of the list's elements
+    fn push(&mut self, value: Option<&str>) -> Result<(), StoreError> {
+        if self.column.list {
+            return self.push_list(value);
+        }
+        match value {
+            Some(value) => {
+                if self.column.optional {
+                    self.def.push(1);
+                }
+                self.push_value(value)
+            }
+            None if self.column.optional => {
+                self.def.push(0);
+                Ok(())
+            }
+            None => Err(StoreError::Unknown(anyhow!(
+                "column {} can not be null",
+                self.column.name
+            ))),
+        }
+    }
+
+    /// Add a list value. The definition levels are 0 for a null list, 1
+    /// for an empty list, 2 for a null element and 3 for an element that is
+    /// present. The repetition level is 0 for the first entry of a row and
+    /// 1 for all later entries
+    fn push_list(&mut self, value: Option<&str>) -> Result<(), StoreError> {
+        let value = match value {
+            Some(value) => value,
+            None => {
+                self.def.push(0);
+                self.rep.push(0);
+                return Ok(());
+            }
+        };
+        let elements: Vec<Option<String>> = serde_json::from_str(value).map_err(|e| {
+            anyhow!(
+                "invalid list `{}` in column {}: {}",
+                value,
+                self.column.name,
+                e
+            )
+        })?;
+        if elements.is_empty() {
+            self.def.push(1);
+            self.rep.push(0);
+        }
+        for (i, element) in elements.iter().enumerate() {
+            self.rep.push(if i == 0 { 0 } else { 1 });
+            match element {
+                Some(element) => {
+                    self.def.push(3);
+                    self.push_value(element)?;
+                }
+                None => self.def.push(2),
+            }
+        }
+        Ok(())
+    }
+
+    fn push_value(&mut self, value: &str) -> Result<(), StoreError> {
+        let column = self.column;
+        let invalid = || {
+            StoreError::Unknown(anyhow!(
+                "invalid value `{}` in column {}",
+                value,
+                column.name
+            ))
+        };
+        match &mut self.values {
+            Values::Boolean(values) => values.push(match value {
+                "true" => true,
+                "false" => false,
+                _ => return Err(invalid()),
+            }),
+            Values::Int32(values) => values.push(value.parse().map_err(|_| invalid())?),
+            Values::Int64(values) => values.push(value.parse().map_err(|_| invalid())?),
+            Values::ByteArray(values) => match column.ptype {
+                ParquetType::Binary => {
+                    values.push(ByteArray::from(parse_bytes(value).ok_or_else(invalid)?))
+                }
+                _ => values.push(ByteArray::from(value)),
+            },
+        }
+        Ok(())
+    }
+
+    fn write(&self, writer: &mut ColumnWriter) -> Result<(), ParquetError> {
+        let def = if self.column.optional || self.column.list {
+            Some(self.def.as_slice())
+        } else {
+            None
+        };
+        let rep = if self.column.list {
+            Some(self.rep.as_slice())
+        } else {
+            None
+        };
+        match (writer, &self.values) {
+            (ColumnWriter::BoolColumnWriter(writer), Values::Boolean(values)) => {
+                writer.write_batch(values, def, rep)?
+            }
+            (ColumnWriter::Int32ColumnWriter(writer), Values::Int32(values)) => {
+                writer.write_batch(values, def, rep)?
+            }
+            (ColumnWriter::Int64ColumnWriter(writer), Values::Int64(values)) => {
+                writer.write_batch(values, def, rep)?
+            }
+            (ColumnWriter::ByteArrayColumnWriter(writer), Values::ByteArray(values)) => {
+                writer.write_batch(values, def, rep)?
+            }
+            _ => {
+                return Err(ParquetError::General(format!(
+                    "unexpected column writer for column {}",
+                    self.column.name
+                )))
+            }
+        };
+        Ok(())
+    }
+}
+
+/// Parse the text representation `[start,end)` or `[start,)` of a block
+/// range
+fn parse_block_range(range: &str) -> Result<(BlockNumber, Option<BlockNumber>), StoreError> {
+    let invalid = || StoreError::Unknown(anyhow!("invalid block range `{}`", range));
+    let (start, end) = range
+        .strip_prefix('[')
+        .and_then(|range| range.strip_suffix(')'))
+        .and_then(|range| range.split_once(','))
+        .ok_or_else(invalid)?;
+    let start = start.parse().map_err(|_| invalid())?;
+    let end = match end {
+        "" => None,
+        end => Some(end.parse().map_err(|_| invalid())?),
+    };
+    Ok((start, end))
+}
+
+/// Parse the `\x..` hex text representation of a `bytea`
+fn parse_bytes(value: &str) -> Option<Vec<u8>> {
+    value
+        .strip_prefix("\\x")
+        .and_then(|hex| hex::decode(hex).ok())
+}
+
+/// Write all rows of `table` into the Parquet file `path`, using one row
+/// group per batch of rows
+fn dump_parquet(conn: &PgConnection, table: &Table, path: &Path) -> Result<usize, StoreError> {
+    let pq_err =
+        |e: ParquetError| StoreError::Unknown(anyhow!("error writing {}: {}", path.display(), e));
+
+    let columns = ParquetColumn::for_table(table);
+    let schema = format!(
+        "message {} {{ {} }}",
+        table.name.as_str(),
+        columns
+            .iter()
+            .map(ParquetColumn::schema)
+            .collect::<Vec<_>>()
+            .join(" ")
+    );
+    let schema = Arc::new(parse_message_type(&schema).map_err(pq_err)?);
+    let props = Arc::new(
+        WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .build(),
+    );
+    let file =
+        File::create(path).map_err(|e| anyhow!("error writing {}: {}", path.display(), e))?;
+    let mut writer = SerializedFileWriter::new(file, schema, props).map_err(pq_err)?;
+
+    // Lists are cast to `text[]` first so that we get the same text
+    // representation for their elements as for scalar values
+    let expressions = std::iter::once(format!("{}::text", BLOCK_RANGE_COLUMN))
+        .chain(table.columns.iter().map(|c| {
+            if c.is_list() {
+                format!("array_to_json({}::text[])::text", c.name.quoted())
+            } else {
+                format!("{}::text", c.name.quoted())
+            }
+        }))
+        .collect::<Vec<_>>();
+    let rows = for_each_batch(conn, table, &expressions, |batch| {
+        let mut chunks: Vec<_> = columns.iter().map(ColumnChunk::new).collect();
+        for row in batch {
+            let (start, end) = parse_block_range(row.values[0].as_deref().unwrap_or_default())?;
+            chunks[0].push(Some(&row.vid.to_string()))?;
+            chunks[1].push(Some(&start.to_string()))?;
+            chunks[2].push(end.map(|end| end.to_string()).as_deref())?;
+            for (chunk, value) in chunks[3..].iter_mut().zip(&row.values[1..]) {
+                chunk.push(value.as_deref())?;
+            }
+        }
+
+        let mut row_group = writer.next_row_group().map_err(pq_err)?;
+        for chunk in &chunks {
+            let mut column = row_group.next_column().map_err(pq_err)?.ok_or_else(|| {
+                anyhow!("missing column {} in {}", chunk.column.name, path.display())
+            })?;
+            chunk.write(&mut column).map_err(pq_err)?;
+            row_group.close_column(column).map_err(pq_err)?;
+        }
+        writer.close_row_group(row_group).map_err(pq_err)
+    })?;
+    writer.close().map_err(pq_err)?;
+    Ok(rows)
+}
+
+/// Write all rows of `table` into a data file in `dir`
+fn dump_table(
+    conn: &PgConnection,
+    table: &Table,
+    dir: &Path,
+    format: Format,
+) -> Result<TableInfo, StoreError> {
+    let file = format!("{}.{}", table.name.as_str(), format.extension());
+    let path = dir.join(&file);
+    let rows = match format {
+        Format::Csv => dump_csv(conn, table, &path)?,
+        Format::Parquet => dump_parquet(conn, table, &path)?,
+    };
+
+    Ok(TableInfo {
+        entity_type: table.object.to_string(),
+        name: table.name.as_str().to_string(),
+        file,
+        rows,
+        columns: table.columns.iter().map(ColumnInfo::from).collect(),
+    })
+}
+
+fn data_sources(conn: &PgConnection, layout: &Layout) -> Result<Vec<DataSourceInfo>, StoreError> {
+    use dynds::dynamic_ethereum_contract_data_source as decds;
+
+    let dds = decds::table
+        .filter(decds::deployment.eq(layout.site.deployment.as_str()))
+        .select((
+            decds::name,
+            decds::address,
+            decds::account,
+            decds::abi,
+            decds::start_block,
+            decds::ethereum_block_hash,
+            decds::ethereum_block_number,
+            decds::context,
+        ))
+        .order_by((decds::ethereum_block_number, decds::vid))
+        .load::<(
+            String,
+            Option<Vec<u8>>,
+            Option<String>,
+            String,
+            BlockNumber,
+            Vec<u8>,
+            BigDecimal,
+            Option<String>,
+        )>(conn)?;
+
+    let mut data_sources = Vec::new();
+    for (name, address, account, abi, start_block, block_hash, block_number, context) in dds {
+        let number = block_number.to_i32().ok_or_else(|| {
+            anyhow!(
+                "creation block {} of data source {} is not a valid block number",
+                block_number,
+                name
+            )
+        })?;
+        data_sources.push(DataSourceInfo {
+            name,
+            address: address.map(|address| format!("0x{}", hex::encode(address))),
+            account,
+            abi,
+            start_block,
+            creation_block: Ptr {
+                hash: format!("0x{}", hex::encode(block_hash)),
+                number,
+            },
+            context,
+        });
+    }
+    Ok(data_sources)
+}
+
+/// Write the data and metadata for the deployment with the given `layout`
+/// into `dir`, which must already exist. The data of each table is written
+/// in the given `format`
+pub fn dump(
+    conn: &PgConnection,
+    layout: &Layout,
+    dir: &Path,
+    format: Format,
+) -> Result<Manifest, StoreError> {
+    conn.build_transaction()
+        .read_only()
+        .repeatable_read()
+        .run(|| {
+            let site = layout.site.as_ref();
+            let entity = detail::deployment_entity(conn, site)?;
+            let head = deployment::block_ptr(conn, &site.deployment)?;
+
+            let mut tables: Vec<_> = layout.tables.values().collect();
+            tables.sort_by(|a, b| a.name.as_str().cmp(b.name.as_str()));
+            let tables = tables
+                .into_iter()
+                .map(|table| dump_table(conn, table, dir, format))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            let manifest = Manifest {
+                version: DUMP_VERSION,
+                format,
+                deployment: site.deployment.to_string(),
+                network: site.network.clone(),
+                spec_version: entity.manifest.spec_version,
+                description: entity.manifest.description,
+                repository: entity.manifest.repository,
+                features: entity.manifest.features,
+                schema: entity.manifest.schema,
+                earliest_block: entity.earliest_block.as_ref().map(Ptr::from),
+                history_blocks: entity.history_blocks,
+                earliest_block_number: entity.earliest_block_number,
+                head: head.as_ref().map(Ptr::from),
+                tables,
+                data_sources: data_sources(conn, layout)?,
+            };
+            manifest.write(dir)?;
+            Ok(manifest)
+        })
+}
+
+fn pg_err(e: postgres::Error) -> StoreError {
+    StoreError::Unknown(e.into())
+}
+
+/// Load the data of the dump in `dir` into the tables of `layout`. All
+/// tables are loaded in one transaction so that a failed restore does not
+/// leave partial data behind.
+pub(crate) fn load(
+    client: &mut postgres::Client,
+    layout: &Layout,
+    manifest: &Manifest,
+    dir: &Path,
+) -> Result<(), StoreError> {
+    let mut tx = client.transaction().map_err(pg_err)?;
+    for info in &manifest.tables {
+        let table = layout.table_for_entity(&info.entity_type())?;
+        for column in &info.columns {
+            if !table.columns.iter().any(|c| c.name.as_str() == column.name) {
+                return Err(StoreError::Unknown(anyhow!(
+                    "the dump has a column {} for table {} that the deployment does not have",
+                    column.name,
+                    info.name
+                )));
+            }
+        }
+
+        let columns = [VID_COLUMN, BLOCK_RANGE_COLUMN]
+            .iter()
+            .map(|name| name.to_string())
+            .chain(info.columns.iter().map(|c| format!("\"{}\"", c.name)))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let query = format!(
+            "copy {}({}) from stdin with (format csv, header)",
+            table.qualified_name, columns
+        );
+
+        let path = dir.join(&info.file);
+        let io_err = |e: io::Error| anyhow!("error reading {}: {}", path.display(), e);
+        let mut file = File::open(&path).map_err(io_err)?;
+        let mut writer = tx.copy_in(query.as_str()).map_err(pg_err)?;
+        io::copy(&mut file, &mut writer).map_err(io_err)?;
+        let rows = writer.finish().map_err(pg_err)?;
+        if rows as usize != info.rows {
+            return Err(StoreError::Unknown(anyhow!(
+                "expected {} rows in {} but loaded {}",
+                info.rows,
+                path.display(),
+                rows
+            )));
+        }
+
+        // Since we kept the `vid` of every row, we need to make sure that
+        // rows that get inserted later do not reuse them
+        let query = format!(
+            "select setval(pg_get_serial_sequence('{table}', '{vid}'), \
+                           coalesce(max({vid}), 0) + 1, false) \
+               from {table}",
+            table = table.qualified_name,
+            vid = VID_COLUMN
+        );
+        tx.batch_execute(&query).map_err(pg_err)?;
+    }
+    tx.commit().map_err(pg_err)
+}
+
+/// Finish restoring the deployment with the given `layout` after its data
+/// has been loaded: insert the dynamic data sources, count entities, and
+/// move the deployment to the head block of the dump
+pub(crate) fn finish(
+    conn: &PgConnection,
+    layout: &Layout,
+    manifest: &Manifest,
+) -> Result<(), StoreError> {
+    let site = layout.site.as_ref();
+
+    for ds in &manifest.data_sources {
+        let address = ds
+            .address
+            .as_ref()
+            .map(|address| {
+                hex::decode(address.trim_start_matches("0x")).map_err(|e| {
+                    anyhow!(
+                        "invalid address {} for data source {}: {}",
+                        address,
+                        ds.name,
+                        e
+                    )
+                })
+            })
+            .transpose()?;
+        let address = dynds::to_address(site.deployment.as_str(), &ds.name, address)?;
+        let creation_block = BlockPtr::try_from(&ds.creation_block)?;
+        let data_source = StoredDynamicDataSource {
+            name: ds.name.clone(),
+            address,
+            account: ds.account.clone(),
+            abi: ds.abi.clone(),
+            start_block: ds.start_block,
+            context: ds.context.clone(),
+            creation_block: Some(creation_block.number),
+        };
+        dynds::insert(conn, &site.deployment, &[data_source], &creation_block)?;
+    }
+
+    deployment::set_entity_count(conn, site, &layout.count_query)?;
+
+    if let Some(head) = &manifest.head {
+        let head = BlockPtr::try_from(head)?;
+        deployment::forward_block_ptr(conn, &site.deployment, &head)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_quoting() {
+        let mut out = Vec::new();
+        write_row(
+            &mut out,
+            vec![Some("1"), None, Some(""), Some("say \"hi\", bye")].into_iter(),
+        )
+        .unwrap();
+        assert_eq!(
+            "\"1\",,\"\",\"say \"\"hi\"\", bye\"\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+
+    #[test]
+    fn block_ranges() {
+        assert_eq!((1, Some(5)), parse_block_range("[1,5)").unwrap());
+        assert_eq!((7, None), parse_block_range("[7,)").unwrap());
+        assert!(parse_block_range("empty").is_err());
+        assert!(parse_block_range("[1,5]").is_err());
+        assert!(parse_block_range("[x,)").is_err());
+    }
+
+    #[test]
+    fn bytes() {
+        assert_eq!(Some(vec![0x01, 0xab]), parse_bytes("\\x01ab"));
+        assert_eq!(Some(vec![]), parse_bytes("\\x"));
+        assert_eq!(None, parse_bytes("01ab"));
+        assert_eq!(None, parse_bytes("\\xzz"));
+    }
+
+    #[test]
+    fn list_levels() {
+        let column = ParquetColumn::new("tags", ParquetType::Int32, true, true);
+        let mut chunk = ColumnChunk::new(&column);
+        chunk.push(None).unwrap();
+        chunk.push(Some("[]")).unwrap();
+        chunk.push(Some("[\"1\", null, \"3\"]")).unwrap();
+
+        assert_eq!(vec![0, 1, 3, 2, 3], chunk.def);
+        assert_eq!(vec![0, 0, 0, 1, 1], chunk.rep);
+        match &chunk.values {
+            Values::Int32(values) => assert_eq!(&vec![1, 3], values),
+            _ => panic!("expected int32 values"),
+        }
+        assert!(chunk.push(Some("[\"x\"]")).is_err());
+    }
+
+    #[test]
+    fn scalar_levels() {
+        let column = ParquetColumn::new("flag", ParquetType::Boolean, true, false);
+        let mut chunk = ColumnChunk::new(&column);
+        chunk.push(Some("true")).unwrap();
+        chunk.push(None).unwrap();
+        chunk.push(Some("false")).unwrap();
+        assert_eq!(vec![1, 0, 1], chunk.def);
+        assert!(chunk.rep.is_empty());
+
+        let column = ParquetColumn::new("vid", ParquetType::Int64, false, false);
+        let mut chunk = ColumnChunk::new(&column);
+        chunk.push(Some("17")).unwrap();
+        assert!(chunk.push(None).is_err());
+        assert!(chunk.def.is_empty());
+    }
+
+    #[test]
+    fn list_schema() {
+        let column = ParquetColumn::new("tags", ParquetType::Utf8, true, true);
+        let schema = format!("message thing {{ {} }}", column.schema());
+        assert!(parse_message_type(&schema).is_ok());
+    }
+}
@@ -12,7 +12,6 @@ use diesel::{insert_into, pg::PgConnection};
 use graph::{
     components::store::StoredDynamicDataSource,
     constraint_violation,
-    data::subgraph::Source,
     prelude::{
         bigdecimal::ToPrimitive, web3::types::H160, BigDecimal, BlockNumber, BlockPtr,
         DeploymentHash, StoreError,
@@ -26,7 +25,10 @@ table! {
     subgraphs.dynamic_ethereum_contract_data_source (vid) {
         vid -> BigInt,
         name -> Text,
-        address -> Binary,
+        // Ethereum data sources have an address, NEAR data sources an
+        // account
+        address -> Nullable<Binary>,
+        account -> Nullable<Text>,
         abi -> Text,
         start_block -> Integer,
         // Never read
@@ -37,27 +39,21 @@ table! {
     }
 }
 
-fn to_source(
+/// Turn the `address` column into an Ethereum address
+pub(crate) fn to_address(
     deployment: &str,
-    vid: i64,
-    address: Vec<u8>,
-    abi: String,
-    start_block: BlockNumber,
-) -> Result<Source, StoreError> {
-    if address.len() != 20 {
-        return Err(constraint_violation!(
-            "Data source address 0x`{:?}` for dynamic data source {} in deployment {} should have be 20 bytes long but is {} bytes long",
-            address, vid, deployment,
+    name: &str,
+    address: Option<Vec<u8>>,
+) -> Result<Option<H160>, StoreError> {
+    match address {
+        None => Ok(None),
+        Some(address) if address.len() == 20 => Ok(Some(H160::from_slice(&address))),
+        Some(address) => Err(constraint_violation!(
+            "Data source address 0x{} for dynamic data source {} in deployment {} should be 20 bytes long but is {} bytes long",
+            hex::encode(&address), name, deployment,
             address.len()
-        ));
+        )),
     }
-    let address = Some(H160::from_slice(address.as_slice()));
-
-    Ok(Source {
-        address,
-        abi,
-        start_block,
-    })
 }
 
 pub fn load(conn: &PgConnection, id: &str) -> Result<Vec<StoredDynamicDataSource>, StoreError> {
@@ -69,32 +65,35 @@ pub fn load(conn: &PgConnection, id: &str) -> Result<Vec<StoredDynamicDataSource
     let dds: Vec<_> = decds::table
         .filter(decds::deployment.eq(id))
         .select((
-            decds::vid,
             decds::name,
             decds::context,
             decds::address,
+            decds::account,
             decds::abi,
             decds::start_block,
             decds::ethereum_block_number,
         ))
         .order_by((decds::ethereum_block_number, decds::vid))
         .load::<(
-            i64,
             String,
             Option<String>,
-            Vec<u8>,
+            Option<Vec<u8>>,
+            Option<String>,
             String,
             BlockNumber,
             BigDecimal,
         )>(conn)?;
 
     let mut data_sources: Vec<StoredDynamicDataSource> = Vec::new();
-    for (vid, name, context, address, abi, start_block, creation_block) in dds.into_iter() {
-        let source = to_source(id, vid, address, abi, start_block)?;
+    for (name, context, address, account, abi, start_block, creation_block) in dds.into_iter() {
+        let address = to_address(id, &name, address)?;
         let creation_block = creation_block.to_i32();
         let data_source = StoredDynamicDataSource {
             name,
-            source,
+            address,
+            account,
+            abi,
+            start_block,
             context,
             creation_block,
         };
@@ -128,29 +127,25 @@ pub(crate) fn insert(
         .map(|ds| {
             let StoredDynamicDataSource {
                 name,
-                source:
-                    Source {
-                        address,
-                        abi,
-                        start_block,
-                    },
+                address,
+                account,
+                abi,
+                start_block,
                 context,
                 creation_block: _,
             } = ds;
-            let address = match address {
-                Some(address) => address.as_bytes().to_vec(),
-                None => {
-                    return Err(constraint_violation!(
-                        "dynamic data sources must have an address, but `{}` has none",
-                        name
-                    ));
-                }
-            };
+            if address.is_none() && account.is_none() {
+                return Err(constraint_violation!(
+                    "dynamic data sources must have an address or an account, but `{}` has neither",
+                    name
+                ));
+            }
             Ok((
                 decds::deployment.eq(deployment.as_str()),
                 decds::name.eq(name),
                 decds::context.eq(context),
-                decds::address.eq(address),
+                decds::address.eq(address.map(|address| address.as_bytes().to_vec())),
+                decds::account.eq(account),
                 decds::abi.eq(abi),
                 decds::start_block.eq(start_block),
                 decds::ethereum_block_number.eq(sql(&format!("{}::numeric", block_ptr.number))),
@@ -194,9 +189,9 @@ pub(crate) fn copy(
     let query = format!(
         "\
       insert into subgraphs.dynamic_ethereum_contract_data_source(name,
-             address, abi, start_block, ethereum_block_hash,
+             address, account, abi, start_block, ethereum_block_hash,
              ethereum_block_number, deployment, context)
-      select e.name, e.address, e.abi, e.start_block,
+      select e.name, e.address, e.account, e.abi, e.start_block,
              e.ethereum_block_hash, e.ethereum_block_number, $2 as deployment,
              e.context
         from {src_nsp}.dynamic_ethereum_contract_data_source e
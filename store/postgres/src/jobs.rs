@@ -6,8 +6,9 @@ use std::time::{Duration, Instant};
 use async_trait::async_trait;
 use diesel::{prelude::RunQueryDsl, sql_query, sql_types::Double};
 
+use graph::blockchain::REORG_THRESHOLD;
 use graph::env::env_var;
-use graph::prelude::{chrono, error, lazy_static, Logger, MetricsRegistry, StoreError};
+use graph::prelude::{chrono, error, info, lazy_static, Logger, MetricsRegistry, StoreError};
 use graph::prometheus::Gauge;
 use graph::util::jobs::{Job, Runner};
 
@@ -46,6 +47,11 @@ pub fn register(
     runner.register(
         Arc::new(UnusedJob::new(store.subgraph_store())),
         Duration::from_secs(2 * 60 * 60),
+    );
+
+    runner.register(
+        Arc::new(PruneJob::new(store.subgraph_store())),
+        Duration::from_secs(10 * 60),
     )
 }
 
@@ -214,3 +220,62 @@ impl Job for UnusedJob {
         }
     }
 }
+
+/// A job that removes old history from deployments that only keep a
+/// limited number of blocks of history
+struct PruneJob {
+    store: Arc<SubgraphStore>,
+}
+
+impl PruneJob {
+    fn new(store: Arc<SubgraphStore>) -> PruneJob {
+        PruneJob { store }
+    }
+}
+
+#[async_trait]
+impl Job for PruneJob {
+    fn name(&self) -> &str {
+        "Prune the history of deployments"
+    }
+
+    async fn run(&self, logger: &Logger) {
+        // Work on pruning for about 5 minutes
+        const PRUNE_DEADLINE: Duration = Duration::from_secs(5 * 60);
+
+        let deadline = Instant::now() + PRUNE_DEADLINE;
+
+        // Pruning never removes history for the last `REORG_THRESHOLD`
+        // blocks so that reverting blocks is always possible
+        let prunable = match self.store.prunable_deployments(*REORG_THRESHOLD) {
+            Ok(prunable) => prunable,
+            Err(e) => {
+                error!(logger, "failed to list prunable deployments"; "error" => e.to_string());
+                return;
+            }
+        };
+
+        for (id, earliest_block) in prunable {
+            let pruning_start = Instant::now();
+            match self.store.prune(id, earliest_block, deadline) {
+                Ok(count) => {
+                    info!(logger, "pruned deployment history";
+                                  "sgd" => id.to_string(),
+                                  "earliest_block" => earliest_block,
+                                  "versions" => count,
+                                  "time_ms" => pruning_start.elapsed().as_millis());
+                }
+                Err(e) => {
+                    error!(logger, "failed to prune deployment history";
+                                   "sgd" => id.to_string(),
+                                   "error" => e.to_string());
+                }
+            }
+            // Stop pruning after a while to not block other jobs for too
+            // long; the remaining deployments get pruned on the next run
+            if Instant::now() > deadline {
+                return;
+            }
+        }
+    }
+}
@@ -34,6 +34,7 @@ mod copy;
 mod deployment;
 mod deployment_store;
 mod detail;
+mod dump;
 mod dynds;
 mod functions;
 mod jobs;
@@ -76,7 +77,7 @@ pub use self::subgraph_store::{unused, DeploymentPlacer, Shard, SubgraphStore, P
 pub mod command_support {
     pub mod catalog {
         pub use crate::block_store::primary as block_store;
-        pub use crate::catalog::{account_like, set_account_like};
+        pub use crate::catalog::{account_like, set_account_like, table_stats};
         pub use crate::copy::{copy_state, copy_table_state};
         pub use crate::primary::Connection;
         pub use crate::primary::{
@@ -84,6 +85,12 @@ pub mod command_support {
             subgraph_version, Site,
         };
     }
+    pub mod dump {
+        pub use crate::dump::{
+            ColumnInfo, DataSourceInfo, Format, Manifest, Ptr, TableInfo, DUMP_VERSION,
+            MANIFEST_FILE,
+        };
+    }
     pub use crate::primary::Namespace;
     pub use crate::relational::{Catalog, Column, ColumnType, Layout, SqlName};
 }
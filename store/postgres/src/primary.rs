@@ -39,7 +39,7 @@ use graph::{data::subgraph::schema::generate_entity_id, prelude::StoreEvent};
 use itertools::Itertools;
 use maybe_owned::MaybeOwned;
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     convert::TryFrom,
     convert::TryInto,
     fmt,
@@ -114,6 +114,14 @@ table! {
     }
 }
 
+table! {
+    public.persisted_queries(hash) {
+        hash -> Text,
+        query -> Text,
+        created_at -> Timestamptz,
+    }
+}
+
 /// We used to support different layout schemes. The old 'Split' scheme
 /// which used JSONB layout has been removed, and we will only deal
 /// with relational layout. Trying to do anything with a 'Split' subgraph
@@ -1096,6 +1104,28 @@ impl<'a> Connection<'a> {
         self.create_site(shard, subgraph.clone(), network, true)
     }
 
+    /// Create a new site for a deployment that is restored from a dump
+    /// into `shard`. The new site only becomes the active site if there is
+    /// no other active site for the deployment. It is an error if `shard`
+    /// already has a site for the deployment
+    pub fn restore_site(
+        &self,
+        shard: Shard,
+        subgraph: &DeploymentHash,
+        network: String,
+    ) -> Result<Site, StoreError> {
+        let conn = self.conn.as_ref();
+        if queries::find_site_in_shard(conn, subgraph, &shard)?.is_some() {
+            return Err(StoreError::Unknown(anyhow!(
+                "deployment {} already exists in shard {}",
+                subgraph,
+                shard
+            )));
+        }
+        let active = queries::find_active_site(conn, subgraph)?.is_none();
+        self.create_site(shard, subgraph.clone(), network, active)
+    }
+
     pub fn assigned_node(&self, site: &Site) -> Result<Option<NodeId>, StoreError> {
         queries::assigned_node(self.conn.as_ref(), site)
     }
@@ -1474,6 +1504,55 @@ impl<'a> Connection<'a> {
             .map_err(|e| anyhow!("error looking up ens_name for hash {}: {}", hash, e).into())
     }
 
+    pub fn find_persisted_query(&self, hash: &str) -> Result<Option<String>, StoreError> {
+        use persisted_queries as pq;
+
+        Ok(pq::table
+            .select(pq::query)
+            .find(hash)
+            .get_result::<String>(self.conn.as_ref())
+            .optional()?)
+    }
+
+    /// Register `query` under `hash`; if the hash is already registered,
+    /// leave the existing entry alone
+    pub fn register_persisted_query(&self, hash: &str, query: &str) -> Result<(), StoreError> {
+        use persisted_queries as pq;
+
+        insert_into(pq::table)
+            .values((pq::hash.eq(hash), pq::query.eq(query)))
+            .on_conflict_do_nothing()
+            .execute(self.conn.as_ref())?;
+        Ok(())
+    }
+
+    /// Return all persisted queries as `(hash, query, created_at)`, oldest
+    /// first
+    pub fn persisted_queries(&self) -> Result<Vec<(String, String, String)>, StoreError> {
+        use persisted_queries as pq;
+
+        Ok(pq::table
+            .select((pq::hash, pq::query, sql::<Text>("created_at::text")))
+            .order_by(pq::created_at)
+            .load(self.conn.as_ref())?)
+    }
+
+    pub fn persisted_query_count(&self) -> Result<usize, StoreError> {
+        use persisted_queries as pq;
+
+        let count = pq::table.count().get_result::<i64>(self.conn.as_ref())?;
+        Ok(count as usize)
+    }
+
+    /// Remove the persisted query with `hash` and return `true` if there
+    /// was such a query
+    pub fn remove_persisted_query(&self, hash: &str) -> Result<bool, StoreError> {
+        use persisted_queries as pq;
+
+        let count = delete(pq::table.find(hash)).execute(self.conn.as_ref())?;
+        Ok(count > 0)
+    }
+
     pub fn record_active_copy(&self, src: &Site, dst: &Site) -> Result<(), StoreError> {
         use active_copies as cp;
 
@@ -1489,6 +1568,18 @@ impl<'a> Connection<'a> {
         Ok(())
     }
 
+    /// Return the ids of all deployments that are the source of a copy or
+    /// graft that has not finished yet
+    pub fn active_copy_sources(&self) -> Result<HashSet<DeploymentId>, StoreError> {
+        use active_copies as cp;
+
+        Ok(cp::table
+            .select(cp::src)
+            .load::<DeploymentId>(self.conn.as_ref())?
+            .into_iter()
+            .collect())
+    }
+
     pub fn copy_finished(&self, dst: &Site) -> Result<(), StoreError> {
         use active_copies as cp;
 
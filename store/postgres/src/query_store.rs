@@ -45,6 +45,34 @@ impl QueryStoreTrait for QueryStore {
         self.store.execute_query(&conn, self.site.clone(), query)
     }
 
+    fn aggregate(
+        &self,
+        query: AggregateQuery,
+    ) -> Result<Vec<BTreeMap<String, r::Value>>, QueryExecutionError> {
+        assert_eq!(&self.site.deployment, &query.subgraph_id);
+        let conn = self
+            .store
+            .get_replica_conn(self.replica_id)
+            .map_err(|e| QueryExecutionError::StoreError(e.into()))?;
+        self.store
+            .execute_aggregate(&conn, self.site.clone(), query)
+    }
+
+    fn entity_changes(&self, blocks: BlockSpan) -> Result<EntityChanges, StoreError> {
+        let conn = self.store.get_replica_conn(self.replica_id)?;
+        self.store.entity_changes(&conn, self.site.clone(), blocks)
+    }
+
+    fn ancestors(&self, block: &BlockPtr, count: BlockNumber) -> Result<Vec<BlockPtr>, StoreError> {
+        self.chain_store
+            .ancestors(block, count)
+            .map_err(StoreError::from)
+    }
+
+    fn chain_head_ptr(&self) -> Result<Option<BlockPtr>, StoreError> {
+        self.chain_store.chain_head_ptr().map_err(StoreError::from)
+    }
+
     /// Return true if the deployment with the given id is fully synced,
     /// and return false otherwise. Errors from the store are passed back up
     async fn is_deployment_synced(&self) -> Result<bool, Error> {
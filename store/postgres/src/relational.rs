@@ -25,8 +25,9 @@ use std::time::{Duration, Instant};
 use crate::{
     primary::{Namespace, Site},
     relational_queries::{
-        ClampRangeQuery, ConflictingEntityQuery, EntityData, FilterCollection, FilterQuery,
-        FindManyQuery, FindQuery, InsertQuery, RevertClampQuery, RevertRemoveQuery,
+        AggregateData, AggregateQuery, ClampRangeQuery, ConflictingEntityQuery, EntityChangeData,
+        EntityChangesQuery, EntityData, FilterCollection, FilterQuery, FindManyQuery, FindQuery,
+        InsertQuery, PruneQuery, RevertClampQuery, RevertRemoveQuery,
     },
 };
 use graph::components::store::EntityType;
@@ -35,9 +36,10 @@ use graph::data::schema::{FulltextConfig, FulltextDefinition, Schema, SCHEMA_TYP
 use graph::data::store::BYTES_SCALAR;
 use graph::data::subgraph::schema::{POI_OBJECT, POI_TABLE};
 use graph::prelude::{
-    anyhow, info, BlockNumber, DeploymentHash, Entity, EntityChange, EntityCollection,
-    EntityFilter, EntityKey, EntityOrder, EntityRange, Logger, QueryExecutionError, StoreError,
-    StoreEvent, ValueType, BLOCK_NUMBER_MAX,
+    anyhow, info, Attribute, AttributeAggregate, BlockNumber, BlockSpan, DeploymentHash, Entity,
+    EntityChange, EntityChangeKind, EntityCollection, EntityFilter, EntityKey, EntityOrder,
+    EntityRange, EntityVersionChange, Logger, QueryExecutionError, StoreError, StoreEvent,
+    ValueType, BLOCK_NUMBER_MAX,
 };
 
 use crate::block_range::BLOCK_RANGE_COLUMN;
@@ -607,6 +609,7 @@ impl Layout {
         order: EntityOrder,
         range: EntityRange,
         block: BlockNumber,
+        history: Option<BlockSpan>,
         query_id: Option<String>,
     ) -> Result<Vec<T>, QueryExecutionError> {
         fn log_query_timing(
@@ -646,6 +649,7 @@ impl Layout {
             order,
             range,
             block,
+            history,
             query_id,
         )?;
         let query_clone = query.clone();
@@ -676,6 +680,122 @@ impl Layout {
             .collect()
     }
 
+    /// Compute `aggregates` over the entities of type `entity_type` that
+    /// match `filter` for every `interval` blocks in `blocks`, grouping
+    /// entities by the attributes in `group_by`
+    pub fn aggregate<T: crate::relational_queries::FromEntityData>(
+        &self,
+        conn: &PgConnection,
+        entity_type: &EntityType,
+        filter: Option<EntityFilter>,
+        aggregates: &[AttributeAggregate],
+        group_by: &[Attribute],
+        blocks: BlockSpan,
+        interval: BlockNumber,
+        range: Option<EntityRange>,
+        query_id: Option<String>,
+    ) -> Result<Vec<T>, QueryExecutionError> {
+        let table = self.table_for_entity(entity_type)?;
+        let query = AggregateQuery::new(
+            table,
+            filter.as_ref(),
+            aggregates,
+            group_by,
+            blocks,
+            interval,
+            range,
+            query_id,
+        )?;
+        let query_clone = query.clone();
+
+        let rows = conn
+            .transaction(|| {
+                if let Some(ref timeout_sql) = *STATEMENT_TIMEOUT {
+                    conn.batch_execute(timeout_sql)?;
+                }
+                query.load::<AggregateData>(conn)
+            })
+            .map_err(|e| {
+                QueryExecutionError::ResolveEntitiesError(format!(
+                    "{}, query = {:?}",
+                    e,
+                    debug_query(&query_clone).to_string()
+                ))
+            })?;
+        query_clone.deserialize(rows).map_err(|e| e.into())
+    }
+
+    /// Reconstruct the changes that the blocks in `blocks` made to the
+    /// entities in this layout from the entity versions. A block inserted
+    /// an entity if it created a version of it without ending an older one,
+    /// it deleted the entity if it ended a version without creating a new
+    /// one, and it updated the entity if it did both
+    pub fn entity_changes(
+        &self,
+        conn: &PgConnection,
+        blocks: BlockSpan,
+    ) -> Result<Vec<EntityVersionChange>, QueryExecutionError> {
+        let tables = self
+            .tables
+            .values()
+            .filter(|table| table.object != *POI_OBJECT)
+            .map(|table| table.as_ref())
+            .collect();
+        let query = EntityChangesQuery::new(tables, blocks);
+        let query_clone = query.clone();
+
+        let rows = conn
+            .transaction(|| {
+                if let Some(ref timeout_sql) = *STATEMENT_TIMEOUT {
+                    conn.batch_execute(timeout_sql)?;
+                }
+                query.load::<EntityChangeData>(conn)
+            })
+            .map_err(|e| {
+                QueryExecutionError::ResolveEntitiesError(format!(
+                    "{}, query = {:?}",
+                    e,
+                    debug_query(&query_clone).to_string()
+                ))
+            })?;
+
+        // Map (block, entity type, id) to the version that the block ended
+        // and the version that it created
+        let mut versions: BTreeMap<_, (Option<Entity>, Option<Entity>)> = BTreeMap::new();
+        for row in rows {
+            let (block, ended) = (row.block, row.ended);
+            let data = row.entity_data();
+            let entity_type = data.entity_type();
+            let entity: Entity = data.deserialize_with_layout(self)?;
+            let id = entity.id().map_err(StoreError::from)?;
+            let entry = versions.entry((block, entity_type, id)).or_default();
+            if ended {
+                entry.0 = Some(entity);
+            } else {
+                entry.1 = Some(entity);
+            }
+        }
+
+        Ok(versions
+            .into_iter()
+            .filter_map(|((block, entity_type, entity_id), versions)| {
+                let (kind, data) = match versions {
+                    (None, Some(created)) => (EntityChangeKind::Insert, created),
+                    (Some(_), Some(created)) => (EntityChangeKind::Update, created),
+                    (Some(ended), None) => (EntityChangeKind::Delete, ended),
+                    (None, None) => return None,
+                };
+                Some(EntityVersionChange {
+                    block,
+                    entity_type,
+                    entity_id,
+                    kind,
+                    data,
+                })
+            })
+            .collect())
+    }
+
     pub fn update<'a>(
         &'a self,
         conn: &PgConnection,
@@ -777,6 +897,31 @@ impl Layout {
         Ok((StoreEvent::new(changes), count))
     }
 
+    /// Remove up to `limit` entity versions that are not visible at
+    /// `earliest_block` or any later block, and return how many versions
+    /// were removed. When that is less than `limit`, no such versions are
+    /// left. The table for the Proof of Indexing is left alone since
+    /// indexers need to be able to produce a PoI for any block
+    pub fn prune(
+        &self,
+        conn: &PgConnection,
+        earliest_block: BlockNumber,
+        limit: usize,
+    ) -> Result<usize, StoreError> {
+        let mut count = 0;
+        for table in self.tables.values() {
+            if count >= limit {
+                break;
+            }
+            if table.object == *POI_OBJECT {
+                continue;
+            }
+            count +=
+                PruneQuery::new(table, earliest_block, (limit - count) as i64).execute(conn)?;
+        }
+        Ok(count)
+    }
+
     /// Revert the metadata (dynamic data sources and related entities) for
     /// the given `subgraph`.
     ///
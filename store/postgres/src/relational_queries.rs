@@ -14,9 +14,10 @@ use diesel::Connection;
 use lazy_static::lazy_static;
 
 use graph::prelude::{
-    anyhow, r, serde_json, Attribute, BlockNumber, ChildMultiplicity, Entity, EntityCollection,
-    EntityFilter, EntityKey, EntityLink, EntityOrder, EntityRange, EntityWindow, ParentLink,
-    QueryExecutionError, StoreError, Value,
+    anyhow, r, serde_json, AggregateFunction, Attribute, AttributeAggregate, BlockNumber,
+    BlockSpan, ChildMultiplicity, Entity, EntityCollection, EntityCursor, EntityFilter, EntityKey,
+    EntityLink, EntityOrder, EntityRange, EntityWindow, ParentLink, QueryExecutionError,
+    StoreError, Value,
 };
 use graph::{
     components::store::{AttributeNames, EntityType},
@@ -36,7 +37,10 @@ use crate::relational::{
 };
 use crate::sql_value::SqlValue;
 use crate::{
-    block_range::{BlockRange, BlockRangeContainsClause, BLOCK_RANGE_COLUMN, BLOCK_RANGE_CURRENT},
+    block_range::{
+        BlockRange, BlockRangeContainsClause, BlockVersions, BlockVersionsClause,
+        BLOCK_RANGE_COLUMN, BLOCK_RANGE_CURRENT,
+    },
     primary::Namespace,
 };
 
@@ -1539,16 +1543,21 @@ impl<'a> TableLink<'a> {
 #[derive(Copy, Clone)]
 enum ParentLimit<'a> {
     /// Limit children to a specific parent
-    Outer,
+    Outer(&'a SortKey<'a>, &'a FilterRange),
     /// Limit children by sorting and picking top n
     Ranked(&'a SortKey<'a>, &'a FilterRange),
 }
 
 impl<'a> ParentLimit<'a> {
-    fn filter(&self, out: &mut AstPass<Pg>) {
+    /// Restrict children to the ones for a specific parent if needed, and
+    /// to the ones that lie within the cursors of the range
+    fn filter(&self, table: &Table, out: &mut AstPass<Pg>) -> QueryResult<()> {
         match self {
-            ParentLimit::Outer => out.push_sql(" and q.id = p.id"),
-            ParentLimit::Ranked(_, _) => (),
+            ParentLimit::Outer(sort_key, range) => {
+                out.push_sql(" and q.id = p.id");
+                range.keyset(sort_key, table, out)
+            }
+            ParentLimit::Ranked(sort_key, range) => range.keyset(sort_key, table, out),
         }
     }
 
@@ -1562,14 +1571,18 @@ impl<'a> ParentLimit<'a> {
     }
 
     /// Include a 'limit {num_parents}+1' clause for single-object queries
-    /// if that is needed
-    fn single_limit(&self, num_parents: usize, out: &mut AstPass<Pg>) {
-        match self {
-            ParentLimit::Ranked(_, _) => {
+    /// if that is needed. When we list the history of entities, a parent
+    /// can have one child for each version of that child
+    fn single_limit(&self, num_parents: usize, versions: BlockVersions, out: &mut AstPass<Pg>) {
+        match (self, versions) {
+            (_, BlockVersions::Span(_)) => {
+                // all versions of the child are needed
+            }
+            (ParentLimit::Ranked(_, _), BlockVersions::At(_)) => {
                 out.push_sql(" limit ");
                 out.push_sql(&(num_parents + 1).to_string());
             }
-            ParentLimit::Outer => {
+            (ParentLimit::Outer(_, _), BlockVersions::At(_)) => {
                 // limiting is taken care of in a wrapper around
                 // the query we are currently building
             }
@@ -1642,7 +1655,7 @@ impl<'a> FilterWindow<'a> {
         &self,
         column: &Column,
         limit: ParentLimit<'_>,
-        block: BlockNumber,
+        versions: BlockVersions,
         out: &mut AstPass<Pg>,
     ) -> QueryResult<()> {
         assert!(column.is_list());
@@ -1665,8 +1678,8 @@ impl<'a> FilterWindow<'a> {
         out.push_sql(" from ");
         out.push_sql(self.table.qualified_name.as_str());
         out.push_sql(" c where ");
-        BlockRangeContainsClause::new(&self.table, "c.", block).walk_ast(out.reborrow())?;
-        limit.filter(out);
+        BlockVersionsClause::new(&self.table, "c.", versions).walk_ast(out.reborrow())?;
+        limit.filter(self.table, out)?;
         out.push_sql(" and p.id = any(c.");
         out.push_identifier(column.name.as_str())?;
         out.push_sql(")");
@@ -1680,7 +1693,7 @@ impl<'a> FilterWindow<'a> {
         &self,
         column: &Column,
         limit: ParentLimit<'_>,
-        block: BlockNumber,
+        versions: BlockVersions,
         out: &mut AstPass<Pg>,
     ) -> QueryResult<()> {
         assert!(column.is_list());
@@ -1701,8 +1714,8 @@ impl<'a> FilterWindow<'a> {
         out.push_sql(") as p(id), ");
         out.push_sql(self.table.qualified_name.as_str());
         out.push_sql(" c where ");
-        BlockRangeContainsClause::new(&self.table, "c.", block).walk_ast(out.reborrow())?;
-        limit.filter(out);
+        BlockVersionsClause::new(&self.table, "c.", versions).walk_ast(out.reborrow())?;
+        limit.filter(self.table, out)?;
         out.push_sql(" and c.");
         out.push_identifier(column.name.as_str())?;
         out.push_sql(" @> array[p.id]");
@@ -1713,7 +1726,7 @@ impl<'a> FilterWindow<'a> {
             column.bind_ids(&self.ids, out)?;
         }
         self.and_filter(out.reborrow())?;
-        limit.single_limit(self.ids.len(), out);
+        limit.single_limit(self.ids.len(), versions, out);
         Ok(())
     }
 
@@ -1721,7 +1734,7 @@ impl<'a> FilterWindow<'a> {
         &self,
         column: &Column,
         limit: ParentLimit<'_>,
-        block: BlockNumber,
+        versions: BlockVersions,
         out: &mut AstPass<Pg>,
     ) -> QueryResult<()> {
         assert!(!column.is_list());
@@ -1744,8 +1757,8 @@ impl<'a> FilterWindow<'a> {
         out.push_sql(" from ");
         out.push_sql(self.table.qualified_name.as_str());
         out.push_sql(" c where ");
-        BlockRangeContainsClause::new(&self.table, "c.", block).walk_ast(out.reborrow())?;
-        limit.filter(out);
+        BlockVersionsClause::new(&self.table, "c.", versions).walk_ast(out.reborrow())?;
+        limit.filter(self.table, out)?;
         out.push_sql(" and p.id = c.");
         out.push_identifier(column.name.as_str())?;
         self.and_filter(out.reborrow())?;
@@ -1758,7 +1771,7 @@ impl<'a> FilterWindow<'a> {
         &self,
         column: &Column,
         limit: ParentLimit<'_>,
-        block: BlockNumber,
+        versions: BlockVersions,
         out: &mut AstPass<Pg>,
     ) -> QueryResult<()> {
         assert!(!column.is_list());
@@ -1774,12 +1787,12 @@ impl<'a> FilterWindow<'a> {
         out.push_sql(") as p(id), ");
         out.push_sql(self.table.qualified_name.as_str());
         out.push_sql(" c where ");
-        BlockRangeContainsClause::new(&self.table, "c.", block).walk_ast(out.reborrow())?;
-        limit.filter(out);
+        BlockVersionsClause::new(&self.table, "c.", versions).walk_ast(out.reborrow())?;
+        limit.filter(self.table, out)?;
         out.push_sql(" and p.id = c.");
         out.push_identifier(column.name.as_str())?;
         self.and_filter(out.reborrow())?;
-        limit.single_limit(self.ids.len(), out);
+        limit.single_limit(self.ids.len(), versions, out);
         Ok(())
     }
 
@@ -1787,7 +1800,7 @@ impl<'a> FilterWindow<'a> {
         &self,
         child_ids: &Vec<Vec<Option<SafeString>>>,
         limit: ParentLimit<'_>,
-        block: BlockNumber,
+        versions: BlockVersions,
         out: &mut AstPass<Pg>,
     ) -> QueryResult<()> {
         // Generate
@@ -1813,8 +1826,8 @@ impl<'a> FilterWindow<'a> {
         out.push_sql(" from ");
         out.push_sql(self.table.qualified_name.as_str());
         out.push_sql(" c where ");
-        BlockRangeContainsClause::new(&self.table, "c.", block).walk_ast(out.reborrow())?;
-        limit.filter(out);
+        BlockVersionsClause::new(&self.table, "c.", versions).walk_ast(out.reborrow())?;
+        limit.filter(self.table, out)?;
         out.push_sql(" and c.id = any(p.child_ids)");
         self.and_filter(out.reborrow())?;
         limit.restrict(out)?;
@@ -1826,7 +1839,7 @@ impl<'a> FilterWindow<'a> {
         &self,
         child_ids: &Vec<String>,
         limit: ParentLimit<'_>,
-        block: BlockNumber,
+        versions: BlockVersions,
         out: &mut AstPass<Pg>,
     ) -> QueryResult<()> {
         // Generate
@@ -1842,8 +1855,8 @@ impl<'a> FilterWindow<'a> {
         out.push_sql(")) as p(id, child_id), ");
         out.push_sql(self.table.qualified_name.as_str());
         out.push_sql(" c where ");
-        BlockRangeContainsClause::new(&self.table, "c.", block).walk_ast(out.reborrow())?;
-        limit.filter(out);
+        BlockVersionsClause::new(&self.table, "c.", versions).walk_ast(out.reborrow())?;
+        limit.filter(self.table, out)?;
         if *TYPED_CHILDREN_SET_SIZE > 0 {
             let mut child_set: Vec<&str> = child_ids.iter().map(|id| id.as_str()).collect();
             child_set.sort();
@@ -1858,14 +1871,14 @@ impl<'a> FilterWindow<'a> {
         out.push_sql(" and ");
         out.push_sql("c.id = p.child_id");
         self.and_filter(out.reborrow())?;
-        limit.single_limit(self.ids.len(), out);
+        limit.single_limit(self.ids.len(), versions, out);
         Ok(())
     }
 
     fn children(
         &self,
         limit: ParentLimit<'_>,
-        block: BlockNumber,
+        versions: BlockVersions,
         mut out: AstPass<Pg>,
     ) -> QueryResult<()> {
         match &self.link {
@@ -1873,21 +1886,21 @@ impl<'a> FilterWindow<'a> {
                 use ChildMultiplicity::*;
                 if column.is_list() {
                     match multiplicity {
-                        Many => self.children_type_a(column, limit, block, &mut out),
-                        Single => self.child_type_a(column, limit, block, &mut out),
+                        Many => self.children_type_a(column, limit, versions, &mut out),
+                        Single => self.child_type_a(column, limit, versions, &mut out),
                     }
                 } else {
                     match multiplicity {
-                        Many => self.children_type_b(column, limit, block, &mut out),
-                        Single => self.child_type_b(column, limit, block, &mut out),
+                        Many => self.children_type_b(column, limit, versions, &mut out),
+                        Single => self.child_type_b(column, limit, versions, &mut out),
                     }
                 }
             }
             TableLink::Parent(ParentIds::List(child_ids)) => {
-                self.children_type_c(child_ids, limit, block, &mut out)
+                self.children_type_c(child_ids, limit, versions, &mut out)
             }
             TableLink::Parent(ParentIds::Scalar(child_ids)) => {
-                self.child_type_d(child_ids, limit, block, &mut out)
+                self.child_type_d(child_ids, limit, versions, &mut out)
             }
         }
     }
@@ -1898,14 +1911,15 @@ impl<'a> FilterWindow<'a> {
     fn children_uniform(
         &self,
         sort_key: &SortKey,
-        block: BlockNumber,
+        range: &FilterRange,
+        versions: BlockVersions,
         mut out: AstPass<Pg>,
     ) -> QueryResult<()> {
         out.push_sql("select '");
         out.push_sql(self.table.object.as_str());
         out.push_sql("' as entity, c.id, c.vid, p.id::text as g$parent_id");
         sort_key.select(&mut out)?;
-        self.children(ParentLimit::Outer, block, out)
+        self.children(ParentLimit::Outer(sort_key, range), versions, out)
     }
 
     /// Collect all the parent id's from all windows
@@ -2005,6 +2019,9 @@ pub enum SortKey<'a> {
     IdAsc,
     /// Order by `id desc`
     IdDesc,
+    /// Order by `id asc, block_range asc` so that all versions of an entity
+    /// are listed in the order in which they were created
+    Versions,
     /// Order by some other column; `column` will never be `id`
     Key {
         column: &'a Column,
@@ -2079,6 +2096,11 @@ impl<'a> SortKey<'a> {
                 }
                 Ok(())
             }
+            SortKey::Versions => {
+                out.push_sql(", c.");
+                out.push_sql(BLOCK_RANGE_COLUMN);
+                Ok(())
+            }
             SortKey::Key {
                 column,
                 value: _,
@@ -2119,6 +2141,13 @@ impl<'a> SortKey<'a> {
                 }
                 Ok(())
             }
+            SortKey::Versions => {
+                out.push_sql("order by ");
+                out.push_identifier(PRIMARY_KEY_COLUMN)?;
+                out.push_sql(", ");
+                out.push_sql(BLOCK_RANGE_COLUMN);
+                Ok(())
+            }
             SortKey::Key {
                 column,
                 value,
@@ -2145,6 +2174,13 @@ impl<'a> SortKey<'a> {
                 out.push_sql(" desc");
                 Ok(())
             }
+            SortKey::Versions => {
+                out.push_sql("order by g$parent_id, ");
+                out.push_identifier(PRIMARY_KEY_COLUMN)?;
+                out.push_sql(", ");
+                out.push_sql(BLOCK_RANGE_COLUMN);
+                Ok(())
+            }
             SortKey::Key {
                 column,
                 value,
@@ -2209,10 +2245,82 @@ impl<'a> SortKey<'a> {
     }
 }
 
-/// Generate `[limit {first}] [offset {skip}]
+/// Generate `[limit {first}] [offset {skip}]`. The `after` and `before`
+/// cursors of the range are turned into conditions on the rows by `keyset`
 #[derive(Debug, Clone)]
 pub struct FilterRange(EntityRange);
 
+impl FilterRange {
+    /// Check that the cursors of this range can be used with `sort_key`.
+    /// Since cursors come from clients, we also need to make sure that the
+    /// value in them has the right type for the column we sort by
+    fn check_cursors(&self, sort_key: &SortKey) -> Result<(), QueryExecutionError> {
+        let cursors = self.0.after.iter().chain(self.0.before.iter());
+        for cursor in cursors {
+            let invalid =
+                |reason: String| QueryExecutionError::InvalidCursor(cursor.encode(), reason);
+            match sort_key {
+                SortKey::None => {}
+                SortKey::Versions => {
+                    return Err(invalid(
+                        "cursors can not be used when listing the history of entities".to_owned(),
+                    ))
+                }
+                SortKey::IdAsc | SortKey::IdDesc => {
+                    if cursor.attribute != PRIMARY_KEY_COLUMN {
+                        return Err(invalid(format!(
+                            "the cursor is for a list ordered by `{}`, not by `{}`",
+                            cursor.attribute, PRIMARY_KEY_COLUMN
+                        )));
+                    }
+                }
+                SortKey::Key { column, .. } => {
+                    if column.is_fulltext() {
+                        return Err(invalid(
+                            "cursors can not be used with fulltext search".to_owned(),
+                        ));
+                    }
+                    if cursor.attribute != column.field {
+                        return Err(invalid(format!(
+                            "the cursor is for a list ordered by `{}`, not by `{}`",
+                            cursor.attribute, column.field
+                        )));
+                    }
+                    let fits = match (&cursor.value, &column.column_type) {
+                        (Value::Null, _)
+                        | (Value::String(_), ColumnType::String)
+                        | (Value::String(_), ColumnType::Enum(_))
+                        | (Value::Int(_), ColumnType::Int)
+                        | (Value::BigInt(_), ColumnType::BigInt)
+                        | (Value::BigDecimal(_), ColumnType::BigDecimal)
+                        | (Value::Bool(_), ColumnType::Boolean)
+                        | (Value::Bytes(_), ColumnType::Bytes) => true,
+                        _ => false,
+                    };
+                    if !fits {
+                        return Err(invalid(format!(
+                            "the value `{}` can not be compared to attribute `{}`",
+                            cursor.value, column.field
+                        )));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Generate ` and {keyset condition}` for each cursor in this range
+    fn keyset(&self, sort_key: &SortKey, table: &Table, out: &mut AstPass<Pg>) -> QueryResult<()> {
+        if let Some(cursor) = &self.0.after {
+            Keyset::new(sort_key, table, cursor, true).walk_ast(out.reborrow())?;
+        }
+        if let Some(cursor) = &self.0.before {
+            Keyset::new(sort_key, table, cursor, false).walk_ast(out.reborrow())?;
+        }
+        Ok(())
+    }
+}
+
 impl QueryFragment<Pg> for FilterRange {
     fn walk_ast(&self, mut out: AstPass<Pg>) -> QueryResult<()> {
         let range = &self.0;
@@ -2228,6 +2336,122 @@ impl QueryFragment<Pg> for FilterRange {
     }
 }
 
+/// Restrict rows to the ones that come strictly after (or before) `cursor`
+/// in the order given by `sort_key`. For `after` and a sort key
+/// `name asc, id asc`, we generate
+///
+///   and (c.name >= $value and (c.name > $value or c.id > $id)
+///        or c.name is null)
+///
+/// since Postgres sorts nulls last in ascending order. If the cursor itself
+/// has a null value, we generate
+///
+///   and (c.name is null and c.id > $id)
+///
+/// Descending order and `before` flip the comparisons, and nulls come
+/// first where that is how Postgres sorts them. The form of the condition
+/// makes it possible for Postgres to use the index on `name` to find the
+/// first row after the cursor rather than scanning all rows before it
+struct Keyset<'a> {
+    sort_key: &'a SortKey<'a>,
+    table: &'a Table,
+    cursor: &'a EntityCursor,
+    after: bool,
+}
+
+impl<'a> Keyset<'a> {
+    fn new(sort_key: &'a SortKey, table: &'a Table, cursor: &'a EntityCursor, after: bool) -> Self {
+        Keyset {
+            sort_key,
+            table,
+            cursor,
+            after,
+        }
+    }
+
+    fn push_id_cmp(&self, forward: bool, out: &mut AstPass<Pg>) -> QueryResult<()> {
+        let op = if forward {
+            Comparison::Greater
+        } else {
+            Comparison::Less
+        };
+        let id = Value::String(self.cursor.id.clone());
+        out.push_sql("c.");
+        out.push_identifier(PRIMARY_KEY_COLUMN)?;
+        out.push_sql(op.as_str());
+        QueryValue(&id, &self.table.primary_key().column_type).walk_ast(out.reborrow())
+    }
+}
+
+impl<'a> QueryFragment<Pg> for Keyset<'a> {
+    fn walk_ast(&self, mut out: AstPass<Pg>) -> QueryResult<()> {
+        match self.sort_key {
+            SortKey::None | SortKey::Versions => Ok(()),
+            SortKey::IdAsc => {
+                out.push_sql(" and ");
+                self.push_id_cmp(self.after, &mut out)
+            }
+            SortKey::IdDesc => {
+                out.push_sql(" and ");
+                self.push_id_cmp(!self.after, &mut out)
+            }
+            SortKey::Key {
+                column, direction, ..
+            } => {
+                // Whether the rows we want have larger values than the
+                // cursor, whether they have larger ids for the same value,
+                // and whether rows with nulls come after all other rows
+                // when going in that direction
+                let forward = (*direction == "asc") == self.after;
+                let (id_forward, nulls_ahead) = if *REVERSIBLE_ORDER_BY_OFF {
+                    // The old behavior sorts by `{column} {direction}
+                    // nulls last, id`
+                    (self.after, self.after)
+                } else {
+                    (forward, forward)
+                };
+                let name = column.name.as_str();
+
+                out.push_sql(" and (");
+                if self.cursor.value == Value::Null {
+                    out.push_sql("c.");
+                    out.push_identifier(name)?;
+                    if nulls_ahead {
+                        out.push_sql(" is null and ");
+                    } else {
+                        out.push_sql(" is not null or ");
+                    }
+                    self.push_id_cmp(id_forward, &mut out)?;
+                } else {
+                    let (op, op_or_equal) = if forward {
+                        (Comparison::Greater, Comparison::GreaterOrEqual)
+                    } else {
+                        (Comparison::Less, Comparison::LessOrEqual)
+                    };
+                    out.push_sql("c.");
+                    out.push_identifier(name)?;
+                    out.push_sql(op_or_equal.as_str());
+                    QueryValue(&self.cursor.value, &column.column_type).walk_ast(out.reborrow())?;
+                    out.push_sql(" and (c.");
+                    out.push_identifier(name)?;
+                    out.push_sql(op.as_str());
+                    QueryValue(&self.cursor.value, &column.column_type).walk_ast(out.reborrow())?;
+                    out.push_sql(" or ");
+                    self.push_id_cmp(id_forward, &mut out)?;
+                    out.push_sql(")");
+                    if nulls_ahead {
+                        out.push_sql(" or c.");
+                        out.push_identifier(name)?;
+                        out.push_sql(" is null");
+                    }
+                }
+                out.push_sql(")");
+                Ok(())
+            }
+        }
+    }
+}
+
 /// The parallel to `EntityQuery`.
 ///
 /// Details of how query generation for `FilterQuery` works can be found
@@ -2237,7 +2461,7 @@ pub struct FilterQuery<'a> {
     collection: &'a FilterCollection<'a>,
     sort_key: SortKey<'a>,
     range: FilterRange,
-    block: BlockNumber,
+    versions: BlockVersions,
     query_id: Option<String>,
 }
 
@@ -2248,6 +2472,7 @@ impl<'a> FilterQuery<'a> {
         order: EntityOrder,
         range: EntityRange,
         block: BlockNumber,
+        history: Option<BlockSpan>,
         query_id: Option<String>,
     ) -> Result<Self, QueryExecutionError> {
         // Get the name of the column we order by; if there is more than one
@@ -2257,13 +2482,25 @@ impl<'a> FilterQuery<'a> {
         let first_table = collection
             .first_table()
             .expect("an entity query always contains at least one entity type/table");
-        let sort_key = SortKey::new(order, first_table, filter)?;
+        // When we list the history of entities, the requested order is
+        // ignored since versions only make sense in the order in which
+        // they were created
+        let sort_key = match history {
+            Some(_) => SortKey::Versions,
+            None => SortKey::new(order, first_table, filter)?,
+        };
+        let range = FilterRange(range);
+        range.check_cursors(&sort_key)?;
+        let versions = match history {
+            Some(span) => BlockVersions::Span(span),
+            None => BlockVersions::At(block),
+        };
 
         Ok(FilterQuery {
             collection,
             sort_key,
-            range: FilterRange(range),
-            block,
+            range,
+            versions,
             query_id,
         })
     }
@@ -2272,6 +2509,8 @@ impl<'a> FilterQuery<'a> {
     ///     from schema.table c
     ///    where block_range @> $block
     ///      and query_filter
+    /// If we are listing the history of entities, the block range condition
+    /// becomes `block_range && int4range($from, $to, '[]')`
     /// Only used when the query is against a `FilterCollection::All`, i.e.
     /// when we do not need to window
     fn filtered_rows(
@@ -2284,11 +2523,12 @@ impl<'a> FilterQuery<'a> {
         out.push_sql(table.qualified_name.as_str());
         out.push_sql(" c");
         out.push_sql("\n where ");
-        BlockRangeContainsClause::new(&table, "c.", self.block).walk_ast(out.reborrow())?;
+        BlockVersionsClause::new(&table, "c.", self.versions).walk_ast(out.reborrow())?;
         if let Some(filter) = table_filter {
             out.push_sql(" and ");
             filter.walk_ast(out.reborrow())?;
         }
+        self.range.keyset(&self.sort_key, table, &mut out)?;
         out.push_sql("\n");
         Ok(())
     }
@@ -2346,7 +2586,7 @@ impl<'a> FilterQuery<'a> {
         out.push_sql("select c.*, p.id::text as g$parent_id");
         window.children(
             ParentLimit::Ranked(&self.sort_key, &self.range),
-            self.block,
+            self.versions,
             out.reborrow(),
         )?;
         out.push_sql(") c");
@@ -2471,7 +2711,7 @@ impl<'a> FilterQuery<'a> {
             if i > 0 {
                 out.push_sql("\nunion all\n");
             }
-            window.children_uniform(&self.sort_key, self.block, out.reborrow())?;
+            window.children_uniform(&self.sort_key, &self.range, self.versions, out.reborrow())?;
         }
         out.push_sql("\n");
         self.sort_key.order_by(&mut out)?;
@@ -2569,6 +2809,393 @@ impl<'a> LoadQuery<PgConnection, EntityData> for FilterQuery<'a> {
 
 impl<'a, Conn> RunQueryDsl<Conn> for FilterQuery<'a> {}
 
+/// One row of the result of an `AggregateQuery`; all values are packed into
+/// one JSONB object
+#[derive(QueryableByName)]
+pub struct AggregateData {
+    #[sql_type = "Jsonb"]
+    data: serde_json::Value,
+}
+
+/// The parallel to `graph::prelude::AggregateQuery`. We generate
+///
+///   select jsonb_build_object('block', b.block, 'count', count(c.vid),
+///                             'g', c."g", ...,
+///                             'f_sum', sum(c."f"), ...) as data
+///     from generate_series($from, $to, $interval) as b(block)
+///          left join lateral (
+///            select * from table c
+///             where c.block_range @> b.block
+///               and query_filter) c on true
+///    group by b.block, c."g", ...
+///    order by b.block, c."g", ...
+///    limit {first} offset {skip}
+///
+/// When grouping, we use an inner join so that there are no rows for
+/// blocks at which no entities match the filter
+#[derive(Debug, Clone)]
+pub struct AggregateQuery<'a> {
+    table: &'a Table,
+    filter: Option<QueryFilter<'a>>,
+    aggregates: Vec<(&'a Column, AggregateFunction)>,
+    group_by: Vec<&'a Column>,
+    blocks: BlockSpan,
+    interval: BlockNumber,
+    range: Option<FilterRange>,
+    query_id: Option<String>,
+}
+
+impl<'a> AggregateQuery<'a> {
+    pub fn new(
+        table: &'a Table,
+        filter: Option<&'a EntityFilter>,
+        aggregates: &'a [AttributeAggregate],
+        group_by: &'a [Attribute],
+        blocks: BlockSpan,
+        interval: BlockNumber,
+        range: Option<EntityRange>,
+        query_id: Option<String>,
+    ) -> Result<Self, QueryExecutionError> {
+        let filter = filter
+            .map(|filter| QueryFilter::new(filter, table))
+            .transpose()?;
+        let aggregates = aggregates
+            .iter()
+            .map(|aggregate| {
+                let column = table.column_for_field(&aggregate.attribute)?;
+                Self::check_column(column, aggregate.function)?;
+                Ok((column, aggregate.function))
+            })
+            .collect::<Result<Vec<_>, QueryExecutionError>>()?;
+        let group_by = group_by
+            .iter()
+            .map(|attribute| {
+                let column = table.column_for_field(attribute)?;
+                Self::check_group_column(column)?;
+                Ok(column)
+            })
+            .collect::<Result<Vec<_>, QueryExecutionError>>()?;
+        Self::check_names(&aggregates, &group_by)?;
+        Ok(AggregateQuery {
+            table,
+            filter,
+            aggregates,
+            group_by,
+            blocks,
+            interval,
+            range: range.map(FilterRange),
+            query_id,
+        })
+    }
+
+    /// Make sure that the values in a result row have different names.
+    /// The API schema does not offer grouped aggregates for types where
+    /// they would not
+    fn check_names(
+        aggregates: &[(&Column, AggregateFunction)],
+        group_by: &[&Column],
+    ) -> Result<(), StoreError> {
+        let mut names: HashSet<_> = aggregates
+            .iter()
+            .map(|(column, function)| Self::output_name(column, *function))
+            .collect();
+        names.insert("count".to_owned());
+        match group_by.iter().find(|column| names.contains(&column.field)) {
+            Some(column) => Err(StoreError::Unknown(anyhow!(
+                "can not group by attribute `{}` since its name clashes with that of an aggregate",
+                column.field
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    fn check_group_column(column: &Column) -> Result<(), StoreError> {
+        if column.is_list() || column.is_fulltext() {
+            return Err(StoreError::Unknown(anyhow!(
+                "can not group by attribute `{}` of type {:?}",
+                column.field,
+                column.column_type
+            )));
+        }
+        Ok(())
+    }
+
+    fn check_column(column: &Column, function: AggregateFunction) -> Result<(), StoreError> {
+        match column.column_type {
+            ColumnType::Int | ColumnType::BigInt | ColumnType::BigDecimal if !column.is_list() => {
+                Ok(())
+            }
+            _ => Err(StoreError::Unknown(anyhow!(
+                "can not compute `{}` of attribute `{}` of type {:?}",
+                function,
+                column.field,
+                column.column_type
+            ))),
+        }
+    }
+
+    /// The type of the value that `function` produces when it is applied to
+    /// a column of type `column_type`
+    fn result_type(column_type: &ColumnType, function: AggregateFunction) -> ColumnType {
+        match (function, column_type) {
+            (AggregateFunction::Avg, _) => ColumnType::BigDecimal,
+            (AggregateFunction::Sum, ColumnType::Int) => ColumnType::BigInt,
+            (_, column_type) => column_type.clone(),
+        }
+    }
+
+    /// Convert the JSONB objects returned by the database into result rows
+    /// using the types of the underlying columns
+    pub fn deserialize<T: FromEntityData>(
+        &self,
+        rows: Vec<AggregateData>,
+    ) -> Result<Vec<T>, StoreError> {
+        use serde_json::Value as j;
+
+        rows.into_iter()
+            .map(|row| match row.data {
+                j::Object(mut map) => {
+                    let mut out = T::default();
+                    for key in &["block", "count"] {
+                        let json = map.remove(*key).unwrap_or(j::Null);
+                        let value = T::Value::from_column_value(&ColumnType::Int, json)?;
+                        out.insert_entity_data(key.to_string(), value);
+                    }
+                    for column in &self.group_by {
+                        let json = map.remove(&Self::group_key(column)).unwrap_or(j::Null);
+                        let value = T::Value::from_column_value(&column.column_type, json)?;
+                        out.insert_entity_data(column.field.clone(), value);
+                    }
+                    for (column, function) in &self.aggregates {
+                        let key = Self::output_name(column, *function);
+                        let json = map.remove(&Self::aggregate_key(&key)).unwrap_or(j::Null);
+                        let column_type = Self::result_type(&column.column_type, *function);
+                        let value = T::Value::from_column_value(&column_type, json)?;
+                        out.insert_entity_data(key, value);
+                    }
+                    Ok(out)
+                }
+                _ => unreachable!(
+                    "we use `jsonb_build_object` in our queries, and will therefore always get an object back"
+                ),
+            })
+            .collect()
+    }
+
+    fn output_name(column: &Column, function: AggregateFunction) -> String {
+        format!("{}_{}", column.field, function)
+    }
+
+    /// The keys under which the query returns the values of group
+    /// columns and aggregates. They are prefixed so that they can not
+    /// clash with `block` and `count` or with each other
+    fn group_key(column: &Column) -> String {
+        format!("g:{}", column.field)
+    }
+
+    fn aggregate_key(output_name: &str) -> String {
+        format!("a:{}", output_name)
+    }
+}
+
+impl<'a> QueryFragment<Pg> for AggregateQuery<'a> {
+    fn walk_ast(&self, mut out: AstPass<Pg>) -> QueryResult<()> {
+        out.unsafe_to_cache_prepared();
+
+        if let Some(qid) = &self.query_id {
+            out.push_sql("/* qid: ");
+            out.push_sql(qid);
+            out.push_sql(" */\n");
+        }
+        out.push_sql("select jsonb_build_object('block', b.block, 'count', count(c.vid)");
+        for column in &self.group_by {
+            out.push_sql(", '");
+            out.push_sql(&Self::group_key(column));
+            out.push_sql("', c.");
+            out.push_identifier(column.name.as_str())?;
+        }
+        for (column, function) in &self.aggregates {
+            out.push_sql(", '");
+            out.push_sql(&Self::aggregate_key(&Self::output_name(column, *function)));
+            out.push_sql("', ");
+            out.push_sql(function.as_str());
+            out.push_sql("(c.");
+            out.push_identifier(column.name.as_str())?;
+            out.push_sql(")");
+        }
+        out.push_sql(") as data");
+        out.push_sql("\n  from generate_series(");
+        out.push_bind_param::<Integer, _>(&self.blocks.from)?;
+        out.push_sql(", ");
+        out.push_bind_param::<Integer, _>(&self.blocks.to)?;
+        out.push_sql(", ");
+        out.push_bind_param::<Integer, _>(&self.interval)?;
+        out.push_sql(") as b(block)");
+        if self.group_by.is_empty() {
+            out.push_sql("\n       left join lateral (select * from ");
+        } else {
+            out.push_sql("\n       join lateral (select * from ");
+        }
+        out.push_sql(self.table.qualified_name.as_str());
+        out.push_sql(" c\n where c.");
+        out.push_identifier(BLOCK_RANGE_COLUMN)?;
+        out.push_sql(" @> b.block");
+        if let Some(filter) = &self.filter {
+            out.push_sql(" and ");
+            filter.walk_ast(out.reborrow())?;
+        }
+        out.push_sql(") c on true");
+        for clause in &["\n group by b.block", "\n order by b.block"] {
+            out.push_sql(clause);
+            for column in &self.group_by {
+                out.push_sql(", c.");
+                out.push_identifier(column.name.as_str())?;
+            }
+        }
+        if let Some(range) = &self.range {
+            range.walk_ast(out.reborrow())?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> QueryId for AggregateQuery<'a> {
+    type QueryId = ();
+
+    const HAS_STATIC_QUERY_ID: bool = false;
+}
+
+impl<'a> LoadQuery<PgConnection, AggregateData> for AggregateQuery<'a> {
+    fn internal_load(self, conn: &PgConnection) -> QueryResult<Vec<AggregateData>> {
+        conn.query_by_name(&self)
+    }
+}
+
+impl<'a, Conn> RunQueryDsl<Conn> for AggregateQuery<'a> {}
+
+/// One row of the result of an `EntityChangesQuery`: an entity version,
+/// together with the block that created it, or the block that ended it
+/// if `ended` is true
+#[derive(QueryableByName)]
+pub struct EntityChangeData {
+    #[sql_type = "Text"]
+    entity: String,
+    #[sql_type = "Jsonb"]
+    data: serde_json::Value,
+    #[sql_type = "Integer"]
+    pub block: BlockNumber,
+    #[sql_type = "Bool"]
+    pub ended: bool,
+}
+
+impl EntityChangeData {
+    pub fn entity_data(self) -> EntityData {
+        EntityData {
+            entity: self.entity,
+            data: self.data,
+        }
+    }
+}
+
+/// A query that finds all entity versions that were created or ended by
+/// a block in `blocks`. The query has the form
+///
+///   select '..' as entity, to_jsonb(c.*) as data,
+///          lower(c.block_range) as block, false as ended
+///     from table c
+///    where lower(c.block_range) between $from and $to
+///   union all
+///   select '..' as entity, to_jsonb(c.*) as data,
+///          upper(c.block_range) as block, true as ended
+///     from table c
+///    where coalesce(upper(c.block_range), 2147483647) between $from and $to
+///      and coalesce(upper(c.block_range), 2147483647) < 2147483647
+///   union all
+///   ...
+///   order by block, entity, ended
+///
+/// The conditions are spelled so that the `brin_<table>` index can be used
+/// for the lower bound and the `<table>_block_range_closed` index for the
+/// upper bound; polling for changes close to the head of a deployment
+/// therefore does not need to scan its tables
+#[derive(Debug, Clone)]
+pub struct EntityChangesQuery<'a> {
+    tables: Vec<&'a Table>,
+    blocks: BlockSpan,
+}
+
+impl<'a> EntityChangesQuery<'a> {
+    pub fn new(tables: Vec<&'a Table>, blocks: BlockSpan) -> Self {
+        EntityChangesQuery { tables, blocks }
+    }
+}
+
+impl<'a> QueryFragment<Pg> for EntityChangesQuery<'a> {
+    fn walk_ast(&self, mut out: AstPass<Pg>) -> QueryResult<()> {
+        out.unsafe_to_cache_prepared();
+
+        // The expression that the `<table>_block_range_closed` index is on
+        const UPPER_OR_MAX: &str = "coalesce(upper(c.block_range), 2147483647)";
+
+        let mut first = true;
+        for table in &self.tables {
+            for (bound, ended) in &[("lower", "false"), ("upper", "true")] {
+                if first {
+                    first = false;
+                } else {
+                    out.push_sql("\nunion all\n");
+                }
+                out.push_sql("select '");
+                out.push_sql(table.object.as_str());
+                out.push_sql("' as entity, to_jsonb(c.*) as data, ");
+                out.push_sql(bound);
+                out.push_sql("(c.");
+                out.push_identifier(BLOCK_RANGE_COLUMN)?;
+                out.push_sql(") as block, ");
+                out.push_sql(ended);
+                out.push_sql(" as ended");
+                out.push_sql("\n  from ");
+                out.push_sql(table.qualified_name.as_str());
+                out.push_sql(" c\n where ");
+                if *bound == "lower" {
+                    out.push_sql("lower(c.");
+                    out.push_identifier(BLOCK_RANGE_COLUMN)?;
+                    out.push_sql(")");
+                } else {
+                    out.push_sql(UPPER_OR_MAX);
+                }
+                out.push_sql(" between ");
+                out.push_bind_param::<Integer, _>(&self.blocks.from)?;
+                out.push_sql(" and ");
+                out.push_bind_param::<Integer, _>(&self.blocks.to)?;
+                if *bound == "upper" {
+                    // Repeat the predicate of the partial index verbatim
+                    // so that Postgres knows it can use the index
+                    out.push_sql("\n   and ");
+                    out.push_sql(UPPER_OR_MAX);
+                    out.push_sql(" < 2147483647");
+                }
+            }
+        }
+        out.push_sql("\n order by block, entity, ended");
+        Ok(())
+    }
+}
+
+impl<'a> QueryId for EntityChangesQuery<'a> {
+    type QueryId = ();
+
+    const HAS_STATIC_QUERY_ID: bool = false;
+}
+
+impl<'a> LoadQuery<PgConnection, EntityChangeData> for EntityChangesQuery<'a> {
+    fn internal_load(self, conn: &PgConnection) -> QueryResult<Vec<EntityChangeData>> {
+        conn.query_by_name(&self)
+    }
+}
+
+impl<'a, Conn> RunQueryDsl<Conn> for EntityChangesQuery<'a> {}
+
 /// Reduce the upper bound of the current entry's block range to `block` as
 /// long as that does not result in an empty block range
 #[derive(Debug, Clone, Constructor)]
@@ -2759,9 +3386,55 @@ impl<'a> LoadQuery<PgConnection, ReturnedEntityData> for RevertClampQuery<'a> {
 
 impl<'a, Conn> RunQueryDsl<Conn> for RevertClampQuery<'a> {}
 
+/// A query that removes at most `limit` of the versions whose block range
+/// ends at or before `earliest_block`. None of these versions are visible
+/// at `earliest_block` or any later block.
+#[derive(Debug, Clone, Constructor)]
+pub struct PruneQuery<'a> {
+    table: &'a Table,
+    earliest_block: BlockNumber,
+    limit: i64,
+}
+
+impl<'a> QueryFragment<Pg> for PruneQuery<'a> {
+    fn walk_ast(&self, mut out: AstPass<Pg>) -> QueryResult<()> {
+        out.unsafe_to_cache_prepared();
+
+        // Construct a query
+        //   delete from table
+        //    where vid in (select vid from table
+        //                   where upper(block_range) <= $earliest_block
+        //                   limit $limit)
+        //
+        // Current versions have an upper bound of `null` and are therefore
+        // never removed
+        out.push_sql("delete from ");
+        out.push_sql(self.table.qualified_name.as_str());
+        out.push_sql("\n where vid in (select vid from ");
+        out.push_sql(self.table.qualified_name.as_str());
+        out.push_sql("\n                where upper(");
+        out.push_identifier(BLOCK_RANGE_COLUMN)?;
+        out.push_sql(") <= ");
+        out.push_bind_param::<Integer, _>(&self.earliest_block)?;
+        out.push_sql("\n                limit ");
+        out.push_bind_param::<BigInt, _>(&self.limit)?;
+        out.push_sql(")");
+        Ok(())
+    }
+}
+
+impl<'a> QueryId for PruneQuery<'a> {
+    type QueryId = ();
+
+    const HAS_STATIC_QUERY_ID: bool = false;
+}
+
+impl<'a, Conn> RunQueryDsl<Conn> for PruneQuery<'a> {}
+
 #[test]
 fn block_number_max_is_i32_max() {
-    // The code in RevertClampQuery::walk_ast embeds i32::MAX
+    // The code in RevertClampQuery::walk_ast and
+    // EntityChangesQuery::walk_ast embeds i32::MAX
     // aka BLOCK_NUMBER_MAX in strings for efficiency. This assertion
     // makes sure that BLOCK_NUMBER_MAX still is what we think it is
     assert_eq!(2147483647, graph::prelude::BLOCK_NUMBER_MAX);
@@ -4,7 +4,9 @@ use std::sync::Arc;
 use graph::{
     components::{
         server::index_node::VersionInfo,
-        store::{BlockStore as BlockStoreTrait, QueryStoreManager, StatusStore},
+        store::{
+            BlockStore as BlockStoreTrait, PersistedQueryStore, QueryStoreManager, StatusStore,
+        },
     },
     constraint_violation,
     data::subgraph::status,
@@ -116,6 +118,13 @@ impl StatusStore for Store {
             .subgraphs_for_deployment_hash(deployment_hash)
     }
 
+    fn deployment_stats(
+        &self,
+        subgraph_id: &DeploymentHash,
+    ) -> Result<Option<status::DeploymentStats>, StoreError> {
+        self.subgraph_store.deployment_stats(subgraph_id)
+    }
+
     async fn get_proof_of_indexing(
         &self,
         subgraph_id: &DeploymentHash,
@@ -132,3 +141,40 @@ impl StatusStore for Store {
         self.block_store.query_permit_primary().await
     }
 }
+
+#[async_trait]
+impl PersistedQueryStore for Store {
+    async fn persisted_query(&self, hash: &str) -> Result<Option<String>, StoreError> {
+        let store = self.subgraph_store.cheap_clone();
+        let hash = hash.to_string();
+        graph::spawn_blocking_allow_panic(move || store.persisted_query(&hash))
+            .await
+            .unwrap() // Propagate panics
+    }
+
+    async fn register_persisted_query(&self, hash: &str, query: &str) -> Result<(), StoreError> {
+        let store = self.subgraph_store.cheap_clone();
+        let (hash, query) = (hash.to_string(), query.to_string());
+        graph::spawn_blocking_allow_panic(move || store.register_persisted_query(&hash, &query))
+            .await
+            .unwrap()
+    }
+
+    async fn persisted_queries(&self) -> Result<Vec<(String, String)>, StoreError> {
+        let store = self.subgraph_store.cheap_clone();
+        let queries = graph::spawn_blocking_allow_panic(move || store.persisted_queries())
+            .await
+            .unwrap()?;
+        Ok(queries
+            .into_iter()
+            .map(|(hash, query, _)| (hash, query))
+            .collect())
+    }
+
+    async fn persisted_query_count(&self) -> Result<usize, StoreError> {
+        let store = self.subgraph_store.cheap_clone();
+        graph::spawn_blocking_allow_panic(move || store.persisted_query_count())
+            .await
+            .unwrap()
+    }
+}
@@ -4,9 +4,12 @@ use diesel::{
     sql_types::Text,
     types::{FromSql, ToSql},
 };
-use std::{collections::BTreeMap, collections::HashMap, sync::Arc};
+use std::{collections::BTreeMap, collections::HashMap, path::Path, sync::Arc};
 use std::{fmt, io::Write};
-use std::{iter::FromIterator, time::Duration};
+use std::{
+    iter::FromIterator,
+    time::{Duration, Instant},
+};
 
 use graph::{
     cheap_clone::CheapClone,
@@ -22,9 +25,9 @@ use graph::{
     prelude::SubgraphDeploymentEntity,
     prelude::{
         anyhow, futures03::future::join_all, lazy_static, o, web3::types::Address, ApiSchema,
-        BlockPtr, DeploymentHash, Entity, EntityKey, EntityModification, Error, Logger, NodeId,
-        Schema, StopwatchMetrics, StoreError, SubgraphName, SubgraphStore as SubgraphStoreTrait,
-        SubgraphVersionSwitchingMode,
+        BlockNumber, BlockPtr, DeploymentHash, Entity, EntityKey, EntityModification, Error,
+        Logger, NodeId, Schema, StopwatchMetrics, StoreError, SubgraphName,
+        SubgraphStore as SubgraphStoreTrait, SubgraphVersionSwitchingMode,
     },
     slog::{error, warn},
     util::{backoff::ExponentialBackoff, timed_cache::TimedCache},
@@ -41,6 +44,7 @@ use crate::{
 use crate::{
     deployment_store::{DeploymentStore, ReplicaId},
     detail::DeploymentDetail,
+    dump,
     primary::UnusedDeployment,
 };
 
@@ -462,7 +466,7 @@ impl SubgraphStoreInner {
         &self,
         name: SubgraphName,
         schema: &Schema,
-        deployment: SubgraphDeploymentEntity,
+        mut deployment: SubgraphDeploymentEntity,
         node_id: NodeId,
         network_name: String,
         mode: SubgraphVersionSwitchingMode,
@@ -499,6 +503,10 @@ impl SubgraphStoreInner {
             .transpose()?;
 
         if let Some(graft_base) = &graft_base {
+            if let Some(graft_block) = &deployment.graft_block {
+                let earliest_block = self.check_graft_history(&graft_base.site, graft_block)?;
+                deployment.earliest_block_number = earliest_block;
+            }
             self.primary_conn()?
                 .record_active_copy(graft_base.site.as_ref(), site.as_ref())?;
         }
@@ -574,6 +582,8 @@ impl SubgraphStoreInner {
             )));
         }
 
+        let earliest_block_number = self.check_graft_history(src.as_ref(), &block)?;
+
         // Transmogrify the deployment into a new one
         let deployment = SubgraphDeploymentEntity {
             manifest: deployment.manifest,
@@ -589,6 +599,8 @@ impl SubgraphStoreInner {
             reorg_count: 0,
             current_reorg_depth: 0,
             max_reorg_depth: 0,
+            history_blocks: deployment.history_blocks,
+            earliest_block_number,
         };
 
         let graft_base = self.layout(&src.deployment)?;
@@ -623,6 +635,98 @@ impl SubgraphStoreInner {
         Ok(dst.as_ref().into())
     }
 
+    /// Check that `base` still has all the history that is needed to graft
+    /// onto or copy it at `block`, and return the earliest block for which
+    /// it has all its history
+    fn check_graft_history(
+        &self,
+        base: &Site,
+        block: &BlockPtr,
+    ) -> Result<BlockNumber, StoreError> {
+        let (_, earliest_block) = self.for_site(base)?.history(base)?;
+        if block.number < earliest_block {
+            return Err(StoreError::Unknown(anyhow!(
+                "can not graft onto or copy deployment {} at block {} since its \
+                 history before block {} has been pruned",
+                base.deployment,
+                block.number,
+                earliest_block
+            )));
+        }
+        Ok(earliest_block)
+    }
+
+    /// Write the data and metadata of `deployment` into the directory
+    /// `dir` so that it can be read by external tools or restored into a
+    /// different installation. The data files are written in `format`.
+    /// See `dump.rs` for the details of the formats
+    pub fn dump(
+        &self,
+        deployment: &DeploymentLocator,
+        dir: &Path,
+        format: dump::Format,
+    ) -> Result<dump::Manifest, StoreError> {
+        let site = self.find_site(deployment.id.into())?;
+        let store = self.for_site(site.as_ref())?;
+        store.dump(site, dir, format)
+    }
+
+    /// Create a new deployment in `shard` from the dump in `dir` and
+    /// assign it to `node`. The data is loaded as it was when the dump was
+    /// made, and indexing resumes from the head block of the dump. If the
+    /// restore fails, the partially restored deployment is removed again.
+    /// Only dumps in CSV format can be restored
+    pub fn restore(
+        &self,
+        dir: &Path,
+        shard: Shard,
+        node: NodeId,
+    ) -> Result<DeploymentLocator, StoreError> {
+        let manifest = dump::Manifest::read(dir)?;
+        if manifest.format != dump::Format::Csv {
+            return Err(StoreError::Unknown(anyhow!(
+                "the dump in {} is in {} format, but only dumps in csv format can be restored",
+                dir.display(),
+                manifest.format
+            )));
+        }
+        let hash = DeploymentHash::new(manifest.deployment.clone()).map_err(|hash| {
+            StoreError::Unknown(anyhow!("the dump has an invalid deployment hash {}", hash))
+        })?;
+        let schema = Schema::parse(&manifest.schema, hash.clone())?;
+
+        let deployment_store = self
+            .stores
+            .get(&shard)
+            .ok_or_else(|| StoreError::UnknownShard(shard.to_string()))?;
+
+        let site = self
+            .primary_conn()?
+            .restore_site(shard, &hash, manifest.network.clone())?;
+        let site = Arc::new(site);
+
+        // Loading the data can not happen in one transaction. If any step
+        // fails, remove everything we created so far so that the restore
+        // can simply be retried
+        let res = deployment_store
+            .restore(site.clone(), &schema, &manifest, dir)
+            .and_then(|()| {
+                let pconn = self.primary_conn()?;
+                pconn.transaction(|| -> Result<_, StoreError> {
+                    let changes = pconn.assign_subgraph(site.as_ref(), &node)?;
+                    let event = StoreEvent::new(changes);
+                    pconn.send_store_event(&self.sender, &event)?;
+                    Ok(())
+                })
+            });
+        if let Err(e) = res {
+            deployment_store.drop_deployment(site.as_ref())?;
+            self.primary_conn()?.drop_site(site.as_ref())?;
+            return Err(e);
+        }
+        Ok(site.as_ref().into())
+    }
+
     /// Mark `deployment` as the only active deployment amongst all sites
     /// with the same deployment hash. Activating this specific deployment
     /// will make queries use that instead of whatever was active before
@@ -893,6 +997,18 @@ impl SubgraphStoreInner {
         self.mirror.subgraphs_by_deployment_hash(deployment_hash)
     }
 
+    pub(crate) fn deployment_stats(
+        &self,
+        id: &DeploymentHash,
+    ) -> Result<Option<status::DeploymentStats>, StoreError> {
+        let (store, site) = match self.store(id) {
+            Ok(store) => store,
+            Err(StoreError::DeploymentNotFound(_)) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        store.deployment_stats(site).map(Some)
+    }
+
     #[cfg(debug_assertions)]
     pub fn error_count(&self, id: &DeploymentHash) -> Result<usize, StoreError> {
         let (store, _) = self.store(id)?;
@@ -904,6 +1020,86 @@ impl SubgraphStoreInner {
         join_all(self.stores.values().map(|store| store.vacuum())).await
     }
 
+    /// Return how many blocks of history `deployment` keeps (`None` if it
+    /// keeps all of it) and the earliest block for which it has all its
+    /// history
+    pub fn history(
+        &self,
+        deployment: &DeploymentLocator,
+    ) -> Result<(Option<BlockNumber>, BlockNumber), StoreError> {
+        let site = self.find_site(deployment.id.into())?;
+        let store = self.for_site(site.as_ref())?;
+        store.history(site.as_ref())
+    }
+
+    /// Change how many blocks of history `deployment` keeps. With `None`,
+    /// the deployment keeps all its history from now on
+    pub fn set_history_blocks(
+        &self,
+        deployment: &DeploymentLocator,
+        history_blocks: Option<BlockNumber>,
+    ) -> Result<(), StoreError> {
+        let site = self.find_site(deployment.id.into())?;
+        let store = self.for_site(site.as_ref())?;
+        store.set_history_blocks(site.as_ref(), history_blocks)
+    }
+
+    /// List the deployments whose history can be pruned together with the
+    /// block before which their history can be removed. Deployments that
+    /// are being copied or grafted are left alone until the copy finishes
+    pub(crate) fn prunable_deployments(
+        &self,
+        reorg_threshold: BlockNumber,
+    ) -> Result<Vec<(DeploymentId, BlockNumber)>, StoreError> {
+        let copying = self.primary_conn()?.active_copy_sources()?;
+
+        let mut prunable = Vec::new();
+        for store in self.stores.values() {
+            prunable.extend(
+                store
+                    .prunable_deployments(reorg_threshold)?
+                    .into_iter()
+                    .filter(|(id, _)| !copying.contains(id)),
+            );
+        }
+        Ok(prunable)
+    }
+
+    /// Remove the history of the deployment `id` before `earliest_block`
+    /// and return the number of entity versions that were removed. Stop
+    /// early once `deadline` has passed
+    pub(crate) fn prune(
+        &self,
+        id: DeploymentId,
+        earliest_block: BlockNumber,
+        deadline: Instant,
+    ) -> Result<usize, StoreError> {
+        let site = self.find_site(id)?;
+        let store = self.for_site(site.as_ref())?;
+        store.prune(site, earliest_block, deadline)
+    }
+
+    pub fn persisted_query(&self, hash: &str) -> Result<Option<String>, StoreError> {
+        self.primary_conn()?.find_persisted_query(hash)
+    }
+
+    pub fn register_persisted_query(&self, hash: &str, query: &str) -> Result<(), StoreError> {
+        self.primary_conn()?.register_persisted_query(hash, query)
+    }
+
+    /// Return all persisted queries as `(hash, query, created_at)`
+    pub fn persisted_queries(&self) -> Result<Vec<(String, String, String)>, StoreError> {
+        self.primary_conn()?.persisted_queries()
+    }
+
+    pub fn persisted_query_count(&self) -> Result<usize, StoreError> {
+        self.primary_conn()?.persisted_query_count()
+    }
+
+    pub fn remove_persisted_query(&self, hash: &str) -> Result<bool, StoreError> {
+        self.primary_conn()?.remove_persisted_query(hash)
+    }
+
     pub fn rewind(&self, id: DeploymentHash, block_ptr_to: BlockPtr) -> Result<(), StoreError> {
         let (store, site) = self.store(&id)?;
         let event = store.rewind(site, block_ptr_to)?;
@@ -1297,6 +1493,29 @@ impl WritableStoreTrait for WritableStore {
         })
     }
 
+    fn transact_block_batch(
+        &self,
+        blocks: Vec<store::BlockOperations>,
+        stopwatch: StopwatchMetrics,
+    ) -> Result<(), StoreError> {
+        assert!(
+            blocks
+                .iter()
+                .all(|block| same_subgraph(&block.mods, &self.site.deployment)),
+            "can only transact operations within one shard"
+        );
+        self.retry("transact_block_batch", move || {
+            let event = self.writable.transact_block_batch(
+                self.site.clone(),
+                &blocks,
+                stopwatch.cheap_clone(),
+            )?;
+
+            let _section = stopwatch.start_section("send_store_event");
+            self.try_send_store_event(event)
+        })
+    }
+
     fn get_many(
         &self,
         ids_for_type: BTreeMap<&EntityType, Vec<&str>>,
@@ -120,6 +120,7 @@ fn insert_test_data(store: Arc<DieselSubgraphStore>) -> DeploymentLocator {
         data_sources: vec![],
         graft: None,
         templates: vec![],
+        indexer_hints: Default::default(),
         chain: PhantomData,
     };
 
@@ -2,7 +2,8 @@
 use diesel::connection::SimpleConnection as _;
 use diesel::pg::PgConnection;
 use graph::prelude::{
-    o, slog, tokio, web3::types::H256, DeploymentHash, Entity, EntityCollection, EntityFilter,
+    o, slog, tokio, web3::types::H256, AggregateFunction, AttributeAggregate, BlockSpan,
+    DeploymentHash, Entity, EntityChangeKind, EntityCollection, EntityCursor, EntityFilter,
     EntityKey, EntityOrder, EntityQuery, EntityRange, Logger, Schema, StopwatchMetrics, Value,
     ValueType, BLOCK_NUMBER_MAX,
 };
@@ -710,9 +711,12 @@ fn count_scalar_entities(conn: &PgConnection, layout: &Layout) -> usize {
             EntityRange {
                 first: None,
                 skip: 0,
+                after: None,
+                before: None,
             },
             BLOCK_NUMBER_MAX,
             None,
+            None,
         )
         .expect("Count query failed")
         .len()
@@ -760,6 +764,264 @@ fn delete() {
     });
 }
 
+#[test]
+fn entity_changes() {
+    run_test(|conn, layout| {
+        insert_pets(conn, layout);
+
+        let dog = EntityType::from("Dog");
+        let key = EntityKey::data(THINGS_SUBGRAPH_ID.clone(), "Dog".to_owned(), "pluto".into());
+        let mut pluto = Entity::new();
+        pluto.set("id", "pluto");
+        pluto.set("name", "Pluto the Pup");
+        let mut entities = vec![(&key, Cow::Borrowed(&pluto))];
+        layout
+            .update(conn, &dog, &mut entities, 2, &MOCK_STOPWATCH)
+            .expect("Failed to update");
+
+        let cat = EntityType::from("Cat");
+        layout
+            .delete(conn, &cat, &["garfield"], 3, &MOCK_STOPWATCH)
+            .expect("Failed to delete");
+
+        use EntityChangeKind::*;
+        let changes: Vec<_> = layout
+            .entity_changes(conn, BlockSpan::new(0, 10))
+            .expect("Failed to get entity changes")
+            .into_iter()
+            .map(|change| {
+                let entity = format!("{}[{}]", change.entity_type, change.entity_id);
+                let name = change.data.get("name").cloned().unwrap();
+                (change.block, entity, change.kind, name)
+            })
+            .collect();
+        let expected: Vec<_> = vec![
+            (0, "Cat[garfield]", Insert, "Garfield"),
+            (0, "Dog[pluto]", Insert, "Pluto"),
+            (2, "Dog[pluto]", Update, "Pluto the Pup"),
+            (3, "Cat[garfield]", Delete, "Garfield"),
+        ]
+        .into_iter()
+        .map(|(block, entity, kind, name)| (block, entity.to_owned(), kind, Value::from(name)))
+        .collect();
+        assert_eq!(expected, changes);
+
+        let changes = layout
+            .entity_changes(conn, BlockSpan::new(1, 2))
+            .expect("Failed to get entity changes");
+        assert_eq!(1, changes.len());
+        assert_eq!(EntityChangeKind::Update, changes[0].kind);
+    });
+}
+
+#[test]
+fn prune() {
+    run_test(|conn, layout| {
+        insert_pets(conn, layout);
+
+        let dog = EntityType::from("Dog");
+        let key = EntityKey::data(THINGS_SUBGRAPH_ID.clone(), "Dog".to_owned(), "pluto".into());
+        let mut pluto = Entity::new();
+        pluto.set("id", "pluto");
+        pluto.set("name", "Pluto the Pup");
+        let mut entities = vec![(&key, Cow::Borrowed(&pluto))];
+        layout
+            .update(conn, &dog, &mut entities, 2, &MOCK_STOPWATCH)
+            .expect("Failed to update");
+        pluto.set("name", "Pluto the Dog");
+        let mut entities = vec![(&key, Cow::Borrowed(&pluto))];
+        layout
+            .update(conn, &dog, &mut entities, 5, &MOCK_STOPWATCH)
+            .expect("Failed to update");
+
+        let cat = EntityType::from("Cat");
+        layout
+            .delete(conn, &cat, &["garfield"], 3, &MOCK_STOPWATCH)
+            .expect("Failed to delete");
+
+        // Removes the first version of Pluto and Garfield, but keeps the
+        // version of Pluto that is still visible at block 4
+        // Removing one version at a time takes two rounds
+        assert_eq!(1, layout.prune(conn, 4, 1).expect("Failed to prune"));
+        assert_eq!(1, layout.prune(conn, 4, 1).expect("Failed to prune"));
+        assert_eq!(0, layout.prune(conn, 4, 1).expect("Failed to prune"));
+
+        let name = |block| {
+            layout
+                .find(conn, &dog, "pluto", block)
+                .expect("Failed to read Dog[pluto]")
+                .map(|pluto| pluto.get("name").cloned().unwrap())
+        };
+        assert_eq!(None, name(1));
+        assert_eq!(Some(Value::from("Pluto the Pup")), name(4));
+        assert_eq!(Some(Value::from("Pluto the Dog")), name(5));
+        assert_eq!(
+            None,
+            layout
+                .find(conn, &cat, "garfield", 2)
+                .expect("Failed to read Cat[garfield]")
+        );
+
+        // Pruning again at the same block does not remove anything
+        assert_eq!(0, layout.prune(conn, 4, 100).expect("Failed to prune"));
+    });
+}
+
+#[test]
+fn history() {
+    run_test(|conn, layout| {
+        insert_pets(conn, layout);
+
+        let dog = EntityType::from("Dog");
+        let key = EntityKey::data(THINGS_SUBGRAPH_ID.clone(), "Dog".to_owned(), "pluto".into());
+        let mut pluto = Entity::new();
+        pluto.set("id", "pluto");
+        pluto.set("name", "Pluto the Pup");
+        let mut entities = vec![(&key, Cow::Borrowed(&pluto))];
+        layout
+            .update(conn, &dog, &mut entities, 2, &MOCK_STOPWATCH)
+            .expect("Failed to update");
+        pluto.set("name", "Pluto the Dog");
+        let mut entities = vec![(&key, Cow::Borrowed(&pluto))];
+        layout
+            .update(conn, &dog, &mut entities, 5, &MOCK_STOPWATCH)
+            .expect("Failed to update");
+
+        let cat = EntityType::from("Cat");
+        layout
+            .delete(conn, &cat, &["garfield"], 3, &MOCK_STOPWATCH)
+            .expect("Failed to delete");
+
+        let names = |entity_types: Vec<&str>, from, to| {
+            let query = query(entity_types);
+            layout
+                .query::<Entity>(
+                    &*LOGGER,
+                    conn,
+                    query.collection,
+                    None,
+                    EntityOrder::Default,
+                    EntityRange::first(100),
+                    BLOCK_NUMBER_MAX,
+                    Some(BlockSpan::new(from, to)),
+                    None,
+                )
+                .expect("history query failed")
+                .into_iter()
+                .map(|entity| entity.get("name").cloned().unwrap())
+                .collect::<Vec<_>>()
+        };
+        let values = |names: Vec<&str>| names.into_iter().map(Value::from).collect::<Vec<_>>();
+
+        // Versions are listed oldest first, and the spans are inclusive
+        assert_eq!(
+            values(vec!["Pluto", "Pluto the Pup", "Pluto the Dog"]),
+            names(vec!["Dog"], 0, 10)
+        );
+        assert_eq!(
+            values(vec!["Pluto", "Pluto the Pup"]),
+            names(vec!["Dog"], 1, 4)
+        );
+        assert_eq!(values(vec!["Pluto the Dog"]), names(vec!["Dog"], 5, 5));
+
+        // Garfield was deleted at block 3, and its only version is part of
+        // the history until then
+        assert_eq!(values(vec!["Garfield"]), names(vec!["Cat"], 2, 2));
+        assert_eq!(values(vec![]), names(vec!["Cat"], 3, 10));
+
+        // Interfaces list the versions of all their implementations
+        assert_eq!(
+            values(vec!["Garfield", "Pluto the Pup"]),
+            names(vec!["Cat", "Dog"], 2, 4)
+        );
+    });
+}
+
+#[test]
+fn aggregate() {
+    run_test(|conn, layout| {
+        insert_users(conn, layout);
+
+        let user = EntityType::from("User");
+        let mut shaq = layout
+            .find(conn, &user, "3", 0)
+            .expect("Failed to read User[3]")
+            .unwrap();
+        shaq.set("age", 30);
+        let key = EntityKey::data(THINGS_SUBGRAPH_ID.clone(), "User".to_owned(), "3".into());
+        let mut entities = vec![(&key, Cow::Borrowed(&shaq))];
+        layout
+            .update(conn, &user, &mut entities, 2, &MOCK_STOPWATCH)
+            .expect("Failed to update");
+
+        let aggregates = vec![
+            AttributeAggregate::new("age".to_owned(), AggregateFunction::Sum),
+            AttributeAggregate::new("age".to_owned(), AggregateFunction::Min),
+        ];
+        let aggregate = |group_by: &[String], interval| {
+            layout
+                .aggregate::<Entity>(
+                    conn,
+                    &user,
+                    None,
+                    &aggregates,
+                    group_by,
+                    BlockSpan::new(0, 2),
+                    interval,
+                    None,
+                    None,
+                )
+                .expect("aggregate query failed")
+                .into_iter()
+                .map(|row| {
+                    let value = |key: &str| row.get(key).cloned().unwrap_or(Value::Null);
+                    (
+                        value("block"),
+                        value("coffee"),
+                        value("count"),
+                        value("age_sum"),
+                        value("age_min"),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        let row = |block: i32, coffee: Option<bool>, count: i32, sum: i32, min: i32| {
+            (
+                Value::Int(block),
+                coffee.map(Value::Bool).unwrap_or(Value::Null),
+                Value::Int(count),
+                Value::BigInt(BigInt::from(sum)),
+                Value::Int(min),
+            )
+        };
+
+        // Each data point only sees the versions current at its block
+        assert_eq!(
+            vec![
+                row(0, None, 3, 138, 28),
+                row(1, None, 3, 138, 28),
+                row(2, None, 3, 140, 30)
+            ],
+            aggregate(&[], 1)
+        );
+        assert_eq!(
+            vec![row(0, None, 3, 138, 28), row(2, None, 3, 140, 30)],
+            aggregate(&[], 2)
+        );
+
+        // Grouping produces one row per block and group
+        assert_eq!(
+            vec![
+                row(0, Some(false), 2, 95, 28),
+                row(0, Some(true), 1, 43, 43),
+                row(2, Some(false), 2, 97, 30),
+                row(2, Some(true), 1, 43, 43)
+            ],
+            aggregate(&["coffee".to_owned()], 2)
+        );
+    });
+}
+
 #[test]
 fn insert_many_and_delete_many() {
     run_test(|conn, layout| {
@@ -901,6 +1163,7 @@ impl<'a> QueryChecker<'a> {
                 query.range,
                 BLOCK_NUMBER_MAX,
                 None,
+                None,
             )
             .expect("layout.query failed to execute query");
 
@@ -1457,6 +1720,87 @@ fn check_find() {
     })
 }
 
+#[test]
+fn check_cursors() {
+    fn after(query: EntityQuery, attribute: &str, value: Value, id: &str) -> EntityQuery {
+        let mut query = query;
+        query.range.after = Some(EntityCursor::new(
+            attribute.to_owned(),
+            value,
+            id.to_owned(),
+        ));
+        query
+    }
+
+    fn before(query: EntityQuery, attribute: &str, value: Value, id: &str) -> EntityQuery {
+        let mut query = query;
+        query.range.before = Some(EntityCursor::new(
+            attribute.to_owned(),
+            value,
+            id.to_owned(),
+        ));
+        query
+    }
+
+    run_test(move |conn, layout| {
+        let color = "favorite_color";
+        let red = Value::from("red");
+        let yellow = Value::from("yellow");
+
+        // Users ordered by favorite_color are "2" (red), "1" (yellow), and
+        // "3" (null) in ascending order; nulls come first in descending order
+        QueryChecker::new(conn, layout)
+            .check(
+                vec!["2", "3"],
+                after(user_query(), "id", Value::from("1"), "1"),
+            )
+            .check(vec!["1"], before(user_query(), "id", Value::from("2"), "2"))
+            .check(
+                vec!["1", "3"],
+                after(user_query().asc(color), color, red.clone(), "2"),
+            )
+            .check(
+                vec!["3"],
+                after(user_query().asc(color), color, yellow, "1"),
+            )
+            .check(
+                vec![],
+                after(user_query().asc(color), color, Value::Null, "3"),
+            )
+            .check(
+                vec!["2", "1"],
+                before(user_query().asc(color), color, Value::Null, "3"),
+            )
+            .check(
+                vec!["1", "2"],
+                after(user_query().desc(color), color, Value::Null, "3"),
+            )
+            .check(
+                vec!["3", "1"],
+                before(user_query().desc(color), color, red.clone(), "2"),
+            )
+            .check(
+                vec!["1"],
+                after(
+                    before(user_query().desc(color), color, red, "2"),
+                    color,
+                    Value::Null,
+                    "3",
+                ),
+            )
+            // Cursors with interfaces
+            .check(
+                vec!["pluto"],
+                after(
+                    query(vec!["Cat", "Dog"]).asc("name"),
+                    "name",
+                    Value::from("Garfield"),
+                    "garfield",
+                ),
+            );
+    })
+}
+
 // We call our test strings aN so that
 //   aN = "a" * (STRING_PREFIX_SIZE - 2 + N)
 // chosen so that they straddle the boundary between strings that fit into
@@ -1496,6 +1840,7 @@ fn text_find(expected_entity_ids: Vec<&str>, filter: EntityFilter) {
                 query.range,
                 BLOCK_NUMBER_MAX,
                 None,
+                None,
             )
             .expect("layout.query failed to execute query");
 
@@ -13,8 +13,8 @@ use graph::prelude::*;
 use graph::{
     blockchain::DataSource,
     components::store::{
-        BlockStore as _, EntityFilter, EntityKey, EntityOrder, EntityQuery, EntityType,
-        StatusStore, SubscriptionManager as _,
+        BlockOperations, BlockStore as _, EntityFilter, EntityKey, EntityOrder, EntityQuery,
+        EntityType, StatusStore, SubscriptionManager as _,
     },
     prelude::ethabi::Contract,
 };
@@ -160,6 +160,7 @@ fn insert_test_data(store: Arc<DieselSubgraphStore>) -> DeploymentLocator {
         data_sources: vec![],
         graft: None,
         templates: vec![],
+        indexer_hints: Default::default(),
         chain: PhantomData,
     };
 
@@ -1151,6 +1152,7 @@ fn mock_data_source() -> graph_chain_ethereum::DataSource {
             event_handlers: vec![],
             call_handlers: vec![],
             block_handlers: vec![],
+            transaction_handlers: vec![],
             link: Link {
                 link: "link".to_owned(),
             },
@@ -1226,7 +1228,10 @@ fn revert_block_with_dynamic_data_source_operations() {
         // Verify that the dynamic data source exists afterwards
         let loaded_dds = writable.load_dynamic_data_sources().await.unwrap();
         assert_eq!(1, loaded_dds.len());
-        assert_eq!(data_source.source, loaded_dds[0].source);
+        let stored = data_source.as_stored_dynamic_data_source().unwrap();
+        assert_eq!(stored.address, loaded_dds[0].address);
+        assert_eq!(stored.abi, loaded_dds[0].abi);
+        assert_eq!(stored.start_block, loaded_dds[0].start_block);
 
         let subscription = subscribe(&deployment.hash, USER);
 
@@ -1274,6 +1279,7 @@ fn entity_changes_are_fired_and_forwarded_to_subscriptions() {
             data_sources: vec![],
             graft: None,
             templates: vec![],
+            indexer_hints: Default::default(),
             chain: PhantomData,
         };
 
@@ -1837,6 +1843,138 @@ fn find_at_block() {
     shaqueeena_at_block(7000, "teeko@email.com");
 }
 
+#[test]
+fn transact_block_batch() {
+    run_test(|store, writable, deployment| async move {
+        let key = EntityKey::data(deployment.hash.clone(), USER.to_owned(), "7".to_owned());
+        let user = |email: &str| {
+            let op = create_test_entity("7", USER, "Wanjon", email, 76, 111.7, true, None);
+            match op {
+                EntityOperation::Set { data, .. } => data,
+                _ => unreachable!(),
+            }
+        };
+        let metrics_registry = Arc::new(MockMetricsRegistry::new());
+        let stopwatch_metrics = StopwatchMetrics::new(
+            Logger::root(slog::Discard, o!()),
+            deployment.hash.clone(),
+            metrics_registry.clone(),
+        );
+
+        let count = get_entity_count(store.clone(), &deployment.hash);
+        let blocks = vec![
+            BlockOperations {
+                block_ptr: TEST_BLOCK_3_PTR.clone(),
+                firehose_cursor: None,
+                mods: vec![EntityModification::Insert {
+                    key: key.clone(),
+                    data: user("wanjon@email.com"),
+                }],
+                data_sources: vec![],
+            },
+            BlockOperations {
+                block_ptr: TEST_BLOCK_4_PTR.clone(),
+                firehose_cursor: None,
+                mods: vec![EntityModification::Overwrite {
+                    key: key.clone(),
+                    data: user("wanawana@email.com"),
+                }],
+                data_sources: vec![],
+            },
+        ];
+        writable
+            .transact_block_batch(blocks, stopwatch_metrics)
+            .unwrap();
+
+        assert_eq!(
+            Some(TEST_BLOCK_4_PTR.clone()),
+            writable.block_ptr().unwrap()
+        );
+        assert_eq!(count + 1, get_entity_count(store.clone(), &deployment.hash));
+
+        // Each block's version of the entity is visible at that block
+        let email_at_block = |block: BlockNumber| {
+            let mut query = user_query().filter(EntityFilter::Equal("id".to_owned(), "7".into()));
+            query.block = block;
+            store
+                .subgraph_store()
+                .find(query)
+                .expect("store.find failed to execute query")
+                .first()
+                .and_then(|entity| entity.get("email").cloned())
+        };
+        assert_eq!(None, email_at_block(2));
+        assert_eq!(Some(Value::from("wanjon@email.com")), email_at_block(3));
+        assert_eq!(Some(Value::from("wanawana@email.com")), email_at_block(4));
+    })
+}
+
+#[test]
+fn transact_block_batch_only_moves_forward() {
+    run_test(|store, writable, deployment| async move {
+        let batch = |ptrs: Vec<&BlockPtr>| {
+            ptrs.into_iter()
+                .map(|ptr| {
+                    let op = create_test_entity(
+                        &ptr.number.to_string(),
+                        USER,
+                        "Wanjon",
+                        "wanjon@email.com",
+                        76,
+                        111.7,
+                        true,
+                        None,
+                    );
+                    let (key, data) = match op {
+                        EntityOperation::Set { key, data } => (key, data),
+                        _ => unreachable!(),
+                    };
+                    BlockOperations {
+                        block_ptr: ptr.clone(),
+                        firehose_cursor: None,
+                        mods: vec![EntityModification::Insert { key, data }],
+                        data_sources: vec![],
+                    }
+                })
+                .collect::<Vec<_>>()
+        };
+        let metrics_registry = Arc::new(MockMetricsRegistry::new());
+        let stopwatch_metrics = StopwatchMetrics::new(
+            Logger::root(slog::Discard, o!()),
+            deployment.hash.clone(),
+            metrics_registry.clone(),
+        );
+
+        let count = get_entity_count(store.clone(), &deployment.hash);
+
+        // The first block is the one the subgraph is already at
+        let res = writable.transact_block_batch(
+            batch(vec![&*TEST_BLOCK_2_PTR, &*TEST_BLOCK_3_PTR]),
+            stopwatch_metrics.cheap_clone(),
+        );
+        assert!(matches!(
+            res,
+            Err(StoreError::DuplicateBlockProcessing(_, 2))
+        ));
+
+        // The blocks are not in ascending order
+        let res = writable.transact_block_batch(
+            batch(vec![&*TEST_BLOCK_4_PTR, &*TEST_BLOCK_3_PTR]),
+            stopwatch_metrics,
+        );
+        assert!(matches!(
+            res,
+            Err(StoreError::DuplicateBlockProcessing(_, 3))
+        ));
+
+        assert_eq!(
+            Some(TEST_BLOCK_2_PTR.clone()),
+            writable.block_ptr().unwrap()
+        );
+        assert_eq!(count, get_entity_count(store.clone(), &deployment.hash));
+    })
+}
+
 #[test]
 fn cleanup_cached_blocks() {
     if store_is_sharded() {
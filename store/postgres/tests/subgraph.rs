@@ -1,8 +1,10 @@
+use graph::entity;
 use graph::{
     components::{
         server::index_node::VersionInfo,
         store::{DeploymentLocator, StatusStore},
     },
+    data::store::scalar::{BigInt, Bytes},
     data::subgraph::schema::SubgraphError,
     data::subgraph::schema::SubgraphHealth,
     prelude::EntityChange,
@@ -15,12 +17,25 @@ use graph::{
     prelude::SubgraphVersionSwitchingMode,
     prelude::{futures03, StoreEvent},
     prelude::{CheapClone, DeploymentHash, NodeId, SubgraphStore as _},
+    prelude::{EntityKey, EntityOperation, Value},
     semver::Version,
 };
+use graph_store_postgres::command_support::dump::{ColumnInfo, Format};
 use graph_store_postgres::layout_for_tests::Connection as Primary;
-use graph_store_postgres::SubgraphStore;
-
-use std::{collections::HashSet, marker::PhantomData, sync::Arc};
+use graph_store_postgres::{SubgraphStore, PRIMARY_SHARD};
+use parquet::data_type::ByteArray;
+use parquet::file::reader::{FileReader, SerializedFileReader};
+use parquet::record::{Field, ListAccessor};
+
+use std::{
+    collections::{HashMap, HashSet},
+    env, fs,
+    fs::File,
+    marker::PhantomData,
+    path::Path,
+    process,
+    sync::Arc,
+};
 use test_store::*;
 
 const SUBGRAPH_GQL: &str = "
@@ -134,6 +149,7 @@ fn create_subgraph() {
             data_sources: vec![],
             graft: None,
             templates: vec![],
+            indexer_hints: Default::default(),
             chain: PhantomData,
         };
         let deployment = SubgraphDeploymentEntity::new(&manifest, false, None);
@@ -979,3 +995,309 @@ fn fail_unfail_non_deterministic_error_noop() {
         test_store::remove_subgraphs();
     })
 }
+
+#[test]
+fn dump_and_restore() {
+    const NAME: &str = "dumpAndRestore";
+    const GQL: &str = "
+        type User @entity {
+            id: ID!,
+            name: String,
+            tags: [String!]
+        }";
+
+    fn set(deployment: &DeploymentLocator, id: &str, name: &str) -> EntityOperation {
+        EntityOperation::Set {
+            key: EntityKey::data(deployment.hash.clone(), "User".to_owned(), id.to_owned()),
+            data: entity! { id: id, name: name, tags: vec![name.to_owned()] },
+        }
+    }
+
+    /// The contents of all files in `dir` other than the manifest
+    fn data_files(dir: &Path) -> Vec<(String, String)> {
+        let mut files: Vec<_> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.file_name().unwrap() != "manifest.json")
+            .map(|path| {
+                let name = path.file_name().unwrap().to_string_lossy().to_string();
+                (name, fs::read_to_string(&path).unwrap())
+            })
+            .collect();
+        files.sort();
+        files
+    }
+
+    run_test_sequentially(|store| async move {
+        remove_subgraphs();
+        let subgraph_store = store.subgraph_store();
+        let hash = DeploymentHash::new(NAME).unwrap();
+        let deployment = create_test_subgraph(&hash, GQL);
+
+        transact_entity_operations(
+            &subgraph_store,
+            &deployment,
+            BLOCKS[1].clone(),
+            vec![set(&deployment, "1", "Alice"), set(&deployment, "2", "Bob")],
+        )
+        .unwrap();
+        transact_entity_operations(
+            &subgraph_store,
+            &deployment,
+            BLOCKS[2].clone(),
+            vec![
+                set(&deployment, "1", "Alicia"),
+                EntityOperation::Remove {
+                    key: EntityKey::data(hash.clone(), "User".to_owned(), "2".to_owned()),
+                },
+            ],
+        )
+        .unwrap();
+
+        let dir = env::temp_dir().join(format!("graph-node-dump-{}", process::id()));
+        let (dumped, restored) = (dir.join("dumped"), dir.join("restored"));
+        fs::create_dir_all(&dumped).unwrap();
+        fs::create_dir_all(&restored).unwrap();
+
+        subgraph_store
+            .dump(&deployment, &dumped, Format::Csv)
+            .unwrap();
+        remove_subgraphs();
+
+        // Restoring creates the deployment with all its history and at the
+        // block where it was dumped
+        let locator = subgraph_store
+            .restore(&dumped, PRIMARY_SHARD.clone(), NODE_ID.clone())
+            .unwrap();
+        let writable = subgraph_store
+            .cheap_clone()
+            .writable(LOGGER.clone(), locator.id)
+            .await
+            .unwrap();
+        assert_eq!(Some(BLOCKS[2].clone()), writable.block_ptr().unwrap());
+        let alice = writable
+            .get(&EntityKey::data(
+                hash.clone(),
+                "User".to_owned(),
+                "1".to_owned(),
+            ))
+            .unwrap()
+            .unwrap();
+        assert_eq!(Some(&Value::from("Alicia")), alice.get("name"));
+
+        let manifest = subgraph_store
+            .dump(&locator, &restored, Format::Csv)
+            .unwrap();
+        assert_eq!(
+            Some(BLOCKS[2].number),
+            manifest.head.as_ref().map(|head| head.number)
+        );
+        assert_eq!(data_files(&dumped), data_files(&restored));
+
+        // A restore that fails does not leave a deployment behind
+        remove_subgraphs();
+        let table = manifest
+            .tables
+            .iter()
+            .find(|table| table.entity_type == "User")
+            .unwrap();
+        fs::remove_file(dumped.join(&table.file)).unwrap();
+        assert!(subgraph_store
+            .restore(&dumped, PRIMARY_SHARD.clone(), NODE_ID.clone())
+            .is_err());
+        assert!(subgraph_store.locators(NAME).unwrap().is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    })
+}
+
+#[test]
+fn dump_formats() {
+    const NAME: &str = "dumpFormats";
+    const GQL: &str = "
+        type Thing @entity {
+            id: ID!,
+            flag: Boolean,
+            count: Int,
+            amount: BigInt,
+            data: Bytes,
+            tags: [String!]
+        }";
+
+    fn set_full(deployment: &DeploymentLocator, count: i32) -> EntityOperation {
+        EntityOperation::Set {
+            key: EntityKey::data(deployment.hash.clone(), "Thing".to_owned(), "1".to_owned()),
+            data: entity! {
+                id: "1",
+                flag: true,
+                count: count,
+                amount: BigInt::from(12345678901234567890u64),
+                data: Bytes::from(&[0x01u8, 0xab][..]),
+                tags: vec!["a".to_owned(), "b c".to_owned()]
+            },
+        }
+    }
+
+    /// The CSV row without the `vid` for an entity version with the given
+    /// `values`; all other columns are null
+    fn csv_row(columns: &[ColumnInfo], block_range: &str, values: &[(&str, &str)]) -> String {
+        std::iter::once(format!("\"{}\"", block_range))
+            .chain(columns.iter().map(|column| {
+                values
+                    .iter()
+                    .find(|(name, _)| *name == column.name)
+                    .map(|(_, value)| format!("\"{}\"", value.replace('"', "\"\"")))
+                    .unwrap_or_default()
+            }))
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    run_test_sequentially(|store| async move {
+        remove_subgraphs();
+        let subgraph_store = store.subgraph_store();
+        let hash = DeploymentHash::new(NAME).unwrap();
+        let deployment = create_test_subgraph(&hash, GQL);
+
+        let empty = EntityOperation::Set {
+            key: EntityKey::data(hash.clone(), "Thing".to_owned(), "2".to_owned()),
+            data: entity! { id: "2" },
+        };
+        transact_entity_operations(
+            &subgraph_store,
+            &deployment,
+            BLOCKS[1].clone(),
+            vec![set_full(&deployment, 7), empty],
+        )
+        .unwrap();
+        transact_entity_operations(
+            &subgraph_store,
+            &deployment,
+            BLOCKS[2].clone(),
+            vec![set_full(&deployment, 8)],
+        )
+        .unwrap();
+
+        let dir = env::temp_dir().join(format!("graph-node-dump-formats-{}", process::id()));
+        let (csv, parquet) = (dir.join("csv"), dir.join("parquet"));
+        fs::create_dir_all(&csv).unwrap();
+        fs::create_dir_all(&parquet).unwrap();
+
+        // CSV files have a header and the text representation of all values
+        let manifest = subgraph_store.dump(&deployment, &csv, Format::Csv).unwrap();
+        assert_eq!(Format::Csv, manifest.format);
+        let table = manifest
+            .tables
+            .iter()
+            .find(|table| table.entity_type == "Thing")
+            .unwrap();
+        assert_eq!(3, table.rows);
+
+        let contents = fs::read_to_string(csv.join(&table.file)).unwrap();
+        let mut lines = contents.lines();
+        let header = ["vid", "block_range"]
+            .iter()
+            .map(|name| name.to_string())
+            .chain(table.columns.iter().map(|column| column.name.clone()))
+            .collect::<Vec<_>>()
+            .join(",");
+        assert_eq!(Some(header.as_str()), lines.next());
+
+        let full = |count| {
+            vec![
+                ("id", "1"),
+                ("flag", "true"),
+                ("count", count),
+                ("amount", "12345678901234567890"),
+                ("data", "\\x01ab"),
+                ("tags", "{a,\"b c\"}"),
+            ]
+        };
+        let expected: HashSet<_> = vec![
+            csv_row(&table.columns, "[1,2)", &full("7")),
+            csv_row(&table.columns, "[2,)", &full("8")),
+            csv_row(&table.columns, "[1,)", &[("id", "2")]),
+        ]
+        .into_iter()
+        .collect();
+        let rows: HashSet<_> = lines
+            .map(|line| line.split_once(',').unwrap().1.to_string())
+            .collect();
+        assert_eq!(expected, rows);
+
+        // Parquet files have typed columns, and the block range is split
+        // into its start and end
+        let manifest = subgraph_store
+            .dump(&deployment, &parquet, Format::Parquet)
+            .unwrap();
+        assert_eq!(Format::Parquet, manifest.format);
+        let table = manifest
+            .tables
+            .iter()
+            .find(|table| table.entity_type == "Thing")
+            .unwrap();
+        assert!(table.file.ends_with(".parquet"));
+
+        let reader =
+            SerializedFileReader::new(File::open(parquet.join(&table.file)).unwrap()).unwrap();
+        assert_eq!(3, reader.metadata().file_metadata().num_rows());
+        let rows: Vec<HashMap<String, Field>> = reader
+            .get_row_iter(None)
+            .unwrap()
+            .map(|row| {
+                row.get_column_iter()
+                    .map(|(name, field)| (name.clone(), field.clone()))
+                    .collect()
+            })
+            .collect();
+        let find = |id: &str, start: i32| {
+            rows.iter()
+                .find(|row| {
+                    row["id"] == Field::Str(id.to_string())
+                        && row["block_range_start"] == Field::Int(start)
+                })
+                .unwrap()
+        };
+
+        let old = find("1", 1);
+        assert_eq!(Field::Int(2), old["block_range_end"]);
+        assert_eq!(Field::Int(7), old["count"]);
+
+        let current = find("1", 2);
+        assert_eq!(Field::Null, current["block_range_end"]);
+        assert_eq!(Field::Bool(true), current["flag"]);
+        assert_eq!(Field::Int(8), current["count"]);
+        assert_eq!(
+            Field::Str("12345678901234567890".to_string()),
+            current["amount"]
+        );
+        assert_eq!(
+            Field::Bytes(ByteArray::from(vec![0x01u8, 0xab])),
+            current["data"]
+        );
+        match &current["tags"] {
+            Field::ListInternal(list) => {
+                let tags: Vec<_> = (0..list.len())
+                    .map(|i| list.get_string(i).unwrap().as_str())
+                    .collect();
+                assert_eq!(vec!["a", "b c"], tags);
+            }
+            field => panic!("expected a list of tags but got {:?}", field),
+        }
+
+        let empty = find("2", 1);
+        assert_eq!(Field::Null, empty["block_range_end"]);
+        for column in ["flag", "count", "amount", "data", "tags"].iter() {
+            assert_eq!(Field::Null, empty[*column]);
+        }
+
+        // Only CSV dumps can be restored
+        remove_subgraphs();
+        assert!(subgraph_store
+            .restore(&parquet, PRIMARY_SHARD.clone(), NODE_ID.clone())
+            .is_err());
+        assert!(subgraph_store.locators(NAME).unwrap().is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    })
+}
@@ -163,6 +163,7 @@ pub fn create_subgraph(
         data_sources: vec![],
         graft: None,
         templates: vec![],
+        indexer_hints: Default::default(),
         chain: PhantomData,
     };
 
@@ -409,6 +410,7 @@ async fn execute_subgraph_query_internal(
         .query_store(deployment.into(), false)
         .await
         .unwrap();
+    let state = return_err!(store.deployment_state().await);
     for (bc, (selection_set, error_policy)) in return_err!(query.block_constraint()) {
         let logger = logger.clone();
         let resolver = return_err!(
@@ -417,6 +419,7 @@ async fn execute_subgraph_query_internal(
                 store.clone(),
                 SUBSCRIPTION_MANAGER.clone(),
                 bc,
+                state.earliest_block_number,
                 error_policy,
                 query.schema.id().clone(),
                 result_size_metrics()